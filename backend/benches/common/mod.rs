@@ -0,0 +1,32 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Market-wide miniTicker frames, one per second of a seeded random walk, shaped like the ones
+// !miniTicker@arr sends
+pub fn mini_ticker_bursts(bursts: usize, symbols: usize) -> Vec<String> {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut closes: Vec<f64> = (0..symbols).map(|_| rng.gen_range(0.01..100_000.0)).collect();
+    (0..bursts)
+        .map(|burst| {
+            let elements: Vec<String> = closes
+                .iter_mut()
+                .enumerate()
+                .map(|(symbol, close)| {
+                    *close *= 1.0 + rng.gen_range(-0.005..0.005);
+                    format!(
+                        r#"{{"e":"24hrMiniTicker","E":{},"s":"SYM{}USDT","c":"{:.8}","o":"{:.8}","h":"{:.8}","l":"{:.8}","v":"{:.3}","q":"{:.2}"}}"#,
+                        1_700_000_000_000_i64 + burst as i64 * 1000,
+                        symbol,
+                        close,
+                        *close * 0.99,
+                        *close * 1.01,
+                        *close * 0.98,
+                        rng.gen_range(1.0..1_000_000.0),
+                        rng.gen_range(1_000.0..100_000_000.0)
+                    )
+                })
+                .collect();
+            format!("[{}]", elements.join(","))
+        })
+        .collect()
+}
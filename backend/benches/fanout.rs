@@ -0,0 +1,91 @@
+// Cost of delivering one market-wide frame to every live subscriber: the broadcast channel plus
+// each connection's frame encoding, for the standard and the raw passthrough format.
+//
+//   cargo bench --bench fanout
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+mod common;
+
+// The server is a binary crate, so the modules under test are compiled in from its sources
+#[allow(dead_code)]
+#[path = "../src/chaos.rs"]
+mod chaos;
+#[allow(dead_code)]
+#[path = "../src/config.rs"]
+mod config;
+#[allow(dead_code)]
+#[path = "../src/ingest.rs"]
+mod ingest;
+#[allow(dead_code, non_snake_case)]
+#[path = "../src/models.rs"]
+mod models;
+
+use models::{ServerMessage, TickerUpdate};
+
+// Matches the server's ticker channel, large enough for a frame without lagging receivers
+const CHANNEL_CAPACITY: usize = 1024;
+
+fn updates(symbols: usize) -> Vec<TickerUpdate> {
+    let frame: Arc<str> = Arc::from(common::mini_ticker_bursts(1, symbols).remove(0).as_str());
+    ingest::decode_raw_tickers(&frame)
+        .into_iter()
+        .map(|(ticker, raw)| TickerUpdate {
+            symbol: ticker.s,
+            price: ticker.c.parse().unwrap_or_default(),
+            quote_volume: ticker.q.parse().unwrap_or_default(),
+            event_time: ticker.E,
+            raw: Some(raw),
+        })
+        .collect()
+}
+
+// Sends the frame's updates, then lets every subscriber drain and encode them
+fn deliver(
+    updates: &[TickerUpdate],
+    sender: &broadcast::Sender<TickerUpdate>,
+    receivers: &mut [broadcast::Receiver<TickerUpdate>],
+    encode: impl Fn(TickerUpdate) -> String,
+) -> usize {
+    for update in updates {
+        let _ = sender.send(update.clone());
+    }
+    let mut bytes = 0;
+    for receiver in receivers {
+        while let Ok(update) = receiver.try_recv() {
+            bytes += encode(update).len();
+        }
+    }
+    bytes
+}
+
+fn fanout(c: &mut Criterion) {
+    let updates = updates(300);
+    let mut group = c.benchmark_group("fanout");
+    for subscribers in [1, 10, 100] {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let mut receivers: Vec<_> = (0..subscribers).map(|_| sender.subscribe()).collect();
+        group.throughput(Throughput::Elements((updates.len() * subscribers) as u64));
+        group.bench_function(BenchmarkId::new("standard", subscribers), |b| {
+            b.iter(|| {
+                deliver(&updates, &sender, &mut receivers, |update| {
+                    serde_json::to_string(&ServerMessage::Ticker(update)).unwrap_or_default()
+                })
+            })
+        });
+        // As handlers::ticker_frame wraps a raw subscription's ticker
+        group.bench_function(BenchmarkId::new("raw", subscribers), |b| {
+            b.iter(|| {
+                deliver(&updates, &sender, &mut receivers, |update| match &update.raw {
+                    Some(raw) => format!("{{\"type\":\"raw_ticker\",\"data\":{}}}", raw.get()),
+                    None => String::new(),
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+// Per-frame cost of the ingest path up to storage: decoding a market-wide miniTicker frame, the
+// outlier filter and the latency metrics. Inserts are measured against a live database by the
+// hypertable_bench and load_test binaries.
+//
+//   cargo bench --bench ingest
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+use std::sync::Arc;
+
+mod common;
+
+// The server is a binary crate, so the modules under test are compiled in from its sources
+#[allow(dead_code)]
+#[path = "../src/chaos.rs"]
+mod chaos;
+#[allow(dead_code)]
+#[path = "../src/config.rs"]
+mod config;
+#[allow(dead_code)]
+#[path = "../src/ingest.rs"]
+mod ingest;
+#[allow(dead_code)]
+#[path = "../src/ingest_metrics.rs"]
+mod ingest_metrics;
+#[allow(dead_code, non_snake_case)]
+#[path = "../src/models.rs"]
+mod models;
+#[allow(dead_code)]
+#[path = "../src/tick_filter.rs"]
+mod tick_filter;
+
+use ingest_metrics::IngestMetrics;
+use tick_filter::{TickFilter, TickFilterConfig};
+
+const BURSTS: usize = 32;
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for symbols in [50, 300] {
+        let frame = common::mini_ticker_bursts(1, symbols).remove(0);
+        let shared: Arc<str> = Arc::from(frame.as_str());
+        group.throughput(Throughput::Elements(symbols as u64));
+        group.bench_with_input(BenchmarkId::new("tickers", symbols), &frame, |b, frame| {
+            b.iter(|| ingest::decode_tickers(black_box(frame)))
+        });
+        group.bench_with_input(BenchmarkId::new("raw_tickers", symbols), &shared, |b, frame| {
+            b.iter(|| ingest::decode_raw_tickers(black_box(frame)))
+        });
+    }
+    group.finish();
+}
+
+// A frame of ticks against warm per-symbol windows, like the steady state of a running feed
+fn filter(c: &mut Criterion) {
+    let bursts: Vec<_> = common::mini_ticker_bursts(BURSTS, 300)
+        .iter()
+        .map(|frame| ingest::decode_tickers(frame))
+        .collect();
+    let mut filter = TickFilter::new(TickFilterConfig::from_env());
+    for burst in &bursts {
+        for ticker in burst {
+            filter.check(ticker);
+        }
+    }
+
+    let mut group = c.benchmark_group("tick_filter");
+    group.throughput(Throughput::Elements(300));
+    let mut next = bursts.iter().cycle();
+    group.bench_function("check_frame", |b| {
+        b.iter(|| {
+            let burst = next.next().unwrap();
+            burst.iter().filter_map(|ticker| filter.check(ticker)).count()
+        })
+    });
+    group.finish();
+}
+
+fn metrics(c: &mut Criterion) {
+    let mut metrics = IngestMetrics::from_env();
+    let mut group = c.benchmark_group("ingest_metrics");
+    group.throughput(Throughput::Elements(300));
+    group.bench_function("record_frame", |b| {
+        b.iter(|| {
+            for i in 0..300 {
+                metrics.record(black_box(1_700_000_000_000), 1_700_000_000_000 + i % 40);
+            }
+        })
+    });
+    group.bench_function("report", |b| b.iter(|| metrics.report()));
+    group.finish();
+}
+
+criterion_group!(benches, decode, filter, metrics);
+criterion_main!(benches);
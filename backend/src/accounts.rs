@@ -0,0 +1,353 @@
+use crate::competitions;
+use crate::config::{env_or, LiveConfig};
+use crate::db;
+use crate::engine::now_millis;
+use crate::models::{AccountEvent, AccountTemplate, OrderRequest, ServerMessage, Side};
+use crate::state::AppState;
+use std::collections::HashMap;
+
+const DAY_MS: i64 = 86_400_000;
+
+// Maker and taker fee as a fraction of notional
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FeeRates {
+    pub maker: f64,
+    pub taker: f64,
+}
+
+// Named fee tiers a template can pick, loosely following Binance Futures' USDT-M VIP levels.
+// FEE_SCHEDULE changes or adds tiers as `name=maker_bps/taker_bps` pairs, e.g. `vip4=1.0/3.0`.
+pub fn fee_rates(config: &LiveConfig, tier: &str) -> Option<FeeRates> {
+    let configured = config
+        .var("FEE_SCHEDULE")
+        .and_then(|schedule| parse_fee_schedule(&schedule).ok())
+        .and_then(|schedule| schedule.get(tier).copied());
+    if configured.is_some() {
+        return configured;
+    }
+    let (maker_bps, taker_bps) = match tier {
+        "none" => (0.0, 0.0),
+        "standard" => (2.0, 5.0),
+        "vip1" => (1.6, 4.0),
+        "vip2" => (1.4, 3.5),
+        "vip3" => (1.2, 3.2),
+        _ => return None,
+    };
+    Some(FeeRates {
+        maker: maker_bps / 10_000.0,
+        taker: taker_bps / 10_000.0,
+    })
+}
+
+pub fn parse_fee_schedule(schedule: &str) -> Result<HashMap<String, FeeRates>, String> {
+    let mut tiers = HashMap::new();
+    for entry in schedule.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let parsed = entry.split_once('=').and_then(|(name, rates)| {
+            let (maker, taker) = rates.split_once('/')?;
+            let (maker, taker) = (maker.trim().parse::<f64>().ok()?, taker.trim().parse::<f64>().ok()?);
+            let valid = maker.is_finite() && taker.is_finite() && taker >= 0.0;
+            // Negative maker fees are rebates
+            valid.then(|| (name.trim().to_string(), FeeRates { maker: maker / 10_000.0, taker: taker / 10_000.0 }))
+        });
+        let Some((name, rates)) = parsed else {
+            return Err(format!("Invalid fee tier {:?}, expected name=maker_bps/taker_bps", entry));
+        };
+        tiers.insert(name, rates);
+    }
+    Ok(tiers)
+}
+
+// Admin-defined account templates and the template each account was created from
+#[derive(Default)]
+pub struct TemplateBook {
+    templates: HashMap<String, AccountTemplate>,
+    assignments: HashMap<String, String>, // account_id -> template name
+}
+
+impl TemplateBook {
+    pub fn load(&mut self, templates: Vec<AccountTemplate>, assignments: Vec<(String, String)>) {
+        self.templates = templates.into_iter().map(|template| (template.name.clone(), template)).collect();
+        self.assignments = assignments.into_iter().collect();
+    }
+
+    pub fn set_template(&mut self, template: AccountTemplate) {
+        self.templates.insert(template.name.clone(), template);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AccountTemplate> {
+        self.templates.get(name)
+    }
+
+    pub fn list(&self) -> Vec<AccountTemplate> {
+        let mut templates: Vec<AccountTemplate> = self.templates.values().cloned().collect();
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        templates
+    }
+
+    pub fn assign(&mut self, account_id: &str, template: &str) {
+        self.assignments.insert(account_id.to_string(), template.to_string());
+    }
+
+    pub fn remove_account(&mut self, account_id: &str) {
+        self.assignments.remove(account_id);
+    }
+
+    pub fn template_for(&self, account_id: &str) -> Option<&AccountTemplate> {
+        self.templates.get(self.assignments.get(account_id)?)
+    }
+
+    pub fn assignments(&self) -> impl Iterator<Item = (&String, &AccountTemplate)> {
+        self.assignments
+            .iter()
+            .filter_map(|(account_id, name)| Some((account_id, self.templates.get(name)?)))
+    }
+}
+
+pub fn validate_template(config: &LiveConfig, template: &AccountTemplate) -> Result<(), String> {
+    if template.name.trim().is_empty() {
+        return Err("Template name is required".to_string());
+    }
+    if !template.starting_balance.is_finite() || template.starting_balance <= 0.0 {
+        return Err("Starting balance must be positive".to_string());
+    }
+    if template.max_leverage.is_some_and(|leverage| !leverage.is_finite() || leverage <= 0.0) {
+        return Err("Max leverage must be positive".to_string());
+    }
+    if fee_rates(config, &template.fee_tier).is_none() {
+        return Err(format!("Unknown fee tier {}", template.fee_tier));
+    }
+    Ok(())
+}
+
+pub async fn set_template(state: &AppState, mut template: AccountTemplate) -> Result<ServerMessage, String> {
+    validate_template(&state.live_config, &template)?;
+    if template.name.starts_with(competitions::RULES_PREFIX) {
+        return Err("Competition rules are frozen and can't be edited".to_string());
+    }
+    template.allowed_symbols = template.allowed_symbols.iter().map(|symbol| symbol.trim().to_uppercase()).collect();
+    db::save_account_template(&state.pool, &template)
+        .await
+        .map_err(|e| format!("Error saving template: {}", e))?;
+
+    let mut templates = state.account_templates.lock().await;
+    templates.set_template(template);
+    // Accounts already on this template pick up the new fee tier
+    let mut portfolios = state.portfolios.lock().await;
+    for (account_id, template) in templates.assignments() {
+        portfolios.set_fee_rates(account_id, fee_rates(&state.live_config, &template.fee_tier).unwrap_or_default());
+    }
+    Ok(ServerMessage::AccountTemplates {
+        templates: templates.list(),
+    })
+}
+
+// Registers an account, optionally from a template whose starting balance, symbols, leverage and
+// fee tier then apply to it
+pub async fn create_account(
+    state: &AppState,
+    account_id: &str,
+    template: Option<String>,
+) -> Result<ServerMessage, String> {
+    let chosen = match &template {
+        Some(name) => Some(
+            state
+                .account_templates
+                .lock()
+                .await
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("Unknown account template {}", name))?,
+        ),
+        None => None,
+    };
+    let created = db::create_account(&state.pool, account_id, template.as_deref())
+        .await
+        .map_err(|e| format!("Error creating account: {}", e))?;
+    if !created {
+        return Err(format!("Account {} already exists", account_id));
+    }
+
+    let starting_balance = chosen
+        .as_ref()
+        .map(|template| template.starting_balance)
+        .unwrap_or_else(|| env_or("STARTING_BALANCE", 10_000.0));
+    apply_template(state, account_id, chosen.as_ref(), starting_balance).await;
+
+    Ok(ServerMessage::AccountCreated {
+        account_id: account_id.to_string(),
+        template,
+        starting_balance,
+    })
+}
+
+// Opens the in-memory portfolio of a new account with the template's balance and fees
+pub async fn apply_template(state: &AppState, account_id: &str, template: Option<&AccountTemplate>, cash: f64) {
+    if let Some(template) = template {
+        state.account_templates.lock().await.assign(account_id, &template.name);
+    }
+    {
+        let mut portfolios = state.portfolios.lock().await;
+        portfolios.open_account(account_id, cash);
+        if let Some(template) = template {
+            portfolios.set_fee_rates(account_id, fee_rates(&state.live_config, &template.fee_tier).unwrap_or_default());
+        }
+    }
+    if let Err(e) = state.ledger.record(&state.pool, account_id, AccountEvent::AccountOpened { cash }).await {
+        eprintln!("Error recording opening of account {}: {:?}", account_id, e);
+    }
+}
+
+// Pre-trade checks: competition trading windows, then the account template's allowed symbols and
+// max leverage. Orders that reduce gross exposure are always accepted so an over-levered account
+// can still close out.
+pub async fn check_order(state: &AppState, account_id: &str, order: &OrderRequest) -> Result<(), String> {
+    state.competitions.lock().await.check(account_id, now_millis())?;
+    let Some(template) = state.account_templates.lock().await.template_for(account_id).cloned() else {
+        return Ok(());
+    };
+    let symbol = order.symbol.to_uppercase();
+    if !template.allowed_symbols.is_empty() && !template.allowed_symbols.contains(&symbol) {
+        return Err(format!("{} is not tradable on the {} template", symbol, template.name));
+    }
+
+    let Some(max_leverage) = template.max_leverage else {
+        return Ok(());
+    };
+    let price = match order.price {
+        Some(price) => price,
+        None => state
+            .engine
+            .lock()
+            .await
+            .last_price(&symbol)
+            .ok_or_else(|| format!("No price for {} yet", symbol))?,
+    };
+    let report = state.portfolio_report(account_id).await;
+    let gross: f64 = report.positions.iter().map(|position| position.market_value.abs()).sum();
+    let current = report.positions.iter().find(|position| position.symbol == symbol);
+    let quantity = current.map(|position| position.quantity).unwrap_or_default();
+    let signed = match order.side {
+        Side::Buy => order.quantity,
+        Side::Sell => -order.quantity,
+    };
+    let gross_after = gross - current.map(|position| position.market_value.abs()).unwrap_or_default()
+        + ((quantity + signed) * price).abs();
+
+    if gross_after > gross && (report.equity <= 0.0 || gross_after / report.equity > max_leverage) {
+        return Err(format!(
+            "Order would take leverage to {:.2}x, the {} template allows {:.2}x",
+            gross_after / report.equity.max(f64::EPSILON),
+            template.name,
+            max_leverage
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct AccountLimits {
+    pub max_top_up: f64,         // Largest single top-up
+    pub daily_top_up_limit: f64, // Total top-ups allowed per account over a rolling day
+    pub max_resets_per_day: i64, // Resets allowed per account over a rolling day
+}
+
+impl AccountLimits {
+    pub fn from_env() -> Self {
+        AccountLimits {
+            max_top_up: env_or("TOP_UP_MAX", 100_000.0),
+            daily_top_up_limit: env_or("TOP_UP_DAILY_LIMIT", 100_000.0),
+            max_resets_per_day: env_or("RESETS_PER_DAY", 5),
+        }
+    }
+}
+
+// Balance an account starts with, and returns to when reset
+pub async fn starting_balance(state: &AppState, account_id: &str) -> Result<f64, sqlx::Error> {
+    if let Some(template) = state.account_templates.lock().await.template_for(account_id) {
+        return Ok(template.starting_balance);
+    }
+    match db::get_guest_starting_balance(&state.pool, account_id).await? {
+        Some(balance) => Ok(balance),
+        None => Ok(state.portfolios.lock().await.starting_balance()),
+    }
+}
+
+// Drops open orders and positions, moves the account's fills to the archive and restores the
+// starting balance
+pub async fn reset_account(state: &AppState, account_id: &str) -> Result<ServerMessage, String> {
+    if competitions::is_competition_account(account_id) {
+        return Err("Competition accounts can't be reset".to_string());
+    }
+    let limits = AccountLimits::from_env();
+    let recent = db::count_account_resets_since(&state.pool, account_id, now_millis() - DAY_MS)
+        .await
+        .map_err(|e| format!("Error checking resets: {}", e))?;
+    if recent >= limits.max_resets_per_day {
+        return Err(format!("Account {} can be reset at most {} times a day", account_id, limits.max_resets_per_day));
+    }
+
+    let starting_balance = starting_balance(state, account_id)
+        .await
+        .map_err(|e| format!("Error loading starting balance: {}", e))?;
+    let before = state.portfolio_report(account_id).await;
+
+    let (reset_id, archived_fills) = db::archive_account_fills(&state.pool, account_id, before.cash, before.equity)
+        .await
+        .map_err(|e| format!("Error archiving account history: {}", e))?;
+    let dropped = state.close_account_orders(account_id).await;
+    state.portfolios.lock().await.reset_account(account_id, starting_balance);
+    let reset = AccountEvent::AccountReset { cash: starting_balance };
+    if let Err(e) = state.ledger.record(&state.pool, account_id, reset).await {
+        eprintln!("Error recording reset of account {}: {:?}", account_id, e);
+    }
+    state.drawdowns.lock().await.reset(account_id);
+    println!(
+        "Reset account {} ({} fills archived, {} orders dropped)",
+        account_id, archived_fills, dropped
+    );
+
+    Ok(ServerMessage::AccountReset {
+        account_id: account_id.to_string(),
+        reset_id,
+        starting_balance,
+        archived_fills,
+        equity_before: before.equity,
+    })
+}
+
+pub async fn top_up(state: &AppState, account_id: &str, amount: f64) -> Result<ServerMessage, String> {
+    if competitions::is_competition_account(account_id) {
+        return Err("Competition accounts can't be topped up".to_string());
+    }
+    let limits = AccountLimits::from_env();
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err("Top-up amount must be positive".to_string());
+    }
+    if amount > limits.max_top_up {
+        return Err(format!("A single top-up is limited to {}", limits.max_top_up));
+    }
+    let recent = db::sum_top_ups_since(&state.pool, account_id, now_millis() - DAY_MS)
+        .await
+        .map_err(|e| format!("Error checking top-ups: {}", e))?;
+    if recent + amount > limits.daily_top_up_limit {
+        return Err(format!(
+            "Top-ups are limited to {} a day, {} is left",
+            limits.daily_top_up_limit,
+            (limits.daily_top_up_limit - recent).max(0.0)
+        ));
+    }
+
+    db::save_top_up(&state.pool, account_id, amount)
+        .await
+        .map_err(|e| format!("Error saving top-up: {}", e))?;
+    let cash = state.portfolios.lock().await.deposit(account_id, amount);
+    if let Err(e) = state.ledger.record(&state.pool, account_id, AccountEvent::FundsCredited { amount }).await {
+        eprintln!("Error recording top-up of account {}: {:?}", account_id, e);
+    }
+
+    Ok(ServerMessage::ToppedUp {
+        account_id: account_id.to_string(),
+        amount,
+        cash,
+    })
+}
@@ -0,0 +1,22 @@
+use crate::models::{Alert, ChannelKind};
+use crate::notify::Notification;
+use crate::state::AppState;
+
+// Push an alert to the account's connected sessions and its notification channels, plus the
+// alert's own webhook when one is set
+pub fn deliver(state: &AppState, alert: Alert, webhook_url: Option<String>) {
+    println!("Alert for {}: {}", alert.account_id, alert.message);
+
+    let notification = Notification {
+        account_id: alert.account_id.clone(),
+        title: format!("{} alert for {}", alert.kind, alert.account_id),
+        text: alert.message.clone(),
+        data: serde_json::to_value(&alert).unwrap_or_default(),
+    };
+    state
+        .notifications
+        .notify_account(notification, webhook_url.map(|url| (ChannelKind::Webhook, url)));
+
+    // No receivers simply means the account has no open session
+    let _ = state.alerts.send(alert);
+}
@@ -0,0 +1,152 @@
+use crate::config::env_or;
+use crate::models::AnalyticsResult;
+use crate::state::AppState;
+use crate::template::format_day;
+use duckdb::types::{TimeUnit, Value};
+use duckdb::Connection;
+use serde_json::json;
+use std::env;
+use std::path::PathBuf;
+use std::time::Instant;
+use tokio::time::Duration;
+
+pub const DEFAULT_ROW_LIMIT: usize = 1000;
+
+// Ad-hoc SQL over the Parquet dataset, run by an embedded DuckDB so research queries never reach
+// Postgres. The dataset is ANALYTICS_DATASET_DIR, or PARQUET_DATASET_DIR when unset, and appears as
+// a `candles` view with its `symbol` and `date` partition columns. Each query gets a fresh
+// in-memory database that can read nothing outside the dataset, write no files and not change
+// those limits, and is interrupted after ANALYTICS_TIMEOUT_SECS.
+pub struct AnalyticsConfig {
+    dataset_dir: PathBuf,
+    max_rows: usize,
+    timeout: Duration,
+    memory_limit: String,
+}
+
+impl AnalyticsConfig {
+    pub fn from_env() -> Option<Self> {
+        let dataset_dir = env::var("ANALYTICS_DATASET_DIR")
+            .or_else(|_| env::var("PARQUET_DATASET_DIR"))
+            .ok()
+            .filter(|dir| !dir.is_empty())?;
+        Some(AnalyticsConfig {
+            dataset_dir: PathBuf::from(dataset_dir),
+            max_rows: env_or("ANALYTICS_MAX_ROWS", 10_000usize).max(1),
+            timeout: Duration::from_secs(env_or("ANALYTICS_TIMEOUT_SECS", 30).max(1)),
+            memory_limit: env_or("ANALYTICS_MEMORY_LIMIT", "1GB".to_string()),
+        })
+    }
+}
+
+// At most `limit` rows of the query's result, capped at ANALYTICS_MAX_ROWS. `truncated` is set
+// when the query had more.
+pub async fn query(state: &AppState, sql: &str, limit: Option<usize>) -> Result<AnalyticsResult, String> {
+    let Some(config) = &state.analytics else {
+        return Err("Analytics are disabled, no Parquet dataset is configured".to_string());
+    };
+    let sql = sql.trim().trim_end_matches(';').trim();
+    if sql.is_empty() {
+        return Err("The query is empty".to_string());
+    }
+    let limit = limit.unwrap_or(DEFAULT_ROW_LIMIT).clamp(1, config.max_rows);
+
+    let connection = open(config).map_err(|e| format!("Error opening the analytics database: {}", e))?;
+    let interrupt = connection.interrupt_handle();
+    // Wrapping the query makes anything but a single SELECT fail to parse, and fetches one extra
+    // row to tell whether the result was cut off
+    let wrapped = format!("SELECT * FROM ({}\n) AS result LIMIT {}", sql, limit + 1);
+    let started = Instant::now();
+    let dir = quote(&config.dataset_dir.display().to_string());
+    let running = tokio::task::spawn_blocking(move || run(&connection, &dir, &wrapped));
+
+    match state.simulation.timeout(config.timeout, running).await {
+        Some(Ok(Ok((columns, mut rows)))) => {
+            let truncated = rows.len() > limit;
+            rows.truncate(limit);
+            Ok(AnalyticsResult {
+                columns,
+                rows,
+                truncated,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+            })
+        }
+        Some(Ok(Err(e))) => Err(format!("Query failed: {}", e)),
+        Some(Err(e)) => Err(format!("Query failed: {}", e)),
+        None => {
+            interrupt.interrupt();
+            Err(format!("Query took longer than {}s and was cancelled", config.timeout.as_secs()))
+        }
+    }
+}
+
+fn open(config: &AnalyticsConfig) -> duckdb::Result<Connection> {
+    let connection = Connection::open_in_memory()?;
+    connection.execute_batch(&format!(
+        "SET memory_limit = '{}';
+         SET allowed_directories = ['{}'];
+         SET enable_external_access = false;
+         SET lock_configuration = true;",
+        quote(&config.memory_limit),
+        quote(&config.dataset_dir.display().to_string()),
+    ))?;
+    Ok(connection)
+}
+
+// Escaped for use inside a single quoted SQL string
+fn quote(text: &str) -> String {
+    text.replace('\'', "''")
+}
+
+type Table = (Vec<String>, Vec<Vec<serde_json::Value>>);
+
+// Creating the view reads the dataset's file footers, so it happens here off the async runtime
+fn run(connection: &Connection, dir: &str, sql: &str) -> duckdb::Result<Table> {
+    connection.execute_batch(&format!(
+        "CREATE VIEW candles AS
+             SELECT * FROM read_parquet('{}/**/*.parquet', hive_partitioning = true, union_by_name = true);",
+        dir
+    ))?;
+    let mut statement = connection.prepare(sql)?;
+    let mut rows = statement.query([])?;
+    // Names are only known once the statement ran, and gone once its rows are used up
+    let columns = rows.as_ref().map(|statement| statement.column_names()).unwrap_or_default();
+    let mut table = Vec::new();
+    while let Some(row) = rows.next()? {
+        let values = (0..columns.len())
+            .map(|index| row.get::<_, Value>(index).map(to_json))
+            .collect::<duckdb::Result<_>>()?;
+        table.push(values);
+    }
+    Ok((columns, table))
+}
+
+// Timestamps come out as epoch milliseconds like everywhere else in the API
+fn to_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Boolean(value) => json!(value),
+        Value::TinyInt(value) => json!(value),
+        Value::SmallInt(value) => json!(value),
+        Value::Int(value) => json!(value),
+        Value::BigInt(value) => json!(value),
+        Value::UTinyInt(value) => json!(value),
+        Value::USmallInt(value) => json!(value),
+        Value::UInt(value) => json!(value),
+        Value::UBigInt(value) => json!(value),
+        Value::HugeInt(value) => json!(value.to_string()),
+        Value::Float(value) => json!(value),
+        Value::Double(value) => json!(value),
+        Value::Decimal(value) => json!(value.to_string()),
+        Value::Text(value) | Value::Enum(value) => json!(value),
+        Value::Timestamp(unit, value) => json!(match unit {
+            TimeUnit::Second => value * 1000,
+            TimeUnit::Millisecond => value,
+            TimeUnit::Microsecond => value / 1000,
+            TimeUnit::Nanosecond => value / 1_000_000,
+        }),
+        Value::Date32(days) => json!(format_day(days as i64)),
+        Value::List(values) => serde_json::Value::Array(values.into_iter().map(to_json).collect()),
+        other => json!(format!("{:?}", other)),
+    }
+}
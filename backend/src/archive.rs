@@ -0,0 +1,196 @@
+use crate::config::env_or;
+use crate::db;
+use crate::object_store::ObjectStore;
+use crate::scheduler::ScheduledJob;
+use crate::state::AppState;
+use async_trait::async_trait;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sqlx::PgPool;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+const ARCHIVE_QUEUE: usize = 10_000;
+const MILLIS_PER_HOUR: i64 = 3_600_000;
+
+// Where raw upstream messages are archived, selected with INGEST_ARCHIVE
+enum ArchiveTarget {
+    Database { pool: PgPool },
+    Files {
+        dir: PathBuf,
+        retention_hours: i64,
+        store: Option<Arc<ObjectStore>>, // Closed hourly files move here when object storage is set up
+    },
+}
+
+// Hands raw upstream messages to a background writer so archival never slows ingestion down
+pub struct IngestArchive {
+    sender: mpsc::Sender<(i64, String)>,
+}
+
+impl IngestArchive {
+    // INGEST_ARCHIVE=db stores gzip payloads in ingest_log, =files writes hourly gzip files
+    pub fn from_env(pool: &PgPool, store: Option<Arc<ObjectStore>>) -> Option<Self> {
+        let retention_hours = env_or("INGEST_ARCHIVE_RETENTION_HOURS", 72);
+        let target = match env_or("INGEST_ARCHIVE", "off".to_string()).as_str() {
+            "db" => ArchiveTarget::Database { pool: pool.clone() },
+            "files" => ArchiveTarget::Files {
+                dir: PathBuf::from(env_or("INGEST_ARCHIVE_DIR", "ingest_archive".to_string())),
+                retention_hours,
+                store,
+            },
+            _ => return None,
+        };
+
+        let (sender, receiver) = mpsc::channel(ARCHIVE_QUEUE);
+        tokio::spawn(run_writer(target, receiver));
+        Some(IngestArchive { sender })
+    }
+
+    pub fn record(&self, received_at: i64, payload: &str) {
+        if self.sender.try_send((received_at, payload.to_string())).is_err() {
+            eprintln!("Ingest archive queue full, raw message dropped");
+        }
+    }
+}
+
+async fn run_writer(target: ArchiveTarget, mut receiver: mpsc::Receiver<(i64, String)>) {
+    let mut hourly: Option<(i64, GzEncoder<File>)> = None;
+
+    while let Some((received_at, payload)) = receiver.recv().await {
+        let result = match &target {
+            ArchiveTarget::Database { pool } => match compress(&payload) {
+                Ok(compressed) => db::save_ingest_log(pool, received_at, &compressed)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+            ArchiveTarget::Files {
+                dir,
+                retention_hours,
+                store,
+            } => match write_file(dir, *retention_hours, &mut hourly, received_at, &payload) {
+                Ok(opened) => {
+                    if let (true, Some(store)) = (opened, store) {
+                        tokio::spawn(upload_closed(Arc::clone(store), dir.clone(), received_at / MILLIS_PER_HOUR));
+                    }
+                    Ok(())
+                }
+                Err(e) => Err(e.to_string()),
+            },
+        };
+        if let Err(e) = result {
+            eprintln!("Error archiving raw message: {}", e);
+        }
+    }
+}
+
+fn compress(payload: &str) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(payload.as_bytes())?;
+    encoder.finish()
+}
+
+// One gzip file per hour of "received_at<TAB>payload" lines, older files are removed past retention.
+// True when this message started a new file.
+fn write_file(
+    dir: &PathBuf,
+    retention_hours: i64,
+    hourly: &mut Option<(i64, GzEncoder<File>)>,
+    received_at: i64,
+    payload: &str,
+) -> io::Result<bool> {
+    let hour = received_at / MILLIS_PER_HOUR;
+    let opened = hourly.as_ref().is_none_or(|(open, _)| *open != hour);
+    if opened {
+        if let Some((_, encoder)) = hourly.take() {
+            encoder.finish()?;
+        }
+        fs::create_dir_all(dir)?;
+        // A restart within the same hour appends a new gzip member, which readers handle transparently
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("ingest-{}.log.gz", hour)))?;
+        *hourly = Some((hour, GzEncoder::new(file, Compression::default())));
+        remove_expired(dir, hour - retention_hours)?;
+    }
+
+    let (_, encoder) = hourly.as_mut().expect("archive file open");
+    writeln!(encoder, "{}\t{}", received_at, payload)?;
+    Ok(opened)
+}
+
+fn file_hour(name: &str) -> Option<i64> {
+    name.strip_prefix("ingest-")?.strip_suffix(".log.gz")?.parse().ok()
+}
+
+fn remove_expired(dir: &PathBuf, oldest_hour: i64) -> io::Result<()> {
+    for entry in fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+        let hour = entry.file_name().to_str().and_then(file_hour);
+        if hour.is_some_and(|hour| hour < oldest_hour) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+// Moves every file of an hour before `current_hour` to object storage, the one just closed and any
+// left behind by an earlier run or a failed upload. A file stays on disk until its upload succeeds.
+async fn upload_closed(store: Arc<ObjectStore>, dir: PathBuf, current_hour: i64) {
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Error listing archive files: {}", e);
+            return;
+        }
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if file_hour(&name).is_none_or(|hour| hour >= current_hour) {
+            continue;
+        }
+        let uploaded = match tokio::fs::read(entry.path()).await {
+            Ok(contents) => store.put(&format!("archive/{}", name), contents, "application/gzip").await,
+            Err(e) => Err(e.to_string()),
+        };
+        match uploaded.and_then(|_| fs::remove_file(entry.path()).map_err(|e| e.to_string())) {
+            Ok(()) => println!("Moved archive {} to object storage", name),
+            Err(e) => eprintln!("Error moving archive {} to object storage: {}", name, e),
+        }
+    }
+}
+
+// Archives in object storage older than OBJECT_STORE_ARCHIVE_RETENTION_DAYS are deleted once a day,
+// unset keeps them for good
+pub struct ArchiveRetentionJob;
+
+#[async_trait]
+impl ScheduledJob for ArchiveRetentionJob {
+    fn name(&self) -> &'static str {
+        "archive_retention"
+    }
+
+    fn every(&self) -> Duration {
+        Duration::from_secs(86_400)
+    }
+
+    async fn run(&self, state: &AppState, due_at: i64) -> Result<(), String> {
+        let days = env::var("OBJECT_STORE_ARCHIVE_RETENTION_DAYS").ok().and_then(|days| days.parse::<i64>().ok());
+        let (Some(store), Some(days)) = (&state.object_store, days) else {
+            return Ok(());
+        };
+        let deleted = store.expire("archive/", due_at - days * 24 * MILLIS_PER_HOUR).await?;
+        if deleted > 0 {
+            println!("Deleted {} archives older than {} days from object storage", deleted, days);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use crate::candles;
+use crate::config::env_or;
+use crate::data_quality;
+use crate::db;
+use crate::models::{Candle, Fill, Liquidity, Side};
+use crate::state::AppState;
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, TimestampMillisecondArray, UInt64Array,
+};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use axum::body::Bytes;
+use std::io;
+use std::mem;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+pub const CONTENT_TYPE: &str = "application/vnd.apache.arrow.stream";
+
+// Record batches buffered ahead of a slow client
+const STREAM_BUFFER: usize = 4;
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+// A history range to stream
+pub enum Dataset {
+    Candles { symbol: String, interval: String },
+    Fills { account_id: String }, // Authorized by the caller
+}
+
+impl Dataset {
+    fn schema(&self) -> SchemaRef {
+        let time = DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()));
+        let fields = match self {
+            Dataset::Candles { .. } => vec![
+                Field::new("open_time", time, false),
+                Field::new("open", DataType::Float64, false),
+                Field::new("high", DataType::Float64, false),
+                Field::new("low", DataType::Float64, false),
+                Field::new("close", DataType::Float64, false),
+                Field::new("volume", DataType::Float64, false),
+                Field::new("gap", DataType::Boolean, false),
+            ],
+            Dataset::Fills { .. } => vec![
+                Field::new("fill_id", DataType::UInt64, false),
+                Field::new("order_id", DataType::UInt64, false),
+                Field::new("symbol", DataType::Utf8, false),
+                Field::new("side", DataType::Utf8, false),
+                Field::new("price", DataType::Float64, false),
+                Field::new("quantity", DataType::Float64, false),
+                Field::new("liquidity", DataType::Utf8, false),
+                Field::new("created_at", time, false),
+            ],
+        };
+        Arc::new(Schema::new(fields))
+    }
+}
+
+// Checked before the response starts, errors after that can only cut the stream short
+pub fn validate(dataset: &Dataset, from: i64, to: i64) -> Result<(), String> {
+    if to <= from {
+        return Err("The range end must be after its start".to_string());
+    }
+    if let Dataset::Candles { interval, .. } = dataset {
+        if candles::interval_seconds(interval).is_none() {
+            return Err(format!("Unsupported candle interval {}", interval));
+        }
+    }
+    Ok(())
+}
+
+// The range as an Arrow IPC stream, `pyarrow.ipc.open_stream(response.raw).read_all()` on the
+// client side. Rows are loaded and sent a window at a time, candles ARROW_CHUNK_CANDLES buckets and
+// fills a day per record batch, so memory stays flat however long the range.
+pub fn stream(state: Arc<AppState>, dataset: Dataset, from: i64, to: i64) -> ReceiverStream<Result<Bytes, io::Error>> {
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    tokio::spawn(async move {
+        if let Err(e) = produce(&state, &dataset, from, to, &sender).await {
+            eprintln!("Error streaming Arrow history: {}", e);
+            let _ = sender.send(Err(io::Error::other(e))).await;
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+async fn produce(
+    state: &AppState,
+    dataset: &Dataset,
+    from: i64,
+    to: i64,
+    sender: &mpsc::Sender<Result<Bytes, io::Error>>,
+) -> Result<(), String> {
+    let schema = dataset.schema();
+    let mut writer = StreamWriter::try_new(Vec::new(), &schema).map_err(|e| e.to_string())?;
+    let window = match dataset {
+        Dataset::Candles { interval, .. } => {
+            candles::interval_seconds(interval).unwrap_or(60) * 1000 * env_or("ARROW_CHUNK_CANDLES", 10_000i64).max(1)
+        }
+        Dataset::Fills { .. } => MILLIS_PER_DAY,
+    };
+    let gaps = match dataset {
+        Dataset::Candles { symbol, .. } => db::get_data_gaps(&state.pool, std::slice::from_ref(symbol), from)
+            .await
+            .map_err(|e| format!("loading data gaps: {}", e))?,
+        Dataset::Fills { .. } => Vec::new(),
+    };
+
+    let mut start = from;
+    while start < to {
+        let end = (start + window).min(to);
+        let batch = match dataset {
+            Dataset::Candles { symbol, interval } => {
+                let interval_ms = candles::interval_seconds(interval).unwrap_or(60) * 1000;
+                let mut candles = db::get_bucketed_ticks(&state.pool, symbol, start, end, interval_ms / 1000)
+                    .await
+                    .map_err(|e| format!("loading candles: {}", e))?;
+                data_quality::mark_gaps(&mut candles, &gaps, interval_ms);
+                candle_batch(&schema, &candles)
+            }
+            Dataset::Fills { account_id } => {
+                let fills = db::get_fills_between(&state.pool, account_id, start, end)
+                    .await
+                    .map_err(|e| format!("loading fills: {}", e))?;
+                fill_batch(&schema, &fills)
+            }
+        }
+        .map_err(|e| e.to_string())?;
+        start = end;
+
+        if batch.num_rows() > 0 {
+            writer.write(&batch).map_err(|e| e.to_string())?;
+        }
+        // The first send also carries the schema message written when the stream opened
+        if !send(sender, &mut writer).await {
+            return Ok(()); // The client went away
+        }
+    }
+
+    writer.finish().map_err(|e| e.to_string())?;
+    send(sender, &mut writer).await;
+    Ok(())
+}
+
+// Hands whatever the writer buffered to the response body, false once the client is gone
+async fn send(sender: &mpsc::Sender<Result<Bytes, io::Error>>, writer: &mut StreamWriter<Vec<u8>>) -> bool {
+    let buffered = mem::take(writer.get_mut());
+    buffered.is_empty() || sender.send(Ok(Bytes::from(buffered))).await.is_ok()
+}
+
+fn candle_batch(schema: &SchemaRef, candles: &[Candle]) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMillisecondArray::from_iter_values(candles.iter().map(|c| c.open_time)).with_timezone("UTC")),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.open))),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.high))),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.low))),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.close))),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.volume))),
+        Arc::new(BooleanArray::from(candles.iter().map(|c| c.gap).collect::<Vec<_>>())),
+    ];
+    RecordBatch::try_new(Arc::clone(schema), columns)
+}
+
+fn fill_batch(schema: &SchemaRef, fills: &[Fill]) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(fills.iter().map(|f| f.fill_id))),
+        Arc::new(UInt64Array::from_iter_values(fills.iter().map(|f| f.order_id))),
+        Arc::new(StringArray::from_iter_values(fills.iter().map(|f| f.symbol.as_str()))),
+        Arc::new(StringArray::from_iter_values(fills.iter().map(|f| match f.side {
+            Side::Buy => "buy",
+            Side::Sell => "sell",
+        }))),
+        Arc::new(Float64Array::from_iter_values(fills.iter().map(|f| f.price))),
+        Arc::new(Float64Array::from_iter_values(fills.iter().map(|f| f.quantity))),
+        Arc::new(StringArray::from_iter_values(fills.iter().map(|f| match f.liquidity {
+            Liquidity::Maker => "maker",
+            Liquidity::Taker => "taker",
+        }))),
+        Arc::new(TimestampMillisecondArray::from_iter_values(fills.iter().map(|f| f.created_at)).with_timezone("UTC")),
+    ];
+    RecordBatch::try_new(Arc::clone(schema), columns)
+}
@@ -0,0 +1,48 @@
+use axum::http::{header, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use rust_embed::RustEmbed;
+
+// The built frontend (`pnpm build` in frontend/), compiled into release binaries and read from disk
+// in debug builds. A backend built without it serves the API only.
+#[derive(RustEmbed)]
+#[folder = "../frontend/dist/"]
+#[allow_missing = true]
+struct Frontend;
+
+const INDEX: &str = "index.html";
+
+// Fallback of the HTTP router: a frontend file, or the app shell for client-side routes
+pub async fn serve(uri: Uri) -> Response {
+    let path = uri.path().trim_start_matches('/');
+    let path = if path.is_empty() { INDEX } else { path };
+    if let Some(response) = asset(path) {
+        return response;
+    }
+    // Paths with an extension name a missing file, anything else is a route the app handles
+    let is_file = path.rsplit('/').next().is_some_and(|name| name.contains('.'));
+    match (is_file, asset(INDEX)) {
+        (false, Some(response)) => response,
+        _ => (StatusCode::NOT_FOUND, "Not found").into_response(),
+    }
+}
+
+fn asset(path: &str) -> Option<Response> {
+    let file = Frontend::get(path)?;
+    // Vite fingerprints everything under assets/, the shell must be revalidated to pick up new builds
+    let cache_control = if path.starts_with("assets/") {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+    Some(
+        (
+            [
+                (header::CONTENT_TYPE, file.metadata.mimetype().to_string()),
+                (header::CACHE_CONTROL, cache_control.to_string()),
+                (header::ETAG, format!("\"{}\"", hex::encode(file.metadata.sha256_hash()))),
+            ],
+            file.data,
+        )
+            .into_response(),
+    )
+}
@@ -0,0 +1,132 @@
+use crate::db;
+use crate::models::{AttributionReport, Fill, PnlBucket, Side};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+const MILLIS_PER_MINUTE: i64 = 60_000;
+const MILLIS_PER_HOUR: i64 = 3_600_000;
+const MILLIS_PER_DAY: i64 = 86_400_000;
+// The epoch fell on a Thursday
+const WEEKDAYS: [&str; 7] = ["thursday", "friday", "saturday", "sunday", "monday", "tuesday", "wednesday"];
+// Upper bound of each holding duration bucket, the last one is open-ended
+const HOLDING_BUCKETS: [(&str, i64); 5] = [
+    ("under_1m", MILLIS_PER_MINUTE),
+    ("1m_to_1h", MILLIS_PER_HOUR),
+    ("1h_to_1d", MILLIS_PER_DAY),
+    ("1d_to_1w", 7 * MILLIS_PER_DAY),
+    ("over_1w", i64::MAX),
+];
+
+// An open lot of a position, closed first in first out
+struct Lot {
+    quantity: f64, // Signed, negative for shorts
+    price: f64,
+    opened_at: i64,
+}
+
+impl PnlBucket {
+    fn new(key: String) -> Self {
+        PnlBucket {
+            key,
+            pnl: 0.0,
+            trades: 0,
+            wins: 0,
+        }
+    }
+
+    fn add(&mut self, pnl: f64) {
+        self.pnl += pnl;
+        self.trades += 1;
+        if pnl > 0.0 {
+            self.wins += 1;
+        }
+    }
+}
+
+// Realized P&L of an account, before fees, split by symbol, UTC hour and weekday of the closing
+// fill, and how long the closed lots were held. Every fill is replayed so lots opened before
+// `start_time` still carry their entry price, only closes inside the window are counted.
+pub async fn attribution_report(
+    pool: &PgPool,
+    account_id: &str,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+) -> Result<AttributionReport, sqlx::Error> {
+    let fills = db::get_fills_since(pool, account_id, 0).await?;
+    let start_time = start_time.unwrap_or(0);
+    let end_time = end_time.unwrap_or(i64::MAX);
+
+    let mut by_symbol: BTreeMap<String, PnlBucket> = BTreeMap::new();
+    let mut by_hour: Vec<PnlBucket> = (0..24).map(|hour| PnlBucket::new(format!("{:02}:00", hour))).collect();
+    let mut by_weekday: Vec<PnlBucket> = WEEKDAYS.iter().map(|day| PnlBucket::new(day.to_string())).collect();
+    let mut by_holding: Vec<PnlBucket> =
+        HOLDING_BUCKETS.iter().map(|(name, _)| PnlBucket::new(name.to_string())).collect();
+
+    let mut lots: HashMap<String, VecDeque<Lot>> = HashMap::new();
+    for fill in fills.iter().filter(|fill| fill.created_at <= end_time) {
+        let counted = fill.created_at >= start_time;
+        for (pnl, held_ms) in close_lots(lots.entry(fill.symbol.clone()).or_default(), fill) {
+            if !counted {
+                continue;
+            }
+            by_symbol
+                .entry(fill.symbol.clone())
+                .or_insert_with(|| PnlBucket::new(fill.symbol.clone()))
+                .add(pnl);
+            by_hour[((fill.created_at / MILLIS_PER_HOUR) % 24) as usize].add(pnl);
+            by_weekday[((fill.created_at / MILLIS_PER_DAY) % 7) as usize].add(pnl);
+            let bucket = HOLDING_BUCKETS
+                .iter()
+                .position(|(_, bound)| held_ms < *bound)
+                .unwrap_or(HOLDING_BUCKETS.len() - 1);
+            by_holding[bucket].add(pnl);
+        }
+    }
+
+    // Monday first
+    by_weekday.rotate_left(4);
+    let mut by_symbol: Vec<PnlBucket> = by_symbol.into_values().collect();
+    by_symbol.sort_by(|a, b| b.pnl.total_cmp(&a.pnl));
+
+    Ok(AttributionReport {
+        account_id: account_id.to_string(),
+        total_pnl: by_symbol.iter().map(|bucket| bucket.pnl).sum(),
+        trades: by_symbol.iter().map(|bucket| bucket.trades).sum(),
+        by_symbol,
+        by_hour,
+        by_weekday,
+        by_holding,
+    })
+}
+
+// Apply a fill to the symbol's lots, returning the P&L and holding time of every lot part it closed
+fn close_lots(lots: &mut VecDeque<Lot>, fill: &Fill) -> Vec<(f64, i64)> {
+    let mut remaining = match fill.side {
+        Side::Buy => fill.quantity,
+        Side::Sell => -fill.quantity,
+    };
+    let mut closed = Vec::new();
+    while let Some(lot) = lots.front_mut() {
+        if lot.quantity * remaining >= 0.0 {
+            break;
+        }
+        let quantity = lot.quantity.abs().min(remaining.abs());
+        closed.push((
+            quantity * (fill.price - lot.price) * lot.quantity.signum(),
+            fill.created_at - lot.opened_at,
+        ));
+        lot.quantity -= quantity * lot.quantity.signum();
+        remaining -= quantity * remaining.signum();
+        if lot.quantity.abs() < f64::EPSILON {
+            lots.pop_front();
+        }
+    }
+    if remaining.abs() >= f64::EPSILON {
+        lots.push_back(Lot {
+            quantity: remaining,
+            price: fill.price,
+            opened_at: fill.created_at,
+        });
+    }
+    closed
+}
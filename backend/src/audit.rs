@@ -0,0 +1,122 @@
+use crate::auth::Session;
+use crate::db;
+use crate::engine;
+use crate::models::{AuditEntry, ClientMessage, ServerMessage};
+use crate::state::AppState;
+use serde_json::json;
+
+// Name recorded for messages that change orders, accounts or settings, None for reads
+pub fn action(msg: &ClientMessage) -> Option<&'static str> {
+    let action = match msg {
+        ClientMessage::PlaceOrder { .. } => "place_order",
+        ClientMessage::PlaceOrderGroup { .. } => "place_order_group",
+        ClientMessage::CancelOrder { .. } => "cancel_order",
+        ClientMessage::CancelOrderGroup { .. } => "cancel_order_group",
+        ClientMessage::AmendOrder(_) => "amend_order",
+        ClientMessage::LadderOrder { .. } => "ladder_order",
+        ClientMessage::LadderCancel { .. } => "ladder_cancel",
+        ClientMessage::PlaceSpreadOrder { .. } => "place_spread_order",
+        ClientMessage::DefineSpread(_) => "define_spread",
+        ClientMessage::RemoveSpread { .. } => "remove_spread",
+        ClientMessage::StartGridBot(_) => "start_grid_bot",
+        ClientMessage::StopGridBot { .. } => "stop_grid_bot",
+        ClientMessage::StartBot(_) => "start_bot",
+        ClientMessage::StopBot { .. } => "stop_bot",
+        ClientMessage::PauseBot { .. } => "pause_bot",
+        ClientMessage::ResumeBot { .. } => "resume_bot",
+        ClientMessage::UpdateBot { .. } => "update_bot",
+        ClientMessage::Authenticate { .. } => "login",
+        ClientMessage::CreateGuest { .. } => "create_guest",
+        ClientMessage::CreateAccount { .. } => "create_account",
+        ClientMessage::SetAccountTemplate(_) => "set_account_template",
+        ClientMessage::CreateCompetition { .. } => "create_competition",
+        ClientMessage::EnrollCompetition { .. } => "enroll_competition",
+        ClientMessage::CreateTeam { .. } => "create_team",
+        ClientMessage::SetTeamMember { .. } => "set_team_member",
+        ClientMessage::SetTeamApproval { .. } => "set_team_approval",
+        ClientMessage::ApproveOrder { .. } => "approve_order",
+        ClientMessage::RejectOrder { .. } => "reject_order",
+        ClientMessage::RefreshSession { .. } => "refresh_session",
+        ClientMessage::Logout => "logout",
+        ClientMessage::RevokeSessions { .. } => "revoke_sessions",
+        ClientMessage::ResetAccount { .. } => "reset_account",
+        ClientMessage::TopUp { .. } => "top_up",
+        ClientMessage::SetPublicProfile { .. } => "set_public_profile",
+        ClientMessage::SetDrawdownAlert(_) => "set_drawdown_alert",
+        ClientMessage::SetDepegAlert(_) => "set_depeg_alert",
+        ClientMessage::SetSetting { .. } => "set_setting",
+        ClientMessage::SetDailyReport(_) => "set_daily_report",
+        ClientMessage::SetTradingRules(_) => "set_trading_rules",
+        ClientMessage::AddNotificationChannel(_) => "add_notification_channel",
+        ClientMessage::RemoveNotificationChannel(_) => "remove_notification_channel",
+        ClientMessage::CreateApiKey { .. } => "create_api_key",
+        ClientMessage::RevokeApiKey { .. } => "revoke_api_key",
+        ClientMessage::CreateUser { .. } => "create_user",
+        ClientMessage::SetUserRole { .. } => "set_user_role",
+        ClientMessage::Backfill { .. } => "backfill",
+        ClientMessage::ImportKlines { .. } => "import_klines",
+        ClientMessage::SimulateOutage { .. } => "simulate_outage",
+        ClientMessage::SetMaintenance { .. } => "set_maintenance",
+        ClientMessage::EndMaintenance { .. } => "end_maintenance",
+        ClientMessage::ReloadConfig { .. } => "reload_config",
+        ClientMessage::SetFeatureFlag { .. } => "set_feature_flag",
+        ClientMessage::SetTenant(_) => "set_tenant",
+        ClientMessage::RetryJob { .. } => "retry_job",
+        _ => return None,
+    };
+    Some(action)
+}
+
+// Appends the outcome of an audited message, failures are logged and never block the reply
+pub async fn record(state: &AppState, session: &Session, action: &str, account_id: Option<String>, reply: &ServerMessage) {
+    let actor = match reply {
+        ServerMessage::Authenticated { user_id, .. } => Some(user_id.clone()),
+        _ => session.user_id.clone(),
+    };
+    let (outcome, details) = match reply {
+        ServerMessage::Error { message } => ("rejected", json!({ "error": message })),
+        ServerMessage::RateLimited { message, .. } | ServerMessage::ExchangeError { message, .. } => {
+            ("rejected", json!({ "error": message }))
+        }
+        // Never keep secrets that are only meant to be shown once
+        ServerMessage::ApiKeyCreated { key, .. } => ("ok", json!(key)),
+        ServerMessage::UserCreated {
+            user_id,
+            role,
+            tenant_id,
+            ..
+        } => ("ok", json!({ "user_id": user_id, "role": role, "tenant_id": tenant_id })),
+        ServerMessage::GuestCreated {
+            user_id,
+            starting_balance,
+            ..
+        } => ("ok", json!({ "user_id": user_id, "starting_balance": starting_balance })),
+        ServerMessage::Authenticated {
+            role,
+            key,
+            session_id,
+            tenant_id,
+            ..
+        } => ("ok", json!({ "role": role, "key": key, "session_id": session_id, "tenant_id": tenant_id })),
+        ServerMessage::SessionRefreshed(tokens) => ("ok", json!({ "session_id": tokens.session_id })),
+        ServerMessage::PublicProfileSet {
+            token, delay_secs, ..
+        } => ("ok", json!({ "published": token.is_some(), "delay_secs": delay_secs })),
+        reply => ("ok", serde_json::to_value(reply).unwrap_or_default()),
+    };
+
+    let entry = AuditEntry {
+        id: 0,
+        actor: actor.unwrap_or_else(|| "anonymous".to_string()),
+        key_id: session.key.as_ref().map(|key| key.key_id.clone()),
+        ip: session.ip.clone(),
+        action: action.to_string(),
+        account_id,
+        outcome: outcome.to_string(),
+        details,
+        created_at: engine::now_millis(),
+    };
+    if let Err(e) = db::save_audit_entry(&state.pool, &entry).await {
+        eprintln!("Error saving audit entry {} by {}: {:?}", entry.action, entry.actor, e);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::config::env_or;
+use crate::db;
+use crate::handlers;
+use crate::engine;
+use crate::models::{ApiKey, ApiScope, ClientMessage, Role, SessionTokens};
+use crate::state::AppState;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+const API_KEY_PREFIX: &str = "ak_";
+const ACCESS_TOKEN_PREFIX: &str = "at.";
+const REFRESH_TOKEN_PREFIX: &str = "rt.";
+
+// Who is behind a connection. Connections start anonymous with DEFAULT_ROLE, read-only unless set,
+// and are upgraded by an Authenticate message or a `?token=` query on the upgrade request.
+// Accounts outside tenants and teams have no owner, so anonymous sessions must not trade them.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user_id: Option<String>,
+    pub role: Role,
+    pub key: Option<ApiKey>, // Further limits what the session can do when it authenticated with a key
+    pub ip: Option<String>,  // Peer address, recorded in the audit log
+    pub session_id: Option<String>, // Login session the connection's tokens belong to
+    pub tenant_id: Option<String>,  // Limits the session to the tenant's accounts
+    pub connection_id: u64,
+}
+
+impl Session {
+    pub fn anonymous() -> Self {
+        Session {
+            user_id: None,
+            role: env_or("DEFAULT_ROLE", Role::ReadOnly),
+            key: None,
+            ip: None,
+            session_id: None,
+            tenant_id: None,
+            connection_id: 0,
+        }
+    }
+
+    // What the connection continues as once its login ended
+    pub fn logged_out(&self) -> Self {
+        Session {
+            ip: self.ip.clone(),
+            connection_id: self.connection_id,
+            ..Session::anonymous()
+        }
+    }
+}
+
+// Logins revoked while connections may still hold them, pushed so those connections log out too
+#[derive(Debug, Clone)]
+pub enum Revocation {
+    Session(String),
+    User(String), // Every session of the user
+    Key(String),  // Sessions started with the API key
+}
+
+impl Revocation {
+    pub fn ends(&self, session: &Session) -> bool {
+        match self {
+            Revocation::Session(session_id) => session.session_id.as_ref() == Some(session_id),
+            Revocation::User(user_id) => session.user_id.as_ref() == Some(user_id),
+            Revocation::Key(key_id) => session.key.as_ref().is_some_and(|key| &key.key_id == key_id),
+        }
+    }
+}
+
+// Lowest role allowed to send a message
+pub fn required_role(msg: &ClientMessage) -> Role {
+    match msg {
+        ClientMessage::Authenticate { .. }
+        | ClientMessage::RefreshSession { .. }
+        | ClientMessage::Logout
+        | ClientMessage::Candles { account_id: None, .. }
+        | ClientMessage::IngestionStats
+        | ClientMessage::Subscribe { .. }
+        | ClientMessage::Unsubscribe { .. }
+        | ClientMessage::SubscribeIndex(_)
+        | ClientMessage::UnsubscribeIndex { .. }
+        | ClientMessage::UnsubscribeSpread { .. }
+        | ClientMessage::SubscribeDepeg
+        | ClientMessage::UnsubscribeDepeg
+        | ClientMessage::OrderBook { .. }
+        | ClientMessage::UnsubscribeLadder { .. }
+        | ClientMessage::SubscribeTrades { .. }
+        | ClientMessage::UnsubscribeTrades { .. }
+        | ClientMessage::TradeHistory { .. }
+        | ClientMessage::Footprint { .. }
+        | ClientMessage::SubscribeIndicator { .. }
+        | ClientMessage::UnsubscribeIndicator { .. }
+        | ClientMessage::KeyLevels { .. }
+        | ClientMessage::SubscribeKeyLevels { .. }
+        | ClientMessage::UnsubscribeKeyLevels { .. } => Role::ReadOnly,
+        ClientMessage::CreateGuest { .. }
+        | ClientMessage::ListAccountTemplates
+        | ClientMessage::ListCompetitions
+        | ClientMessage::CompetitionStandings { .. } => Role::ReadOnly,
+        ClientMessage::CreateApiKey { .. } | ClientMessage::RevokeApiKey { .. } | ClientMessage::ListApiKeys => {
+            Role::ReadOnly
+        }
+        ClientMessage::Backfill { .. }
+        | ClientMessage::ImportKlines { .. }
+        | ClientMessage::SimulateOutage { .. }
+        | ClientMessage::SetMaintenance { .. }
+        | ClientMessage::EndMaintenance { .. }
+        | ClientMessage::ReloadConfig { .. }
+        | ClientMessage::SetFeatureFlag { .. }
+        | ClientMessage::ListFeatureFlags { .. }
+        | ClientMessage::SetTenant(_)
+        | ClientMessage::ListTenants
+        | ClientMessage::StorageUsage { .. }
+        | ClientMessage::AuditLog { .. }
+        | ClientMessage::UsageStats { .. }
+        | ClientMessage::ListJobs { .. }
+        | ClientMessage::RetryJob { .. }
+        | ClientMessage::SetAccountTemplate(_)
+        | ClientMessage::CreateCompetition { .. }
+        | ClientMessage::RevokeSessions { .. }
+        | ClientMessage::CreateUser { .. }
+        | ClientMessage::SetUserRole { .. } => Role::Admin,
+        _ => Role::User,
+    }
+}
+
+// Scope an API key needs to send a message
+pub fn required_scope(msg: &ClientMessage) -> Option<ApiScope> {
+    match msg {
+        ClientMessage::Authenticate { .. } | ClientMessage::RefreshSession { .. } | ClientMessage::Logout => None,
+        // Reads market data only, but needs a signed-in user for its cost
+        ClientMessage::AnalyticsQuery { .. } => Some(ApiScope::ReadMarket),
+        ClientMessage::PlaceOrder { .. }
+        | ClientMessage::PlaceOrderGroup { .. }
+        | ClientMessage::CancelOrder { .. }
+        | ClientMessage::CancelOrderGroup { .. }
+        | ClientMessage::AmendOrder(_)
+        | ClientMessage::LadderOrder { .. }
+        | ClientMessage::LadderCancel { .. }
+        | ClientMessage::PlaceSpreadOrder { .. }
+        | ClientMessage::StartGridBot(_)
+        | ClientMessage::StopGridBot { .. }
+        | ClientMessage::StartBot(_)
+        | ClientMessage::StopBot { .. }
+        | ClientMessage::PauseBot { .. }
+        | ClientMessage::ResumeBot { .. }
+        | ClientMessage::UpdateBot { .. }
+        | ClientMessage::ApproveOrder { .. }
+        | ClientMessage::RejectOrder { .. } => Some(ApiScope::Trade),
+        msg if required_role(msg) == Role::ReadOnly && !is_key_management(msg) => Some(ApiScope::ReadMarket),
+        _ => Some(ApiScope::ManageAccount),
+    }
+}
+
+fn is_key_management(msg: &ClientMessage) -> bool {
+    matches!(
+        msg,
+        ClientMessage::CreateApiKey { .. } | ClientMessage::RevokeApiKey { .. } | ClientMessage::ListApiKeys
+    )
+}
+
+pub fn authorize(state: &AppState, session: &Session, msg: &ClientMessage) -> Result<(), String> {
+    if let Some(key) = &session.key {
+        if let Some(scope) = required_scope(msg) {
+            if !key.scopes.contains(&scope) {
+                return Err(format!("API key {} lacks the {} scope", key.key_id, scope.name()));
+            }
+        }
+        if let (Some(allowed), Some(account_id)) = (&key.account_id, handlers::message_account(msg)) {
+            if allowed != account_id {
+                return Err(format!("API key {} cannot act on account {}", key.key_id, account_id));
+            }
+        }
+    }
+
+    let required = required_role(msg);
+    if session.role >= required {
+        return Ok(());
+    }
+
+    // The shared ADMIN_TOKEN inside admin messages still works for deployments without users
+    let admin_token = match msg {
+        ClientMessage::Backfill { admin_token, .. }
+        | ClientMessage::ImportKlines { admin_token, .. }
+        | ClientMessage::SimulateOutage { admin_token, .. }
+        | ClientMessage::SetMaintenance { admin_token, .. }
+        | ClientMessage::EndMaintenance { admin_token }
+        | ClientMessage::ReloadConfig { admin_token }
+        | ClientMessage::SetFeatureFlag { admin_token, .. }
+        | ClientMessage::ListFeatureFlags { admin_token } => admin_token.as_deref(),
+        _ => None,
+    };
+    if required == Role::Admin && admin_token.is_some_and(|token| state.is_admin(token)) {
+        return Ok(());
+    }
+
+    Err(format!("The {} role is required for this request", required.name()))
+}
+
+pub fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    hex::encode(bytes)
+}
+
+// New key id and the full `<key_id>.<secret>` token handed to the user
+pub fn generate_api_key() -> (String, String) {
+    let id: [u8; 8] = rand::thread_rng().gen();
+    let key_id = format!("{}{}", API_KEY_PREFIX, hex::encode(id));
+    let token = format!("{}.{}", key_id, generate_token());
+    (key_id, token)
+}
+
+pub fn hash_secret(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Resolves a token to the session it grants. A user token or API key is a full login and also returns
+// fresh session tokens, an access token resumes its existing login session.
+pub async fn authenticate(state: &AppState, token: &str) -> Result<Option<(Session, Option<SessionTokens>)>, sqlx::Error> {
+    if let Some(session_id) = verify_access_token(state, token) {
+        return Ok(resume_session(state, &session_id).await?.map(|session| (session, None)));
+    }
+
+    let (user_id, role, key) = if let Some((key_id, _)) = token.split_once('.').filter(|(id, _)| id.starts_with(API_KEY_PREFIX)) {
+        let Some((key, secret_hash, role)) = db::get_active_api_key(&state.pool, key_id).await? else {
+            return Ok(None);
+        };
+        if secret_hash != hash_secret(token) {
+            return Ok(None);
+        }
+        (key.user_id.clone(), role, Some(key))
+    } else {
+        match db::get_user_by_token(&state.pool, &hash_secret(token)).await? {
+            Some((user_id, role)) => (user_id, role, None),
+            None => return Ok(None),
+        }
+    };
+
+    let tenant_id = db::get_user_tenant(&state.pool, &user_id).await?;
+    let session_id = generate_token();
+    db::create_auth_session(&state.pool, &session_id, &user_id, key.as_ref().map(|key| key.key_id.as_str())).await?;
+    let tokens = issue_tokens(state, &session_id).await?;
+    let session = Session {
+        user_id: Some(user_id),
+        role,
+        key,
+        session_id: Some(session_id),
+        tenant_id,
+        ..Session::anonymous()
+    };
+    Ok(Some((session, Some(tokens))))
+}
+
+// Session of a request-per-call API from its `Bearer <token>` authorization header, anonymous
+// without one. The caller fills in the peer address and connection id.
+pub async fn bearer_session(state: &AppState, header: Option<&str>) -> Result<Session, String> {
+    let Some(header) = header else {
+        return Ok(Session::anonymous());
+    };
+    let token = header
+        .strip_prefix("Bearer ")
+        .ok_or("Expected authorization: Bearer <token>")?;
+    match authenticate(state, token).await {
+        Ok(Some((session, _))) => Ok(session),
+        Ok(None) => Err("Invalid token".to_string()),
+        Err(e) => Err(format!("Error checking token: {}", e)),
+    }
+}
+
+// For a connection that missed revocations, whether its login still stands. A failed check keeps it.
+pub async fn session_active(state: &AppState, session: &Session) -> bool {
+    match &session.session_id {
+        Some(session_id) => !matches!(resume_session(state, session_id).await, Ok(None)),
+        None => true,
+    }
+}
+
+// Session for a login that hasn't been revoked, with the user's current role and key
+async fn resume_session(state: &AppState, session_id: &str) -> Result<Option<Session>, sqlx::Error> {
+    let Some((user_id, key_id)) = db::get_active_auth_session(&state.pool, session_id).await? else {
+        return Ok(None);
+    };
+    let Some(role) = db::get_user_role(&state.pool, &user_id).await? else {
+        return Ok(None);
+    };
+    let key = match key_id {
+        // A revoked key ends every session started with it
+        Some(key_id) => match db::get_active_api_key(&state.pool, &key_id).await? {
+            Some((key, _, _)) => Some(key),
+            None => return Ok(None),
+        },
+        None => None,
+    };
+    let tenant_id = db::get_user_tenant(&state.pool, &user_id).await?;
+
+    Ok(Some(Session {
+        user_id: Some(user_id),
+        role,
+        key,
+        session_id: Some(session_id.to_string()),
+        tenant_id,
+        ..Session::anonymous()
+    }))
+}
+
+// Exchanges a refresh token for new tokens. Presenting an already used refresh token means it was
+// copied, so the whole session is revoked.
+pub async fn refresh_session(state: &AppState, refresh_token: &str) -> Result<SessionTokens, String> {
+    let used = db::use_refresh_token(&state.pool, &hash_secret(refresh_token))
+        .await
+        .map_err(|e| format!("Error checking refresh token: {}", e))?;
+    let session_id = match used {
+        db::RefreshTokenUse::Fresh { session_id, expires_at } if expires_at > engine::now_millis() => session_id,
+        db::RefreshTokenUse::Fresh { .. } => return Err("Refresh token expired".to_string()),
+        db::RefreshTokenUse::Reused { session_id } => {
+            eprintln!("Refresh token reused, revoking session {}", session_id);
+            if let Err(e) = db::revoke_auth_session(&state.pool, &session_id).await {
+                eprintln!("Error revoking session {}: {:?}", session_id, e);
+            }
+            let _ = state.revocations.send(Revocation::Session(session_id));
+            return Err("Refresh token already used, the session has been revoked".to_string());
+        }
+        db::RefreshTokenUse::Unknown => return Err("Invalid refresh token".to_string()),
+    };
+
+    match resume_session(state, &session_id).await {
+        Ok(Some(_)) => issue_tokens(state, &session_id)
+            .await
+            .map_err(|e| format!("Error issuing tokens: {}", e)),
+        Ok(None) => Err("Session has been revoked".to_string()),
+        Err(e) => Err(format!("Error checking session: {}", e)),
+    }
+}
+
+async fn issue_tokens(state: &AppState, session_id: &str) -> Result<SessionTokens, sqlx::Error> {
+    let now = engine::now_millis();
+    let access_expires_at = now + env_or("ACCESS_TOKEN_TTL_SECS", 900i64) * 1000;
+    let refresh_expires_at = now + env_or("REFRESH_TOKEN_TTL_SECS", 30 * 86_400i64) * 1000;
+
+    let refresh_token = format!("{}{}", REFRESH_TOKEN_PREFIX, generate_token());
+    db::save_refresh_token(&state.pool, &hash_secret(&refresh_token), session_id, refresh_expires_at).await?;
+
+    let claims = format!("{}.{}", session_id, access_expires_at);
+    Ok(SessionTokens {
+        session_id: session_id.to_string(),
+        access_token: format!("{}{}.{}", ACCESS_TOKEN_PREFIX, claims, sign(state, &claims)),
+        access_expires_at,
+        refresh_token,
+        refresh_expires_at,
+    })
+}
+
+fn sign(state: &AppState, claims: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&state.token_secret).expect("HMAC accepts keys of any length");
+    mac.update(claims.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// Session id of an `at.<session_id>.<expires_at>.<signature>` token that is signed by us and unexpired
+fn verify_access_token(state: &AppState, token: &str) -> Option<String> {
+    let (claims, signature) = token.strip_prefix(ACCESS_TOKEN_PREFIX)?.rsplit_once('.')?;
+    let (session_id, expires_at) = claims.split_once('.')?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&state.token_secret).ok()?;
+    mac.update(claims.as_bytes());
+    mac.verify_slice(&hex::decode(signature).ok()?).ok()?;
+
+    (expires_at.parse::<i64>().ok()? > engine::now_millis()).then(|| session_id.to_string())
+}
+
+// `token` parameter of an upgrade request's query string
+pub fn query_token(query: Option<&str>) -> Option<String> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "token")
+        .map(|(_, value)| value.to_string())
+}
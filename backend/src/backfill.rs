@@ -0,0 +1,180 @@
+use crate::candles::interval_seconds;
+use crate::db;
+use crate::execution::binance::BinanceEndpoints;
+use crate::engine::now_millis;
+use sqlx::PgPool;
+use std::error::Error;
+use std::time::Instant;
+
+// Binance returns at most 1500 klines per request
+const KLINES_PER_REQUEST: i64 = 1500;
+// Rows buffered before each COPY round
+const COPY_BATCH_ROWS: usize = 50_000;
+
+#[derive(Debug, Clone)]
+pub struct Kline {
+    pub open_time: i64, // Epoch milliseconds
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub quote_volume: f64,
+}
+
+// Prints running totals as batches land, with a percentage when the total is known
+struct Progress {
+    label: String,
+    started: Instant,
+    loaded: u64,
+}
+
+impl Progress {
+    fn new(label: String) -> Self {
+        Progress {
+            label,
+            started: Instant::now(),
+            loaded: 0,
+        }
+    }
+
+    fn add(&mut self, rows: u64, fraction_done: Option<f64>) {
+        self.loaded += rows;
+        let rate = self.loaded as f64 / self.started.elapsed().as_secs_f64().max(0.001);
+        match fraction_done {
+            Some(fraction) => println!(
+                "{}: {} rows loaded ({:.1}%, {:.0} rows/s)",
+                self.label,
+                self.loaded,
+                fraction * 100.0,
+                rate
+            ),
+            None => println!("{}: {} rows loaded ({:.0} rows/s)", self.label, self.loaded, rate),
+        }
+    }
+
+    fn finish(&self) {
+        println!(
+            "{}: done, {} rows in {:.1}s",
+            self.label,
+            self.loaded,
+            self.started.elapsed().as_secs_f64()
+        );
+    }
+}
+
+// Page through the public klines endpoint and COPY the history into the klines table
+pub async fn backfill_klines(
+    pool: &PgPool,
+    symbol: &str,
+    interval: &str,
+    start_time: i64,
+    end_time: Option<i64>,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let interval_ms = interval_seconds(interval).ok_or("unsupported interval")? * 1000;
+    let end_time = end_time.unwrap_or_else(now_millis);
+    let url = format!("{}/fapi/v1/klines", BinanceEndpoints::futures_mainnet_data().rest_url);
+    let client = reqwest::Client::new();
+
+    let mut progress = Progress::new(format!("Backfill {} {}", symbol, interval));
+    let mut buffer: Vec<Kline> = Vec::with_capacity(COPY_BATCH_ROWS);
+    let mut cursor = start_time;
+
+    while cursor < end_time {
+        let page: Vec<Vec<serde_json::Value>> = client
+            .get(&url)
+            .query(&[
+                ("symbol", symbol.to_string()),
+                ("interval", interval.to_string()),
+                ("startTime", cursor.to_string()),
+                ("endTime", end_time.to_string()),
+                ("limit", KLINES_PER_REQUEST.to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let Some(last_open) = page.last().and_then(|row| row.first()).and_then(|v| v.as_i64()) else {
+            break;
+        };
+        buffer.extend(page.iter().filter_map(|row| parse_rest_kline(row)));
+        cursor = last_open + interval_ms;
+
+        if buffer.len() >= COPY_BATCH_ROWS || cursor >= end_time {
+            let rows = db::copy_klines(pool, symbol, interval, &buffer).await?;
+            buffer.clear();
+            let fraction = (cursor - start_time) as f64 / (end_time - start_time).max(1) as f64;
+            progress.add(rows, Some(fraction.min(1.0)));
+        }
+    }
+
+    if !buffer.is_empty() {
+        let rows = db::copy_klines(pool, symbol, interval, &buffer).await?;
+        progress.add(rows, Some(1.0));
+    }
+    progress.finish();
+    Ok(progress.loaded)
+}
+
+// Load a Binance public data dump (data.binance.vision klines CSV) from the server's filesystem
+pub async fn import_klines_csv(
+    pool: &PgPool,
+    symbol: &str,
+    interval: &str,
+    path: &str,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    let total_lines = contents.lines().count().max(1);
+
+    let mut progress = Progress::new(format!("Import {} {} from {}", symbol, interval, path));
+    let mut buffer: Vec<Kline> = Vec::with_capacity(COPY_BATCH_ROWS);
+
+    for (index, line) in contents.lines().enumerate() {
+        // Newer dumps start with a header row, which does not parse and is skipped
+        if let Some(kline) = parse_csv_kline(line) {
+            buffer.push(kline);
+        }
+        if buffer.len() >= COPY_BATCH_ROWS {
+            let rows = db::copy_klines(pool, symbol, interval, &buffer).await?;
+            buffer.clear();
+            progress.add(rows, Some((index + 1) as f64 / total_lines as f64));
+        }
+    }
+
+    if !buffer.is_empty() {
+        let rows = db::copy_klines(pool, symbol, interval, &buffer).await?;
+        progress.add(rows, Some(1.0));
+    }
+    progress.finish();
+    Ok(progress.loaded)
+}
+
+// [openTime, "open", "high", "low", "close", "volume", closeTime, "quoteVolume", ...]
+fn parse_rest_kline(row: &[serde_json::Value]) -> Option<Kline> {
+    let number = |index: usize| -> Option<f64> { row.get(index)?.as_str()?.parse().ok() };
+    Some(Kline {
+        open_time: row.first()?.as_i64()?,
+        open: number(1)?,
+        high: number(2)?,
+        low: number(3)?,
+        close: number(4)?,
+        volume: number(5)?,
+        quote_volume: number(7)?,
+    })
+}
+
+fn parse_csv_kline(line: &str) -> Option<Kline> {
+    let fields: Vec<&str> = line.trim().split(',').collect();
+    let number = |index: usize| -> Option<f64> { fields.get(index)?.parse().ok() };
+    Some(Kline {
+        open_time: fields.first()?.parse().ok()?,
+        open: number(1)?,
+        high: number(2)?,
+        low: number(3)?,
+        close: number(4)?,
+        volume: number(5)?,
+        quote_volume: number(7)?,
+    })
+}
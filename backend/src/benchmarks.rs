@@ -0,0 +1,178 @@
+use crate::accounts;
+use crate::db;
+use crate::exposure::BENCHMARK_SYMBOL;
+use crate::models::{BenchmarkPoint, BenchmarkReport, Liquidity, Side, TickerQuery};
+use crate::state::AppState;
+use std::collections::{BTreeSet, HashMap};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+pub const DEFAULT_BENCHMARK_DAYS: i64 = 90;
+pub const MAX_BENCHMARK_DAYS: i64 = 365;
+// Symbols in the equal-weight index, the most traded by 24h quote volume
+const INDEX_SIZE: i64 = 10;
+
+// A passive strategy started with the account's equity on its first priced day, later top-ups buy
+// more of it the day they arrive
+#[derive(Default)]
+struct Benchmark {
+    units: HashMap<String, f64>,
+    invested: Option<f64>, // None until the first investment
+}
+
+impl Benchmark {
+    // Spread `amount` equally over the symbols that have a price, nothing happens if none has
+    fn invest(&mut self, amount: f64, symbols: &[String], marks: &HashMap<String, f64>) {
+        let priced: Vec<(&String, f64)> = symbols
+            .iter()
+            .filter_map(|symbol| marks.get(symbol).filter(|price| **price > 0.0).map(|price| (symbol, *price)))
+            .collect();
+        if priced.is_empty() {
+            return;
+        }
+        let share = amount / priced.len() as f64;
+        for (symbol, price) in priced {
+            *self.units.entry(symbol.clone()).or_default() += share / price;
+        }
+        *self.invested.get_or_insert(0.0) += amount;
+    }
+
+    fn value(&self, marks: &HashMap<String, f64>) -> Option<f64> {
+        self.invested.map(|_| {
+            self.units
+                .iter()
+                .map(|(symbol, units)| units * marks.get(symbol).copied().unwrap_or_default())
+                .sum()
+        })
+    }
+}
+
+// Gain over everything put in, in percent
+fn return_pct(value: Option<f64>, invested: Option<f64>) -> Option<f64> {
+    value
+        .zip(invested.filter(|invested| *invested > 0.0))
+        .map(|(value, invested)| (value / invested - 1.0) * 100.0)
+}
+
+// Daily equity of the account since its last reset, valued at the stored daily closes, next to
+// buy-and-hold BTCUSDT and an equal-weight basket of the most traded symbols over the same days
+pub async fn benchmark_report(state: &AppState, account_id: &str, days: Option<i64>) -> Result<BenchmarkReport, String> {
+    let days = days.unwrap_or(DEFAULT_BENCHMARK_DAYS).clamp(1, MAX_BENCHMARK_DAYS);
+    let pool = &state.pool;
+
+    let fills = db::get_fills_since(pool, account_id, 0)
+        .await
+        .map_err(|e| format!("Error loading fills: {}", e))?;
+    let reset_at = db::get_last_reset_time(pool, account_id)
+        .await
+        .map_err(|e| format!("Error loading resets: {}", e))?
+        .unwrap_or(0);
+    let top_ups = db::get_top_ups_since(pool, account_id, reset_at)
+        .await
+        .map_err(|e| format!("Error loading top-ups: {}", e))?;
+    let starting_balance = accounts::starting_balance(state, account_id)
+        .await
+        .map_err(|e| format!("Error loading starting balance: {}", e))?;
+    let rates = state.portfolios.lock().await.fee_rates(account_id);
+
+    let index_symbols: Vec<String> = db::get_latest_tickers(pool, 1, INDEX_SIZE, &TickerQuery::default())
+        .await
+        .map_err(|e| format!("Error loading index symbols: {}", e))?
+        .data
+        .into_iter()
+        .map(|ticker| ticker.symbol)
+        .collect();
+    let btc = vec![BENCHMARK_SYMBOL.to_string()];
+
+    let mut symbols: BTreeSet<String> = fills.iter().map(|fill| fill.symbol.clone()).collect();
+    symbols.extend(index_symbols.iter().cloned());
+    symbols.extend(btc.iter().cloned());
+    let closes = db::get_daily_closes(pool, &symbols.into_iter().collect::<Vec<_>>(), days)
+        .await
+        .map_err(|e| format!("Error loading daily closes: {}", e))?;
+    let closes_by_day: HashMap<(&str, i64), f64> = closes
+        .iter()
+        .flat_map(|(symbol, series)| series.iter().map(move |(day, close)| ((symbol.as_str(), *day), *close)))
+        .collect();
+    let calendar: BTreeSet<i64> = closes
+        .values()
+        .flatten()
+        .map(|(day, _)| *day)
+        .filter(|day| day + MILLIS_PER_DAY > reset_at)
+        .collect();
+
+    let mut cash = starting_balance;
+    let mut positions: HashMap<String, f64> = HashMap::new();
+    let mut marks: HashMap<String, f64> = HashMap::new();
+    let (mut next_fill, mut next_top_up) = (0, 0);
+    let mut invested: Option<f64> = None;
+    let mut hold_btc = Benchmark::default();
+    let mut equal_weight = Benchmark::default();
+    let mut points = Vec::with_capacity(calendar.len());
+
+    for day in calendar {
+        let day_end = day + MILLIS_PER_DAY;
+
+        // Fills first price their symbols, the day's close then overrides them where stored
+        while let Some(fill) = fills.get(next_fill).filter(|fill| fill.created_at < day_end) {
+            let signed = match fill.side {
+                Side::Buy => fill.quantity,
+                Side::Sell => -fill.quantity,
+            };
+            let fee_rate = match fill.liquidity {
+                Liquidity::Maker => rates.maker,
+                Liquidity::Taker => rates.taker,
+            };
+            cash -= signed * fill.price + fill.quantity * fill.price * fee_rate;
+            *positions.entry(fill.symbol.clone()).or_default() += signed;
+            marks.insert(fill.symbol.clone(), fill.price);
+            next_fill += 1;
+        }
+        for symbol in closes.keys() {
+            if let Some(close) = closes_by_day.get(&(symbol.as_str(), day)) {
+                marks.insert(symbol.clone(), *close);
+            }
+        }
+
+        let mut deposited = 0.0;
+        while let Some((_, amount)) = top_ups.get(next_top_up).filter(|(time, _)| *time < day_end) {
+            cash += amount;
+            deposited += amount;
+            next_top_up += 1;
+        }
+
+        let equity = cash
+            + positions
+                .iter()
+                .map(|(symbol, quantity)| quantity * marks.get(symbol).copied().unwrap_or_default())
+                .sum::<f64>();
+
+        *invested.get_or_insert(equity - deposited) += deposited;
+        for (benchmark, basket) in [(&mut hold_btc, &btc), (&mut equal_weight, &index_symbols)] {
+            if benchmark.invested.is_none() {
+                benchmark.invest(equity, basket, &marks);
+            } else if deposited > 0.0 {
+                benchmark.invest(deposited, basket, &marks);
+            }
+        }
+
+        points.push(BenchmarkPoint {
+            time: day,
+            equity,
+            buy_and_hold_btc: hold_btc.value(&marks),
+            equal_weight_index: equal_weight.value(&marks),
+        });
+    }
+
+    let last = points.last();
+    Ok(BenchmarkReport {
+        account_id: account_id.to_string(),
+        index_symbols,
+        account_return_pct: return_pct(last.map(|point| point.equity), invested),
+        buy_and_hold_btc_return_pct: return_pct(last.and_then(|point| point.buy_and_hold_btc), hold_btc.invested),
+        equal_weight_index_return_pct: return_pct(
+            last.and_then(|point| point.equal_weight_index),
+            equal_weight.invested,
+        ),
+        points,
+    })
+}
@@ -0,0 +1,168 @@
+// Compares insert and query throughput of the old time-only ticker_data layout against the
+// symbol space-partitioned one, using throwaway hypertables in the DATABASE_URL database.
+//
+//   cargo run --release --bin hypertable_bench -- [rows] [symbols] [writers]
+use dotenv::dotenv;
+use rand::Rng;
+use sqlx::PgPool;
+use std::env;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+const BATCH_SIZE: usize = 1000;
+
+struct Layout {
+    table: &'static str,
+    create: &'static str,
+}
+
+const LAYOUTS: [Layout; 2] = [
+    Layout {
+        table: "bench_ticker_time_only",
+        create: "SELECT create_hypertable('bench_ticker_time_only', 'created_at',
+            chunk_time_interval => INTERVAL '10 minutes')",
+    },
+    Layout {
+        table: "bench_ticker_space",
+        create: "SELECT create_hypertable('bench_ticker_space', 'created_at',
+            partitioning_column => 'symbol', number_partitions => 4,
+            chunk_time_interval => INTERVAL '10 minutes')",
+    },
+];
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    dotenv().ok();
+
+    let mut args = env::args().skip(1);
+    let rows: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(500_000);
+    let symbols: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(300);
+    let writers: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(8);
+
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await?;
+
+    println!("{} rows, {} symbols, {} concurrent writers", rows, symbols, writers);
+    for layout in &LAYOUTS {
+        setup(&pool, layout).await?;
+
+        let insert_time = insert(&pool, layout.table, rows, symbols, writers).await?;
+        let query_time = query(&pool, layout.table, symbols).await?;
+
+        println!(
+            "{:<24} insert {:>9.0} rows/s   candle query {:>8.2} ms avg",
+            layout.table,
+            rows as f64 / insert_time.as_secs_f64(),
+            query_time.as_secs_f64() * 1000.0
+        );
+
+        sqlx::query(&format!("DROP TABLE {}", layout.table)).execute(&pool).await?;
+    }
+
+    Ok(())
+}
+
+async fn setup(pool: &PgPool, layout: &Layout) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!("DROP TABLE IF EXISTS {}", layout.table)).execute(pool).await?;
+    sqlx::query(&format!(
+        "CREATE TABLE {} (
+            symbol TEXT,
+            close_price DECIMAL,
+            quote_volume DECIMAL,
+            created_at TIMESTAMPTZ
+        )",
+        layout.table
+    ))
+    .execute(pool)
+    .await?;
+    sqlx::query(layout.create).execute(pool).await?;
+    sqlx::query(&format!(
+        "CREATE INDEX ON {} (symbol, created_at DESC)",
+        layout.table
+    ))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Each writer owns a slice of the symbols, like several ingest workers would
+async fn insert(
+    pool: &PgPool,
+    table: &'static str,
+    rows: usize,
+    symbols: usize,
+    writers: usize,
+) -> Result<Duration, sqlx::Error> {
+    let started = Instant::now();
+    let writers = writers.max(1);
+    let mut tasks = Vec::with_capacity(writers);
+
+    for writer in 0..writers {
+        let pool = pool.clone();
+        let writer_rows = rows / writers;
+        tasks.push(tokio::spawn(async move {
+            let mut written = 0;
+            while written < writer_rows {
+                let batch = BATCH_SIZE.min(writer_rows - written);
+                let (names, prices, volumes, offsets) = {
+                    let mut rng = rand::thread_rng();
+                    let mut names = Vec::with_capacity(batch);
+                    let mut prices = Vec::with_capacity(batch);
+                    let mut volumes = Vec::with_capacity(batch);
+                    let mut offsets = Vec::with_capacity(batch);
+                    for i in 0..batch {
+                        let symbol = (rng.gen_range(0..symbols) / writers) * writers + writer;
+                        names.push(format!("SYM{}USDT", symbol));
+                        prices.push(rng.gen_range(1.0..100.0));
+                        volumes.push(rng.gen_range(1_000.0..1_000_000.0));
+                        offsets.push(((written + i) * writers + writer) as f64 / 100.0);
+                    }
+                    (names, prices, volumes, offsets)
+                };
+
+                sqlx::query(&format!(
+                    "INSERT INTO {} (symbol, close_price, quote_volume, created_at)
+                    SELECT s, p, v, NOW() - INTERVAL '1 hour' + make_interval(secs => o)
+                    FROM unnest($1::text[], $2::float8[], $3::float8[], $4::float8[]) AS t(s, p, v, o)",
+                    table
+                ))
+                .bind(&names)
+                .bind(&prices)
+                .bind(&volumes)
+                .bind(&offsets)
+                .execute(&pool)
+                .await?;
+                written += batch;
+            }
+            Ok::<(), sqlx::Error>(())
+        }));
+    }
+
+    for task in tasks {
+        task.await.expect("writer task panicked")?;
+    }
+    Ok(started.elapsed())
+}
+
+// One-minute candles for a sample of single symbols, the access pattern of the chart endpoints
+async fn query(pool: &PgPool, table: &'static str, symbols: usize) -> Result<Duration, sqlx::Error> {
+    let samples = symbols.min(50);
+    let started = Instant::now();
+
+    for symbol in 0..samples {
+        sqlx::query(&format!(
+            "SELECT time_bucket(INTERVAL '1 minute', created_at) AS bucket,
+                first(close_price, created_at), max(close_price), min(close_price), last(close_price, created_at)
+            FROM {}
+            WHERE symbol = $1 AND created_at >= NOW() - INTERVAL '1 hour'
+            GROUP BY bucket
+            ORDER BY bucket",
+            table
+        ))
+        .bind(format!("SYM{}USDT", symbol))
+        .fetch_all(pool)
+        .await?;
+    }
+
+    Ok(started.elapsed() / samples.max(1) as u32)
+}
@@ -0,0 +1,305 @@
+// Drives a running server with recorded ticker bursts and N subscribed WebSocket clients, then
+// reports fan-out latency percentiles, the server's ingest latency and, with DATABASE_URL set, how
+// many ticks per second reached ticker_data.
+//
+// The harness plays the exchange: it listens for the server's feed connection, so start it first
+// and then the server with BINANCE_FEED_URL pointing at it. Bursts come from an ingest archive file
+// (INGEST_ARCHIVE=files) or are generated when none is given. Each tick's event time is set to the
+// moment it is sent, so latencies are end to end.
+//
+//   cargo run --release --bin load_test -- [clients] [seconds] [speedup] [archive.gz]
+use dotenv::dotenv;
+use flate2::read::GzDecoder;
+use futures_util::{SinkExt, StreamExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sqlx::PgPool;
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::time::{sleep, sleep_until, timeout, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{accept_async, connect_async};
+
+const DEFAULT_FEED_ADDR: &str = "127.0.0.1:9944";
+const SYNTHETIC_BURSTS: usize = 60;
+const SYNTHETIC_SYMBOLS: usize = 300;
+// Clients still connected this long after the last burst have received all they will
+const DRAIN_TIME: Duration = Duration::from_secs(2);
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    dotenv().ok();
+
+    let mut args = env::args().skip(1);
+    let clients: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(100);
+    let seconds: u64 = args.next().and_then(|a| a.parse().ok()).unwrap_or(60);
+    let speedup: f64 = args.next().and_then(|a| a.parse().ok()).unwrap_or(1.0_f64).max(0.01);
+    let bursts = match args.next() {
+        Some(path) => read_archive(&path)?,
+        None => synthetic_bursts(),
+    };
+    if bursts.is_empty() {
+        return Err("No ticker bursts to replay".into());
+    }
+    let symbols = burst_symbols(&bursts);
+
+    let bind_addr = env::var("WEBSOCKET_URL").expect("WEBSOCKET_URL must be set");
+    let server_url = format!("ws://{}", bind_addr.replace("0.0.0.0", "127.0.0.1"));
+    let feed_addr = env::var("LOAD_TEST_FEED_ADDR").unwrap_or_else(|_| DEFAULT_FEED_ADDR.to_string());
+    let listener = TcpListener::bind(&feed_addr).await?;
+    println!("Start the server with BINANCE_FEED_URL=ws://{}, waiting for it to connect", feed_addr);
+    let (stream, _) = listener.accept().await?;
+    let mut feed = accept_async(stream).await?;
+    println!(
+        "Server connected, {} bursts of {} symbols, {} clients, {}s at {}x",
+        bursts.len(),
+        symbols.len(),
+        clients,
+        seconds,
+        speedup
+    );
+
+    let mut tasks = Vec::with_capacity(clients);
+    for _ in 0..clients {
+        let (url, symbols) = (server_url.clone(), symbols.clone());
+        tasks.push(tokio::spawn(run_client(url, symbols)));
+    }
+    // Let every client subscribe before the first burst
+    let mut receivers = Vec::with_capacity(clients);
+    for task in tasks {
+        receivers.push(task.await??);
+    }
+
+    let started_at = now_millis();
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let mut sent = 0;
+    let mut next_send = Instant::now();
+    'replay: loop {
+        for (i, (received_at, payload)) in bursts.iter().enumerate() {
+            sleep_until(next_send).await;
+            if Instant::now() >= deadline {
+                break 'replay;
+            }
+            let (frame, ticks) = restamp(payload, now_millis());
+            feed.send(Message::Text(frame.into())).await?;
+            sent += ticks;
+            // Recorded spacing, and a second from the last burst back to the first
+            let gap = bursts.get(i + 1).map(|(next, _)| next - received_at).unwrap_or(1000).clamp(0, 60_000);
+            next_send += Duration::from_secs_f64(gap as f64 / 1000.0 / speedup);
+        }
+    }
+    let elapsed_secs = (now_millis() - started_at) as f64 / 1000.0;
+    sleep(DRAIN_TIME).await;
+
+    let mut latencies = Vec::new();
+    let mut received = 0;
+    for receiver in receivers {
+        let client_latencies = receiver.stop().await;
+        received += client_latencies.len();
+        latencies.extend(client_latencies);
+    }
+    latencies.sort_unstable();
+    println!(
+        "Sent {} ticks ({:.0}/s), delivered {} of {} expected frames",
+        sent,
+        sent as f64 / elapsed_secs,
+        received,
+        sent * clients
+    );
+    println!(
+        "Fan-out latency ms: p50 {}  p90 {}  p99 {}  p99.9 {}  max {}",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.9),
+        percentile(&latencies, 0.99),
+        percentile(&latencies, 0.999),
+        percentile(&latencies, 1.0)
+    );
+
+    match ingestion_stats(&server_url).await {
+        Ok(stats) => println!("Server ingest latency: {}", stats),
+        Err(e) => eprintln!("Error reading ingestion stats: {}", e),
+    }
+
+    if let Ok(database_url) = env::var("DATABASE_URL") {
+        let pool = PgPool::connect(&database_url).await?;
+        let (rows,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM ticker_data
+            WHERE received_at >= to_timestamp($1::double precision / 1000) AT TIME ZONE 'UTC'",
+        )
+        .bind(started_at)
+        .fetch_one(&pool)
+        .await?;
+        println!("Inserted {} ticker rows ({:.0}/s)", rows, rows as f64 / elapsed_secs);
+    }
+
+    Ok(())
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64
+}
+
+// "received_at<TAB>payload" lines of an hourly ingest archive file
+fn read_archive(path: &str) -> Result<Vec<(i64, String)>, Box<dyn Error + Send + Sync>> {
+    let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    let mut bursts = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if let Some((received_at, payload)) = line.split_once('\t') {
+            if let Ok(received_at) = received_at.parse() {
+                bursts.push((received_at, payload.to_string()));
+            }
+        }
+    }
+    Ok(bursts)
+}
+
+// One market-wide frame per second of a random walk, like !miniTicker@arr. SIMULATION_SEED makes
+// the walk the same on every run.
+fn synthetic_bursts() -> Vec<(i64, String)> {
+    let mut rng = match env::var("SIMULATION_SEED").ok().and_then(|seed| seed.parse().ok()) {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut closes: Vec<f64> = (0..SYNTHETIC_SYMBOLS).map(|_| rng.gen_range(0.01..100_000.0)).collect();
+    (0..SYNTHETIC_BURSTS)
+        .map(|burst| {
+            let elements: Vec<String> = closes
+                .iter_mut()
+                .enumerate()
+                .map(|(symbol, close)| {
+                    *close *= 1.0 + rng.gen_range(-0.005..0.005);
+                    format!(
+                        r#"{{"e":"24hrMiniTicker","E":0,"s":"SYM{}USDT","c":"{:.8}","o":"{:.8}","h":"{:.8}","l":"{:.8}","v":"{:.3}","q":"{:.2}"}}"#,
+                        symbol,
+                        close,
+                        *close * 0.99,
+                        *close * 1.01,
+                        *close * 0.98,
+                        rng.gen_range(1.0..1_000_000.0),
+                        rng.gen_range(1_000.0..100_000_000.0)
+                    )
+                })
+                .collect();
+            (burst as i64 * 1000, format!("[{}]", elements.join(",")))
+        })
+        .collect()
+}
+
+fn burst_symbols(bursts: &[(i64, String)]) -> Vec<String> {
+    let mut symbols = BTreeSet::new();
+    for (_, payload) in bursts {
+        if let Ok(serde_json::Value::Array(elements)) = serde_json::from_str(payload) {
+            for element in elements {
+                if let Some(symbol) = element.get("s").and_then(|s| s.as_str()) {
+                    symbols.insert(symbol.to_string());
+                }
+            }
+        }
+    }
+    symbols.into_iter().collect()
+}
+
+// The burst as a combined-stream frame with every event time set to `now`, and how many tickers it
+// carries
+fn restamp(payload: &str, now: i64) -> (String, usize) {
+    let Ok(serde_json::Value::Array(mut elements)) = serde_json::from_str(payload) else {
+        return (String::new(), 0);
+    };
+    for element in &mut elements {
+        if let Some(fields) = element.as_object_mut() {
+            fields.insert("E".to_string(), now.into());
+        }
+    }
+    let ticks = elements.len();
+    let frame = serde_json::json!({ "stream": "!miniTicker@arr", "data": elements });
+    (frame.to_string(), ticks)
+}
+
+fn percentile(sorted: &[i64], p: f64) -> String {
+    let index = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+    sorted.get(index).map_or_else(|| "-".to_string(), i64::to_string)
+}
+
+// A subscribed client, collecting the latency of every ticker it receives until stopped
+struct Receiver {
+    stop: tokio::sync::oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<Vec<i64>>,
+}
+
+impl Receiver {
+    async fn stop(self) -> Vec<i64> {
+        let _ = self.stop.send(());
+        self.task.await.unwrap_or_default()
+    }
+}
+
+async fn run_client(url: String, symbols: Vec<String>) -> Result<Receiver, Box<dyn Error + Send + Sync>> {
+    let (ws_stream, _) = connect_async(url.as_str()).await?;
+    let (mut write, mut read) = ws_stream.split();
+    let subscribe = serde_json::json!({ "type": "subscribe", "symbols": symbols });
+    write.send(Message::Text(subscribe.to_string().into())).await?;
+    // Frames before the acknowledgement, like the first ticker page, aren't measured
+    while let Some(message) = read.next().await {
+        if let Message::Text(text) = message? {
+            let frame: serde_json::Value = serde_json::from_str(&text).unwrap_or_default();
+            match frame.get("type").and_then(|kind| kind.as_str()) {
+                Some("subscribed") => break,
+                Some("error") | Some("rate_limited") => return Err(format!("Subscribe failed: {}", text).into()),
+                _ => {}
+            }
+        }
+    }
+
+    let (stop, mut stopped) = tokio::sync::oneshot::channel();
+    let task = tokio::spawn(async move {
+        let _write = write; // Dropping it would close the connection
+        let mut latencies = Vec::new();
+        loop {
+            tokio::select! {
+                message = read.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let received_at = now_millis();
+                        let Ok(frame) = serde_json::from_str::<serde_json::Value>(&text) else {
+                            continue;
+                        };
+                        if frame.get("type").and_then(|kind| kind.as_str()) == Some("ticker") {
+                            if let Some(event_time) = frame.get("event_time").and_then(|time| time.as_i64()) {
+                                latencies.push(received_at - event_time);
+                            }
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => break,
+                },
+                _ = &mut stopped => break,
+            }
+        }
+        latencies
+    });
+    Ok(Receiver { stop, task })
+}
+
+async fn ingestion_stats(url: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let (mut ws_stream, _) = connect_async(url).await?;
+    ws_stream
+        .send(Message::Text(r#"{"type":"ingestion_stats"}"#.into()))
+        .await?;
+    let reply = timeout(Duration::from_secs(10), async {
+        while let Some(message) = ws_stream.next().await {
+            if let Message::Text(text) = message? {
+                if text.contains(r#""type":"ingestion_stats""#) {
+                    return Ok(text.to_string());
+                }
+            }
+        }
+        Err::<String, Box<dyn Error + Send + Sync>>("Connection closed".into())
+    })
+    .await;
+    reply.map_err(|_| "Timed out waiting for ingestion stats")?
+}
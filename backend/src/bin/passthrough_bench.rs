@@ -0,0 +1,143 @@
+// Compares the CPU cost of relaying miniTicker frames to live subscribers by decoding and
+// reserializing every ticker against forwarding the exchange's validated JSON slices. Single
+// threaded, so the elapsed time is the CPU time of one ingest loop.
+//
+//   cargo run --release --bin passthrough_bench -- [frames] [symbols] [subscribers]
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// Same shape as the feed's models::TickerData
+#[allow(non_snake_case, dead_code)]
+#[derive(Deserialize)]
+struct TickerData {
+    E: i64,
+    s: String,
+    c: String,
+    o: String,
+    h: String,
+    l: String,
+    q: String,
+}
+
+#[derive(Serialize)]
+struct TickerFrame<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    symbol: &'a str,
+    price: f64,
+    quote_volume: f64,
+    event_time: i64,
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let frames: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(2_000);
+    let symbols: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(300);
+    let subscribers: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(20);
+
+    // !miniTicker@arr carries every symbol that traded in the last second, the full market is all of them
+    let payloads: Vec<String> = (0..16).map(|i| mini_ticker_frame(symbols, i)).collect();
+    let tickers = frames * symbols;
+    println!(
+        "{} frames of {} tickers, {} subscribers per symbol ({} frames sent)",
+        frames,
+        symbols,
+        subscribers,
+        tickers * subscribers
+    );
+
+    let reserialized = run(frames, &payloads, |text| reserialize(text, subscribers));
+    let passthrough = run(frames, &payloads, |text| passthrough(text, subscribers));
+    for (name, elapsed) in [("reserialize", reserialized), ("passthrough", passthrough)] {
+        println!(
+            "{:<12} {:>9.2} ms total   {:>7.0} ns/ticker   {:>10.0} tickers/s",
+            name,
+            elapsed.as_secs_f64() * 1000.0,
+            elapsed.as_nanos() as f64 / tickers as f64,
+            tickers as f64 / elapsed.as_secs_f64()
+        );
+    }
+    println!(
+        "passthrough uses {:.0}% less CPU",
+        (1.0 - passthrough.as_secs_f64() / reserialized.as_secs_f64()) * 100.0
+    );
+}
+
+fn run(frames: usize, payloads: &[String], relay: impl Fn(&str) -> usize) -> Duration {
+    let started = Instant::now();
+    let mut bytes = 0;
+    for i in 0..frames {
+        bytes += relay(&payloads[i % payloads.len()]);
+    }
+    black_box(bytes);
+    started.elapsed()
+}
+
+fn mini_ticker_frame(symbols: usize, seed: usize) -> String {
+    let mut rng = rand::thread_rng();
+    let elements: Vec<String> = (0..symbols)
+        .map(|symbol| {
+            let close: f64 = rng.gen_range(1.0..100_000.0);
+            format!(
+                r#"{{"e":"24hrMiniTicker","E":{},"s":"SYM{}USDT","c":"{:.4}","o":"{:.4}","h":"{:.4}","l":"{:.4}","v":"{:.3}","q":"{:.2}"}}"#,
+                1_700_000_000_000_u64 + seed as u64 * 1000,
+                symbol,
+                close,
+                close * 0.98,
+                close * 1.02,
+                close * 0.97,
+                rng.gen_range(1.0..1_000_000.0),
+                rng.gen_range(1_000.0..100_000_000.0)
+            )
+        })
+        .collect();
+    format!("[{}]", elements.join(","))
+}
+
+// Parse the frame, decode each ticker into numbers, then serialize a frame per subscriber
+fn reserialize(text: &str, subscribers: usize) -> usize {
+    let Ok(serde_json::Value::Array(elements)) = serde_json::from_str::<serde_json::Value>(text) else {
+        return 0;
+    };
+    let mut bytes = 0;
+    for element in elements {
+        let Ok(ticker) = serde_json::from_value::<TickerData>(element) else {
+            continue;
+        };
+        let Ok(price) = ticker.c.parse::<f64>() else {
+            continue;
+        };
+        let frame = TickerFrame {
+            kind: "ticker",
+            symbol: &ticker.s,
+            price,
+            quote_volume: ticker.q.parse().unwrap_or_default(),
+            event_time: ticker.E,
+        };
+        for _ in 0..subscribers {
+            bytes += black_box(serde_json::to_string(&frame).unwrap_or_default()).len();
+        }
+    }
+    bytes
+}
+
+// Validate each ticker once, then wrap its slice of the frame for every subscriber
+fn passthrough(text: &str, subscribers: usize) -> usize {
+    let Ok(elements) = serde_json::from_str::<Vec<&RawValue>>(text) else {
+        return 0;
+    };
+    let mut bytes = 0;
+    for element in elements {
+        if serde_json::from_str::<TickerData>(element.get()).is_err() {
+            continue;
+        }
+        for _ in 0..subscribers {
+            bytes += black_box(format!("{{\"type\":\"raw_ticker\",\"data\":{}}}", element.get())).len();
+        }
+    }
+    bytes
+}
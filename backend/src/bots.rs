@@ -0,0 +1,621 @@
+use crate::accounts;
+use crate::alerts;
+use crate::db;
+use crate::engine::OrderError;
+use crate::grid;
+use crate::models::{
+    Alert, Bot, BotConfig, BotParams, BotStatus, BotTemplate, Fill, OrderRequest, OrderStatus, OrderType,
+    ServerMessage, Side,
+};
+use crate::rules;
+use crate::state::AppState;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+// Caps no configuration gets past, both templates lose everything without them
+const MAX_SAFETY_ORDERS: u32 = 10;
+const MAX_MARTINGALE_STEPS: u32 = 6;
+const MAX_MULTIPLIER: f64 = 3.0;
+const MAX_STOP_LOSS: f64 = 0.5;
+const QUANTITY_EPSILON: f64 = 1e-9;
+
+// DCA and martingale bots by (account_id, symbol), stopped ones are kept for their results. Each
+// runs the strategy its template is bound to, see `strategy`. Grids keep their own book in
+// grid.rs, the lifecycle calls below hand a symbol's grid over to it.
+#[derive(Default)]
+pub struct BotBook {
+    bots: HashMap<(String, String), Bot>,
+}
+
+impl BotBook {
+    pub fn load(&mut self, bots: Vec<Bot>) {
+        for bot in bots {
+            self.set(bot);
+        }
+    }
+
+    fn set(&mut self, bot: Bot) {
+        let key = (bot.config.account_id.clone(), bot.config.symbol.clone());
+        self.bots.insert(key, bot);
+    }
+
+    fn get(&self, account_id: &str, symbol: &str) -> Option<&Bot> {
+        self.bots.get(&(account_id.to_string(), symbol.to_string()))
+    }
+
+    pub fn account_bots(&self, account_id: &str) -> Vec<Bot> {
+        let mut bots: Vec<Bot> = self
+            .bots
+            .values()
+            .filter(|bot| bot.config.account_id == account_id)
+            .cloned()
+            .collect();
+        bots.sort_by(|a, b| a.config.symbol.cmp(&b.config.symbol));
+        bots
+    }
+
+    // Every active bot in the symbol is checked against its price, only running ones act on it
+    fn running_in(&mut self, symbol: &str, now: i64) -> Vec<String> {
+        let mut running = Vec::new();
+        for bot in self.bots.values_mut() {
+            if bot.status == BotStatus::Stopped || bot.config.symbol != symbol {
+                continue;
+            }
+            bot.heartbeat_at = Some(now);
+            if bot.status == BotStatus::Running {
+                running.push(bot.config.account_id.clone());
+            }
+        }
+        running
+    }
+
+    // A paused bot still settles the order it placed before the pause
+    fn pending(&self) -> Vec<(String, String)> {
+        self.bots
+            .iter()
+            .filter(|(_, bot)| bot.status != BotStatus::Stopped && bot.pending_order.is_some())
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+
+fn direction(side: Side) -> f64 {
+    match side {
+        Side::Buy => 1.0,
+        Side::Sell => -1.0,
+    }
+}
+
+// What a bot trades. The runner below keeps the position, closes every cycle at the take-profit
+// or the stop-loss and settles the orders, a strategy sizes the entries and decides what a losing
+// cycle means for the next one. A new one gets a template and an arm in `strategy`.
+pub trait Strategy: Send + Sync {
+    // Checks the strategy's own parameters, `validate` has checked the shared ones
+    fn validate(&self, config: &BotConfig) -> Result<(), String>;
+    // The largest position the bot can hold at `price`, with every entry its plan allows
+    fn worst_notional(&self, config: &BotConfig, price: f64) -> f64;
+    // Size of the next entry at `price`, None to hold. Not asked while an exit is due.
+    fn next_entry(&self, bot: &Bot, price: f64) -> Option<f64>;
+    // Called once a losing cycle is counted, a reason halts the bot
+    fn lost(&self, bot: &mut Bot) -> Option<String>;
+}
+
+fn strategy(template: BotTemplate) -> &'static dyn Strategy {
+    match template {
+        BotTemplate::Dca => &Dca,
+        BotTemplate::Martingale => &Martingale,
+    }
+}
+
+// Size of the entry after `step` safety orders or losing cycles
+fn entry_quantity(config: &BotConfig, step: u32) -> f64 {
+    config.base_quantity * config.multiplier.powi(step as i32)
+}
+
+// Adds safety orders of growing size, evenly spaced as the price moves against the first entry
+struct Dca;
+
+impl Strategy for Dca {
+    fn validate(&self, config: &BotConfig) -> Result<(), String> {
+        if config.max_steps > MAX_SAFETY_ORDERS {
+            return Err(format!("A DCA bot has at most {} safety orders", MAX_SAFETY_ORDERS));
+        }
+        if !(config.step_deviation.is_finite() && config.step_deviation > 0.0) {
+            return Err("The step deviation must be a positive fraction".to_string());
+        }
+        // Every safety order has to come before the stop-loss could
+        if config.step_deviation * config.max_steps as f64 >= config.stop_loss {
+            return Err("The last safety order must trigger before the stop-loss".to_string());
+        }
+        Ok(())
+    }
+
+    fn worst_notional(&self, config: &BotConfig, price: f64) -> f64 {
+        (0..=config.max_steps)
+            .map(|step| {
+                let fill_price = price * (1.0 - direction(config.side) * config.step_deviation * step as f64);
+                entry_quantity(config, step) * fill_price
+            })
+            .sum()
+    }
+
+    fn next_entry(&self, bot: &Bot, price: f64) -> Option<f64> {
+        let config = &bot.config;
+        if bot.quantity <= QUANTITY_EPSILON {
+            return Some(entry_quantity(config, 0));
+        }
+        let first = bot.first_entry_price?;
+        let adverse = (1.0 - price / first) * direction(config.side);
+        let due = adverse >= config.step_deviation * (bot.step + 1) as f64;
+        (bot.step < config.max_steps && due).then(|| entry_quantity(config, bot.step + 1))
+    }
+
+    fn lost(&self, bot: &mut Bot) -> Option<String> {
+        Some(format!("Stop-loss hit after {} safety orders", bot.step))
+    }
+}
+
+// Enters once per cycle, growing the entry after every losing cycle
+struct Martingale;
+
+impl Strategy for Martingale {
+    fn validate(&self, config: &BotConfig) -> Result<(), String> {
+        if config.max_steps > MAX_MARTINGALE_STEPS {
+            return Err(format!("A martingale bot doubles down at most {} times", MAX_MARTINGALE_STEPS));
+        }
+        Ok(())
+    }
+
+    fn worst_notional(&self, config: &BotConfig, price: f64) -> f64 {
+        entry_quantity(config, config.max_steps) * price
+    }
+
+    fn next_entry(&self, bot: &Bot, _price: f64) -> Option<f64> {
+        (bot.quantity <= QUANTITY_EPSILON).then(|| entry_quantity(&bot.config, bot.step))
+    }
+
+    fn lost(&self, bot: &mut Bot) -> Option<String> {
+        bot.step += 1;
+        (bot.step > bot.config.max_steps)
+            .then(|| format!("Lost {} cycles in a row, the next entry would pass the cap", bot.step))
+    }
+}
+
+fn worst_notional(config: &BotConfig, price: f64) -> f64 {
+    strategy(config.template).worst_notional(config, price)
+}
+
+fn validate(config: &mut BotConfig, price: f64) -> Result<(), String> {
+    config.symbol = config.symbol.trim().to_uppercase();
+    if !(config.base_quantity.is_finite() && config.base_quantity > 0.0) {
+        return Err("The base quantity must be a positive number".to_string());
+    }
+    if !(config.take_profit > 0.0 && config.take_profit <= 1.0) {
+        return Err("The take-profit must be a fraction between 0 and 1".to_string());
+    }
+    if !(config.stop_loss > 0.0 && config.stop_loss <= MAX_STOP_LOSS) {
+        return Err(format!("The stop-loss must be a fraction above 0 and at most {}", MAX_STOP_LOSS));
+    }
+    if !(1.0..=MAX_MULTIPLIER).contains(&config.multiplier) {
+        return Err(format!("The multiplier must be between 1 and {}", MAX_MULTIPLIER));
+    }
+    strategy(config.template).validate(config)?;
+    let notional = worst_notional(config, price);
+    if !(config.max_notional.is_finite() && notional <= config.max_notional) {
+        return Err(format!(
+            "The plan builds a position of up to {:.2} at the last price, above max_notional",
+            notional
+        ));
+    }
+    Ok(())
+}
+
+// A running or paused bot holds the symbol, a grid can't start in it meanwhile
+pub async fn is_active(state: &AppState, account_id: &str, symbol: &str) -> bool {
+    state
+        .bots
+        .lock()
+        .await
+        .get(account_id, symbol)
+        .is_some_and(|bot| bot.status != BotStatus::Stopped)
+}
+
+async fn last_price(state: &AppState, symbol: &str) -> Result<f64, String> {
+    state
+        .engine
+        .lock()
+        .await
+        .last_price(symbol)
+        .ok_or_else(|| format!("No price for {} yet", symbol))
+}
+
+// Checked against the last price, the first entry goes in with the next one
+pub async fn start(state: &AppState, mut config: BotConfig) -> Result<ServerMessage, String> {
+    let symbol = config.symbol.trim().to_uppercase();
+    let account_id = config.account_id.clone();
+    if is_active(state, &account_id, &symbol).await || grid::is_active(state, &account_id, &symbol).await {
+        return Err(format!("A bot is already active in {}, stop it first", symbol));
+    }
+    let price = last_price(state, &symbol).await?;
+    validate(&mut config, price)?;
+    // Bot orders can't wait for a second member, so a plan that could pass the threshold doesn't start
+    let notional = worst_notional(&config, price);
+    if state.teams.lock().await.requires_approval(&account_id, Some(notional)) {
+        return Err("Bots that could trade above the approval threshold can't run on this account".to_string());
+    }
+
+    let bot = Bot {
+        config,
+        status: BotStatus::Running,
+        cycles: 0,
+        wins: 0,
+        losses: 0,
+        step: 0,
+        quantity: 0.0,
+        average_price: 0.0,
+        first_entry_price: None,
+        realized_pnl: 0.0,
+        pending_order: None,
+        pending_filled: 0.0,
+        pending_exit: false,
+        halted: None,
+        started_at: state.simulation.now_millis(),
+        stopped_at: None,
+        heartbeat_at: None,
+    };
+    save(state, &bot).await;
+    state.bots.lock().await.set(bot.clone());
+    Ok(ServerMessage::Bot(bot))
+}
+
+// Moves an active bot from one status to another, a grid in the symbol is handed to the grid runner
+async fn transition(
+    state: &AppState,
+    account_id: &str,
+    symbol: &str,
+    from: &[BotStatus],
+    to: BotStatus,
+) -> Result<ServerMessage, String> {
+    let symbol = symbol.trim().to_uppercase();
+    if grid::is_active(state, account_id, &symbol).await {
+        return match to {
+            BotStatus::Running => grid::resume(state, account_id, &symbol).await,
+            BotStatus::Paused => grid::pause(state, account_id, &symbol).await,
+            BotStatus::Stopped => grid::stop(state, account_id, &symbol).await,
+        };
+    }
+    let bot = {
+        let mut book = state.bots.lock().await;
+        let mut bot = match book.get(account_id, &symbol) {
+            Some(bot) if from.contains(&bot.status) => bot.clone(),
+            Some(bot) if bot.status == BotStatus::Stopped => return Err(format!("The bot in {} is stopped", symbol)),
+            Some(bot) if bot.status == BotStatus::Paused => return Err(format!("The bot in {} is paused", symbol)),
+            Some(_) => return Err(format!("The bot in {} is running", symbol)),
+            None => return Err(format!("No bot in {}", symbol)),
+        };
+        bot.status = to;
+        if to == BotStatus::Stopped {
+            bot.stopped_at = Some(state.simulation.now_millis());
+        }
+        book.set(bot.clone());
+        bot
+    };
+    save(state, &bot).await;
+    Ok(ServerMessage::Bot(bot))
+}
+
+// An open position stays open when stopping or pausing, the bot only stops placing orders
+pub async fn stop(state: &AppState, account_id: &str, symbol: &str) -> Result<ServerMessage, String> {
+    transition(state, account_id, symbol, &[BotStatus::Running, BotStatus::Paused], BotStatus::Stopped).await
+}
+
+pub async fn pause(state: &AppState, account_id: &str, symbol: &str) -> Result<ServerMessage, String> {
+    transition(state, account_id, symbol, &[BotStatus::Running], BotStatus::Paused).await
+}
+
+pub async fn resume(state: &AppState, account_id: &str, symbol: &str) -> Result<ServerMessage, String> {
+    transition(state, account_id, symbol, &[BotStatus::Paused], BotStatus::Running).await
+}
+
+// Takes on new parameters between two orders. The caps are checked again at the last price, and
+// a martingale bot can't lower max_steps below the losing streak it is already on.
+pub async fn update_params(
+    state: &AppState,
+    account_id: &str,
+    symbol: &str,
+    params: BotParams,
+) -> Result<ServerMessage, String> {
+    let symbol = symbol.trim().to_uppercase();
+    if grid::is_active(state, account_id, &symbol).await {
+        return Err("A grid's levels and order size are fixed while it runs, stop it and start a new one".to_string());
+    }
+    let current = match state.bots.lock().await.get(account_id, &symbol) {
+        Some(bot) if bot.status != BotStatus::Stopped => bot.clone(),
+        Some(_) => return Err(format!("The bot in {} is stopped", symbol)),
+        None => return Err(format!("No bot in {}", symbol)),
+    };
+    let mut config = current.config.clone();
+    config.base_quantity = params.base_quantity.unwrap_or(config.base_quantity);
+    config.take_profit = params.take_profit.unwrap_or(config.take_profit);
+    config.stop_loss = params.stop_loss.unwrap_or(config.stop_loss);
+    config.max_steps = params.max_steps.unwrap_or(config.max_steps);
+    config.multiplier = params.multiplier.unwrap_or(config.multiplier);
+    config.step_deviation = params.step_deviation.unwrap_or(config.step_deviation);
+    config.max_notional = params.max_notional.unwrap_or(config.max_notional);
+    if config.max_steps < current.step {
+        return Err(format!("The bot is already at step {}, max_steps can't go below it", current.step));
+    }
+    let price = last_price(state, &symbol).await?;
+    validate(&mut config, price)?;
+    if state.teams.lock().await.requires_approval(account_id, Some(worst_notional(&config, price))) {
+        return Err("Bots that could trade above the approval threshold can't run on this account".to_string());
+    }
+
+    let bot = {
+        let mut book = state.bots.lock().await;
+        let mut bot = match book.get(account_id, &symbol) {
+            Some(bot) if bot.status != BotStatus::Stopped => bot.clone(),
+            _ => return Err(format!("The bot in {} was stopped", symbol)),
+        };
+        bot.config = config;
+        book.set(bot.clone());
+        bot
+    };
+    save(state, &bot).await;
+    Ok(ServerMessage::Bot(bot))
+}
+
+async fn save(state: &AppState, bot: &Bot) {
+    if let Err(e) = db::save_bot(&state.pool, bot).await {
+        eprintln!("Error saving bot: {:?}", e);
+    }
+}
+
+fn halt(bot: &mut Bot, reason: String, now: i64) {
+    bot.status = BotStatus::Stopped;
+    bot.halted = Some(reason);
+    bot.stopped_at = Some(now);
+}
+
+enum Action {
+    Enter(f64),
+    Exit,
+}
+
+// What a flat or open bot does at `price`. An open position past its take-profit or stop-loss
+// exits, otherwise the strategy may enter.
+fn decide(bot: &Bot, price: f64) -> Option<Action> {
+    let config = &bot.config;
+    if bot.quantity > QUANTITY_EPSILON {
+        let gain = (price / bot.average_price - 1.0) * direction(config.side);
+        if gain >= config.take_profit || gain <= -config.stop_loss {
+            return Some(Action::Exit);
+        }
+    }
+    strategy(config.template).next_entry(bot, price).map(Action::Enter)
+}
+
+async fn on_price(state: &AppState, symbol: &str, price: f64) {
+    let accounts = state.bots.lock().await.running_in(symbol, state.simulation.now_millis());
+    for account_id in accounts {
+        act(state, &account_id, symbol, price).await;
+    }
+}
+
+async fn act(state: &AppState, account_id: &str, symbol: &str, price: f64) {
+    let running = |bot: &&Bot| bot.status == BotStatus::Running;
+    let Some(mut bot) = state.bots.lock().await.get(account_id, symbol).filter(running).cloned() else {
+        return;
+    };
+    if let Some(order_id) = bot.pending_order {
+        // A cancelled order never fills, anything else settles with its fills. Only an order the
+        // backend rolled back is unknown to it, a venue still knows the orders it accepted.
+        let cancelled = match state.backend.order(account_id, order_id).await {
+            Ok(order) => order.status == OrderStatus::Cancelled,
+            Err(OrderError::OrderNotFound(_)) => true,
+            Err(e) => {
+                eprintln!("Bot {} {} couldn't look up order {}: {}", account_id, symbol, order_id, e);
+                false
+            }
+        };
+        if !cancelled {
+            return;
+        }
+        bot.pending_order = None;
+    } else {
+        let Some(action) = decide(&bot, price) else {
+            return;
+        };
+        let (side, quantity, exit) = match action {
+            Action::Enter(quantity) => (bot.config.side, quantity, false),
+            Action::Exit => (bot.config.side.opposite(), bot.quantity, true),
+        };
+        let order = OrderRequest {
+            symbol: symbol.to_string(),
+            side,
+            order_type: OrderType::Market,
+            price: None,
+            quantity,
+            time_in_force: Default::default(),
+            client_order_id: None,
+        };
+        // The threshold may have been lowered since the bot started, exits included
+        let notional = quantity * price;
+        if state.teams.lock().await.requires_approval(account_id, Some(notional)) {
+            let reason = "The order would need approval, bots can't wait for it".to_string();
+            halt(&mut bot, reason, state.simulation.now_millis());
+        }
+        if !exit && bot.status == BotStatus::Running {
+            let checked = match accounts::check_order(state, account_id, &order).await {
+                Ok(()) => rules::check_orders(state, account_id, 1).await,
+                Err(message) => Err(message),
+            };
+            if let Err(message) = checked {
+                halt(&mut bot, format!("Entry rejected: {}", message), state.simulation.now_millis());
+            }
+        }
+        if bot.status == BotStatus::Running {
+            match state.backend.place_order(account_id, order).await {
+                Ok(order) => {
+                    bot.pending_order = Some(order.id);
+                    bot.pending_filled = 0.0;
+                    bot.pending_exit = exit;
+                    // Safety orders count when placed, so a slow fill doesn't place the next one twice
+                    if !exit && bot.quantity > QUANTITY_EPSILON {
+                        bot.step += 1;
+                    }
+                }
+                Err(e) => eprintln!("Bot {} {} couldn't place an order: {}", account_id, symbol, e),
+            }
+        }
+    }
+    update(state, bot).await;
+}
+
+// Stores the bot's position. A pause, stop or parameter update that came in meanwhile is kept,
+// and an owner whose bot just halted is told why.
+async fn update(state: &AppState, mut bot: Bot) {
+    let halted = {
+        let mut book = state.bots.lock().await;
+        let mut halted = bot.halted.is_some();
+        if let Some(current) = book.get(&bot.config.account_id, &bot.config.symbol) {
+            bot.config = current.config.clone();
+            bot.heartbeat_at = bot.heartbeat_at.max(current.heartbeat_at);
+            if current.status != BotStatus::Running && bot.status == BotStatus::Running {
+                bot.status = current.status;
+                bot.stopped_at = current.stopped_at;
+            }
+            halted &= current.halted.is_none();
+        }
+        book.set(bot.clone());
+        halted
+    };
+    save(state, &bot).await;
+    if halted {
+        alerts::deliver(
+            state,
+            Alert {
+                account_id: bot.config.account_id.clone(),
+                kind: "bot".to_string(),
+                message: format!(
+                    "Bot in {} halted: {}",
+                    bot.config.symbol,
+                    bot.halted.as_deref().unwrap_or_default()
+                ),
+                value: bot.realized_pnl,
+                threshold: 0.0,
+                created_at: state.simulation.now_millis(),
+            },
+            None,
+        );
+    }
+}
+
+// Folds a fill of the pending order into the position. A finished exit closes the cycle, and a
+// losing one can halt the bot for good.
+fn apply(bot: &mut Bot, order_quantity: f64, quantity: f64, price: f64, now: i64) {
+    let sign = direction(bot.config.side);
+    if bot.pending_exit {
+        let quantity = quantity.min(bot.quantity);
+        bot.realized_pnl += (price - bot.average_price) * quantity * sign;
+        bot.quantity -= quantity;
+    } else {
+        bot.average_price = (bot.average_price * bot.quantity + price * quantity) / (bot.quantity + quantity);
+        bot.quantity += quantity;
+        bot.first_entry_price.get_or_insert(price);
+    }
+    bot.pending_filled += quantity;
+    if bot.pending_filled < order_quantity - QUANTITY_EPSILON {
+        return;
+    }
+    bot.pending_order = None;
+    if !bot.pending_exit || bot.quantity > QUANTITY_EPSILON {
+        return;
+    }
+
+    let won = (price - bot.average_price) * sign > 0.0;
+    bot.cycles += 1;
+    bot.quantity = 0.0;
+    bot.first_entry_price = None;
+    if won {
+        bot.wins += 1;
+        bot.step = 0;
+        return;
+    }
+    bot.losses += 1;
+    if let Some(reason) = strategy(bot.config.template).lost(bot) {
+        halt(bot, reason, now);
+    }
+}
+
+async fn on_fill(state: &AppState, fill: &Fill) {
+    let Some(mut bot) = state
+        .bots
+        .lock()
+        .await
+        .get(&fill.account_id, &fill.symbol)
+        .filter(|bot| bot.pending_order == Some(fill.order_id))
+        .cloned()
+    else {
+        return;
+    };
+    let Ok(order_quantity) = state
+        .backend
+        .order(&fill.account_id, fill.order_id)
+        .await
+        .map(|order| order.quantity)
+    else {
+        return;
+    };
+    apply(&mut bot, order_quantity, fill.quantity, fill.price, state.simulation.now_millis());
+    update(state, bot).await;
+}
+
+// After missed fills, pending orders that are done settle at the last price
+async fn resync(state: &AppState) {
+    let pending = state.bots.lock().await.pending();
+    for (account_id, symbol) in pending {
+        let Some(mut bot) = state.bots.lock().await.get(&account_id, &symbol).cloned() else {
+            continue;
+        };
+        let filled = match bot.pending_order {
+            Some(order_id) => state.backend.order(&account_id, order_id).await.ok(),
+            None => None,
+        };
+        let settled = filled
+            .filter(|order| order.status == OrderStatus::Filled)
+            .map(|order| order.quantity)
+            .zip(state.engine.lock().await.last_price(&symbol));
+        let Some((order_quantity, price)) = settled else {
+            continue;
+        };
+        let missed = order_quantity - bot.pending_filled;
+        apply(&mut bot, order_quantity, missed, price, state.simulation.now_millis());
+        update(state, bot).await;
+    }
+}
+
+// Drives every running bot from the ticker stream and settles its orders from the fill stream
+pub async fn run_bots(state: Arc<AppState>) {
+    let mut tickers = state.tickers.subscribe();
+    let mut fills = state.fills.subscribe();
+    loop {
+        tokio::select! {
+            ticker = tickers.recv() => match ticker {
+                Ok(update) => on_price(&state, &update.symbol, update.price).await,
+                // The next update carries a newer price
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            fill = fills.recv() => match fill {
+                Ok(fill) => on_fill(&state, &fill).await,
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Bots lagged behind, {} fills skipped", skipped);
+                    resync(&state).await;
+                }
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use crate::data_quality;
+use crate::db;
+use crate::models::{
+    Candle, CandleSeries, CandleSeriesRequest, CandleType, DataGap, Fill, History, Side, Timeline, TradeMarker,
+    TradeOverlay,
+};
+use crate::portfolio::Position;
+use crate::state::AppState;
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+pub const DEFAULT_CANDLE_LIMIT: i64 = 100;
+pub const MAX_CANDLE_LIMIT: i64 = 1000;
+pub const MAX_SERIES_PER_REQUEST: usize = 32;
+
+// Binance style interval names mapped to their length in seconds
+pub fn interval_seconds(interval: &str) -> Option<i64> {
+    match interval {
+        "1s" => Some(1),
+        "1m" => Some(60),
+        "3m" => Some(3 * 60),
+        "5m" => Some(5 * 60),
+        "15m" => Some(15 * 60),
+        "30m" => Some(30 * 60),
+        "1h" => Some(60 * 60),
+        "2h" => Some(2 * 60 * 60),
+        "4h" => Some(4 * 60 * 60),
+        "1d" => Some(24 * 60 * 60),
+        _ => None,
+    }
+}
+
+// Load every requested (symbol, interval) series with one query, the response keeps the request order
+pub async fn get_candle_batch(
+    pool: &PgPool,
+    series: &[CandleSeriesRequest],
+    limit: Option<i64>,
+    timeline: Timeline,
+) -> Result<Vec<CandleSeries>, String> {
+    if series.is_empty() {
+        return Err("At least one candle series is required".to_string());
+    }
+    if series.len() > MAX_SERIES_PER_REQUEST {
+        return Err(format!(
+            "At most {} candle series can be requested at once",
+            MAX_SERIES_PER_REQUEST
+        ));
+    }
+
+    let mut intervals = Vec::with_capacity(series.len());
+    for request in series {
+        match interval_seconds(&request.interval) {
+            Some(seconds) => intervals.push(seconds),
+            None => return Err(format!("Unsupported candle interval {}", request.interval)),
+        }
+        if request.candle_type == CandleType::Renko && !request.brick_size.is_some_and(|size| size > 0.0) {
+            return Err("Renko candles require a positive brick_size".to_string());
+        }
+    }
+    let limit = limit.unwrap_or(DEFAULT_CANDLE_LIMIT).clamp(1, MAX_CANDLE_LIMIT);
+
+    let mut candles = db::get_candles_batch(pool, series, &intervals, limit, timeline)
+        .await
+        .map_err(|e| format!("Error loading candles: {}", e))?;
+
+    let symbols: Vec<String> = series.iter().map(|s| s.symbol.clone()).collect();
+    let since = candles
+        .values()
+        .filter_map(|series| series.first().map(|candle| candle.open_time))
+        .min()
+        .unwrap_or_default();
+    let gaps = db::get_data_gaps(pool, &symbols, since)
+        .await
+        .map_err(|e| format!("Error loading data gaps: {}", e))?;
+
+    for ((symbol, interval), series_candles) in candles.iter_mut() {
+        let symbol_gaps: Vec<DataGap> = gaps.iter().filter(|gap| &gap.symbol == symbol).cloned().collect();
+        let interval_ms = interval_seconds(interval).unwrap_or_default() * 1000;
+        data_quality::mark_gaps(series_candles, &symbol_gaps, interval_ms);
+    }
+
+    Ok(series
+        .iter()
+        .map(|request| {
+            // The same base series may be requested with several candle types, so it is cloned
+            let base = candles
+                .get(&(request.symbol.clone(), request.interval.clone()))
+                .cloned()
+                .unwrap_or_default();
+            let range_start = base.first().map(|candle| candle.open_time).unwrap_or(i64::MAX);
+            let series_gaps = gaps
+                .iter()
+                .filter(|gap| gap.symbol == request.symbol && gap.end > range_start)
+                .cloned()
+                .collect();
+            CandleSeries {
+                symbol: request.symbol.clone(),
+                interval: request.interval.clone(),
+                candle_type: request.candle_type,
+                candles: match request.candle_type {
+                    CandleType::Standard => base,
+                    CandleType::HeikinAshi => heikin_ashi(&base),
+                    CandleType::Renko => renko(&base, request.brick_size.unwrap_or_default()),
+                },
+                gaps: series_gaps,
+                overlay: None,
+            }
+        })
+        .collect())
+}
+
+// Attach the account's fills inside each series' time range, classified against the position they
+// traded into, and its working order levels in the series' symbol
+pub async fn add_trade_overlays(state: &AppState, account_id: &str, series: &mut [CandleSeries]) -> Result<(), String> {
+    let fills = db::get_fills_since(&state.pool, account_id, 0)
+        .await
+        .map_err(|e| format!("Error loading fills: {}", e))?;
+    let markers = trade_markers(&fills);
+
+    let engine = state.engine.lock().await;
+    for series in series.iter_mut() {
+        let interval_ms = interval_seconds(&series.interval).unwrap_or(60) * 1000;
+        let range = series
+            .candles
+            .first()
+            .zip(series.candles.last())
+            .map(|(first, last)| (first.open_time, last.open_time + interval_ms));
+        let series_markers = match range {
+            Some((start, end)) => markers
+                .iter()
+                .filter(|(symbol, marker)| *symbol == series.symbol && marker.time >= start && marker.time < end)
+                .map(|(_, marker)| TradeMarker {
+                    kind: marker.kind.clone(),
+                    candle_time: marker.time - marker.time.rem_euclid(interval_ms),
+                    ..*marker
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        series.overlay = Some(TradeOverlay {
+            account_id: account_id.to_string(),
+            markers: series_markers,
+            levels: engine.order_levels(account_id, &series.symbol),
+        });
+    }
+    Ok(())
+}
+
+// Every fill as an entry, exit or reversal of the position in its symbol
+fn trade_markers(fills: &[Fill]) -> Vec<(String, TradeMarker)> {
+    let mut positions: HashMap<&str, Position> = HashMap::new();
+    fills
+        .iter()
+        .map(|fill| {
+            let position = positions.entry(fill.symbol.as_str()).or_default();
+            let before = position.quantity;
+            let signed = match fill.side {
+                Side::Buy => fill.quantity,
+                Side::Sell => -fill.quantity,
+            };
+            position.apply(signed, fill.price);
+            let after = position.quantity;
+            let kind = if before * after < 0.0 {
+                "reversal"
+            } else if after.abs() > before.abs() {
+                "entry"
+            } else {
+                "exit"
+            };
+            (
+                fill.symbol.clone(),
+                TradeMarker {
+                    order_id: fill.order_id,
+                    side: fill.side,
+                    price: fill.price,
+                    quantity: fill.quantity,
+                    kind: kind.to_string(),
+                    time: fill.created_at,
+                    candle_time: fill.created_at,
+                },
+            )
+        })
+        .collect()
+}
+
+// Bucket sizes tried in order when raw ticks would exceed the point budget
+const HISTORY_RESOLUTIONS: [(&str, i64); 3] = [("1m", 60), ("5m", 5 * 60), ("1h", 60 * 60)];
+pub const DEFAULT_HISTORY_POINTS: i64 = 500;
+pub const MAX_HISTORY_POINTS: i64 = 5000;
+
+// Prices for a time range at the finest resolution that stays within `max_points`
+pub async fn get_history(
+    pool: &PgPool,
+    symbol: &str,
+    from: i64,
+    to: i64,
+    max_points: Option<i64>,
+) -> Result<History, String> {
+    if to <= from {
+        return Err("The range end must be after its start".to_string());
+    }
+    let max_points = max_points.unwrap_or(DEFAULT_HISTORY_POINTS).clamp(1, MAX_HISTORY_POINTS);
+    let range_secs = (to - from + 999) / 1000;
+
+    let tick_count = db::count_ticks(pool, symbol, from, to)
+        .await
+        .map_err(|e| format!("Error counting ticks: {}", e))?;
+
+    let (resolution, mut points, bucket_ms) = if tick_count <= max_points {
+        let points = db::get_ticks(pool, symbol, from, to)
+            .await
+            .map_err(|e| format!("Error loading ticks: {}", e))?;
+        ("raw".to_string(), points, 0)
+    } else {
+        let (name, seconds) = HISTORY_RESOLUTIONS
+            .iter()
+            .find(|(_, seconds)| range_secs / seconds <= max_points)
+            .map(|(name, seconds)| (name.to_string(), *seconds))
+            .unwrap_or_else(|| {
+                // Wider than an hour per point, round the bucket up to whole hours
+                let hours = (range_secs + max_points * 3600 - 1) / (max_points * 3600);
+                (format!("{}h", hours), hours * 3600)
+            });
+        let points = db::get_bucketed_ticks(pool, symbol, from, to, seconds)
+            .await
+            .map_err(|e| format!("Error loading history: {}", e))?;
+        (name, points, seconds * 1000)
+    };
+
+    let gaps = db::get_data_gaps(pool, &[symbol.to_string()], from)
+        .await
+        .map_err(|e| format!("Error loading data gaps: {}", e))?
+        .into_iter()
+        .filter(|gap| gap.start < to)
+        .collect::<Vec<_>>();
+    data_quality::mark_gaps(&mut points, &gaps, bucket_ms);
+
+    Ok(History {
+        symbol: symbol.to_string(),
+        resolution,
+        points,
+        gaps,
+    })
+}
+
+pub fn heikin_ashi(candles: &[Candle]) -> Vec<Candle> {
+    let mut output: Vec<Candle> = Vec::with_capacity(candles.len());
+
+    for candle in candles {
+        let close = (candle.open + candle.high + candle.low + candle.close) / 4.0;
+        let open = match output.last() {
+            Some(previous) => (previous.open + previous.close) / 2.0,
+            None => (candle.open + candle.close) / 2.0,
+        };
+        output.push(Candle {
+            open_time: candle.open_time,
+            open,
+            high: candle.high.max(open).max(close),
+            low: candle.low.min(open).min(close),
+            close,
+            volume: candle.volume,
+            gap: candle.gap,
+        });
+    }
+
+    output
+}
+
+// Traditional Renko on closing prices: a new brick needs a full brick move past the last brick,
+// so a reversal needs two bricks of movement
+pub fn renko(candles: &[Candle], brick_size: f64) -> Vec<Candle> {
+    let mut bricks: Vec<Candle> = Vec::new();
+    let Some(first) = candles.first() else {
+        return bricks;
+    };
+
+    let (mut top, mut bottom) = (first.close, first.close);
+    let mut volume = 0.0;
+    let mut gap = false;
+
+    for candle in candles {
+        volume += candle.volume;
+        gap |= candle.gap;
+
+        while candle.close >= top + brick_size {
+            bricks.push(brick(candle.open_time, top, top + brick_size, volume, gap));
+            gap = false;
+            bottom = top;
+            top += brick_size;
+            volume = 0.0;
+        }
+        while candle.close <= bottom - brick_size {
+            bricks.push(brick(candle.open_time, bottom, bottom - brick_size, volume, gap));
+            gap = false;
+            top = bottom;
+            bottom -= brick_size;
+            volume = 0.0;
+        }
+    }
+
+    bricks
+}
+
+fn brick(open_time: i64, open: f64, close: f64, volume: f64, gap: bool) -> Candle {
+    Candle {
+        open_time,
+        open,
+        high: open.max(close),
+        low: open.min(close),
+        close,
+        volume,
+        gap,
+    }
+}
@@ -0,0 +1,103 @@
+use crate::config::env_or;
+use serde::Deserialize;
+use std::env;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutageMode {
+    OrderErrors,   // Order API answers with a simulated 503
+    OrderTimeouts, // Order API hangs and then reports a 504 without processing the request
+    FeedStall,     // Upstream market data is dropped
+    Full,          // Order errors and a stalled feed at the same time
+}
+
+impl OutageMode {
+    fn parse(value: &str) -> Option<OutageMode> {
+        serde_json::from_value(serde_json::Value::String(value.to_string())).ok()
+    }
+}
+
+// Simulated failure returned to an order request during an outage
+pub struct OrderFailure {
+    pub status: u16,
+    pub message: String,
+    pub delay: Duration,
+}
+
+#[derive(Default)]
+pub struct ChaosState {
+    order_errors_until: Option<Instant>,
+    order_timeouts_until: Option<Instant>,
+    feed_stalled_until: Option<Instant>,
+}
+
+impl ChaosState {
+    pub fn start(&mut self, mode: OutageMode, duration: Duration) {
+        let until = Some(Instant::now() + duration);
+        match mode {
+            OutageMode::OrderErrors => self.order_errors_until = until,
+            OutageMode::OrderTimeouts => self.order_timeouts_until = until,
+            OutageMode::FeedStall => self.feed_stalled_until = until,
+            OutageMode::Full => {
+                self.order_errors_until = until;
+                self.feed_stalled_until = until;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = ChaosState::default();
+    }
+
+    pub fn order_failure(&self) -> Option<OrderFailure> {
+        let now = Instant::now();
+        if self.order_timeouts_until.is_some_and(|until| now < until) {
+            return Some(OrderFailure {
+                status: 504,
+                message: "Simulated exchange timeout, the request was not processed".to_string(),
+                delay: Duration::from_millis(env_or("CHAOS_TIMEOUT_MS", 10_000)),
+            });
+        }
+        if self.order_errors_until.is_some_and(|until| now < until) {
+            return Some(OrderFailure {
+                status: 503,
+                message: "Simulated exchange outage, service unavailable".to_string(),
+                delay: Duration::ZERO,
+            });
+        }
+        None
+    }
+
+    pub fn feed_stalled(&self) -> bool {
+        self.feed_stalled_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+}
+
+// Optional recurring outage: every CHAOS_INTERVAL_SECS, run CHAOS_MODE for CHAOS_DURATION_SECS
+pub struct ChaosSchedule {
+    pub mode: OutageMode,
+    pub every: Duration,
+    pub duration: Duration,
+}
+
+impl ChaosSchedule {
+    pub fn from_env() -> Option<Self> {
+        let every: u64 = env_or("CHAOS_INTERVAL_SECS", 0);
+        if every == 0 {
+            return None;
+        }
+
+        let mode = env::var("CHAOS_MODE")
+            .ok()
+            .and_then(|value| OutageMode::parse(&value))
+            .unwrap_or(OutageMode::Full);
+
+        Some(ChaosSchedule {
+            mode,
+            every: Duration::from_secs(every),
+            duration: Duration::from_secs(env_or("CHAOS_DURATION_SECS", 30)),
+        })
+    }
+}
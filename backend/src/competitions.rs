@@ -0,0 +1,216 @@
+use crate::accounts;
+use crate::config::env_or;
+use crate::db;
+use crate::engine::now_millis;
+use crate::models::{AccountTemplate, Competition, CompetitionStanding, ServerMessage};
+use crate::state::AppState;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+// Rule snapshots are stored as templates under this prefix and can't be edited afterwards
+pub const RULES_PREFIX: &str = "competition-";
+
+// Trading window of every competition account, checked before each order
+#[derive(Default)]
+pub struct CompetitionBook {
+    windows: HashMap<String, (i64, i64)>, // account_id -> (start_time, end_time)
+}
+
+impl CompetitionBook {
+    pub fn load(&mut self, windows: Vec<(String, i64, i64)>) {
+        self.windows = windows
+            .into_iter()
+            .map(|(account_id, start, end)| (account_id, (start, end)))
+            .collect();
+    }
+
+    pub fn enroll(&mut self, account_id: &str, start: i64, end: i64) {
+        self.windows.insert(account_id.to_string(), (start, end));
+    }
+
+    pub fn check(&self, account_id: &str, now: i64) -> Result<(), String> {
+        match self.windows.get(account_id) {
+            Some((start, _)) if now < *start => Err("The competition has not started yet".to_string()),
+            Some((_, end)) if now >= *end => Err("The competition has ended".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn rules_name(competition_id: i64) -> String {
+    format!("{}{}", RULES_PREFIX, competition_id)
+}
+
+// Competition accounts can't be reset or topped up
+pub fn is_competition_account(account_id: &str) -> bool {
+    account_id.starts_with(RULES_PREFIX)
+}
+
+pub fn account_id(competition_id: i64, user_id: &str) -> String {
+    format!("{}{}-{}", RULES_PREFIX, competition_id, user_id)
+}
+
+pub async fn create_competition(
+    state: &AppState,
+    mut competition: Competition,
+    rules: AccountTemplate,
+) -> Result<ServerMessage, String> {
+    if competition.name.trim().is_empty() {
+        return Err("Competition name is required".to_string());
+    }
+    let ordered = competition.enrollment_start < competition.enrollment_end
+        && competition.enrollment_start < competition.end_time
+        && competition.start_time < competition.end_time
+        && competition.enrollment_end <= competition.end_time;
+    if !ordered {
+        return Err("Enrollment must open before it closes, and the competition must start before it ends".to_string());
+    }
+    accounts::validate_template(&state.live_config, &rules)?;
+
+    competition.id = db::create_competition(&state.pool, &competition)
+        .await
+        .map_err(|e| format!("Error creating competition: {}", e))?;
+
+    // Freeze the rules as they are now
+    let rules = AccountTemplate {
+        name: rules_name(competition.id),
+        ..rules
+    };
+    db::save_account_template(&state.pool, &rules)
+        .await
+        .map_err(|e| format!("Error saving competition rules: {}", e))?;
+    db::set_competition_rules(&state.pool, competition.id, &rules.name)
+        .await
+        .map_err(|e| format!("Error saving competition rules: {}", e))?;
+    competition.rules = Some(rules.clone());
+    state.account_templates.lock().await.set_template(rules);
+
+    Ok(ServerMessage::CompetitionCreated(competition))
+}
+
+// Gives the user a fresh account under the competition's frozen rules
+pub async fn enroll(state: &AppState, user_id: &str, competition_id: i64) -> Result<ServerMessage, String> {
+    let competition = db::get_competition(&state.pool, competition_id)
+        .await
+        .map_err(|e| format!("Error loading competition: {}", e))?
+        .ok_or_else(|| format!("Unknown competition {}", competition_id))?;
+    let now = now_millis();
+    if now < competition.enrollment_start || now >= competition.enrollment_end {
+        return Err(format!("Enrollment for {} is closed", competition.name));
+    }
+    let rules = competition
+        .rules
+        .ok_or_else(|| format!("Competition {} has no rules", competition_id))?;
+
+    let account_id = account_id(competition_id, user_id);
+    db::create_account(&state.pool, &account_id, Some(&rules.name))
+        .await
+        .map_err(|e| format!("Error creating competition account: {}", e))?;
+    let enrolled = db::enroll_competition(&state.pool, competition_id, user_id, &account_id)
+        .await
+        .map_err(|e| format!("Error enrolling: {}", e))?;
+    if !enrolled {
+        return Err(format!("Already enrolled in {}", competition.name));
+    }
+    accounts::apply_template(state, &account_id, Some(&rules), rules.starting_balance).await;
+    state
+        .competitions
+        .lock()
+        .await
+        .enroll(&account_id, competition.start_time, competition.end_time);
+
+    Ok(ServerMessage::CompetitionEnrolled {
+        competition_id,
+        account_id,
+        starting_balance: rules.starting_balance,
+    })
+}
+
+// Ranks entrants by return on their starting balance at current marks
+pub async fn live_standings(
+    state: &AppState,
+    competition_id: i64,
+    starting_balance: f64,
+) -> Result<Vec<CompetitionStanding>, sqlx::Error> {
+    let entries = db::get_competition_entries(&state.pool, competition_id).await?;
+    let mut standings = Vec::with_capacity(entries.len());
+    for (user_id, account_id) in entries {
+        let equity = state.portfolio_report(&account_id).await.equity;
+        standings.push(CompetitionStanding {
+            rank: 0,
+            user_id,
+            account_id,
+            equity,
+            return_pct: (equity / starting_balance - 1.0) * 100.0,
+        });
+    }
+    standings.sort_by(|a, b| b.return_pct.total_cmp(&a.return_pct));
+    for (index, standing) in standings.iter_mut().enumerate() {
+        standing.rank = index as u32 + 1;
+    }
+    Ok(standings)
+}
+
+pub async fn standings(state: &AppState, competition_id: i64) -> Result<ServerMessage, String> {
+    let competition = db::get_competition(&state.pool, competition_id)
+        .await
+        .map_err(|e| format!("Error loading competition: {}", e))?
+        .ok_or_else(|| format!("Unknown competition {}", competition_id))?;
+    let standings = if competition.finalized {
+        db::get_competition_results(&state.pool, competition_id).await
+    } else {
+        let starting_balance = competition.rules.as_ref().map(|rules| rules.starting_balance).unwrap_or(1.0);
+        live_standings(state, competition_id, starting_balance).await
+    }
+    .map_err(|e| format!("Error loading standings: {}", e))?;
+
+    Ok(ServerMessage::CompetitionStandings {
+        competition_id,
+        finalized: competition.finalized,
+        standings,
+    })
+}
+
+// Computes and archives the final ranking of every competition that has ended
+pub async fn run_competition_scheduler(state: Arc<AppState>) {
+    let mut ticker = state.simulation.interval(Duration::from_secs(env_or("COMPETITION_CHECK_SECS", 30)));
+    loop {
+        ticker.tick().await;
+
+        let ended = match db::get_unfinalized_competitions(&state.pool, now_millis()).await {
+            Ok(ended) => ended,
+            Err(e) => {
+                eprintln!("Error loading ended competitions: {:?}", e);
+                continue;
+            }
+        };
+
+        for competition in ended {
+            let starting_balance = competition.rules.as_ref().map(|rules| rules.starting_balance).unwrap_or(1.0);
+            let standings = match live_standings(&state, competition.id, starting_balance).await {
+                Ok(standings) => standings,
+                Err(e) => {
+                    eprintln!("Error ranking competition {}: {:?}", competition.id, e);
+                    continue;
+                }
+            };
+            if let Err(e) = db::save_competition_results(&state.pool, competition.id, &standings).await {
+                eprintln!("Error archiving results of competition {}: {:?}", competition.id, e);
+                continue;
+            }
+
+            // Resting orders would otherwise keep trading after the final ranking
+            let mut engine = state.engine.lock().await;
+            for standing in &standings {
+                engine.close_account(&standing.account_id);
+            }
+            println!(
+                "Competition {} ({}) finalized with {} entrants",
+                competition.id,
+                competition.name,
+                standings.len()
+            );
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+// Read an optional setting from the environment, falling back to `default` when unset or invalid
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse::<T>().ok())
+        .unwrap_or(default)
+}
+
+// Settings a config reload changed, layered over the environment the server started with. The
+// process environment is never written while other tasks read it.
+#[derive(Debug, Default)]
+pub struct LiveConfig {
+    reloaded: RwLock<HashMap<String, Option<String>>>, // None for a setting removed from the file
+    file_keys: RwLock<HashSet<String>>,                // Keys the config file held when last read
+}
+
+impl LiveConfig {
+    // Remembers which settings the config file held at startup, so a reload can tell which were removed
+    pub fn from_file(path: &Path) -> Self {
+        let keys = read_config_file(path)
+            .map(|settings| settings.into_iter().map(|(key, _)| key).collect())
+            .unwrap_or_default();
+        LiveConfig {
+            reloaded: RwLock::default(),
+            file_keys: RwLock::new(keys),
+        }
+    }
+
+    pub fn var(&self, key: &str) -> Option<String> {
+        match self.reloaded.read().unwrap().get(key) {
+            Some(value) => value.clone(),
+            None => env::var(key).ok(),
+        }
+    }
+
+    // Like env_or, with the reloaded value first
+    pub fn value_or<T: FromStr>(&self, key: &str, default: T) -> T {
+        self.var(key)
+            .and_then(|value| value.parse::<T>().ok())
+            .unwrap_or(default)
+    }
+
+    pub fn set(&self, key: String, value: String) {
+        self.reloaded.write().unwrap().insert(key, Some(value));
+    }
+
+    // Drops the setting, startup value included, so its default applies again
+    pub fn unset(&self, key: String) {
+        self.reloaded.write().unwrap().insert(key, None);
+    }
+
+    pub fn file_keys(&self) -> HashSet<String> {
+        self.file_keys.read().unwrap().clone()
+    }
+
+    pub fn set_file_keys(&self, keys: HashSet<String>) {
+        *self.file_keys.write().unwrap() = keys;
+    }
+}
+
+// Browser origins allowed to open WebSocket connections and call the REST endpoints.
+// ALLOWED_ORIGINS is a comma separated list such as `https://app.example.com,https://*.example.org`,
+// unset or `*` allows every origin. Requests without an Origin header are not from a browser page and
+// are always accepted.
+#[derive(Debug, Clone)]
+pub struct AllowedOrigins {
+    origins: Option<Vec<String>>, // None allows everything
+}
+
+impl AllowedOrigins {
+    pub fn from_env() -> Self {
+        let origins = env::var("ALLOWED_ORIGINS")
+            .ok()
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|origin| origin.trim().trim_end_matches('/').to_lowercase())
+                    .filter(|origin| !origin.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|origins| !origins.is_empty() && !origins.iter().any(|origin| origin == "*"));
+        AllowedOrigins { origins }
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.origins.is_some()
+    }
+
+    pub fn allows(&self, origin: Option<&str>) -> bool {
+        let (Some(origins), Some(origin)) = (&self.origins, origin) else {
+            return true;
+        };
+        let origin = origin.trim_end_matches('/').to_lowercase();
+        origins.iter().any(|allowed| match allowed.split_once("://*.") {
+            // `scheme://*.domain` matches any subdomain but not the bare domain
+            Some((scheme, domain)) => origin
+                .strip_prefix(scheme)
+                .and_then(|rest| rest.strip_prefix("://"))
+                .is_some_and(|host| host.ends_with(&format!(".{}", domain))),
+            None => *allowed == origin,
+        })
+    }
+}
+
+pub const MIN_PAGE_INTERVAL_SECS: u64 = 5;
+pub const MAX_PAGE_INTERVAL_SECS: u64 = 3600;
+pub const MAX_TICKER_INTERVAL_MS: u64 = 60_000;
+
+// How often the WebSocket channels push by default, PAGE_INTERVAL_SECS for the ticker page and
+// TICKER_INTERVAL_MS between live updates of a subscribed symbol (0 streams every update). A
+// connection may pick its own within the bounds above. The defaults change on a config reload.
+#[derive(Debug)]
+pub struct UpdateIntervals {
+    page_secs: AtomicU64,
+    ticker_ms: AtomicU64,
+}
+
+impl UpdateIntervals {
+    pub fn from_env() -> Self {
+        Self::from_config(&LiveConfig::default())
+    }
+
+    pub fn from_config(config: &LiveConfig) -> Self {
+        UpdateIntervals {
+            page_secs: AtomicU64::new(
+                config
+                    .value_or("PAGE_INTERVAL_SECS", 60)
+                    .clamp(MIN_PAGE_INTERVAL_SECS, MAX_PAGE_INTERVAL_SECS),
+            ),
+            ticker_ms: AtomicU64::new(config.value_or("TICKER_INTERVAL_MS", 0).min(MAX_TICKER_INTERVAL_MS)),
+        }
+    }
+
+    // Takes the defaults of `other`, connections pick them up with their next subscription
+    pub fn set(&self, other: UpdateIntervals) {
+        self.page_secs.store(other.page_secs.into_inner(), Ordering::Relaxed);
+        self.ticker_ms.store(other.ticker_ms.into_inner(), Ordering::Relaxed);
+    }
+
+    // A connection's requested interval held to the bounds, the default when it asked for none
+    pub fn page(&self, requested: Option<u64>) -> u64 {
+        requested.map_or(self.page_secs.load(Ordering::Relaxed), |secs| {
+            secs.clamp(MIN_PAGE_INTERVAL_SECS, MAX_PAGE_INTERVAL_SECS)
+        })
+    }
+
+    pub fn ticker(&self, requested: Option<u64>) -> u64 {
+        requested.map_or(self.ticker_ms.load(Ordering::Relaxed), |ms| ms.min(MAX_TICKER_INTERVAL_MS))
+    }
+}
+
+// The config file read at startup and on reloads, CONFIG_FILE or `.env`
+pub fn config_file() -> PathBuf {
+    PathBuf::from(env::var("CONFIG_FILE").unwrap_or_else(|_| ".env".to_string()))
+}
+
+// Settings in the config file, in file order. The iterator is deprecated, but it is dotenv's only
+// way to read a file without writing it into the process environment.
+#[allow(deprecated)]
+pub fn read_config_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    dotenv::from_path_iter(path)
+        .and_then(|settings| settings.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Error reading {}: {}", path.display(), e))
+}
@@ -0,0 +1,109 @@
+use crate::config::LiveConfig;
+use crate::models::{Conversion, UsdPricing};
+
+// Currencies tried as the middle leg when no direct pair is listed
+const BRIDGE_CURRENCIES: [&str; 2] = ["USDT", "BTC"];
+// Prices in this quote are taken as USD
+const USD_QUOTE: &str = "USDT";
+
+// Rate to turn one unit of `from` into `to` through a single listed pair, in either direction
+fn pair_rate(from: &str, to: &str, latest_price: &impl Fn(&str) -> Option<f64>) -> Option<(f64, String)> {
+    let direct = format!("{}{}", from, to);
+    if let Some(price) = latest_price(&direct).filter(|price| *price > 0.0) {
+        return Some((price, direct));
+    }
+    let inverse = format!("{}{}", to, from);
+    latest_price(&inverse)
+        .filter(|price| *price > 0.0)
+        .map(|price| (1.0 / price, inverse))
+}
+
+// Convert using the latest cached prices, directly or through USDT or BTC
+pub fn convert(
+    from: &str,
+    to: &str,
+    amount: f64,
+    latest_price: impl Fn(&str) -> Option<f64>,
+) -> Result<Conversion, String> {
+    let (from, to) = (from.trim().to_uppercase(), to.trim().to_uppercase());
+
+    let (rate, route) = if from == to {
+        (1.0, Vec::new())
+    } else if let Some((rate, symbol)) = pair_rate(&from, &to, &latest_price) {
+        (rate, vec![symbol])
+    } else {
+        BRIDGE_CURRENCIES
+            .iter()
+            .filter(|bridge| **bridge != from && **bridge != to)
+            .find_map(|bridge| {
+                let (first_rate, first) = pair_rate(&from, bridge, &latest_price)?;
+                let (second_rate, second) = pair_rate(bridge, &to, &latest_price)?;
+                Some((first_rate * second_rate, vec![first, second]))
+            })
+            .ok_or_else(|| format!("No price route from {} to {}", from, to))?
+    };
+
+    Ok(Conversion {
+        from,
+        to,
+        amount,
+        result: amount * rate,
+        rate,
+        route,
+    })
+}
+
+// Quote assets symbols are listed against, QUOTE_ASSETS. A symbol's quote is the longest one it
+// ends with, USDT is always included.
+pub struct QuoteAssets {
+    assets: Vec<String>,
+}
+
+impl QuoteAssets {
+    pub fn from_env() -> Self {
+        Self::from_config(&LiveConfig::default())
+    }
+
+    pub fn from_config(config: &LiveConfig) -> Self {
+        let list = config.value_or("QUOTE_ASSETS", "USDT,USDC,FDUSD,BTC,ETH,BNB".to_string());
+        let mut assets: Vec<String> = list
+            .split(',')
+            .map(|asset| asset.trim().to_uppercase())
+            .filter(|asset| !asset.is_empty())
+            .collect();
+        if !assets.iter().any(|asset| asset == USD_QUOTE) {
+            assets.push(USD_QUOTE.to_string());
+        }
+        assets.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        assets.dedup();
+        QuoteAssets { assets }
+    }
+
+    pub fn is_known(&self, asset: &str) -> bool {
+        self.assets.iter().any(|known| known == asset)
+    }
+
+    pub fn quote_of<'a>(&'a self, symbol: &str) -> Option<&'a str> {
+        self.assets
+            .iter()
+            .find(|asset| symbol.len() > asset.len() && symbol.ends_with(asset.as_str()))
+            .map(String::as_str)
+    }
+
+    // The symbol's quote asset and its price in USD through the quote's USDT pair, the price is None
+    // until that pair has traded
+    pub fn usd_pricing(&self, symbol: &str, price: f64, latest_price: impl Fn(&str) -> Option<f64>) -> UsdPricing {
+        let Some(quote) = self.quote_of(symbol) else {
+            return UsdPricing::default();
+        };
+        let rate = if quote == USD_QUOTE {
+            Some(1.0)
+        } else {
+            latest_price(&format!("{}{}", quote, USD_QUOTE)).filter(|rate| *rate > 0.0)
+        };
+        UsdPricing {
+            quote_asset: Some(quote.to_string()),
+            usd_price: rate.map(|rate| price * rate),
+        }
+    }
+}
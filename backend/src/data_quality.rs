@@ -0,0 +1,37 @@
+use crate::config::env_or;
+use crate::db;
+use crate::models::{Candle, DataGap};
+use sqlx::PgPool;
+use tokio::time::{interval, Duration};
+
+// Scan the ticker stream for gaps and record them, so candles and backtests can flag unreliable periods
+pub async fn run_gap_scanner(pool: PgPool) {
+    let min_gap_secs: f64 = env_or("GAP_MIN_SECS", 5.0);
+    let cadence_multiplier: f64 = env_or("GAP_CADENCE_MULTIPLIER", 10.0);
+
+    let mut ticker = interval(Duration::from_secs(env_or("GAP_SCAN_INTERVAL_SECS", 60)));
+    loop {
+        ticker.tick().await;
+
+        match db::detect_data_gaps(&pool, min_gap_secs, cadence_multiplier).await {
+            Ok(0) => {}
+            Ok(count) => println!("Recorded {} new data gaps", count),
+            Err(e) => eprintln!("Error scanning for data gaps: {:?}", e),
+        }
+    }
+}
+
+// Flag every candle whose bucket overlaps one of the series' gaps, a zero interval marks raw ticks
+// that directly follow a gap
+pub fn mark_gaps(candles: &mut [Candle], gaps: &[DataGap], interval_ms: i64) {
+    for candle in candles {
+        let candle_end = candle.open_time + interval_ms;
+        candle.gap = gaps.iter().any(|gap| {
+            if interval_ms == 0 {
+                gap.end == candle.open_time
+            } else {
+                gap.start < candle_end && gap.end > candle.open_time
+            }
+        });
+    }
+}
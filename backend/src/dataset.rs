@@ -0,0 +1,283 @@
+use crate::candles;
+use crate::config::env_or;
+use crate::models::{Candle, TickerUpdate};
+use crate::state::AppState;
+use crate::template::format_day;
+use parquet::basic::Compression;
+use parquet::data_type::{DoubleType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::RowAccessor;
+use parquet::schema::parser::parse_message_type;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Duration;
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+const COMPACTED_FILE: &str = "data.parquet";
+
+// Partition keys live in the directory names, so a file only holds the candle columns
+const SCHEMA: &str = "
+    message candle {
+        REQUIRED INT64 open_time (TIMESTAMP(MILLIS, true));
+        REQUIRED DOUBLE open;
+        REQUIRED DOUBLE high;
+        REQUIRED DOUBLE low;
+        REQUIRED DOUBLE close;
+        REQUIRED DOUBLE volume;
+    }
+";
+
+// A Parquet dataset of candles for research, laid out as
+// `<PARQUET_DATASET_DIR>/symbol=BTCUSDT/date=2024-05-01/*.parquet` so DuckDB and Polars read it with
+// hive partitioning, e.g. `read_parquet('dataset/**/*.parquet', hive_partitioning = true)`.
+pub struct DatasetConfig {
+    dir: PathBuf,
+    interval: String,
+    interval_ms: i64,
+    flush_every: Duration,
+}
+
+impl DatasetConfig {
+    // Enabled by PARQUET_DATASET_DIR, candles are PARQUET_DATASET_INTERVAL long (1m by default)
+    pub fn from_env() -> Option<Self> {
+        let dir = env::var("PARQUET_DATASET_DIR").ok().filter(|dir| !dir.is_empty())?;
+        let interval = env_or("PARQUET_DATASET_INTERVAL", "1m".to_string());
+        let Some(seconds) = candles::interval_seconds(&interval) else {
+            eprintln!("Parquet dataset disabled, unsupported PARQUET_DATASET_INTERVAL {}", interval);
+            return None;
+        };
+        Some(DatasetConfig {
+            dir: PathBuf::from(dir),
+            interval,
+            interval_ms: seconds * 1000,
+            flush_every: Duration::from_secs(env_or("PARQUET_FLUSH_SECS", 300).max(1)),
+        })
+    }
+}
+
+// A candle still taking ticks. Volume is the growth of the rolling 24h quote volume over the
+// bucket, as for candles built in the database.
+struct OpenCandle {
+    candle: Candle,
+    first_volume: f64,
+}
+
+impl OpenCandle {
+    fn new(open_time: i64, update: &TickerUpdate) -> Self {
+        OpenCandle {
+            candle: Candle {
+                open_time,
+                open: update.price,
+                high: update.price,
+                low: update.price,
+                close: update.price,
+                volume: 0.0,
+                gap: false,
+            },
+            first_volume: update.quote_volume,
+        }
+    }
+
+    fn add(&mut self, update: &TickerUpdate) {
+        self.candle.high = self.candle.high.max(update.price);
+        self.candle.low = self.candle.low.min(update.price);
+        self.candle.close = update.price;
+        self.candle.volume = (update.quote_volume - self.first_volume).max(0.0);
+    }
+}
+
+// Builds candles from the live ticker stream and appends the closed ones to the dataset every
+// PARQUET_FLUSH_SECS, one new file per partition and flush. Days that have ended are then compacted
+// into a single file. Candles still open when the server stops are lost, a restart mid-bucket
+// writes a second partial candle that compaction merges with the first.
+pub async fn run_dataset_writer(state: Arc<AppState>, config: DatasetConfig) {
+    let config = Arc::new(config);
+    println!("Writing {} candles to the Parquet dataset in {}", config.interval, config.dir.display());
+    let mut tickers = state.tickers.subscribe();
+    let mut flush = state.simulation.interval_at(config.flush_every, config.flush_every);
+    let mut open: HashMap<String, OpenCandle> = HashMap::new();
+    let mut closed: BTreeMap<(String, i64), Vec<Candle>> = BTreeMap::new(); // By (symbol, day)
+
+    loop {
+        tokio::select! {
+            ticker = tickers.recv() => match ticker {
+                Ok(update) => {
+                    let open_time = update.event_time - update.event_time.rem_euclid(config.interval_ms);
+                    match open.get_mut(&update.symbol) {
+                        Some(building) if building.candle.open_time == open_time => building.add(&update),
+                        // Ticks arriving late for a closed bucket are dropped
+                        Some(building) if building.candle.open_time > open_time => {}
+                        _ => {
+                            let started = OpenCandle::new(open_time, &update);
+                            if let Some(done) = open.insert(update.symbol.clone(), started) {
+                                let day = done.candle.open_time.div_euclid(MILLIS_PER_DAY);
+                                closed.entry((update.symbol.clone(), day)).or_default().push(done.candle);
+                            }
+                        }
+                    }
+                }
+                // Missed ticks only make the affected candles coarser
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            _ = flush.tick() => {
+                let batch = mem::take(&mut closed);
+                let today = state.simulation.now_millis().div_euclid(MILLIS_PER_DAY);
+                let flushed_at = state.simulation.now_millis();
+                let config = Arc::clone(&config);
+                let written = tokio::task::spawn_blocking(move || {
+                    write_batch(&config.dir, batch, flushed_at)?;
+                    compact_finished_days(&config.dir, today)
+                })
+                .await;
+                match written {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => eprintln!("Error writing the Parquet dataset: {}", e),
+                    Err(e) => eprintln!("Parquet dataset writer panicked: {}", e),
+                }
+            }
+        }
+    }
+}
+
+fn partition_dir(dir: &Path, symbol: &str, day: i64) -> PathBuf {
+    dir.join(format!("symbol={}", symbol)).join(format!("date={}", format_day(day)))
+}
+
+fn write_batch(dir: &Path, batch: BTreeMap<(String, i64), Vec<Candle>>, flushed_at: i64) -> Result<(), String> {
+    for ((symbol, day), candles) in batch {
+        let partition = partition_dir(dir, &symbol, day);
+        fs::create_dir_all(&partition).map_err(|e| format!("creating {}: {}", partition.display(), e))?;
+        write_file(&partition.join(format!("part-{}.parquet", flushed_at)), &candles)?;
+    }
+    Ok(())
+}
+
+// Merges the flushed parts of every day before `today` into one file per partition, sorted by
+// open time with candles split across a restart merged back together
+fn compact_finished_days(dir: &Path, today: i64) -> Result<(), String> {
+    let Ok(symbols) = fs::read_dir(dir) else {
+        return Ok(()); // Nothing was flushed yet
+    };
+    let today = format_day(today);
+    for symbol in symbols.filter_map(|entry| entry.ok()) {
+        let Ok(dates) = fs::read_dir(symbol.path()) else {
+            continue;
+        };
+        for date in dates.filter_map(|entry| entry.ok()) {
+            let finished = date
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("date="))
+                .is_some_and(|day| day < today.as_str());
+            if finished {
+                compact_partition(&date.path())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn compact_partition(partition: &Path) -> Result<(), String> {
+    let mut files: Vec<PathBuf> = fs::read_dir(partition)
+        .map_err(|e| format!("listing {}: {}", partition.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "parquet"))
+        .collect();
+    let has_parts = files.iter().any(|path| !path.ends_with(COMPACTED_FILE));
+    if !has_parts {
+        return Ok(());
+    }
+    // The compacted file sorts before the parts, which sort by flush time
+    files.sort();
+
+    let mut merged: BTreeMap<i64, Candle> = BTreeMap::new();
+    for path in &files {
+        for candle in read_file(path)? {
+            match merged.get_mut(&candle.open_time) {
+                Some(earlier) => {
+                    earlier.high = earlier.high.max(candle.high);
+                    earlier.low = earlier.low.min(candle.low);
+                    earlier.close = candle.close;
+                    earlier.volume += candle.volume;
+                }
+                None => {
+                    merged.insert(candle.open_time, candle);
+                }
+            }
+        }
+    }
+
+    let candles: Vec<Candle> = merged.into_values().collect();
+    write_file(&partition.join(COMPACTED_FILE), &candles)?;
+    for path in files.iter().filter(|path| !path.ends_with(COMPACTED_FILE)) {
+        fs::remove_file(path).map_err(|e| format!("removing {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+// Written under a temporary name and renamed, so readers never see a half written file
+fn write_file(path: &Path, candles: &[Candle]) -> Result<(), String> {
+    let staging = path.with_extension("parquet.tmp");
+    write_parquet(&staging, candles).map_err(|e| format!("writing {}: {}", staging.display(), e))?;
+    fs::rename(&staging, path).map_err(|e| format!("renaming {}: {}", staging.display(), e))
+}
+
+fn write_parquet(path: &Path, candles: &[Candle]) -> Result<(), ParquetError> {
+    let schema = Arc::new(parse_message_type(SCHEMA)?);
+    let properties = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+    let file = File::create(path)?;
+    let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+
+    let open_times: Vec<i64> = candles.iter().map(|candle| candle.open_time).collect();
+    let prices: [Vec<f64>; 5] = [
+        candles.iter().map(|candle| candle.open).collect(),
+        candles.iter().map(|candle| candle.high).collect(),
+        candles.iter().map(|candle| candle.low).collect(),
+        candles.iter().map(|candle| candle.close).collect(),
+        candles.iter().map(|candle| candle.volume).collect(),
+    ];
+
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        // Columns come in schema order
+        match index {
+            0 => column.typed::<Int64Type>().write_batch(&open_times, None, None)?,
+            _ => column.typed::<DoubleType>().write_batch(&prices[index - 1], None, None)?,
+        };
+        column.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+fn read_file(path: &Path) -> Result<Vec<Candle>, String> {
+    let read_error = |e: ParquetError| format!("reading {}: {}", path.display(), e);
+    let file = File::open(path).map_err(|e| format!("opening {}: {}", path.display(), e))?;
+    let reader = SerializedFileReader::new(file).map_err(read_error)?;
+    let mut candles = Vec::new();
+    for row in reader.get_row_iter(None).map_err(read_error)? {
+        let row = row.map_err(read_error)?;
+        candles.push(Candle {
+            open_time: row.get_timestamp_millis(0).map_err(read_error)?,
+            open: row.get_double(1).map_err(read_error)?,
+            high: row.get_double(2).map_err(read_error)?,
+            low: row.get_double(3).map_err(read_error)?,
+            close: row.get_double(4).map_err(read_error)?,
+            volume: row.get_double(5).map_err(read_error)?,
+            gap: false,
+        });
+    }
+    Ok(candles)
+}
@@ -1,8 +1,23 @@
-use crate::models::{TickerData, VolumeData, PaginatedResponse, PaginationParams};
+use crate::models::{
+    AccountEvent, Candle, CandleSeriesRequest, DataGap, MirrorAccount, Order, RecordedEvent, PricePoint, MirrorBalance, MirrorFill, MirrorPosition, PaginatedResponse, PaginationParams,
+    AccountTemplate, ApiKey, AttributedFill, AuditEntry, Competition, CompetitionStanding, JobRecord, JobStatus, OrderProposal, PortfolioReport, Team, TradingRules,
+    TeamMember, ChannelKind, DepegAlertSettings, DrawdownAlertSettings, FeatureFlagSetting, Fill, GridBot, Bot, InboxNotification, KeyLevels, Liquidity, MarketTrade, Role, NotificationChannel, ReportSchedule, RiskMetrics, Side,
+    SpreadDefinition, StorageKind, Tenant, TenantUsageSnapshot, TickerData, TickerQuery, TickerSort, Timeline, UsageSnapshot, UsdPricing, UserStorage, VolumeData,
+};
+use crate::backfill::Kline;
+use crate::engine::{now_millis, OrderChanges, OrderGroup, StoredOrders};
+use crate::portfolio::Portfolio;
+use crate::config::env_or;
+use crate::risk::{self, ReturnSeries};
+use crate::tick_filter::TickAnomaly;
+use crate::resilience;
+use sqlx::postgres::PgPoolOptions;
 use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::time::Duration;
 
 pub async fn init_db(database_url: &str) -> Result<PgPool, sqlx::Error> {
-    let pool = PgPool::connect(database_url).await?;
+    let pool = connect(database_url).await?;
 
     // Create the timescaledb extension
     sqlx::query("CREATE EXTENSION IF NOT EXISTS timescaledb;")
@@ -26,18 +41,44 @@ pub async fn init_db(database_url: &str) -> Result<PgPool, sqlx::Error> {
     .execute(&pool)
     .await?;
 
-    // Create the hypertable with a chunk interval of 10 minutes on the created_at column to store the data with time-series optimizations
+    // Server receive time next to the exchange event time in created_at
+    sqlx::query("ALTER TABLE ticker_data ADD COLUMN IF NOT EXISTS received_at TIMESTAMPTZ;")
+        .execute(&pool)
+        .await?;
+
+    // Quote asset and the price in USD through its USDT pair, for listing symbols of different quotes together
+    sqlx::query("ALTER TABLE ticker_data ADD COLUMN IF NOT EXISTS quote_asset TEXT, ADD COLUMN IF NOT EXISTS usd_price DOUBLE PRECISION;")
+        .execute(&pool)
+        .await?;
+
+    // Create the hypertable on the created_at column with hash space partitions on symbol, so concurrent
+    // writes for different symbols land in different chunks
+    let space_partitions: i32 = env_or("TICKER_SPACE_PARTITIONS", 4);
     sqlx::query(
         r#"
-        SELECT create_hypertable('ticker_data', 'created_at', 
+        SELECT create_hypertable('ticker_data', 'created_at',
+            partitioning_column => 'symbol',
+            number_partitions => $1,
             if_not_exists => TRUE,
             chunk_time_interval => INTERVAL '10 minutes'
         );
         "#,
     )
+    .bind(space_partitions)
     .execute(&pool)
     .await?;
 
+    // Hypertables created before space partitioning only accept the new dimension while empty
+    if let Err(e) = sqlx::query("SELECT add_dimension('ticker_data', 'symbol', number_partitions => $1, if_not_exists => TRUE);")
+        .bind(space_partitions)
+        .execute(&pool)
+        .await
+    {
+        eprintln!("Could not add symbol space partitioning to ticker_data, truncate it to migrate: {:?}", e);
+    }
+
+    tune_chunk_interval(&pool).await?;
+
     // Compress the hypertable with the created_at column as the order and symbol as the segment
     sqlx::query(
         r#"
@@ -86,85 +127,4859 @@ pub async fn init_db(database_url: &str) -> Result<PgPool, sqlx::Error> {
     .execute(&pool)
     .await?;
 
-    Ok(pool)
-}
+    // Per-minute continuous aggregate of the cumulative quote volume, it outlives the 1 hour raw
+    // retention and backs the rolling 24h volume leaderboard
+    sqlx::query(
+        r#"
+        CREATE MATERIALIZED VIEW IF NOT EXISTS volume_1m
+        WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+        SELECT
+            time_bucket(INTERVAL '1 minute', created_at) AS bucket,
+            symbol,
+            last(quote_volume, created_at) AS last_quote_volume
+        FROM ticker_data
+        GROUP BY bucket, symbol
+        WITH NO DATA;
+        "#,
+    )
+    .execute(&pool)
+    .await?;
 
-pub async fn save_ticker_data(pool: &PgPool, ticker: &TickerData) -> Result<(), sqlx::Error> {
+    // Refresh well inside the raw retention window so dropped chunks never erase materialized minutes
     sqlx::query(
         r#"
-        INSERT INTO ticker_data 
-        (symbol, close_price, open_price, high_price, low_price, quote_volume, created_at)
-        VALUES ($1, $2, $3, $4, $5, $6, to_timestamp($7::double precision / 1000) AT TIME ZONE 'UTC')
-        ON CONFLICT DO NOTHING
+        SELECT add_continuous_aggregate_policy('volume_1m',
+            start_offset => INTERVAL '50 minutes',
+            end_offset => INTERVAL '1 minute',
+            schedule_interval => INTERVAL '1 minute',
+            if_not_exists => TRUE
+        );
         "#,
     )
-    .bind(&ticker.s)
-    .bind(ticker.c.parse::<f64>().unwrap_or_default())
-    .bind(ticker.o.parse::<f64>().unwrap_or_default())
-    .bind(ticker.h.parse::<f64>().unwrap_or_default())
-    .bind(ticker.l.parse::<f64>().unwrap_or_default())
-    .bind(ticker.q.parse::<f64>().unwrap_or_default())
-    .bind(ticker.E)
-    .execute(pool)
+    .execute(&pool)
     .await?;
 
-    Ok(())
-}
+    sqlx::query(
+        r#"
+        SELECT add_retention_policy('volume_1m',
+            INTERVAL '2 days',
+            if_not_exists => TRUE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
 
-pub async fn get_latest_tickers(
-    pool: &PgPool,
-    page: i64,
-    per_page: i64,
-) -> Result<PaginatedResponse, sqlx::Error> {
-    let total: i64 = sqlx::query_scalar(
-        "SELECT COUNT(DISTINCT symbol) FROM ticker_data"
+    // Create the mirror tables holding the state of linked real exchange accounts
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS mirror_balances (
+            account_id TEXT,
+            asset TEXT,
+            wallet_balance DECIMAL,
+            available_balance DECIMAL,
+            updated_at TIMESTAMPTZ DEFAULT NOW(),
+            PRIMARY KEY (account_id, asset)
+        );
+        "#,
     )
-    .fetch_one(pool)
+    .execute(&pool)
     .await?;
 
-    let offset = (page - 1) * per_page;
-    
-    // Query the latest data for each symbol and sort by volume in descending order with pagination
-    let volume_data = sqlx::query(
+    sqlx::query(
         r#"
-        WITH LatestData AS (
-            SELECT DISTINCT ON (symbol) 
-                symbol,
-                close_price,
-                quote_volume,
-                created_at
-            FROM ticker_data
-            ORDER BY symbol ASC, created_at DESC
-        ),
-        SortedData AS (
-            SELECT 
-                symbol,
-                CAST(close_price AS DOUBLE PRECISION) as close_price,
-                CAST(quote_volume AS DOUBLE PRECISION) as quote_volume
-            FROM LatestData
-            ORDER BY quote_volume DESC
-            LIMIT $1
-            OFFSET $2
-        )
-        SELECT * FROM SortedData
+        CREATE TABLE IF NOT EXISTS mirror_positions (
+            account_id TEXT,
+            symbol TEXT,
+            position_side TEXT,
+            position_amount DECIMAL,
+            entry_price DECIMAL,
+            unrealized_pnl DECIMAL,
+            updated_at TIMESTAMPTZ DEFAULT NOW(),
+            PRIMARY KEY (account_id, symbol, position_side)
+        );
         "#,
     )
-    .bind(per_page)
-    .bind(offset)
-    .try_map(|row: sqlx::postgres::PgRow| {
-        Ok(VolumeData {
-            symbol: row.try_get("symbol")?,
-            price: row.try_get("close_price")?,
-            volume: row.try_get("quote_volume")?,
-        })
-    })
-    .fetch_all(pool)
+    .execute(&pool)
     .await?;
 
-    Ok(PaginatedResponse {
-        data: volume_data,
-        total,
-        page,
-        per_page,
-    })
-}
\ No newline at end of file
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS mirror_fills (
+            account_id TEXT,
+            trade_id BIGINT,
+            order_id BIGINT,
+            symbol TEXT,
+            side TEXT,
+            price DECIMAL,
+            quantity DECIMAL,
+            commission DECIMAL,
+            created_at TIMESTAMPTZ,
+            PRIMARY KEY (account_id, symbol, trade_id)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Create the daily closes table, the return series behind the portfolio risk metrics
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS daily_closes (
+            symbol TEXT,
+            day DATE,
+            close_price DECIMAL,
+            PRIMARY KEY (symbol, day)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS portfolio_risk (
+            account_id TEXT,
+            confidence DOUBLE PRECISION,
+            value_at_risk DOUBLE PRECISION,
+            expected_shortfall DOUBLE PRECISION,
+            scenarios INTEGER,
+            computed_at TIMESTAMPTZ
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS drawdown_alerts (
+            account_id TEXT PRIMARY KEY,
+            threshold DOUBLE PRECISION,
+            webhook_url TEXT
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS depeg_alerts (
+            account_id TEXT PRIMARY KEY,
+            threshold DOUBLE PRECISION,
+            webhook_url TEXT
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Feature flag settings, an empty user_id is the deployment-wide setting
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS feature_flags (
+            flag TEXT NOT NULL,
+            user_id TEXT NOT NULL DEFAULT '',
+            enabled BOOLEAN NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (flag, user_id)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Symbols seen in exchangeInfo, listings and delistings are diffed against it
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS symbol_listings (
+            symbol TEXT PRIMARY KEY,
+            status TEXT NOT NULL,
+            trading BOOLEAN NOT NULL,
+            changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS fills (
+            order_id BIGINT,
+            account_id TEXT,
+            symbol TEXT,
+            side TEXT,
+            price DECIMAL,
+            quantity DECIMAL,
+            liquidity TEXT,
+            created_at TIMESTAMPTZ
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_fills_account
+        ON fills (account_id, created_at DESC);
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Engine-assigned fill ids, a fill stored twice is dropped. Fills from before had none.
+    sqlx::query("ALTER TABLE fills ADD COLUMN IF NOT EXISTS fill_id BIGINT;")
+        .execute(&pool)
+        .await?;
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_fills_fill_id ON fills (fill_id);")
+        .execute(&pool)
+        .await?;
+
+    // Simulated orders and take-profit groups as last stored, the matching engine is rebuilt from
+    // them on startup. `revision` orders the engine's change batches.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS orders (
+            order_id BIGINT PRIMARY KEY,
+            account_id TEXT NOT NULL,
+            status TEXT NOT NULL,
+            body JSONB NOT NULL,
+            revision BIGINT NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS order_groups (
+            group_id BIGINT PRIMARY KEY,
+            account_id TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            entry_order_id BIGINT NOT NULL,
+            take_profit_order_ids BIGINT[] NOT NULL,
+            cancelled BOOLEAN NOT NULL,
+            revision BIGINT NOT NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Append-only history of every account, numbered per account. Cash and positions are rebuilt
+    // from it, starting at the latest snapshot.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS account_events (
+            account_id TEXT NOT NULL,
+            sequence BIGINT NOT NULL,
+            event JSONB NOT NULL,
+            recorded_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY (account_id, sequence)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS account_snapshots (
+            account_id TEXT PRIMARY KEY,
+            sequence BIGINT NOT NULL,
+            portfolio JSONB NOT NULL,
+            recorded_at TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Every stored change of an order's state, new through partially filled to filled or cancelled
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS order_transitions (
+            order_id BIGINT NOT NULL,
+            revision BIGINT NOT NULL,
+            status TEXT NOT NULL,
+            filled_quantity DOUBLE PRECISION NOT NULL,
+            version BIGINT NOT NULL,
+            recorded_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY (order_id, revision)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS account_templates (
+            name TEXT PRIMARY KEY,
+            starting_balance DOUBLE PRECISION NOT NULL,
+            allowed_symbols TEXT[] NOT NULL,
+            max_leverage DOUBLE PRECISION,
+            fee_tier TEXT NOT NULL,
+            updated_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Explicitly created accounts, others still come into being on their first order
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS accounts (
+            account_id TEXT PRIMARY KEY,
+            template TEXT REFERENCES account_templates (name),
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS competitions (
+            id BIGSERIAL PRIMARY KEY,
+            name TEXT NOT NULL,
+            enrollment_start TIMESTAMPTZ NOT NULL,
+            enrollment_end TIMESTAMPTZ NOT NULL,
+            start_time TIMESTAMPTZ NOT NULL,
+            end_time TIMESTAMPTZ NOT NULL,
+            rules TEXT REFERENCES account_templates (name),
+            finalized_at TIMESTAMPTZ
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS competition_entries (
+            competition_id BIGINT REFERENCES competitions (id),
+            user_id TEXT NOT NULL,
+            account_id TEXT NOT NULL,
+            enrolled_at TIMESTAMPTZ DEFAULT NOW(),
+            PRIMARY KEY (competition_id, user_id)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS competition_results (
+            competition_id BIGINT REFERENCES competitions (id),
+            rank INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            account_id TEXT NOT NULL,
+            equity DOUBLE PRECISION NOT NULL,
+            return_pct DOUBLE PRECISION NOT NULL,
+            PRIMARY KEY (competition_id, user_id)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS teams (
+            account_id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            approval_mode BOOLEAN NOT NULL DEFAULT FALSE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Order notional above which approval is needed, zero holds every order
+    sqlx::query("ALTER TABLE teams ADD COLUMN IF NOT EXISTS approval_min_notional DOUBLE PRECISION NOT NULL DEFAULT 0;")
+        .execute(&pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS team_members (
+            account_id TEXT REFERENCES teams (account_id),
+            user_id TEXT NOT NULL,
+            role TEXT NOT NULL,
+            PRIMARY KEY (account_id, user_id)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Member behind each team order. Order ids restart with the engine, so fills are matched to
+    // the latest attribution placed before them.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS order_attribution (
+            account_id TEXT NOT NULL,
+            order_id BIGINT NOT NULL,
+            user_id TEXT NOT NULL,
+            placed_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_order_attribution
+        ON order_attribution (account_id, order_id, placed_at DESC);
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS order_proposals (
+            proposal_id BIGSERIAL PRIMARY KEY,
+            account_id TEXT NOT NULL,
+            proposed_by TEXT NOT NULL,
+            request JSONB NOT NULL,
+            status TEXT NOT NULL,
+            decided_by TEXT,
+            order_id BIGINT,
+            created_at TIMESTAMPTZ NOT NULL,
+            decided_at TIMESTAMPTZ
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Fills of an account before each reset, kept out of the live history
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS account_resets (
+            reset_id BIGSERIAL PRIMARY KEY,
+            account_id TEXT NOT NULL,
+            cash_before DOUBLE PRECISION,
+            equity_before DOUBLE PRECISION,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS archived_fills (
+            LIKE fills,
+            reset_id BIGINT REFERENCES account_resets (reset_id)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("ALTER TABLE archived_fills ADD COLUMN IF NOT EXISTS fill_id BIGINT;")
+        .execute(&pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS trading_rules (
+            account_id TEXT PRIMARY KEY,
+            rules JSONB NOT NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Share tokens of published accounts, stored hashed like API key secrets
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS public_profiles (
+            account_id TEXT PRIMARY KEY,
+            token_hash TEXT UNIQUE NOT NULL,
+            delay_secs BIGINT NOT NULL DEFAULT 0,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS account_top_ups (
+            account_id TEXT NOT NULL,
+            amount DOUBLE PRECISION NOT NULL,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS report_schedules (
+            account_id TEXT PRIMARY KEY,
+            send_at TEXT,
+            channel TEXT,
+            target TEXT,
+            last_sent_day BIGINT,
+            last_equity DOUBLE PRECISION
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS data_gaps (
+            symbol TEXT,
+            gap_start TIMESTAMPTZ,
+            gap_end TIMESTAMPTZ,
+            detected_at TIMESTAMPTZ DEFAULT NOW(),
+            PRIMARY KEY (symbol, gap_start)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Ticks rejected or flagged by the ingestion sanity filter
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS quarantined_ticks (
+            symbol TEXT,
+            raw_close TEXT,
+            median_price DOUBLE PRECISION,
+            deviation DOUBLE PRECISION,
+            reason TEXT,
+            action TEXT,
+            created_at TIMESTAMPTZ,
+            received_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Historical klines loaded by the backfill and import paths
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS klines (
+            symbol TEXT,
+            interval TEXT,
+            open_time TIMESTAMPTZ,
+            open_price DECIMAL,
+            high_price DECIMAL,
+            low_price DECIMAL,
+            close_price DECIMAL,
+            volume DECIMAL,
+            quote_volume DECIMAL,
+            PRIMARY KEY (symbol, interval, open_time)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        SELECT create_hypertable('klines', 'open_time',
+            if_not_exists => TRUE,
+            chunk_time_interval => INTERVAL '7 days'
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Optional archive of raw upstream messages, gzip compressed
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS ingest_log (
+            received_at TIMESTAMPTZ NOT NULL,
+            payload BYTEA
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        SELECT create_hypertable('ingest_log', 'received_at',
+            if_not_exists => TRUE,
+            chunk_time_interval => INTERVAL '1 hour'
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query("SELECT add_retention_policy('ingest_log', make_interval(hours => $1), if_not_exists => TRUE);")
+        .bind(env_or::<i32>("INGEST_ARCHIVE_RETENTION_HOURS", 72))
+        .execute(&pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS notification_channels (
+            account_id TEXT,
+            kind TEXT,
+            target TEXT,
+            PRIMARY KEY (account_id, kind, target)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS spreads (
+            account_id TEXT,
+            name TEXT,
+            long_symbol TEXT NOT NULL,
+            short_symbol TEXT NOT NULL,
+            ratio DOUBLE PRECISION NOT NULL,
+            PRIMARY KEY (account_id, name)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS grid_bots (
+            account_id TEXT,
+            symbol TEXT,
+            bot JSONB NOT NULL,
+            PRIMARY KEY (account_id, symbol)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS bots (
+            account_id TEXT,
+            symbol TEXT,
+            bot JSONB NOT NULL,
+            PRIMARY KEY (account_id, symbol)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Orders placed on an external venue, which only knows them by id and not by account
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS venue_orders (
+            venue TEXT,
+            order_id BIGINT,
+            account_id TEXT NOT NULL,
+            is_open BOOLEAN NOT NULL,
+            body JSONB NOT NULL,
+            PRIMARY KEY (venue, order_id)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Bots saved before pausing existed carry a running flag instead of a status
+    for table in ["grid_bots", "bots"] {
+        sqlx::query(&format!(
+            r#"
+            UPDATE {table} SET bot = (bot - 'running') || jsonb_build_object(
+                'status', CASE WHEN (bot->>'running')::boolean THEN 'running' ELSE 'stopped' END
+            )
+            WHERE bot ? 'running';
+            "#
+        ))
+        .execute(&pool)
+        .await?;
+    }
+
+    // Fills and alerts raised while the account was offline
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS notifications (
+            id BIGSERIAL PRIMARY KEY,
+            account_id TEXT NOT NULL,
+            kind TEXT,
+            title TEXT,
+            payload JSONB,
+            read BOOLEAN DEFAULT FALSE,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_notifications_account
+        ON notifications (account_id, created_at DESC);
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Isolated groups of users and accounts sharing the deployment
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS tenants (
+            tenant_id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            max_connections BIGINT,
+            max_storage_bytes BIGINT,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Bearer tokens are stored hashed like API key secrets, the raw token is only returned at creation
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS users (
+            user_id TEXT PRIMARY KEY,
+            token_hash TEXT UNIQUE NOT NULL,
+            role TEXT NOT NULL,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Users created while tokens were stored as they are keep them, hashed in place
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF EXISTS (
+                SELECT 1 FROM information_schema.columns WHERE table_name = 'users' AND column_name = 'token'
+            ) THEN
+                UPDATE users SET token = encode(sha256(convert_to(token, 'UTF8')), 'hex');
+                ALTER TABLE users RENAME COLUMN token TO token_hash;
+            END IF;
+        END $$;
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Users without a tenant share the accounts outside every tenant
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS tenant_id TEXT REFERENCES tenants (tenant_id);")
+        .execute(&pool)
+        .await?;
+
+    // Accounts belong to the tenant whose user used them first
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS account_tenants (
+            account_id TEXT PRIMARY KEY,
+            tenant_id TEXT NOT NULL REFERENCES tenants (tenant_id),
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Guest users trade on an account of the same id
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS guests (
+            user_id TEXT PRIMARY KEY REFERENCES users (user_id),
+            starting_balance DOUBLE PRECISION NOT NULL,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Append-only: updates and deletes are refused by a trigger
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_log (
+            id BIGSERIAL PRIMARY KEY,
+            actor TEXT NOT NULL,
+            key_id TEXT,
+            ip TEXT,
+            action TEXT NOT NULL,
+            account_id TEXT,
+            outcome TEXT NOT NULL,
+            details JSONB NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_audit_log_account
+        ON audit_log (account_id, created_at DESC);
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_audit_log_actor
+        ON audit_log (actor, created_at DESC);
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+        BEGIN
+            RAISE EXCEPTION 'audit_log is append-only';
+        END;
+        $$ LANGUAGE plpgsql;
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query("DROP TRIGGER IF EXISTS audit_log_append_only ON audit_log")
+        .execute(&pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER audit_log_append_only BEFORE UPDATE OR DELETE ON audit_log
+        FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_settings (
+            user_id TEXT NOT NULL REFERENCES users (user_id),
+            key TEXT NOT NULL,
+            value JSONB NOT NULL,
+            updated_at TIMESTAMPTZ DEFAULT NOW(),
+            PRIMARY KEY (user_id, key)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Stored items attributed to the user that created them, for storage quotas
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_storage (
+            kind TEXT NOT NULL,
+            item_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            bytes BIGINT NOT NULL,
+            created_at TIMESTAMPTZ DEFAULT NOW(),
+            PRIMARY KEY (kind, item_id)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_user_storage_user ON user_storage (user_id);")
+        .execute(&pool)
+        .await?;
+
+    // Exchange trades of followed symbols, kept briefly for time-and-sales scroll-back
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS market_trades (
+            symbol TEXT NOT NULL,
+            trade_id BIGINT NOT NULL,
+            price DOUBLE PRECISION NOT NULL,
+            quantity DOUBLE PRECISION NOT NULL,
+            side TEXT NOT NULL,
+            traded_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY (symbol, trade_id, traded_at)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        SELECT create_hypertable('market_trades', 'traded_at',
+            if_not_exists => TRUE,
+            chunk_time_interval => INTERVAL '1 hour'
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query("SELECT add_retention_policy('market_trades', make_interval(hours => $1), if_not_exists => TRUE);")
+        .bind(env_or::<i32>("TRADES_RETENTION_HOURS", 24))
+        .execute(&pool)
+        .await?;
+
+    // Taker buy and sell volume per minute, it outlives the raw trades and backs volume delta and CVD
+    sqlx::query(
+        r#"
+        CREATE MATERIALIZED VIEW IF NOT EXISTS volume_delta_1m
+        WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+        SELECT
+            time_bucket(INTERVAL '1 minute', traded_at) AS bucket,
+            symbol,
+            COALESCE(SUM(quantity) FILTER (WHERE side = 'buy'), 0) AS buy_volume,
+            COALESCE(SUM(quantity) FILTER (WHERE side = 'sell'), 0) AS sell_volume
+        FROM market_trades
+        GROUP BY bucket, symbol
+        WITH NO DATA;
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Refresh well inside the raw trade retention so dropped chunks never erase materialized minutes
+    sqlx::query(
+        r#"
+        SELECT add_continuous_aggregate_policy('volume_delta_1m',
+            start_offset => INTERVAL '2 hours',
+            end_offset => INTERVAL '1 minute',
+            schedule_interval => INTERVAL '1 minute',
+            if_not_exists => TRUE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Taker volume per minute and traded price, the footprint of every candle is summed from it
+    sqlx::query(
+        r#"
+        CREATE MATERIALIZED VIEW IF NOT EXISTS footprint_1m
+        WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+        SELECT
+            time_bucket(INTERVAL '1 minute', traded_at) AS bucket,
+            symbol,
+            price,
+            COALESCE(SUM(quantity) FILTER (WHERE side = 'buy'), 0) AS buy_volume,
+            COALESCE(SUM(quantity) FILTER (WHERE side = 'sell'), 0) AS sell_volume
+        FROM market_trades
+        GROUP BY bucket, symbol, price
+        WITH NO DATA;
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        SELECT add_continuous_aggregate_policy('footprint_1m',
+            start_offset => INTERVAL '2 hours',
+            end_offset => INTERVAL '1 minute',
+            schedule_interval => INTERVAL '1 minute',
+            if_not_exists => TRUE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        SELECT add_retention_policy('footprint_1m',
+            make_interval(days => $1),
+            if_not_exists => TRUE
+        );
+        "#,
+    )
+    .bind(env_or::<i32>("FOOTPRINT_RETENTION_DAYS", 30))
+    .execute(&pool)
+    .await?;
+
+    // Login sessions, revoking one invalidates its refresh tokens and every access token issued for it
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS auth_sessions (
+            session_id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL REFERENCES users (user_id),
+            key_id TEXT,
+            created_at TIMESTAMPTZ DEFAULT NOW(),
+            revoked_at TIMESTAMPTZ
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS refresh_tokens (
+            token_hash TEXT PRIMARY KEY,
+            session_id TEXT NOT NULL REFERENCES auth_sessions (session_id),
+            expires_at TIMESTAMPTZ NOT NULL,
+            used_at TIMESTAMPTZ
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Only a SHA-256 of each key secret is kept
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            key_id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL REFERENCES users (user_id),
+            secret_hash TEXT NOT NULL,
+            scopes TEXT[] NOT NULL,
+            account_id TEXT,
+            rate_limit INTEGER,
+            created_at TIMESTAMPTZ DEFAULT NOW(),
+            revoked_at TIMESTAMPTZ
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Periodic connection and subscription counts for capacity planning
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS usage_stats (
+            recorded_at TIMESTAMPTZ PRIMARY KEY,
+            connections BIGINT NOT NULL,
+            ticker_subscriptions BIGINT NOT NULL,
+            index_subscriptions BIGINT NOT NULL,
+            account_subscriptions BIGINT NOT NULL,
+            messages_in_per_sec DOUBLE PRECISION NOT NULL,
+            messages_out_per_sec DOUBLE PRECISION NOT NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS tenant_usage_stats (
+            tenant_id TEXT NOT NULL,
+            recorded_at TIMESTAMPTZ NOT NULL,
+            connections BIGINT NOT NULL,
+            storage_bytes BIGINT NOT NULL,
+            PRIMARY KEY (tenant_id, recorded_at)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // When each scheduled job last completed, so runs missed during downtime are caught up
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS job_runs (
+            job TEXT PRIMARY KEY,
+            last_run_at TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Queue of background jobs. Dead jobs exhausted their attempts and stay until retried by hand.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS jobs (
+            id BIGSERIAL PRIMARY KEY,
+            kind TEXT NOT NULL,
+            dedupe_key TEXT,
+            payload JSONB NOT NULL,
+            status TEXT NOT NULL DEFAULT 'queued',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            max_attempts INTEGER NOT NULL,
+            last_error TEXT,
+            run_after TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            started_at TIMESTAMPTZ,
+            finished_at TIMESTAMPTZ
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // One unfinished job per dedupe key. A dead job keeps its key until retried, so work that keeps
+    // failing waits for an admin instead of being queued again.
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS jobs_pending_dedupe ON jobs (dedupe_key) WHERE status IN ('queued', 'running', 'dead');",
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS jobs_queued ON jobs (run_after, id) WHERE status = 'queued';")
+        .execute(&pool)
+        .await?;
+
+    // Every account's cash and equity once a day
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS equity_snapshots (
+            account_id TEXT NOT NULL,
+            day DATE NOT NULL,
+            cash DOUBLE PRECISION NOT NULL,
+            equity DOUBLE PRECISION NOT NULL,
+            recorded_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY (account_id, day)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(pool)
+}
+
+// Size new chunks from the observed ingest rate so each holds about TICKER_TARGET_CHUNK_ROWS rows per
+// space partition, bounded by the compression and retention windows
+// Connect with a short acquire timeout so queries fail fast while the database is down, the pool
+// reconnects on its own once it is back. The first connection is retried with backoff.
+async fn connect(database_url: &str) -> Result<PgPool, sqlx::Error> {
+    let options = PgPoolOptions::new()
+        .max_connections(env_or("DB_MAX_CONNECTIONS", 10))
+        .acquire_timeout(Duration::from_secs(env_or("DB_ACQUIRE_TIMEOUT_SECS", 5)));
+    let attempts: u32 = env_or("DB_CONNECT_ATTEMPTS", 10);
+
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        match options.clone().connect(database_url).await {
+            Ok(pool) => return Ok(pool),
+            Err(e) if attempt < attempts && resilience::is_transient(&e) => {
+                eprintln!("Database connection attempt {} failed: {:?}, retrying in {:?}", attempt, e, delay);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(30));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub async fn tune_chunk_interval(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let target_rows: f64 = env_or("TICKER_TARGET_CHUNK_ROWS", 5_000_000.0);
+    let space_partitions: f64 = env_or("TICKER_SPACE_PARTITIONS", 4.0);
+
+    let rows_per_sec: Option<f64> = sqlx::query_scalar(
+        r#"
+        SELECT CAST(COUNT(*) AS DOUBLE PRECISION) / 600
+        FROM ticker_data
+        WHERE created_at >= NOW() - INTERVAL '10 minutes'
+        HAVING COUNT(*) > 0
+        "#,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(rows_per_sec) = rows_per_sec else {
+        return Ok(());
+    };
+    let interval_secs = (target_rows * space_partitions.max(1.0) / rows_per_sec).clamp(60.0, 3600.0);
+
+    sqlx::query("SELECT set_chunk_time_interval('ticker_data', make_interval(secs => $1));")
+        .bind(interval_secs)
+        .execute(pool)
+        .await?;
+    println!(
+        "Ingesting {:.0} ticks/s, ticker_data chunk interval set to {:.0}s",
+        rows_per_sec, interval_secs
+    );
+
+    Ok(())
+}
+
+pub async fn save_ticker_data(
+    pool: &PgPool,
+    ticker: &TickerData,
+    received_at: i64,
+    pricing: &UsdPricing,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO ticker_data 
+        (symbol, close_price, open_price, high_price, low_price, quote_volume, created_at, received_at, quote_asset, usd_price)
+        VALUES ($1, $2, $3, $4, $5, $6,
+            to_timestamp($7::double precision / 1000) AT TIME ZONE 'UTC',
+            to_timestamp($8::double precision / 1000) AT TIME ZONE 'UTC',
+            $9, $10)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(&ticker.s)
+    .bind(ticker.c.parse::<f64>().unwrap_or_default())
+    .bind(ticker.o.parse::<f64>().unwrap_or_default())
+    .bind(ticker.h.parse::<f64>().unwrap_or_default())
+    .bind(ticker.l.parse::<f64>().unwrap_or_default())
+    .bind(ticker.q.parse::<f64>().unwrap_or_default())
+    .bind(ticker.E)
+    .bind(received_at)
+    .bind(&pricing.quote_asset)
+    .bind(pricing.usd_price)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Price observed closest to `timestamp` within `tolerance_ms` either side, from the live ticks or,
+// for older times, the backfilled klines (priced at their open)
+pub async fn count_ticks(pool: &PgPool, symbol: &str, from: i64, to: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM ticker_data
+        WHERE symbol = $1
+            AND created_at >= to_timestamp($2::double precision / 1000)
+            AND created_at < to_timestamp($3::double precision / 1000)
+        "#,
+    )
+    .bind(symbol)
+    .bind(from)
+    .bind(to)
+    .fetch_one(pool)
+    .await
+}
+
+// Raw ticks as flat candles, volume is the quote volume traded since the previous tick
+pub async fn get_ticks(pool: &PgPool, symbol: &str, from: i64, to: i64) -> Result<Vec<Candle>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT
+            CAST(EXTRACT(EPOCH FROM created_at) * 1000 AS BIGINT) as open_time,
+            CAST(close_price AS DOUBLE PRECISION) as close_price,
+            CAST(GREATEST(quote_volume - LAG(quote_volume) OVER (ORDER BY created_at), 0) AS DOUBLE PRECISION) as volume
+        FROM ticker_data
+        WHERE symbol = $1
+            AND created_at >= to_timestamp($2::double precision / 1000)
+            AND created_at < to_timestamp($3::double precision / 1000)
+        ORDER BY created_at ASC
+        "#,
+    )
+    .bind(symbol)
+    .bind(from)
+    .bind(to)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        let price: f64 = row.try_get("close_price")?;
+        Ok(Candle {
+            open_time: row.try_get("open_time")?,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: row.try_get::<Option<f64>, _>("volume")?.unwrap_or_default(),
+            gap: false,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_bucketed_ticks(
+    pool: &PgPool,
+    symbol: &str,
+    from: i64,
+    to: i64,
+    bucket_secs: i64,
+) -> Result<Vec<Candle>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT
+            CAST(EXTRACT(EPOCH FROM bucket) * 1000 AS BIGINT) as open_time,
+            CAST(open_price AS DOUBLE PRECISION) as open_price,
+            CAST(high_price AS DOUBLE PRECISION) as high_price,
+            CAST(low_price AS DOUBLE PRECISION) as low_price,
+            CAST(close_price AS DOUBLE PRECISION) as close_price,
+            CAST(volume AS DOUBLE PRECISION) as volume
+        FROM (
+            SELECT
+                time_bucket(make_interval(secs => $4), created_at) AS bucket,
+                first(close_price, created_at) AS open_price,
+                max(close_price) AS high_price,
+                min(close_price) AS low_price,
+                last(close_price, created_at) AS close_price,
+                GREATEST(last(quote_volume, created_at) - first(quote_volume, created_at), 0) AS volume
+            FROM ticker_data
+            WHERE symbol = $1
+                AND created_at >= to_timestamp($2::double precision / 1000)
+                AND created_at < to_timestamp($3::double precision / 1000)
+            GROUP BY bucket
+        ) buckets
+        ORDER BY bucket ASC
+        "#,
+    )
+    .bind(symbol)
+    .bind(from)
+    .bind(to)
+    .bind(bucket_secs as f64)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(Candle {
+            open_time: row.try_get("open_time")?,
+            open: row.try_get("open_price")?,
+            high: row.try_get("high_price")?,
+            low: row.try_get("low_price")?,
+            close: row.try_get("close_price")?,
+            volume: row.try_get("volume")?,
+            gap: false,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_price_at(
+    pool: &PgPool,
+    symbol: &str,
+    timestamp: i64,
+    tolerance_ms: i64,
+) -> Result<Option<PricePoint>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH target AS (
+            SELECT to_timestamp($2::double precision / 1000) AS at,
+                make_interval(secs => $3::double precision / 1000) AS tolerance
+        ),
+        candidates AS (
+            (SELECT close_price AS price, created_at AS observed_at, 'ticker' AS source
+            FROM ticker_data, target
+            WHERE symbol = $1 AND created_at <= at AND created_at >= at - tolerance
+            ORDER BY created_at DESC LIMIT 1)
+            UNION ALL
+            (SELECT close_price, created_at, 'ticker'
+            FROM ticker_data, target
+            WHERE symbol = $1 AND created_at > at AND created_at <= at + tolerance
+            ORDER BY created_at ASC LIMIT 1)
+            UNION ALL
+            (SELECT open_price, open_time, 'kline'
+            FROM klines, target
+            WHERE symbol = $1 AND open_time <= at AND open_time >= at - tolerance
+            ORDER BY open_time DESC LIMIT 1)
+            UNION ALL
+            (SELECT open_price, open_time, 'kline'
+            FROM klines, target
+            WHERE symbol = $1 AND open_time > at AND open_time <= at + tolerance
+            ORDER BY open_time ASC LIMIT 1)
+        )
+        SELECT
+            CAST(price AS DOUBLE PRECISION) as price,
+            CAST(EXTRACT(EPOCH FROM observed_at) * 1000 AS BIGINT) as observed_at,
+            source
+        FROM candidates, target
+        ORDER BY ABS(EXTRACT(EPOCH FROM observed_at - at)) ASC, source DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(symbol)
+    .bind(timestamp)
+    .bind(tolerance_ms)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(PricePoint {
+            symbol: symbol.to_string(),
+            price: row.try_get("price")?,
+            timestamp: row.try_get("observed_at")?,
+            source: row.try_get("source")?,
+        })
+    })
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn save_ingest_log(pool: &PgPool, received_at: i64, payload: &[u8]) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO ingest_log (received_at, payload)
+        VALUES (to_timestamp($1::double precision / 1000), $2)
+        "#,
+    )
+    .bind(received_at)
+    .bind(payload)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_ingest_log(pool: &PgPool, from: i64, to: i64) -> Result<Vec<(i64, Vec<u8>)>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT CAST(EXTRACT(EPOCH FROM received_at) * 1000 AS BIGINT) as received_at, payload
+        FROM ingest_log
+        WHERE received_at >= to_timestamp($1::double precision / 1000)
+            AND received_at < to_timestamp($2::double precision / 1000)
+        ORDER BY received_at ASC
+        "#,
+    )
+    .bind(from)
+    .bind(to)
+    .try_map(|row: sqlx::postgres::PgRow| Ok((row.try_get("received_at")?, row.try_get("payload")?)))
+    .fetch_all(pool)
+    .await
+}
+
+// Trades arrive in batches, one the feed already delivered is skipped
+pub async fn save_market_trades(pool: &PgPool, trades: &[MarketTrade]) -> Result<(), sqlx::Error> {
+    let symbols: Vec<&str> = trades.iter().map(|trade| trade.symbol.as_str()).collect();
+    let trade_ids: Vec<i64> = trades.iter().map(|trade| trade.trade_id).collect();
+    let prices: Vec<f64> = trades.iter().map(|trade| trade.price).collect();
+    let quantities: Vec<f64> = trades.iter().map(|trade| trade.quantity).collect();
+    let sides: Vec<&str> = trades.iter().map(|trade| side_name(trade.side)).collect();
+    let times: Vec<i64> = trades.iter().map(|trade| trade.time).collect();
+    sqlx::query(
+        r#"
+        INSERT INTO market_trades (symbol, trade_id, price, quantity, side, traded_at)
+        SELECT symbol, trade_id, price, quantity, side, to_timestamp(time::double precision / 1000)
+        FROM UNNEST($1::text[], $2::bigint[], $3::float8[], $4::float8[], $5::text[], $6::bigint[])
+            AS t (symbol, trade_id, price, quantity, side, time)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(&symbols)
+    .bind(&trade_ids)
+    .bind(&prices)
+    .bind(&quantities)
+    .bind(&sides)
+    .bind(&times)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Up to `limit` trades of the symbol before the `before` trade id, or the latest ones, newest first
+pub async fn get_market_trades(
+    pool: &PgPool,
+    symbol: &str,
+    before: Option<i64>,
+    limit: i64,
+) -> Result<Vec<MarketTrade>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT symbol, trade_id, price, quantity, side,
+            CAST(EXTRACT(EPOCH FROM traded_at) * 1000 AS BIGINT) as time
+        FROM market_trades
+        WHERE symbol = $1 AND ($2::bigint IS NULL OR trade_id < $2)
+        ORDER BY trade_id DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(symbol)
+    .bind(before)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(MarketTrade {
+            symbol: row.try_get("symbol")?,
+            trade_id: row.try_get("trade_id")?,
+            price: row.try_get("price")?,
+            quantity: row.try_get("quantity")?,
+            side: if row.try_get::<String, _>("side")? == "buy" { Side::Buy } else { Side::Sell },
+            time: row.try_get("time")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// Newest buckets of the symbol first as (open time, taker buy volume, taker sell volume)
+pub async fn get_volume_delta(
+    pool: &PgPool,
+    symbol: &str,
+    bucket_secs: i64,
+    limit: i64,
+) -> Result<Vec<(i64, f64, f64)>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT CAST(EXTRACT(EPOCH FROM time_bucket(make_interval(secs => $2), bucket)) * 1000 AS BIGINT) AS open_time,
+            SUM(buy_volume) AS buy_volume,
+            SUM(sell_volume) AS sell_volume
+        FROM volume_delta_1m
+        WHERE symbol = $1
+        GROUP BY open_time
+        ORDER BY open_time DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(symbol)
+    .bind(bucket_secs as f64)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok((row.try_get("open_time")?, row.try_get("buy_volume")?, row.try_get("sell_volume")?))
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// The spread's last `limit` candles, newest first. Only minutes where both legs have a 1m kline
+// count, their closes give the spread's price path and their quote volumes add up.
+pub async fn get_spread_candles(
+    pool: &PgPool,
+    spread: &SpreadDefinition,
+    bucket_secs: i64,
+    limit: i64,
+) -> Result<Vec<Candle>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH minutes AS (
+            SELECT l.open_time,
+                CAST(l.close_price AS DOUBLE PRECISION) - $3 * CAST(s.close_price AS DOUBLE PRECISION) AS price,
+                CAST(COALESCE(l.quote_volume, 0) + COALESCE(s.quote_volume, 0) AS DOUBLE PRECISION) AS volume
+            FROM klines l
+            JOIN klines s ON s.symbol = $2 AND s.interval = '1m' AND s.open_time = l.open_time
+            WHERE l.symbol = $1 AND l.interval = '1m'
+                AND l.open_time >= NOW() - make_interval(secs => $4 * $5)
+        )
+        SELECT CAST(EXTRACT(EPOCH FROM bucket) * 1000 AS BIGINT) AS open_time,
+            first(price, open_time) AS open_price,
+            MAX(price) AS high_price,
+            MIN(price) AS low_price,
+            last(price, open_time) AS close_price,
+            SUM(volume) AS volume
+        FROM (SELECT *, time_bucket(make_interval(secs => $4), open_time) AS bucket FROM minutes) m
+        GROUP BY bucket
+        ORDER BY bucket DESC
+        LIMIT $5
+        "#,
+    )
+    .bind(&spread.long_symbol)
+    .bind(&spread.short_symbol)
+    .bind(spread.ratio)
+    .bind(bucket_secs as f64)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(Candle {
+            open_time: row.try_get("open_time")?,
+            open: row.try_get("open_price")?,
+            high: row.try_get("high_price")?,
+            low: row.try_get("low_price")?,
+            close: row.try_get("close_price")?,
+            volume: row.try_get("volume")?,
+            gap: false,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// Key levels of the symbol from its stored 1m klines, for the session starting at `session_start`
+// in the week starting at `week_start`
+pub async fn get_key_levels(
+    pool: &PgPool,
+    symbol: &str,
+    session_start: i64,
+    week_start: i64,
+) -> Result<KeyLevels, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        WITH bounds AS (
+            SELECT to_timestamp($2::double precision / 1000) AS session_start,
+                to_timestamp($2::double precision / 1000) - INTERVAL '1 day' AS previous_start,
+                to_timestamp($3::double precision / 1000) AS week_start
+        ),
+        recent AS (
+            SELECT k.open_time, k.open_price, k.high_price, k.low_price, k.close_price
+            FROM klines k, bounds b
+            WHERE k.symbol = $1 AND k.interval = '1m' AND k.open_time >= LEAST(b.previous_start, b.week_start)
+        )
+        SELECT
+            CAST(MAX(r.high_price) FILTER (WHERE r.open_time >= b.session_start) AS DOUBLE PRECISION)
+                AS session_high,
+            CAST(MIN(r.low_price) FILTER (WHERE r.open_time >= b.session_start) AS DOUBLE PRECISION)
+                AS session_low,
+            CAST(MAX(r.high_price) FILTER (
+                WHERE r.open_time >= b.previous_start AND r.open_time < b.session_start
+            ) AS DOUBLE PRECISION) AS previous_high,
+            CAST(MIN(r.low_price) FILTER (
+                WHERE r.open_time >= b.previous_start AND r.open_time < b.session_start
+            ) AS DOUBLE PRECISION) AS previous_low,
+            CAST(last(r.close_price, r.open_time) FILTER (
+                WHERE r.open_time >= b.previous_start AND r.open_time < b.session_start
+            ) AS DOUBLE PRECISION) AS previous_close,
+            CAST(first(r.open_price, r.open_time) FILTER (WHERE r.open_time >= b.week_start) AS DOUBLE PRECISION)
+                AS weekly_open
+        FROM recent r, bounds b
+        "#,
+    )
+    .bind(symbol)
+    .bind(session_start)
+    .bind(week_start)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(KeyLevels {
+        symbol: symbol.to_string(),
+        session_start,
+        session_high: row.try_get("session_high")?,
+        session_low: row.try_get("session_low")?,
+        previous_high: row.try_get("previous_high")?,
+        previous_low: row.try_get("previous_low")?,
+        previous_close: row.try_get("previous_close")?,
+        weekly_open: row.try_get("weekly_open")?,
+        last_price: None,
+    })
+}
+
+// Volume at price of the symbol's last `limit` candles as (open time, price level, bid volume, ask
+// volume), oldest candle first and highest level first within it. Levels are `row_size` wide, the
+// traded prices themselves without one.
+pub async fn get_footprint(
+    pool: &PgPool,
+    symbol: &str,
+    bucket_secs: i64,
+    row_size: Option<f64>,
+    limit: i64,
+) -> Result<Vec<(i64, f64, f64, f64)>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH candles AS (
+            SELECT time_bucket(make_interval(secs => $2), bucket) AS open_time
+            FROM footprint_1m
+            WHERE symbol = $1
+            GROUP BY open_time
+            ORDER BY open_time DESC
+            LIMIT $4
+        )
+        SELECT CAST(EXTRACT(EPOCH FROM time_bucket(make_interval(secs => $2), bucket)) * 1000 AS BIGINT) AS open_time,
+            CASE WHEN $3::float8 IS NULL THEN price ELSE floor(price / $3) * $3 END AS level,
+            SUM(sell_volume) AS bid_volume,
+            SUM(buy_volume) AS ask_volume
+        FROM footprint_1m
+        WHERE symbol = $1 AND bucket >= (SELECT MIN(open_time) FROM candles)
+        GROUP BY open_time, level
+        ORDER BY open_time ASC, level DESC
+        "#,
+    )
+    .bind(symbol)
+    .bind(bucket_secs as f64)
+    .bind(row_size)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok((
+            row.try_get("open_time")?,
+            row.try_get("level")?,
+            row.try_get("bid_volume")?,
+            row.try_get("ask_volume")?,
+        ))
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// Swap everything ingested during [from, to) for the reprocessed ticks in a single transaction
+pub async fn replace_ingested_window(
+    pool: &PgPool,
+    from: i64,
+    to: i64,
+    ticks: &[(TickerData, i64, UsdPricing)],
+    quarantined: &[(TickerData, TickAnomaly)],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM ticker_data
+        WHERE received_at >= to_timestamp($1::double precision / 1000)
+            AND received_at < to_timestamp($2::double precision / 1000)
+        "#,
+    )
+    .bind(from)
+    .bind(to)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM quarantined_ticks
+        WHERE received_at >= to_timestamp($1::double precision / 1000)
+            AND received_at < to_timestamp($2::double precision / 1000)
+        "#,
+    )
+    .bind(from)
+    .bind(to)
+    .execute(&mut *tx)
+    .await?;
+
+    let price = |value: &str| value.parse::<f64>().unwrap_or_default();
+    sqlx::query(
+        r#"
+        INSERT INTO ticker_data
+        (symbol, close_price, open_price, high_price, low_price, quote_volume, created_at, received_at, quote_asset, usd_price)
+        SELECT s, c, o, h, l, q, to_timestamp(e::double precision / 1000), to_timestamp(r::double precision / 1000), qa, u
+        FROM unnest($1::text[], $2::float8[], $3::float8[], $4::float8[], $5::float8[], $6::float8[], $7::bigint[], $8::bigint[],
+            $9::text[], $10::float8[])
+            AS t(s, c, o, h, l, q, e, r, qa, u)
+        "#,
+    )
+    .bind(ticks.iter().map(|(t, _, _)| t.s.clone()).collect::<Vec<_>>())
+    .bind(ticks.iter().map(|(t, _, _)| price(&t.c)).collect::<Vec<_>>())
+    .bind(ticks.iter().map(|(t, _, _)| price(&t.o)).collect::<Vec<_>>())
+    .bind(ticks.iter().map(|(t, _, _)| price(&t.h)).collect::<Vec<_>>())
+    .bind(ticks.iter().map(|(t, _, _)| price(&t.l)).collect::<Vec<_>>())
+    .bind(ticks.iter().map(|(t, _, _)| price(&t.q)).collect::<Vec<_>>())
+    .bind(ticks.iter().map(|(t, _, _)| t.E).collect::<Vec<_>>())
+    .bind(ticks.iter().map(|(_, received_at, _)| *received_at).collect::<Vec<_>>())
+    .bind(ticks.iter().map(|(_, _, pricing)| pricing.quote_asset.clone()).collect::<Vec<_>>())
+    .bind(ticks.iter().map(|(_, _, pricing)| pricing.usd_price).collect::<Vec<_>>())
+    .execute(&mut *tx)
+    .await?;
+
+    // Quarantined ticks are stamped with the window start, so the next run over it replaces them too
+    for (ticker, anomaly) in quarantined {
+        sqlx::query(
+            r#"
+            INSERT INTO quarantined_ticks (symbol, raw_close, median_price, deviation, reason, action, created_at, received_at)
+            VALUES ($1, $2, $3, $4, $5, $6, to_timestamp($7::double precision / 1000), to_timestamp($8::double precision / 1000))
+            "#,
+        )
+        .bind(&ticker.s)
+        .bind(&ticker.c)
+        .bind(anomaly.median)
+        .bind(anomaly.deviation)
+        .bind(&anomaly.reason)
+        .bind(anomaly.action.name())
+        .bind(ticker.E)
+        .bind(from)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+pub async fn save_quarantined_tick(
+    pool: &PgPool,
+    ticker: &TickerData,
+    anomaly: &TickAnomaly,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO quarantined_ticks (symbol, raw_close, median_price, deviation, reason, action, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, to_timestamp($7::double precision / 1000) AT TIME ZONE 'UTC')
+        "#,
+    )
+    .bind(&ticker.s)
+    .bind(&ticker.c)
+    .bind(anomaly.median)
+    .bind(anomaly.deviation)
+    .bind(&anomaly.reason)
+    .bind(anomaly.action.name())
+    .bind(ticker.E)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_latest_tickers(
+    pool: &PgPool,
+    page: i64,
+    per_page: i64,
+    query: &TickerQuery,
+) -> Result<PaginatedResponse, sqlx::Error> {
+    let total: i64 = sqlx::query_scalar(
+        "SELECT COUNT(DISTINCT symbol) FROM ticker_data WHERE $1::text IS NULL OR quote_asset = $1"
+    )
+    .bind(&query.quote)
+    .fetch_one(pool)
+    .await?;
+
+    let offset = (page - 1) * per_page;
+    
+    // Query the latest price for each symbol and sort by rolling 24h traded volume with pagination.
+    // The miniTicker `q` resets at day boundaries, so traded volume is summed from per-minute
+    // increments of it, a drop marks a reset and the new value is all volume since then.
+    // USD volume converts it at the rate between the latest native and USD prices.
+    let order = match query.sort {
+        TickerSort::Volume => "quote_volume DESC",
+        TickerSort::UsdVolume => "usd_volume DESC NULLS LAST",
+        TickerSort::UsdPrice => "usd_price DESC NULLS LAST",
+    };
+    let sql = format!(
+        r#"
+        WITH LatestData AS (
+            SELECT DISTINCT ON (symbol) 
+                symbol,
+                close_price,
+                quote_asset,
+                usd_price,
+                created_at
+            FROM ticker_data
+            ORDER BY symbol ASC, created_at DESC
+        ),
+        MinuteVolume AS (
+            SELECT
+                symbol,
+                bucket,
+                last_quote_volume,
+                LAG(last_quote_volume) OVER (PARTITION BY symbol ORDER BY bucket) AS previous_quote_volume
+            FROM volume_1m
+            WHERE bucket >= NOW() - INTERVAL '24 hours 1 minute'
+        ),
+        RollingVolume AS (
+            SELECT
+                symbol,
+                SUM(CASE
+                    WHEN previous_quote_volume IS NULL THEN 0
+                    WHEN last_quote_volume >= previous_quote_volume THEN last_quote_volume - previous_quote_volume
+                    ELSE last_quote_volume
+                END) FILTER (WHERE bucket >= NOW() - INTERVAL '24 hours') AS volume_24h
+            FROM MinuteVolume
+            GROUP BY symbol
+        ),
+        SortedData AS (
+            SELECT 
+                l.symbol,
+                CAST(l.close_price AS DOUBLE PRECISION) as close_price,
+                CAST(COALESCE(r.volume_24h, 0) AS DOUBLE PRECISION) as quote_volume,
+                l.quote_asset,
+                l.usd_price,
+                CAST(COALESCE(r.volume_24h, 0) AS DOUBLE PRECISION) * l.usd_price
+                    / NULLIF(CAST(l.close_price AS DOUBLE PRECISION), 0) as usd_volume
+            FROM LatestData l
+            LEFT JOIN RollingVolume r ON r.symbol = l.symbol
+            WHERE $3::text IS NULL OR l.quote_asset = $3
+            ORDER BY {}
+            LIMIT $1
+            OFFSET $2
+        )
+        SELECT * FROM SortedData
+        "#,
+        order
+    );
+    let volume_data = sqlx::query(&sql)
+    .bind(per_page)
+    .bind(offset)
+    .bind(&query.quote)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(VolumeData {
+            symbol: row.try_get("symbol")?,
+            price: row.try_get("close_price")?,
+            volume: row.try_get("quote_volume")?,
+            quote_asset: row.try_get("quote_asset")?,
+            usd_price: row.try_get("usd_price")?,
+            usd_volume: row.try_get("usd_volume")?,
+        })
+    })
+    .fetch_all(pool)
+    .await?;
+
+    Ok(PaginatedResponse {
+        data: volume_data,
+        total,
+        page,
+        per_page,
+        degraded: false,
+    })
+}
+
+// Bucket the ticker stream into candles for all requested series at once, keyed by (symbol, interval)
+pub async fn get_candles_batch(
+    pool: &PgPool,
+    series: &[CandleSeriesRequest],
+    interval_seconds: &[i64],
+    limit: i64,
+    timeline: Timeline,
+) -> Result<HashMap<(String, String), Vec<Candle>>, sqlx::Error> {
+    let symbols: Vec<&str> = series.iter().map(|s| s.symbol.as_str()).collect();
+    let intervals: Vec<&str> = series.iter().map(|s| s.interval.as_str()).collect();
+    let time_column = match timeline {
+        Timeline::Event => "created_at",
+        Timeline::Ingest => "received_at",
+    };
+
+    // The miniTicker open/high/low are rolling 24h values, so candles are built from the close price stream
+    let query = format!(
+        r#"
+        WITH series AS (
+            SELECT * FROM unnest($1::text[], $2::text[], $3::bigint[])
+                AS s(symbol, interval_name, interval_secs)
+        ),
+        buckets AS (
+            SELECT
+                s.symbol,
+                s.interval_name,
+                time_bucket(make_interval(secs => s.interval_secs), t.{time}) AS bucket,
+                first(t.close_price, t.{time}) AS open_price,
+                max(t.close_price) AS high_price,
+                min(t.close_price) AS low_price,
+                last(t.close_price, t.{time}) AS close_price,
+                GREATEST(last(t.quote_volume, t.{time}) - first(t.quote_volume, t.{time}), 0) AS volume
+            FROM series s
+            JOIN ticker_data t ON t.symbol = s.symbol
+                AND t.{time} >= NOW() - make_interval(secs => s.interval_secs * $4)
+            GROUP BY s.symbol, s.interval_name, s.interval_secs, bucket
+        )
+        SELECT
+            symbol,
+            interval_name,
+            CAST(EXTRACT(EPOCH FROM bucket) * 1000 AS BIGINT) as open_time,
+            CAST(open_price AS DOUBLE PRECISION) as open_price,
+            CAST(high_price AS DOUBLE PRECISION) as high_price,
+            CAST(low_price AS DOUBLE PRECISION) as low_price,
+            CAST(close_price AS DOUBLE PRECISION) as close_price,
+            CAST(volume AS DOUBLE PRECISION) as volume
+        FROM buckets
+        ORDER BY symbol ASC, interval_name ASC, bucket ASC
+        "#,
+        time = time_column,
+    );
+    let rows = sqlx::query(&query)
+        .bind(&symbols)
+        .bind(&intervals)
+        .bind(interval_seconds)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    let mut candles: HashMap<(String, String), Vec<Candle>> = HashMap::new();
+    for row in rows {
+        candles
+            .entry((row.try_get("symbol")?, row.try_get("interval_name")?))
+            .or_default()
+            .push(Candle {
+                open_time: row.try_get("open_time")?,
+                open: row.try_get("open_price")?,
+                high: row.try_get("high_price")?,
+                low: row.try_get("low_price")?,
+                close: row.try_get("close_price")?,
+                volume: row.try_get("volume")?,
+                gap: false,
+            });
+    }
+
+    Ok(candles)
+}
+
+pub async fn upsert_mirror_balance(
+    pool: &PgPool,
+    account_id: &str,
+    balance: &MirrorBalance,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO mirror_balances (account_id, asset, wallet_balance, available_balance, updated_at)
+        VALUES ($1, $2, $3, $4, NOW())
+        ON CONFLICT (account_id, asset) DO UPDATE SET
+            wallet_balance = EXCLUDED.wallet_balance,
+            available_balance = EXCLUDED.available_balance,
+            updated_at = NOW()
+        "#,
+    )
+    .bind(account_id)
+    .bind(&balance.asset)
+    .bind(balance.wallet_balance)
+    .bind(balance.available_balance)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn upsert_mirror_position(
+    pool: &PgPool,
+    account_id: &str,
+    position: &MirrorPosition,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO mirror_positions
+        (account_id, symbol, position_side, position_amount, entry_price, unrealized_pnl, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, NOW())
+        ON CONFLICT (account_id, symbol, position_side) DO UPDATE SET
+            position_amount = EXCLUDED.position_amount,
+            entry_price = EXCLUDED.entry_price,
+            unrealized_pnl = EXCLUDED.unrealized_pnl,
+            updated_at = NOW()
+        "#,
+    )
+    .bind(account_id)
+    .bind(&position.symbol)
+    .bind(&position.position_side)
+    .bind(position.position_amount)
+    .bind(position.entry_price)
+    .bind(position.unrealized_pnl)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_mirror_fill(
+    pool: &PgPool,
+    account_id: &str,
+    fill: &MirrorFill,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO mirror_fills
+        (account_id, trade_id, order_id, symbol, side, price, quantity, commission, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, to_timestamp($9::double precision / 1000) AT TIME ZONE 'UTC')
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(account_id)
+    .bind(fill.trade_id)
+    .bind(fill.order_id)
+    .bind(&fill.symbol)
+    .bind(&fill.side)
+    .bind(fill.price)
+    .bind(fill.quantity)
+    .bind(fill.commission)
+    .bind(fill.created_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_mirror_account(pool: &PgPool, account_id: &str) -> Result<MirrorAccount, sqlx::Error> {
+    let balances = sqlx::query(
+        r#"
+        SELECT asset,
+            CAST(wallet_balance AS DOUBLE PRECISION) as wallet_balance,
+            CAST(available_balance AS DOUBLE PRECISION) as available_balance
+        FROM mirror_balances
+        WHERE account_id = $1 AND wallet_balance <> 0
+        ORDER BY asset
+        "#,
+    )
+    .bind(account_id)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(MirrorBalance {
+            asset: row.try_get("asset")?,
+            wallet_balance: row.try_get("wallet_balance")?,
+            available_balance: row.try_get("available_balance")?,
+        })
+    })
+    .fetch_all(pool)
+    .await?;
+
+    let positions = sqlx::query(
+        r#"
+        SELECT symbol, position_side,
+            CAST(position_amount AS DOUBLE PRECISION) as position_amount,
+            CAST(entry_price AS DOUBLE PRECISION) as entry_price,
+            CAST(unrealized_pnl AS DOUBLE PRECISION) as unrealized_pnl
+        FROM mirror_positions
+        WHERE account_id = $1 AND position_amount <> 0
+        ORDER BY symbol
+        "#,
+    )
+    .bind(account_id)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(MirrorPosition {
+            symbol: row.try_get("symbol")?,
+            position_side: row.try_get("position_side")?,
+            position_amount: row.try_get("position_amount")?,
+            entry_price: row.try_get("entry_price")?,
+            unrealized_pnl: row.try_get("unrealized_pnl")?,
+        })
+    })
+    .fetch_all(pool)
+    .await?;
+
+    let recent_fills = sqlx::query(
+        r#"
+        SELECT trade_id, order_id, symbol, side,
+            CAST(price AS DOUBLE PRECISION) as price,
+            CAST(quantity AS DOUBLE PRECISION) as quantity,
+            CAST(commission AS DOUBLE PRECISION) as commission,
+            CAST(EXTRACT(EPOCH FROM created_at) * 1000 AS BIGINT) as created_at
+        FROM mirror_fills
+        WHERE account_id = $1
+        ORDER BY created_at DESC
+        LIMIT 100
+        "#,
+    )
+    .bind(account_id)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(MirrorFill {
+            trade_id: row.try_get("trade_id")?,
+            order_id: row.try_get("order_id")?,
+            symbol: row.try_get("symbol")?,
+            side: row.try_get("side")?,
+            price: row.try_get("price")?,
+            quantity: row.try_get("quantity")?,
+            commission: row.try_get("commission")?,
+            created_at: row.try_get("created_at")?,
+        })
+    })
+    .fetch_all(pool)
+    .await?;
+
+    Ok(MirrorAccount {
+        account_id: account_id.to_string(),
+        balances,
+        positions,
+        recent_fills,
+    })
+}
+
+// Store the latest price of every symbol as today's close, later runs on the same day overwrite it
+pub async fn record_daily_closes(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO daily_closes (symbol, day, close_price)
+        SELECT DISTINCT ON (symbol) symbol, CURRENT_DATE, close_price
+        FROM ticker_data
+        ORDER BY symbol ASC, created_at DESC
+        ON CONFLICT (symbol, day) DO UPDATE SET close_price = EXCLUDED.close_price
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_daily_returns(
+    pool: &PgPool,
+    symbols: &[String],
+    days: i64,
+) -> Result<ReturnSeries, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT symbol, CAST(day AS TEXT) as day, CAST(close_price AS DOUBLE PRECISION) as close_price
+        FROM daily_closes
+        WHERE symbol = ANY($1) AND day >= CURRENT_DATE - CAST($2 AS INTEGER)
+        ORDER BY symbol ASC, day ASC
+        "#,
+    )
+    .bind(symbols)
+    .bind(days)
+    .fetch_all(pool)
+    .await?;
+
+    let mut closes: std::collections::HashMap<String, Vec<(String, f64)>> = Default::default();
+    for row in rows {
+        let symbol: String = row.try_get("symbol")?;
+        closes
+            .entry(symbol)
+            .or_default()
+            .push((row.try_get("day")?, row.try_get("close_price")?));
+    }
+
+    Ok(closes
+        .into_iter()
+        .map(|(symbol, series)| (symbol, risk::daily_returns(&series)))
+        .collect())
+}
+
+// Stored daily closes per symbol as (day start in epoch milliseconds, close), oldest first
+pub async fn get_daily_closes(
+    pool: &PgPool,
+    symbols: &[String],
+    days: i64,
+) -> Result<HashMap<String, Vec<(i64, f64)>>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT symbol, CAST(EXTRACT(EPOCH FROM day) * 1000 AS BIGINT) as day,
+            CAST(close_price AS DOUBLE PRECISION) as close_price
+        FROM daily_closes
+        WHERE symbol = ANY($1) AND day >= CURRENT_DATE - CAST($2 AS INTEGER)
+        ORDER BY symbol ASC, day ASC
+        "#,
+    )
+    .bind(symbols)
+    .bind(days)
+    .fetch_all(pool)
+    .await?;
+
+    let mut closes: HashMap<String, Vec<(i64, f64)>> = HashMap::new();
+    for row in rows {
+        let symbol: String = row.try_get("symbol")?;
+        closes
+            .entry(symbol)
+            .or_default()
+            .push((row.try_get("day")?, row.try_get("close_price")?));
+    }
+    Ok(closes)
+}
+
+pub async fn save_portfolio_risk(
+    pool: &PgPool,
+    account_id: &str,
+    risk: &RiskMetrics,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO portfolio_risk
+        (account_id, confidence, value_at_risk, expected_shortfall, scenarios, computed_at)
+        VALUES ($1, $2, $3, $4, $5, to_timestamp($6::double precision / 1000) AT TIME ZONE 'UTC')
+        "#,
+    )
+    .bind(account_id)
+    .bind(risk.confidence)
+    .bind(risk.value_at_risk)
+    .bind(risk.expected_shortfall)
+    .bind(risk.scenarios as i32)
+    .bind(risk.computed_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_latest_portfolio_risk(
+    pool: &PgPool,
+    account_id: &str,
+) -> Result<Option<RiskMetrics>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT confidence, value_at_risk, expected_shortfall, scenarios,
+            CAST(EXTRACT(EPOCH FROM computed_at) * 1000 AS BIGINT) as computed_at
+        FROM portfolio_risk
+        WHERE account_id = $1
+        ORDER BY computed_at DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(account_id)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(RiskMetrics {
+            confidence: row.try_get("confidence")?,
+            value_at_risk: row.try_get("value_at_risk")?,
+            expected_shortfall: row.try_get("expected_shortfall")?,
+            scenarios: row.try_get::<i32, _>("scenarios")? as usize,
+            computed_at: row.try_get("computed_at")?,
+        })
+    })
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn save_drawdown_alert(
+    pool: &PgPool,
+    settings: &DrawdownAlertSettings,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO drawdown_alerts (account_id, threshold, webhook_url)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (account_id) DO UPDATE SET
+            threshold = EXCLUDED.threshold,
+            webhook_url = EXCLUDED.webhook_url
+        "#,
+    )
+    .bind(&settings.account_id)
+    .bind(settings.threshold)
+    .bind(&settings.webhook_url)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_drawdown_alerts(pool: &PgPool) -> Result<Vec<DrawdownAlertSettings>, sqlx::Error> {
+    sqlx::query("SELECT account_id, threshold, webhook_url FROM drawdown_alerts")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            Ok(DrawdownAlertSettings {
+                account_id: row.try_get("account_id")?,
+                threshold: row.try_get("threshold")?,
+                webhook_url: row.try_get("webhook_url")?,
+            })
+        })
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn save_depeg_alert(pool: &PgPool, settings: &DepegAlertSettings) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO depeg_alerts (account_id, threshold, webhook_url)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (account_id) DO UPDATE SET
+            threshold = EXCLUDED.threshold,
+            webhook_url = EXCLUDED.webhook_url
+        "#,
+    )
+    .bind(&settings.account_id)
+    .bind(settings.threshold)
+    .bind(&settings.webhook_url)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_depeg_alerts(pool: &PgPool) -> Result<Vec<DepegAlertSettings>, sqlx::Error> {
+    sqlx::query("SELECT account_id, threshold, webhook_url FROM depeg_alerts")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            Ok(DepegAlertSettings {
+                account_id: row.try_get("account_id")?,
+                threshold: row.try_get("threshold")?,
+                webhook_url: row.try_get("webhook_url")?,
+            })
+        })
+        .fetch_all(pool)
+        .await
+}
+
+// Whether each known symbol was trading when last checked
+pub async fn load_symbol_listings(pool: &PgPool) -> Result<HashMap<String, bool>, sqlx::Error> {
+    sqlx::query("SELECT symbol, trading FROM symbol_listings")
+        .try_map(|row: sqlx::postgres::PgRow| Ok((row.try_get("symbol")?, row.try_get("trading")?)))
+        .fetch_all(pool)
+        .await
+        .map(|rows| rows.into_iter().collect())
+}
+
+pub async fn save_symbol_listing(pool: &PgPool, symbol: &str, status: &str, trading: bool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO symbol_listings (symbol, status, trading)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (symbol) DO UPDATE SET
+            status = EXCLUDED.status,
+            trading = EXCLUDED.trading,
+            changed_at = NOW()
+        "#,
+    )
+    .bind(symbol)
+    .bind(status)
+    .bind(trading)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_feature_flags(pool: &PgPool) -> Result<Vec<FeatureFlagSetting>, sqlx::Error> {
+    sqlx::query("SELECT flag, user_id, enabled FROM feature_flags")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            let user_id: String = row.try_get("user_id")?;
+            Ok(FeatureFlagSetting {
+                flag: row.try_get("flag")?,
+                user_id: Some(user_id).filter(|user_id| !user_id.is_empty()),
+                enabled: row.try_get("enabled")?,
+            })
+        })
+        .fetch_all(pool)
+        .await
+}
+
+// A None `enabled` deletes the setting
+pub async fn save_feature_flag(
+    pool: &PgPool,
+    flag: &str,
+    user_id: Option<&str>,
+    enabled: Option<bool>,
+) -> Result<(), sqlx::Error> {
+    let user_id = user_id.unwrap_or("");
+    match enabled {
+        Some(enabled) => {
+            sqlx::query(
+                r#"
+                INSERT INTO feature_flags (flag, user_id, enabled)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (flag, user_id) DO UPDATE SET
+                    enabled = EXCLUDED.enabled,
+                    updated_at = NOW()
+                "#,
+            )
+            .bind(flag)
+            .bind(user_id)
+            .bind(enabled)
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM feature_flags WHERE flag = $1 AND user_id = $2")
+                .bind(flag)
+                .bind(user_id)
+                .execute(pool)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn fill_insert(fill: &Fill) -> sqlx::query::Query<'_, sqlx::Postgres, sqlx::postgres::PgArguments> {
+    sqlx::query(
+        r#"
+        INSERT INTO fills (order_id, account_id, symbol, side, price, quantity, liquidity, created_at, fill_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, to_timestamp($8::double precision / 1000) AT TIME ZONE 'UTC', $9)
+        ON CONFLICT (fill_id) DO NOTHING
+        "#,
+    )
+    .bind(fill.order_id as i64)
+    .bind(&fill.account_id)
+    .bind(&fill.symbol)
+    .bind(side_name(fill.side))
+    .bind(fill.price)
+    .bind(fill.quantity)
+    .bind(liquidity_name(fill.liquidity))
+    .bind(fill.created_at)
+    .bind((fill.fill_id != 0).then_some(fill.fill_id as i64))
+}
+
+// Stores a batch of engine changes with the fills that caused them inside the caller's transaction,
+// so a restart never finds an order filled without its fill or a fill without its order
+pub async fn save_order_changes(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    changes: &OrderChanges,
+    fills: &[Fill],
+) -> Result<(), sqlx::Error> {
+    let now = now_millis();
+
+    for account_id in &changes.closed_accounts {
+        sqlx::query("DELETE FROM orders WHERE account_id = $1")
+            .bind(account_id)
+            .execute(&mut **tx)
+            .await?;
+        sqlx::query("DELETE FROM order_groups WHERE account_id = $1")
+            .bind(account_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    for order in &changes.orders {
+        let status = serde_json::to_value(order.status).unwrap_or_default();
+        let stored = sqlx::query(
+            r#"
+            INSERT INTO orders (order_id, account_id, status, body, revision, updated_at)
+            VALUES ($1, $2, $3, $4, $5, to_timestamp($6::double precision / 1000))
+            ON CONFLICT (order_id) DO UPDATE SET status = EXCLUDED.status, body = EXCLUDED.body,
+                revision = EXCLUDED.revision, updated_at = EXCLUDED.updated_at
+            WHERE orders.revision < EXCLUDED.revision
+            "#,
+        )
+        .bind(order.id as i64)
+        .bind(&order.account_id)
+        .bind(status.as_str().unwrap_or_default())
+        .bind(serde_json::to_value(order).unwrap_or_default())
+        .bind(changes.revision as i64)
+        .bind(now)
+        .execute(&mut **tx)
+        .await?;
+        // A newer batch already stored this order
+        if stored.rows_affected() == 0 {
+            continue;
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO order_transitions (order_id, revision, status, filled_quantity, version, recorded_at)
+            VALUES ($1, $2, $3, $4, $5, to_timestamp($6::double precision / 1000))
+            "#,
+        )
+        .bind(order.id as i64)
+        .bind(changes.revision as i64)
+        .bind(status.as_str().unwrap_or_default())
+        .bind(order.filled_quantity)
+        .bind(order.version as i64)
+        .bind(now)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    for group in &changes.groups {
+        let take_profit_order_ids: Vec<i64> = group.take_profit_order_ids.iter().map(|id| *id as i64).collect();
+        sqlx::query(
+            r#"
+            INSERT INTO order_groups
+                (group_id, account_id, symbol, entry_order_id, take_profit_order_ids, cancelled, revision)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (group_id) DO UPDATE SET cancelled = EXCLUDED.cancelled, revision = EXCLUDED.revision
+            WHERE order_groups.revision < EXCLUDED.revision
+            "#,
+        )
+        .bind(group.id as i64)
+        .bind(&group.account_id)
+        .bind(&group.symbol)
+        .bind(group.entry_order_id as i64)
+        .bind(take_profit_order_ids)
+        .bind(group.cancelled)
+        .bind(changes.revision as i64)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    for fill in fills {
+        fill_insert(fill).execute(&mut **tx).await?;
+    }
+
+    Ok(())
+}
+
+// One event at the next sequence of its account. The key rejects a second writer racing for it.
+pub async fn append_account_event(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    account_id: &str,
+    sequence: u64,
+    event: &AccountEvent,
+    recorded_at: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO account_events (account_id, sequence, event, recorded_at)
+        VALUES ($1, $2, $3, to_timestamp($4::double precision / 1000))
+        "#,
+    )
+    .bind(account_id)
+    .bind(sequence as i64)
+    .bind(serde_json::to_value(event).unwrap_or_default())
+    .bind(recorded_at)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_account_events(
+    pool: &PgPool,
+    account_id: &str,
+    after_sequence: u64,
+    limit: Option<i64>,
+) -> Result<Vec<RecordedEvent>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT account_id, sequence, event, CAST(EXTRACT(EPOCH FROM recorded_at) * 1000 AS BIGINT) as recorded_at
+        FROM account_events
+        WHERE account_id = $1 AND sequence > $2
+        ORDER BY sequence
+        LIMIT $3
+        "#,
+    )
+    .bind(account_id)
+    .bind(after_sequence as i64)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        let event: serde_json::Value = row.try_get("event")?;
+        Ok(RecordedEvent {
+            account_id: row.try_get("account_id")?,
+            sequence: row.try_get::<i64, _>("sequence")? as u64,
+            recorded_at: row.try_get("recorded_at")?,
+            event: serde_json::from_value(event).map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// Last sequence written for every account with events
+pub async fn get_account_sequences(pool: &PgPool) -> Result<Vec<(String, u64)>, sqlx::Error> {
+    let rows: Vec<(String, i64)> =
+        sqlx::query_as("SELECT account_id, MAX(sequence) FROM account_events GROUP BY account_id")
+            .fetch_all(pool)
+            .await?;
+    Ok(rows.into_iter().map(|(account_id, sequence)| (account_id, sequence as u64)).collect())
+}
+
+// Accounts with events newer than their latest snapshot
+pub async fn get_unsnapshotted_accounts(pool: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT e.account_id
+        FROM account_events e
+        LEFT JOIN account_snapshots s ON s.account_id = e.account_id
+        GROUP BY e.account_id
+        HAVING MAX(e.sequence) > COALESCE(MAX(s.sequence), 0)
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// The account's state after event `sequence`, None once it was closed
+pub async fn save_account_snapshot(
+    pool: &PgPool,
+    account_id: &str,
+    sequence: u64,
+    portfolio: Option<&Portfolio>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO account_snapshots (account_id, sequence, portfolio, recorded_at)
+        VALUES ($1, $2, $3, NOW())
+        ON CONFLICT (account_id) DO UPDATE SET sequence = EXCLUDED.sequence, portfolio = EXCLUDED.portfolio,
+            recorded_at = EXCLUDED.recorded_at
+        WHERE account_snapshots.sequence < EXCLUDED.sequence
+        "#,
+    )
+    .bind(account_id)
+    .bind(sequence as i64)
+    .bind(serde_json::to_value(portfolio).unwrap_or_default())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn load_account_snapshot(
+    pool: &PgPool,
+    account_id: &str,
+) -> Result<Option<(u64, Option<Portfolio>)>, sqlx::Error> {
+    let row: Option<(i64, serde_json::Value)> =
+        sqlx::query_as("SELECT sequence, portfolio FROM account_snapshots WHERE account_id = $1")
+            .bind(account_id)
+            .fetch_optional(pool)
+            .await?;
+    row.map(|(sequence, portfolio)| {
+        let portfolio = serde_json::from_value(portfolio).map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+        Ok((sequence as u64, portfolio))
+    })
+    .transpose()
+}
+
+// Every stored order and group, with the highest revision and fill id saved so the engine continues
+// after them
+pub async fn load_orders(pool: &PgPool) -> Result<StoredOrders, sqlx::Error> {
+    let orders = sqlx::query("SELECT body FROM orders ORDER BY order_id")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            let body: serde_json::Value = row.try_get("body")?;
+            serde_json::from_value::<Order>(body).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+        })
+        .fetch_all(pool)
+        .await?;
+
+    let groups = sqlx::query(
+        "SELECT group_id, account_id, symbol, entry_order_id, take_profit_order_ids, cancelled FROM order_groups",
+    )
+    .try_map(|row: sqlx::postgres::PgRow| {
+        let take_profit_order_ids: Vec<i64> = row.try_get("take_profit_order_ids")?;
+        Ok(OrderGroup {
+            id: row.try_get::<i64, _>("group_id")? as u64,
+            account_id: row.try_get("account_id")?,
+            symbol: row.try_get("symbol")?,
+            entry_order_id: row.try_get::<i64, _>("entry_order_id")? as u64,
+            take_profit_order_ids: take_profit_order_ids.into_iter().map(|id| id as u64).collect(),
+            cancelled: row.try_get("cancelled")?,
+        })
+    })
+    .fetch_all(pool)
+    .await?;
+
+    let (revision, last_fill_id): (i64, i64) = sqlx::query_as(
+        r#"
+        SELECT GREATEST((SELECT MAX(revision) FROM orders), (SELECT MAX(revision) FROM order_groups), 0),
+            GREATEST((SELECT MAX(fill_id) FROM fills), (SELECT MAX(fill_id) FROM archived_fills), 0)
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(StoredOrders {
+        orders,
+        groups,
+        revision: revision as u64,
+        last_fill_id: last_fill_id as u64,
+    })
+}
+
+fn fill_from_row(row: &sqlx::postgres::PgRow) -> Result<Fill, sqlx::Error> {
+    Ok(Fill {
+        fill_id: row.try_get::<Option<i64>, _>("fill_id")?.unwrap_or_default() as u64,
+        order_id: row.try_get::<i64, _>("order_id")? as u64,
+        account_id: row.try_get("account_id")?,
+        symbol: row.try_get("symbol")?,
+        side: if row.try_get::<String, _>("side")? == "buy" { Side::Buy } else { Side::Sell },
+        price: row.try_get("price")?,
+        quantity: row.try_get("quantity")?,
+        liquidity: if row.try_get::<String, _>("liquidity")? == "maker" {
+            Liquidity::Maker
+        } else {
+            Liquidity::Taker
+        },
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+pub async fn get_fills_since(
+    pool: &PgPool,
+    account_id: &str,
+    since_ms: i64,
+) -> Result<Vec<Fill>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT fill_id, order_id, account_id, symbol, side, liquidity,
+            CAST(price AS DOUBLE PRECISION) as price,
+            CAST(quantity AS DOUBLE PRECISION) as quantity,
+            CAST(EXTRACT(EPOCH FROM created_at) * 1000 AS BIGINT) as created_at
+        FROM fills
+        WHERE account_id = $1 AND created_at >= to_timestamp($2::double precision / 1000)
+        ORDER BY created_at ASC
+        "#,
+    )
+    .bind(account_id)
+    .bind(since_ms)
+    .try_map(|row: sqlx::postgres::PgRow| fill_from_row(&row))
+    .fetch_all(pool)
+    .await
+}
+
+// Fills in [from_ms, to_ms), oldest first
+pub async fn get_fills_between(
+    pool: &PgPool,
+    account_id: &str,
+    from_ms: i64,
+    to_ms: i64,
+) -> Result<Vec<Fill>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT fill_id, order_id, account_id, symbol, side, liquidity,
+            CAST(price AS DOUBLE PRECISION) as price,
+            CAST(quantity AS DOUBLE PRECISION) as quantity,
+            CAST(EXTRACT(EPOCH FROM created_at) * 1000 AS BIGINT) as created_at
+        FROM fills
+        WHERE account_id = $1
+            AND created_at >= to_timestamp($2::double precision / 1000)
+            AND created_at < to_timestamp($3::double precision / 1000)
+        ORDER BY created_at ASC, fill_id ASC
+        "#,
+    )
+    .bind(account_id)
+    .bind(from_ms)
+    .bind(to_ms)
+    .try_map(|row: sqlx::postgres::PgRow| fill_from_row(&row))
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn save_report_schedule(pool: &PgPool, schedule: &ReportSchedule) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO report_schedules (account_id, send_at, channel, target)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (account_id) DO UPDATE SET
+            send_at = EXCLUDED.send_at,
+            channel = EXCLUDED.channel,
+            target = EXCLUDED.target
+        "#,
+    )
+    .bind(&schedule.account_id)
+    .bind(&schedule.send_at)
+    .bind(schedule.channel.name())
+    .bind(&schedule.target)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_report_schedules(pool: &PgPool) -> Result<Vec<ReportSchedule>, sqlx::Error> {
+    sqlx::query("SELECT account_id, send_at, channel, target, last_sent_day, last_equity FROM report_schedules")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            Ok(ReportSchedule {
+                account_id: row.try_get("account_id")?,
+                send_at: row.try_get("send_at")?,
+                channel: ChannelKind::from_name(&row.try_get::<String, _>("channel")?)
+                    .unwrap_or(ChannelKind::Webhook),
+                target: row.try_get("target")?,
+                last_sent_day: row.try_get("last_sent_day")?,
+                last_equity: row.try_get("last_equity")?,
+            })
+        })
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn create_user(
+    pool: &PgPool,
+    user_id: &str,
+    role: Role,
+    tenant_id: Option<&str>,
+    token_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO users (user_id, token_hash, role, tenant_id) VALUES ($1, $2, $3, $4)")
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(role.name())
+        .bind(tenant_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_user_tenant(pool: &PgPool, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+    let tenant_id: Option<Option<String>> = sqlx::query_scalar("SELECT tenant_id FROM users WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(tenant_id.flatten())
+}
+
+pub async fn save_tenant(pool: &PgPool, tenant: &Tenant) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO tenants (tenant_id, name, max_connections, max_storage_bytes)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (tenant_id) DO UPDATE SET
+            name = EXCLUDED.name,
+            max_connections = EXCLUDED.max_connections,
+            max_storage_bytes = EXCLUDED.max_storage_bytes
+        "#,
+    )
+    .bind(&tenant.tenant_id)
+    .bind(&tenant.name)
+    .bind(tenant.max_connections)
+    .bind(tenant.max_storage_bytes)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_tenants(pool: &PgPool) -> Result<Vec<Tenant>, sqlx::Error> {
+    sqlx::query("SELECT tenant_id, name, max_connections, max_storage_bytes FROM tenants")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            Ok(Tenant {
+                tenant_id: row.try_get("tenant_id")?,
+                name: row.try_get("name")?,
+                max_connections: row.try_get("max_connections")?,
+                max_storage_bytes: row.try_get("max_storage_bytes")?,
+            })
+        })
+        .fetch_all(pool)
+        .await
+}
+
+// Tenant of every account that belongs to one
+pub async fn load_account_tenants(pool: &PgPool) -> Result<HashMap<String, String>, sqlx::Error> {
+    sqlx::query("SELECT account_id, tenant_id FROM account_tenants")
+        .try_map(|row: sqlx::postgres::PgRow| Ok((row.try_get("account_id")?, row.try_get("tenant_id")?)))
+        .fetch_all(pool)
+        .await
+        .map(|rows| rows.into_iter().collect())
+}
+
+// False when the account already belongs to a tenant
+pub async fn save_account_tenant(pool: &PgPool, account_id: &str, tenant_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO account_tenants (account_id, tenant_id) VALUES ($1, $2)
+        ON CONFLICT (account_id) DO NOTHING
+        "#,
+    )
+    .bind(account_id)
+    .bind(tenant_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Bytes of settings, alerts and exports stored by the users of each tenant, or of just the given one
+pub async fn get_tenant_storage(pool: &PgPool, tenant_id: Option<&str>) -> Result<HashMap<String, i64>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH stored AS (
+            SELECT user_id, octet_length(value::text) AS bytes FROM user_settings
+            UNION ALL
+            SELECT user_id, bytes FROM user_storage
+        )
+        SELECT users.tenant_id, CAST(SUM(stored.bytes) AS BIGINT) AS storage_bytes
+        FROM stored
+        JOIN users ON users.user_id = stored.user_id
+        WHERE users.tenant_id IS NOT NULL AND ($1::text IS NULL OR users.tenant_id = $1)
+        GROUP BY users.tenant_id
+        "#,
+    )
+    .bind(tenant_id)
+    .try_map(|row: sqlx::postgres::PgRow| Ok((row.try_get("tenant_id")?, row.try_get("storage_bytes")?)))
+    .fetch_all(pool)
+    .await
+    .map(|rows| rows.into_iter().collect())
+}
+
+// Records an item against the user's storage, replacing an earlier copy of it
+pub async fn save_storage_item(
+    pool: &PgPool,
+    kind: StorageKind,
+    item_id: &str,
+    user_id: &str,
+    bytes: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO user_storage (kind, item_id, user_id, bytes)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (kind, item_id) DO UPDATE SET
+            user_id = EXCLUDED.user_id,
+            bytes = EXCLUDED.bytes,
+            created_at = NOW()
+        "#,
+    )
+    .bind(kind.name())
+    .bind(item_id)
+    .bind(user_id)
+    .bind(bytes)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Size of a stored item, None when nobody stores it yet
+pub async fn get_storage_item(pool: &PgPool, kind: StorageKind, item_id: &str) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT bytes FROM user_storage WHERE kind = $1 AND item_id = $2")
+        .bind(kind.name())
+        .bind(item_id)
+        .fetch_optional(pool)
+        .await
+}
+
+// Forgets items of a kind recorded before `before`, returns how many
+pub async fn delete_storage_items_before(pool: &PgPool, kind: StorageKind, before: i64) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "DELETE FROM user_storage WHERE kind = $1 AND created_at < to_timestamp($2::double precision / 1000)",
+    )
+    .bind(kind.name())
+    .bind(before)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Storage of every user, largest first, or of just the given one
+pub async fn get_user_storage(
+    pool: &PgPool,
+    user_id: Option<&str>,
+    limit: i64,
+) -> Result<Vec<UserStorage>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH settings AS (
+            SELECT user_id, SUM(octet_length(value::text)) AS bytes FROM user_settings GROUP BY user_id
+        ), items AS (
+            SELECT user_id,
+                COUNT(*) FILTER (WHERE kind = 'alert') AS alerts,
+                COALESCE(SUM(bytes) FILTER (WHERE kind = 'alert'), 0) AS alert_bytes,
+                COUNT(*) FILTER (WHERE kind = 'export') AS exports,
+                COALESCE(SUM(bytes) FILTER (WHERE kind = 'export'), 0) AS export_bytes
+            FROM user_storage
+            GROUP BY user_id
+        )
+        SELECT users.user_id, users.tenant_id,
+            CAST(COALESCE(settings.bytes, 0) AS BIGINT) AS settings_bytes,
+            COALESCE(items.alerts, 0) AS alerts,
+            CAST(COALESCE(items.alert_bytes, 0) AS BIGINT) AS alert_bytes,
+            COALESCE(items.exports, 0) AS exports,
+            CAST(COALESCE(items.export_bytes, 0) AS BIGINT) AS export_bytes,
+            CAST(COALESCE(settings.bytes, 0) + COALESCE(items.alert_bytes, 0) + COALESCE(items.export_bytes, 0)
+                AS BIGINT) AS total_bytes
+        FROM users
+        LEFT JOIN settings ON settings.user_id = users.user_id
+        LEFT JOIN items ON items.user_id = users.user_id
+        WHERE $1::text IS NULL OR users.user_id = $1
+        ORDER BY total_bytes DESC, users.user_id
+        LIMIT $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(UserStorage {
+            user_id: row.try_get("user_id")?,
+            tenant_id: row.try_get("tenant_id")?,
+            settings_bytes: row.try_get("settings_bytes")?,
+            alerts: row.try_get("alerts")?,
+            alert_bytes: row.try_get("alert_bytes")?,
+            exports: row.try_get("exports")?,
+            export_bytes: row.try_get("export_bytes")?,
+            total_bytes: row.try_get("total_bytes")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn set_user_role(pool: &PgPool, user_id: &str, role: Role) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE users SET role = $2 WHERE user_id = $1")
+        .bind(user_id)
+        .bind(role.name())
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_user_by_token(pool: &PgPool, token_hash: &str) -> Result<Option<(String, Role)>, sqlx::Error> {
+    let row = sqlx::query("SELECT user_id, role FROM users WHERE token_hash = $1")
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await?;
+
+    match row {
+        Some(row) => {
+            let role: String = row.try_get("role")?;
+            Ok(role.parse().ok().map(|role| (row.try_get("user_id").unwrap_or_default(), role)))
+        }
+        None => Ok(None),
+    }
+}
+
+pub async fn get_user_role(pool: &PgPool, user_id: &str) -> Result<Option<Role>, sqlx::Error> {
+    let role: Option<String> = sqlx::query_scalar("SELECT role FROM users WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(role.and_then(|role| role.parse().ok()))
+}
+
+pub async fn save_account_template(pool: &PgPool, template: &AccountTemplate) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO account_templates (name, starting_balance, allowed_symbols, max_leverage, fee_tier)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (name) DO UPDATE SET
+            starting_balance = EXCLUDED.starting_balance,
+            allowed_symbols = EXCLUDED.allowed_symbols,
+            max_leverage = EXCLUDED.max_leverage,
+            fee_tier = EXCLUDED.fee_tier,
+            updated_at = NOW()
+        "#,
+    )
+    .bind(&template.name)
+    .bind(template.starting_balance)
+    .bind(&template.allowed_symbols)
+    .bind(template.max_leverage)
+    .bind(&template.fee_tier)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_account_templates(pool: &PgPool) -> Result<Vec<AccountTemplate>, sqlx::Error> {
+    sqlx::query("SELECT name, starting_balance, allowed_symbols, max_leverage, fee_tier FROM account_templates")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            Ok(AccountTemplate {
+                name: row.try_get("name")?,
+                starting_balance: row.try_get("starting_balance")?,
+                allowed_symbols: row.try_get("allowed_symbols")?,
+                max_leverage: row.try_get("max_leverage")?,
+                fee_tier: row.try_get("fee_tier")?,
+            })
+        })
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn create_competition(pool: &PgPool, competition: &Competition) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO competitions (name, enrollment_start, enrollment_end, start_time, end_time)
+        VALUES (
+            $1,
+            to_timestamp($2::double precision / 1000),
+            to_timestamp($3::double precision / 1000),
+            to_timestamp($4::double precision / 1000),
+            to_timestamp($5::double precision / 1000)
+        )
+        RETURNING id
+        "#,
+    )
+    .bind(&competition.name)
+    .bind(competition.enrollment_start)
+    .bind(competition.enrollment_end)
+    .bind(competition.start_time)
+    .bind(competition.end_time)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn set_competition_rules(pool: &PgPool, competition_id: i64, template: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE competitions SET rules = $2 WHERE id = $1")
+        .bind(competition_id)
+        .bind(template)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+const COMPETITION_QUERY: &str = r#"
+    SELECT c.id, c.name,
+        CAST(EXTRACT(EPOCH FROM c.enrollment_start) * 1000 AS BIGINT) AS enrollment_start,
+        CAST(EXTRACT(EPOCH FROM c.enrollment_end) * 1000 AS BIGINT) AS enrollment_end,
+        CAST(EXTRACT(EPOCH FROM c.start_time) * 1000 AS BIGINT) AS start_time,
+        CAST(EXTRACT(EPOCH FROM c.end_time) * 1000 AS BIGINT) AS end_time,
+        c.finalized_at IS NOT NULL AS finalized,
+        t.name AS rules_name, t.starting_balance, t.allowed_symbols, t.max_leverage, t.fee_tier
+    FROM competitions c
+    LEFT JOIN account_templates t ON t.name = c.rules
+"#;
+
+fn competition_from_row(row: sqlx::postgres::PgRow) -> Result<Competition, sqlx::Error> {
+    let rules_name: Option<String> = row.try_get("rules_name")?;
+    let rules = match rules_name {
+        Some(name) => Some(AccountTemplate {
+            name,
+            starting_balance: row.try_get("starting_balance")?,
+            allowed_symbols: row.try_get("allowed_symbols")?,
+            max_leverage: row.try_get("max_leverage")?,
+            fee_tier: row.try_get("fee_tier")?,
+        }),
+        None => None,
+    };
+    Ok(Competition {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        enrollment_start: row.try_get("enrollment_start")?,
+        enrollment_end: row.try_get("enrollment_end")?,
+        start_time: row.try_get("start_time")?,
+        end_time: row.try_get("end_time")?,
+        rules,
+        finalized: row.try_get("finalized")?,
+    })
+}
+
+pub async fn get_competition(pool: &PgPool, competition_id: i64) -> Result<Option<Competition>, sqlx::Error> {
+    sqlx::query(&format!("{} WHERE c.id = $1", COMPETITION_QUERY))
+        .bind(competition_id)
+        .try_map(competition_from_row)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn get_competitions(pool: &PgPool) -> Result<Vec<Competition>, sqlx::Error> {
+    sqlx::query(&format!("{} ORDER BY c.start_time DESC", COMPETITION_QUERY))
+        .try_map(competition_from_row)
+        .fetch_all(pool)
+        .await
+}
+
+// Competitions that ended at or before `now` and have no final ranking yet
+pub async fn get_unfinalized_competitions(pool: &PgPool, now: i64) -> Result<Vec<Competition>, sqlx::Error> {
+    sqlx::query(&format!(
+        "{} WHERE c.finalized_at IS NULL AND c.end_time <= to_timestamp($1::double precision / 1000)",
+        COMPETITION_QUERY
+    ))
+    .bind(now)
+    .try_map(competition_from_row)
+    .fetch_all(pool)
+    .await
+}
+
+// Returns false if the user is already enrolled
+pub async fn enroll_competition(
+    pool: &PgPool,
+    competition_id: i64,
+    user_id: &str,
+    account_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO competition_entries (competition_id, user_id, account_id) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+    )
+    .bind(competition_id)
+    .bind(user_id)
+    .bind(account_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// (user_id, account_id) of every entrant
+pub async fn get_competition_entries(pool: &PgPool, competition_id: i64) -> Result<Vec<(String, String)>, sqlx::Error> {
+    sqlx::query("SELECT user_id, account_id FROM competition_entries WHERE competition_id = $1")
+        .bind(competition_id)
+        .try_map(|row: sqlx::postgres::PgRow| Ok((row.try_get("user_id")?, row.try_get("account_id")?)))
+        .fetch_all(pool)
+        .await
+}
+
+// (account_id, start_time, end_time) of every competition account
+pub async fn load_competition_windows(pool: &PgPool) -> Result<Vec<(String, i64, i64)>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT e.account_id,
+            CAST(EXTRACT(EPOCH FROM c.start_time) * 1000 AS BIGINT) AS start_time,
+            CAST(EXTRACT(EPOCH FROM c.end_time) * 1000 AS BIGINT) AS end_time
+        FROM competition_entries e
+        JOIN competitions c ON c.id = e.competition_id
+        "#,
+    )
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok((row.try_get("account_id")?, row.try_get("start_time")?, row.try_get("end_time")?))
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// Archives the final ranking and marks the competition finalized, in one transaction
+pub async fn save_competition_results(
+    pool: &PgPool,
+    competition_id: i64,
+    standings: &[CompetitionStanding],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    for standing in standings {
+        sqlx::query(
+            r#"
+            INSERT INTO competition_results (competition_id, rank, user_id, account_id, equity, return_pct)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(competition_id)
+        .bind(standing.rank as i32)
+        .bind(&standing.user_id)
+        .bind(&standing.account_id)
+        .bind(standing.equity)
+        .bind(standing.return_pct)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    sqlx::query("UPDATE competitions SET finalized_at = NOW() WHERE id = $1")
+        .bind(competition_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
+}
+
+pub async fn get_competition_results(pool: &PgPool, competition_id: i64) -> Result<Vec<CompetitionStanding>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT rank, user_id, account_id, equity, return_pct FROM competition_results
+        WHERE competition_id = $1
+        ORDER BY rank
+        "#,
+    )
+    .bind(competition_id)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        let rank: i32 = row.try_get("rank")?;
+        Ok(CompetitionStanding {
+            rank: rank as u32,
+            user_id: row.try_get("user_id")?,
+            account_id: row.try_get("account_id")?,
+            equity: row.try_get("equity")?,
+            return_pct: row.try_get("return_pct")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// Replaces the team and its member list
+pub async fn save_team(pool: &PgPool, team: &Team) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO teams (account_id, name, approval_mode, approval_min_notional) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (account_id) DO UPDATE SET name = EXCLUDED.name, approval_mode = EXCLUDED.approval_mode,
+            approval_min_notional = EXCLUDED.approval_min_notional
+        "#,
+    )
+    .bind(&team.account_id)
+    .bind(&team.name)
+    .bind(team.approval_mode)
+    .bind(team.approval_min_notional)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM team_members WHERE account_id = $1")
+        .bind(&team.account_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for member in &team.members {
+        sqlx::query("INSERT INTO team_members (account_id, user_id, role) VALUES ($1, $2, $3)")
+            .bind(&team.account_id)
+            .bind(&member.user_id)
+            .bind(member.role.name())
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await
+}
+
+pub async fn load_teams(pool: &PgPool) -> Result<Vec<Team>, sqlx::Error> {
+    let mut teams: Vec<Team> = sqlx::query("SELECT account_id, name, approval_mode, approval_min_notional FROM teams")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            Ok(Team {
+                account_id: row.try_get("account_id")?,
+                name: row.try_get("name")?,
+                approval_mode: row.try_get("approval_mode")?,
+                approval_min_notional: row.try_get("approval_min_notional")?,
+                members: Vec::new(),
+            })
+        })
+        .fetch_all(pool)
+        .await?;
+
+    let members = sqlx::query("SELECT account_id, user_id, role FROM team_members")
+        .fetch_all(pool)
+        .await?;
+    for row in members {
+        let account_id: String = row.try_get("account_id")?;
+        let role: String = row.try_get("role")?;
+        let (Some(team), Ok(role)) = (teams.iter_mut().find(|team| team.account_id == account_id), role.parse()) else {
+            continue;
+        };
+        team.members.push(TeamMember {
+            user_id: row.try_get("user_id")?,
+            role,
+        });
+    }
+
+    Ok(teams)
+}
+
+pub async fn save_order_attribution(
+    pool: &PgPool,
+    account_id: &str,
+    user_id: &str,
+    order_ids: &[u64],
+) -> Result<(), sqlx::Error> {
+    let order_ids: Vec<i64> = order_ids.iter().map(|id| *id as i64).collect();
+    sqlx::query(
+        r#"
+        INSERT INTO order_attribution (account_id, order_id, user_id)
+        SELECT $1, order_id, $2 FROM UNNEST($3::bigint[]) AS order_id
+        "#,
+    )
+    .bind(account_id)
+    .bind(user_id)
+    .bind(&order_ids)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Every fill of the account, oldest first, with the member whose order it was
+pub async fn get_attributed_fills(pool: &PgPool, account_id: &str) -> Result<Vec<AttributedFill>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT f.fill_id, f.order_id, f.account_id, f.symbol, f.side, f.liquidity,
+            CAST(f.price AS DOUBLE PRECISION) as price,
+            CAST(f.quantity AS DOUBLE PRECISION) as quantity,
+            CAST(EXTRACT(EPOCH FROM f.created_at) * 1000 AS BIGINT) as created_at,
+            a.user_id
+        FROM fills f
+        LEFT JOIN LATERAL (
+            SELECT user_id FROM order_attribution
+            WHERE account_id = f.account_id AND order_id = f.order_id AND placed_at <= f.created_at
+            ORDER BY placed_at DESC
+            LIMIT 1
+        ) a ON TRUE
+        WHERE f.account_id = $1
+        ORDER BY f.created_at ASC
+        "#,
+    )
+    .bind(account_id)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(AttributedFill {
+            fill: fill_from_row(&row)?,
+            user_id: row.try_get("user_id")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn save_order_proposal(pool: &PgPool, proposal: &OrderProposal) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO order_proposals (account_id, proposed_by, request, status, created_at)
+        VALUES ($1, $2, $3, $4, to_timestamp($5::double precision / 1000))
+        RETURNING proposal_id
+        "#,
+    )
+    .bind(&proposal.account_id)
+    .bind(&proposal.proposed_by)
+    .bind(serde_json::to_value(&proposal.request).unwrap_or_default())
+    .bind(&proposal.status)
+    .bind(proposal.created_at)
+    .fetch_one(pool)
+    .await
+}
+
+const PROPOSAL_COLUMNS: &str = r#"
+    proposal_id, account_id, proposed_by, request, status, decided_by, order_id,
+    CAST(EXTRACT(EPOCH FROM created_at) * 1000 AS BIGINT) as created_at
+"#;
+
+fn proposal_from_row(row: sqlx::postgres::PgRow) -> Result<OrderProposal, sqlx::Error> {
+    let request: serde_json::Value = row.try_get("request")?;
+    let order_id: Option<i64> = row.try_get("order_id")?;
+    Ok(OrderProposal {
+        proposal_id: row.try_get("proposal_id")?,
+        account_id: row.try_get("account_id")?,
+        proposed_by: row.try_get("proposed_by")?,
+        request: serde_json::from_value(request).map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+        status: row.try_get("status")?,
+        decided_by: row.try_get("decided_by")?,
+        order_id: order_id.map(|id| id as u64),
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+pub async fn get_order_proposal(
+    pool: &PgPool,
+    account_id: &str,
+    proposal_id: i64,
+) -> Result<Option<OrderProposal>, sqlx::Error> {
+    sqlx::query(&format!(
+        "SELECT {} FROM order_proposals WHERE account_id = $1 AND proposal_id = $2",
+        PROPOSAL_COLUMNS
+    ))
+    .bind(account_id)
+    .bind(proposal_id)
+    .try_map(proposal_from_row)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn get_order_proposals(pool: &PgPool, account_id: &str, limit: i64) -> Result<Vec<OrderProposal>, sqlx::Error> {
+    sqlx::query(&format!(
+        "SELECT {} FROM order_proposals WHERE account_id = $1 ORDER BY created_at DESC LIMIT $2",
+        PROPOSAL_COLUMNS
+    ))
+    .bind(account_id)
+    .bind(limit)
+    .try_map(proposal_from_row)
+    .fetch_all(pool)
+    .await
+}
+
+// Moves a pending proposal to `status`, returns false if someone else decided it first
+pub async fn decide_order_proposal(
+    pool: &PgPool,
+    proposal_id: i64,
+    status: &str,
+    decided_by: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE order_proposals SET status = $2, decided_by = $3, decided_at = NOW()
+        WHERE proposal_id = $1 AND status = 'pending_approval'
+        "#,
+    )
+    .bind(proposal_id)
+    .bind(status)
+    .bind(decided_by)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Links an approved proposal to its order, or marks it failed when placement was refused
+pub async fn finish_order_proposal(pool: &PgPool, proposal_id: i64, order_id: Option<u64>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE order_proposals
+        SET order_id = $2, status = CASE WHEN $2 IS NULL THEN 'failed' ELSE status END
+        WHERE proposal_id = $1
+        "#,
+    )
+    .bind(proposal_id)
+    .bind(order_id.map(|id| id as i64))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Returns false if the account already exists
+pub async fn create_account(pool: &PgPool, account_id: &str, template: Option<&str>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("INSERT INTO accounts (account_id, template) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(account_id)
+        .bind(template)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// (account_id, template) of every account created from a template
+pub async fn load_account_assignments(pool: &PgPool) -> Result<Vec<(String, String)>, sqlx::Error> {
+    sqlx::query("SELECT account_id, template FROM accounts WHERE template IS NOT NULL")
+        .try_map(|row: sqlx::postgres::PgRow| Ok((row.try_get("account_id")?, row.try_get("template")?)))
+        .fetch_all(pool)
+        .await
+}
+
+// Records a reset and moves the account's fills to archived_fills, returns the reset id and the
+// number of fills archived
+pub async fn archive_account_fills(
+    pool: &PgPool,
+    account_id: &str,
+    cash_before: f64,
+    equity_before: f64,
+) -> Result<(i64, u64), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let reset_id: i64 = sqlx::query_scalar(
+        "INSERT INTO account_resets (account_id, cash_before, equity_before) VALUES ($1, $2, $3) RETURNING reset_id",
+    )
+    .bind(account_id)
+    .bind(cash_before)
+    .bind(equity_before)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let archived = sqlx::query(
+        r#"
+        INSERT INTO archived_fills
+            (order_id, account_id, symbol, side, price, quantity, liquidity, created_at, fill_id, reset_id)
+        SELECT order_id, account_id, symbol, side, price, quantity, liquidity, created_at, fill_id, $2
+        FROM fills WHERE account_id = $1
+        "#,
+    )
+    .bind(account_id)
+    .bind(reset_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    sqlx::query("DELETE FROM fills WHERE account_id = $1")
+        .bind(account_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok((reset_id, archived))
+}
+
+pub async fn count_account_resets_since(pool: &PgPool, account_id: &str, since: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM account_resets WHERE account_id = $1 AND created_at >= to_timestamp($2::double precision / 1000)",
+    )
+    .bind(account_id)
+    .bind(since)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn save_top_up(pool: &PgPool, account_id: &str, amount: f64) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO account_top_ups (account_id, amount) VALUES ($1, $2)")
+        .bind(account_id)
+        .bind(amount)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn save_trading_rules(pool: &PgPool, rules: &TradingRules) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO trading_rules (account_id, rules) VALUES ($1, $2)
+        ON CONFLICT (account_id) DO UPDATE SET rules = EXCLUDED.rules
+        "#,
+    )
+    .bind(&rules.account_id)
+    .bind(serde_json::to_value(rules).unwrap_or_default())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_trading_rules(pool: &PgPool) -> Result<Vec<TradingRules>, sqlx::Error> {
+    sqlx::query("SELECT rules FROM trading_rules")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            let rules: serde_json::Value = row.try_get("rules")?;
+            serde_json::from_value(rules).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+        })
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn save_grid_bot(pool: &PgPool, bot: &GridBot) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO grid_bots (account_id, symbol, bot) VALUES ($1, $2, $3)
+        ON CONFLICT (account_id, symbol) DO UPDATE SET bot = EXCLUDED.bot
+        "#,
+    )
+    .bind(&bot.config.account_id)
+    .bind(&bot.config.symbol)
+    .bind(serde_json::to_value(bot).unwrap_or_default())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_grid_bots(pool: &PgPool) -> Result<Vec<GridBot>, sqlx::Error> {
+    sqlx::query("SELECT bot FROM grid_bots")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            let bot: serde_json::Value = row.try_get("bot")?;
+            serde_json::from_value(bot).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+        })
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn save_venue_order(pool: &PgPool, venue: &str, order: &Order) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO venue_orders (venue, order_id, account_id, is_open, body) VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (venue, order_id) DO UPDATE SET is_open = EXCLUDED.is_open, body = EXCLUDED.body
+        "#,
+    )
+    .bind(venue)
+    .bind(order.id as i64)
+    .bind(&order.account_id)
+    .bind(order.status.is_open())
+    .bind(serde_json::to_value(order).unwrap_or_default())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_open_venue_orders(pool: &PgPool, venue: &str) -> Result<Vec<Order>, sqlx::Error> {
+    sqlx::query("SELECT body FROM venue_orders WHERE venue = $1 AND is_open")
+        .bind(venue)
+        .try_map(|row: sqlx::postgres::PgRow| {
+            let body: serde_json::Value = row.try_get("body")?;
+            serde_json::from_value(body).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+        })
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn save_bot(pool: &PgPool, bot: &Bot) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO bots (account_id, symbol, bot) VALUES ($1, $2, $3)
+        ON CONFLICT (account_id, symbol) DO UPDATE SET bot = EXCLUDED.bot
+        "#,
+    )
+    .bind(&bot.config.account_id)
+    .bind(&bot.config.symbol)
+    .bind(serde_json::to_value(bot).unwrap_or_default())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_bots(pool: &PgPool) -> Result<Vec<Bot>, sqlx::Error> {
+    sqlx::query("SELECT bot FROM bots")
+        .try_map(|row: sqlx::postgres::PgRow| {
+            let bot: serde_json::Value = row.try_get("bot")?;
+            serde_json::from_value(bot).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+        })
+        .fetch_all(pool)
+        .await
+}
+
+// Orders the account placed since `since`, on the matching engine or an exchange
+pub async fn count_orders_since(pool: &PgPool, account_id: &str, since: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT (SELECT COUNT(*) FROM orders WHERE account_id = $1 AND (body->>'created_at')::BIGINT >= $2)
+            + (SELECT COUNT(*) FROM venue_orders WHERE account_id = $1 AND (body->>'created_at')::BIGINT >= $2)
+        "#,
+    )
+    .bind(account_id)
+    .bind(since)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn save_public_profile(
+    pool: &PgPool,
+    account_id: &str,
+    token_hash: &str,
+    delay_secs: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO public_profiles (account_id, token_hash, delay_secs) VALUES ($1, $2, $3)
+        ON CONFLICT (account_id) DO UPDATE
+        SET token_hash = EXCLUDED.token_hash, delay_secs = EXCLUDED.delay_secs, created_at = NOW()
+        "#,
+    )
+    .bind(account_id)
+    .bind(token_hash)
+    .bind(delay_secs)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_public_profile(pool: &PgPool, account_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM public_profiles WHERE account_id = $1")
+        .bind(account_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Account and delay published under a share token
+pub async fn get_public_profile(pool: &PgPool, token_hash: &str) -> Result<Option<(String, i64)>, sqlx::Error> {
+    sqlx::query_as("SELECT account_id, delay_secs FROM public_profiles WHERE token_hash = $1")
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn get_last_reset_time(pool: &PgPool, account_id: &str) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT CAST(EXTRACT(EPOCH FROM MAX(created_at)) * 1000 AS BIGINT) FROM account_resets WHERE account_id = $1",
+    )
+    .bind(account_id)
+    .fetch_one(pool)
+    .await
+}
+
+// Top-ups as (time, amount), oldest first
+pub async fn get_top_ups_since(pool: &PgPool, account_id: &str, since: i64) -> Result<Vec<(i64, f64)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT CAST(EXTRACT(EPOCH FROM created_at) * 1000 AS BIGINT), amount FROM account_top_ups
+        WHERE account_id = $1 AND created_at >= to_timestamp($2::double precision / 1000)
+        ORDER BY created_at ASC
+        "#,
+    )
+    .bind(account_id)
+    .bind(since)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn sum_top_ups_since(pool: &PgPool, account_id: &str, since: i64) -> Result<f64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT COALESCE(SUM(amount), 0) FROM account_top_ups
+        WHERE account_id = $1 AND created_at >= to_timestamp($2::double precision / 1000)
+        "#,
+    )
+    .bind(account_id)
+    .bind(since)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get_guest_starting_balance(pool: &PgPool, user_id: &str) -> Result<Option<f64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT starting_balance FROM guests WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn create_guest(
+    pool: &PgPool,
+    user_id: &str,
+    token_hash: &str,
+    starting_balance: f64,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("INSERT INTO users (user_id, token_hash, role) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(Role::User.name())
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("INSERT INTO guests (user_id, starting_balance) VALUES ($1, $2)")
+        .bind(user_id)
+        .bind(starting_balance)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
+}
+
+pub async fn count_guests(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM guests").fetch_one(pool).await
+}
+
+// Guest accounts with their starting balance, to seed portfolios at startup
+pub async fn load_guests(pool: &PgPool) -> Result<Vec<(String, f64)>, sqlx::Error> {
+    sqlx::query("SELECT user_id, starting_balance FROM guests")
+        .try_map(|row: sqlx::postgres::PgRow| Ok((row.try_get("user_id")?, row.try_get("starting_balance")?)))
+        .fetch_all(pool)
+        .await
+}
+
+// Guests older than `days` without an audit entry in that time, the audit log already records
+// every login and order
+pub async fn get_inactive_guests(pool: &PgPool, days: i32) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT g.user_id FROM guests g
+        WHERE g.created_at < NOW() - make_interval(days => $1)
+            AND NOT EXISTS (
+                SELECT 1 FROM audit_log a
+                WHERE a.actor = g.user_id AND a.created_at >= NOW() - make_interval(days => $1)
+            )
+        "#,
+    )
+    .bind(days)
+    .fetch_all(pool)
+    .await
+}
+
+// Removes a guest user and everything stored for its account, except the audit trail
+pub async fn delete_guest(pool: &PgPool, user_id: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let statements = [
+        "DELETE FROM refresh_tokens WHERE session_id IN (SELECT session_id FROM auth_sessions WHERE user_id = $1)",
+        "DELETE FROM auth_sessions WHERE user_id = $1",
+        "DELETE FROM api_keys WHERE user_id = $1",
+        "DELETE FROM user_settings WHERE user_id = $1",
+        "DELETE FROM fills WHERE account_id = $1",
+        "DELETE FROM archived_fills WHERE account_id = $1",
+        "DELETE FROM account_resets WHERE account_id = $1",
+        "DELETE FROM account_top_ups WHERE account_id = $1",
+        "DELETE FROM notifications WHERE account_id = $1",
+        "DELETE FROM notification_channels WHERE account_id = $1",
+        "DELETE FROM spreads WHERE account_id = $1",
+        "DELETE FROM venue_orders WHERE account_id = $1",
+        "DELETE FROM grid_bots WHERE account_id = $1",
+        "DELETE FROM bots WHERE account_id = $1",
+        "DELETE FROM report_schedules WHERE account_id = $1",
+        "DELETE FROM drawdown_alerts WHERE account_id = $1",
+        "DELETE FROM portfolio_risk WHERE account_id = $1",
+        "DELETE FROM accounts WHERE account_id = $1",
+        "DELETE FROM guests WHERE user_id = $1",
+        "DELETE FROM users WHERE user_id = $1",
+    ];
+    for statement in statements {
+        sqlx::query(statement).bind(user_id).execute(&mut *tx).await?;
+    }
+
+    tx.commit().await
+}
+
+pub async fn get_user_settings(pool: &PgPool, user_id: &str) -> Result<HashMap<String, serde_json::Value>, sqlx::Error> {
+    let rows = sqlx::query("SELECT key, value FROM user_settings WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter()
+        .map(|row| Ok((row.try_get("key")?, row.try_get("value")?)))
+        .collect()
+}
+
+pub async fn set_user_setting(
+    pool: &PgPool,
+    user_id: &str,
+    key: &str,
+    value: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    if value.is_null() {
+        sqlx::query("DELETE FROM user_settings WHERE user_id = $1 AND key = $2")
+            .bind(user_id)
+            .bind(key)
+            .execute(pool)
+            .await?;
+    } else {
+        sqlx::query(
+            r#"
+            INSERT INTO user_settings (user_id, key, value) VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, key) DO UPDATE SET value = EXCLUDED.value, updated_at = NOW()
+            "#,
+        )
+        .bind(user_id)
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn create_auth_session(
+    pool: &PgPool,
+    session_id: &str,
+    user_id: &str,
+    key_id: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO auth_sessions (session_id, user_id, key_id) VALUES ($1, $2, $3)")
+        .bind(session_id)
+        .bind(user_id)
+        .bind(key_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// User and API key of a session that hasn't been revoked
+pub async fn get_active_auth_session(
+    pool: &PgPool,
+    session_id: &str,
+) -> Result<Option<(String, Option<String>)>, sqlx::Error> {
+    let row = sqlx::query("SELECT user_id, key_id FROM auth_sessions WHERE session_id = $1 AND revoked_at IS NULL")
+        .bind(session_id)
+        .fetch_optional(pool)
+        .await?;
+
+    match row {
+        Some(row) => Ok(Some((row.try_get("user_id")?, row.try_get("key_id")?))),
+        None => Ok(None),
+    }
+}
+
+pub async fn revoke_auth_session(pool: &PgPool, session_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE auth_sessions SET revoked_at = NOW() WHERE session_id = $1 AND revoked_at IS NULL")
+        .bind(session_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn revoke_user_sessions(pool: &PgPool, user_id: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("UPDATE auth_sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn save_refresh_token(
+    pool: &PgPool,
+    token_hash: &str,
+    session_id: &str,
+    expires_at: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (token_hash, session_id, expires_at)
+        VALUES ($1, $2, to_timestamp($3::double precision / 1000))
+        "#,
+    )
+    .bind(token_hash)
+    .bind(session_id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub enum RefreshTokenUse {
+    Fresh { session_id: String, expires_at: i64 },
+    Reused { session_id: String }, // Already exchanged once, the token has leaked
+    Unknown,
+}
+
+// Marks a refresh token used, atomically so two concurrent refreshes can't both succeed
+pub async fn use_refresh_token(pool: &PgPool, token_hash: &str) -> Result<RefreshTokenUse, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        UPDATE refresh_tokens SET used_at = NOW()
+        WHERE token_hash = $1 AND used_at IS NULL
+        RETURNING session_id, CAST(EXTRACT(EPOCH FROM expires_at) * 1000 AS BIGINT) AS expires_at
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(row) = row {
+        return Ok(RefreshTokenUse::Fresh {
+            session_id: row.try_get("session_id")?,
+            expires_at: row.try_get("expires_at")?,
+        });
+    }
+
+    let session_id: Option<String> = sqlx::query_scalar("SELECT session_id FROM refresh_tokens WHERE token_hash = $1")
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await?;
+    Ok(match session_id {
+        Some(session_id) => RefreshTokenUse::Reused { session_id },
+        None => RefreshTokenUse::Unknown,
+    })
+}
+
+pub async fn create_api_key(pool: &PgPool, key: &ApiKey, secret_hash: &str) -> Result<(), sqlx::Error> {
+    let scopes: Vec<&str> = key.scopes.iter().map(|scope| scope.name()).collect();
+    sqlx::query(
+        r#"
+        INSERT INTO api_keys (key_id, user_id, secret_hash, scopes, account_id, rate_limit, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, to_timestamp($7::double precision / 1000))
+        "#,
+    )
+    .bind(&key.key_id)
+    .bind(&key.user_id)
+    .bind(secret_hash)
+    .bind(&scopes)
+    .bind(&key.account_id)
+    .bind(key.rate_limit.map(|limit| limit as i32))
+    .bind(key.created_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn revoke_api_key(pool: &PgPool, user_id: &str, key_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET revoked_at = NOW() WHERE user_id = $1 AND key_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(key_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+const API_KEY_COLUMNS: &str = r#"
+    k.key_id, k.user_id, k.secret_hash, k.scopes, k.account_id, k.rate_limit,
+    CAST(EXTRACT(EPOCH FROM k.created_at) * 1000 AS BIGINT) AS created_at,
+    k.revoked_at IS NOT NULL AS revoked
+"#;
+
+fn api_key_from_row(row: &sqlx::postgres::PgRow) -> Result<ApiKey, sqlx::Error> {
+    let scopes: Vec<String> = row.try_get("scopes")?;
+    let rate_limit: Option<i32> = row.try_get("rate_limit")?;
+    Ok(ApiKey {
+        key_id: row.try_get("key_id")?,
+        user_id: row.try_get("user_id")?,
+        scopes: scopes.iter().filter_map(|scope| scope.parse().ok()).collect(),
+        account_id: row.try_get("account_id")?,
+        rate_limit: rate_limit.map(|limit| limit as u32),
+        created_at: row.try_get("created_at")?,
+        revoked: row.try_get("revoked")?,
+    })
+}
+
+pub async fn get_api_keys(pool: &PgPool, user_id: &str) -> Result<Vec<ApiKey>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM api_keys k WHERE k.user_id = $1 ORDER BY k.created_at",
+        API_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter().map(api_key_from_row).collect()
+}
+
+// An active key with its secret hash and the role of the user owning it
+pub async fn get_active_api_key(pool: &PgPool, key_id: &str) -> Result<Option<(ApiKey, String, Role)>, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        r#"
+        SELECT {}, u.role FROM api_keys k
+        JOIN users u ON u.user_id = k.user_id
+        WHERE k.key_id = $1 AND k.revoked_at IS NULL
+        "#,
+        API_KEY_COLUMNS
+    ))
+    .bind(key_id)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let role: String = row.try_get("role")?;
+    let Ok(role) = role.parse() else {
+        return Ok(None);
+    };
+
+    Ok(Some((api_key_from_row(&row)?, row.try_get("secret_hash")?, role)))
+}
+
+pub async fn save_audit_entry(pool: &PgPool, entry: &AuditEntry) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log (actor, key_id, ip, action, account_id, outcome, details, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, to_timestamp($8::double precision / 1000))
+        "#,
+    )
+    .bind(&entry.actor)
+    .bind(&entry.key_id)
+    .bind(&entry.ip)
+    .bind(&entry.action)
+    .bind(&entry.account_id)
+    .bind(&entry.outcome)
+    .bind(&entry.details)
+    .bind(entry.created_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn save_usage_snapshot(pool: &PgPool, snapshot: &UsageSnapshot) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO usage_stats (recorded_at, connections, ticker_subscriptions, index_subscriptions,
+            account_subscriptions, messages_in_per_sec, messages_out_per_sec)
+        VALUES (to_timestamp($1::double precision / 1000), $2, $3, $4, $5, $6, $7)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(snapshot.recorded_at)
+    .bind(snapshot.connections)
+    .bind(snapshot.ticker_subscriptions)
+    .bind(snapshot.index_subscriptions)
+    .bind(snapshot.account_subscriptions)
+    .bind(snapshot.messages_in_per_sec)
+    .bind(snapshot.messages_out_per_sec)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn save_tenant_usage(
+    pool: &PgPool,
+    tenant_id: &str,
+    snapshot: &TenantUsageSnapshot,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO tenant_usage_stats (tenant_id, recorded_at, connections, storage_bytes)
+        VALUES ($1, to_timestamp($2::double precision / 1000), $3, $4)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(tenant_id)
+    .bind(snapshot.recorded_at)
+    .bind(snapshot.connections)
+    .bind(snapshot.storage_bytes)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Newest buckets of one tenant first, each with its peak connections and storage
+pub async fn get_tenant_usage_stats(
+    pool: &PgPool,
+    tenant_id: &str,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    bucket_secs: i64,
+    limit: i64,
+) -> Result<Vec<TenantUsageSnapshot>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT CAST(EXTRACT(EPOCH FROM time_bucket(make_interval(secs => $4), recorded_at)) * 1000 AS BIGINT) AS bucket,
+            MAX(connections) AS connections,
+            MAX(storage_bytes) AS storage_bytes
+        FROM tenant_usage_stats
+        WHERE tenant_id = $1
+            AND ($2::bigint IS NULL OR recorded_at >= to_timestamp($2::double precision / 1000))
+            AND ($3::bigint IS NULL OR recorded_at < to_timestamp($3::double precision / 1000))
+        GROUP BY bucket
+        ORDER BY bucket DESC
+        LIMIT $5
+        "#,
+    )
+    .bind(tenant_id)
+    .bind(start_time)
+    .bind(end_time)
+    .bind(bucket_secs as f64)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(TenantUsageSnapshot {
+            recorded_at: row.try_get("bucket")?,
+            connections: row.try_get("connections")?,
+            storage_bytes: row.try_get("storage_bytes")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// Newest buckets first, each with the peak counts and average message rates of its snapshots
+pub async fn get_usage_stats(
+    pool: &PgPool,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    bucket_secs: i64,
+    limit: i64,
+) -> Result<Vec<UsageSnapshot>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT CAST(EXTRACT(EPOCH FROM time_bucket(make_interval(secs => $3), recorded_at)) * 1000 AS BIGINT) AS bucket,
+            MAX(connections) AS connections,
+            MAX(ticker_subscriptions) AS ticker_subscriptions,
+            MAX(index_subscriptions) AS index_subscriptions,
+            MAX(account_subscriptions) AS account_subscriptions,
+            AVG(messages_in_per_sec) AS messages_in_per_sec,
+            AVG(messages_out_per_sec) AS messages_out_per_sec
+        FROM usage_stats
+        WHERE ($1::bigint IS NULL OR recorded_at >= to_timestamp($1::double precision / 1000))
+            AND ($2::bigint IS NULL OR recorded_at < to_timestamp($2::double precision / 1000))
+        GROUP BY bucket
+        ORDER BY bucket DESC
+        LIMIT $4
+        "#,
+    )
+    .bind(start_time)
+    .bind(end_time)
+    .bind(bucket_secs as f64)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(UsageSnapshot {
+            recorded_at: row.try_get("bucket")?,
+            connections: row.try_get("connections")?,
+            ticker_subscriptions: row.try_get("ticker_subscriptions")?,
+            index_subscriptions: row.try_get("index_subscriptions")?,
+            account_subscriptions: row.try_get("account_subscriptions")?,
+            messages_in_per_sec: row.try_get("messages_in_per_sec")?,
+            messages_out_per_sec: row.try_get("messages_out_per_sec")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_audit_log(
+    pool: &PgPool,
+    actor: Option<&str>,
+    account_id: Option<&str>,
+    action: Option<&str>,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    limit: i64,
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT id, actor, key_id, ip, action, account_id, outcome, details,
+            CAST(EXTRACT(EPOCH FROM created_at) * 1000 AS BIGINT) as created_at
+        FROM audit_log
+        WHERE ($1::text IS NULL OR actor = $1)
+            AND ($2::text IS NULL OR account_id = $2)
+            AND ($3::text IS NULL OR action = $3)
+            AND ($4::bigint IS NULL OR created_at >= to_timestamp($4::double precision / 1000))
+            AND ($5::bigint IS NULL OR created_at < to_timestamp($5::double precision / 1000))
+        ORDER BY created_at DESC
+        LIMIT $6
+        "#,
+    )
+    .bind(actor)
+    .bind(account_id)
+    .bind(action)
+    .bind(start_time)
+    .bind(end_time)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(AuditEntry {
+            id: row.try_get("id")?,
+            actor: row.try_get("actor")?,
+            key_id: row.try_get("key_id")?,
+            ip: row.try_get("ip")?,
+            action: row.try_get("action")?,
+            account_id: row.try_get("account_id")?,
+            outcome: row.try_get("outcome")?,
+            details: row.try_get("details")?,
+            created_at: row.try_get("created_at")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn save_notification(
+    pool: &PgPool,
+    account_id: &str,
+    kind: &str,
+    title: &str,
+    payload: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO notifications (account_id, kind, title, payload) VALUES ($1, $2, $3, $4)")
+        .bind(account_id)
+        .bind(kind)
+        .bind(title)
+        .bind(payload)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_notifications(
+    pool: &PgPool,
+    account_id: &str,
+    unread_only: bool,
+    limit: i64,
+) -> Result<Vec<InboxNotification>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT id, account_id, kind, title, payload, read,
+            CAST(EXTRACT(EPOCH FROM created_at) * 1000 AS BIGINT) as created_at
+        FROM notifications
+        WHERE account_id = $1 AND (NOT $2 OR NOT read)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(account_id)
+    .bind(unread_only)
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(InboxNotification {
+            id: row.try_get("id")?,
+            account_id: row.try_get("account_id")?,
+            kind: row.try_get("kind")?,
+            title: row.try_get("title")?,
+            payload: row.try_get("payload")?,
+            read: row.try_get("read")?,
+            created_at: row.try_get("created_at")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn count_unread_notifications(pool: &PgPool, account_id: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM notifications WHERE account_id = $1 AND NOT read")
+        .bind(account_id)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn mark_notifications_read(pool: &PgPool, account_id: &str, ids: &[i64]) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE notifications SET read = TRUE
+        WHERE account_id = $1 AND NOT read AND (cardinality($2::bigint[]) = 0 OR id = ANY($2))
+        "#,
+    )
+    .bind(account_id)
+    .bind(ids)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn save_notification_channel(pool: &PgPool, channel: &NotificationChannel) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO notification_channels (account_id, kind, target)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(&channel.account_id)
+    .bind(channel.kind.name())
+    .bind(&channel.target)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_notification_channel(pool: &PgPool, channel: &NotificationChannel) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM notification_channels WHERE account_id = $1 AND kind = $2 AND target = $3")
+        .bind(&channel.account_id)
+        .bind(channel.kind.name())
+        .bind(&channel.target)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn save_spread(pool: &PgPool, spread: &SpreadDefinition) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO spreads (account_id, name, long_symbol, short_symbol, ratio)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (account_id, name) DO UPDATE SET
+            long_symbol = EXCLUDED.long_symbol,
+            short_symbol = EXCLUDED.short_symbol,
+            ratio = EXCLUDED.ratio
+        "#,
+    )
+    .bind(&spread.account_id)
+    .bind(&spread.name)
+    .bind(&spread.long_symbol)
+    .bind(&spread.short_symbol)
+    .bind(spread.ratio)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_spread(pool: &PgPool, account_id: &str, name: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM spreads WHERE account_id = $1 AND name = $2")
+        .bind(account_id)
+        .bind(name)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_spreads(pool: &PgPool, account_id: &str) -> Result<Vec<SpreadDefinition>, sqlx::Error> {
+    sqlx::query(
+        "SELECT account_id, name, long_symbol, short_symbol, ratio FROM spreads WHERE account_id = $1 ORDER BY name",
+    )
+    .bind(account_id)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(SpreadDefinition {
+            account_id: row.try_get("account_id")?,
+            name: row.try_get("name")?,
+            long_symbol: row.try_get("long_symbol")?,
+            short_symbol: row.try_get("short_symbol")?,
+            ratio: row.try_get("ratio")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn load_notification_channels(pool: &PgPool) -> Result<Vec<NotificationChannel>, sqlx::Error> {
+    let rows = sqlx::query("SELECT account_id, kind, target FROM notification_channels")
+        .fetch_all(pool)
+        .await?;
+
+    let mut channels = Vec::with_capacity(rows.len());
+    for row in rows {
+        let kind: String = row.try_get("kind")?;
+        if let Some(kind) = ChannelKind::from_name(&kind) {
+            channels.push(NotificationChannel {
+                account_id: row.try_get("account_id")?,
+                kind,
+                target: row.try_get("target")?,
+            });
+        }
+    }
+    Ok(channels)
+}
+
+pub async fn mark_report_sent(
+    pool: &PgPool,
+    account_id: &str,
+    day: i64,
+    equity: f64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE report_schedules SET last_sent_day = $2, last_equity = $3 WHERE account_id = $1")
+        .bind(account_id)
+        .bind(day)
+        .bind(equity)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "buy",
+        Side::Sell => "sell",
+    }
+}
+
+fn liquidity_name(liquidity: Liquidity) -> &'static str {
+    match liquidity {
+        Liquidity::Maker => "maker",
+        Liquidity::Taker => "taker",
+    }
+}
+
+// Find silences in each symbol's tick stream longer than its usual cadence and store them,
+// the threshold is the median tick spacing times the multiplier but never below the minimum
+pub async fn detect_data_gaps(
+    pool: &PgPool,
+    min_gap_secs: f64,
+    cadence_multiplier: f64,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        WITH spacing AS (
+            SELECT
+                symbol,
+                LAG(created_at) OVER (PARTITION BY symbol ORDER BY created_at) AS previous_at,
+                created_at
+            FROM ticker_data
+        ),
+        deltas AS (
+            SELECT symbol, previous_at, created_at,
+                EXTRACT(EPOCH FROM created_at - previous_at) AS delta_secs
+            FROM spacing
+            WHERE previous_at IS NOT NULL
+        ),
+        cadence AS (
+            SELECT symbol, percentile_cont(0.5) WITHIN GROUP (ORDER BY delta_secs) AS median_secs
+            FROM deltas
+            GROUP BY symbol
+        )
+        INSERT INTO data_gaps (symbol, gap_start, gap_end)
+        SELECT d.symbol, d.previous_at, d.created_at
+        FROM deltas d
+        JOIN cadence c ON c.symbol = d.symbol
+        WHERE d.delta_secs > GREATEST($1, c.median_secs * $2)
+        ON CONFLICT (symbol, gap_start) DO NOTHING
+        "#,
+    )
+    .bind(min_gap_secs)
+    .bind(cadence_multiplier)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn get_data_gaps(
+    pool: &PgPool,
+    symbols: &[String],
+    since_ms: i64,
+) -> Result<Vec<DataGap>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT symbol,
+            CAST(EXTRACT(EPOCH FROM gap_start) * 1000 AS BIGINT) as gap_start,
+            CAST(EXTRACT(EPOCH FROM gap_end) * 1000 AS BIGINT) as gap_end
+        FROM data_gaps
+        WHERE symbol = ANY($1) AND gap_end >= to_timestamp($2::double precision / 1000)
+        ORDER BY symbol ASC, gap_start ASC
+        "#,
+    )
+    .bind(symbols)
+    .bind(since_ms)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        Ok(DataGap {
+            symbol: row.try_get("symbol")?,
+            start: row.try_get("gap_start")?,
+            end: row.try_get("gap_end")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// One closed kline from a live kline stream
+pub async fn save_kline(pool: &PgPool, symbol: &str, interval: &str, kline: &Kline) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO klines
+            (symbol, interval, open_time, open_price, high_price, low_price, close_price, volume, quote_volume)
+        VALUES ($1, $2, to_timestamp($3::double precision / 1000), $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (symbol, interval, open_time) DO NOTHING
+        "#,
+    )
+    .bind(symbol)
+    .bind(interval)
+    .bind(kline.open_time)
+    .bind(kline.open)
+    .bind(kline.high)
+    .bind(kline.low)
+    .bind(kline.close)
+    .bind(kline.volume)
+    .bind(kline.quote_volume)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Bulk load klines with COPY into a staging table, then merge so re-running an overlapping
+// backfill skips rows that are already stored
+pub async fn copy_klines(
+    pool: &PgPool,
+    symbol: &str,
+    interval: &str,
+    klines: &[Kline],
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        CREATE TEMP TABLE klines_staging (
+            open_time BIGINT,
+            open_price DOUBLE PRECISION,
+            high_price DOUBLE PRECISION,
+            low_price DOUBLE PRECISION,
+            close_price DOUBLE PRECISION,
+            volume DOUBLE PRECISION,
+            quote_volume DOUBLE PRECISION
+        ) ON COMMIT DROP
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let mut data = String::with_capacity(klines.len() * 96);
+    for k in klines {
+        data.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            k.open_time, k.open, k.high, k.low, k.close, k.volume, k.quote_volume
+        ));
+    }
+    let mut copy = tx.copy_in_raw("COPY klines_staging FROM STDIN WITH (FORMAT text)").await?;
+    copy.send(data.into_bytes()).await?;
+    copy.finish().await?;
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO klines
+            (symbol, interval, open_time, open_price, high_price, low_price, close_price, volume, quote_volume)
+        SELECT $1, $2, to_timestamp(open_time::double precision / 1000),
+            open_price, high_price, low_price, close_price, volume, quote_volume
+        FROM klines_staging
+        ON CONFLICT (symbol, interval, open_time) DO NOTHING
+        "#,
+    )
+    .bind(symbol)
+    .bind(interval)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+// Due time of the job's last completed run, None if it never ran
+pub async fn get_job_last_run(pool: &PgPool, job: &str) -> Result<Option<i64>, sqlx::Error> {
+    let row: Option<(i64,)> = sqlx::query_as(
+        "SELECT CAST(EXTRACT(EPOCH FROM last_run_at) * 1000 AS BIGINT) FROM job_runs WHERE job = $1",
+    )
+    .bind(job)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|(last_run_at,)| last_run_at))
+}
+
+pub async fn record_job_run(pool: &PgPool, job: &str, due_at: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO job_runs (job, last_run_at)
+        VALUES ($1, to_timestamp($2::double precision / 1000))
+        ON CONFLICT (job) DO UPDATE SET last_run_at = GREATEST(job_runs.last_run_at, EXCLUDED.last_run_at)
+        "#,
+    )
+    .bind(job)
+    .bind(due_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Stores the accounts' values under the day `due_at` falls on, replacing that day's earlier snapshot
+pub async fn save_equity_snapshots(pool: &PgPool, due_at: i64, reports: &[PortfolioReport]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for report in reports {
+        sqlx::query(
+            r#"
+            INSERT INTO equity_snapshots (account_id, day, cash, equity, recorded_at)
+            VALUES ($1, (to_timestamp($2::double precision / 1000) AT TIME ZONE 'UTC')::date, $3, $4, NOW())
+            ON CONFLICT (account_id, day) DO UPDATE SET cash = EXCLUDED.cash, equity = EXCLUDED.equity,
+                recorded_at = EXCLUDED.recorded_at
+            "#,
+        )
+        .bind(&report.account_id)
+        .bind(due_at)
+        .bind(report.cash)
+        .bind(report.equity)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// Drops order history older than `before`: state transitions, and filled or cancelled standalone
+// orders. Grouped orders stay while their group does. Returns the rows deleted.
+pub async fn prune_order_history(pool: &PgPool, before: i64) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let transitions = sqlx::query("DELETE FROM order_transitions WHERE recorded_at < to_timestamp($1::double precision / 1000)")
+        .bind(before)
+        .execute(&mut *tx)
+        .await?;
+    let orders = sqlx::query(
+        r#"
+        DELETE FROM orders
+        WHERE updated_at < to_timestamp($1::double precision / 1000)
+            AND status IN ('filled', 'cancelled')
+            AND body->>'group_id' IS NULL
+        "#,
+    )
+    .bind(before)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(transitions.rows_affected() + orders.rows_affected())
+}
+
+// Queues a job, None when one with the same dedupe key is queued, running or dead
+pub async fn enqueue_job(
+    pool: &PgPool,
+    kind: &str,
+    dedupe_key: Option<&str>,
+    payload: &serde_json::Value,
+    max_attempts: i32,
+) -> Result<Option<i64>, sqlx::Error> {
+    let row: Option<(i64,)> = sqlx::query_as(
+        r#"
+        INSERT INTO jobs (kind, dedupe_key, payload, max_attempts)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (dedupe_key) WHERE status IN ('queued', 'running', 'dead') DO NOTHING
+        RETURNING id
+        "#,
+    )
+    .bind(kind)
+    .bind(dedupe_key)
+    .bind(payload)
+    .bind(max_attempts)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|(id,)| id))
+}
+
+// Takes the oldest due job for this worker, returning its id, payload and the attempt it is on.
+// Concurrent workers skip each other's rows instead of waiting on them.
+pub async fn claim_job(pool: &PgPool) -> Result<Option<(i64, serde_json::Value, i32, i32)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        UPDATE jobs SET status = 'running', attempts = attempts + 1, started_at = NOW(), finished_at = NULL
+        WHERE id = (
+            SELECT id FROM jobs
+            WHERE status = 'queued' AND run_after <= NOW()
+            ORDER BY run_after, id
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, payload, attempts, max_attempts
+        "#,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn complete_job(pool: &PgPool, job_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE jobs SET status = 'succeeded', last_error = NULL, finished_at = NOW() WHERE id = $1")
+        .bind(job_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// Queues the job again after `retry_in_secs`, or marks it dead when there is no retry left
+pub async fn fail_job(pool: &PgPool, job_id: i64, error: &str, retry_in_secs: Option<i64>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE jobs SET
+            status = CASE WHEN $3::bigint IS NULL THEN 'dead' ELSE 'queued' END,
+            last_error = $2,
+            run_after = NOW() + make_interval(secs => COALESCE($3, 0)),
+            finished_at = CASE WHEN $3::bigint IS NULL THEN NOW() END
+        WHERE id = $1
+        "#,
+    )
+    .bind(job_id)
+    .bind(error)
+    .bind(retry_in_secs)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Jobs a stopped server was running when it went down, put back in the queue. The attempt they
+// were on still counts.
+pub async fn requeue_running_jobs(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE jobs SET
+            status = CASE WHEN attempts >= max_attempts THEN 'dead' ELSE 'queued' END,
+            last_error = COALESCE(last_error, 'interrupted by a server restart'),
+            finished_at = CASE WHEN attempts >= max_attempts THEN NOW() END
+        WHERE status = 'running'
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// Gives a dead job its attempts back, false if there is no dead job with that id
+pub async fn retry_dead_job(pool: &PgPool, job_id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE jobs SET status = 'queued', attempts = 0, run_after = NOW(), finished_at = NULL
+        WHERE id = $1 AND status = 'dead'
+        "#,
+    )
+    .bind(job_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_jobs(pool: &PgPool, status: Option<JobStatus>, limit: i64) -> Result<Vec<JobRecord>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT id, kind, payload, status, attempts, max_attempts, last_error,
+            CAST(EXTRACT(EPOCH FROM run_after) * 1000 AS BIGINT) as run_after,
+            CAST(EXTRACT(EPOCH FROM created_at) * 1000 AS BIGINT) as created_at,
+            CAST(EXTRACT(EPOCH FROM started_at) * 1000 AS BIGINT) as started_at,
+            CAST(EXTRACT(EPOCH FROM finished_at) * 1000 AS BIGINT) as finished_at
+        FROM jobs
+        WHERE ($1::text IS NULL OR status = $1)
+        ORDER BY id DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(status.map(JobStatus::name))
+    .bind(limit)
+    .try_map(|row: sqlx::postgres::PgRow| {
+        let status: String = row.try_get("status")?;
+        Ok(JobRecord {
+            id: row.try_get("id")?,
+            kind: row.try_get("kind")?,
+            payload: row.try_get("payload")?,
+            status: serde_json::from_value(serde_json::Value::String(status))
+                .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+            attempts: row.try_get("attempts")?,
+            max_attempts: row.try_get("max_attempts")?,
+            last_error: row.try_get("last_error")?,
+            run_after: row.try_get("run_after")?,
+            created_at: row.try_get("created_at")?,
+            started_at: row.try_get("started_at")?,
+            finished_at: row.try_get("finished_at")?,
+        })
+    })
+    .fetch_all(pool)
+    .await
+}
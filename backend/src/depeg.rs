@@ -0,0 +1,133 @@
+use crate::alerts;
+use crate::config::env_or;
+use crate::engine::now_millis;
+use crate::models::{Alert, DepegAlertSettings, DepegStatus, TickerUpdate};
+use crate::state::AppState;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+// Alerts to deliver, each with the webhook it also goes to
+type Triggered = Vec<(Alert, Option<String>)>;
+
+#[derive(Debug, Default)]
+struct AccountDepegAlert {
+    settings: Option<DepegAlertSettings>,
+    triggered: HashSet<String>, // Pairs past the threshold, re-armed once they are back within it
+}
+
+// Deviation from 1.0 of the stablecoin pairs in DEPEG_PAIRS, a pair counts as depegged on the
+// channel beyond DEPEG_THRESHOLD (0.005 for 0.5%). Accounts set their own alert thresholds.
+pub struct DepegMonitor {
+    pairs: Vec<String>,
+    threshold: f64,
+    latest: HashMap<String, DepegStatus>,
+    accounts: HashMap<String, AccountDepegAlert>,
+}
+
+impl DepegMonitor {
+    pub fn from_env() -> Self {
+        let pairs = env_or("DEPEG_PAIRS", "USDCUSDT,FDUSDUSDT,TUSDUSDT,USDPUSDT,DAIUSDT".to_string())
+            .split(',')
+            .map(|pair| pair.trim().to_uppercase())
+            .filter(|pair| !pair.is_empty())
+            .collect();
+        DepegMonitor {
+            pairs,
+            threshold: env_or("DEPEG_THRESHOLD", 0.005),
+            latest: HashMap::new(),
+            accounts: HashMap::new(),
+        }
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    // Latest status of every tracked pair that has traded, sent when a connection subscribes
+    pub fn statuses(&self) -> Vec<DepegStatus> {
+        self.pairs.iter().filter_map(|pair| self.latest.get(pair).cloned()).collect()
+    }
+
+    pub fn load_alerts(&mut self, settings: Vec<DepegAlertSettings>) {
+        for alert in settings {
+            self.set_alert(alert);
+        }
+    }
+
+    pub fn set_alert(&mut self, settings: DepegAlertSettings) {
+        let account = self.accounts.entry(settings.account_id.clone()).or_default();
+        account.settings = Some(settings);
+        account.triggered.clear();
+    }
+
+    // New status of a tracked pair and the alerts its price just triggered, None for other symbols
+    fn observe(&mut self, update: &TickerUpdate) -> Option<(DepegStatus, Triggered)> {
+        if !self.pairs.contains(&update.symbol) {
+            return None;
+        }
+        let deviation = update.price - 1.0;
+        let status = DepegStatus {
+            symbol: update.symbol.clone(),
+            price: update.price,
+            deviation,
+            depegged: deviation.abs() > self.threshold,
+            event_time: update.event_time,
+        };
+        self.latest.insert(update.symbol.clone(), status.clone());
+
+        let mut triggered = Vec::new();
+        for (account_id, account) in &mut self.accounts {
+            let Some(settings) = &account.settings else {
+                continue;
+            };
+            if deviation.abs() <= settings.threshold {
+                account.triggered.remove(&update.symbol);
+                continue;
+            }
+            if !account.triggered.insert(update.symbol.clone()) {
+                continue;
+            }
+            triggered.push((
+                Alert {
+                    account_id: account_id.clone(),
+                    kind: "depeg".to_string(),
+                    message: format!(
+                        "{} at {} is {:.2}% off its peg, beyond the {:.2}% threshold",
+                        update.symbol,
+                        update.price,
+                        deviation * 100.0,
+                        settings.threshold * 100.0
+                    ),
+                    value: deviation,
+                    threshold: settings.threshold,
+                    created_at: now_millis(),
+                },
+                settings.webhook_url.clone(),
+            ));
+        }
+        Some((status, triggered))
+    }
+}
+
+// Follows the tracked pairs in the live ticker stream, pushing each update to the depeg channel
+// and alerting accounts whose threshold was just crossed
+pub async fn run_depeg_monitor(state: Arc<AppState>) {
+    let mut tickers = state.tickers.subscribe();
+    loop {
+        let update = match tickers.recv().await {
+            Ok(update) => update,
+            // The next tick of a pair carries its current price
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+        let Some((status, triggered)) = state.depeg.lock().await.observe(&update) else {
+            continue;
+        };
+        // No receivers simply means nobody is subscribed
+        let _ = state.depeg_updates.send(status);
+        for (alert, webhook_url) in triggered {
+            alerts::deliver(&state, alert, webhook_url);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::config::env_or;
+use crate::models::{BookLevel, BookSnapshot, MarketTrade, Side};
+use crate::state::AppState;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+const DEFAULT_LEVELS: usize = 20;
+const TRADES_KEPT: usize = 50;
+
+// A partial book depth frame of the futures stream, `{symbol}@depth20@500ms` and the like
+#[derive(Deserialize)]
+struct DepthEvent {
+    s: String, // Symbol
+    #[serde(rename = "E")]
+    event_time: i64,
+    b: Vec<[String; 2]>, // Bids as [price, quantity], best first
+    a: Vec<[String; 2]>, // Asks as [price, quantity], best first
+}
+
+// An aggregate trade frame, `{symbol}@aggTrade`
+#[derive(Deserialize)]
+struct AggTradeEvent {
+    s: String,
+    a: i64, // Aggregate trade id
+    p: String,
+    q: String,
+    #[serde(rename = "T")]
+    trade_time: i64,
+    m: bool, // Buyer was the maker, so the taker sold
+}
+
+struct Book {
+    event_time: i64,
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+}
+
+// Latest exchange book of every symbol the feed follows a depth stream for, and its last trades
+#[derive(Default)]
+pub struct DepthBooks {
+    books: HashMap<String, Book>,
+    trades: HashMap<String, VecDeque<MarketTrade>>,
+}
+
+impl DepthBooks {
+    pub fn record(&mut self, payload: &str) {
+        let event: DepthEvent = match serde_json::from_str(payload) {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Error parsing depth frame: {}", e);
+                return;
+            }
+        };
+        let levels = |levels: Vec<[String; 2]>| -> Vec<(f64, f64)> {
+            levels
+                .iter()
+                .filter_map(|[price, quantity]| Some((price.parse::<f64>().ok()?, quantity.parse::<f64>().ok()?)))
+                .filter(|(_, quantity)| *quantity > 0.0)
+                .collect()
+        };
+        let book = Book {
+            event_time: event.event_time,
+            bids: levels(event.b),
+            asks: levels(event.a),
+        };
+        self.books.insert(event.s, book);
+    }
+
+    pub fn record_trade(&mut self, payload: &str) -> Option<MarketTrade> {
+        let event: AggTradeEvent = match serde_json::from_str(payload) {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Error parsing trade frame: {}", e);
+                return None;
+            }
+        };
+        let trade = MarketTrade {
+            trade_id: event.a,
+            price: event.p.parse().ok()?,
+            quantity: event.q.parse().ok()?,
+            side: if event.m { Side::Sell } else { Side::Buy },
+            time: event.trade_time,
+            symbol: event.s,
+        };
+        let trades = self.trades.entry(trade.symbol.clone()).or_default();
+        trades.push_front(trade.clone());
+        trades.truncate(TRADES_KEPT);
+        Some(trade)
+    }
+
+    // Newest first
+    pub fn trades(&self, symbol: &str) -> Vec<MarketTrade> {
+        self.trades.get(symbol).map(|trades| trades.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    // Best bid and best ask
+    pub fn touch(&self, symbol: &str) -> (Option<f64>, Option<f64>) {
+        match self.books.get(symbol) {
+            Some(book) => (book.bids.first().map(|(price, _)| *price), book.asks.first().map(|(price, _)| *price)),
+            None => (None, None),
+        }
+    }
+
+    // The book in at most `levels` buckets per side (BOOK_LEVELS by default), each `group` price
+    // units wide, or the exchange's own levels without a group. Simulated resting limit orders
+    // are added per bucket next to the exchange quantity.
+    pub fn snapshot(
+        &self,
+        symbol: &str,
+        levels: Option<usize>,
+        group: Option<f64>,
+        resting: &[(Side, f64, f64)],
+    ) -> Result<BookSnapshot, String> {
+        validate_group(group)?;
+        let book = self.books.get(symbol).ok_or_else(|| {
+            format!("No order book for {}, it is only followed while the symbol is streamed", symbol)
+        })?;
+        let levels = levels.unwrap_or_else(|| env_or("BOOK_LEVELS", DEFAULT_LEVELS)).clamp(1, 1000);
+        let side_resting = |side: Side| resting.iter().filter(move |(s, _, _)| *s == side).map(|(_, p, q)| (*p, *q));
+        Ok(BookSnapshot {
+            symbol: symbol.to_string(),
+            event_time: book.event_time,
+            group,
+            bids: aggregate(&book.bids, side_resting(Side::Buy), Side::Buy, group, levels),
+            asks: aggregate(&book.asks, side_resting(Side::Sell), Side::Sell, group, levels),
+        })
+    }
+}
+
+pub fn validate_group(group: Option<f64>) -> Result<(), String> {
+    if group.is_some_and(|group| !(group.is_finite() && group > 0.0)) {
+        return Err("The price group must be a positive number".to_string());
+    }
+    Ok(())
+}
+
+// Current book of a symbol with the simulator's own resting orders in it
+pub async fn book(
+    state: &AppState,
+    symbol: &str,
+    levels: Option<usize>,
+    group: Option<f64>,
+) -> Result<BookSnapshot, String> {
+    let symbol = symbol.trim().to_uppercase();
+    let resting = state.engine.lock().await.resting_limits(&symbol);
+    state.depth.lock().await.snapshot(&symbol, levels, group, &resting)
+}
+
+// Bucket edge nearest the touch. Keys are whole ticks of the group so equal prices always share
+// a bucket, ungrouped prices are kept in hundred-millionths.
+pub fn bucket_key(price: f64, side: Side, group: Option<f64>) -> i64 {
+    match (group, side) {
+        (Some(group), Side::Buy) => (price / group).floor() as i64,
+        (Some(group), Side::Sell) => (price / group).ceil() as i64,
+        (None, _) => (price * 1e8).round() as i64,
+    }
+}
+
+pub fn bucket_price(key: i64, group: Option<f64>) -> f64 {
+    match group {
+        Some(group) => key as f64 * group,
+        None => key as f64 / 1e8,
+    }
+}
+
+fn aggregate(
+    book: &[(f64, f64)],
+    resting: impl Iterator<Item = (f64, f64)>,
+    side: Side,
+    group: Option<f64>,
+    levels: usize,
+) -> Vec<BookLevel> {
+    let mut buckets: BTreeMap<i64, (f64, f64)> = BTreeMap::new();
+    for (price, quantity) in book {
+        buckets.entry(bucket_key(*price, side, group)).or_default().0 += quantity;
+    }
+    // Simulated orders only show within the range the exchange book covers
+    let (Some(first), Some(last)) = (buckets.keys().next().copied(), buckets.keys().next_back().copied()) else {
+        return Vec::new();
+    };
+    for (price, quantity) in resting {
+        let key = bucket_key(price, side, group);
+        if (first..=last).contains(&key) {
+            buckets.entry(key).or_default().1 += quantity;
+        }
+    }
+
+    // Bids run from the highest price down, asks from the lowest up
+    let mut ordered: Vec<(i64, (f64, f64))> = buckets.into_iter().collect();
+    if side == Side::Buy {
+        ordered.reverse();
+    }
+    let mut cumulative = 0.0;
+    ordered
+        .into_iter()
+        .take(levels)
+        .map(|(key, (quantity, simulated_quantity))| {
+            cumulative += quantity;
+            BookLevel {
+                price: bucket_price(key, group),
+                quantity,
+                cumulative,
+                simulated_quantity,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,111 @@
+use crate::alerts;
+use crate::config::env_or;
+use crate::engine::now_millis;
+use crate::models::{Alert, DrawdownAlertSettings};
+use crate::state::AppState;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+#[derive(Debug, Default)]
+struct AccountDrawdown {
+    peak_equity: f64,
+    drawdown: f64, // Fraction of the peak currently lost, 0.0 at a new high
+    alert: Option<DrawdownAlertSettings>,
+    triggered: bool, // Re-armed once the drawdown recovers below the threshold
+}
+
+// Peak equity and drawdown of every account, updated by the monitor task
+#[derive(Default)]
+pub struct DrawdownTracker {
+    accounts: HashMap<String, AccountDrawdown>,
+}
+
+impl DrawdownTracker {
+    pub fn load_alerts(&mut self, settings: Vec<DrawdownAlertSettings>) {
+        for alert in settings {
+            self.set_alert(alert);
+        }
+    }
+
+    pub fn set_alert(&mut self, settings: DrawdownAlertSettings) {
+        let account = self.accounts.entry(settings.account_id.clone()).or_default();
+        account.alert = Some(settings);
+        account.triggered = false;
+    }
+
+    // Start peak tracking over, e.g. after the account was reset, keeping the alert settings
+    pub fn reset(&mut self, account_id: &str) {
+        if let Some(account) = self.accounts.get_mut(account_id) {
+            account.peak_equity = 0.0;
+            account.drawdown = 0.0;
+            account.triggered = false;
+        }
+    }
+
+    // Returns (peak equity, drawdown) for the account if it has been observed
+    pub fn get(&self, account_id: &str) -> Option<(f64, f64)> {
+        self.accounts
+            .get(account_id)
+            .map(|account| (account.peak_equity, account.drawdown))
+    }
+
+    // Record a new equity observation, returning an alert if the threshold was just breached
+    fn observe(&mut self, account_id: &str, equity: f64) -> Option<(Alert, Option<String>)> {
+        let account = self.accounts.entry(account_id.to_string()).or_default();
+        account.peak_equity = account.peak_equity.max(equity);
+        account.drawdown = if account.peak_equity > 0.0 {
+            (1.0 - equity / account.peak_equity).max(0.0)
+        } else {
+            0.0
+        };
+
+        let settings = account.alert.as_ref()?;
+        if account.drawdown < settings.threshold {
+            account.triggered = false;
+            return None;
+        }
+        if account.triggered {
+            return None;
+        }
+        account.triggered = true;
+
+        Some((
+            Alert {
+                account_id: account_id.to_string(),
+                kind: "drawdown".to_string(),
+                message: format!(
+                    "Drawdown of {:.2}% from peak equity {:.2} breached the {:.2}% threshold",
+                    account.drawdown * 100.0,
+                    account.peak_equity,
+                    settings.threshold * 100.0
+                ),
+                value: account.drawdown,
+                threshold: settings.threshold,
+                created_at: now_millis(),
+            },
+            settings.webhook_url.clone(),
+        ))
+    }
+}
+
+// Revalue every account on a short interval so peaks and drawdowns follow the market
+pub async fn run_drawdown_monitor(state: Arc<AppState>) {
+    let mut ticker = state.simulation.interval(Duration::from_secs(env_or("DRAWDOWN_CHECK_SECS", 5)));
+    loop {
+        ticker.tick().await;
+
+        let reports = state.all_portfolio_reports().await;
+        let triggered: Vec<(Alert, Option<String>)> = {
+            let mut tracker = state.drawdowns.lock().await;
+            reports
+                .iter()
+                .filter_map(|report| tracker.observe(&report.account_id, report.equity))
+                .collect()
+        };
+
+        for (alert, webhook_url) in triggered {
+            alerts::deliver(&state, alert, webhook_url);
+        }
+    }
+}
@@ -0,0 +1,924 @@
+use crate::models::{
+    AmendOrderRequest, Fill, GroupStatus, LadderLevel, Liquidity, Order, OrderGroupReport,
+    OrderLevel, OrderRequest, OrderStatus, OrderType, QueueEstimate, Side, TimeInForce,
+};
+use crate::simulation::{Clock, SystemClock};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Tolerance used when comparing summed quantities
+const QUANTITY_EPSILON: f64 = 1e-9;
+// Relative tolerance for deciding that a tick printed exactly at an order's limit price
+const PRICE_EPSILON: f64 = 1e-9;
+
+#[derive(Debug)]
+pub enum OrderError {
+    InvalidOrder(String),
+    GroupNotFound(u64),
+    GroupClosed(u64),
+    OrderNotFound(u64),
+    OrderClosed(u64),
+    VersionConflict { expected: u64, actual: u64 },
+    Unsupported(String),
+    Backend(String), // Failure reported by an external execution venue
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::InvalidOrder(reason) => write!(f, "Invalid order: {}", reason),
+            OrderError::GroupNotFound(id) => write!(f, "Order group {} not found", id),
+            OrderError::GroupClosed(id) => write!(f, "Order group {} is already closed", id),
+            OrderError::OrderNotFound(id) => write!(f, "Order {} not found", id),
+            OrderError::OrderClosed(id) => write!(f, "Order {} is no longer working", id),
+            OrderError::VersionConflict { expected, actual } => write!(
+                f,
+                "Order version mismatch: amendment was based on version {} but the order is at version {}",
+                expected, actual
+            ),
+            OrderError::Unsupported(reason) => write!(f, "Unsupported: {}", reason),
+            OrderError::Backend(reason) => write!(f, "Execution backend error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+#[derive(Debug, Clone)]
+pub struct OrderGroup {
+    pub id: u64,
+    pub account_id: String,
+    pub symbol: String,
+    pub entry_order_id: u64,
+    pub take_profit_order_ids: Vec<u64>,
+    pub cancelled: bool,
+}
+
+// Orders and groups changed since the last `take_changes`, stored together in one transaction.
+// Every batch has a higher revision than the one before, so batches saved out of order can't
+// overwrite newer state.
+#[derive(Debug, Default)]
+pub struct OrderChanges {
+    pub revision: u64,
+    pub orders: Vec<Order>,
+    pub groups: Vec<OrderGroup>,
+    pub closed_accounts: Vec<String>, // Accounts whose orders and groups were all dropped
+}
+
+// What the engine is restored from after a restart
+pub struct StoredOrders {
+    pub orders: Vec<Order>,
+    pub groups: Vec<OrderGroup>,
+    pub revision: u64,
+    pub last_fill_id: u64,
+}
+
+impl OrderChanges {
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty() && self.groups.is_empty() && self.closed_accounts.is_empty()
+    }
+}
+
+// An account's or a symbol's working orders and groups before an operation, put back if it can't
+// be stored
+pub struct AccountSnapshot {
+    orders: Vec<Order>,
+    groups: Vec<OrderGroup>,
+}
+
+// Latest tick seen for a symbol
+struct MarketState {
+    last_price: f64,
+    last_quote_volume: f64,
+    last_traded: f64, // Base volume traded during the last tick, used as a proxy for depth at touch
+}
+
+pub struct MatchingEngine {
+    next_order_id: u64,
+    next_group_id: u64,
+    next_fill_id: u64,
+    orders: BTreeMap<u64, Order>, // Matched in id order, so a replayed feed fills the same way
+    groups: HashMap<u64, OrderGroup>,
+    client_order_ids: HashMap<(String, String), u64>, // (account_id, client_order_id) -> order id
+    markets: HashMap<String, MarketState>,
+    revision: u64,
+    changed_orders: HashSet<u64>,
+    changed_groups: HashSet<u64>,
+    closed_accounts: Vec<String>,
+    clock: Arc<dyn Clock>,
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+impl Default for MatchingEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MatchingEngine {
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    // Orders and fills are stamped with this clock's time
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        MatchingEngine {
+            next_order_id: 1,
+            next_group_id: 1,
+            next_fill_id: 1,
+            orders: BTreeMap::new(),
+            groups: HashMap::new(),
+            client_order_ids: HashMap::new(),
+            markets: HashMap::new(),
+            revision: 0,
+            changed_orders: HashSet::new(),
+            changed_groups: HashSet::new(),
+            closed_accounts: Vec::new(),
+            clock,
+        }
+    }
+
+    // Rebuild the engine from stored orders and groups after a restart. Matching picks up where it
+    // stopped, liquidity and queue estimates included, once the next tick for a symbol arrives.
+    pub fn restore(stored: StoredOrders, clock: Arc<dyn Clock>) -> Self {
+        let mut engine = Self::with_clock(clock);
+        engine.revision = stored.revision;
+        engine.next_fill_id = stored.last_fill_id + 1;
+        for order in stored.orders {
+            engine.next_order_id = engine.next_order_id.max(order.id + 1);
+            if let Some(client_order_id) = &order.client_order_id {
+                engine
+                    .client_order_ids
+                    .insert((order.account_id.clone(), client_order_id.clone()), order.id);
+            }
+            engine.orders.insert(order.id, order);
+        }
+        for group in stored.groups {
+            engine.next_group_id = engine.next_group_id.max(group.id + 1);
+            engine.groups.insert(group.id, group);
+        }
+        engine
+    }
+
+    // Everything to store since the last call
+    pub fn take_changes(&mut self) -> OrderChanges {
+        self.revision += 1;
+        let orders = self
+            .changed_orders
+            .drain()
+            .filter_map(|id| self.orders.get(&id).cloned())
+            .collect();
+        let groups = self
+            .changed_groups
+            .drain()
+            .filter_map(|id| self.groups.get(&id).cloned())
+            .collect();
+        OrderChanges {
+            revision: self.revision,
+            orders,
+            groups,
+            closed_accounts: std::mem::take(&mut self.closed_accounts),
+        }
+    }
+
+    // Finished orders and cancelled groups don't change anymore, so they are left out
+    pub fn snapshot(&self, account_id: &str) -> AccountSnapshot {
+        AccountSnapshot {
+            orders: self
+                .orders
+                .values()
+                .filter(|order| order.account_id == account_id && !order.status.is_final())
+                .cloned()
+                .collect(),
+            groups: self
+                .groups
+                .values()
+                .filter(|group| group.account_id == account_id && !group.cancelled)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    // Like `snapshot`, for the orders and groups a tick in the symbol can match
+    pub fn symbol_snapshot(&self, symbol: &str) -> AccountSnapshot {
+        AccountSnapshot {
+            orders: self
+                .orders
+                .values()
+                .filter(|order| order.symbol == symbol && !order.status.is_final())
+                .cloned()
+                .collect(),
+            groups: self
+                .groups
+                .values()
+                .filter(|group| group.symbol == symbol && !group.cancelled)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    // Undoes `changes` back to the snapshot. Orders and groups the snapshot doesn't know were
+    // created by the operation and are dropped again.
+    pub fn rollback(&mut self, snapshot: AccountSnapshot, changes: &OrderChanges) {
+        let mut orders: HashMap<u64, Order> = snapshot.orders.into_iter().map(|order| (order.id, order)).collect();
+        let mut groups: HashMap<u64, OrderGroup> = snapshot.groups.into_iter().map(|group| (group.id, group)).collect();
+        for changed in &changes.orders {
+            if let Some(order) = orders.remove(&changed.id) {
+                self.orders.insert(order.id, order);
+                continue;
+            }
+            self.remove_order(changed);
+        }
+        for changed in &changes.groups {
+            match groups.remove(&changed.id) {
+                Some(group) => self.groups.insert(group.id, group),
+                None => self.groups.remove(&changed.id),
+            };
+        }
+    }
+
+    // Duplicate submissions with a known client_order_id return the original order untouched
+    pub fn place_order(
+        &mut self,
+        account_id: &str,
+        request: OrderRequest,
+    ) -> Result<Order, OrderError> {
+        if let Some(existing) = self.find_client_order(account_id, request.client_order_id.as_deref()) {
+            return Ok(existing.clone());
+        }
+
+        validate_request(&request)?;
+
+        let order_id = self.insert_order(Order {
+            id: 0,
+            account_id: account_id.to_string(),
+            symbol: request.symbol,
+            side: request.side,
+            order_type: request.order_type,
+            price: request.price,
+            quantity: request.quantity,
+            filled_quantity: 0.0,
+            time_in_force: request.time_in_force,
+            status: OrderStatus::New,
+            group_id: None,
+            client_order_id: request.client_order_id,
+            liquidity: None,
+            queue: None,
+            version: 0,
+            created_at: self.clock.now_millis(),
+        });
+
+        Ok(self.orders[&order_id].clone())
+    }
+
+    // Validates the whole group up front so that either every leg is accepted or none is
+    pub fn place_group(
+        &mut self,
+        account_id: &str,
+        entry: OrderRequest,
+        take_profits: Vec<LadderLevel>,
+    ) -> Result<OrderGroupReport, OrderError> {
+        // A retried group submission is identified by its entry's client_order_id
+        if let Some(existing) = self.find_client_order(account_id, entry.client_order_id.as_deref()) {
+            return match existing.group_id {
+                Some(group_id) => self.group_report(account_id, group_id),
+                None => Err(OrderError::InvalidOrder(
+                    "client_order_id is already used by a standalone order".to_string(),
+                )),
+            };
+        }
+
+        validate_request(&entry)?;
+
+        if take_profits.is_empty() {
+            return Err(OrderError::InvalidOrder(
+                "at least one take-profit level is required".to_string(),
+            ));
+        }
+
+        let ladder_quantity: f64 = take_profits.iter().map(|level| level.quantity).sum();
+        if ladder_quantity > entry.quantity + QUANTITY_EPSILON {
+            return Err(OrderError::InvalidOrder(
+                "take-profit quantities exceed the entry quantity".to_string(),
+            ));
+        }
+
+        for level in &take_profits {
+            if level.quantity <= 0.0 || level.price <= 0.0 {
+                return Err(OrderError::InvalidOrder(
+                    "take-profit levels need a positive price and quantity".to_string(),
+                ));
+            }
+            // A take-profit must sit on the profitable side of a limit entry
+            if let Some(entry_price) = entry.price {
+                let profitable = match entry.side {
+                    Side::Buy => level.price > entry_price,
+                    Side::Sell => level.price < entry_price,
+                };
+                if !profitable {
+                    return Err(OrderError::InvalidOrder(format!(
+                        "take-profit price {} is not beyond the entry price {}",
+                        level.price, entry_price
+                    )));
+                }
+            }
+        }
+
+        let group_id = self.next_group_id;
+        self.next_group_id += 1;
+
+        let entry_order_id = self.insert_order(Order {
+            id: 0,
+            account_id: account_id.to_string(),
+            symbol: entry.symbol.clone(),
+            side: entry.side,
+            order_type: entry.order_type,
+            price: entry.price,
+            quantity: entry.quantity,
+            filled_quantity: 0.0,
+            time_in_force: entry.time_in_force,
+            status: OrderStatus::New,
+            group_id: Some(group_id),
+            client_order_id: entry.client_order_id.clone(),
+            liquidity: None,
+            queue: None,
+            version: 0,
+            created_at: self.clock.now_millis(),
+        });
+
+        let take_profit_order_ids = take_profits
+            .iter()
+            .map(|level| {
+                self.insert_order(Order {
+                    id: 0,
+                    account_id: account_id.to_string(),
+                    symbol: entry.symbol.clone(),
+                    side: entry.side.opposite(),
+                    order_type: OrderType::Limit,
+                    price: Some(level.price),
+                    quantity: level.quantity,
+                    filled_quantity: 0.0,
+                    time_in_force: TimeInForce::Gtc,
+                    status: OrderStatus::Pending,
+                    group_id: Some(group_id),
+                    client_order_id: None,
+                    liquidity: None,
+                    queue: None,
+                    version: 0,
+                    created_at: self.clock.now_millis(),
+                })
+            })
+            .collect();
+
+        self.changed_groups.insert(group_id);
+        self.groups.insert(
+            group_id,
+            OrderGroup {
+                id: group_id,
+                account_id: account_id.to_string(),
+                symbol: entry.symbol,
+                entry_order_id,
+                take_profit_order_ids,
+                cancelled: false,
+            },
+        );
+
+        self.group_report(account_id, group_id)
+    }
+
+    // Places orders that only make sense together, e.g. the legs of a spread. Every leg is validated
+    // before any is placed, so either all of them are working or none is.
+    pub fn place_legs(&mut self, account_id: &str, legs: Vec<OrderRequest>) -> Result<Vec<Order>, OrderError> {
+        let mut client_order_ids = HashSet::new();
+        for client_order_id in legs.iter().filter_map(|leg| leg.client_order_id.as_deref()) {
+            if !client_order_ids.insert(client_order_id) {
+                return Err(OrderError::InvalidOrder(format!(
+                    "client_order_id {} is used by more than one leg",
+                    client_order_id
+                )));
+            }
+        }
+
+        // A retry finds every leg by its client_order_id
+        let existing: Vec<Order> = legs
+            .iter()
+            .filter_map(|leg| self.find_client_order(account_id, leg.client_order_id.as_deref()))
+            .cloned()
+            .collect();
+        if !existing.is_empty() {
+            if existing.len() == legs.len() {
+                return Ok(existing);
+            }
+            return Err(OrderError::InvalidOrder(
+                "client_order_id is already used by another order".to_string(),
+            ));
+        }
+
+        for leg in &legs {
+            validate_request(leg)?;
+        }
+
+        // A leg that still fails takes the legs placed before it back out
+        let mut placed = Vec::with_capacity(legs.len());
+        for leg in legs {
+            match self.place_order(account_id, leg) {
+                Ok(order) => placed.push(order),
+                Err(e) => {
+                    for order in placed {
+                        self.remove_order(&order);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(placed)
+    }
+
+    // Drops an order that was never seen outside the engine
+    fn remove_order(&mut self, order: &Order) {
+        self.orders.remove(&order.id);
+        self.changed_orders.remove(&order.id);
+        if let Some(client_order_id) = &order.client_order_id {
+            self.client_order_ids
+                .remove(&(order.account_id.clone(), client_order_id.clone()));
+        }
+    }
+
+    // Forgets every order and group of an account, returns how many orders were dropped
+    pub fn close_account(&mut self, account_id: &str) -> usize {
+        let before = self.orders.len();
+        self.orders.retain(|_, order| order.account_id != account_id);
+        self.groups.retain(|_, group| group.account_id != account_id);
+        self.client_order_ids.retain(|(account, _), _| account != account_id);
+        self.changed_orders.retain(|id| self.orders.contains_key(id));
+        self.changed_groups.retain(|id| self.groups.contains_key(id));
+        self.closed_accounts.push(account_id.to_string());
+        before - self.orders.len()
+    }
+
+    pub fn cancel_group(
+        &mut self,
+        account_id: &str,
+        group_id: u64,
+    ) -> Result<OrderGroupReport, OrderError> {
+        let report = self.group_report(account_id, group_id)?;
+        if matches!(report.status, GroupStatus::Completed | GroupStatus::Cancelled) {
+            return Err(OrderError::GroupClosed(group_id));
+        }
+
+        let group = self
+            .groups
+            .get_mut(&group_id)
+            .ok_or(OrderError::GroupNotFound(group_id))?;
+        group.cancelled = true;
+        self.changed_groups.insert(group_id);
+
+        let order_ids: Vec<u64> = std::iter::once(group.entry_order_id)
+            .chain(group.take_profit_order_ids.iter().copied())
+            .collect();
+        for order_id in order_ids {
+            if let Some(order) = self.orders.get_mut(&order_id) {
+                if !order.status.is_final() {
+                    order.status = OrderStatus::Cancelled;
+                    order.version += 1;
+                    self.changed_orders.insert(order_id);
+                }
+            }
+        }
+
+        self.group_report(account_id, group_id)
+    }
+
+    pub fn cancel_order(&mut self, account_id: &str, order_id: u64) -> Result<Order, OrderError> {
+        let order = self
+            .orders
+            .get_mut(&order_id)
+            .filter(|order| order.account_id == account_id)
+            .ok_or(OrderError::OrderNotFound(order_id))?;
+
+        if order.status.is_final() {
+            return Err(OrderError::OrderClosed(order_id));
+        }
+        order.status = OrderStatus::Cancelled;
+        order.version += 1;
+        self.changed_orders.insert(order_id);
+
+        Ok(order.clone())
+    }
+
+    pub fn group_report(
+        &self,
+        account_id: &str,
+        group_id: u64,
+    ) -> Result<OrderGroupReport, OrderError> {
+        let group = self
+            .groups
+            .get(&group_id)
+            .filter(|group| group.account_id == account_id)
+            .ok_or(OrderError::GroupNotFound(group_id))?;
+
+        let entry = self.orders[&group.entry_order_id].clone();
+        let take_profits: Vec<Order> = group
+            .take_profit_order_ids
+            .iter()
+            .map(|id| self.orders[id].clone())
+            .collect();
+
+        let status = if group.cancelled || entry.status == OrderStatus::Cancelled {
+            GroupStatus::Cancelled
+        } else if entry.status != OrderStatus::Filled {
+            GroupStatus::Pending
+        } else if take_profits.iter().all(|o| o.status == OrderStatus::Filled) {
+            GroupStatus::Completed
+        } else {
+            GroupStatus::Active
+        };
+
+        Ok(OrderGroupReport {
+            group_id: group.id,
+            account_id: group.account_id.clone(),
+            symbol: group.symbol.clone(),
+            status,
+            entry,
+            take_profits,
+        })
+    }
+
+    pub fn order(&self, account_id: &str, order_id: u64) -> Option<&Order> {
+        self.orders.get(&order_id).filter(|order| order.account_id == account_id)
+    }
+
+    // Working limit orders of the account in a symbol, take-profit legs included while they wait
+    // for their entry to fill
+    pub fn order_levels(&self, account_id: &str, symbol: &str) -> Vec<OrderLevel> {
+        let take_profits: HashSet<u64> = self
+            .groups
+            .values()
+            .filter(|group| group.account_id == account_id && !group.cancelled)
+            .flat_map(|group| group.take_profit_order_ids.iter().copied())
+            .collect();
+        let mut levels: Vec<OrderLevel> = self
+            .orders
+            .values()
+            .filter(|order| order.account_id == account_id && order.symbol == symbol && !order.status.is_final())
+            .filter_map(|order| {
+                Some(OrderLevel {
+                    order_id: order.id,
+                    side: order.side,
+                    price: order.price?,
+                    quantity: order.quantity - order.filled_quantity,
+                    kind: if take_profits.contains(&order.id) { "take_profit" } else { "limit" }.to_string(),
+                })
+            })
+            .collect();
+        levels.sort_by(|a, b| b.price.total_cmp(&a.price));
+        levels
+    }
+
+    // Symbols with working orders, whose prices have to keep arriving
+    pub fn active_symbols(&self) -> HashSet<String> {
+        self.orders.values().filter(|order| !order.status.is_final()).map(|order| order.symbol.clone()).collect()
+    }
+
+    pub fn last_price(&self, symbol: &str) -> Option<f64> {
+        self.markets.get(symbol).map(|market| market.last_price)
+    }
+
+    // Last price and cumulative quote volume seen for the symbol
+    pub fn last_tick(&self, symbol: &str) -> Option<(f64, f64)> {
+        self.markets.get(symbol).map(|market| (market.last_price, market.last_quote_volume))
+    }
+
+    // (account, order id) of every working order in the symbol
+    pub fn open_orders(&self, symbol: &str) -> Vec<(String, u64)> {
+        self.orders
+            .values()
+            .filter(|order| order.symbol == symbol && !order.status.is_final())
+            .map(|order| (order.account_id.clone(), order.id))
+            .collect()
+    }
+
+    // (side, price, remaining quantity) of every resting limit order in the symbol
+    pub fn resting_limits(&self, symbol: &str) -> Vec<(Side, f64, f64)> {
+        self.orders
+            .values()
+            .filter(|order| order.symbol == symbol && order.status.is_open() && order.order_type == OrderType::Limit)
+            .filter_map(|order| Some((order.side, order.price?, order.quantity - order.filled_quantity)))
+            .collect()
+    }
+
+    // Match every open order of the symbol against the latest tick. `quote_volume` is the
+    // exchange's cumulative 24h quote volume, its growth between ticks is the traded volume
+    // used to work through the queue of resting maker orders at a touched price level
+    pub fn on_price(&mut self, symbol: &str, price: f64, quote_volume: f64) -> Vec<Fill> {
+        let market = self.markets.entry(symbol.to_string()).or_insert(MarketState {
+            last_price: price,
+            last_quote_volume: quote_volume,
+            last_traded: 0.0,
+        });
+        // The rolling volume can shrink when old trades leave the window, count that as no volume
+        let traded = if price > 0.0 {
+            (quote_volume - market.last_quote_volume).max(0.0) / price
+        } else {
+            0.0
+        };
+        market.last_price = price;
+        market.last_quote_volume = quote_volume;
+        market.last_traded = traded;
+
+        let mut fills = Vec::new();
+
+        for order in self.orders.values_mut() {
+            if order.symbol != symbol || !order.status.is_open() {
+                continue;
+            }
+
+            let (status, filled_quantity) = (order.status, order.filled_quantity);
+            let remaining = order.quantity - order.filled_quantity;
+            let taker = order.liquidity == Some(Liquidity::Taker);
+            let (fill_price, fill_quantity) = match (order.order_type, order.price) {
+                (OrderType::Market, _) => (price, remaining),
+                (OrderType::Limit, Some(limit)) => {
+                    let crossed = match order.side {
+                        Side::Buy => price < limit,
+                        Side::Sell => price > limit,
+                    };
+                    let touched = (price - limit).abs() <= limit * PRICE_EPSILON;
+
+                    if taker && (crossed || touched) {
+                        // A taker limit order sweeps at the market, never worse than its limit
+                        (price, remaining)
+                    } else if crossed {
+                        // Trading through the level means the whole queue was consumed
+                        (limit, remaining)
+                    } else if touched {
+                        // Only volume beyond the queue ahead reaches this order
+                        let queue = order.queue.get_or_insert(QueueEstimate {
+                            ahead: 0.0,
+                            fill_probability: 0.0,
+                        });
+                        let reached = (traded - queue.ahead).max(0.0);
+                        queue.ahead = (queue.ahead - traded).max(0.0);
+                        (limit, reached.min(remaining))
+                    } else {
+                        (limit, 0.0)
+                    }
+                }
+                (OrderType::Limit, None) => (price, 0.0),
+            };
+
+            let fill_or_kill_short = order.time_in_force == TimeInForce::Fok
+                && fill_quantity + QUANTITY_EPSILON < remaining;
+
+            if fill_quantity > QUANTITY_EPSILON && !fill_or_kill_short {
+                order.filled_quantity += fill_quantity;
+                order.status = if order.filled_quantity + QUANTITY_EPSILON >= order.quantity {
+                    OrderStatus::Filled
+                } else {
+                    OrderStatus::PartiallyFilled
+                };
+                fills.push(Fill {
+                    fill_id: self.next_fill_id,
+                    order_id: order.id,
+                    account_id: order.account_id.clone(),
+                    symbol: order.symbol.clone(),
+                    side: order.side,
+                    price: fill_price,
+                    quantity: fill_quantity,
+                    liquidity: order.liquidity.unwrap_or(Liquidity::Taker),
+                    created_at: self.clock.now_millis(),
+                });
+                self.next_fill_id += 1;
+            }
+
+            // Immediate orders get exactly one chance to trade, whatever is left is cancelled
+            if order.time_in_force != TimeInForce::Gtc && order.status.is_open() {
+                order.status = OrderStatus::Cancelled;
+            }
+
+            if let Some(queue) = order.queue.as_mut() {
+                let remaining = order.quantity - order.filled_quantity;
+                queue.fill_probability = fill_probability(queue.ahead, remaining, traded);
+            }
+
+            // Queue estimates move with every tick, only fills and status changes are stored
+            if order.status != status || order.filled_quantity != filled_quantity {
+                self.changed_orders.insert(order.id);
+            }
+        }
+
+        for fill in &fills {
+            self.release_group(fill.order_id);
+        }
+
+        fills
+    }
+
+    // Release the take-profit ladder of the group whose entry this is, once the entry is filled
+    fn release_group(&mut self, order_id: u64) {
+        let Some(group_id) = self.orders[&order_id].group_id else {
+            return;
+        };
+        let Some(group) = self.groups.get(&group_id) else {
+            return;
+        };
+        let entry_filled = self.orders[&order_id].status == OrderStatus::Filled;
+        if group.entry_order_id != order_id || !entry_filled {
+            return;
+        }
+        for take_profit_id in group.take_profit_order_ids.clone() {
+            if let Some(order) = self.orders.get_mut(&take_profit_id) {
+                if order.status == OrderStatus::Pending {
+                    order.status = OrderStatus::New;
+                    let market = self.markets.get(&order.symbol);
+                    classify_liquidity(order, market);
+                    self.changed_orders.insert(take_profit_id);
+                }
+            }
+        }
+    }
+
+    // Amend a resting order in place. Lowering the quantity keeps the queue priority,
+    // while a price change or a larger quantity sends the order to the back of the queue
+    pub fn amend_order(&mut self, request: AmendOrderRequest) -> Result<Order, OrderError> {
+        let order = self
+            .orders
+            .get(&request.order_id)
+            .filter(|order| order.account_id == request.account_id)
+            .ok_or(OrderError::OrderNotFound(request.order_id))?;
+
+        if order.status.is_final() {
+            return Err(OrderError::OrderClosed(order.id));
+        }
+        if order.version != request.version {
+            return Err(OrderError::VersionConflict {
+                expected: request.version,
+                actual: order.version,
+            });
+        }
+
+        if let Some(price) = request.price {
+            if order.order_type != OrderType::Limit {
+                return Err(OrderError::InvalidOrder(
+                    "only limit orders can have their price amended".to_string(),
+                ));
+            }
+            if price <= 0.0 {
+                return Err(OrderError::InvalidOrder("price must be positive".to_string()));
+            }
+        }
+
+        if let Some(quantity) = request.quantity {
+            if quantity <= 0.0 || quantity < order.filled_quantity {
+                return Err(OrderError::InvalidOrder(
+                    "quantity must be positive and not below the filled quantity".to_string(),
+                ));
+            }
+            self.check_ladder_quantity(order, quantity)?;
+        }
+
+        let order = self.orders.get_mut(&request.order_id).unwrap();
+        let price_changed = request.price.is_some_and(|price| Some(price) != order.price);
+        let quantity_increased = request.quantity.is_some_and(|quantity| quantity > order.quantity);
+
+        if let Some(price) = request.price {
+            order.price = Some(price);
+        }
+        if let Some(quantity) = request.quantity {
+            order.quantity = quantity;
+            if order.filled_quantity > 0.0 && order.filled_quantity + QUANTITY_EPSILON >= quantity {
+                order.status = OrderStatus::Filled;
+            }
+        }
+        if let Some(time_in_force) = request.time_in_force {
+            order.time_in_force = time_in_force;
+        }
+        // Reclassifying starts the queue estimate over at the back of the level
+        if (price_changed || quantity_increased) && order.status.is_open() {
+            let market = self.markets.get(&order.symbol);
+            classify_liquidity(order, market);
+        }
+        order.version += 1;
+        self.changed_orders.insert(order.id);
+        let order = order.clone();
+
+        // Cutting an entry down to its filled quantity completes it like a last fill would
+        if order.status == OrderStatus::Filled {
+            self.release_group(order.id);
+        }
+        Ok(order)
+    }
+
+    // Keep a group's take-profit ladder within its entry quantity when one side is amended
+    fn check_ladder_quantity(&self, order: &Order, new_quantity: f64) -> Result<(), OrderError> {
+        let Some(group) = order.group_id.and_then(|id| self.groups.get(&id)) else {
+            return Ok(());
+        };
+
+        let entry_quantity = if group.entry_order_id == order.id {
+            new_quantity
+        } else {
+            self.orders[&group.entry_order_id].quantity
+        };
+        let ladder_quantity: f64 = group
+            .take_profit_order_ids
+            .iter()
+            .map(|id| if *id == order.id { new_quantity } else { self.orders[id].quantity })
+            .sum();
+
+        if ladder_quantity > entry_quantity + QUANTITY_EPSILON {
+            return Err(OrderError::InvalidOrder(
+                "take-profit quantities would exceed the entry quantity".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn find_client_order(&self, account_id: &str, client_order_id: Option<&str>) -> Option<&Order> {
+        let key = (account_id.to_string(), client_order_id?.to_string());
+        self.client_order_ids
+            .get(&key)
+            .and_then(|id| self.orders.get(id))
+    }
+
+    fn insert_order(&mut self, mut order: Order) -> u64 {
+        let id = self.next_order_id;
+        self.next_order_id += 1;
+        order.id = id;
+        order.version = 1;
+        if order.status.is_open() {
+            let market = self.markets.get(&order.symbol);
+            classify_liquidity(&mut order, market);
+        }
+        if let Some(client_order_id) = &order.client_order_id {
+            self.client_order_ids
+                .insert((order.account_id.clone(), client_order_id.clone()), id);
+        }
+        self.orders.insert(id, order);
+        self.changed_orders.insert(id);
+        id
+    }
+}
+
+// Decide whether an order that just became active takes liquidity or joins the queue,
+// a maker starts behind roughly one tick's worth of traded volume at its level
+fn classify_liquidity(order: &mut Order, market: Option<&MarketState>) {
+    let crosses = match (order.order_type, order.price, market) {
+        (OrderType::Market, _, _) => true,
+        (OrderType::Limit, Some(limit), Some(market)) => match order.side {
+            Side::Buy => market.last_price <= limit,
+            Side::Sell => market.last_price >= limit,
+        },
+        _ => false,
+    };
+
+    if crosses {
+        order.liquidity = Some(Liquidity::Taker);
+        order.queue = None;
+    } else {
+        let traded = market.map(|market| market.last_traded).unwrap_or_default();
+        let remaining = order.quantity - order.filled_quantity;
+        order.liquidity = Some(Liquidity::Maker);
+        order.queue = Some(QueueEstimate {
+            ahead: traded,
+            fill_probability: fill_probability(traded, remaining, traded),
+        });
+    }
+}
+
+// Share of the order a tick with `traded` volume at the level would fill
+fn fill_probability(ahead: f64, remaining: f64, traded: f64) -> f64 {
+    if remaining <= 0.0 {
+        return 1.0;
+    }
+    ((traded - ahead).max(0.0) / remaining).min(1.0)
+}
+
+fn validate_request(request: &OrderRequest) -> Result<(), OrderError> {
+    if request.symbol.is_empty() {
+        return Err(OrderError::InvalidOrder("symbol is required".to_string()));
+    }
+    if request.quantity <= 0.0 {
+        return Err(OrderError::InvalidOrder(
+            "quantity must be positive".to_string(),
+        ));
+    }
+    match (request.order_type, request.price) {
+        (OrderType::Limit, Some(price)) if price > 0.0 => Ok(()),
+        (OrderType::Limit, _) => Err(OrderError::InvalidOrder(
+            "limit orders need a positive price".to_string(),
+        )),
+        (OrderType::Market, None) => Ok(()),
+        (OrderType::Market, Some(_)) => Err(OrderError::InvalidOrder(
+            "market orders cannot carry a price".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,646 @@
+use super::ExecutionBackend;
+use crate::db;
+use crate::engine::{now_millis, OrderChanges, OrderError};
+use crate::ledger::Ledger;
+use crate::models::{
+    AccountEvent, AmendOrderRequest, Fill, LadderLevel, Liquidity, Order, OrderGroupReport, OrderRequest,
+    OrderStatus, OrderType, Side, TimeInForce,
+};
+use crate::portfolio::PortfolioBook;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::Method;
+use serde::Deserialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::{interval, sleep, Duration};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+const RECV_WINDOW_MS: u64 = 5000;
+// Binance expires a listen key after 60 minutes without a keepalive
+pub const LISTEN_KEY_KEEPALIVE: Duration = Duration::from_secs(30 * 60);
+pub const USER_STREAM_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Updates for an order the REST reply hasn't registered yet are held this long, the rest are for
+// orders placed outside this backend
+const EARLY_UPDATE_TTL_MS: i64 = 60_000;
+
+pub type UserStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub struct BinanceEndpoints {
+    pub rest_url: String,
+    pub ws_url: String,
+}
+
+impl BinanceEndpoints {
+    pub fn futures_testnet() -> Self {
+        BinanceEndpoints {
+            rest_url: "https://testnet.binancefuture.com".to_string(),
+            ws_url: "wss://stream.binancefuture.com/ws".to_string(),
+        }
+    }
+
+    // Mainnet endpoints for read-only use such as account mirroring
+    pub fn futures_mainnet_data() -> Self {
+        BinanceEndpoints {
+            rest_url: "https://fapi.binance.com".to_string(),
+            ws_url: "wss://fstream.binance.com/ws".to_string(),
+        }
+    }
+
+    // Real-money order entry, only compiled in with the `live-trading` feature
+    #[cfg(feature = "live-trading")]
+    pub fn futures_live() -> Self {
+        Self::futures_mainnet_data()
+    }
+}
+
+pub struct BinanceCredentials {
+    pub api_key: String,
+    pub api_secret: String,
+}
+
+// Signed REST client for the USDⓈ-M Futures API
+pub struct BinanceClient {
+    http: reqwest::Client,
+    endpoints: BinanceEndpoints,
+    credentials: BinanceCredentials,
+}
+
+impl BinanceClient {
+    pub fn new(endpoints: BinanceEndpoints, credentials: BinanceCredentials) -> Self {
+        BinanceClient {
+            http: reqwest::Client::new(),
+            endpoints,
+            credentials,
+        }
+    }
+
+    // Create a listen key and connect to the account's user-data stream
+    pub async fn open_user_stream(&self) -> Result<UserStream, OrderError> {
+        let listen_key: ListenKey = self
+            .keyed_request(Method::POST, "/fapi/v1/listenKey")
+            .await?;
+        let url = format!("{}/{}", self.endpoints.ws_url, listen_key.listen_key);
+        let (ws_stream, _) = connect_async(url.as_str())
+            .await
+            .map_err(|e| OrderError::Backend(e.to_string()))?;
+        Ok(ws_stream)
+    }
+
+    pub async fn keepalive_user_stream(&self) -> Result<(), OrderError> {
+        self.keyed_request::<serde_json::Value>(Method::PUT, "/fapi/v1/listenKey")
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn signed_request<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        path: &str,
+        params: Vec<(&str, String)>,
+    ) -> Result<T, OrderError> {
+        let mut query: Vec<String> = params
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, urlencode(&value)))
+            .collect();
+        query.push(format!("recvWindow={}", RECV_WINDOW_MS));
+        query.push(format!("timestamp={}", now_millis()));
+        let query = query.join("&");
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.credentials.api_secret.as_bytes())
+            .map_err(|e| OrderError::Backend(e.to_string()))?;
+        mac.update(query.as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+
+        let url = format!(
+            "{}{}?{}&signature={}",
+            self.endpoints.rest_url, path, query, signature
+        );
+        self.send(method, &url).await
+    }
+
+    async fn keyed_request<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        path: &str,
+    ) -> Result<T, OrderError> {
+        let url = format!("{}{}", self.endpoints.rest_url, path);
+        self.send(method, &url).await
+    }
+
+    async fn send<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        url: &str,
+    ) -> Result<T, OrderError> {
+        let response = self
+            .http
+            .request(method, url)
+            .header("X-MBX-APIKEY", &self.credentials.api_key)
+            .send()
+            .await
+            .map_err(|e| OrderError::Backend(e.to_string()))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| OrderError::Backend(e.to_string()))?;
+
+        if !status.is_success() {
+            return Err(match serde_json::from_str::<BinanceError>(&body) {
+                Ok(error) => OrderError::Backend(format!("Binance error {}: {}", error.code, error.msg)),
+                Err(_) => OrderError::Backend(format!("Binance HTTP {}: {}", status, body)),
+            });
+        }
+
+        serde_json::from_str(&body).map_err(|e| OrderError::Backend(e.to_string()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BinanceError {
+    code: i64,
+    msg: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceOrder {
+    order_id: u64,
+    symbol: String,
+    status: String,
+    client_order_id: String,
+    price: String,
+    orig_qty: String,
+    executed_qty: String,
+    time_in_force: String,
+    #[serde(rename = "type")]
+    order_type: String,
+    side: String,
+    update_time: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListenKey {
+    listen_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserDataEvent {
+    #[serde(rename = "e")]
+    event_type: String,
+    #[serde(rename = "o")]
+    order: Option<OrderTradeUpdate>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OrderTradeUpdate {
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "S")]
+    side: String,
+    #[serde(rename = "x")]
+    execution_type: String,
+    #[serde(rename = "X")]
+    status: String,
+    #[serde(rename = "i")]
+    order_id: u64,
+    #[serde(rename = "l")]
+    last_filled_quantity: String,
+    #[serde(rename = "L")]
+    last_filled_price: String,
+    #[serde(rename = "z")]
+    cumulative_filled_quantity: String,
+    #[serde(rename = "m")]
+    is_maker: bool,
+    #[serde(rename = "T")]
+    trade_time: i64,
+}
+
+// Orders placed through this backend, cancels and amendments need their symbol. A market order's
+// trades can stream in before its POST returns, they wait in `early` until the order is tracked.
+#[derive(Default)]
+struct VenueOrders {
+    orders: HashMap<u64, Order>,
+    early: HashMap<u64, Vec<(i64, OrderTradeUpdate)>>, // order id -> (received at, update)
+}
+
+// Settles venue fills like the matching engine's: into the account's portfolio and event log,
+// then out on the fill stream
+pub struct VenueSettlement {
+    pub pool: PgPool,
+    pub portfolios: Arc<Mutex<PortfolioBook>>,
+    pub ledger: Arc<Ledger>,
+    pub fills: broadcast::Sender<Fill>,
+}
+
+impl VenueSettlement {
+    async fn settle(&self, fill: Fill) {
+        let mut events = Vec::new();
+        if let Some(fee) = self.portfolios.lock().await.apply_fill(&fill) {
+            events.push((fill.account_id.clone(), AccountEvent::Filled { fill: fill.clone() }));
+            if fee > 0.0 {
+                events.push((fill.account_id.clone(), AccountEvent::FundsDebited { amount: fee }));
+            }
+        }
+        // The trade happened on the venue either way, so the portfolio keeps it
+        if let Err(e) = self.ledger.commit(&self.pool, &OrderChanges::default(), &[], events).await {
+            eprintln!("Error recording the fill of venue order {}: {:?}", fill.order_id, e);
+        }
+        let _ = self.fills.send(fill);
+    }
+}
+
+// Executes orders on a Binance Futures account, fills arrive through the user-data stream
+pub struct BinanceFuturesBackend {
+    name: &'static str,
+    client: Arc<BinanceClient>,
+    orders: Arc<Mutex<VenueOrders>>,
+    settlement: Arc<VenueSettlement>,
+    // Binance doesn't know our accounts, so each order is stored with its owner to survive restarts
+    pool: PgPool,
+}
+
+impl BinanceFuturesBackend {
+    pub fn new(
+        name: &'static str,
+        endpoints: BinanceEndpoints,
+        credentials: BinanceCredentials,
+        settlement: VenueSettlement,
+    ) -> Self {
+        let backend = BinanceFuturesBackend {
+            name,
+            client: Arc::new(BinanceClient::new(endpoints, credentials)),
+            orders: Arc::new(Mutex::new(VenueOrders::default())),
+            pool: settlement.pool.clone(),
+            settlement: Arc::new(settlement),
+        };
+
+        let client = Arc::clone(&backend.client);
+        let orders = Arc::clone(&backend.orders);
+        let settlement = Arc::clone(&backend.settlement);
+        tokio::spawn(async move {
+            restore_orders(&client, &orders, &settlement.pool, name).await;
+            loop {
+                if let Err(e) = run_user_stream(&client, &orders, name, &settlement).await {
+                    eprintln!("Binance user data stream error: {}", e);
+                }
+                sleep(USER_STREAM_RECONNECT_DELAY).await;
+            }
+        });
+
+        backend
+    }
+
+    async fn tracked_order(&self, account_id: &str, order_id: u64) -> Result<Order, OrderError> {
+        self.orders
+            .lock()
+            .await
+            .orders
+            .get(&order_id)
+            .filter(|order| order.account_id == account_id)
+            .cloned()
+            .ok_or(OrderError::OrderNotFound(order_id))
+    }
+
+    // Registers the order as the venue answered, then applies the updates that streamed in first.
+    // A reply never takes back fills the stream already reported.
+    async fn track(&self, account_id: &str, placed: BinanceOrder) -> Order {
+        let (order, fills) = {
+            let mut orders = self.orders.lock().await;
+            let current = orders.orders.get(&placed.order_id).cloned();
+            let mut order = to_order(account_id, placed, current.as_ref().map_or(1, |o| o.version + 1));
+            // Binance reports when the order last changed, it was placed when first tracked
+            order.created_at = current.as_ref().map_or(order.created_at, |current| current.created_at);
+            if let Some(current) = current.filter(|current| current.filled_quantity > order.filled_quantity) {
+                order.status = current.status;
+                order.filled_quantity = current.filled_quantity;
+            }
+            let order_id = order.id;
+            orders.orders.insert(order_id, order);
+            let early = orders.early.remove(&order_id).unwrap_or_default();
+            let fills: Vec<Fill> = early
+                .into_iter()
+                .filter_map(|(_, update)| apply_update(&mut orders, update))
+                .filter_map(|(_, fill)| fill)
+                .collect();
+            (orders.orders[&order_id].clone(), fills)
+        };
+        save_order(&self.pool, self.name, &order).await;
+        for fill in fills {
+            self.settlement.settle(fill).await;
+        }
+        order
+    }
+
+    fn unsupported(&self, feature: &str) -> OrderError {
+        OrderError::Unsupported(format!("{} is not supported by the {} backend", feature, self.name))
+    }
+}
+
+#[async_trait]
+impl ExecutionBackend for BinanceFuturesBackend {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn place_order(
+        &self,
+        account_id: &str,
+        request: OrderRequest,
+    ) -> Result<Order, OrderError> {
+        let mut params = vec![
+            ("symbol", request.symbol.clone()),
+            ("side", side_param(request.side).to_string()),
+            ("quantity", request.quantity.to_string()),
+        ];
+        match (request.order_type, request.price) {
+            (OrderType::Limit, Some(price)) => {
+                params.push(("type", "LIMIT".to_string()));
+                params.push(("price", price.to_string()));
+                params.push(("timeInForce", tif_param(request.time_in_force).to_string()));
+            }
+            (OrderType::Limit, None) => {
+                return Err(OrderError::InvalidOrder(
+                    "limit orders need a positive price".to_string(),
+                ))
+            }
+            (OrderType::Market, _) => params.push(("type", "MARKET".to_string())),
+        }
+        if let Some(client_order_id) = &request.client_order_id {
+            params.push(("newClientOrderId", client_order_id.clone()));
+        }
+
+        let placed: BinanceOrder = self
+            .client
+            .signed_request(Method::POST, "/fapi/v1/order", params)
+            .await?;
+        Ok(self.track(account_id, placed).await)
+    }
+
+    async fn cancel_order(&self, account_id: &str, order_id: u64) -> Result<Order, OrderError> {
+        let order = self.tracked_order(account_id, order_id).await?;
+        let cancelled: BinanceOrder = self
+            .client
+            .signed_request(
+                Method::DELETE,
+                "/fapi/v1/order",
+                vec![("symbol", order.symbol), ("orderId", order_id.to_string())],
+            )
+            .await?;
+        Ok(self.track(account_id, cancelled).await)
+    }
+
+    async fn amend_order(&self, request: AmendOrderRequest) -> Result<Order, OrderError> {
+        let order = self
+            .tracked_order(&request.account_id, request.order_id)
+            .await?;
+        if order.version != request.version {
+            return Err(OrderError::VersionConflict {
+                expected: request.version,
+                actual: order.version,
+            });
+        }
+        if request.time_in_force.is_some() {
+            return Err(self.unsupported("amending the time in force"));
+        }
+
+        // Binance requires both price and quantity on every modification
+        let price = request.price.or(order.price).ok_or_else(|| {
+            OrderError::InvalidOrder("only limit orders can be amended".to_string())
+        })?;
+        let quantity = request.quantity.unwrap_or(order.quantity);
+        let amended: BinanceOrder = self
+            .client
+            .signed_request(
+                Method::PUT,
+                "/fapi/v1/order",
+                vec![
+                    ("symbol", order.symbol.clone()),
+                    ("orderId", order.id.to_string()),
+                    ("side", side_param(order.side).to_string()),
+                    ("quantity", quantity.to_string()),
+                    ("price", price.to_string()),
+                ],
+            )
+            .await?;
+        Ok(self.track(&request.account_id, amended).await)
+    }
+
+    async fn order(&self, account_id: &str, order_id: u64) -> Result<Order, OrderError> {
+        self.tracked_order(account_id, order_id).await
+    }
+
+    async fn place_group(
+        &self,
+        _account_id: &str,
+        _entry: OrderRequest,
+        _take_profits: Vec<LadderLevel>,
+    ) -> Result<OrderGroupReport, OrderError> {
+        Err(self.unsupported("order groups"))
+    }
+
+    async fn place_legs(&self, _account_id: &str, _legs: Vec<OrderRequest>) -> Result<Vec<Order>, OrderError> {
+        Err(self.unsupported("spread orders"))
+    }
+
+    async fn cancel_group(
+        &self,
+        _account_id: &str,
+        _group_id: u64,
+    ) -> Result<OrderGroupReport, OrderError> {
+        Err(self.unsupported("order groups"))
+    }
+
+    async fn group_report(
+        &self,
+        _account_id: &str,
+        _group_id: u64,
+    ) -> Result<OrderGroupReport, OrderError> {
+        Err(self.unsupported("order groups"))
+    }
+}
+
+// Stream order updates for the account and turn trades into fills, keeping the listen key alive
+async fn run_user_stream(
+    client: &BinanceClient,
+    orders: &Mutex<VenueOrders>,
+    venue: &str,
+    settlement: &VenueSettlement,
+) -> Result<(), OrderError> {
+    let mut ws_stream = client.open_user_stream().await?;
+    println!("Connected to Binance user data stream");
+
+    let mut keepalive = interval(LISTEN_KEY_KEEPALIVE);
+    keepalive.tick().await;
+
+    loop {
+        tokio::select! {
+            msg = ws_stream.next() => {
+                let Some(msg) = msg else {
+                    return Ok(());
+                };
+                let msg = msg.map_err(|e| OrderError::Backend(e.to_string()))?;
+                let Ok(text) = msg.to_text() else {
+                    continue;
+                };
+                let Ok(event) = serde_json::from_str::<UserDataEvent>(text) else {
+                    continue;
+                };
+                if event.event_type != "ORDER_TRADE_UPDATE" {
+                    continue;
+                }
+                let Some(update) = event.order else {
+                    continue;
+                };
+                let applied = {
+                    let mut orders = orders.lock().await;
+                    if orders.orders.contains_key(&update.order_id) {
+                        apply_update(&mut orders, update)
+                    } else {
+                        hold_early(&mut orders, update);
+                        None
+                    }
+                };
+                if let Some((order, fill)) = applied {
+                    save_order(&settlement.pool, venue, &order).await;
+                    if let Some(fill) = fill {
+                        settlement.settle(fill).await;
+                    }
+                }
+            }
+
+            _ = keepalive.tick() => {
+                if let Err(e) = client.keepalive_user_stream().await {
+                    eprintln!("Error keeping Binance listen key alive: {}", e);
+                }
+            }
+        }
+    }
+}
+
+// Keeps an update for an order that isn't tracked yet, dropping the ones nobody claimed in time
+fn hold_early(orders: &mut VenueOrders, update: OrderTradeUpdate) {
+    let now = now_millis();
+    orders.early.retain(|_, updates| {
+        updates.retain(|(received_at, _)| now - received_at < EARLY_UPDATE_TTL_MS);
+        !updates.is_empty()
+    });
+    orders.early.entry(update.order_id).or_default().push((now, update));
+}
+
+// Returns the updated order, with a fill when the update is a trade
+fn apply_update(orders: &mut VenueOrders, update: OrderTradeUpdate) -> Option<(Order, Option<Fill>)> {
+    let order = orders.orders.get_mut(&update.order_id)?;
+    order.status = parse_status(&update.status);
+    order.filled_quantity = update.cumulative_filled_quantity.parse().unwrap_or(order.filled_quantity);
+
+    if update.execution_type != "TRADE" {
+        return Some((order.clone(), None));
+    }
+
+    let fill = Fill {
+        fill_id: 0,
+        order_id: update.order_id,
+        account_id: order.account_id.clone(),
+        symbol: update.symbol,
+        side: if update.side == "BUY" { Side::Buy } else { Side::Sell },
+        price: update.last_filled_price.parse().unwrap_or_default(),
+        quantity: update.last_filled_quantity.parse().unwrap_or_default(),
+        liquidity: if update.is_maker { Liquidity::Maker } else { Liquidity::Taker },
+        created_at: update.trade_time,
+    };
+    Some((order.clone(), Some(fill)))
+}
+
+// Reload the orders that were open at shutdown and refresh each from Binance, so cancels, amendments
+// and fills after a restart still reach their accounts
+async fn restore_orders(client: &BinanceClient, orders: &Mutex<VenueOrders>, pool: &PgPool, venue: &str) {
+    let stored = match db::load_open_venue_orders(pool, venue).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            eprintln!("Error loading {} orders: {}", venue, e);
+            return;
+        }
+    };
+
+    for order in stored {
+        let params = vec![("symbol", order.symbol.clone()), ("orderId", order.id.to_string())];
+        let order = match client.signed_request::<BinanceOrder>(Method::GET, "/fapi/v1/order", params).await {
+            Ok(current) => to_order(&order.account_id, current, order.version + 1),
+            Err(e) => {
+                eprintln!("Error refreshing {} order {}: {}", venue, order.id, e);
+                order
+            }
+        };
+        save_order(pool, venue, &order).await;
+        orders.lock().await.orders.insert(order.id, order);
+    }
+}
+
+async fn save_order(pool: &PgPool, venue: &str, order: &Order) {
+    if let Err(e) = db::save_venue_order(pool, venue, order).await {
+        eprintln!("Error saving {} order {}: {}", venue, order.id, e);
+    }
+}
+
+fn to_order(account_id: &str, order: BinanceOrder, version: u64) -> Order {
+    let price = order.price.parse::<f64>().ok().filter(|price| *price > 0.0);
+    Order {
+        id: order.order_id,
+        account_id: account_id.to_string(),
+        symbol: order.symbol,
+        side: if order.side == "BUY" { Side::Buy } else { Side::Sell },
+        order_type: if order.order_type == "MARKET" { OrderType::Market } else { OrderType::Limit },
+        price,
+        quantity: order.orig_qty.parse().unwrap_or_default(),
+        filled_quantity: order.executed_qty.parse().unwrap_or_default(),
+        time_in_force: match order.time_in_force.as_str() {
+            "IOC" => TimeInForce::Ioc,
+            "FOK" => TimeInForce::Fok,
+            _ => TimeInForce::Gtc,
+        },
+        status: parse_status(&order.status),
+        group_id: None,
+        client_order_id: Some(order.client_order_id),
+        liquidity: None,
+        queue: None,
+        version,
+        created_at: order.update_time,
+    }
+}
+
+fn parse_status(status: &str) -> OrderStatus {
+    match status {
+        "NEW" => OrderStatus::New,
+        "PARTIALLY_FILLED" => OrderStatus::PartiallyFilled,
+        "FILLED" => OrderStatus::Filled,
+        _ => OrderStatus::Cancelled, // CANCELED, EXPIRED, REJECTED
+    }
+}
+
+fn side_param(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "BUY",
+        Side::Sell => "SELL",
+    }
+}
+
+fn tif_param(time_in_force: TimeInForce) -> &'static str {
+    match time_in_force {
+        TimeInForce::Gtc => "GTC",
+        TimeInForce::Ioc => "IOC",
+        TimeInForce::Fok => "FOK",
+    }
+}
+
+fn urlencode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
@@ -0,0 +1,109 @@
+use super::ExecutionBackend;
+use crate::engine::{MatchingEngine, OrderError};
+use crate::ledger::Ledger;
+use crate::models::{AmendOrderRequest, LadderLevel, Order, OrderGroupReport, OrderRequest};
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Executes orders against the in-process matching engine fed by the Binance ticker stream
+pub struct InternalBackend {
+    engine: Arc<Mutex<MatchingEngine>>,
+    ledger: Arc<Ledger>,
+    pool: PgPool,
+}
+
+impl InternalBackend {
+    pub fn new(engine: Arc<Mutex<MatchingEngine>>, ledger: Arc<Ledger>, pool: PgPool) -> Self {
+        InternalBackend { engine, ledger, pool }
+    }
+
+    // Runs an engine operation on the account's orders and stores the order state it changed
+    // before answering. The engine stays locked until then, so an operation that can't be stored
+    // is undone before any tick could match it, and the request fails.
+    async fn execute<T>(
+        &self,
+        account_id: &str,
+        operation: impl FnOnce(&mut MatchingEngine) -> Result<T, OrderError>,
+    ) -> Result<T, OrderError> {
+        let mut engine = self.engine.lock().await;
+        let snapshot = engine.snapshot(account_id);
+        let result = operation(&mut engine);
+        let changes = engine.take_changes();
+        if changes.is_empty() {
+            return result;
+        }
+        let turn = self.ledger.turn().await;
+        if let Err(e) = turn.commit(&self.pool, &changes, &[], Vec::new()).await {
+            eprintln!("Error saving order changes: {:?}", e);
+            engine.rollback(snapshot, &changes);
+            return Err(OrderError::Backend("The order change couldn't be stored and was undone".to_string()));
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl ExecutionBackend for InternalBackend {
+    fn name(&self) -> &'static str {
+        "internal"
+    }
+
+    async fn place_order(
+        &self,
+        account_id: &str,
+        request: OrderRequest,
+    ) -> Result<Order, OrderError> {
+        self.execute(account_id, |engine| engine.place_order(account_id, request)).await
+    }
+
+    async fn cancel_order(&self, account_id: &str, order_id: u64) -> Result<Order, OrderError> {
+        self.execute(account_id, |engine| engine.cancel_order(account_id, order_id)).await
+    }
+
+    async fn amend_order(&self, request: AmendOrderRequest) -> Result<Order, OrderError> {
+        let account_id = request.account_id.clone();
+        self.execute(&account_id, |engine| engine.amend_order(request)).await
+    }
+
+    async fn order(&self, account_id: &str, order_id: u64) -> Result<Order, OrderError> {
+        self.engine
+            .lock()
+            .await
+            .order(account_id, order_id)
+            .cloned()
+            .ok_or(OrderError::OrderNotFound(order_id))
+    }
+
+    async fn place_group(
+        &self,
+        account_id: &str,
+        entry: OrderRequest,
+        take_profits: Vec<LadderLevel>,
+    ) -> Result<OrderGroupReport, OrderError> {
+        self.execute(account_id, |engine| engine.place_group(account_id, entry, take_profits))
+            .await
+    }
+
+    async fn place_legs(&self, account_id: &str, legs: Vec<OrderRequest>) -> Result<Vec<Order>, OrderError> {
+        self.execute(account_id, |engine| engine.place_legs(account_id, legs)).await
+    }
+
+    async fn cancel_group(
+        &self,
+        account_id: &str,
+        group_id: u64,
+    ) -> Result<OrderGroupReport, OrderError> {
+        self.execute(account_id, |engine| engine.cancel_group(account_id, group_id))
+            .await
+    }
+
+    async fn group_report(
+        &self,
+        account_id: &str,
+        group_id: u64,
+    ) -> Result<OrderGroupReport, OrderError> {
+        self.engine.lock().await.group_report(account_id, group_id)
+    }
+}
@@ -0,0 +1,40 @@
+use crate::engine::OrderError;
+use crate::models::{AmendOrderRequest, LadderLevel, Order, OrderGroupReport, OrderRequest};
+use async_trait::async_trait;
+
+pub mod binance;
+pub mod internal;
+
+// Where client orders are executed. Client messages are handled the same way whichever
+// backend is active, so a strategy can move from the internal simulation to the exchange
+// testnet without changing a single frame it sends.
+#[async_trait]
+pub trait ExecutionBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn place_order(&self, account_id: &str, request: OrderRequest)
+        -> Result<Order, OrderError>;
+
+    async fn cancel_order(&self, account_id: &str, order_id: u64) -> Result<Order, OrderError>;
+
+    async fn amend_order(&self, request: AmendOrderRequest) -> Result<Order, OrderError>;
+
+    // Current state of an order placed through this backend
+    async fn order(&self, account_id: &str, order_id: u64) -> Result<Order, OrderError>;
+
+    async fn place_group(
+        &self,
+        account_id: &str,
+        entry: OrderRequest,
+        take_profits: Vec<LadderLevel>,
+    ) -> Result<OrderGroupReport, OrderError>;
+
+    // Orders accepted together or not at all, in the order given
+    async fn place_legs(&self, account_id: &str, legs: Vec<OrderRequest>) -> Result<Vec<Order>, OrderError>;
+
+    async fn cancel_group(&self, account_id: &str, group_id: u64)
+        -> Result<OrderGroupReport, OrderError>;
+
+    async fn group_report(&self, account_id: &str, group_id: u64)
+        -> Result<OrderGroupReport, OrderError>;
+}
@@ -0,0 +1,120 @@
+use crate::config::env_or;
+use crate::db;
+use crate::models::{Fill, FillExplanation, Liquidity, Side};
+use crate::portfolio::Position;
+use crate::state::AppState;
+
+// How far from the order's placement a recorded price may be to serve as its arrival price
+const ARRIVAL_TOLERANCE_MS: i64 = 60_000;
+
+// Step-by-step breakdown of one fill: what the market was doing when the order arrived, what the
+// fill cost in slippage and fees, and how it changed the position, margin and liquidation price.
+// `fill_time` picks one of several partial fills of the order, the latest is used otherwise.
+pub async fn explain_fill(
+    state: &AppState,
+    account_id: &str,
+    order_id: u64,
+    fill_time: Option<i64>,
+) -> Result<FillExplanation, String> {
+    let fills = db::get_fills_since(&state.pool, account_id, 0)
+        .await
+        .map_err(|e| format!("Error loading fills: {}", e))?;
+    let index = fills
+        .iter()
+        .rposition(|fill| fill.order_id == order_id && fill_time.is_none_or(|time| fill.created_at == time))
+        .ok_or_else(|| format!("No fill of order {} on {}", order_id, account_id))?;
+    let fill = fills[index].clone();
+
+    // Replay the symbol's earlier fills to get the position the fill traded against
+    let mut position = Position::default();
+    for earlier in fills[..index].iter().filter(|earlier| earlier.symbol == fill.symbol) {
+        position.apply(signed_quantity(earlier), earlier.price);
+    }
+    let position_before = position.quantity;
+    let realized_before = position.realized_pnl;
+    position.apply(signed_quantity(&fill), fill.price);
+
+    // Order ids restart with the engine, only trust an order that predates the fill
+    let order = state
+        .engine
+        .lock()
+        .await
+        .order(account_id, order_id)
+        .filter(|order| order.symbol == fill.symbol && order.created_at <= fill.created_at)
+        .cloned();
+    let arrival_price = match &order {
+        Some(order) => db::get_price_at(&state.pool, &fill.symbol, order.created_at, ARRIVAL_TOLERANCE_MS)
+            .await
+            .map_err(|e| format!("Error loading arrival price: {}", e))?
+            .map(|point| point.price),
+        None => None,
+    };
+
+    // Positive slippage means the fill was worse than the arrival price for the order's side
+    let direction = signed_quantity(&fill).signum();
+    let slippage = arrival_price.map(|arrival| (fill.price - arrival) * direction);
+    let notional = fill.quantity * fill.price;
+
+    let rates = state.portfolios.lock().await.fee_rates(account_id);
+    let fee_rate = match fill.liquidity {
+        Liquidity::Maker => rates.maker,
+        Liquidity::Taker => rates.taker,
+    };
+
+    // Without a template leverage cap the account is treated as fully funded
+    let leverage = state
+        .account_templates
+        .lock()
+        .await
+        .template_for(account_id)
+        .and_then(|template| template.max_leverage)
+        .unwrap_or(1.0);
+    let margin_before = position_before.abs() * fill.price / leverage;
+    let margin_after = position.quantity.abs() * fill.price / leverage;
+
+    let maintenance_margin_rate = env_or("MAINTENANCE_MARGIN_RATE", 0.005);
+    let equity = state.portfolio_report(account_id).await.equity;
+
+    Ok(FillExplanation {
+        order_type: order.as_ref().map(|order| order.order_type),
+        limit_price: order.as_ref().and_then(|order| order.price),
+        arrival_price,
+        slippage,
+        slippage_bps: slippage.zip(arrival_price).map(|(slippage, arrival)| slippage / arrival * 10_000.0),
+        slippage_cost: slippage.map(|slippage| slippage * fill.quantity),
+        notional,
+        fee_rate,
+        fee: notional * fee_rate,
+        position_before,
+        position_after: position.quantity,
+        average_price_after: position.average_price,
+        realized_pnl: position.realized_pnl - realized_before,
+        leverage,
+        margin_before,
+        margin_after,
+        margin_change: margin_after - margin_before,
+        maintenance_margin_rate,
+        equity,
+        liquidation_price: liquidation_price(position.quantity, fill.price, equity, maintenance_margin_rate),
+        fill,
+    })
+}
+
+fn signed_quantity(fill: &Fill) -> f64 {
+    match fill.side {
+        Side::Buy => fill.quantity,
+        Side::Sell => -fill.quantity,
+    }
+}
+
+// Price at which equity falls to the maintenance margin of the position, with every other holding
+// unchanged: equity + q * (p - price) = rate * |q| * p. None when flat or when no positive price
+// gets there, e.g. a fully funded long.
+fn liquidation_price(quantity: f64, price: f64, equity: f64, maintenance_margin_rate: f64) -> Option<f64> {
+    let denominator = quantity - maintenance_margin_rate * quantity.abs();
+    if quantity == 0.0 || denominator == 0.0 {
+        return None;
+    }
+    let liquidation = (quantity * price - equity) / denominator;
+    (liquidation > 0.0).then_some(liquidation)
+}
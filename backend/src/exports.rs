@@ -0,0 +1,221 @@
+use crate::candles;
+use crate::config::env_or;
+use crate::data_quality;
+use crate::db;
+use crate::models::{ExportStage, ExportUpdate, StorageKind};
+use crate::scheduler::ScheduledJob;
+use crate::state::AppState;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::time::Duration;
+
+// Candles fetched per query, a month of 1m candles takes nine
+const DEFAULT_CHUNK_CANDLES: i64 = 5000;
+
+// Finished exports are served from EXPORT_DIR as `<token>.csv`
+pub fn export_dir() -> PathBuf {
+    PathBuf::from(env_or("EXPORT_DIR", "exports".to_string()))
+}
+
+// Path of a finished export, None for anything that is not a token we could have issued
+pub fn export_path(token: &str) -> Option<PathBuf> {
+    let issued = token.len() == 64 && token.bytes().all(|b| b.is_ascii_hexdigit());
+    issued.then(|| export_dir().join(format!("{}.csv", token)))
+}
+
+// Where a finished export is kept when object storage is set up
+pub fn object_key(token: &str) -> String {
+    format!("exports/{}.csv", token)
+}
+
+// How long finished exports are kept, EXPORT_RETENTION_HOURS
+pub fn retention() -> Duration {
+    Duration::from_secs(env_or("EXPORT_RETENTION_HOURS", 24u64).max(1) * 3600)
+}
+
+pub fn download_url(token: &str) -> String {
+    format!("/exports/{}", token)
+}
+
+// Writes the symbol's candles over [start_time, end_time) to a CSV file, a chunk at a time, and
+// reports each whole percent done to the connection that asked for it. The file only takes its
+// final name once complete, so the download route never serves a partial export and a retried
+// job starts over cleanly. Returns the size of the file.
+pub async fn export_candles(
+    state: &AppState,
+    connection_id: u64,
+    token: &str,
+    symbol: &str,
+    interval: &str,
+    start_time: i64,
+    end_time: i64,
+) -> Result<u64, String> {
+    let result = write_export(state, connection_id, token, symbol, interval, start_time, end_time).await;
+    let stage = match &result {
+        Ok((rows, _)) => ExportStage::Ready {
+            url: download_url(token),
+            rows: *rows,
+        },
+        Err(message) => ExportStage::Failed {
+            message: message.clone(),
+        },
+    };
+    let _ = state.exports.send(ExportUpdate {
+        connection_id,
+        token: token.to_string(),
+        stage,
+    });
+    result.map(|(_, bytes)| bytes)
+}
+
+async fn write_export(
+    state: &AppState,
+    connection_id: u64,
+    token: &str,
+    symbol: &str,
+    interval: &str,
+    start_time: i64,
+    end_time: i64,
+) -> Result<(u64, u64), String> {
+    let path = export_path(token).ok_or_else(|| "invalid export token".to_string())?;
+    let interval_ms = candles::interval_seconds(interval)
+        .map(|seconds| seconds * 1000)
+        .ok_or_else(|| format!("unsupported candle interval {}", interval))?;
+    let chunk_ms = interval_ms * env_or("EXPORT_CHUNK_CANDLES", DEFAULT_CHUNK_CANDLES).max(1);
+
+    let gaps: Vec<_> = db::get_data_gaps(&state.pool, &[symbol.to_string()], start_time)
+        .await
+        .map_err(|e| format!("loading data gaps: {}", e))?
+        .into_iter()
+        .filter(|gap| gap.start < end_time)
+        .collect();
+
+    fs::create_dir_all(export_dir())
+        .await
+        .map_err(|e| format!("creating the export directory: {}", e))?;
+    let partial = path.with_extension("csv.part");
+    let file = File::create(&partial)
+        .await
+        .map_err(|e| format!("creating {}: {}", partial.display(), e))?;
+    let mut writer = BufWriter::new(file);
+    let write_error = |e: std::io::Error| format!("writing {}: {}", partial.display(), e);
+
+    writer
+        .write_all(b"open_time,open,high,low,close,volume,gap\n")
+        .await
+        .map_err(write_error)?;
+
+    let mut rows = 0u64;
+    let mut reported = 0u8;
+    let mut from = start_time;
+    while from < end_time {
+        let to = (from + chunk_ms).min(end_time);
+        let mut chunk = db::get_bucketed_ticks(&state.pool, symbol, from, to, interval_ms / 1000)
+            .await
+            .map_err(|e| format!("loading candles: {}", e))?;
+        data_quality::mark_gaps(&mut chunk, &gaps, interval_ms);
+
+        let mut lines = String::new();
+        for candle in &chunk {
+            lines.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                candle.open_time, candle.open, candle.high, candle.low, candle.close, candle.volume, candle.gap
+            ));
+        }
+        writer.write_all(lines.as_bytes()).await.map_err(write_error)?;
+        rows += chunk.len() as u64;
+        from = to;
+
+        // 100% is only reported with the download link
+        let percent = ((from - start_time) * 100 / (end_time - start_time)).min(99) as u8;
+        if percent > reported {
+            reported = percent;
+            let _ = state.exports.send(ExportUpdate {
+                connection_id,
+                token: token.to_string(),
+                stage: ExportStage::Progress { percent, rows },
+            });
+        }
+    }
+
+    writer.flush().await.map_err(write_error)?;
+    fs::rename(&partial, &path)
+        .await
+        .map_err(|e| format!("finishing {}: {}", path.display(), e))?;
+    println!("Exported {} {} candles of {} to {}", rows, interval, symbol, path.display());
+    let bytes = fs::metadata(&path)
+        .await
+        .map(|metadata| metadata.len())
+        .map_err(|e| format!("reading {}: {}", path.display(), e))?;
+
+    // The download route redirects to the stored copy, so the local one can go
+    if let Some(store) = &state.object_store {
+        let contents = fs::read(&path).await.map_err(|e| format!("reading {}: {}", path.display(), e))?;
+        store.put(&object_key(token), contents, "text/csv").await?;
+        if let Err(e) = fs::remove_file(&path).await {
+            eprintln!("Error removing uploaded export {}: {}", path.display(), e);
+        }
+    }
+    Ok((rows, bytes))
+}
+
+// Exports, and partial files left by crashed jobs, are deleted EXPORT_RETENTION_HOURS after they
+// were last written, on disk and in object storage
+pub struct ExportCleanupJob;
+
+#[async_trait]
+impl ScheduledJob for ExportCleanupJob {
+    fn name(&self) -> &'static str {
+        "export_cleanup"
+    }
+
+    fn every(&self) -> Duration {
+        Duration::from_secs(3600)
+    }
+
+    async fn run(&self, state: &AppState, due_at: i64) -> Result<(), String> {
+        let retention = retention();
+        let mut deleted = remove_expired_files(retention).await?;
+        if let Some(store) = &state.object_store {
+            deleted += store.expire("exports/", due_at - retention.as_millis() as i64).await?;
+        }
+        // Expired exports stop counting towards their users' storage
+        db::delete_storage_items_before(&state.pool, StorageKind::Export, due_at - retention.as_millis() as i64)
+            .await
+            .map_err(|e| format!("releasing storage of expired exports: {}", e))?;
+        if deleted > 0 {
+            println!("Deleted {} expired exports", deleted);
+        }
+        Ok(())
+    }
+}
+
+async fn remove_expired_files(retention: Duration) -> Result<usize, String> {
+    let mut entries = match fs::read_dir(export_dir()).await {
+        Ok(entries) => entries,
+        // Nothing was exported to disk yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("listing exports: {}", e)),
+    };
+
+    let mut deleted = 0;
+    while let Some(entry) = entries.next_entry().await.map_err(|e| format!("listing exports: {}", e))? {
+        let expired = entry
+            .metadata()
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > retention);
+        if expired {
+            match fs::remove_file(entry.path()).await {
+                Ok(()) => deleted += 1,
+                Err(e) => eprintln!("Error deleting export {}: {}", entry.path().display(), e),
+            }
+        }
+    }
+    Ok(deleted)
+}
@@ -0,0 +1,119 @@
+use crate::db;
+use crate::models::{AssetExposure, ExposureReport, PositionReport};
+use crate::risk::ReturnSeries;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+
+pub const BENCHMARK_SYMBOL: &str = "BTCUSDT";
+const LOOKBACK_DAYS: i64 = 90;
+// Fewer overlapping days than this make a beta estimate too noisy to report
+const MIN_OBSERVATIONS: usize = 20;
+
+// Quote assets recognized when splitting a symbol, stablecoins are checked before crypto quotes
+const QUOTE_ASSETS: &[&str] = &["FDUSD", "USDT", "USDC", "BUSD", "TUSD", "BTC", "ETH", "BNB"];
+
+// Split a symbol such as ETHUSDT into its base and quote asset
+pub fn split_symbol(symbol: &str) -> (&str, &str) {
+    for quote in QUOTE_ASSETS {
+        if let Some(base) = symbol.strip_suffix(quote) {
+            if !base.is_empty() {
+                return (base, quote);
+            }
+        }
+    }
+    (symbol, "")
+}
+
+// Exposure by base asset and direction plus the portfolio's beta to BTCUSDT from daily returns
+pub async fn exposure_report(
+    pool: &PgPool,
+    positions: &[PositionReport],
+    equity: f64,
+) -> Result<ExposureReport, sqlx::Error> {
+    let mut by_asset: BTreeMap<String, AssetExposure> = BTreeMap::new();
+    for position in positions.iter().filter(|p| p.quantity != 0.0) {
+        let (base, _) = split_symbol(&position.symbol);
+        let exposure = by_asset.entry(base.to_string()).or_insert(AssetExposure {
+            asset: base.to_string(),
+            long: 0.0,
+            short: 0.0,
+            net: 0.0,
+        });
+        if position.market_value > 0.0 {
+            exposure.long += position.market_value;
+        } else {
+            exposure.short += -position.market_value;
+        }
+        exposure.net += position.market_value;
+    }
+
+    let long: f64 = by_asset.values().map(|e| e.long).sum();
+    let short: f64 = by_asset.values().map(|e| e.short).sum();
+
+    let mut symbols: Vec<String> = positions
+        .iter()
+        .filter(|p| p.quantity != 0.0)
+        .map(|p| p.symbol.clone())
+        .collect();
+    let beta = if symbols.is_empty() || equity <= 0.0 {
+        None
+    } else {
+        symbols.push(BENCHMARK_SYMBOL.to_string());
+        let returns = db::get_daily_returns(pool, &symbols, LOOKBACK_DAYS).await?;
+        portfolio_beta(positions, &returns, equity)
+    };
+
+    Ok(ExposureReport {
+        long,
+        short,
+        gross: long + short,
+        net: long - short,
+        beta,
+        beta_weighted_exposure: beta.map(|beta| beta * equity),
+        by_asset: by_asset.into_values().collect(),
+    })
+}
+
+// Equity-weighted sum of each position's beta to the benchmark
+fn portfolio_beta(positions: &[PositionReport], returns: &ReturnSeries, equity: f64) -> Option<f64> {
+    let benchmark: HashMap<&str, f64> = returns
+        .get(BENCHMARK_SYMBOL)?
+        .iter()
+        .map(|(day, r)| (day.as_str(), *r))
+        .collect();
+
+    let mut beta = 0.0;
+    for position in positions.iter().filter(|p| p.quantity != 0.0) {
+        let pairs: Vec<(f64, f64)> = returns
+            .get(&position.symbol)?
+            .iter()
+            .filter_map(|(day, r)| benchmark.get(day.as_str()).map(|b| (*r, *b)))
+            .collect();
+        beta += position.market_value / equity * asset_beta(&pairs)?;
+    }
+    Some(beta)
+}
+
+// Covariance of (asset, benchmark) return pairs divided by the benchmark variance
+fn asset_beta(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < MIN_OBSERVATIONS {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_asset = pairs.iter().map(|(a, _)| a).sum::<f64>() / n;
+    let mean_benchmark = pairs.iter().map(|(_, b)| b).sum::<f64>() / n;
+
+    let covariance = pairs
+        .iter()
+        .map(|(a, b)| (a - mean_asset) * (b - mean_benchmark))
+        .sum::<f64>();
+    let variance = pairs
+        .iter()
+        .map(|(_, b)| (b - mean_benchmark).powi(2))
+        .sum::<f64>();
+
+    if variance == 0.0 {
+        return None;
+    }
+    Some(covariance / variance)
+}
@@ -0,0 +1,93 @@
+use crate::models::{FeatureFlag, FeatureFlagSetting};
+use crate::state::AppState;
+use std::collections::{BTreeMap, HashMap};
+
+// Flags the server checks and whether each is on when nothing is stored for it. Experimental
+// subsystems start out off and are switched on per deployment or per user once they are ready.
+const FLAGS: &[(&str, bool)] = &[
+    ("analytics", false),       // SQL over the Parquet dataset
+    ("delisting_close", false), // Closing positions in delisted symbols at the last price
+];
+
+pub fn validate(flag: &str) -> Result<(), String> {
+    if FLAGS.iter().any(|(name, _)| *name == flag) {
+        Ok(())
+    } else {
+        let known: Vec<&str> = FLAGS.iter().map(|(name, _)| *name).collect();
+        Err(format!("Unknown feature flag {}, known flags are {}", flag, known.join(", ")))
+    }
+}
+
+fn default_of(flag: &str) -> bool {
+    FLAGS.iter().find(|(name, _)| *name == flag).is_some_and(|(_, enabled)| *enabled)
+}
+
+// Stored flag settings, a user's own setting wins over the deployment-wide one, which wins over
+// the built-in default
+#[derive(Debug, Default)]
+pub struct FeatureFlags {
+    deployment: HashMap<String, bool>,
+    users: HashMap<(String, String), bool>,
+}
+
+impl FeatureFlags {
+    pub fn load(&mut self, settings: Vec<FeatureFlagSetting>) {
+        for setting in settings {
+            self.set(setting.flag, setting.user_id, Some(setting.enabled));
+        }
+    }
+
+    // None removes the setting, falling back to the deployment-wide one or the default
+    pub fn set(&mut self, flag: String, user_id: Option<String>, enabled: Option<bool>) {
+        match (user_id, enabled) {
+            (Some(user_id), Some(enabled)) => {
+                self.users.insert((flag, user_id), enabled);
+            }
+            (Some(user_id), None) => {
+                self.users.remove(&(flag, user_id));
+            }
+            (None, Some(enabled)) => {
+                self.deployment.insert(flag, enabled);
+            }
+            (None, None) => {
+                self.deployment.remove(&flag);
+            }
+        }
+    }
+
+    pub fn is_enabled(&self, flag: &str, user_id: Option<&str>) -> bool {
+        if let Some(enabled) = user_id.and_then(|user_id| self.users.get(&(flag.to_string(), user_id.to_string()))) {
+            return *enabled;
+        }
+        self.deployment.get(flag).copied().unwrap_or_else(|| default_of(flag))
+    }
+
+    pub fn list(&self) -> Vec<FeatureFlag> {
+        FLAGS
+            .iter()
+            .map(|(name, default)| {
+                let users: BTreeMap<String, bool> = self
+                    .users
+                    .iter()
+                    .filter(|((flag, _), _)| flag == name)
+                    .map(|((_, user_id), enabled)| (user_id.clone(), *enabled))
+                    .collect();
+                FeatureFlag {
+                    flag: name.to_string(),
+                    default: *default,
+                    enabled: self.deployment.get(*name).copied().unwrap_or(*default),
+                    users,
+                }
+            })
+            .collect()
+    }
+}
+
+// Rejects a request to a subsystem switched off for the user
+pub async fn require(state: &AppState, flag: &str, user_id: Option<&str>) -> Result<(), String> {
+    if state.feature_flags.lock().await.is_enabled(flag, user_id) {
+        Ok(())
+    } else {
+        Err(format!("The {} feature is not enabled", flag))
+    }
+}
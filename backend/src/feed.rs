@@ -0,0 +1,578 @@
+use crate::backfill::Kline;
+use crate::config::env_or;
+use crate::db;
+use crate::engine;
+use crate::ingest;
+use crate::models::{AccountEvent, TickerUpdate};
+use crate::resilience::{self, DbError};
+use crate::state::AppState;
+use crate::tick_filter::TickAction;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::Duration;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+// Market-wide ticker stream every price, fill and stored tick comes from
+const TICKER_STREAM: &str = "!miniTicker@arr";
+// Binance futures accept at most 200 streams on one connection
+const DEFAULT_STREAMS_PER_CONNECTION: usize = 200;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const RECONCILE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+// Upstream streams and how they are spread over connections
+pub struct FeedConfig {
+    base_url: String,
+    streams: Vec<String>,        // Always subscribed
+    symbol_streams: Vec<String>, // Per-symbol stream templates, `{symbol}` is the lowercase symbol
+    per_connection: usize,
+    unsubscribe_grace: Duration, // How long a per-symbol stream outlives its last user
+}
+
+impl FeedConfig {
+    // BINANCE_STREAMS adds comma separated streams, like `btcusdt@kline_1m`, to the ticker stream.
+    // BINANCE_SYMBOL_STREAMS, `{symbol}@kline_1m,{symbol}@depth20@500ms,{symbol}@aggTrade` by default,
+    // are only followed while a client streams the symbol or an order in it is working.
+    // BINANCE_FEED_URL points the connections elsewhere, such as the load-test harness.
+    pub fn from_env() -> Self {
+        let mut streams = stream_list(&env_or("BINANCE_STREAMS", String::new()));
+        streams.retain(|stream| stream != TICKER_STREAM);
+        streams.insert(0, TICKER_STREAM.to_string());
+        FeedConfig {
+            base_url: env_or("BINANCE_FEED_URL", "wss://fstream.binance.com".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            streams,
+            symbol_streams: stream_list(&env_or(
+                "BINANCE_SYMBOL_STREAMS",
+                "{symbol}@kline_1m,{symbol}@depth20@500ms,{symbol}@aggTrade".to_string(),
+            )),
+            per_connection: env_or("BINANCE_STREAMS_PER_CONNECTION", DEFAULT_STREAMS_PER_CONNECTION).max(1),
+            unsubscribe_grace: Duration::from_secs(env_or("FEED_UNSUBSCRIBE_GRACE_SECS", 60)),
+        }
+    }
+}
+
+fn stream_list(list: &str) -> Vec<String> {
+    let mut streams: Vec<String> = Vec::new();
+    for stream in list.split(',') {
+        let stream = stream.trim().to_lowercase();
+        if !stream.is_empty() && !streams.contains(&stream) {
+            streams.push(stream);
+        }
+    }
+    streams
+}
+
+// A combined-stream frame, `data` is left unparsed until the stream is known
+#[derive(Deserialize)]
+struct Envelope<'a> {
+    stream: &'a str,
+    #[serde(borrow)]
+    data: &'a RawValue,
+}
+
+// Answer to a SUBSCRIBE or UNSUBSCRIBE request
+#[derive(Deserialize)]
+struct ControlReply {
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct KlineEvent {
+    s: String, // Symbol
+    k: KlineData,
+}
+
+#[derive(Deserialize)]
+struct KlineData {
+    t: i64,    // Open time
+    i: String, // Interval
+    o: String,
+    h: String,
+    l: String,
+    c: String,
+    v: String,
+    q: String,
+    x: bool, // Closed, later updates of the kline won't follow
+}
+
+// Symbols connections stream live, counted over connections. With the symbols of working orders
+// they decide which per-symbol streams the feed subscribes to.
+#[derive(Default)]
+pub struct FeedInterest {
+    watched: std::sync::Mutex<HashMap<String, usize>>,
+    changed: Notify,
+}
+
+impl FeedInterest {
+    // Tracks one connection's symbols until the returned guard is dropped
+    pub fn watcher(&self) -> SymbolWatcher<'_> {
+        SymbolWatcher {
+            interest: self,
+            symbols: HashSet::new(),
+        }
+    }
+
+    fn watched(&self) -> HashSet<String> {
+        self.watched.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+pub struct SymbolWatcher<'a> {
+    interest: &'a FeedInterest,
+    symbols: HashSet<String>,
+}
+
+impl SymbolWatcher<'_> {
+    pub fn set<'s>(&mut self, symbols: impl IntoIterator<Item = &'s String>) {
+        let symbols: HashSet<String> = symbols.into_iter().cloned().collect();
+        if symbols == self.symbols {
+            return;
+        }
+        let mut watched = self.interest.watched.lock().unwrap();
+        for added in symbols.difference(&self.symbols) {
+            *watched.entry(added.clone()).or_default() += 1;
+        }
+        for removed in self.symbols.difference(&symbols) {
+            if let Some(count) = watched.get_mut(removed) {
+                *count -= 1;
+                if *count == 0 {
+                    watched.remove(removed);
+                }
+            }
+        }
+        drop(watched);
+        self.symbols = symbols;
+        self.interest.changed.notify_one();
+    }
+}
+
+impl Drop for SymbolWatcher<'_> {
+    fn drop(&mut self) {
+        self.set(std::iter::empty::<&String>());
+    }
+}
+
+enum ShardCommand {
+    Subscribe(Vec<String>),
+    Unsubscribe(Vec<String>),
+}
+
+// A connection as the supervisor sees it, commands not yet sent included
+struct Shard {
+    commands: mpsc::UnboundedSender<ShardCommand>,
+    streams: HashSet<String>,
+}
+
+// Keeps the wanted streams spread over upstream connections of at most BINANCE_STREAMS_PER_CONNECTION.
+// Streams come and go with live SUBSCRIBE and UNSUBSCRIBE requests, unsubscribing only once a stream
+// went unused for FEED_UNSUBSCRIBE_GRACE_SECS so clients reconnecting or flipping between symbols
+// don't churn them. When a connection drops its streams move to the others with room left, the rest
+// reconnect on a new one.
+struct Supervisor {
+    state: Arc<AppState>,
+    config: FeedConfig,
+    shards: HashMap<usize, Shard>,
+    next_id: usize,
+    wanted: HashSet<String>,
+    unused_since: HashMap<String, Duration>, // Subscribed streams no longer wanted, since when on the timer
+    closed: mpsc::UnboundedSender<(usize, Vec<String>)>,
+}
+
+impl Supervisor {
+    // The fixed streams, and the per-symbol ones of every known symbol that is watched or has
+    // working orders
+    async fn wanted(&self) -> HashSet<String> {
+        let mut symbols = self.state.feed_interest.watched();
+        let engine = self.state.engine.lock().await;
+        symbols.extend(engine.active_symbols());
+        symbols.retain(|symbol| engine.last_price(symbol).is_some());
+        drop(engine);
+
+        let mut wanted: HashSet<String> = self.config.streams.iter().cloned().collect();
+        for symbol in symbols {
+            let symbol = symbol.to_lowercase();
+            for template in &self.config.symbol_streams {
+                wanted.insert(template.replace("{symbol}", &symbol));
+            }
+        }
+        wanted
+    }
+
+    async fn reconcile(&mut self) {
+        self.wanted = self.wanted().await;
+        let assigned: HashSet<&String> = self.shards.values().flat_map(|shard| &shard.streams).collect();
+        let added: Vec<String> = self.wanted.iter().filter(|stream| !assigned.contains(stream)).cloned().collect();
+        self.place(added, Duration::ZERO);
+
+        let now = self.state.simulation.elapsed();
+        self.unused_since.retain(|stream, _| !self.wanted.contains(stream));
+        for stream in self.shards.values().flat_map(|shard| &shard.streams) {
+            if !self.wanted.contains(stream) {
+                self.unused_since.entry(stream.clone()).or_insert(now);
+            }
+        }
+        let grace = self.config.unsubscribe_grace;
+        let expired: HashSet<String> = self
+            .unused_since
+            .iter()
+            .filter(|(_, since)| now.saturating_sub(**since) >= grace)
+            .map(|(stream, _)| stream.clone())
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        self.unused_since.retain(|stream, _| !expired.contains(stream));
+
+        let mut emptied = Vec::new();
+        for (id, shard) in &mut self.shards {
+            let removed: Vec<String> = shard.streams.iter().filter(|stream| expired.contains(*stream)).cloned().collect();
+            if removed.is_empty() {
+                continue;
+            }
+            for stream in &removed {
+                shard.streams.remove(stream);
+            }
+            if shard.streams.is_empty() {
+                emptied.push(*id);
+            } else {
+                let _ = shard.commands.send(ShardCommand::Unsubscribe(removed));
+            }
+        }
+        // Dropping the command channel closes the connection
+        for id in emptied {
+            self.shards.remove(&id);
+        }
+    }
+
+    fn on_closed(&mut self, id: usize, streams: Vec<String>) {
+        // Connections closed on purpose are already gone
+        if self.shards.remove(&id).is_none() {
+            return;
+        }
+        // Unused streams aren't worth a reconnect
+        let (orphans, unused): (Vec<String>, Vec<String>) =
+            streams.into_iter().partition(|stream| self.wanted.contains(stream));
+        for stream in unused {
+            self.unused_since.remove(&stream);
+        }
+        if !orphans.is_empty() {
+            println!("Feed connection {} closed, moving {} streams", id, orphans.len());
+        }
+        self.place(orphans, RECONNECT_DELAY);
+    }
+
+    // Adds streams to connections with room left, then opens new ones after `delay`
+    fn place(&mut self, mut streams: Vec<String>, delay: Duration) {
+        for shard in self.shards.values_mut() {
+            let room = self.config.per_connection.saturating_sub(shard.streams.len()).min(streams.len());
+            if room == 0 {
+                continue;
+            }
+            let moved: Vec<String> = streams.drain(..room).collect();
+            // A connection that closed meanwhile hands them back with its own streams
+            if shard.commands.send(ShardCommand::Subscribe(moved.clone())).is_ok() {
+                shard.streams.extend(moved);
+            } else {
+                streams.extend(moved);
+            }
+        }
+        for chunk in streams.chunks(self.config.per_connection) {
+            self.spawn(chunk.to_vec(), delay);
+        }
+    }
+
+    fn spawn(&mut self, streams: Vec<String>, delay: Duration) {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.shards.insert(
+            id,
+            Shard {
+                commands,
+                streams: streams.iter().cloned().collect(),
+            },
+        );
+        tokio::spawn(run_shard(
+            Arc::clone(&self.state),
+            self.config.base_url.clone(),
+            id,
+            streams,
+            receiver,
+            self.closed.clone(),
+            delay,
+        ));
+    }
+}
+
+pub async fn run_feed(state: Arc<AppState>) {
+    let (closed_tx, mut closed) = mpsc::unbounded_channel();
+    let mut supervisor = Supervisor {
+        state: Arc::clone(&state),
+        config: FeedConfig::from_env(),
+        shards: HashMap::new(),
+        next_id: 0,
+        wanted: HashSet::new(),
+        unused_since: HashMap::new(),
+        closed: closed_tx,
+    };
+    // Working orders don't announce themselves, they are picked up on this interval
+    let mut ticker = state.simulation.interval(Duration::from_secs(env_or("FEED_RECONCILE_SECS", 5).max(1)));
+
+    loop {
+        tokio::select! {
+            Some((id, streams)) = closed.recv() => supervisor.on_closed(id, streams),
+            _ = state.feed_interest.changed.notified() => {
+                // Let a burst of subscription changes settle into one request per connection
+                state.simulation.sleep(RECONCILE_DEBOUNCE).await;
+                supervisor.reconcile().await;
+            }
+            _ = ticker.tick() => supervisor.reconcile().await,
+        }
+    }
+}
+
+// One upstream connection, reporting its streams back to the supervisor once it closes
+async fn run_shard(
+    state: Arc<AppState>,
+    base_url: String,
+    id: usize,
+    mut streams: Vec<String>,
+    mut commands: mpsc::UnboundedReceiver<ShardCommand>,
+    closed: mpsc::UnboundedSender<(usize, Vec<String>)>,
+    delay: Duration,
+) {
+    state.simulation.sleep(delay).await;
+    if let Err(e) = stream_shard(&state, &base_url, id, &mut streams, &mut commands).await {
+        eprintln!("Feed connection {} error: {}", id, e);
+    }
+    commands.close();
+    while let Ok(command) = commands.try_recv() {
+        apply(&mut streams, &command);
+    }
+    let _ = closed.send((id, streams));
+}
+
+fn apply(streams: &mut Vec<String>, command: &ShardCommand) {
+    match command {
+        ShardCommand::Subscribe(added) => streams.extend(added.iter().cloned()),
+        ShardCommand::Unsubscribe(removed) => streams.retain(|stream| !removed.contains(stream)),
+    }
+}
+
+async fn stream_shard(
+    state: &Arc<AppState>,
+    base_url: &str,
+    id: usize,
+    streams: &mut Vec<String>,
+    commands: &mut mpsc::UnboundedReceiver<ShardCommand>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let url = format!("{}/stream?streams={}", base_url, streams.join("/"));
+    let (ws_stream, _) = connect_async(url.as_str()).await?;
+    let (mut write, mut read) = ws_stream.split();
+    println!("Feed connection {} open with {} streams", id, streams.len());
+
+    let mut request_id: u64 = 0;
+    loop {
+        tokio::select! {
+            msg = read.next() => match msg {
+                Some(Ok(Message::Text(text))) => handle_message(state, id, &text).await,
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+            },
+            command = commands.recv() => {
+                // The supervisor let go of this connection
+                let Some(command) = command else {
+                    return Ok(());
+                };
+                apply(streams, &command);
+                let (method, params) = match command {
+                    ShardCommand::Subscribe(added) => ("SUBSCRIBE", added),
+                    ShardCommand::Unsubscribe(removed) => ("UNSUBSCRIBE", removed),
+                };
+                request_id += 1;
+                let request = serde_json::json!({ "method": method, "params": params, "id": request_id });
+                write.send(Message::Text(request.to_string().into())).await?;
+            }
+        }
+    }
+}
+
+// Routes a frame by its stream, acknowledgements of SUBSCRIBE and UNSUBSCRIBE requests are dropped
+async fn handle_message(state: &Arc<AppState>, id: usize, text: &str) {
+    let received_at = engine::now_millis();
+    let Ok(envelope) = serde_json::from_str::<Envelope>(text) else {
+        if let Ok(ControlReply { error: Some(error), .. }) = serde_json::from_str::<ControlReply>(text) {
+            eprintln!("Feed connection {} request failed: {}", id, error);
+        }
+        return;
+    };
+    if envelope.stream == TICKER_STREAM {
+        if let Some(archive) = &state.archive {
+            archive.record(received_at, envelope.data.get());
+        }
+    }
+    // A stalled feed silently drops upstream data, like a frozen exchange stream
+    if state.chaos.lock().await.feed_stalled() {
+        return;
+    }
+    if envelope.stream == TICKER_STREAM {
+        ingest_tickers(state, received_at, envelope.data.get()).await;
+    } else if envelope.stream.contains("@kline_") {
+        record_kline(state, envelope.data.get()).await;
+    } else if envelope.stream.contains("@depth") {
+        state.depth.lock().await.record(envelope.data.get());
+    } else if envelope.stream.ends_with("@aggTrade") {
+        let trade = state.depth.lock().await.record_trade(envelope.data.get());
+        if let Some(trade) = trade {
+            state.tape.record(trade.clone());
+            let _ = state.trades.send(trade);
+        }
+    }
+}
+
+async fn ingest_tickers(state: &Arc<AppState>, received_at: i64, payload: &str) {
+    let frame: Arc<str> = Arc::from(payload);
+    let mut spooled = Vec::new();
+    for (ticker, raw) in ingest::decode_raw_tickers(&frame) {
+        state.ingest_metrics.lock().await.record(ticker.E, received_at);
+
+        // Quarantine exchange glitches before they reach storage or the matching engine
+        let anomaly = state.tick_filter.lock().await.check(&ticker);
+        if let Some(anomaly) = anomaly {
+            if let Err(e) = db::save_quarantined_tick(&state.pool, &ticker, &anomaly).await {
+                eprintln!("Error saving quarantined tick: {:?}", e);
+            }
+            if anomaly.action == TickAction::Rejected {
+                continue;
+            }
+        }
+
+        // Keep the tick on disk while the database is unreachable, it is replayed later
+        let pricing = state.usd_pricing(&ticker.s, ticker.c.parse::<f64>().unwrap_or_default()).await;
+        let saved =
+            resilience::call(&state.db_breaker, || db::save_ticker_data(&state.pool, &ticker, received_at, &pricing)).await;
+        if let Err(e) = saved {
+            if !matches!(e, DbError::CircuitOpen) {
+                eprintln!("Error saving ticker data, spooling to disk: {}", e);
+            }
+            spooled.push((ticker.clone(), received_at, pricing));
+        }
+
+        // Match resting simulated orders against the new price
+        if let Ok(price) = ticker.c.parse::<f64>() {
+            // In deterministic mode the tick's event time is the simulation's time
+            state.simulation.observe(ticker.E);
+            let quote_volume = ticker.q.parse::<f64>().unwrap_or_default();
+            let _ = state.tickers.send(TickerUpdate {
+                symbol: ticker.s.clone(),
+                price,
+                quote_volume,
+                event_time: ticker.E,
+                raw: Some(raw),
+            });
+            let moved = state.key_levels.lock().await.on_price(&ticker.s, price, ticker.E);
+            if let Some(levels) = moved {
+                let _ = state.key_level_updates.send(levels);
+            }
+            match_price(state, &ticker.s, price, quote_volume).await;
+        }
+    }
+
+    // The frame's spooled ticks are synced together, on a blocking thread
+    if !spooled.is_empty() {
+        let spool_state = Arc::clone(state);
+        let written = tokio::task::spawn_blocking(move || spool_state.spool.blocking_lock().push_batch(spooled)).await;
+        match written {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => eprintln!("Error spooling ticker data: {:?}", e),
+            Err(e) => eprintln!("Tick spool writer panicked: {}", e),
+        }
+    }
+}
+
+// Match the symbol's open orders against a price and settle the fills. Matching, settlement and
+// the ledger turn happen under the engine lock, so fills settle and are stored in the order they
+// were made, each exactly once. A batch that can't be stored is undone before the lock goes, the
+// orders stay open in memory as in the database and match again on a later tick.
+pub async fn match_price(state: &Arc<AppState>, symbol: &str, price: f64, quote_volume: f64) {
+    let mut engine = state.engine.lock().await;
+    let snapshot = engine.symbol_snapshot(symbol);
+    let fills = engine.on_price(symbol, price, quote_volume);
+    let changes = engine.take_changes();
+    if changes.is_empty() {
+        return;
+    }
+
+    let mut events = Vec::new();
+    let mut portfolios = state.portfolios.lock().await;
+    let accounts: Vec<String> = fills.iter().map(|fill| fill.account_id.clone()).collect();
+    let saved = portfolios.save(&accounts);
+    for fill in &fills {
+        let Some(fee) = portfolios.apply_fill(fill) else {
+            continue;
+        };
+        events.push((fill.account_id.clone(), AccountEvent::Filled { fill: fill.clone() }));
+        if fee > 0.0 {
+            events.push((fill.account_id.clone(), AccountEvent::FundsDebited { amount: fee }));
+        }
+    }
+    // Fills are stored with the order states they produced and their account events
+    let turn = state.ledger.turn().await;
+    if let Err(e) = turn.commit(&state.pool, &changes, &fills, events).await {
+        eprintln!("Error saving {} fills and order changes, undoing them: {:?}", symbol, e);
+        portfolios.rollback(saved);
+        engine.rollback(snapshot, &changes);
+        return;
+    }
+    drop(portfolios);
+    drop(engine);
+
+    for fill in fills {
+        println!(
+            "Order {} filled ({:?}): {} {} @ {}",
+            fill.order_id, fill.liquidity, fill.quantity, fill.symbol, fill.price
+        );
+        // Deliver the fill after the simulated notification delay, drawn here in fill order
+        let delay = state.latency.fill_notification.sample(&mut *state.simulation.rng());
+        let fill_state = Arc::clone(state);
+        tokio::spawn(async move {
+            fill_state.simulation.sleep(delay).await;
+            let _ = fill_state.fills.send(fill);
+        });
+    }
+}
+
+// Closed klines of kline streams are stored next to the backfilled ones
+async fn record_kline(state: &AppState, payload: &str) {
+    let event = match serde_json::from_str::<KlineEvent>(payload) {
+        Ok(event) => event,
+        Err(e) => {
+            eprintln!("Skipping malformed kline ({}): {}", e, payload);
+            return;
+        }
+    };
+    if !event.k.x {
+        return;
+    }
+    let parse = |value: &str| value.parse::<f64>().unwrap_or_default();
+    let kline = Kline {
+        open_time: event.k.t,
+        open: parse(&event.k.o),
+        high: parse(&event.k.h),
+        low: parse(&event.k.l),
+        close: parse(&event.k.c),
+        volume: parse(&event.k.v),
+        quote_volume: parse(&event.k.q),
+    };
+    if let Err(e) = db::save_kline(&state.pool, &event.s, &event.k.i, &kline).await {
+        eprintln!("Error saving {} {} kline: {:?}", event.s, event.k.i, e);
+    }
+}
@@ -0,0 +1,451 @@
+use crate::auth::Session;
+use crate::config::env_or;
+use crate::engine::now_millis;
+use crate::handlers;
+use crate::models::{ClientMessage, Fill, Order, OrderRequest, OrderStatus, OrderType, ServerMessage, Side, TimeInForce};
+use crate::state::AppState;
+use crate::template::civil_from_days;
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::time::Duration;
+
+const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: u8 = 0x01;
+// A peer that sends this much without completing a message is dropped
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+type Fields = Vec<(u32, String)>;
+
+// Minimal FIX 4.4 order-entry gateway for existing algo bots: Logon (A) with the user token or API
+// key in Password (554), NewOrderSingle (D), OrderCancelRequest (F), Heartbeat (0), TestRequest (1)
+// and Logout (5). Replies and fills go out as ExecutionReport (8) or OrderCancelReject (9).
+// Incoming sequence numbers and checksums are not enforced, and `|` is accepted in place of SOH so
+// sessions can be typed by hand.
+pub async fn serve(state: Arc<AppState>, bind_addr: String) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&bind_addr).await?;
+    println!("FIX gateway started on {}", bind_addr);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            if let Err(e) = FixSession::new(&state, addr).run(stream).await {
+                eprintln!("FIX session error: {:?}", e);
+            }
+        });
+    }
+}
+
+struct FixSession<'a> {
+    state: &'a AppState,
+    session: Session,
+    sender_comp_id: String, // Ours, the peer's TargetCompID
+    target_comp_id: String, // The peer's SenderCompID
+    next_seq_num: u64,
+    exec_id: u64,
+    accounts: HashSet<String>, // Accounts traded in this session, their fills are reported
+}
+
+impl<'a> FixSession<'a> {
+    fn new(state: &'a AppState, addr: std::net::SocketAddr) -> Self {
+        FixSession {
+            state,
+            session: Session {
+                ip: Some(addr.ip().to_string()),
+                connection_id: crate::NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+                ..Session::anonymous()
+            },
+            sender_comp_id: "SIMULATOR".to_string(),
+            target_comp_id: String::new(),
+            next_seq_num: 1,
+            exec_id: 0,
+            accounts: HashSet::new(),
+        }
+    }
+
+    async fn run(mut self, stream: TcpStream) -> std::io::Result<()> {
+        let (mut reader, mut stream) = stream.into_split();
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let mut fills = self.state.fills.subscribe();
+        let mut heartbeat = self.state.simulation.interval(Duration::from_secs(env_or("FIX_HEARTBEAT_SECS", 30)));
+        let mut logged_in = false;
+
+        loop {
+            tokio::select! {
+                read = reader.read(&mut chunk) => {
+                    let read = read?;
+                    if read == 0 {
+                        break;
+                    }
+                    buffer.extend_from_slice(&chunk[..read]);
+                    while let Some(fields) = next_message(&mut buffer) {
+                        let msg_type = field(&fields, 35).unwrap_or_default().to_string();
+                        if msg_type != "A" && !logged_in {
+                            let reply = self.reject(&fields, "Logon required");
+                            stream.write_all(&reply).await?;
+                            continue;
+                        }
+                        let (replies, close) = self.on_message(&msg_type, &fields).await;
+                        logged_in |= msg_type == "A" && self.session.user_id.is_some();
+                        for reply in replies {
+                            stream.write_all(&reply).await?;
+                        }
+                        if close {
+                            return Ok(());
+                        }
+                    }
+                    if buffer.len() > MAX_MESSAGE_BYTES {
+                        eprintln!("FIX peer sent {} bytes without a complete message", buffer.len());
+                        break;
+                    }
+                }
+
+                fill_result = fills.recv(), if logged_in => {
+                    match fill_result {
+                        Ok(fill) if self.accounts.contains(&fill.account_id) => {
+                            let report = self.fill_report(&fill).await;
+                            stream.write_all(&report).await?;
+                        }
+                        Ok(_) => {}
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            eprintln!("FIX session lagged behind, {} fills dropped", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+
+                _ = heartbeat.tick(), if logged_in => {
+                    let message = self.encode("0", Vec::new());
+                    stream.write_all(&message).await?;
+                }
+            }
+        }
+
+        for account_id in &self.accounts {
+            self.state.session_closed(account_id).await;
+        }
+        Ok(())
+    }
+
+    // Replies to one inbound message, and whether the session ends with them
+    async fn on_message(&mut self, msg_type: &str, fields: &Fields) -> (Vec<Vec<u8>>, bool) {
+        match msg_type {
+            "A" => {
+                self.target_comp_id = field(fields, 49).unwrap_or_default().to_string();
+                if let Some(comp_id) = field(fields, 56) {
+                    self.sender_comp_id = comp_id.to_string();
+                }
+                let token = field(fields, 554).unwrap_or_default().to_string();
+                let message = ClientMessage::Authenticate { token };
+                match handlers::handle_client_message(self.state, &self.session, message).await {
+                    ServerMessage::Authenticated {
+                        user_id,
+                        role,
+                        key,
+                        session_id,
+                        tenant_id,
+                        ..
+                    } => {
+                        self.session = Session {
+                            user_id: Some(user_id),
+                            role,
+                            key,
+                            session_id,
+                            tenant_id,
+                            ..self.session.clone()
+                        };
+                        let heartbeat = field(fields, 108).unwrap_or("30").to_string();
+                        (vec![self.encode("A", vec![(98, "0".to_string()), (108, heartbeat)])], false)
+                    }
+                    _ => (vec![self.encode("5", vec![(58, "Logon rejected".to_string())])], true),
+                }
+            }
+            "0" => (Vec::new(), false),
+            "1" => {
+                let test_req_id = field(fields, 112).unwrap_or_default().to_string();
+                (vec![self.encode("0", vec![(112, test_req_id)])], false)
+            }
+            "5" => (vec![self.encode("5", Vec::new())], true),
+            "D" => (vec![self.new_order(fields).await], false),
+            "F" => (vec![self.cancel_order(fields).await], false),
+            _ => (vec![self.reject(fields, "Unsupported message type")], false),
+        }
+    }
+
+    async fn new_order(&mut self, fields: &Fields) -> Vec<u8> {
+        let cl_ord_id = field(fields, 11).unwrap_or_default().to_string();
+        let account_id = self.account(fields);
+        let order = match parse_order(fields) {
+            Ok(order) => order,
+            Err(text) => return self.order_rejected(&cl_ord_id, fields, &text),
+        };
+        self.track(&account_id).await;
+
+        let message = ClientMessage::PlaceOrder { account_id, order };
+        match handlers::handle_client_message(self.state, &self.session, message).await {
+            ServerMessage::Order(order) => self.execution_report(&order, &cl_ord_id, "0", None),
+            reply => self.order_rejected(&cl_ord_id, fields, &reply_text(reply)),
+        }
+    }
+
+    async fn cancel_order(&mut self, fields: &Fields) -> Vec<u8> {
+        let cl_ord_id = field(fields, 11).unwrap_or_default().to_string();
+        let account_id = self.account(fields);
+        let Some(order_id) = field(fields, 37).and_then(|id| id.parse().ok()) else {
+            return self.cancel_rejected(&cl_ord_id, fields, "OrderID (37) is required");
+        };
+        self.track(&account_id).await;
+
+        let message = ClientMessage::CancelOrder { account_id, order_id };
+        match handlers::handle_client_message(self.state, &self.session, message).await {
+            ServerMessage::Order(order) => self.execution_report(&order, &cl_ord_id, "4", None),
+            reply => self.cancel_rejected(&cl_ord_id, fields, &reply_text(reply)),
+        }
+    }
+
+    async fn fill_report(&mut self, fill: &Fill) -> Vec<u8> {
+        let order = self.state.engine.lock().await.order(&fill.account_id, fill.order_id).cloned();
+        match order {
+            Some(order) => {
+                let cl_ord_id = order.client_order_id.clone().unwrap_or_default();
+                self.execution_report(&order, &cl_ord_id, "F", Some(fill))
+            }
+            // The order is gone from the engine, report the trade on its own
+            None => {
+                let exec_id = self.next_exec_id();
+                self.encode(
+                    "8",
+                    vec![
+                        (1, fill.account_id.clone()),
+                        (6, fill.price.to_string()),
+                        (17, exec_id),
+                        (31, fill.price.to_string()),
+                        (32, fill.quantity.to_string()),
+                        (37, fill.order_id.to_string()),
+                        (54, side_code(fill.side).to_string()),
+                        (55, fill.symbol.clone()),
+                        (150, "F".to_string()),
+                    ],
+                )
+            }
+        }
+    }
+
+    fn execution_report(&mut self, order: &Order, cl_ord_id: &str, exec_type: &str, fill: Option<&Fill>) -> Vec<u8> {
+        let exec_id = self.next_exec_id();
+        let mut body = vec![
+            (1, order.account_id.clone()),
+            (11, cl_ord_id.to_string()),
+            (14, order.filled_quantity.to_string()),
+            (17, exec_id),
+            (37, order.id.to_string()),
+            (38, order.quantity.to_string()),
+            (39, status_code(order.status).to_string()),
+            (40, if order.order_type == OrderType::Market { "1" } else { "2" }.to_string()),
+            (54, side_code(order.side).to_string()),
+            (55, order.symbol.clone()),
+            (150, exec_type.to_string()),
+            (151, (order.quantity - order.filled_quantity).max(0.0).to_string()),
+        ];
+        if let Some(price) = order.price {
+            body.push((44, price.to_string()));
+        }
+        if let Some(fill) = fill {
+            body.push((31, fill.price.to_string()));
+            body.push((32, fill.quantity.to_string()));
+        }
+        self.encode("8", body)
+    }
+
+    fn order_rejected(&mut self, cl_ord_id: &str, fields: &Fields, text: &str) -> Vec<u8> {
+        let exec_id = self.next_exec_id();
+        let mut body = vec![
+            (11, cl_ord_id.to_string()),
+            (17, exec_id),
+            (37, "NONE".to_string()),
+            (39, "8".to_string()),
+            (58, text.to_string()),
+            (150, "8".to_string()),
+        ];
+        for tag in [1, 38, 54, 55] {
+            if let Some(value) = field(fields, tag) {
+                body.push((tag, value.to_string()));
+            }
+        }
+        self.encode("8", body)
+    }
+
+    fn cancel_rejected(&mut self, cl_ord_id: &str, fields: &Fields, text: &str) -> Vec<u8> {
+        let order_id = field(fields, 37).unwrap_or("NONE").to_string();
+        self.encode(
+            "9",
+            vec![
+                (11, cl_ord_id.to_string()),
+                (37, order_id),
+                (39, "8".to_string()),
+                (58, text.to_string()),
+                (434, "1".to_string()),
+            ],
+        )
+    }
+
+    // Session-level reject of a message the gateway can't process
+    fn reject(&mut self, fields: &Fields, text: &str) -> Vec<u8> {
+        let ref_seq_num = field(fields, 34).unwrap_or("0").to_string();
+        let ref_msg_type = field(fields, 35).unwrap_or_default().to_string();
+        self.encode("3", vec![(45, ref_seq_num), (58, text.to_string()), (372, ref_msg_type)])
+    }
+
+    // Account (1) of the message, defaulting to the logged in user's own account
+    fn account(&self, fields: &Fields) -> String {
+        field(fields, 1)
+            .map(str::to_string)
+            .or_else(|| self.session.user_id.clone())
+            .unwrap_or_default()
+    }
+
+    async fn track(&mut self, account_id: &str) {
+        if self.accounts.insert(account_id.to_string()) {
+            self.state.session_opened(account_id).await;
+        }
+    }
+
+    fn next_exec_id(&mut self) -> String {
+        self.exec_id += 1;
+        format!("{}-{}", self.session.connection_id, self.exec_id)
+    }
+
+    fn encode(&mut self, msg_type: &str, fields: Fields) -> Vec<u8> {
+        let mut body = format!(
+            "35={}\x0149={}\x0156={}\x0134={}\x0152={}\x01",
+            msg_type,
+            self.sender_comp_id,
+            self.target_comp_id,
+            self.next_seq_num,
+            sending_time(now_millis())
+        );
+        self.next_seq_num += 1;
+        for (tag, value) in fields {
+            body.push_str(&format!("{}={}\x01", tag, value));
+        }
+        let mut message = format!("8={}\x019={}\x01{}", BEGIN_STRING, body.len(), body).into_bytes();
+        let checksum = message.iter().map(|byte| *byte as u32).sum::<u32>() % 256;
+        message.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+        message
+    }
+}
+
+// Takes the first complete message, one ending in a CheckSum (10) field, off the buffer
+fn next_message(buffer: &mut Vec<u8>) -> Option<Fields> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for (i, byte) in buffer.iter().enumerate() {
+        if *byte != SOH && *byte != b'|' {
+            continue;
+        }
+        let raw = String::from_utf8_lossy(&buffer[start..i]);
+        start = i + 1;
+        let Some((tag, value)) = raw.split_once('=') else {
+            continue;
+        };
+        let Ok(tag) = tag.trim().parse::<u32>() else {
+            continue;
+        };
+        fields.push((tag, value.to_string()));
+        if tag == 10 {
+            buffer.drain(..start);
+            return Some(fields);
+        }
+    }
+    None
+}
+
+fn field(fields: &Fields, tag: u32) -> Option<&str> {
+    fields.iter().find(|(t, _)| *t == tag).map(|(_, value)| value.as_str())
+}
+
+fn parse_order(fields: &Fields) -> Result<OrderRequest, String> {
+    let symbol = field(fields, 55).ok_or("Symbol (55) is required")?.to_uppercase();
+    let side = match field(fields, 54) {
+        Some("1") => Side::Buy,
+        Some("2") => Side::Sell,
+        _ => return Err("Side (54) must be 1 (buy) or 2 (sell)".to_string()),
+    };
+    let quantity: f64 = field(fields, 38)
+        .and_then(|quantity| quantity.parse().ok())
+        .ok_or("OrderQty (38) is required")?;
+    let order_type = match field(fields, 40) {
+        Some("1") => OrderType::Market,
+        Some("2") => OrderType::Limit,
+        _ => return Err("OrdType (40) must be 1 (market) or 2 (limit)".to_string()),
+    };
+    let price = match field(fields, 44) {
+        Some(price) => Some(price.parse().map_err(|_| "Price (44) is not a number")?),
+        None => None,
+    };
+    let time_in_force = match field(fields, 59) {
+        None | Some("0") | Some("1") => TimeInForce::Gtc,
+        Some("3") => TimeInForce::Ioc,
+        Some("4") => TimeInForce::Fok,
+        Some(other) => return Err(format!("TimeInForce (59) {} is not supported", other)),
+    };
+    Ok(OrderRequest {
+        symbol,
+        side,
+        order_type,
+        price,
+        quantity,
+        time_in_force,
+        client_order_id: field(fields, 11).map(str::to_string),
+    })
+}
+
+fn reply_text(reply: ServerMessage) -> String {
+    match reply {
+        ServerMessage::Error { message }
+        | ServerMessage::ExchangeError { message, .. }
+        | ServerMessage::RateLimited { message, .. } => message,
+        ServerMessage::OrderProposed(proposal) => {
+            format!("Held for approval as proposal {}", proposal.proposal_id)
+        }
+        other => format!("Unexpected reply {:?}", other),
+    }
+}
+
+fn side_code(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "1",
+        Side::Sell => "2",
+    }
+}
+
+// OrdStatus (39), an order waiting for its group's entry counts as pending new
+fn status_code(status: OrderStatus) -> &'static str {
+    match status {
+        OrderStatus::Pending => "A",
+        OrderStatus::New => "0",
+        OrderStatus::PartiallyFilled => "1",
+        OrderStatus::Filled => "2",
+        OrderStatus::Cancelled => "4",
+    }
+}
+
+// SendingTime (52) as YYYYMMDD-HH:MM:SS.sss in UTC
+fn sending_time(ms: i64) -> String {
+    let (year, month, day) = civil_from_days(ms.div_euclid(86_400_000));
+    let ms_of_day = ms.rem_euclid(86_400_000);
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        ms_of_day / 3_600_000,
+        ms_of_day / 60_000 % 60,
+        ms_of_day / 1000 % 60,
+        ms_of_day % 1000
+    )
+}
@@ -0,0 +1,53 @@
+use crate::db;
+use crate::indicators;
+use crate::models::{FootprintCandle, ServerMessage};
+use crate::state::AppState;
+
+const DEFAULT_FOOTPRINT_CANDLES: i64 = 50;
+const MAX_FOOTPRINT_CANDLES: i64 = 500;
+
+pub async fn footprint(
+    state: &AppState,
+    symbol: &str,
+    interval: String,
+    limit: Option<i64>,
+    row_size: Option<f64>,
+) -> Result<ServerMessage, String> {
+    let symbol = symbol.trim().to_uppercase();
+    let seconds = indicators::bucket_seconds(&interval)?;
+    if row_size.is_some_and(|size| !(size.is_finite() && size > 0.0)) {
+        return Err("The row size must be a positive number".to_string());
+    }
+    let limit = limit.unwrap_or(DEFAULT_FOOTPRINT_CANDLES).clamp(1, MAX_FOOTPRINT_CANDLES);
+    let rows = db::get_footprint(&state.pool, &symbol, seconds, row_size, limit)
+        .await
+        .map_err(|e| format!("Error loading footprint: {}", e))?;
+
+    // Rows arrive grouped by candle, highest level first
+    let mut candles: Vec<FootprintCandle> = Vec::new();
+    for (open_time, price, bid_volume, ask_volume) in rows {
+        if candles.last().is_none_or(|candle| candle.open_time != open_time) {
+            candles.push(FootprintCandle {
+                open_time,
+                levels: Vec::new(),
+                delta: 0.0,
+                point_of_control: price,
+            });
+        }
+        if let Some(candle) = candles.last_mut() {
+            candle.levels.push([price, bid_volume, ask_volume]);
+            candle.delta += ask_volume - bid_volume;
+        }
+    }
+    for candle in &mut candles {
+        if let Some(level) = candle.levels.iter().max_by(|a, b| (a[1] + a[2]).total_cmp(&(b[1] + b[2]))) {
+            candle.point_of_control = level[0];
+        }
+    }
+    Ok(ServerMessage::Footprint {
+        symbol,
+        interval,
+        row_size,
+        candles,
+    })
+}
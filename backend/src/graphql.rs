@@ -0,0 +1,342 @@
+use crate::auth::{self, Session};
+use crate::candles;
+use crate::db;
+use crate::engine::now_millis;
+use crate::handlers;
+use crate::models::{self, CandleSeriesRequest, CandleType, ClientMessage, Liquidity, PositionReport, Side, Timeline};
+use crate::state::AppState;
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, HeaderMap};
+use axum::Extension;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+pub type TradingSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+// How far the first recorded price of the day may be from UTC midnight
+const DAY_OPEN_TOLERANCE_MS: i64 = 5 * 60_000;
+// Bounds on a single query, nested lists multiply the work behind one request
+const MAX_QUERY_DEPTH: usize = 8;
+const MAX_QUERY_COMPLEXITY: usize = 500;
+
+pub fn schema() -> TradingSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish()
+}
+
+// POST /graphql, signed in like the gRPC API by an `Authorization: Bearer <token>` header. Loaders
+// are per request so cached rows never outlive the query that read them.
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    Extension(schema): Extension<TradingSchema>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let header = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+    let session = match auth::bearer_session(&state, header).await {
+        Ok(session) => Session {
+            ip: Some(addr.ip().to_string()),
+            connection_id: crate::NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            ..session
+        },
+        Err(message) => {
+            return async_graphql::Response::from_errors(vec![async_graphql::ServerError::new(message, None)]).into();
+        }
+    };
+    let request = request
+        .into_inner()
+        .data(DataLoader::new(FillLoader { state: Arc::clone(&state) }, tokio::spawn))
+        .data(DataLoader::new(DayOpenLoader { state: Arc::clone(&state) }, tokio::spawn))
+        .data(session)
+        .data(state);
+    schema.execute(request).await.into()
+}
+
+// Every fill of an account, shared by all its fields in one query
+struct FillLoader {
+    state: Arc<AppState>,
+}
+
+impl Loader<String> for FillLoader {
+    type Value = Arc<Vec<models::Fill>>;
+    type Error = String;
+
+    async fn load(&self, account_ids: &[String]) -> Result<HashMap<String, Self::Value>, Self::Error> {
+        let mut fills = HashMap::with_capacity(account_ids.len());
+        for account_id in account_ids {
+            let account_fills = db::get_fills_since(&self.state.pool, account_id, 0)
+                .await
+                .map_err(|e| format!("Error loading fills: {}", e))?;
+            fills.insert(account_id.clone(), Arc::new(account_fills));
+        }
+        Ok(fills)
+    }
+}
+
+// First recorded price of each symbol at or after the current UTC day's start
+struct DayOpenLoader {
+    state: Arc<AppState>,
+}
+
+impl Loader<String> for DayOpenLoader {
+    type Value = f64;
+    type Error = String;
+
+    async fn load(&self, symbols: &[String]) -> Result<HashMap<String, Self::Value>, Self::Error> {
+        let now = now_millis();
+        let day_start = now - now % MILLIS_PER_DAY;
+        let mut opens = HashMap::with_capacity(symbols.len());
+        for symbol in symbols {
+            let point = db::get_price_at(&self.state.pool, symbol, day_start, DAY_OPEN_TOLERANCE_MS)
+                .await
+                .map_err(|e| format!("Error loading day open: {}", e))?;
+            if let Some(point) = point {
+                opens.insert(symbol.clone(), point.price);
+            }
+        }
+        Ok(opens)
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    // Cash, positions and fills of an account the caller may read
+    async fn account(&self, ctx: &Context<'_>, id: String) -> async_graphql::Result<Account> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let session = ctx.data::<Session>()?;
+        // Same checks as a Portfolio message over the WebSocket
+        let message = ClientMessage::Portfolio {
+            account_id: id.clone(),
+            recalculate_risk: false,
+        };
+        auth::authorize(state, session, &message)?;
+        handlers::authorize_account(state, session, &id, &message).await?;
+
+        let report = state.portfolio_report(&id).await;
+        Ok(Account {
+            id,
+            cash: report.cash,
+            equity: report.equity,
+            peak_equity: report.peak_equity,
+            drawdown: report.drawdown,
+            report_positions: report.positions,
+        })
+    }
+
+    // Standard candles of one series, newest last
+    async fn candles(
+        &self,
+        ctx: &Context<'_>,
+        symbol: String,
+        interval: String,
+        limit: Option<i64>,
+    ) -> async_graphql::Result<Vec<Candle>> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let series = CandleSeriesRequest {
+            symbol: symbol.trim().to_uppercase(),
+            interval,
+            candle_type: CandleType::Standard,
+            brick_size: None,
+        };
+        let mut loaded = candles::get_candle_batch(&state.pool, &[series], limit, Timeline::default()).await?;
+        Ok(loaded
+            .pop()
+            .map(|series| series.candles)
+            .unwrap_or_default()
+            .into_iter()
+            .map(Candle::from)
+            .collect())
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Account {
+    id: String,
+    cash: f64,
+    equity: f64,
+    peak_equity: Option<f64>,
+    drawdown: Option<f64>, // Fraction of peak equity currently lost
+    #[graphql(skip)]
+    report_positions: Vec<PositionReport>,
+}
+
+#[ComplexObject]
+impl Account {
+    // Positions valued at the latest prices, closed ones only carry realized P&L
+    async fn positions(&self, #[graphql(default)] open_only: bool) -> Vec<Position> {
+        self.report_positions
+            .iter()
+            .filter(|position| !open_only || position.quantity != 0.0)
+            .map(|position| Position {
+                account_id: self.id.clone(),
+                report: position.clone(),
+            })
+            .collect()
+    }
+
+    // Fills since `since` (epoch milliseconds), newest first
+    async fn fills(
+        &self,
+        ctx: &Context<'_>,
+        since: Option<i64>,
+        symbol: Option<String>,
+        #[graphql(default = 100)] limit: usize,
+    ) -> async_graphql::Result<Vec<Fill>> {
+        let loader = ctx.data::<DataLoader<FillLoader>>()?;
+        let fills = loader.load_one(self.id.clone()).await?.unwrap_or_default();
+        let symbol = symbol.map(|symbol| symbol.trim().to_uppercase());
+        Ok(fills
+            .iter()
+            .rev()
+            .filter(|fill| fill.created_at >= since.unwrap_or(0))
+            .filter(|fill| symbol.as_ref().is_none_or(|symbol| &fill.symbol == symbol))
+            .take(limit)
+            .map(Fill::from)
+            .collect())
+    }
+}
+
+pub struct Position {
+    account_id: String,
+    report: PositionReport,
+}
+
+#[Object]
+impl Position {
+    async fn symbol(&self) -> &str {
+        &self.report.symbol
+    }
+
+    // Signed, negative for shorts
+    async fn quantity(&self) -> f64 {
+        self.report.quantity
+    }
+
+    async fn average_price(&self) -> f64 {
+        self.report.average_price
+    }
+
+    // Latest price, the average price when the symbol hasn't traded since startup
+    async fn mark_price(&self) -> f64 {
+        self.report.mark_price
+    }
+
+    async fn market_value(&self) -> f64 {
+        self.report.market_value
+    }
+
+    async fn unrealized_pnl(&self) -> f64 {
+        self.report.unrealized_pnl
+    }
+
+    async fn realized_pnl(&self) -> f64 {
+        self.report.realized_pnl
+    }
+
+    // Mark-to-market change since UTC midnight before fees: the quantity held at the day's start
+    // from the day's open, each of today's fills from its price. Null without a recorded open.
+    async fn today_pnl(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<f64>> {
+        let now = now_millis();
+        let day_start = now - now % MILLIS_PER_DAY;
+        let mark = self.report.mark_price;
+
+        let fills = ctx
+            .data::<DataLoader<FillLoader>>()?
+            .load_one(self.account_id.clone())
+            .await?
+            .unwrap_or_default();
+        let mut traded_quantity = 0.0;
+        let mut traded_pnl = 0.0;
+        for fill in fills
+            .iter()
+            .filter(|fill| fill.symbol == self.report.symbol && fill.created_at >= day_start)
+        {
+            let signed = match fill.side {
+                Side::Buy => fill.quantity,
+                Side::Sell => -fill.quantity,
+            };
+            traded_quantity += signed;
+            traded_pnl += signed * (mark - fill.price);
+        }
+
+        let held_quantity = self.report.quantity - traded_quantity;
+        if held_quantity.abs() < f64::EPSILON {
+            return Ok(Some(traded_pnl));
+        }
+        let day_open = ctx
+            .data::<DataLoader<DayOpenLoader>>()?
+            .load_one(self.report.symbol.clone())
+            .await?;
+        Ok(day_open.map(|open| held_quantity * (mark - open) + traded_pnl))
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Fill {
+    order_id: u64,
+    symbol: String,
+    side: String, // "buy" or "sell"
+    price: f64,
+    quantity: f64,
+    liquidity: String, // "maker" or "taker"
+    created_at: i64,
+}
+
+impl From<&models::Fill> for Fill {
+    fn from(fill: &models::Fill) -> Self {
+        Fill {
+            order_id: fill.order_id,
+            symbol: fill.symbol.clone(),
+            side: match fill.side {
+                Side::Buy => "buy",
+                Side::Sell => "sell",
+            }
+            .to_string(),
+            price: fill.price,
+            quantity: fill.quantity,
+            liquidity: match fill.liquidity {
+                Liquidity::Maker => "maker",
+                Liquidity::Taker => "taker",
+            }
+            .to_string(),
+            created_at: fill.created_at,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Candle {
+    open_time: i64, // Bucket start in epoch milliseconds
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: f64,
+    gap: bool,
+}
+
+impl From<models::Candle> for Candle {
+    fn from(candle: models::Candle) -> Self {
+        Candle {
+            open_time: candle.open_time,
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: candle.close,
+            volume: candle.volume,
+            gap: candle.gap,
+        }
+    }
+}
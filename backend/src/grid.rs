@@ -0,0 +1,447 @@
+use crate::accounts;
+use crate::bots;
+use crate::config::env_or;
+use crate::db;
+use crate::models::{
+    BotStatus, GridBot, GridBotConfig, GridLevel, OrderRequest, OrderStatus, OrderType, ServerMessage, Side,
+    TimeInForce,
+};
+use crate::rules;
+use crate::state::AppState;
+use crate::teams;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+const MAX_GRIDS: u32 = 200;
+
+// Grid bots by (account_id, symbol), stopped ones are kept for their results
+#[derive(Default)]
+pub struct GridBook {
+    bots: HashMap<(String, String), GridBot>,
+}
+
+impl GridBook {
+    pub fn load(&mut self, bots: Vec<GridBot>) {
+        for bot in bots {
+            self.set(bot);
+        }
+    }
+
+    fn set(&mut self, bot: GridBot) {
+        let key = (bot.config.account_id.clone(), bot.config.symbol.clone());
+        self.bots.insert(key, bot);
+    }
+
+    fn get(&self, account_id: &str, symbol: &str) -> Option<&GridBot> {
+        self.bots.get(&(account_id.to_string(), symbol.to_string()))
+    }
+
+    pub fn account_bots(&self, account_id: &str) -> Vec<GridBot> {
+        let mut bots: Vec<GridBot> = self
+            .bots
+            .values()
+            .filter(|bot| bot.config.account_id == account_id)
+            .cloned()
+            .collect();
+        bots.sort_by(|a, b| a.config.symbol.cmp(&b.config.symbol));
+        bots
+    }
+
+    fn running(&self) -> Vec<(String, String)> {
+        self.bots
+            .iter()
+            .filter(|(_, bot)| bot.status == BotStatus::Running)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    // Running and paused grids were checked on by their runner just now
+    fn heartbeat(&mut self, now: i64) {
+        for bot in self.bots.values_mut() {
+            if bot.status != BotStatus::Stopped {
+                bot.heartbeat_at = Some(now);
+            }
+        }
+    }
+}
+
+// A running or paused grid holds the symbol, other bots can't start in it meanwhile
+pub async fn is_active(state: &AppState, account_id: &str, symbol: &str) -> bool {
+    state
+        .grid_bots
+        .lock()
+        .await
+        .get(account_id, symbol)
+        .is_some_and(|bot| bot.status != BotStatus::Stopped)
+}
+
+fn validate(config: &mut GridBotConfig) -> Result<(), String> {
+    config.symbol = config.symbol.trim().to_uppercase();
+    if config.symbol.is_empty() {
+        return Err("symbol is required".to_string());
+    }
+    if !(config.lower_price.is_finite() && config.lower_price > 0.0) {
+        return Err("The lower price must be a positive number".to_string());
+    }
+    if !(config.upper_price.is_finite() && config.upper_price > config.lower_price) {
+        return Err("The upper price must be above the lower price".to_string());
+    }
+    if !(2..=MAX_GRIDS).contains(&config.grids) {
+        return Err(format!("A grid needs between 2 and {} grids", MAX_GRIDS));
+    }
+    if !(config.order_quantity.is_finite() && config.order_quantity > 0.0) {
+        return Err("The order quantity must be a positive number".to_string());
+    }
+    Ok(())
+}
+
+fn spacing(config: &GridBotConfig) -> f64 {
+    (config.upper_price - config.lower_price) / config.grids as f64
+}
+
+fn grid_order(config: &GridBotConfig, side: Side, price: f64) -> OrderRequest {
+    OrderRequest {
+        symbol: config.symbol.clone(),
+        side,
+        order_type: OrderType::Limit,
+        price: Some(price),
+        quantity: config.order_quantity,
+        time_in_force: TimeInForce::Gtc,
+        client_order_id: None,
+    }
+}
+
+// A grid's orders go in together, so like spreads they can't be proposed for approval, and each
+// passes the same checks as a single order
+async fn check_orders(state: &AppState, account_id: &str, orders: &[OrderRequest]) -> Result<(), String> {
+    let mut notional = Some(0.0);
+    for order in orders {
+        notional = notional.zip(teams::order_notional(state, order).await).map(|(sum, order)| sum + order);
+    }
+    if state.teams.lock().await.requires_approval(account_id, notional) {
+        return Err(
+            "Grids above the approval threshold can't be proposed, place single orders for approval".to_string(),
+        );
+    }
+    for order in orders {
+        accounts::check_order(state, account_id, order).await?;
+    }
+    rules::check_orders(state, account_id, orders.len() as u32).await
+}
+
+// Lays out the levels around the last price, leaving the level nearest to it empty, and places
+// every order of the grid at once
+pub async fn start(state: &AppState, mut config: GridBotConfig) -> Result<ServerMessage, String> {
+    validate(&mut config)?;
+    let account_id = config.account_id.clone();
+    if is_active(state, &account_id, &config.symbol).await || bots::is_active(state, &account_id, &config.symbol).await
+    {
+        return Err(format!("A bot is already active in {}, stop it first", config.symbol));
+    }
+    let price = state
+        .engine
+        .lock()
+        .await
+        .last_price(&config.symbol)
+        .ok_or_else(|| format!("No price for {} yet", config.symbol))?;
+    if price < config.lower_price || price > config.upper_price {
+        return Err(format!("The last price {} is outside the grid range", price));
+    }
+
+    let step = spacing(&config);
+    let empty = ((price - config.lower_price) / step).round() as usize;
+    let mut levels: Vec<GridLevel> = (0..=config.grids as usize)
+        .map(|index| GridLevel {
+            price: config.lower_price + index as f64 * step,
+            side: match index.cmp(&empty) {
+                std::cmp::Ordering::Less => Some(Side::Buy),
+                std::cmp::Ordering::Equal => None,
+                std::cmp::Ordering::Greater => Some(Side::Sell),
+            },
+            order_id: None,
+            counter: false,
+        })
+        .collect();
+
+    let orders: Vec<OrderRequest> = levels
+        .iter()
+        .filter_map(|level| Some(grid_order(&config, level.side?, level.price)))
+        .collect();
+    check_orders(state, &account_id, &orders).await?;
+    let placed = state
+        .backend
+        .place_legs(&account_id, orders)
+        .await
+        .map_err(|e| e.to_string())?;
+    let working = levels.iter_mut().filter(|level| level.side.is_some());
+    for (level, order) in working.zip(placed) {
+        level.order_id = Some(order.id);
+    }
+
+    let bot = GridBot {
+        config,
+        status: BotStatus::Running,
+        levels,
+        grid_profit: 0.0,
+        round_trips: 0,
+        started_at: state.simulation.now_millis(),
+        stopped_at: None,
+        heartbeat_at: None,
+    };
+    save(state, &bot).await;
+    state.grid_bots.lock().await.set(bot.clone());
+    Ok(ServerMessage::GridBot(bot))
+}
+
+pub async fn stop(state: &AppState, account_id: &str, symbol: &str) -> Result<ServerMessage, String> {
+    let symbol = symbol.trim().to_uppercase();
+    let mut bot = {
+        let mut book = state.grid_bots.lock().await;
+        let bot = match book.get(account_id, &symbol) {
+            Some(bot) if bot.status != BotStatus::Stopped => bot.clone(),
+            Some(_) => return Err(format!("The grid bot in {} is already stopped", symbol)),
+            None => return Err(format!("No grid bot in {}", symbol)),
+        };
+        // Stopped before its orders go, so fills in between aren't answered
+        book.set(GridBot {
+            status: BotStatus::Stopped,
+            ..bot.clone()
+        });
+        bot
+    };
+    for level in &mut bot.levels {
+        level.side = None;
+        level.counter = false;
+        let Some(order_id) = level.order_id.take() else {
+            continue;
+        };
+        // Orders filled or cancelled in the meantime are already gone
+        if let Err(e) = state.backend.cancel_order(account_id, order_id).await {
+            eprintln!("Error cancelling grid order {}: {}", order_id, e);
+        }
+    }
+    bot.status = BotStatus::Stopped;
+    bot.stopped_at = Some(state.simulation.now_millis());
+    save(state, &bot).await;
+    state.grid_bots.lock().await.set(bot.clone());
+    Ok(ServerMessage::GridBot(bot))
+}
+
+// Pulls the grid's orders and keeps each level's side, resuming puts them back where they were.
+// An order that filled before it could be cancelled is kept and answered on resume.
+pub async fn pause(state: &AppState, account_id: &str, symbol: &str) -> Result<ServerMessage, String> {
+    let symbol = symbol.trim().to_uppercase();
+    let mut bot = {
+        let mut book = state.grid_bots.lock().await;
+        let bot = match book.get(account_id, &symbol) {
+            Some(bot) => match bot.status {
+                BotStatus::Running => bot.clone(),
+                BotStatus::Paused => return Err(format!("The grid bot in {} is already paused", symbol)),
+                BotStatus::Stopped => return Err(format!("The grid bot in {} is stopped", symbol)),
+            },
+            None => return Err(format!("No grid bot in {}", symbol)),
+        };
+        book.set(GridBot {
+            status: BotStatus::Paused,
+            ..bot.clone()
+        });
+        bot
+    };
+    for level in &mut bot.levels {
+        let Some(order_id) = level.order_id else {
+            continue;
+        };
+        match state.backend.cancel_order(account_id, order_id).await {
+            Ok(_) => level.order_id = None,
+            Err(e) => eprintln!("Error cancelling grid order {}: {}", order_id, e),
+        }
+    }
+    bot.status = BotStatus::Paused;
+
+    {
+        let mut book = state.grid_bots.lock().await;
+        // Stopped meanwhile, the stop cancelled what was left
+        if !book.get(account_id, &symbol).is_some_and(|current| current.status == BotStatus::Paused) {
+            return Err(format!("The grid bot in {} was stopped", symbol));
+        }
+        book.set(bot.clone());
+    }
+    save(state, &bot).await;
+    Ok(ServerMessage::GridBot(bot))
+}
+
+// Places the paused levels again at their old prices. Levels the price moved through fill at
+// once and are answered like any other fill.
+pub async fn resume(state: &AppState, account_id: &str, symbol: &str) -> Result<ServerMessage, String> {
+    let symbol = symbol.trim().to_uppercase();
+    let mut bot = match state.grid_bots.lock().await.get(account_id, &symbol) {
+        Some(bot) => match bot.status {
+            BotStatus::Paused => bot.clone(),
+            BotStatus::Running => return Err(format!("The grid bot in {} isn't paused", symbol)),
+            BotStatus::Stopped => return Err(format!("The grid bot in {} is stopped", symbol)),
+        },
+        None => return Err(format!("No grid bot in {}", symbol)),
+    };
+
+    let orders: Vec<OrderRequest> = bot
+        .levels
+        .iter()
+        .filter(|level| level.order_id.is_none())
+        .filter_map(|level| Some(grid_order(&bot.config, level.side?, level.price)))
+        .collect();
+    check_orders(state, account_id, &orders).await?;
+    let placed = state
+        .backend
+        .place_legs(account_id, orders)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut ids = Vec::with_capacity(placed.len());
+    let paused = bot.levels.iter_mut().filter(|level| level.side.is_some() && level.order_id.is_none());
+    for (level, order) in paused.zip(placed) {
+        level.order_id = Some(order.id);
+        ids.push(order.id);
+    }
+    bot.status = BotStatus::Running;
+
+    let resumed = {
+        let mut book = state.grid_bots.lock().await;
+        let paused = book.get(account_id, &symbol).is_some_and(|current| current.status == BotStatus::Paused);
+        if paused {
+            book.set(bot.clone());
+        }
+        paused
+    };
+    // Stopped while the orders were placed, they go again
+    if !resumed {
+        for order_id in ids {
+            if let Err(e) = state.backend.cancel_order(account_id, order_id).await {
+                eprintln!("Error cancelling grid order {}: {}", order_id, e);
+            }
+        }
+        return Err(format!("The grid bot in {} was stopped", symbol));
+    }
+    save(state, &bot).await;
+    reconcile(state, account_id, &symbol).await;
+    let bot = state.grid_bots.lock().await.get(account_id, &symbol).cloned().unwrap_or(bot);
+    Ok(ServerMessage::GridBot(bot))
+}
+
+async fn save(state: &AppState, bot: &GridBot) {
+    if let Err(e) = db::save_grid_bot(&state.pool, bot).await {
+        eprintln!("Error saving grid bot: {:?}", e);
+    }
+}
+
+// Answers every fully filled order of the bot with the opposite order one level away. A filled
+// order that closed a round trip adds one level spacing of profit per unit.
+async fn reconcile(state: &AppState, account_id: &str, symbol: &str) {
+    let running = |bot: &&GridBot| bot.status == BotStatus::Running;
+    let Some(mut bot) = state.grid_bots.lock().await.get(account_id, symbol).filter(running).cloned() else {
+        return;
+    };
+    // Asked of the backend, a venue's fills never reach the engine
+    let mut filled = Vec::new();
+    for (index, level) in bot.levels.iter().enumerate() {
+        let Some(order_id) = level.order_id else {
+            continue;
+        };
+        if let Ok(order) = state.backend.order(account_id, order_id).await {
+            if order.status == OrderStatus::Filled {
+                filled.push(index);
+            }
+        }
+    }
+    if filled.is_empty() {
+        return;
+    }
+
+    // Every filled level is cleared first, a fast move fills several and frees their neighbours
+    let step = spacing(&bot.config);
+    let mut answers = Vec::with_capacity(filled.len());
+    for index in filled {
+        let level = &mut bot.levels[index];
+        level.order_id = None;
+        if std::mem::take(&mut level.counter) {
+            bot.round_trips += 1;
+            bot.grid_profit += step * bot.config.order_quantity;
+        }
+        let target = match level.side.take() {
+            Some(Side::Buy) => Some((index + 1, Side::Sell)),
+            Some(Side::Sell) => index.checked_sub(1).map(|target| (target, Side::Buy)),
+            None => None,
+        };
+        if let Some(target) = target.filter(|(target, _)| *target < bot.levels.len()) {
+            answers.push(target);
+        }
+    }
+    let mut placed = Vec::new();
+    for (target, side) in answers {
+        let level = &bot.levels[target];
+        if level.order_id.is_some() {
+            continue;
+        }
+        let order = grid_order(&bot.config, side, level.price);
+        if let Err(message) = check_orders(state, account_id, std::slice::from_ref(&order)).await {
+            eprintln!("Grid bot {} {} skipped a level: {}", account_id, symbol, message);
+            continue;
+        }
+        match state.backend.place_order(account_id, order).await {
+            Ok(order) => {
+                let level = &mut bot.levels[target];
+                level.side = Some(side);
+                level.order_id = Some(order.id);
+                level.counter = true;
+                placed.push(order.id);
+            }
+            Err(e) => eprintln!("Grid bot {} {} skipped a level: {}", account_id, symbol, e),
+        }
+    }
+
+    {
+        let mut book = state.grid_bots.lock().await;
+        if book.get(account_id, symbol).is_some_and(|current| current.status == BotStatus::Running) {
+            book.set(bot.clone());
+            placed.clear();
+        }
+    }
+    // Paused or stopped while the answers were placed, they go again
+    if !placed.is_empty() {
+        for order_id in placed {
+            if let Err(e) = state.backend.cancel_order(account_id, order_id).await {
+                eprintln!("Error cancelling grid order {}: {}", order_id, e);
+            }
+        }
+        return;
+    }
+    save(state, &bot).await;
+}
+
+// Follows fills to keep every running grid answered. After a lag, and on every heartbeat, every
+// running grid is checked, the backend has the state of their orders either way.
+pub async fn run_grid_bots(state: Arc<AppState>) {
+    let mut fills = state.fills.subscribe();
+    let mut heartbeat = state.simulation.interval(Duration::from_secs(env_or("BOT_HEARTBEAT_SECS", 10).max(1)));
+    let mut pending = state.grid_bots.lock().await.running();
+    loop {
+        for (account_id, symbol) in pending.drain(..) {
+            reconcile(&state, &account_id, &symbol).await;
+        }
+        tokio::select! {
+            received = fills.recv() => match received {
+                Ok(fill) => pending.push((fill.account_id, fill.symbol)),
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Grid bots lagged behind, {} fills skipped", skipped);
+                    pending = state.grid_bots.lock().await.running();
+                }
+                Err(RecvError::Closed) => return,
+            },
+            _ = heartbeat.tick() => {
+                let mut book = state.grid_bots.lock().await;
+                book.heartbeat(state.simulation.now_millis());
+                pending = book.running();
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use crate::auth::{self, Session};
+use crate::candles;
+use crate::handlers;
+use crate::models::{
+    self, CandleSeriesRequest, CandleType, ClientMessage, OrderRequest, OrderStatus, OrderType, ServerMessage, Side,
+    Timeline, TimeInForce, WireFormat,
+};
+use crate::state::AppState;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Duration;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+pub mod pb {
+    tonic::include_proto!("trading");
+}
+
+use pb::trading_simulator_server::{TradingSimulator, TradingSimulatorServer};
+
+// Buffered messages per stream before a slow consumer holds up its producer
+const STREAM_BUFFER: usize = 256;
+// Newest candle refresh period, shorter intervals poll at their own length
+const CANDLE_POLL_SECS: i64 = 5;
+
+pub async fn serve(state: Arc<AppState>, bind_addr: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let addr = bind_addr.parse()?;
+    println!("gRPC server started on {}", bind_addr);
+    tonic::transport::Server::builder()
+        .add_service(TradingSimulatorServer::new(GrpcService { state }))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+struct GrpcService {
+    state: Arc<AppState>,
+}
+
+impl GrpcService {
+    // Every call is its own connection for audit and rate limiting, signed in by its bearer token
+    async fn session<T>(&self, request: &Request<T>) -> Result<Session, Status> {
+        let header = match request.metadata().get("authorization") {
+            Some(header) => Some(header.to_str().map_err(|_| Status::unauthenticated("Malformed authorization"))?),
+            None => None,
+        };
+        let session = auth::bearer_session(&self.state, header).await.map_err(Status::unauthenticated)?;
+        Ok(Session {
+            ip: request.remote_addr().map(|addr| addr.ip().to_string()),
+            connection_id: crate::NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            ..session
+        })
+    }
+
+    async fn call(&self, session: &Session, message: ClientMessage) -> ServerMessage {
+        handlers::handle_client_message(&self.state, session, message).await
+    }
+}
+
+#[tonic::async_trait]
+impl TradingSimulator for GrpcService {
+    type StreamTickersStream = ReceiverStream<Result<pb::Ticker, Status>>;
+    type StreamCandlesStream = ReceiverStream<Result<pb::Candle, Status>>;
+
+    async fn login(&self, request: Request<pb::LoginRequest>) -> Result<Response<pb::LoginReply>, Status> {
+        let session = self.session(&request).await?;
+        let token = request.into_inner().token;
+        match self.call(&session, ClientMessage::Authenticate { token }).await {
+            ServerMessage::Authenticated {
+                user_id,
+                role,
+                tokens: Some(tokens),
+                ..
+            } => Ok(Response::new(pb::LoginReply {
+                user_id,
+                role: role.name().to_string(),
+                access_token: tokens.access_token,
+                access_expires_at: tokens.access_expires_at,
+                refresh_token: tokens.refresh_token,
+                refresh_expires_at: tokens.refresh_expires_at,
+            })),
+            ServerMessage::Authenticated { .. } => {
+                Err(Status::invalid_argument("Already an access token, log in with a user token or API key"))
+            }
+            reply => Err(status(reply)),
+        }
+    }
+
+    async fn stream_tickers(
+        &self,
+        request: Request<pb::TickerRequest>,
+    ) -> Result<Response<Self::StreamTickersStream>, Status> {
+        let session = self.session(&request).await?;
+        let symbols = request.into_inner().symbols;
+        let message = ClientMessage::Subscribe {
+            symbols,
+            interval_ms: None,
+            fields: None,
+            format: WireFormat::Standard,
+        };
+        let symbols: HashSet<String> = match self.call(&session, message).await {
+            ServerMessage::Subscribed { symbols, .. } => symbols.into_iter().collect(),
+            reply => return Err(status(reply)),
+        };
+
+        let mut tickers = self.state.tickers.subscribe();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let ticker = match tickers.recv().await {
+                    Ok(ticker) => ticker,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if !symbols.is_empty() && !symbols.contains(&ticker.symbol) {
+                    continue;
+                }
+                let ticker = pb::Ticker {
+                    symbol: ticker.symbol,
+                    price: ticker.price,
+                    quote_volume: ticker.quote_volume,
+                    event_time: ticker.event_time,
+                };
+                if tx.send(Ok(ticker)).await.is_err() {
+                    break; // The client went away
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn stream_candles(
+        &self,
+        request: Request<pb::CandleRequest>,
+    ) -> Result<Response<Self::StreamCandlesStream>, Status> {
+        let session = self.session(&request).await?;
+        let request = request.into_inner();
+        let series = CandleSeriesRequest {
+            symbol: request.symbol.trim().to_uppercase(),
+            interval: request.interval,
+            candle_type: CandleType::Standard,
+            brick_size: None,
+        };
+        // The first load goes through the shared handler for authorization and validation
+        let message = ClientMessage::Candles {
+            series: vec![series.clone()],
+            limit: request.limit,
+            timeline: Timeline::default(),
+            account_id: None,
+            format: WireFormat::Standard,
+        };
+        let history = match self.call(&session, message).await {
+            ServerMessage::Candles { mut series } => series.pop().map(|series| series.candles).unwrap_or_default(),
+            reply => return Err(status(reply)),
+        };
+        let poll_secs = candles::interval_seconds(&series.interval)
+            .unwrap_or(CANDLE_POLL_SECS)
+            .clamp(1, CANDLE_POLL_SECS);
+
+        let state = Arc::clone(&self.state);
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            // Last values sent for the newest buckets, a candle goes out again only when it changed
+            let mut sent: BTreeMap<i64, (f64, f64, f64, f64)> = BTreeMap::new();
+            let mut batch = history;
+            let mut ticker = state.simulation.interval(Duration::from_secs(poll_secs as u64));
+            ticker.tick().await;
+            loop {
+                for candle in batch {
+                    let values = (candle.high, candle.low, candle.close, candle.volume);
+                    if sent.get(&candle.open_time) == Some(&values) {
+                        continue;
+                    }
+                    let reply = pb::Candle {
+                        symbol: series.symbol.clone(),
+                        interval: series.interval.clone(),
+                        open_time: candle.open_time,
+                        open: candle.open,
+                        high: candle.high,
+                        low: candle.low,
+                        close: candle.close,
+                        volume: candle.volume,
+                        gap: candle.gap,
+                    };
+                    if tx.send(Ok(reply)).await.is_err() {
+                        return; // The client went away
+                    }
+                    sent.insert(candle.open_time, values);
+                }
+                while sent.len() > 2 {
+                    sent.pop_first();
+                }
+
+                ticker.tick().await;
+                // The previous bucket too, its last ticks may land after the next one opened
+                batch = match candles::get_candle_batch(&state.pool, std::slice::from_ref(&series), Some(2), Timeline::default())
+                    .await
+                {
+                    Ok(mut loaded) => loaded.pop().map(|series| series.candles).unwrap_or_default(),
+                    Err(message) => {
+                        let _ = tx.send(Err(Status::unavailable(message))).await;
+                        return;
+                    }
+                };
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn place_order(&self, request: Request<pb::PlaceOrderRequest>) -> Result<Response<pb::PlaceOrderReply>, Status> {
+        let session = self.session(&request).await?;
+        let request = request.into_inner();
+        let side = match request.side() {
+            pb::Side::Buy => Side::Buy,
+            pb::Side::Sell => Side::Sell,
+            pb::Side::Unspecified => return Err(Status::invalid_argument("side is required")),
+        };
+        let order_type = match request.order_type() {
+            pb::OrderType::Market => OrderType::Market,
+            pb::OrderType::Limit => OrderType::Limit,
+            pb::OrderType::Unspecified => return Err(Status::invalid_argument("order_type is required")),
+        };
+        let order = OrderRequest {
+            symbol: request.symbol.clone(),
+            side,
+            order_type,
+            price: request.price,
+            quantity: request.quantity,
+            time_in_force: match request.time_in_force() {
+                pb::TimeInForce::Gtc => TimeInForce::Gtc,
+                pb::TimeInForce::Ioc => TimeInForce::Ioc,
+                pb::TimeInForce::Fok => TimeInForce::Fok,
+            },
+            client_order_id: request.client_order_id,
+        };
+        let message = ClientMessage::PlaceOrder {
+            account_id: request.account_id,
+            order,
+        };
+        let result = match self.call(&session, message).await {
+            ServerMessage::Order(order) => pb::place_order_reply::Result::Order(order_reply(&order)),
+            ServerMessage::OrderProposed(proposal) => pb::place_order_reply::Result::ProposalId(proposal.proposal_id),
+            reply => return Err(status(reply)),
+        };
+        Ok(Response::new(pb::PlaceOrderReply { result: Some(result) }))
+    }
+
+    async fn cancel_order(&self, request: Request<pb::CancelOrderRequest>) -> Result<Response<pb::Order>, Status> {
+        let session = self.session(&request).await?;
+        let request = request.into_inner();
+        let message = ClientMessage::CancelOrder {
+            account_id: request.account_id,
+            order_id: request.order_id,
+        };
+        match self.call(&session, message).await {
+            ServerMessage::Order(order) => Ok(Response::new(order_reply(&order))),
+            reply => Err(status(reply)),
+        }
+    }
+}
+
+fn order_reply(order: &models::Order) -> pb::Order {
+    let side = match order.side {
+        Side::Buy => pb::Side::Buy,
+        Side::Sell => pb::Side::Sell,
+    };
+    let order_type = match order.order_type {
+        OrderType::Market => pb::OrderType::Market,
+        OrderType::Limit => pb::OrderType::Limit,
+    };
+    let time_in_force = match order.time_in_force {
+        TimeInForce::Gtc => pb::TimeInForce::Gtc,
+        TimeInForce::Ioc => pb::TimeInForce::Ioc,
+        TimeInForce::Fok => pb::TimeInForce::Fok,
+    };
+    let status = match order.status {
+        OrderStatus::Pending => pb::OrderStatus::Pending,
+        OrderStatus::New => pb::OrderStatus::New,
+        OrderStatus::PartiallyFilled => pb::OrderStatus::PartiallyFilled,
+        OrderStatus::Filled => pb::OrderStatus::Filled,
+        OrderStatus::Cancelled => pb::OrderStatus::Cancelled,
+    };
+    pb::Order {
+        id: order.id,
+        account_id: order.account_id.clone(),
+        symbol: order.symbol.clone(),
+        side: side as i32,
+        order_type: order_type as i32,
+        price: order.price,
+        quantity: order.quantity,
+        filled_quantity: order.filled_quantity,
+        time_in_force: time_in_force as i32,
+        status: status as i32,
+        group_id: order.group_id,
+        client_order_id: order.client_order_id.clone(),
+        created_at: order.created_at,
+    }
+}
+
+// gRPC status for a handler reply that wasn't the expected one
+fn status(reply: ServerMessage) -> Status {
+    match reply {
+        ServerMessage::RateLimited { message, .. } => Status::resource_exhausted(message),
+        ServerMessage::ExchangeError { message, .. } => Status::unavailable(message),
+        ServerMessage::Error { message } => Status::failed_precondition(message),
+        other => Status::internal(format!("Unexpected reply {:?}", other)),
+    }
+}
@@ -0,0 +1,113 @@
+use crate::accounts;
+use crate::auth;
+use crate::config::env_or;
+use crate::db;
+use crate::jobs::{self, Task};
+use crate::models::{AccountEvent, Role, ServerMessage};
+use crate::state::AppState;
+use rand::Rng;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+const GUEST_PREFIX: &str = "guest-";
+
+const MIN_STARTING_BALANCE: f64 = 100.0;
+const MAX_STARTING_BALANCE: f64 = 1_000_000.0;
+
+// Creates a guest user with its own account, ready to trade after authenticating with the token
+pub async fn create_guest(
+    state: &AppState,
+    starting_balance: Option<f64>,
+    template: Option<String>,
+) -> Result<ServerMessage, String> {
+    let max_guests: i64 = env_or("MAX_GUESTS", 1000);
+    let live = db::count_guests(&state.pool)
+        .await
+        .map_err(|e| format!("Error counting guests: {}", e))?;
+    if live >= max_guests {
+        return Err("No guest accounts are available right now, try again later".to_string());
+    }
+
+    let template = match template {
+        Some(name) => Some(
+            state
+                .account_templates
+                .lock()
+                .await
+                .get(&name)
+                .cloned()
+                .ok_or_else(|| format!("Unknown account template {}", name))?,
+        ),
+        None => None,
+    };
+    let starting_balance = match &template {
+        Some(template) => template.starting_balance,
+        None => starting_balance.unwrap_or_else(|| env_or("GUEST_STARTING_BALANCE", 10_000.0)),
+    };
+    if template.is_none() && !(MIN_STARTING_BALANCE..=MAX_STARTING_BALANCE).contains(&starting_balance) {
+        return Err(format!(
+            "Starting balance must be between {} and {}",
+            MIN_STARTING_BALANCE, MAX_STARTING_BALANCE
+        ));
+    }
+
+    let id: [u8; 6] = rand::thread_rng().gen();
+    let user_id = format!("{}{}", GUEST_PREFIX, hex::encode(id));
+    let token = auth::generate_token();
+    db::create_guest(&state.pool, &user_id, &auth::hash_secret(&token), starting_balance)
+        .await
+        .map_err(|e| format!("Error creating guest: {}", e))?;
+    db::create_account(&state.pool, &user_id, template.as_ref().map(|template| template.name.as_str()))
+        .await
+        .map_err(|e| format!("Error creating guest account: {}", e))?;
+    accounts::apply_template(state, &user_id, template.as_ref(), starting_balance).await;
+
+    Ok(ServerMessage::GuestCreated {
+        account_id: user_id.clone(),
+        user_id,
+        role: Role::User,
+        token,
+        starting_balance,
+        expires_after_days: env_or("GUEST_INACTIVE_DAYS", 7),
+    })
+}
+
+// Queues a sweep of inactive guests every GUEST_REAP_SECS, unless the last one hasn't finished
+pub async fn run_guest_reaper(state: Arc<AppState>) {
+    let mut ticker = state.simulation.interval(Duration::from_secs(env_or("GUEST_REAP_SECS", 3600)));
+    loop {
+        ticker.tick().await;
+        if let Err(e) = jobs::enqueue(&state.pool, &Task::GuestSweep).await {
+            eprintln!("Error queueing guest sweep: {:?}", e);
+        }
+    }
+}
+
+// Deletes guests that have had no audited activity for GUEST_INACTIVE_DAYS, along with their
+// account data and any orders still resting in the engine. A guest that fails to delete is left
+// for the next sweep.
+pub async fn sweep_inactive(state: &AppState) -> Result<(), String> {
+    let inactive_days: i32 = env_or("GUEST_INACTIVE_DAYS", 7);
+    let expired = db::get_inactive_guests(&state.pool, inactive_days)
+        .await
+        .map_err(|e| format!("loading inactive guests: {:?}", e))?;
+
+    for account_id in expired {
+        if state.is_online(&account_id).await {
+            continue;
+        }
+        if let Err(e) = db::delete_guest(&state.pool, &account_id).await {
+            eprintln!("Error deleting guest {}: {:?}", account_id, e);
+            continue;
+        }
+        let _ = state.revocations.send(auth::Revocation::User(account_id.clone()));
+        let dropped = state.close_account_orders(&account_id).await;
+        state.portfolios.lock().await.close_account(&account_id);
+        if let Err(e) = state.ledger.record(&state.pool, &account_id, AccountEvent::AccountClosed).await {
+            eprintln!("Error recording closing of guest {}: {:?}", account_id, e);
+        }
+        state.account_templates.lock().await.remove_account(&account_id);
+        println!("Expired guest {} ({} orders dropped)", account_id, dropped);
+    }
+    Ok(())
+}
@@ -0,0 +1,1464 @@
+use crate::accounts;
+use crate::analytics;
+use crate::attribution;
+use crate::audit;
+use crate::auth::{self, Session};
+use crate::benchmarks;
+use crate::bots;
+use crate::candles;
+use crate::competitions;
+use crate::db;
+use crate::depth;
+use crate::engine;
+use crate::explain;
+use crate::exposure;
+use crate::feature_flags;
+use crate::footprint;
+use crate::grid;
+use crate::guests;
+use crate::index;
+use crate::indicators;
+use crate::jobs::{self, Task};
+use crate::key_levels;
+use crate::ladder;
+use crate::maintenance;
+use crate::models::{
+    AmendOrderRequest, ApiKey, ClientMessage, CompactPage, CompactTicker, Competition, MaintenanceWindow, Order,
+    OrderRequest, PaginatedResponse, PaginationParams, PortfolioReport, ServerMessage, SettingChange, Side,
+    SpreadOrderReport, StorageKind, TickerQuery, TickerUpdate, WireFormat,
+};
+use crate::profiles;
+use crate::rate_limit::{LimitKind, RateLimitError};
+use crate::reload;
+use crate::reports;
+use crate::risk;
+use crate::rules;
+use crate::spread;
+use crate::state::AppState;
+use crate::storage;
+use crate::tape;
+use crate::teams;
+use crate::tenants;
+use std::time::Duration;
+
+pub async fn handle_client_message(state: &AppState, session: &Session, msg: ClientMessage) -> ServerMessage {
+    handle_message(state, session, msg).await.0
+}
+
+// Like handle_client_message, also telling whether the session may see the message's account.
+// Connections only follow an account's fills and alerts once it was.
+pub async fn handle_message(state: &AppState, session: &Session, msg: ClientMessage) -> (ServerMessage, bool) {
+    let audited = audit::action(&msg).map(|action| (action, message_account(&msg).map(str::to_string)));
+    let (reply, authorized) = match admit(state, session, &msg).await {
+        Ok(()) => (dispatch(state, session, msg).await, true),
+        Err(reply) => (reply, false),
+    };
+    if let Some((action, account_id)) = audited {
+        audit::record(state, session, action, account_id, &reply).await;
+    }
+    (reply, authorized)
+}
+
+// Messages that pass the order-entry gate, their replies wait for its simulated latency
+pub fn is_order_entry(msg: &ClientMessage) -> bool {
+    matches!(
+        msg,
+        ClientMessage::PlaceOrder { .. }
+            | ClientMessage::PlaceOrderGroup { .. }
+            | ClientMessage::AmendOrder(_)
+            | ClientMessage::CancelOrder { .. }
+            | ClientMessage::CancelOrderGroup { .. }
+            | ClientMessage::LadderOrder { .. }
+            | ClientMessage::LadderCancel { .. }
+            | ClientMessage::PlaceSpreadOrder { .. }
+            | ClientMessage::ApproveOrder { .. }
+    )
+}
+
+// Role, maintenance, key limits and the account's tenant and team, checked before anything runs
+async fn admit(state: &AppState, session: &Session, msg: &ClientMessage) -> Result<(), ServerMessage> {
+    if let Err(message) = auth::authorize(state, session, msg) {
+        return Err(ServerMessage::Error { message });
+    }
+    if let Err(message) = maintenance::check(state, msg).await {
+        return Err(ServerMessage::Error { message });
+    }
+    if let Some(key) = &session.key {
+        admit_key_request(state, key).await?;
+    }
+    if let Some(account_id) = message_account(msg) {
+        if let Err(message) = authorize_account(state, session, account_id, msg).await {
+            return Err(ServerMessage::Error { message });
+        }
+    }
+    Ok(())
+}
+
+// The account's tenant, then its team, for `msg` on the account. Every transport that touches an
+// account goes through this.
+pub async fn authorize_account(
+    state: &AppState,
+    session: &Session,
+    account_id: &str,
+    msg: &ClientMessage,
+) -> Result<(), String> {
+    tenants::authorize(state, session, account_id).await?;
+    teams::authorize(state, session, account_id, msg).await
+}
+
+async fn dispatch(state: &AppState, session: &Session, msg: ClientMessage) -> ServerMessage {
+    let backend = &state.backend;
+
+    let result = match msg {
+        ClientMessage::PlaceOrder { account_id, order } => {
+            return place_order(state, session, &account_id, order).await;
+        }
+        ClientMessage::PlaceOrderGroup {
+            account_id,
+            entry,
+            take_profits,
+        } => {
+            let notional = teams::order_notional(state, &entry).await;
+            if state.teams.lock().await.requires_approval(&account_id, notional) {
+                return ServerMessage::Error {
+                    message: "Order groups above the approval threshold can't be proposed, place single orders for approval"
+                        .to_string(),
+                };
+            }
+            if let Err(message) = accounts::check_order(state, &account_id, &entry).await {
+                return ServerMessage::Error { message };
+            }
+            // Every leg of the group counts towards the order limits
+            let weight = 1 + take_profits.len() as u32;
+            if let Err(message) = rules::check_orders(state, &account_id, weight).await {
+                return ServerMessage::Error { message };
+            }
+            if let Err(reply) = admit_order_request(state, &account_id, LimitKind::Order, weight).await {
+                return reply;
+            }
+            match backend.place_group(&account_id, entry, take_profits).await {
+                Ok(report) => {
+                    let mut order_ids = vec![report.entry.id];
+                    order_ids.extend(report.take_profits.iter().map(|order| order.id));
+                    teams::attribute(state, session, &account_id, &order_ids).await;
+                    Ok(ServerMessage::OrderGroup(report))
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        ClientMessage::PlaceSpreadOrder {
+            account_id,
+            name,
+            side,
+            quantity,
+            client_order_id,
+        } => {
+            return place_spread_order(state, session, &account_id, &name, side, quantity, client_order_id).await;
+        }
+        ClientMessage::StartGridBot(config) => grid::start(state, config).await,
+        ClientMessage::StopGridBot { account_id, symbol } => grid::stop(state, &account_id, &symbol).await,
+        ClientMessage::GridBots { account_id } => Ok(ServerMessage::GridBots {
+            bots: state.grid_bots.lock().await.account_bots(&account_id),
+            account_id,
+        }),
+        ClientMessage::StartBot(config) => bots::start(state, config).await,
+        ClientMessage::StopBot { account_id, symbol } => bots::stop(state, &account_id, &symbol).await,
+        ClientMessage::PauseBot { account_id, symbol } => bots::pause(state, &account_id, &symbol).await,
+        ClientMessage::ResumeBot { account_id, symbol } => bots::resume(state, &account_id, &symbol).await,
+        ClientMessage::UpdateBot {
+            account_id,
+            symbol,
+            params,
+        } => bots::update_params(state, &account_id, &symbol, params).await,
+        ClientMessage::Bots { account_id } => Ok(ServerMessage::Bots {
+            bots: state.bots.lock().await.account_bots(&account_id),
+            account_id,
+        }),
+        ClientMessage::AmendOrder(request) => return amend_order(state, request).await,
+        ClientMessage::CancelOrder {
+            account_id,
+            order_id,
+        } => {
+            if let Err(reply) = admit_order_request(state, &account_id, LimitKind::Cancel, 1).await {
+                return reply;
+            }
+            backend
+                .cancel_order(&account_id, order_id)
+                .await
+                .map(ServerMessage::Order)
+                .map_err(|e| e.to_string())
+        }
+        ClientMessage::CancelOrderGroup {
+            account_id,
+            group_id,
+        } => {
+            if let Err(reply) = admit_order_request(state, &account_id, LimitKind::Cancel, 1).await {
+                return reply;
+            }
+            backend
+                .cancel_group(&account_id, group_id)
+                .await
+                .map(ServerMessage::OrderGroup)
+                .map_err(|e| e.to_string())
+        }
+        ClientMessage::OrderGroupStatus {
+            account_id,
+            group_id,
+        } => backend
+            .group_report(&account_id, group_id)
+            .await
+            .map(ServerMessage::OrderGroup)
+            .map_err(|e| e.to_string()),
+        ClientMessage::MirrorAccount { account_id } => db::get_mirror_account(&state.pool, &account_id)
+            .await
+            .map(ServerMessage::MirrorAccount)
+            .map_err(|e| format!("Error loading mirrored account: {}", e)),
+        ClientMessage::Portfolio {
+            account_id,
+            recalculate_risk,
+        } => portfolio_report(state, &account_id, recalculate_risk)
+            .await
+            .map(ServerMessage::Portfolio)
+            .map_err(|e| format!("Error loading portfolio: {}", e)),
+        ClientMessage::SetPublicProfile {
+            account_id,
+            enabled,
+            delay_secs,
+        } => profiles::set_public_profile(state, &account_id, enabled, delay_secs).await,
+        ClientMessage::Benchmarks { account_id, days } => benchmarks::benchmark_report(state, &account_id, days)
+            .await
+            .map(ServerMessage::Benchmarks),
+        ClientMessage::PnlAttribution {
+            account_id,
+            start_time,
+            end_time,
+        } => attribution::attribution_report(&state.pool, &account_id, start_time, end_time)
+            .await
+            .map(ServerMessage::PnlAttribution)
+            .map_err(|e| format!("Error computing P&L attribution: {}", e)),
+        ClientMessage::ExplainFill {
+            account_id,
+            order_id,
+            fill_time,
+        } => explain::explain_fill(state, &account_id, order_id, fill_time)
+            .await
+            .map(ServerMessage::FillExplanation),
+        ClientMessage::SetDrawdownAlert(settings) => {
+            if !(settings.threshold > 0.0 && settings.threshold < 1.0) {
+                return ServerMessage::Error {
+                    message: "Drawdown threshold must be between 0 and 1".to_string(),
+                };
+            }
+            let item_id = format!("drawdown:{}", settings.account_id);
+            let bytes = serde_json::to_string(&settings).map_or(0, |json| json.len());
+            if let Err(message) = storage::check(state, session, StorageKind::Alert, &item_id, bytes).await {
+                return ServerMessage::Error { message };
+            }
+            match db::save_drawdown_alert(&state.pool, &settings).await {
+                Ok(()) => {
+                    let user_id = session.user_id.as_deref();
+                    storage::record(state, user_id, StorageKind::Alert, &item_id, bytes as u64).await;
+                    state.drawdowns.lock().await.set_alert(settings.clone());
+                    Ok(ServerMessage::DrawdownAlertSet(settings))
+                }
+                Err(e) => Err(format!("Error saving drawdown alert: {}", e)),
+            }
+        }
+        ClientMessage::SetDepegAlert(settings) => {
+            if !(settings.threshold > 0.0 && settings.threshold < 1.0) {
+                return ServerMessage::Error {
+                    message: "Depeg threshold must be between 0 and 1".to_string(),
+                };
+            }
+            let item_id = format!("depeg:{}", settings.account_id);
+            let bytes = serde_json::to_string(&settings).map_or(0, |json| json.len());
+            if let Err(message) = storage::check(state, session, StorageKind::Alert, &item_id, bytes).await {
+                return ServerMessage::Error { message };
+            }
+            match db::save_depeg_alert(&state.pool, &settings).await {
+                Ok(()) => {
+                    let user_id = session.user_id.as_deref();
+                    storage::record(state, user_id, StorageKind::Alert, &item_id, bytes as u64).await;
+                    state.depeg.lock().await.set_alert(settings.clone());
+                    Ok(ServerMessage::DepegAlertSet(settings))
+                }
+                Err(e) => Err(format!("Error saving depeg alert: {}", e)),
+            }
+        }
+        ClientMessage::SetDailyReport(schedule) => {
+            if reports::parse_send_at(&schedule.send_at).is_none() {
+                return ServerMessage::Error {
+                    message: "send_at must be an HH:MM time in UTC".to_string(),
+                };
+            }
+            db::save_report_schedule(&state.pool, &schedule)
+                .await
+                .map(|_| ServerMessage::DailyReportSet(schedule))
+                .map_err(|e| format!("Error saving report schedule: {}", e))
+        }
+        ClientMessage::SetTradingRules(trading_rules) => rules::set_rules(state, trading_rules).await,
+        ClientMessage::Inbox {
+            account_id,
+            unread_only,
+            limit,
+        } => inbox(state, account_id, unread_only, limit.unwrap_or(100).clamp(1, 1000))
+            .await
+            .map_err(|e| format!("Error loading inbox: {}", e)),
+        ClientMessage::MarkRead { account_id, ids } => db::mark_notifications_read(&state.pool, &account_id, &ids)
+            .await
+            .map(|updated| ServerMessage::MarkedRead { account_id, updated })
+            .map_err(|e| format!("Error updating inbox: {}", e)),
+        ClientMessage::AddNotificationChannel(channel) => {
+            if channel.target.trim().is_empty() {
+                return ServerMessage::Error {
+                    message: "Notification target is required".to_string(),
+                };
+            }
+            match db::save_notification_channel(&state.pool, &channel).await {
+                Ok(()) => {
+                    let account_id = channel.account_id.clone();
+                    let channels = state.notifications.add_channel(channel).await;
+                    Ok(ServerMessage::NotificationChannels { account_id, channels })
+                }
+                Err(e) => Err(format!("Error saving notification channel: {}", e)),
+            }
+        }
+        ClientMessage::RemoveNotificationChannel(channel) => {
+            match db::delete_notification_channel(&state.pool, &channel).await {
+                Ok(()) => Ok(ServerMessage::NotificationChannels {
+                    account_id: channel.account_id.clone(),
+                    channels: state.notifications.remove_channel(&channel).await,
+                }),
+                Err(e) => Err(format!("Error removing notification channel: {}", e)),
+            }
+        }
+        ClientMessage::Candles {
+            series,
+            limit,
+            timeline,
+            account_id,
+            format,
+        } => match candles::get_candle_batch(&state.pool, &series, limit, timeline).await {
+            Ok(mut series) => {
+                let overlaid = match account_id {
+                    Some(account_id) => candles::add_trade_overlays(state, &account_id, &mut series).await,
+                    None => Ok(()),
+                };
+                overlaid.map(|_| match format {
+                    WireFormat::Standard | WireFormat::Raw => ServerMessage::Candles { series },
+                    WireFormat::Compact => ServerMessage::CompactCandles {
+                        s: series.into_iter().map(Into::into).collect(),
+                    },
+                })
+            }
+            Err(message) => Err(message),
+        },
+        ClientMessage::AnalyticsQuery { sql, limit } => {
+            if let Err(message) = feature_flags::require(state, "analytics", session.user_id.as_deref()).await {
+                return ServerMessage::Error { message };
+            }
+            analytics::query(state, &sql, limit).await.map(ServerMessage::AnalyticsResult)
+        }
+        ClientMessage::ExportCandles {
+            symbol,
+            interval,
+            start_time,
+            end_time,
+        } => {
+            // Fixed now, so a retried job exports the same range
+            let end_time = end_time.unwrap_or_else(|| state.simulation.now_millis());
+            if candles::interval_seconds(&interval).is_none() {
+                Err(format!("Unsupported candle interval {}", interval))
+            } else if end_time <= start_time {
+                Err("The range end must be after its start".to_string())
+            } else {
+                let token = auth::generate_token();
+                // The size is only known once written, a new export needs room left under the quota
+                if let Err(message) = storage::check(state, session, StorageKind::Export, &token, 0).await {
+                    return ServerMessage::Error { message };
+                }
+                let task = Task::CandleExport {
+                    token: token.clone(),
+                    connection_id: session.connection_id,
+                    user_id: session.user_id.clone(),
+                    symbol: symbol.trim().to_uppercase(),
+                    interval,
+                    start_time,
+                    end_time,
+                };
+                match jobs::enqueue(&state.pool, &task).await {
+                    Ok(Some(job_id)) => Ok(ServerMessage::ExportQueued { token, job_id }),
+                    Ok(None) => Err("The export is already queued".to_string()),
+                    Err(e) => Err(format!("Error queueing the export: {}", e)),
+                }
+            }
+        }
+        // The connection loop tracks its own subscriptions, this only acknowledges them
+        ClientMessage::Subscribe {
+            symbols,
+            interval_ms,
+            fields,
+            format,
+        } => field_selection(fields.as_deref(), TICKER_FIELDS).map(|fields| ServerMessage::Subscribed {
+            symbols: normalize_symbols(&symbols),
+            interval_ms: state.update_intervals.ticker(interval_ms),
+            fields,
+            format,
+        }),
+        ClientMessage::Unsubscribe { symbols } => Ok(ServerMessage::Unsubscribed {
+            symbols: normalize_symbols(&symbols),
+        }),
+        ClientMessage::SubscribeIndex(mut definition) => {
+            for component in &mut definition.components {
+                component.symbol = component.symbol.trim().to_uppercase();
+            }
+            index::validate(&definition).map(|_| ServerMessage::IndexSubscribed(definition))
+        }
+        ClientMessage::UnsubscribeIndex { name } => Ok(ServerMessage::IndexUnsubscribed { name }),
+        ClientMessage::DefineSpread(mut definition) => {
+            if let Err(message) = spread::validate(&mut definition) {
+                return ServerMessage::Error { message };
+            }
+            match db::save_spread(&state.pool, &definition).await {
+                Ok(()) => spreads(state, definition.account_id).await,
+                Err(e) => Err(format!("Error saving spread: {}", e)),
+            }
+        }
+        ClientMessage::RemoveSpread { account_id, name } => {
+            match db::delete_spread(&state.pool, &account_id, &name).await {
+                Ok(true) => spreads(state, account_id).await,
+                Ok(false) => Err(format!("Unknown spread {}", name)),
+                Err(e) => Err(format!("Error removing spread: {}", e)),
+            }
+        }
+        ClientMessage::ListSpreads { account_id } => spreads(state, account_id).await,
+        ClientMessage::SubscribeSpread { account_id, name } => spread::definition(state, &account_id, &name)
+            .await
+            .map(ServerMessage::SpreadSubscribed),
+        ClientMessage::UnsubscribeSpread { name } => Ok(ServerMessage::SpreadUnsubscribed { name }),
+        ClientMessage::SpreadCandles {
+            account_id,
+            name,
+            interval,
+            limit,
+        } => match spread::definition(state, &account_id, &name).await {
+            Ok(definition) => spread::candles(state, &definition, &interval, limit)
+                .await
+                .map(|candles| ServerMessage::SpreadCandles {
+                    name: definition.name,
+                    interval,
+                    candles,
+                }),
+            Err(message) => Err(message),
+        },
+        ClientMessage::SubscribeDepeg => {
+            let depeg = state.depeg.lock().await;
+            Ok(ServerMessage::DepegSubscribed {
+                threshold: depeg.threshold(),
+                pairs: depeg.statuses(),
+            })
+        }
+        ClientMessage::UnsubscribeDepeg => Ok(ServerMessage::DepegUnsubscribed),
+        ClientMessage::IngestionStats => Ok(ServerMessage::IngestionStats(
+            state.ingest_metrics.lock().await.report(),
+        )),
+        ClientMessage::Authenticate { token } => match auth::authenticate(state, &token).await {
+            Ok(Some((session, tokens))) => Ok(ServerMessage::Authenticated {
+                user_id: session.user_id.unwrap_or_default(),
+                role: session.role,
+                key: session.key,
+                session_id: session.session_id,
+                tenant_id: session.tenant_id,
+                tokens,
+            }),
+            Ok(None) => Err("Invalid token".to_string()),
+            Err(e) => Err(format!("Error checking token: {}", e)),
+        },
+        ClientMessage::RefreshSession { refresh_token } => auth::refresh_session(state, &refresh_token)
+            .await
+            .map(ServerMessage::SessionRefreshed),
+        // Other connections resuming the same login are logged out with it
+        ClientMessage::Logout => match &session.session_id {
+            Some(session_id) => db::revoke_auth_session(&state.pool, session_id)
+                .await
+                .map(|_| {
+                    let _ = state.revocations.send(auth::Revocation::Session(session_id.clone()));
+                    ServerMessage::LoggedOut
+                })
+                .map_err(|e| format!("Error revoking session: {}", e)),
+            None => Ok(ServerMessage::LoggedOut),
+        },
+        ClientMessage::RevokeSessions { user_id } => db::revoke_user_sessions(&state.pool, &user_id)
+            .await
+            .map(|count| {
+                let _ = state.revocations.send(auth::Revocation::User(user_id.clone()));
+                ServerMessage::SessionsRevoked { user_id, count }
+            })
+            .map_err(|e| format!("Error revoking sessions: {}", e)),
+        ClientMessage::CreateApiKey {
+            scopes,
+            account_id,
+            rate_limit,
+        } => {
+            let Some(user_id) = &session.user_id else {
+                return ServerMessage::Error {
+                    message: "Authenticate before managing API keys".to_string(),
+                };
+            };
+            // A key can only hand out what it has itself
+            if let Some(parent) = &session.key {
+                if let Some(scope) = scopes.iter().find(|scope| !parent.scopes.contains(scope)) {
+                    return ServerMessage::Error {
+                        message: format!("API key {} lacks the {} scope", parent.key_id, scope.name()),
+                    };
+                }
+            }
+            let (key_id, secret) = auth::generate_api_key();
+            let key = ApiKey {
+                key_id,
+                user_id: user_id.clone(),
+                scopes,
+                account_id,
+                rate_limit,
+                created_at: engine::now_millis(),
+                revoked: false,
+            };
+            db::create_api_key(&state.pool, &key, &auth::hash_secret(&secret))
+                .await
+                .map(|_| ServerMessage::ApiKeyCreated { key, secret })
+                .map_err(|e| format!("Error creating API key: {}", e))
+        }
+        ClientMessage::RevokeApiKey { key_id } => match &session.user_id {
+            Some(user_id) => match db::revoke_api_key(&state.pool, user_id, &key_id).await {
+                Ok(true) => {
+                    let _ = state.revocations.send(auth::Revocation::Key(key_id.clone()));
+                    Ok(ServerMessage::ApiKeyRevoked { key_id })
+                }
+                Ok(false) => Err(format!("Unknown API key {}", key_id)),
+                Err(e) => Err(format!("Error revoking API key: {}", e)),
+            },
+            None => Err("Authenticate before managing API keys".to_string()),
+        },
+        ClientMessage::ListApiKeys => match &session.user_id {
+            Some(user_id) => db::get_api_keys(&state.pool, user_id)
+                .await
+                .map(|keys| ServerMessage::ApiKeys { keys })
+                .map_err(|e| format!("Error loading API keys: {}", e)),
+            None => Err("Authenticate before managing API keys".to_string()),
+        },
+        ClientMessage::ResetAccount { account_id } => accounts::reset_account(state, &account_id).await,
+        ClientMessage::TopUp { account_id, amount } => accounts::top_up(state, &account_id, amount).await,
+        ClientMessage::CreateGuest {
+            starting_balance,
+            template,
+        } => guests::create_guest(state, starting_balance, template).await,
+        ClientMessage::CreateAccount { account_id, template } => {
+            accounts::create_account(state, &account_id, template).await
+        }
+        ClientMessage::CreateTeam {
+            account_id,
+            name,
+            approval_mode,
+        } => teams::create_team(state, session, &account_id, name, approval_mode).await,
+        ClientMessage::SetTeamMember {
+            account_id,
+            user_id,
+            role,
+        } => teams::set_member(state, session, &account_id, &user_id, role).await,
+        ClientMessage::SetTeamApproval {
+            account_id,
+            enabled,
+            min_notional,
+        } => teams::set_approval_mode(state, session, &account_id, enabled, min_notional).await,
+        ClientMessage::TeamBlotter { account_id, limit } => {
+            teams::blotter(state, &account_id, limit.unwrap_or(100).clamp(1, 1000)).await
+        }
+        ClientMessage::OrderProposals { account_id } => db::get_order_proposals(&state.pool, &account_id, 100)
+            .await
+            .map(|proposals| ServerMessage::OrderProposals { account_id, proposals })
+            .map_err(|e| format!("Error loading proposals: {}", e)),
+        ClientMessage::ApproveOrder {
+            account_id,
+            proposal_id,
+        } => match teams::decide(state, session, &account_id, proposal_id, true).await {
+            Ok(mut proposal) => {
+                let placed = submit_order(state, &account_id, proposal.request.clone()).await;
+                let order_id = placed.as_ref().ok().map(|order| order.id);
+                if let Err(e) = db::finish_order_proposal(&state.pool, proposal_id, order_id).await {
+                    eprintln!("Error updating proposal {}: {:?}", proposal_id, e);
+                }
+                match placed {
+                    Ok(order) => {
+                        if let Err(e) =
+                            db::save_order_attribution(&state.pool, &account_id, &proposal.proposed_by, &[order.id])
+                                .await
+                        {
+                            eprintln!("Error attributing orders on {}: {:?}", account_id, e);
+                        }
+                        proposal.order_id = Some(order.id);
+                        Ok(ServerMessage::ProposalApproved { proposal, order })
+                    }
+                    Err(reply) => return reply,
+                }
+            }
+            Err(message) => Err(message),
+        },
+        ClientMessage::RejectOrder {
+            account_id,
+            proposal_id,
+        } => teams::decide(state, session, &account_id, proposal_id, false)
+            .await
+            .map(ServerMessage::ProposalRejected),
+        ClientMessage::ListAccountTemplates => Ok(ServerMessage::AccountTemplates {
+            templates: state.account_templates.lock().await.list(),
+        }),
+        ClientMessage::SetAccountTemplate(template) => accounts::set_template(state, template).await,
+        ClientMessage::CreateCompetition {
+            name,
+            enrollment_start,
+            enrollment_end,
+            start_time,
+            end_time,
+            rules,
+        } => {
+            let competition = Competition {
+                id: 0,
+                name,
+                enrollment_start,
+                enrollment_end,
+                start_time,
+                end_time,
+                rules: None,
+                finalized: false,
+            };
+            competitions::create_competition(state, competition, rules).await
+        }
+        ClientMessage::ListCompetitions => db::get_competitions(&state.pool)
+            .await
+            .map(|competitions| ServerMessage::Competitions { competitions })
+            .map_err(|e| format!("Error loading competitions: {}", e)),
+        ClientMessage::EnrollCompetition { competition_id } => match &session.user_id {
+            Some(user_id) => competitions::enroll(state, user_id, competition_id).await,
+            None => Err("Authenticate before enrolling".to_string()),
+        },
+        ClientMessage::CompetitionStandings { competition_id } => {
+            competitions::standings(state, competition_id).await
+        }
+        ClientMessage::GetSettings => match &session.user_id {
+            Some(user_id) => db::get_user_settings(&state.pool, user_id)
+                .await
+                .map(|settings| ServerMessage::Settings { settings })
+                .map_err(|e| format!("Error loading settings: {}", e)),
+            None => Err("Authenticate before using settings".to_string()),
+        },
+        ClientMessage::SetSetting { key, value } => {
+            let Some(user_id) = &session.user_id else {
+                return ServerMessage::Error {
+                    message: "Authenticate before using settings".to_string(),
+                };
+            };
+            set_setting(state, session, user_id, key, value).await
+        }
+        ClientMessage::AuditLog {
+            actor,
+            account_id,
+            action,
+            start_time,
+            end_time,
+            limit,
+        } => db::get_audit_log(
+            &state.pool,
+            actor.as_deref(),
+            account_id.as_deref(),
+            action.as_deref(),
+            start_time,
+            end_time,
+            limit.unwrap_or(100).clamp(1, 1000),
+        )
+        .await
+        .map(|entries| ServerMessage::AuditLog { entries })
+        .map_err(|e| format!("Error loading audit log: {}", e)),
+        ClientMessage::UsageStats {
+            tenant_id,
+            start_time,
+            end_time,
+            bucket_secs,
+            limit,
+        } => {
+            let bucket_secs = bucket_secs.unwrap_or(3600).max(60);
+            let limit = limit.unwrap_or(168).clamp(1, 5000);
+            let stats = match tenant_id {
+                Some(tenant_id) => {
+                    db::get_tenant_usage_stats(&state.pool, &tenant_id, start_time, end_time, bucket_secs, limit)
+                        .await
+                        .map(|snapshots| ServerMessage::TenantUsageStats { tenant_id, snapshots })
+                }
+                None => db::get_usage_stats(&state.pool, start_time, end_time, bucket_secs, limit)
+                    .await
+                    .map(|snapshots| ServerMessage::UsageStats { snapshots }),
+            };
+            stats.map_err(|e| format!("Error loading usage stats: {}", e))
+        }
+        ClientMessage::AccountEvents {
+            account_id,
+            after_sequence,
+            limit,
+        } => db::get_account_events(
+            &state.pool,
+            &account_id,
+            after_sequence.unwrap_or(0),
+            Some(limit.unwrap_or(100).clamp(1, 1000)),
+        )
+        .await
+        .map(|events| ServerMessage::AccountEvents { account_id, events })
+        .map_err(|e| format!("Error loading account events: {}", e)),
+        ClientMessage::CreateUser { user_id, role, tenant_id } => {
+            let tenant_id = match tenants::user_tenant(state, session, tenant_id).await {
+                Ok(tenant_id) => tenant_id,
+                Err(message) => return ServerMessage::Error { message },
+            };
+            let token = auth::generate_token();
+            db::create_user(&state.pool, &user_id, role, tenant_id.as_deref(), &auth::hash_secret(&token))
+                .await
+                .map(|_| ServerMessage::UserCreated {
+                    user_id,
+                    role,
+                    tenant_id,
+                    token,
+                })
+                .map_err(|e| format!("Error creating user: {}", e))
+        }
+        ClientMessage::SetUserRole { user_id, role } => match db::set_user_role(&state.pool, &user_id, role).await {
+            Ok(true) => Ok(ServerMessage::UserUpdated { user_id, role }),
+            Ok(false) => Err(format!("Unknown user {}", user_id)),
+            Err(e) => Err(format!("Error updating user: {}", e)),
+        },
+        ClientMessage::Backfill {
+            symbol,
+            interval,
+            start_time,
+            end_time,
+            ..
+        } => {
+            if candles::interval_seconds(&interval).is_none() {
+                return ServerMessage::Error {
+                    message: format!("Unsupported kline interval {}", interval),
+                };
+            }
+            // Loads can take minutes, a job worker runs them and progress goes to the server log
+            let task = Task::Backfill {
+                symbol: symbol.clone(),
+                interval: interval.clone(),
+                start_time,
+                end_time,
+            };
+            queue_load(state, task, symbol, interval).await
+        }
+        ClientMessage::ImportKlines {
+            symbol,
+            interval,
+            path,
+            ..
+        } => {
+            let task = Task::ImportKlines {
+                symbol: symbol.clone(),
+                interval: interval.clone(),
+                path,
+            };
+            queue_load(state, task, symbol, interval).await
+        }
+        ClientMessage::ListJobs { status, limit } => db::list_jobs(&state.pool, status, limit.unwrap_or(100).clamp(1, 1000))
+            .await
+            .map(|jobs| ServerMessage::Jobs { jobs })
+            .map_err(|e| format!("Error loading jobs: {}", e)),
+        ClientMessage::RetryJob { job_id } => match db::retry_dead_job(&state.pool, job_id).await {
+            Ok(true) => Ok(ServerMessage::JobRetried { job_id }),
+            Ok(false) => Err(format!("No dead job {}", job_id)),
+            Err(e) => Err(format!("Error retrying job: {}", e)),
+        },
+        ClientMessage::SimulateOutage {
+            mode, duration_secs, ..
+        } => {
+            let mut chaos = state.chaos.lock().await;
+            if duration_secs == 0 {
+                chaos.clear();
+            } else {
+                chaos.start(mode, Duration::from_secs(duration_secs));
+            }
+            println!("Simulated outage {:?} for {}s", mode, duration_secs);
+            Ok(ServerMessage::OutageStarted {
+                mode: format!("{:?}", mode),
+                duration_secs,
+            })
+        }
+        ClientMessage::SetMaintenance {
+            message,
+            starts_at,
+            duration_secs,
+            ..
+        } => {
+            let starts_at = starts_at.unwrap_or_else(|| state.simulation.now_millis());
+            let window = MaintenanceWindow {
+                message,
+                starts_at,
+                ends_at: duration_secs.map(|secs| starts_at + secs as i64 * 1000),
+            };
+            maintenance::schedule(state, window.clone()).await;
+            Ok(ServerMessage::Maintenance(window))
+        }
+        ClientMessage::EndMaintenance { .. } => {
+            maintenance::end(state).await;
+            Ok(ServerMessage::MaintenanceEnded)
+        }
+        ClientMessage::ReloadConfig { .. } => reload::reload(state).await.map(|report| ServerMessage::ConfigReloaded {
+            applied: report.applied,
+            restart_required: report.restart_required,
+        }),
+        ClientMessage::SetFeatureFlag { flag, user_id, enabled, .. } => match feature_flags::validate(&flag) {
+            Ok(()) => match db::save_feature_flag(&state.pool, &flag, user_id.as_deref(), enabled).await {
+                Ok(()) => {
+                    state.feature_flags.lock().await.set(flag.clone(), user_id.clone(), enabled);
+                    let target = user_id.as_deref().unwrap_or("everyone");
+                    println!("Feature flag {} set to {:?} for {}", flag, enabled, target);
+                    Ok(ServerMessage::FeatureFlagSet { flag, user_id, enabled })
+                }
+                Err(e) => Err(format!("Error saving feature flag: {}", e)),
+            },
+            Err(message) => Err(message),
+        },
+        ClientMessage::ListFeatureFlags { .. } => Ok(ServerMessage::FeatureFlags {
+            flags: state.feature_flags.lock().await.list(),
+        }),
+        ClientMessage::SetTenant(tenant) => tenants::set_tenant(state, session, tenant).await,
+        ClientMessage::ListTenants => tenants::list_tenants(state, session).await,
+        ClientMessage::OrderBook { symbol, levels, group } => {
+            depth::book(state, &symbol, levels, group).await.map(ServerMessage::OrderBook)
+        }
+        ClientMessage::SubscribeLadder {
+            account_id,
+            symbol,
+            levels,
+            group,
+        } => ladder::ladder(state, &account_id, &symbol, levels, group)
+            .await
+            .map(|ladder| ServerMessage::LadderSubscribed { levels, ladder }),
+        ClientMessage::UnsubscribeLadder { symbol } => Ok(ServerMessage::LadderUnsubscribed {
+            symbol: symbol.trim().to_uppercase(),
+        }),
+        ClientMessage::LadderOrder {
+            account_id,
+            symbol,
+            side,
+            price,
+            quantity,
+            client_order_id,
+        } => match ladder::click_order(state, &symbol, side, price, quantity, client_order_id).await {
+            Ok(order) => return place_order(state, session, &account_id, order).await,
+            Err(message) => Err(message),
+        },
+        ClientMessage::LadderCancel {
+            account_id,
+            symbol,
+            price,
+            group,
+            side,
+        } => {
+            let order_ids = ladder::row_orders(state, &account_id, &symbol, price, group, side).await;
+            if order_ids.is_empty() {
+                return ServerMessage::Error {
+                    message: format!("No working orders of {} at {}", account_id, price),
+                };
+            }
+            let weight = order_ids.len() as u32;
+            if let Err(reply) = admit_order_request(state, &account_id, LimitKind::Cancel, weight).await {
+                return reply;
+            }
+            let mut orders = Vec::new();
+            for order_id in order_ids {
+                match backend.cancel_order(&account_id, order_id).await {
+                    Ok(order) => orders.push(order),
+                    // Filled or cancelled since the row was read
+                    Err(e) => eprintln!("Error cancelling order {} of {}: {}", order_id, account_id, e),
+                }
+            }
+            Ok(ServerMessage::LadderCancelled {
+                symbol: symbol.trim().to_uppercase(),
+                price,
+                orders,
+            })
+        }
+        ClientMessage::SubscribeTrades { symbol, aggregate } => Ok(tape::subscribe(state, &symbol, aggregate).await),
+        ClientMessage::UnsubscribeTrades { symbol } => Ok(ServerMessage::TradesUnsubscribed {
+            symbol: symbol.trim().to_uppercase(),
+        }),
+        ClientMessage::TradeHistory {
+            symbol,
+            before,
+            limit,
+            aggregate,
+        } => tape::history(state, &symbol, before, limit, aggregate).await,
+        ClientMessage::KeyLevels { symbol } => {
+            key_levels::levels(state, &symbol).await.map(ServerMessage::KeyLevels)
+        }
+        ClientMessage::SubscribeKeyLevels { symbols } => {
+            key_levels::subscribe(state, &normalize_symbols(&symbols)).await
+        }
+        ClientMessage::UnsubscribeKeyLevels { symbols } => Ok(ServerMessage::KeyLevelsUnsubscribed {
+            symbols: normalize_symbols(&symbols),
+        }),
+        ClientMessage::Footprint {
+            symbol,
+            interval,
+            limit,
+            row_size,
+        } => footprint::footprint(state, &symbol, interval, limit, row_size).await,
+        ClientMessage::SubscribeIndicator {
+            symbol,
+            interval,
+            indicator,
+            limit,
+        } => indicators::subscribe(state, &symbol, interval, indicator, limit).await,
+        ClientMessage::UnsubscribeIndicator {
+            symbol,
+            interval,
+            indicator,
+        } => Ok(ServerMessage::IndicatorUnsubscribed {
+            symbol: symbol.trim().to_uppercase(),
+            interval,
+            indicator,
+        }),
+        ClientMessage::StorageUsage { user_id, limit } => {
+            db::get_user_storage(&state.pool, user_id.as_deref(), limit.unwrap_or(100).clamp(1, 1000))
+                .await
+                .map(|users| ServerMessage::StorageUsage { users })
+                .map_err(|e| format!("Error loading storage usage: {}", e))
+        }
+    };
+
+    result.unwrap_or_else(|message| ServerMessage::Error { message })
+}
+
+// Backfills and imports have no dedupe key, so queueing one always yields a job
+async fn queue_load(state: &AppState, task: Task, symbol: String, interval: String) -> Result<ServerMessage, String> {
+    match jobs::enqueue(&state.pool, &task).await {
+        Ok(Some(job_id)) => Ok(ServerMessage::BackfillStarted { symbol, interval, job_id }),
+        Ok(None) => Err(format!("A load of {} {} is already queued", symbol, interval)),
+        Err(e) => Err(format!("Error queueing the load: {}", e)),
+    }
+}
+
+const MAX_SETTING_KEY_LEN: usize = 64;
+const MAX_SETTING_VALUE_BYTES: usize = 16 * 1024;
+const MAX_SETTINGS_PER_USER: i64 = 200;
+
+async fn set_setting(
+    state: &AppState,
+    session: &Session,
+    user_id: &str,
+    key: String,
+    value: serde_json::Value,
+) -> Result<ServerMessage, String> {
+    let valid_key = !key.is_empty()
+        && key.len() <= MAX_SETTING_KEY_LEN
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !valid_key {
+        return Err(format!("Invalid setting key {:?}", key));
+    }
+    if value.to_string().len() > MAX_SETTING_VALUE_BYTES {
+        return Err(format!("Setting {} is larger than {} bytes", key, MAX_SETTING_VALUE_BYTES));
+    }
+    if !value.is_null() {
+        let settings = db::get_user_settings(&state.pool, user_id)
+            .await
+            .map_err(|e| format!("Error loading settings: {}", e))?;
+        if !settings.contains_key(&key) && settings.len() as i64 >= MAX_SETTINGS_PER_USER {
+            return Err(format!("At most {} settings can be stored", MAX_SETTINGS_PER_USER));
+        }
+        if let Some(tenant_id) = &session.tenant_id {
+            let replaced = settings.get(&key).map_or(0, |previous| previous.to_string().len());
+            tenants::check_storage(state, tenant_id, value.to_string().len(), replaced).await?;
+        }
+    }
+
+    db::set_user_setting(&state.pool, user_id, &key, &value)
+        .await
+        .map_err(|e| format!("Error saving setting: {}", e))?;
+    // No receivers just means no other connection of the user is open
+    let _ = state.settings.send(SettingChange {
+        user_id: user_id.to_string(),
+        key: key.clone(),
+        value: value.clone(),
+        origin: session.connection_id,
+    });
+    Ok(ServerMessage::SettingUpdated { key, value })
+}
+
+async fn inbox(
+    state: &AppState,
+    account_id: String,
+    unread_only: bool,
+    limit: i64,
+) -> Result<ServerMessage, sqlx::Error> {
+    let notifications = db::get_notifications(&state.pool, &account_id, unread_only, limit).await?;
+    let unread = db::count_unread_notifications(&state.pool, &account_id).await?;
+    Ok(ServerMessage::Inbox {
+        account_id,
+        unread,
+        notifications,
+    })
+}
+
+async fn portfolio_report(
+    state: &AppState,
+    account_id: &str,
+    recalculate_risk: bool,
+) -> Result<PortfolioReport, sqlx::Error> {
+    let mut report = state.portfolio_report(account_id).await;
+
+    report.risk = if recalculate_risk {
+        let risk = risk::compute_risk(&state.pool, &report.positions).await?;
+        if let Some(risk) = &risk {
+            db::save_portfolio_risk(&state.pool, account_id, risk).await?;
+        }
+        risk
+    } else {
+        db::get_latest_portfolio_risk(&state.pool, account_id).await?
+    };
+    report.exposure =
+        Some(exposure::exposure_report(&state.pool, &report.positions, report.equity).await?);
+
+    Ok(report)
+}
+
+// A single order, proposed instead when it is above the account's team approval threshold
+async fn place_order(state: &AppState, session: &Session, account_id: &str, order: OrderRequest) -> ServerMessage {
+    let notional = teams::order_notional(state, &order).await;
+    if state.teams.lock().await.requires_approval(account_id, notional) {
+        return teams::propose(state, session, account_id, order)
+            .await
+            .unwrap_or_else(|message| ServerMessage::Error { message });
+    }
+    let order = match submit_order(state, account_id, order).await {
+        Ok(order) => order,
+        Err(reply) => return reply,
+    };
+    teams::attribute(state, session, account_id, &[order.id]).await;
+    ServerMessage::Order(order)
+}
+
+// The order as amended passes the same checks as a new one. Amendments can't be proposed, so
+// one that takes the order above the approval threshold is rejected.
+async fn amend_order(state: &AppState, request: AmendOrderRequest) -> ServerMessage {
+    let account_id = request.account_id.clone();
+    let order = match state.backend.order(&account_id, request.order_id).await {
+        Ok(order) => order,
+        Err(e) => return ServerMessage::Error { message: e.to_string() },
+    };
+    let amended = OrderRequest {
+        symbol: order.symbol,
+        side: order.side,
+        order_type: order.order_type,
+        price: request.price.or(order.price),
+        quantity: request.quantity.unwrap_or(order.quantity),
+        time_in_force: request.time_in_force.unwrap_or(order.time_in_force),
+        client_order_id: None,
+    };
+    let notional = teams::order_notional(state, &amended).await;
+    if state.teams.lock().await.requires_approval(&account_id, notional) {
+        return ServerMessage::Error {
+            message: "Amendments above the approval threshold can't be proposed, place a new order for approval"
+                .to_string(),
+        };
+    }
+    if let Err(message) = accounts::check_order(state, &account_id, &amended).await {
+        return ServerMessage::Error { message };
+    }
+    if let Err(message) = rules::check_orders(state, &account_id, 0).await {
+        return ServerMessage::Error { message };
+    }
+    if let Err(reply) = admit_order_request(state, &account_id, LimitKind::Order, 1).await {
+        return reply;
+    }
+    match state.backend.amend_order(request).await {
+        Ok(order) => ServerMessage::Order(order),
+        Err(e) => ServerMessage::Error { message: e.to_string() },
+    }
+}
+
+async fn spreads(state: &AppState, account_id: String) -> Result<ServerMessage, String> {
+    db::get_spreads(&state.pool, &account_id)
+        .await
+        .map(|spreads| ServerMessage::Spreads { account_id, spreads })
+        .map_err(|e| format!("Error loading spreads: {}", e))
+}
+
+// Both legs pass the same checks as single orders before either is placed. Like order groups,
+// spreads can't be proposed for approval.
+async fn place_spread_order(
+    state: &AppState,
+    session: &Session,
+    account_id: &str,
+    name: &str,
+    side: Side,
+    quantity: f64,
+    client_order_id: Option<String>,
+) -> ServerMessage {
+    let definition = match spread::definition(state, account_id, name).await {
+        Ok(definition) => definition,
+        Err(message) => return ServerMessage::Error { message },
+    };
+    let legs = spread::legs(&definition, side, quantity, client_order_id.as_deref());
+    let mut notional = Some(0.0);
+    for leg in &legs {
+        notional = notional.zip(teams::order_notional(state, leg).await).map(|(sum, leg)| sum + leg);
+    }
+    if state.teams.lock().await.requires_approval(account_id, notional) {
+        return ServerMessage::Error {
+            message: "Spread orders above the approval threshold can't be proposed, place single orders for approval"
+                .to_string(),
+        };
+    }
+    for leg in &legs {
+        if let Err(message) = accounts::check_order(state, account_id, leg).await {
+            return ServerMessage::Error { message };
+        }
+    }
+    if let Err(message) = rules::check_orders(state, account_id, legs.len() as u32).await {
+        return ServerMessage::Error { message };
+    }
+    if let Err(reply) = admit_order_request(state, account_id, LimitKind::Order, legs.len() as u32).await {
+        return reply;
+    }
+    let mut placed = match state.backend.place_legs(account_id, legs).await {
+        Ok(placed) => placed,
+        Err(e) => return ServerMessage::Error { message: e.to_string() },
+    };
+    let order_ids: Vec<u64> = placed.iter().map(|order| order.id).collect();
+    teams::attribute(state, session, account_id, &order_ids).await;
+    let short_leg = placed.pop();
+    match (placed.pop(), short_leg) {
+        (Some(long_leg), Some(short_leg)) => ServerMessage::SpreadOrder(SpreadOrderReport {
+            spread: definition,
+            side,
+            quantity,
+            long_leg,
+            short_leg,
+        }),
+        _ => ServerMessage::Error {
+            message: "The execution backend returned an incomplete spread".to_string(),
+        },
+    }
+}
+
+// Account and self-imposed trading rules, then the order-entry gate, then the execution backend
+async fn submit_order(state: &AppState, account_id: &str, order: OrderRequest) -> Result<Order, ServerMessage> {
+    if let Err(message) = accounts::check_order(state, account_id, &order).await {
+        return Err(ServerMessage::Error { message });
+    }
+    if let Err(message) = rules::check_orders(state, account_id, 1).await {
+        return Err(ServerMessage::Error { message });
+    }
+    admit_order_request(state, account_id, LimitKind::Order, 1).await?;
+    state.backend.place_order(account_id, order).await.map_err(|e| ServerMessage::Error {
+        message: e.to_string(),
+    })
+}
+
+// Applies a key's own request limit, shared by every connection using that key
+async fn admit_key_request(state: &AppState, key: &ApiKey) -> Result<(), ServerMessage> {
+    let Some(limit) = key.rate_limit else {
+        return Ok(());
+    };
+    let checked = state
+        .rate_limiter
+        .lock()
+        .await
+        .check_with_limit(&key.key_id, LimitKind::ApiKey, 1, limit);
+    checked.map_err(|e| rate_limited(e, &format!("API key {}", key.key_id)))
+}
+
+fn rate_limited(error: RateLimitError, owner: &str) -> ServerMessage {
+    match error {
+        RateLimitError::Exceeded {
+            kind,
+            limit,
+            retry_after,
+        } => ServerMessage::RateLimited {
+            limit: kind.name().to_string(),
+            max_weight: limit,
+            retry_after_ms: retry_after.as_millis() as u64,
+            message: format!("Too many {} requests for {}, retry later", kind.name(), owner),
+        },
+        RateLimitError::WeightExceedsLimit { kind, limit, weight } => ServerMessage::Error {
+            message: format!(
+                "The request weighs {} but the {} limit for {} is {}, it can never be accepted",
+                weight,
+                kind.name(),
+                owner,
+                limit
+            ),
+        },
+    }
+}
+
+// Gate every order-entry request the way an exchange gateway would: simulated outages first,
+// then the account's rate limits, then the artificial network latency
+async fn admit_order_request(
+    state: &AppState,
+    account_id: &str,
+    kind: LimitKind,
+    weight: u32,
+) -> Result<(), ServerMessage> {
+    let failure = state.chaos.lock().await.order_failure();
+    if let Some(failure) = failure {
+        state.simulation.sleep(failure.delay).await;
+        return Err(ServerMessage::ExchangeError {
+            status: failure.status,
+            message: failure.message,
+        });
+    }
+
+    let checked = state.rate_limiter.lock().await.check(account_id, kind, weight);
+    checked.map_err(|e| rate_limited(e, &format!("account {}", account_id)))?;
+
+    state.latency.order_entry.wait(&state.simulation).await;
+    Ok(())
+}
+
+pub fn normalize_symbols(symbols: &[String]) -> Vec<String> {
+    symbols
+        .iter()
+        .map(|symbol| symbol.trim().to_uppercase())
+        .filter(|symbol| !symbol.is_empty())
+        .collect()
+}
+
+// Fields a subscriber may pick, the symbol is always sent
+pub const TICKER_FIELDS: &[&str] = &["price", "quote_volume", "event_time"];
+pub const PAGE_ROW_FIELDS: &[&str] = &["price", "volume", "quote_asset", "usd_price", "usd_volume"];
+
+// The ticker list filter and order a pagination message asks for, what it leaves out is kept
+pub fn ticker_query(state: &AppState, params: &PaginationParams, current: &TickerQuery) -> Result<TickerQuery, String> {
+    let quote = match params.quote.as_deref().map(|quote| quote.trim().to_uppercase()) {
+        None => current.quote.clone(),
+        Some(quote) if quote.is_empty() => None,
+        Some(quote) if state.quote_assets.read().unwrap().is_known(&quote) => Some(quote),
+        Some(quote) => return Err(format!("Unknown quote asset {}", quote)),
+    };
+    Ok(TickerQuery {
+        quote,
+        sort: params.sort.unwrap_or(current.sort),
+    })
+}
+
+// A requested field selection checked against the known fields, None (or an empty list) sends them all
+pub fn field_selection(fields: Option<&[String]>, known: &[&str]) -> Result<Option<Vec<String>>, String> {
+    let Some(fields) = fields.filter(|fields| !fields.is_empty()) else {
+        return Ok(None);
+    };
+    if let Some(unknown) = fields.iter().find(|field| *field != "symbol" && !known.contains(&field.as_str())) {
+        return Err(format!("Unknown field {}, expected one of {}", unknown, known.join(", ")));
+    }
+    Ok(Some(fields.to_vec()))
+}
+
+// Drop the unselected fields of a serialized object, its "type" tag and symbol stay
+fn select_fields(value: &mut serde_json::Value, fields: &[String]) {
+    if let Some(object) = value.as_object_mut() {
+        object.retain(|key, _| key == "type" || key == "symbol" || fields.contains(key));
+    }
+}
+
+// A live ticker frame in the subscription's format, trimmed to the selected fields. Raw frames wrap
+// the exchange's bytes without parsing them again, tickers that didn't come from the feed are sent
+// as standard ones.
+pub fn ticker_frame(update: TickerUpdate, fields: Option<&[String]>, format: WireFormat) -> Option<String> {
+    let selected = |field: &str| fields.is_none_or(|fields| fields.iter().any(|selected| selected == field));
+    match (format, &update.raw) {
+        (WireFormat::Raw, Some(raw)) => Some(format!("{{\"type\":\"raw_ticker\",\"data\":{}}}", raw.get())),
+        (WireFormat::Compact, _) => serde_json::to_string(&CompactTicker {
+            kind: "ticker",
+            s: &update.symbol,
+            p: selected("price").then_some(update.price),
+            q: selected("quote_volume").then_some(update.quote_volume),
+            e: selected("event_time").then_some(update.event_time),
+        })
+        .ok(),
+        _ => {
+            let mut frame = serde_json::to_value(ServerMessage::Ticker(update)).ok()?;
+            if let Some(fields) = fields {
+                select_fields(&mut frame, fields);
+            }
+            serde_json::to_string(&frame).ok()
+        }
+    }
+}
+
+// The ticker page frame in the connection's format, each row trimmed to the selected fields
+pub fn page_frame(page: &PaginatedResponse, fields: Option<&[String]>, format: WireFormat) -> Option<String> {
+    if format == WireFormat::Compact {
+        let selected = |field: &str| fields.is_none_or(|fields| fields.iter().any(|selected| selected == field));
+        let rows = page
+            .data
+            .iter()
+            .map(|row| {
+                let mut values = vec![row.symbol.clone().into()];
+                if selected("price") {
+                    values.push(row.price.into());
+                }
+                if selected("volume") {
+                    values.push(row.volume.into());
+                }
+                if selected("quote_asset") {
+                    values.push(row.quote_asset.clone().into());
+                }
+                if selected("usd_price") {
+                    values.push(row.usd_price.into());
+                }
+                if selected("usd_volume") {
+                    values.push(row.usd_volume.into());
+                }
+                values
+            })
+            .collect();
+        return serde_json::to_string(&CompactPage {
+            n: page.total,
+            pg: page.page,
+            pp: page.per_page,
+            dg: page.degraded,
+            d: rows,
+        })
+        .ok();
+    }
+    let mut value = serde_json::to_value(page).ok()?;
+    if let (Some(fields), Some(rows)) = (fields, value.get_mut("data").and_then(|data| data.as_array_mut())) {
+        for row in rows {
+            select_fields(row, fields);
+        }
+    }
+    serde_json::to_string(&value).ok()
+}
+
+// Connections to `/listings` are pushed symbol listings and delistings
+pub fn is_listings_path(path: &str) -> bool {
+    path.trim_end_matches('/') == "/listings"
+}
+
+// Symbols requested by a `/currency/BTCUSDT,ETHUSDT` connection path
+pub fn path_symbols(path: &str) -> Vec<String> {
+    match path.trim_end_matches('/').strip_prefix("/currency/") {
+        Some(list) => normalize_symbols(&list.split(',').map(str::to_string).collect::<Vec<_>>()),
+        None => Vec::new(),
+    }
+}
+
+// Account a message acts on, used to route that account's fills back to the connection
+pub fn message_account(msg: &ClientMessage) -> Option<&str> {
+    match msg {
+        ClientMessage::PlaceOrder { account_id, .. }
+        | ClientMessage::PlaceOrderGroup { account_id, .. }
+        | ClientMessage::CancelOrder { account_id, .. }
+        | ClientMessage::CancelOrderGroup { account_id, .. }
+        | ClientMessage::OrderGroupStatus { account_id, .. }
+        | ClientMessage::MirrorAccount { account_id }
+        | ClientMessage::Portfolio { account_id, .. }
+        | ClientMessage::ExplainFill { account_id, .. }
+        | ClientMessage::PnlAttribution { account_id, .. }
+        | ClientMessage::Benchmarks { account_id, .. }
+        | ClientMessage::SetPublicProfile { account_id, .. }
+        | ClientMessage::Inbox { account_id, .. }
+        | ClientMessage::AccountEvents { account_id, .. }
+        | ClientMessage::ResetAccount { account_id }
+        | ClientMessage::CreateAccount { account_id, .. }
+        | ClientMessage::TopUp { account_id, .. }
+        | ClientMessage::CreateTeam { account_id, .. }
+        | ClientMessage::SetTeamMember { account_id, .. }
+        | ClientMessage::SetTeamApproval { account_id, .. }
+        | ClientMessage::TeamBlotter { account_id, .. }
+        | ClientMessage::OrderProposals { account_id }
+        | ClientMessage::ApproveOrder { account_id, .. }
+        | ClientMessage::RejectOrder { account_id, .. }
+        | ClientMessage::MarkRead { account_id, .. }
+        | ClientMessage::SubscribeLadder { account_id, .. }
+        | ClientMessage::LadderOrder { account_id, .. }
+        | ClientMessage::LadderCancel { account_id, .. }
+        | ClientMessage::RemoveSpread { account_id, .. }
+        | ClientMessage::ListSpreads { account_id }
+        | ClientMessage::SubscribeSpread { account_id, .. }
+        | ClientMessage::SpreadCandles { account_id, .. }
+        | ClientMessage::PlaceSpreadOrder { account_id, .. }
+        | ClientMessage::StopGridBot { account_id, .. }
+        | ClientMessage::GridBots { account_id }
+        | ClientMessage::StopBot { account_id, .. }
+        | ClientMessage::PauseBot { account_id, .. }
+        | ClientMessage::ResumeBot { account_id, .. }
+        | ClientMessage::UpdateBot { account_id, .. }
+        | ClientMessage::Bots { account_id } => Some(account_id),
+        ClientMessage::StartGridBot(config) => Some(&config.account_id),
+        ClientMessage::StartBot(config) => Some(&config.account_id),
+        ClientMessage::DefineSpread(definition) => Some(&definition.account_id),
+        ClientMessage::AmendOrder(request) => Some(&request.account_id),
+        ClientMessage::Candles { account_id, .. } => account_id.as_deref(),
+        ClientMessage::SetDrawdownAlert(settings) => Some(&settings.account_id),
+        ClientMessage::SetDepegAlert(settings) => Some(&settings.account_id),
+        ClientMessage::SetDailyReport(schedule) => Some(&schedule.account_id),
+        ClientMessage::SetTradingRules(rules) => Some(&rules.account_id),
+        ClientMessage::AddNotificationChannel(channel) | ClientMessage::RemoveNotificationChannel(channel) => {
+            Some(&channel.account_id)
+        }
+        ClientMessage::IngestionStats
+        | ClientMessage::ExportCandles { .. }
+        | ClientMessage::AnalyticsQuery { .. }
+        | ClientMessage::Subscribe { .. }
+        | ClientMessage::Unsubscribe { .. }
+        | ClientMessage::SubscribeIndex(_)
+        | ClientMessage::UnsubscribeIndex { .. }
+        | ClientMessage::UnsubscribeSpread { .. }
+        | ClientMessage::SubscribeDepeg
+        | ClientMessage::UnsubscribeDepeg
+        | ClientMessage::OrderBook { .. }
+        | ClientMessage::UnsubscribeLadder { .. }
+        | ClientMessage::SubscribeTrades { .. }
+        | ClientMessage::UnsubscribeTrades { .. }
+        | ClientMessage::TradeHistory { .. }
+        | ClientMessage::Footprint { .. }
+        | ClientMessage::SubscribeIndicator { .. }
+        | ClientMessage::UnsubscribeIndicator { .. }
+        | ClientMessage::KeyLevels { .. }
+        | ClientMessage::SubscribeKeyLevels { .. }
+        | ClientMessage::UnsubscribeKeyLevels { .. }
+        | ClientMessage::Authenticate { .. }
+        | ClientMessage::RefreshSession { .. }
+        | ClientMessage::Logout
+        | ClientMessage::RevokeSessions { .. }
+        | ClientMessage::CreateApiKey { .. }
+        | ClientMessage::RevokeApiKey { .. }
+        | ClientMessage::ListApiKeys
+        | ClientMessage::CreateGuest { .. }
+        | ClientMessage::ListAccountTemplates
+        | ClientMessage::SetAccountTemplate(_)
+        | ClientMessage::CreateCompetition { .. }
+        | ClientMessage::ListCompetitions
+        | ClientMessage::EnrollCompetition { .. }
+        | ClientMessage::CompetitionStandings { .. }
+        | ClientMessage::GetSettings
+        | ClientMessage::SetSetting { .. }
+        | ClientMessage::AuditLog { .. }
+        | ClientMessage::UsageStats { .. }
+        | ClientMessage::ListJobs { .. }
+        | ClientMessage::RetryJob { .. }
+        | ClientMessage::CreateUser { .. }
+        | ClientMessage::SetUserRole { .. }
+        | ClientMessage::Backfill { .. }
+        | ClientMessage::ImportKlines { .. }
+        | ClientMessage::SimulateOutage { .. }
+        | ClientMessage::SetMaintenance { .. }
+        | ClientMessage::EndMaintenance { .. }
+        | ClientMessage::ReloadConfig { .. }
+        | ClientMessage::SetFeatureFlag { .. }
+        | ClientMessage::ListFeatureFlags { .. }
+        | ClientMessage::SetTenant(_)
+        | ClientMessage::ListTenants
+        | ClientMessage::StorageUsage { .. } => None,
+    }
+}
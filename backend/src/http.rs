@@ -0,0 +1,483 @@
+use crate::analytics;
+use crate::arrow_stream::{self, Dataset};
+use crate::assets;
+use crate::auth::{self, Session};
+use crate::candles;
+use crate::db;
+use crate::depth;
+use crate::exports;
+use crate::feature_flags;
+use crate::graphql;
+use crate::handlers;
+use crate::models::{
+    AnalyticsResult, BenchmarkPoint, BookLevel, BookSnapshot, Candle, ClientMessage, Conversion, DataGap, History,
+    PositionReport, PricePoint, PublicProfile, WireFormat,
+};
+use crate::profiles;
+use crate::state::AppState;
+use crate::updates::{self, UpdateFilter, UpdatesPage};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+// Spec served at /api/openapi.json, browsable at /api/docs
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Trading simulator REST API"),
+    paths(
+        convert,
+        price_at,
+        order_book,
+        history,
+        arrow_history,
+        public_profile,
+        download_export,
+        poll_updates,
+        analytics_query
+    ),
+    components(schemas(
+        AnalyticsQuery,
+        AnalyticsResult,
+        BenchmarkPoint,
+        BookLevel,
+        BookSnapshot,
+        Candle,
+        Conversion,
+        DataGap,
+        ErrorBody,
+        History,
+        PositionReport,
+        PricePoint,
+        PublicProfile,
+        UpdatesPage
+    ))
+)]
+struct ApiDoc;
+
+// Plain request/response endpoints next to the WebSocket server, and the frontend for every other path
+pub async fn serve(state: Arc<AppState>, bind_addr: String) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/convert", get(convert))
+        .route("/price_at", get(price_at))
+        .route("/book/:symbol", get(order_book))
+        .route("/history", get(history))
+        .route("/history/arrow", get(arrow_history))
+        .route("/public/:token", get(public_profile))
+        .route("/exports/:token", get(download_export))
+        .route("/api/updates", get(poll_updates))
+        .route("/analytics/query", post(analytics_query))
+        .route("/graphql", post(graphql::handler))
+        .layer(Extension(graphql::schema()))
+        .merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi()))
+        .fallback(assets::serve)
+        .layer(cors_layer(&state))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
+    println!("HTTP server started on {}", bind_addr);
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
+}
+
+// Same origin allow-list as the WebSocket upgrade
+fn cors_layer(state: &AppState) -> CorsLayer {
+    if !state.allowed_origins.is_restricted() {
+        return CorsLayer::permissive();
+    }
+    let origins = state.allowed_origins.clone();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+            origin.to_str().is_ok_and(|origin| origins.allows(Some(origin)))
+        }))
+        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+        .max_age(Duration::from_secs(3600))
+}
+
+// Body of every non-2xx response
+#[derive(Serialize, ToSchema)]
+struct ErrorBody {
+    error: String,
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    (status, Json(ErrorBody { error: message })).into_response()
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ConvertParams {
+    from: String,
+    to: String,
+    amount: f64,
+}
+
+// GET /convert?from=ETH&to=BTC&amount=2
+#[utoipa::path(
+    get,
+    path = "/convert",
+    params(ConvertParams),
+    responses(
+        (status = 200, body = Conversion),
+        (status = 404, description = "No conversion route between the assets", body = ErrorBody)
+    )
+)]
+async fn convert(State(state): State<Arc<AppState>>, Query(params): Query<ConvertParams>) -> Response {
+    match state.convert(&params.from, &params.to, params.amount).await {
+        Ok(conversion) => Json(conversion).into_response(),
+        Err(message) => error(StatusCode::NOT_FOUND, message),
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PriceAtParams {
+    symbol: String,
+    timestamp: i64, // Epoch milliseconds
+    tolerance_ms: Option<i64>,
+}
+
+const DEFAULT_PRICE_TOLERANCE_MS: i64 = 60_000;
+
+// GET /price_at?symbol=BTCUSDT&timestamp=1700000000000&tolerance_ms=60000
+#[utoipa::path(
+    get,
+    path = "/price_at",
+    params(PriceAtParams),
+    responses(
+        (status = 200, body = PricePoint),
+        (status = 404, description = "No price recorded within the tolerance", body = ErrorBody),
+        (status = 500, body = ErrorBody)
+    )
+)]
+async fn price_at(State(state): State<Arc<AppState>>, Query(params): Query<PriceAtParams>) -> Response {
+    let symbol = params.symbol.trim().to_uppercase();
+    let tolerance = params.tolerance_ms.unwrap_or(DEFAULT_PRICE_TOLERANCE_MS).max(0);
+    match db::get_price_at(&state.pool, &symbol, params.timestamp, tolerance).await {
+        Ok(Some(point)) => Json(point).into_response(),
+        Ok(None) => error(
+            StatusCode::NOT_FOUND,
+            format!("No {} price within {}ms of {}", symbol, tolerance, params.timestamp),
+        ),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, format!("Error loading price: {}", e)),
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct BookParams {
+    levels: Option<usize>, // Buckets per side, BOOK_LEVELS by default
+    group: Option<f64>,    // Bucket width in price units, the exchange's own levels without it
+}
+
+// GET /book/BTCUSDT?levels=50&group=10
+#[utoipa::path(
+    get,
+    path = "/book/{symbol}",
+    params(("symbol" = String, Path, description = "Symbol whose book is followed"), BookParams),
+    responses(
+        (status = 200, body = BookSnapshot),
+        (status = 400, description = "Invalid price group", body = ErrorBody),
+        (status = 404, description = "The symbol's book is not being followed", body = ErrorBody)
+    )
+)]
+async fn order_book(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+    Query(params): Query<BookParams>,
+) -> Response {
+    if let Err(message) = depth::validate_group(params.group) {
+        return error(StatusCode::BAD_REQUEST, message);
+    }
+    match depth::book(&state, &symbol, params.levels, params.group).await {
+        Ok(book) => Json(book).into_response(),
+        Err(message) => error(StatusCode::NOT_FOUND, message),
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryParams {
+    symbol: String,
+    from: i64, // Epoch milliseconds
+    to: i64,
+    max_points: Option<i64>,
+}
+
+// GET /history?symbol=BTCUSDT&from=...&to=...&max_points=500
+#[utoipa::path(
+    get,
+    path = "/history",
+    params(HistoryParams),
+    responses(
+        (status = 200, body = History),
+        (status = 400, description = "Invalid range or symbol", body = ErrorBody)
+    )
+)]
+async fn history(State(state): State<Arc<AppState>>, Query(params): Query<HistoryParams>) -> Response {
+    let symbol = params.symbol.trim().to_uppercase();
+    match candles::get_history(&state.pool, &symbol, params.from, params.to, params.max_points).await {
+        Ok(history) => Json(history).into_response(),
+        Err(message) => error(StatusCode::BAD_REQUEST, message),
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ArrowHistoryParams {
+    symbol: Option<String>, // With interval, for candles
+    interval: Option<String>,
+    account_id: Option<String>, // For the account's fills instead, needs a bearer token
+    from: i64,                  // Epoch milliseconds
+    to: i64,
+}
+
+// GET /history/arrow?symbol=BTCUSDT&interval=1m&from=...&to=... or ?account_id=acc1&from=...&to=...,
+// a whole range as an Arrow IPC stream for bulk loads into pandas, Polars or DuckDB. Fills are
+// authorized like the Portfolio message.
+#[utoipa::path(
+    get,
+    path = "/history/arrow",
+    params(ArrowHistoryParams),
+    responses(
+        (status = 200, description = "Arrow IPC stream of candles or fills", content_type = "application/vnd.apache.arrow.stream"),
+        (status = 400, description = "Invalid range, interval or selection", body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody)
+    )
+)]
+async fn arrow_history(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<ArrowHistoryParams>,
+) -> Response {
+    let dataset = match (params.account_id, params.symbol, params.interval) {
+        (Some(account_id), None, None) => {
+            let header = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+            let session = match auth::bearer_session(&state, header).await {
+                Ok(session) => session,
+                Err(message) => return error(StatusCode::UNAUTHORIZED, message),
+            };
+            let message = ClientMessage::Portfolio {
+                account_id: account_id.clone(),
+                recalculate_risk: false,
+            };
+            if let Err(message) = auth::authorize(&state, &session, &message) {
+                return error(StatusCode::FORBIDDEN, message);
+            }
+            if let Err(message) = handlers::authorize_account(&state, &session, &account_id, &message).await {
+                return error(StatusCode::FORBIDDEN, message);
+            }
+            Dataset::Fills { account_id }
+        }
+        (None, Some(symbol), Some(interval)) => Dataset::Candles {
+            symbol: symbol.trim().to_uppercase(),
+            interval,
+        },
+        _ => return error(StatusCode::BAD_REQUEST, "Name a symbol and interval, or an account_id".to_string()),
+    };
+    if let Err(message) = arrow_stream::validate(&dataset, params.from, params.to) {
+        return error(StatusCode::BAD_REQUEST, message);
+    }
+    let body = Body::from_stream(arrow_stream::stream(state, dataset, params.from, params.to));
+    ([(header::CONTENT_TYPE, arrow_stream::CONTENT_TYPE)], body).into_response()
+}
+
+// GET /public/<token>, the read-only profile an account owner shared
+#[utoipa::path(
+    get,
+    path = "/public/{token}",
+    params(("token" = String, Path, description = "Share token issued by SetPublicProfile")),
+    responses(
+        (status = 200, body = PublicProfile),
+        (status = 404, description = "Unknown or revoked token", body = ErrorBody),
+        (status = 500, body = ErrorBody)
+    )
+)]
+async fn public_profile(State(state): State<Arc<AppState>>, Path(token): Path<String>) -> Response {
+    match profiles::public_profile(&state, &token).await {
+        Ok(Some(profile)) => Json(profile).into_response(),
+        Ok(None) => error(StatusCode::NOT_FOUND, "Unknown or revoked profile".to_string()),
+        Err(message) => error(StatusCode::INTERNAL_SERVER_ERROR, message),
+    }
+}
+
+// How long the signed link an export download redirects to stays valid
+const EXPORT_LINK_SECS: u64 = 900;
+
+// GET /exports/<token>, the CSV written by an ExportCandles job. Like a share link, holding the
+// token is what authorizes the download. Exports moved to object storage are a redirect to a
+// short-lived signed link on the bucket.
+#[utoipa::path(
+    get,
+    path = "/exports/{token}",
+    params(("token" = String, Path, description = "Token returned by ExportCandles")),
+    responses(
+        (status = 200, description = "open_time,open,high,low,close,volume,gap rows", content_type = "text/csv", body = String),
+        (status = 307, description = "Redirect to the export in object storage"),
+        (status = 404, description = "Unknown, unfinished or expired export", body = ErrorBody),
+        (status = 502, description = "Object storage unavailable", body = ErrorBody)
+    )
+)]
+async fn download_export(State(state): State<Arc<AppState>>, Path(token): Path<String>) -> Response {
+    let Some(path) = exports::export_path(&token) else {
+        return error(StatusCode::NOT_FOUND, "Unknown export".to_string());
+    };
+    match tokio::fs::read(&path).await {
+        Ok(contents) => {
+            let disposition = format!("attachment; filename=\"candles-{}.csv\"", &token[..8]);
+            ([(header::CONTENT_TYPE, "text/csv".to_string()), (header::CONTENT_DISPOSITION, disposition)], contents)
+                .into_response()
+        }
+        Err(_) => match &state.object_store {
+            Some(store) => {
+                let key = exports::object_key(&token);
+                match store.exists(&key).await {
+                    Ok(true) => Redirect::temporary(&store.presigned_get(&key, EXPORT_LINK_SECS)).into_response(),
+                    Ok(false) => error(StatusCode::NOT_FOUND, "Export not ready or expired".to_string()),
+                    Err(message) => error(StatusCode::BAD_GATEWAY, message),
+                }
+            }
+            None => error(StatusCode::NOT_FOUND, "Export not ready or expired".to_string()),
+        },
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UpdatesParams {
+    cursor: Option<u64>,        // From the previous response, omitted to start from now
+    symbols: Option<String>,    // Comma separated, for their ticker updates
+    account_id: Option<String>, // For the account's fills and alerts, needs a bearer token
+    timeout_secs: Option<u64>,  // How long to hold the request open when nothing is new
+}
+
+// GET /api/updates?cursor=1200&symbols=BTCUSDT,ETHUSDT&account_id=acc1, long-polled by clients
+// that can't keep a WebSocket open. Authorized like Subscribe and Portfolio messages.
+#[utoipa::path(
+    get,
+    path = "/api/updates",
+    params(UpdatesParams),
+    responses(
+        (status = 200, body = UpdatesPage),
+        (status = 400, description = "Neither symbols nor an account named", body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody)
+    )
+)]
+async fn poll_updates(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(params): Query<UpdatesParams>,
+) -> Response {
+    let requested: Vec<String> = params
+        .symbols
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::to_string)
+        .collect();
+    let symbols = handlers::normalize_symbols(&requested);
+    if symbols.is_empty() && params.account_id.is_none() {
+        return error(StatusCode::BAD_REQUEST, "Name symbols, an account_id or both".to_string());
+    }
+
+    let header = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+    let session = match auth::bearer_session(&state, header).await {
+        Ok(session) => Session {
+            ip: Some(addr.ip().to_string()),
+            connection_id: crate::NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            ..session
+        },
+        Err(message) => return error(StatusCode::UNAUTHORIZED, message),
+    };
+    if !symbols.is_empty() {
+        let message = ClientMessage::Subscribe {
+            symbols: symbols.clone(),
+            interval_ms: None,
+            fields: None,
+            format: WireFormat::Standard,
+        };
+        if let Err(message) = auth::authorize(&state, &session, &message) {
+            return error(StatusCode::FORBIDDEN, message);
+        }
+    }
+    if let Some(account_id) = &params.account_id {
+        let message = ClientMessage::Portfolio {
+            account_id: account_id.clone(),
+            recalculate_risk: false,
+        };
+        if let Err(message) = auth::authorize(&state, &session, &message) {
+            return error(StatusCode::FORBIDDEN, message);
+        }
+        if let Err(message) = handlers::authorize_account(&state, &session, account_id, &message).await {
+            return error(StatusCode::FORBIDDEN, message);
+        }
+    }
+
+    let filter = UpdateFilter {
+        symbols: symbols.into_iter().collect(),
+        account_id: params.account_id,
+    };
+    let timeout = params
+        .timeout_secs
+        .unwrap_or(updates::DEFAULT_POLL_TIMEOUT_SECS)
+        .min(updates::MAX_POLL_TIMEOUT_SECS);
+    Json(updates::poll(&state, params.cursor, &filter, Duration::from_secs(timeout)).await).into_response()
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct AnalyticsQuery {
+    sql: String,
+    limit: Option<usize>, // Rows returned, capped at ANALYTICS_MAX_ROWS
+}
+
+// POST /analytics/query {"sql": "SELECT ... FROM candles"}, read-only SQL over the Parquet dataset
+// for signed-in users. Authorized like the AnalyticsQuery message.
+#[utoipa::path(
+    post,
+    path = "/analytics/query",
+    request_body = AnalyticsQuery,
+    responses(
+        (status = 200, body = AnalyticsResult),
+        (status = 400, description = "Invalid, failed or timed out query", body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody)
+    )
+)]
+async fn analytics_query(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<AnalyticsQuery>,
+) -> Response {
+    let header = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+    let session = match auth::bearer_session(&state, header).await {
+        Ok(session) => session,
+        Err(message) => return error(StatusCode::UNAUTHORIZED, message),
+    };
+    let message = ClientMessage::AnalyticsQuery {
+        sql: request.sql.clone(),
+        limit: request.limit,
+    };
+    if let Err(message) = auth::authorize(&state, &session, &message) {
+        return error(StatusCode::FORBIDDEN, message);
+    }
+    if let Err(message) = feature_flags::require(&state, "analytics", session.user_id.as_deref()).await {
+        return error(StatusCode::FORBIDDEN, message);
+    }
+    match analytics::query(&state, &request.sql, request.limit).await {
+        Ok(result) => Json(result).into_response(),
+        Err(message) => error(StatusCode::BAD_REQUEST, message),
+    }
+}
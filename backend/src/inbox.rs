@@ -0,0 +1,49 @@
+use crate::db;
+use crate::state::AppState;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+// Keep fills and alerts for accounts without an open session, so they can be read from the inbox
+// after reconnecting
+pub async fn run_inbox_recorder(state: Arc<AppState>) {
+    let mut fills = state.fills.subscribe();
+    let mut alerts = state.alerts.subscribe();
+
+    loop {
+        let (account_id, kind, title, payload) = tokio::select! {
+            fill = fills.recv() => match fill {
+                Ok(fill) => (
+                    fill.account_id.clone(),
+                    "fill",
+                    format!("Order {} filled: {} {} @ {}", fill.order_id, fill.quantity, fill.symbol, fill.price),
+                    serde_json::to_value(&fill).unwrap_or_default(),
+                ),
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Inbox recorder lagged behind, {} fills not recorded", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            alert = alerts.recv() => match alert {
+                Ok(alert) => (
+                    alert.account_id.clone(),
+                    "alert",
+                    alert.message.clone(),
+                    serde_json::to_value(&alert).unwrap_or_default(),
+                ),
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Inbox recorder lagged behind, {} alerts not recorded", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
+        };
+
+        if state.is_online(&account_id).await {
+            continue;
+        }
+        if let Err(e) = db::save_notification(&state.pool, &account_id, kind, &title, &payload).await {
+            eprintln!("Error saving notification: {:?}", e);
+        }
+    }
+}
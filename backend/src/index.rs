@@ -0,0 +1,76 @@
+use crate::models::{IndexDefinition, IndexUpdate, TickerUpdate};
+use std::collections::HashMap;
+
+// A client-defined weighted basket, valued from the latest price of each component. The return is
+// measured against the first complete valuation after subscribing.
+pub struct CustomIndex {
+    definition: IndexDefinition,
+    prices: HashMap<String, f64>,
+    base_value: Option<f64>,
+}
+
+impl CustomIndex {
+    pub fn new(definition: IndexDefinition, latest_price: impl Fn(&str) -> Option<f64>) -> Self {
+        let prices = definition
+            .components
+            .iter()
+            .filter_map(|c| latest_price(&c.symbol).map(|price| (c.symbol.clone(), price)))
+            .collect();
+        let mut index = CustomIndex {
+            definition,
+            prices,
+            base_value: None,
+        };
+        index.base_value = index.value();
+        index
+    }
+
+    pub fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    fn value(&self) -> Option<f64> {
+        self.definition
+            .components
+            .iter()
+            .map(|c| self.prices.get(&c.symbol).map(|price| price * c.weight))
+            .sum()
+    }
+
+    pub fn snapshot(&self) -> IndexUpdate {
+        let value = self.value();
+        IndexUpdate {
+            name: self.definition.name.clone(),
+            value,
+            index_return: match (value, self.base_value) {
+                (Some(value), Some(base)) if base != 0.0 => Some(value / base - 1.0),
+                _ => None,
+            },
+        }
+    }
+
+    // Recalculate on a component's price update, other symbols are ignored
+    pub fn on_ticker(&mut self, update: &TickerUpdate) -> Option<IndexUpdate> {
+        if !self.definition.components.iter().any(|c| c.symbol == update.symbol) {
+            return None;
+        }
+        self.prices.insert(update.symbol.clone(), update.price);
+        if self.base_value.is_none() {
+            self.base_value = self.value();
+        }
+        Some(self.snapshot())
+    }
+}
+
+pub fn validate(definition: &IndexDefinition) -> Result<(), String> {
+    if definition.name.trim().is_empty() {
+        return Err("Index name is required".to_string());
+    }
+    if definition.components.is_empty() {
+        return Err("Index needs at least one component".to_string());
+    }
+    if definition.components.iter().any(|c| !c.weight.is_finite()) {
+        return Err("Index weights must be finite numbers".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use crate::candles::{self, DEFAULT_CANDLE_LIMIT, MAX_CANDLE_LIMIT};
+use crate::config::env_or;
+use crate::db;
+use crate::models::{IndicatorKind, MarketTrade, ServerMessage, Side, VolumeDelta};
+use crate::state::AppState;
+use std::collections::HashMap;
+use tokio::time::Duration;
+
+// Least time between two pushes of a changing indicator, INDICATOR_INTERVAL_MS
+pub fn interval() -> Duration {
+    Duration::from_millis(env_or("INDICATOR_INTERVAL_MS", 1000u64).max(100))
+}
+
+// Trade volume is aggregated per minute, shorter candles can't be told apart
+pub fn bucket_seconds(interval: &str) -> Result<i64, String> {
+    match candles::interval_seconds(interval) {
+        Some(seconds) if seconds >= 60 => Ok(seconds),
+        Some(_) => Err(format!("Trade volume is kept per minute, {} candles are too short", interval)),
+        None => Err(format!("Unsupported candle interval {}", interval)),
+    }
+}
+
+// The last `limit` candles of the symbol, oldest first
+pub async fn volume_delta(
+    state: &AppState,
+    symbol: &str,
+    interval: &str,
+    limit: Option<i64>,
+) -> Result<Vec<VolumeDelta>, String> {
+    let seconds = bucket_seconds(interval)?;
+    let limit = limit.unwrap_or(DEFAULT_CANDLE_LIMIT).clamp(1, MAX_CANDLE_LIMIT);
+    let rows = db::get_volume_delta(&state.pool, symbol, seconds, limit)
+        .await
+        .map_err(|e| format!("Error loading volume delta: {}", e))?;
+    let mut cvd = 0.0;
+    Ok(rows
+        .into_iter()
+        .rev()
+        .map(|(open_time, buy_volume, sell_volume)| {
+            let delta = buy_volume - sell_volume;
+            cvd += delta;
+            VolumeDelta {
+                open_time,
+                buy_volume,
+                sell_volume,
+                delta,
+                cvd,
+            }
+        })
+        .collect())
+}
+
+pub async fn subscribe(
+    state: &AppState,
+    symbol: &str,
+    interval: String,
+    indicator: IndicatorKind,
+    limit: Option<i64>,
+) -> Result<ServerMessage, String> {
+    let symbol = symbol.trim().to_uppercase();
+    let values = match indicator {
+        IndicatorKind::VolumeDelta => volume_delta(state, &symbol, &interval, limit).await?,
+    };
+    Ok(ServerMessage::IndicatorSubscribed {
+        symbol,
+        interval,
+        indicator,
+        values,
+    })
+}
+
+struct LiveDelta {
+    interval_ms: i64,
+    current: Option<VolumeDelta>,
+    changed: bool,
+}
+
+// Indicators streamed to one connection, kept up to date from the trade stream and pushed every
+// INDICATOR_INTERVAL_MS while they change
+#[derive(Default)]
+pub struct IndicatorStream {
+    series: HashMap<(String, String, IndicatorKind), LiveDelta>,
+}
+
+impl IndicatorStream {
+    // Carries on from the last value the subscription was answered with
+    pub fn subscribe(
+        &mut self,
+        symbol: String,
+        interval: String,
+        indicator: IndicatorKind,
+        last: Option<VolumeDelta>,
+    ) {
+        let live = LiveDelta {
+            interval_ms: candles::interval_seconds(&interval).unwrap_or(60) * 1000,
+            current: last,
+            changed: false,
+        };
+        self.series.insert((symbol, interval, indicator), live);
+    }
+
+    pub fn unsubscribe(&mut self, symbol: &str, interval: &str, indicator: IndicatorKind) {
+        self.series.remove(&(symbol.to_string(), interval.to_string(), indicator));
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &String> {
+        self.series.keys().map(|(symbol, _, _)| symbol)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+
+    pub fn push(&mut self, trade: &MarketTrade) {
+        for ((symbol, _, _), live) in &mut self.series {
+            if *symbol != trade.symbol {
+                continue;
+            }
+            let open_time = trade.time - trade.time.rem_euclid(live.interval_ms);
+            // A new candle starts from the cumulative delta of the one before it
+            let cvd = match &live.current {
+                Some(current) if current.open_time > open_time => continue,
+                Some(current) if current.open_time < open_time => Some(current.cvd),
+                Some(_) => None,
+                None => Some(0.0),
+            };
+            if let Some(cvd) = cvd {
+                live.current = Some(VolumeDelta {
+                    open_time,
+                    buy_volume: 0.0,
+                    sell_volume: 0.0,
+                    delta: 0.0,
+                    cvd,
+                });
+            }
+            let Some(current) = live.current.as_mut() else {
+                continue;
+            };
+            let signed = match trade.side {
+                Side::Buy => {
+                    current.buy_volume += trade.quantity;
+                    trade.quantity
+                }
+                Side::Sell => {
+                    current.sell_volume += trade.quantity;
+                    -trade.quantity
+                }
+            };
+            current.delta += signed;
+            current.cvd += signed;
+            live.changed = true;
+        }
+    }
+
+    pub fn flush(&mut self) -> Vec<ServerMessage> {
+        let mut messages = Vec::new();
+        for ((symbol, interval, indicator), live) in &mut self.series {
+            let Some(current) = live.current.as_ref().filter(|_| live.changed) else {
+                continue;
+            };
+            messages.push(ServerMessage::Indicator {
+                symbol: symbol.clone(),
+                interval: interval.clone(),
+                indicator: *indicator,
+                value: current.clone(),
+            });
+            live.changed = false;
+        }
+        messages
+    }
+}
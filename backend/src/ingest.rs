@@ -0,0 +1,70 @@
+use crate::models::TickerData;
+use serde_json::value::RawValue;
+use std::ops::Range;
+use std::sync::Arc;
+
+// One ticker's JSON inside an upstream frame. Relayed as is, so passthrough subscribers share the
+// exchange's bytes instead of each getting a reserialized copy.
+#[derive(Debug, Clone)]
+pub struct RawJson {
+    frame: Arc<str>,
+    range: Range<usize>,
+}
+
+impl RawJson {
+    pub fn get(&self) -> &str {
+        &self.frame[self.range.clone()]
+    }
+}
+
+// Decode a miniTicker array element by element, so one malformed ticker does not discard the batch
+pub fn decode_tickers(text: &str) -> Vec<TickerData> {
+    decode_elements(text).into_iter().map(|(ticker, _)| ticker).collect()
+}
+
+// Like `decode_tickers`, keeping each ticker's validated slice of the frame for passthrough
+pub fn decode_raw_tickers(frame: &Arc<str>) -> Vec<(TickerData, RawJson)> {
+    decode_elements(frame)
+        .into_iter()
+        .map(|(ticker, range)| {
+            let raw = RawJson {
+                frame: Arc::clone(frame),
+                range,
+            };
+            (ticker, raw)
+        })
+        .collect()
+}
+
+// Tickers with the byte range each was decoded from, elements borrow from `text` so their offsets
+// are found by pointer
+fn decode_elements(text: &str) -> Vec<(TickerData, Range<usize>)> {
+    let elements = match serde_json::from_str::<&RawValue>(text) {
+        Ok(value) if value.get().starts_with('[') => match serde_json::from_str::<Vec<&RawValue>>(value.get()) {
+            Ok(elements) => elements,
+            Err(e) => {
+                eprintln!("Malformed ticker message ({}): {}", e, text);
+                return Vec::new();
+            }
+        },
+        Ok(value) => vec![value],
+        Err(e) => {
+            eprintln!("Malformed ticker message ({}): {}", e, text);
+            return Vec::new();
+        }
+    };
+
+    elements
+        .into_iter()
+        .filter_map(|element| match serde_json::from_str::<TickerData>(element.get()) {
+            Ok(ticker) => {
+                let start = element.get().as_ptr() as usize - text.as_ptr() as usize;
+                Some((ticker, start..start + element.get().len()))
+            }
+            Err(e) => {
+                eprintln!("Skipping malformed ticker ({}): {}", e, element.get());
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,51 @@
+use crate::config::env_or;
+use crate::models::IngestLatencyReport;
+use std::collections::VecDeque;
+
+// Rolling ingestion latency, the gap between the exchange event time and our receive time.
+// A negative latency can only come from clock skew, so the minimum doubles as a skew estimate.
+pub struct IngestMetrics {
+    window: usize,
+    samples: VecDeque<i64>,
+    total_ticks: u64,
+}
+
+impl IngestMetrics {
+    pub fn from_env() -> Self {
+        IngestMetrics {
+            window: env_or("INGEST_METRICS_WINDOW", 10_000),
+            samples: VecDeque::new(),
+            total_ticks: 0,
+        }
+    }
+
+    pub fn record(&mut self, event_time: i64, received_at: i64) {
+        self.samples.push_back(received_at - event_time);
+        while self.samples.len() > self.window.max(1) {
+            self.samples.pop_front();
+        }
+        self.total_ticks += 1;
+    }
+
+    pub fn report(&self) -> IngestLatencyReport {
+        let mut sorted: Vec<i64> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let percentile = |p: f64| -> Option<i64> {
+            let index = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+            sorted.get(index).copied()
+        };
+        let min_ms = sorted.first().copied();
+
+        IngestLatencyReport {
+            total_ticks: self.total_ticks,
+            samples: sorted.len(),
+            mean_ms: (!sorted.is_empty())
+                .then(|| sorted.iter().sum::<i64>() as f64 / sorted.len() as f64),
+            min_ms,
+            p50_ms: percentile(0.5),
+            p99_ms: percentile(0.99),
+            max_ms: sorted.last().copied(),
+            clock_skew_suspected: min_ms.is_some_and(|min| min < 0),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::backfill;
+use crate::config::env_or;
+use crate::db;
+use crate::exports;
+use crate::guests;
+use crate::models::StorageKind;
+use crate::reports;
+use crate::state::AppState;
+use crate::storage;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+// Longest wait between two attempts of a failing job
+const MAX_RETRY_SECS: i64 = 3600;
+
+// Work done off the request path by the job workers, stored as the job's payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Task {
+    Backfill {
+        symbol: String,
+        interval: String,
+        start_time: i64,
+        end_time: Option<i64>,
+    },
+    ImportKlines {
+        symbol: String,
+        interval: String,
+        path: String,
+    },
+    DailyReport {
+        account_id: String,
+        day: i64, // Days since the Unix epoch
+    },
+    GuestSweep,
+    CandleExport {
+        token: String,      // Names the file and authorizes its download
+        connection_id: u64, // Progress goes to this connection while it is open
+        #[serde(default)]
+        user_id: Option<String>, // The file counts towards this user's storage
+        symbol: String,
+        interval: String,
+        start_time: i64,
+        end_time: i64,
+    },
+}
+
+impl Task {
+    fn kind(&self) -> &'static str {
+        match self {
+            Task::Backfill { .. } => "backfill",
+            Task::ImportKlines { .. } => "import_klines",
+            Task::DailyReport { .. } => "daily_report",
+            Task::GuestSweep => "guest_sweep",
+            Task::CandleExport { .. } => "candle_export",
+        }
+    }
+
+    // Tasks that must not be queued twice while one is outstanding
+    fn dedupe_key(&self) -> Option<String> {
+        match self {
+            Task::DailyReport { account_id, day } => Some(format!("daily_report:{}:{}", account_id, day)),
+            Task::GuestSweep => Some("guest_sweep".to_string()),
+            Task::Backfill { .. } | Task::ImportKlines { .. } | Task::CandleExport { .. } => None,
+        }
+    }
+
+    async fn run(self, state: &AppState) -> Result<(), String> {
+        match self {
+            Task::Backfill {
+                symbol,
+                interval,
+                start_time,
+                end_time,
+            } => backfill::backfill_klines(&state.pool, &symbol, &interval, start_time, end_time)
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("backfill of {} {}: {}", symbol, interval, e)),
+            Task::ImportKlines { symbol, interval, path } => {
+                backfill::import_klines_csv(&state.pool, &symbol, &interval, &path)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| format!("import of {} {}: {}", symbol, interval, e))
+            }
+            Task::DailyReport { account_id, day } => reports::send_daily_report(state, &account_id, day).await,
+            Task::GuestSweep => guests::sweep_inactive(state).await,
+            Task::CandleExport {
+                token,
+                connection_id,
+                user_id,
+                symbol,
+                interval,
+                start_time,
+                end_time,
+            } => {
+                let bytes = exports::export_candles(
+                    state,
+                    connection_id,
+                    &token,
+                    &symbol,
+                    &interval,
+                    start_time,
+                    end_time,
+                )
+                .await?;
+                storage::record(state, user_id.as_deref(), StorageKind::Export, &token, bytes).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+// Queues a task for the workers, None when an identical one is still outstanding
+pub async fn enqueue(pool: &PgPool, task: &Task) -> Result<Option<i64>, sqlx::Error> {
+    let payload = serde_json::to_value(task).unwrap_or_default();
+    let max_attempts = env_or("JOB_MAX_ATTEMPTS", 5).max(1);
+    db::enqueue_job(pool, task.kind(), task.dedupe_key().as_deref(), &payload, max_attempts).await
+}
+
+// Starts JOB_WORKERS workers on the queue, after putting back any job a previous run of the
+// server was in the middle of
+pub async fn run_workers(state: Arc<AppState>) {
+    match db::requeue_running_jobs(&state.pool).await {
+        Ok(0) => {}
+        Ok(requeued) => println!("Requeued {} jobs interrupted by the last shutdown", requeued),
+        Err(e) => eprintln!("Error requeueing interrupted jobs: {:?}", e),
+    }
+    for worker in 0..env_or("JOB_WORKERS", 2).max(1) {
+        tokio::spawn(run_worker(Arc::clone(&state), worker));
+    }
+}
+
+// Takes due jobs one at a time, polling every JOB_POLL_MS while the queue is empty. A failed job
+// is retried after JOB_RETRY_SECS, doubling with each attempt, and goes dead once it has used
+// JOB_MAX_ATTEMPTS.
+async fn run_worker(state: Arc<AppState>, worker: usize) {
+    let poll = Duration::from_millis(env_or("JOB_POLL_MS", 1000).max(10));
+    let retry_secs: i64 = env_or("JOB_RETRY_SECS", 30).max(1);
+    loop {
+        let (job_id, payload, attempt, max_attempts) = match db::claim_job(&state.pool).await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                state.simulation.sleep(poll).await;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Job worker {} error claiming a job: {:?}", worker, e);
+                state.simulation.sleep(poll).await;
+                continue;
+            }
+        };
+
+        let result = match serde_json::from_value::<Task>(payload) {
+            Ok(task) => task.run(&state).await,
+            Err(e) => Err(format!("unreadable job payload: {}", e)),
+        };
+        let saved = match result {
+            Ok(()) => db::complete_job(&state.pool, job_id).await,
+            Err(error) => {
+                let retry_in = (attempt < max_attempts)
+                    .then(|| (retry_secs << (attempt - 1).clamp(0, 16)).min(MAX_RETRY_SECS));
+                match retry_in {
+                    Some(secs) => eprintln!("Job {} failed, retrying in {}s: {}", job_id, secs, error),
+                    None => eprintln!("Job {} failed on its last attempt: {}", job_id, error),
+                }
+                db::fail_job(&state.pool, job_id, &error, retry_in).await
+            }
+        };
+        if let Err(e) = saved {
+            eprintln!("Error recording the outcome of job {}: {:?}", job_id, e);
+        }
+    }
+}
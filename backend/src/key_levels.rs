@@ -0,0 +1,93 @@
+use crate::config::env_or;
+use crate::db;
+use crate::models::{KeyLevels, ServerMessage};
+use crate::state::AppState;
+use std::collections::HashMap;
+
+const DAY_MS: i64 = 24 * 3_600_000;
+const WEEK_MS: i64 = 7 * DAY_MS;
+// 1970-01-01 was a Thursday, the first Monday came four days later
+const FIRST_MONDAY_MS: i64 = 4 * DAY_MS;
+
+fn session_offset() -> i64 {
+    env_or("SESSION_START_HOUR_UTC", 0i64).clamp(0, 23) * 3_600_000
+}
+
+fn session_start(time: i64) -> i64 {
+    time - (time - session_offset()).rem_euclid(DAY_MS)
+}
+
+fn week_start(time: i64) -> i64 {
+    time - (time - session_offset() - FIRST_MONDAY_MS).rem_euclid(WEEK_MS)
+}
+
+// Levels of the symbols someone asked for, kept current from the ticker stream
+#[derive(Debug, Default)]
+pub struct KeyLevelBook {
+    levels: HashMap<String, KeyLevels>,
+}
+
+impl KeyLevelBook {
+    // Folds a tick into a tracked symbol's levels, returning them when they moved. At a session
+    // change the finished session becomes the previous one, after a longer silence the symbol is
+    // dropped so it is loaded from history again.
+    pub fn on_price(&mut self, symbol: &str, price: f64, time: i64) -> Option<KeyLevels> {
+        if time >= self.levels.get(symbol)?.session_start + 2 * DAY_MS {
+            self.levels.remove(symbol);
+            return None;
+        }
+        let levels = self.levels.get_mut(symbol)?;
+        let mut moved = false;
+        if time >= levels.session_start + DAY_MS {
+            if week_start(time) > levels.session_start {
+                levels.weekly_open = Some(price);
+            }
+            levels.previous_high = levels.session_high.take();
+            levels.previous_low = levels.session_low.take();
+            levels.previous_close = levels.last_price;
+            levels.session_start = session_start(time);
+            moved = true;
+        }
+        if levels.session_high.is_none_or(|high| price > high) {
+            levels.session_high = Some(price);
+            moved = true;
+        }
+        if levels.session_low.is_none_or(|low| price < low) {
+            levels.session_low = Some(price);
+            moved = true;
+        }
+        levels.last_price = Some(price);
+        moved.then(|| levels.clone())
+    }
+}
+
+// The symbol's levels, loaded from stored klines the first time and tracked from then on
+pub async fn levels(state: &AppState, symbol: &str) -> Result<KeyLevels, String> {
+    let symbol = symbol.trim().to_uppercase();
+    if let Some(levels) = state.key_levels.lock().await.levels.get(&symbol) {
+        return Ok(levels.clone());
+    }
+    let now = state.simulation.now_millis();
+    let mut levels = db::get_key_levels(&state.pool, &symbol, session_start(now), week_start(now))
+        .await
+        .map_err(|e| format!("Error loading key levels: {}", e))?;
+    // The minute in progress has no closed kline yet
+    levels.last_price = state.engine.lock().await.last_price(&symbol);
+    if let Some(price) = levels.last_price {
+        levels.session_high = Some(levels.session_high.map_or(price, |high| high.max(price)));
+        levels.session_low = Some(levels.session_low.map_or(price, |low| low.min(price)));
+    }
+    if levels.session_high.is_none() && levels.previous_close.is_none() {
+        return Err(format!("No price history for {}", symbol));
+    }
+    state.key_levels.lock().await.levels.insert(symbol, levels.clone());
+    Ok(levels)
+}
+
+pub async fn subscribe(state: &AppState, symbols: &[String]) -> Result<ServerMessage, String> {
+    let mut subscribed = Vec::with_capacity(symbols.len());
+    for symbol in symbols {
+        subscribed.push(levels(state, symbol).await?);
+    }
+    Ok(ServerMessage::KeyLevelsSubscribed { levels: subscribed })
+}
@@ -0,0 +1,146 @@
+use crate::config::env_or;
+use crate::depth::{bucket_key, bucket_price};
+use crate::models::{Ladder, LadderRow, OrderRequest, OrderType, Side, TimeInForce};
+use crate::state::AppState;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// Time between two pushes of a subscribed ladder, LADDER_INTERVAL_MS
+pub fn interval() -> Duration {
+    Duration::from_millis(env_or("LADDER_INTERVAL_MS", 500u64).max(100))
+}
+
+// Key of the row a price shown on the ladder stands for. Row prices are bucket edges already, so
+// they are rounded rather than floored or ceiled.
+fn row_key(price: f64, group: Option<f64>) -> i64 {
+    match group {
+        Some(group) => (price / group).round() as i64,
+        None => (price * 1e8).round() as i64,
+    }
+}
+
+fn row(rows: &mut BTreeMap<i64, LadderRow>, key: i64, group: Option<f64>) -> &mut LadderRow {
+    rows.entry(key).or_insert_with(|| LadderRow {
+        price: bucket_price(key, group),
+        ..Default::default()
+    })
+}
+
+// The book, the recent trades and the account's own working orders merged into one price column.
+// Rows with an own order are shown even when the book doesn't reach them.
+pub async fn ladder(
+    state: &AppState,
+    account_id: &str,
+    symbol: &str,
+    levels: Option<usize>,
+    group: Option<f64>,
+) -> Result<Ladder, String> {
+    let symbol = symbol.trim().to_uppercase();
+    let (resting, own, last_price) = {
+        let engine = state.engine.lock().await;
+        (
+            engine.resting_limits(&symbol),
+            engine.order_levels(account_id, &symbol),
+            engine.last_price(&symbol),
+        )
+    };
+    let (book, trades, (best_bid, best_ask)) = {
+        let depth = state.depth.lock().await;
+        (depth.snapshot(&symbol, levels, group, &resting)?, depth.trades(&symbol), depth.touch(&symbol))
+    };
+
+    let mut rows: BTreeMap<i64, LadderRow> = BTreeMap::new();
+    for level in &book.bids {
+        let row = row(&mut rows, row_key(level.price, group), group);
+        row.bid_quantity += level.quantity;
+        row.simulated_quantity += level.simulated_quantity;
+    }
+    for level in &book.asks {
+        let row = row(&mut rows, row_key(level.price, group), group);
+        row.ask_quantity += level.quantity;
+        row.simulated_quantity += level.simulated_quantity;
+    }
+    for order in &own {
+        let row = row(&mut rows, bucket_key(order.price, order.side, group), group);
+        match order.side {
+            Side::Buy => row.own_buy_quantity += order.quantity,
+            Side::Sell => row.own_sell_quantity += order.quantity,
+        }
+        row.order_ids.push(order.order_id);
+    }
+    // A taking buy lifted an ask, so it lands in the ask bucket of its price, a taking sell in the bid one
+    for trade in &trades {
+        if let Some(row) = rows.get_mut(&bucket_key(trade.price, trade.side, group)) {
+            row.traded_quantity += trade.quantity;
+        }
+    }
+
+    Ok(Ladder {
+        symbol,
+        account_id: account_id.to_string(),
+        event_time: book.event_time,
+        group,
+        last_price,
+        best_bid,
+        best_ask,
+        rows: rows.into_values().rev().collect(),
+        trades,
+    })
+}
+
+// The order a click at a ladder level places. Clicking at or through the opposite side of the book
+// takes liquidity up to the level and cancels what is left, anywhere else the order joins the book.
+pub async fn click_order(
+    state: &AppState,
+    symbol: &str,
+    side: Side,
+    price: f64,
+    quantity: f64,
+    client_order_id: Option<String>,
+) -> Result<OrderRequest, String> {
+    if !(price.is_finite() && price > 0.0) {
+        return Err("The level price must be a positive number".to_string());
+    }
+    let symbol = symbol.trim().to_uppercase();
+    let (best_bid, best_ask) = state.depth.lock().await.touch(&symbol);
+    // Without a book the last price stands in for both sides
+    let last_price = state.engine.lock().await.last_price(&symbol);
+    if best_bid.is_none() && best_ask.is_none() && last_price.is_none() {
+        return Err(format!("No market for {} yet", symbol));
+    }
+    let crosses = match side {
+        Side::Buy => best_ask.or(last_price).is_some_and(|ask| price >= ask),
+        Side::Sell => best_bid.or(last_price).is_some_and(|bid| price <= bid),
+    };
+    Ok(OrderRequest {
+        symbol,
+        side,
+        order_type: OrderType::Limit,
+        price: Some(price),
+        quantity,
+        time_in_force: if crosses { TimeInForce::Ioc } else { TimeInForce::Gtc },
+        client_order_id,
+    })
+}
+
+// The account's working orders in the ladder row at `price`
+pub async fn row_orders(
+    state: &AppState,
+    account_id: &str,
+    symbol: &str,
+    price: f64,
+    group: Option<f64>,
+    side: Option<Side>,
+) -> Vec<u64> {
+    let key = row_key(price, group);
+    state
+        .engine
+        .lock()
+        .await
+        .order_levels(account_id, &symbol.trim().to_uppercase())
+        .into_iter()
+        .filter(|order| side.is_none_or(|side| side == order.side))
+        .filter(|order| bucket_key(order.price, order.side, group) == key)
+        .map(|order| order.order_id)
+        .collect()
+}
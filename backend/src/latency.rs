@@ -0,0 +1,46 @@
+use crate::config::env_or;
+use crate::simulation::Simulation;
+use rand::Rng;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LatencyProfile {
+    pub fixed: Duration,
+    pub jitter: Duration, // Uniform extra delay in [0, jitter]
+}
+
+impl LatencyProfile {
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        if self.jitter.is_zero() {
+            return self.fixed;
+        }
+        let jitter_ms = rng.gen_range(0..=self.jitter.as_millis() as u64);
+        self.fixed + Duration::from_millis(jitter_ms)
+    }
+
+    pub async fn wait(&self, simulation: &Simulation) {
+        let delay = self.sample(&mut *simulation.rng());
+        simulation.sleep(delay).await;
+    }
+}
+
+// Artificial delays applied on the order path, all zero by default
+#[derive(Debug, Clone, Copy)]
+pub struct LatencyConfig {
+    pub order_entry: LatencyProfile,        // Submission -> matching engine
+    pub fill_notification: LatencyProfile, // Fill -> client notification
+}
+
+impl LatencyConfig {
+    pub fn from_env() -> Self {
+        let profile = |fixed_key: &str, jitter_key: &str| LatencyProfile {
+            fixed: Duration::from_millis(env_or(fixed_key, 0)),
+            jitter: Duration::from_millis(env_or(jitter_key, 0)),
+        };
+
+        LatencyConfig {
+            order_entry: profile("ORDER_LATENCY_MS", "ORDER_LATENCY_JITTER_MS"),
+            fill_notification: profile("FILL_LATENCY_MS", "FILL_LATENCY_JITTER_MS"),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::config::env_or;
+use crate::db;
+use crate::engine::{now_millis, OrderChanges};
+use crate::models::{AccountEvent, Fill, OrderStatus};
+use crate::portfolio::Portfolio;
+use crate::state::AppState;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::time::Duration;
+
+// Appends account events to their per-account logs. Writers queue for their turn on the sequence
+// map, so every account's events are numbered and committed one batch at a time, and the key on
+// (account_id, sequence) turns away a second server writing to the same log.
+#[derive(Default)]
+pub struct Ledger {
+    sequences: Mutex<HashMap<String, u64>>, // Last sequence committed per account
+}
+
+impl Ledger {
+    pub async fn load(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        self.sequences.lock().await.extend(db::get_account_sequences(pool).await?);
+        Ok(())
+    }
+
+    // Waits for the ledger. Taken before the engine lock is released, batches commit in the order
+    // the engine produced them, so no fill is stored ahead of the order state it came from.
+    pub async fn turn(&self) -> LedgerTurn<'_> {
+        LedgerTurn {
+            sequences: self.sequences.lock().await,
+        }
+    }
+
+    pub async fn commit(
+        &self,
+        pool: &PgPool,
+        changes: &OrderChanges,
+        fills: &[Fill],
+        events: Vec<(String, AccountEvent)>,
+    ) -> Result<(), sqlx::Error> {
+        self.turn().await.commit(pool, changes, fills, events).await
+    }
+
+    pub async fn record(&self, pool: &PgPool, account_id: &str, event: AccountEvent) -> Result<(), sqlx::Error> {
+        self.commit(pool, &OrderChanges::default(), &[], vec![(account_id.to_string(), event)])
+            .await
+    }
+}
+
+pub struct LedgerTurn<'a> {
+    sequences: MutexGuard<'a, HashMap<String, u64>>,
+}
+
+impl LedgerTurn<'_> {
+    // Stores engine changes, the fills behind them and their account events in one transaction.
+    // Order events come first, followed by `events` in the order given.
+    pub async fn commit(
+        mut self,
+        pool: &PgPool,
+        changes: &OrderChanges,
+        fills: &[Fill],
+        events: Vec<(String, AccountEvent)>,
+    ) -> Result<(), sqlx::Error> {
+        let events: Vec<(String, AccountEvent)> = order_events(changes).into_iter().chain(events).collect();
+        let sequences = &mut self.sequences;
+        let mut tx = pool.begin().await?;
+        db::save_order_changes(&mut tx, changes, fills).await?;
+
+        let recorded_at = now_millis();
+        let mut appended: HashMap<String, u64> = HashMap::new();
+        for (account_id, event) in &events {
+            let sequence = appended
+                .entry(account_id.clone())
+                .or_insert_with(|| sequences.get(account_id).copied().unwrap_or(0));
+            *sequence += 1;
+            db::append_account_event(&mut tx, account_id, *sequence, event, recorded_at).await?;
+        }
+
+        tx.commit().await?;
+        sequences.extend(appended);
+        Ok(())
+    }
+}
+
+fn order_events(changes: &OrderChanges) -> Vec<(String, AccountEvent)> {
+    let mut orders: Vec<_> = changes.orders.iter().collect();
+    orders.sort_by_key(|order| order.id);
+    orders
+        .into_iter()
+        .map(|order| {
+            let event = match order.status {
+                OrderStatus::Cancelled => AccountEvent::OrderCancelled { order: order.clone() },
+                _ if order.version == 1 && order.filled_quantity == 0.0 => {
+                    AccountEvent::OrderPlaced { order: order.clone() }
+                }
+                _ => AccountEvent::OrderUpdated { order: order.clone() },
+            };
+            (order.account_id.clone(), event)
+        })
+        .collect()
+}
+
+// Cash and positions after one more event, None while the account is closed. Order events leave
+// them alone, only fills move money.
+pub fn apply(portfolio: &mut Option<Portfolio>, event: &AccountEvent, starting_balance: f64) {
+    match event {
+        AccountEvent::AccountOpened { cash } | AccountEvent::AccountReset { cash } => {
+            *portfolio = Some(Portfolio::new(*cash));
+        }
+        AccountEvent::AccountClosed => *portfolio = None,
+        AccountEvent::FundsCredited { amount } => {
+            portfolio.get_or_insert_with(|| Portfolio::new(starting_balance)).cash += amount;
+        }
+        AccountEvent::FundsDebited { amount } => {
+            portfolio.get_or_insert_with(|| Portfolio::new(starting_balance)).cash -= amount;
+        }
+        AccountEvent::Filled { fill } => {
+            portfolio.get_or_insert_with(|| Portfolio::new(starting_balance)).apply_fill(fill);
+        }
+        AccountEvent::OrderPlaced { .. } | AccountEvent::OrderUpdated { .. } | AccountEvent::OrderCancelled { .. } => {}
+    }
+}
+
+// Folds the account's events onto its latest snapshot, returning the state and the last sequence
+pub async fn replay(
+    pool: &PgPool,
+    account_id: &str,
+    starting_balance: f64,
+) -> Result<(Option<Portfolio>, u64), sqlx::Error> {
+    let (mut sequence, mut portfolio) = db::load_account_snapshot(pool, account_id).await?.unwrap_or((0, None));
+    for recorded in db::get_account_events(pool, account_id, sequence, None).await? {
+        apply(&mut portfolio, &recorded.event, starting_balance);
+        sequence = recorded.sequence;
+    }
+    Ok((portfolio, sequence))
+}
+
+// Rebuilds every logged account's cash and positions on startup, returns how many are open
+pub async fn restore_portfolios(state: &AppState) -> Result<usize, sqlx::Error> {
+    state.ledger.load(&state.pool).await?;
+    let starting_balance = state.portfolios.lock().await.starting_balance();
+    let mut restored = 0;
+    for (account_id, _) in db::get_account_sequences(&state.pool).await? {
+        if let (Some(portfolio), _) = replay(&state.pool, &account_id, starting_balance).await? {
+            state.portfolios.lock().await.restore(&account_id, portfolio);
+            restored += 1;
+        }
+    }
+    Ok(restored)
+}
+
+// Snapshots the accounts with new events every LEDGER_SNAPSHOT_SECS so replays stay short. Each
+// snapshot is folded from the log itself, never copied from the live portfolios.
+pub async fn run_snapshotter(state: Arc<AppState>) {
+    let mut ticker = state.simulation.interval(Duration::from_secs(env_or("LEDGER_SNAPSHOT_SECS", 300).max(1)));
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+
+        let accounts = match db::get_unsnapshotted_accounts(&state.pool).await {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                eprintln!("Error finding accounts to snapshot: {:?}", e);
+                continue;
+            }
+        };
+        let starting_balance = state.portfolios.lock().await.starting_balance();
+        for account_id in accounts {
+            let saved = match replay(&state.pool, &account_id, starting_balance).await {
+                Ok((portfolio, sequence)) => {
+                    db::save_account_snapshot(&state.pool, &account_id, sequence, portfolio.as_ref()).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = saved {
+                eprintln!("Error snapshotting account {}: {:?}", account_id, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::config::env_or;
+use crate::db;
+use crate::engine::now_millis;
+use crate::feature_flags;
+use crate::execution::binance::BinanceEndpoints;
+use crate::feed;
+use crate::jobs::{self, Task};
+use crate::models::{ListingEvent, ListingKind, OrderRequest, OrderType, Side, TimeInForce};
+use crate::state::AppState;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+const TRADING: &str = "TRADING";
+
+#[derive(Deserialize)]
+struct ExchangeInfo {
+    symbols: Vec<SymbolInfo>,
+}
+
+#[derive(Deserialize)]
+struct SymbolInfo {
+    symbol: String,
+    status: String,
+    #[serde(rename = "onboardDate")]
+    onboard_date: Option<i64>,
+}
+
+// Diffs exchangeInfo against the symbols seen before every LISTINGS_CHECK_SECS. New listings are
+// already in the market-wide ticker stream, their kline history since onboarding is backfilled.
+// Delisted symbols have their simulated orders cancelled and positions closed at the last price.
+// The first check only records what is listed.
+pub async fn run_listing_watcher(state: Arc<AppState>) {
+    let client = reqwest::Client::new();
+    let url = format!("{}/fapi/v1/exchangeInfo", BinanceEndpoints::futures_mainnet_data().rest_url);
+    let mut ticker = state.simulation.interval(Duration::from_secs(env_or("LISTINGS_CHECK_SECS", 300).max(1)));
+    loop {
+        ticker.tick().await;
+        let info = match fetch(&client, &url).await {
+            Ok(info) => info,
+            Err(e) => {
+                eprintln!("Error fetching exchangeInfo: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = sync(&state, info).await {
+            eprintln!("Error updating symbol listings: {}", e);
+        }
+    }
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<ExchangeInfo, reqwest::Error> {
+    client.get(url).send().await?.error_for_status()?.json().await
+}
+
+async fn sync(state: &Arc<AppState>, info: ExchangeInfo) -> Result<(), sqlx::Error> {
+    let known = db::load_symbol_listings(&state.pool).await?;
+    let seeding = known.is_empty();
+    let listed: HashSet<&str> = info.symbols.iter().map(|info| info.symbol.as_str()).collect();
+
+    for info in &info.symbols {
+        let trading = info.status == TRADING;
+        match known.get(&info.symbol) {
+            Some(was_trading) if *was_trading == trading => continue,
+            None if seeding || !trading => {
+                db::save_symbol_listing(&state.pool, &info.symbol, &info.status, trading).await?;
+                continue;
+            }
+            _ => {}
+        }
+        db::save_symbol_listing(&state.pool, &info.symbol, &info.status, trading).await?;
+        if trading {
+            listed_symbol(state, info).await;
+        } else {
+            delisted_symbol(state, &info.symbol, &info.status).await;
+        }
+    }
+
+    // Symbols gone from exchangeInfo altogether are delisted too
+    for (symbol, trading) in &known {
+        if !trading || listed.contains(symbol.as_str()) {
+            continue;
+        }
+        db::save_symbol_listing(&state.pool, symbol, "REMOVED", false).await?;
+        delisted_symbol(state, symbol, "REMOVED").await;
+    }
+    Ok(())
+}
+
+fn publish(state: &AppState, symbol: &str, kind: ListingKind, status: &str) {
+    println!("Symbol {} {:?} ({})", symbol, kind, status);
+    // No receivers simply means nobody is listening
+    let _ = state.listings.send(ListingEvent {
+        symbol: symbol.to_string(),
+        kind,
+        status: status.to_string(),
+        detected_at: now_millis(),
+    });
+}
+
+async fn listed_symbol(state: &AppState, info: &SymbolInfo) {
+    publish(state, &info.symbol, ListingKind::Listed, &info.status);
+    let Some(start_time) = info.onboard_date else {
+        return;
+    };
+    let task = Task::Backfill {
+        symbol: info.symbol.clone(),
+        interval: env_or("LISTINGS_BACKFILL_INTERVAL", "1m".to_string()),
+        start_time,
+        end_time: None,
+    };
+    if let Err(e) = jobs::enqueue(&state.pool, &task).await {
+        eprintln!("Error queueing backfill of new listing {}: {}", info.symbol, e);
+    }
+}
+
+// Cancels working orders in the symbol and flattens every position with a market order, matched
+// right away against the last price since no more ticks will come
+async fn delisted_symbol(state: &Arc<AppState>, symbol: &str, status: &str) {
+    publish(state, symbol, ListingKind::Delisted, status);
+    if let Err(message) = feature_flags::require(state, "delisting_close", None).await {
+        println!("Leaving orders and positions in delisted {}: {}", symbol, message);
+        return;
+    }
+
+    let orders = state.engine.lock().await.open_orders(symbol);
+    for (account_id, order_id) in orders {
+        if let Err(e) = state.backend.cancel_order(&account_id, order_id).await {
+            eprintln!("Error cancelling order {} in delisted {}: {}", order_id, symbol, e);
+        }
+    }
+
+    let positions: Vec<(String, f64)> = {
+        let portfolios = state.portfolios.lock().await;
+        portfolios
+            .account_ids()
+            .into_iter()
+            .filter_map(|account_id| {
+                let quantity = portfolios.get(&account_id)?.positions.get(symbol)?.quantity;
+                (quantity != 0.0).then_some((account_id, quantity))
+            })
+            .collect()
+    };
+    for (account_id, quantity) in &positions {
+        let request = OrderRequest {
+            symbol: symbol.to_string(),
+            side: if *quantity > 0.0 { Side::Sell } else { Side::Buy },
+            order_type: OrderType::Market,
+            price: None,
+            quantity: quantity.abs(),
+            time_in_force: TimeInForce::default(),
+            client_order_id: None,
+        };
+        if let Err(e) = state.backend.place_order(account_id, request).await {
+            eprintln!("Error closing {} position of {} in delisted {}: {}", quantity, account_id, symbol, e);
+        }
+    }
+
+    if positions.is_empty() {
+        return;
+    }
+    let last_tick = state.engine.lock().await.last_tick(symbol);
+    if let Some((price, quote_volume)) = last_tick {
+        feed::match_price(state, symbol, price, quote_volume).await;
+    }
+}
@@ -1,17 +1,100 @@
 use dotenv::dotenv;
 use std::env;
 use std::error::Error;
-use tokio_tungstenite::{connect_async, accept_async, tungstenite::Message};
-use url::Url;
+use tokio_tungstenite::{accept_hdr_async, tungstenite::Message};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
 use tokio::net::TcpListener;
-use futures_util::{StreamExt, SinkExt};
+use futures_util::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::time::{interval, Duration};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Duration;
 
+mod accounts;
+mod alerts;
+mod analytics;
+mod archive;
+mod arrow_stream;
+mod assets;
+mod attribution;
+mod audit;
+mod auth;
+mod backfill;
+mod benchmarks;
+mod bots;
+mod candles;
+mod chaos;
+mod competitions;
+mod config;
+mod conversion;
+mod data_quality;
+mod dataset;
 mod db;
+mod depeg;
+mod depth;
+mod drawdown;
+mod engine;
+mod execution;
+mod explain;
+mod exports;
+mod exposure;
+mod feature_flags;
+mod feed;
+mod fix;
+mod footprint;
+mod graphql;
+mod grid;
+mod grpc;
+mod guests;
+mod handlers;
+mod http;
+mod inbox;
+mod index;
+mod indicators;
+mod ingest;
+mod ingest_metrics;
+mod jobs;
+mod key_levels;
+mod ladder;
+mod latency;
+mod ledger;
+mod listings;
+mod maintenance;
+mod mirror;
 mod models;
+mod notify;
+mod object_store;
+mod outbound;
+mod portfolio;
+mod profiles;
+mod rate_limit;
+mod reload;
+mod resilience;
+mod reports;
+mod reprocess;
+mod risk;
+mod rules;
+mod scheduler;
+mod simulation;
+mod spool;
+mod spread;
+mod state;
+mod storage;
+mod tape;
+mod teams;
+mod tenants;
+mod template;
+mod tick_filter;
+mod updates;
+mod usage;
 
-use models::{TickerData, PaginationParams};
+use models::{ClientMessage, ExportStage, ListingKind, ServerMessage, PaginationParams, TickerQuery, WireFormat};
+use state::AppState;
+
+// Tells a connection's own setting changes apart from those of the user's other connections
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -19,70 +102,317 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     println!("Connecting to database: {}", database_url);
-    let pool = Arc::new(db::init_db(&database_url).await?);
+    let pool = db::init_db(&database_url).await?;
+
+    // `reprocess <from_ms> <to_ms>` rebuilds derived tables from the raw archive and exits
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("reprocess") {
+        let from: i64 = args.get(2).and_then(|a| a.parse().ok()).expect("usage: reprocess <from_ms> <to_ms>");
+        let to: i64 = args.get(3).and_then(|a| a.parse().ok()).unwrap_or_else(engine::now_millis);
+        return reprocess::run_reprocess(&pool, from, to).await;
+    }
+
+    let drawdown_alerts = db::load_drawdown_alerts(&pool).await?;
+    let depeg_alerts = db::load_depeg_alerts(&pool).await?;
+    let feature_flags = db::load_feature_flags(&pool).await?;
+    let state = Arc::new(AppState::new(pool));
+    state.drawdowns.lock().await.load_alerts(drawdown_alerts);
+    state.depeg.lock().await.load_alerts(depeg_alerts);
+    state.feature_flags.lock().await.load(feature_flags);
+    state
+        .notifications
+        .load_channels(db::load_notification_channels(&state.pool).await?)
+        .await;
+    {
+        let mut templates = state.account_templates.lock().await;
+        templates.load(
+            db::load_account_templates(&state.pool).await?,
+            db::load_account_assignments(&state.pool).await?,
+        );
+        let mut portfolios = state.portfolios.lock().await;
+        for (account_id, starting_balance) in db::load_guests(&state.pool).await? {
+            portfolios.open_account(&account_id, starting_balance);
+        }
+        for (account_id, template) in templates.assignments() {
+            portfolios.open_account(account_id, template.starting_balance);
+            let rates = accounts::fee_rates(&state.live_config, &template.fee_tier).unwrap_or_default();
+            portfolios.set_fee_rates(account_id, rates);
+        }
+    }
+    state
+        .competitions
+        .lock()
+        .await
+        .load(db::load_competition_windows(&state.pool).await?);
+    state.teams.lock().await.load(db::load_teams(&state.pool).await?);
+    state.tenants.lock().await.load(
+        db::load_tenants(&state.pool).await?,
+        db::load_account_tenants(&state.pool).await?,
+    );
+    state.trading_rules.lock().await.load(db::load_trading_rules(&state.pool).await?);
+    state.grid_bots.lock().await.load(db::load_grid_bots(&state.pool).await?);
+    state.bots.lock().await.load(db::load_bots(&state.pool).await?);
+    // Cash and positions come back from the account event logs, replacing the opening balances above
+    let restored = ledger::restore_portfolios(&state).await?;
+    println!("Restored {} accounts from their event logs", restored);
+    // Working orders survive restarts, matching resumes with the next tick of their symbol
+    let stored = db::load_orders(&state.pool).await?;
+    println!("Restored {} orders and {} order groups", stored.orders.len(), stored.groups.len());
+    *state.engine.lock().await = engine::MatchingEngine::restore(stored, state.simulation.clock());
+
+    // Binance market data over as many upstream connections as the configured streams need
+    tokio::spawn(feed::run_feed(Arc::clone(&state)));
+
+    // Mirror a linked real Binance account, if configured
+    if let Some(mirror_config) = mirror::MirrorConfig::from_env() {
+        tokio::spawn(mirror::run_mirror(state.pool.clone(), mirror_config));
+    }
+
+    // Record fills and alerts for offline accounts in their inbox
+    tokio::spawn(inbox::run_inbox_recorder(Arc::clone(&state)));
+
+    // Buffer pushes for GET /api/updates
+    tokio::spawn(updates::run_update_recorder(Arc::clone(&state)));
+
+    // Snapshot account event logs so startup replays stay short
+    tokio::spawn(ledger::run_snapshotter(Arc::clone(&state)));
+
+    // Connection and subscription counts for capacity planning
+    tokio::spawn(usage::run_usage_recorder(Arc::clone(&state)));
+
+    // Replay ticks spooled to disk during database outages
+    tokio::spawn(spool::run_spool_replay(Arc::clone(&state)));
+
+    // Record gaps in the ticker stream for data-quality reporting
+    tokio::spawn(data_quality::run_gap_scanner(state.pool.clone()));
+
+    // Candles for research tools as a partitioned Parquet dataset, if configured
+    if let Some(dataset_config) = dataset::DatasetConfig::from_env() {
+        tokio::spawn(dataset::run_dataset_writer(Arc::clone(&state), dataset_config));
+    }
+
+    // Answer the fills of running grid bots
+    tokio::spawn(grid::run_grid_bots(Arc::clone(&state)));
+
+    // Trade the cycles of running DCA and martingale bots
+    tokio::spawn(bots::run_bots(Arc::clone(&state)));
+
+    // Peak equity and drawdown tracking with threshold alerts
+    tokio::spawn(drawdown::run_drawdown_monitor(Arc::clone(&state)));
+
+    // Stablecoin pair deviations from 1.0 for the depeg channel and alerts
+    tokio::spawn(depeg::run_depeg_monitor(Arc::clone(&state)));
+
+    // Symbol listings and delistings from exchangeInfo
+    tokio::spawn(listings::run_listing_watcher(Arc::clone(&state)));
+
+    // Apply config file changes without a restart
+    tokio::spawn(reload::run_config_watcher(Arc::clone(&state)));
+
+    // Workers for backfills, imports, report sending and guest sweeps
+    tokio::spawn(jobs::run_workers(Arc::clone(&state)));
 
-    // Spawn Binance WebSocket listener as a separate task
-    let binance_pool = Arc::clone(&pool);
+    // Scheduled daily account summaries over webhook or email
+    tokio::spawn(reports::run_report_scheduler(Arc::clone(&state)));
+
+    // Final rankings of competitions as they end
+    tokio::spawn(competitions::run_competition_scheduler(Arc::clone(&state)));
+
+    // Expire guest accounts after a period of inactivity
+    tokio::spawn(guests::run_guest_reaper(Arc::clone(&state)));
+
+    // Daily closes and risk, equity snapshots and order history retention, caught up after downtime
+    tokio::spawn(scheduler::run_scheduler(Arc::clone(&state)));
+
+    // Recurring simulated outages, if configured
+    if let Some(schedule) = chaos::ChaosSchedule::from_env() {
+        let chaos_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let mut ticker = chaos_state.simulation.interval(schedule.every);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                println!("Scheduled outage {:?} for {:?}", schedule.mode, schedule.duration);
+                chaos_state.chaos.lock().await.start(schedule.mode, schedule.duration);
+            }
+        });
+    }
+
+    // REST endpoints
+    let http_addr = env::var("HTTP_URL").unwrap_or_else(|_| "0.0.0.0:8081".to_string());
+    let http_state = Arc::clone(&state);
     tokio::spawn(async move {
-        if let Err(e) = handle_binance_ws(binance_pool).await {
-            eprintln!("Binance WebSocket error: {:?}", e);
+        if let Err(e) = http::serve(http_state, http_addr).await {
+            eprintln!("HTTP server error: {:?}", e);
         }
     });
 
+    // Typed gRPC API next to the JSON protocols, only when a port is configured
+    if let Ok(grpc_addr) = env::var("GRPC_URL") {
+        let grpc_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(grpc_state, grpc_addr).await {
+                eprintln!("gRPC server error: {:?}", e);
+            }
+        });
+    }
+
+    // FIX order entry for algo clients, only when a port is configured
+    if let Ok(fix_addr) = env::var("FIX_URL") {
+        let fix_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            if let Err(e) = fix::serve(fix_state, fix_addr).await {
+                eprintln!("FIX gateway error: {:?}", e);
+            }
+        });
+    }
+
     let bind_addr = env::var("WEBSOCKET_URL").expect("WEBSOCKET_URL must be set");
     let listener = TcpListener::bind(&bind_addr).await?;
     println!("WebSocket server started on {}", bind_addr);
 
     while let Ok((stream, addr)) = listener.accept().await {
         println!("New connection from {}", addr);
-        let pool_clone = Arc::clone(&pool);
-        tokio::spawn(handle_connection(stream, pool_clone));
+        let state_clone = Arc::clone(&state);
+        tokio::spawn(handle_connection(stream, addr, state_clone));
     }
 
     Ok(())
 }
 
-async fn handle_binance_ws(pool: Arc<sqlx::PgPool>) -> Result<(), Box<dyn Error>> {
-    let url = Url::parse("wss://fstream.binance.com/ws/!miniTicker@arr")?;
-    let (mut ws_stream, _) = connect_async(url.as_str()).await?;
-
-    println!("Connected to Binance WebSocket!");
+// The handshake callback's error is tungstenite's own response type, large as it is
+#[allow(clippy::result_large_err)]
+async fn handle_connection(
+    stream: tokio::net::TcpStream,
+    addr: std::net::SocketAddr,
+    state: Arc<AppState>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut path = String::new();
+    let mut token = None;
+    let ws_stream = accept_hdr_async(stream, |request: &Request, response: Response| {
+        let origin = request.headers().get("origin").and_then(|value| value.to_str().ok());
+        if !state.allowed_origins.allows(origin) {
+            eprintln!("Rejected WebSocket upgrade from origin {:?}", origin);
+            let mut rejection = ErrorResponse::new(Some("Origin not allowed".to_string()));
+            *rejection.status_mut() = StatusCode::FORBIDDEN;
+            return Err(rejection);
+        }
+        path = request.uri().path().to_string();
+        token = auth::query_token(request.uri().query());
+        Ok(response)
+    })
+    .await?;
+    println!("WebSocket connection established on {}", path);
 
-    while let Some(msg) = ws_stream.next().await {
-        match msg {
-            Ok(msg) => {
-                if let Ok(tickers) = serde_json::from_str::<Vec<TickerData>>(&msg.to_string()) {
-                    for ticker in tickers {
-                        if let Err(e) = db::save_ticker_data(&pool, &ticker).await {
-                            eprintln!("Error saving ticker data: {:?}", e);
-                        }
+    let mut session = auth::Session {
+        ip: Some(addr.ip().to_string()),
+        connection_id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+        ..auth::Session::anonymous()
+    };
+    let mut usage = state.usage.connection();
+    // A `?token=` login goes through the same path as an Authenticate message, audit included
+    if let Some(token) = token {
+        match handlers::handle_client_message(&state, &session, ClientMessage::Authenticate { token }).await {
+            ServerMessage::Authenticated {
+                user_id,
+                role,
+                key,
+                session_id,
+                tenant_id,
+                ..
+            } => match tenants::admit_connection(&state, &mut usage, tenant_id.as_deref()).await {
+                Ok(()) => {
+                    session = auth::Session {
+                        user_id: Some(user_id),
+                        role,
+                        key,
+                        session_id,
+                        tenant_id,
+                        ..session
                     }
                 }
-            }
-            Err(e) => eprintln!("Error receiving message: {:?}", e),
+                Err(message) => eprintln!("Connection token rejected: {}", message),
+            },
+            reply => eprintln!("Connection token rejected: {:?}", reply),
         }
     }
 
-    Ok(())
-}
-
-async fn handle_connection(
-    stream: tokio::net::TcpStream,
-    pool: Arc<sqlx::PgPool>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let ws_stream = accept_async(stream).await?;
-    println!("WebSocket connection established");
-
-    let (mut write, mut read) = ws_stream.split();
-    let mut interval = interval(Duration::from_secs(60)); // Changed to 60 seconds
+    let (write, mut read) = ws_stream.split();
+    let outbound = outbound::Outbound::spawn(Arc::clone(&state), write);
+    let mut interval = state.simulation.interval(Duration::from_secs(state.update_intervals.page(None)));
 
     let mut current_page = 1;
     let mut items_per_page = 30;
+    let mut page_fields: Option<Vec<String>> = None; // Row fields picked by the client, None for all
+    let mut page_format = WireFormat::Standard;
+    let mut ticker_query = TickerQuery::default();
+
+    // Accounts this connection has traded on, their fills are pushed to it
+    let mut accounts: HashSet<String> = HashSet::new();
+    // Order entry waits out simulated latency and outages in its own task, in arrival order, so the
+    // connection keeps streaming meanwhile. Replies come back with the account they authorized and
+    // the login they were sent under.
+    let (orders, mut pending_orders) = mpsc::unbounded_channel::<(auth::Session, ClientMessage, Option<String>)>();
+    let (order_replies, mut order_reply) = mpsc::unbounded_channel();
+    tokio::spawn({
+        let state = Arc::clone(&state);
+        async move {
+            while let Some((session, message, account_id)) = pending_orders.recv().await {
+                let (reply, authorized) = handlers::handle_message(&state, &session, message).await;
+                if order_replies.send((reply, account_id.filter(|_| authorized), session.session_id)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    let mut fills = state.fills.subscribe();
+    let mut alerts = state.alerts.subscribe();
+    let mut settings = state.settings.subscribe();
+    let mut exports = state.exports.subscribe();
+    let mut depeg = state.depeg_updates.subscribe();
+    let mut depeg_subscribed = false;
+    let mut key_level_updates = state.key_level_updates.subscribe();
+    let mut key_level_symbols: HashSet<String> = HashSet::new();
+    let mut listings = state.listings.subscribe();
+    let listings_channel = handlers::is_listings_path(&path);
+    let mut maintenance_notices = state.maintenance_notices.subscribe();
+    let mut revocations = state.revocations.subscribe();
+
+    // Symbols streamed live to this connection, from the path or Subscribe messages, with the least
+    // time between two of their updates, the fields sent, their format, and when the last one went out
+    let default_ticker_ms = state.update_intervals.ticker(None);
+    let mut symbols: HashMap<String, (u64, Option<Vec<String>>, WireFormat)> = handlers::path_symbols(&path)
+        .into_iter()
+        .map(|symbol| (symbol, (default_ticker_ms, None, WireFormat::Standard)))
+        .collect();
+    let mut watcher = state.feed_interest.watcher();
+    watcher.set(symbols.keys());
+    let mut last_pushed: HashMap<String, i64> = HashMap::new();
+    let mut tickers = state.tickers.subscribe();
+    let mut indices: HashMap<String, index::CustomIndex> = HashMap::new();
+    let mut spreads: HashMap<String, spread::SpreadQuote> = HashMap::new();
+    // DOM ladders pushed to this connection, by symbol, with their account, depth and row width
+    let mut ladders: HashMap<String, (String, Option<usize>, Option<f64>)> = HashMap::new();
+    let mut ladder_interval = state.simulation.interval(ladder::interval());
+    // Time and sales pushed to this connection
+    let mut tape = tape::TapeStream::default();
+    let mut trades = state.trades.subscribe();
+    let mut tape_interval = state.simulation.interval(tape::interval());
+    // Indicators computed from the same trades
+    let mut indicators = indicators::IndicatorStream::default();
+    let mut indicator_interval = state.simulation.interval(indicators::interval());
+
+    // Clients joining during announced maintenance learn about it first
+    if let Some(window) = maintenance::current(&state).await {
+        if let Ok(json) = serde_json::to_string(&ServerMessage::Maintenance(window)) {
+            let _ = outbound.trading(json);
+        }
+    }
 
     // Send initial data immediately
-    if let Ok(tickers) = db::get_latest_tickers(&pool, current_page, items_per_page).await {
+    if let Some(tickers) = state.ticker_page(current_page, items_per_page, &ticker_query).await {
         if let Ok(json) = serde_json::to_string(&tickers) {
-            let _ = write.send(Message::Text(json.into())).await;
+            let _ = outbound.market(json);
         }
     }
 
@@ -91,13 +421,184 @@ async fn handle_connection(
             Some(msg_result) = read.next() => {
                 match msg_result {
                     Ok(Message::Text(text)) => {
-                        if let Ok(params) = serde_json::from_str::<PaginationParams>(&text) {
+                        state.usage.message_in();
+                        if let Ok(client_msg) = serde_json::from_str::<ClientMessage>(&text) {
+                            let account_id = handlers::message_account(&client_msg).map(str::to_string);
+                            match &client_msg {
+                                ClientMessage::Subscribe { symbols: requested, interval_ms, fields, format } => {
+                                    let interval_ms = state.update_intervals.ticker(*interval_ms);
+                                    // An invalid selection subscribes nothing, the reply carries the error
+                                    if let Ok(fields) = handlers::field_selection(fields.as_deref(), handlers::TICKER_FIELDS) {
+                                        for symbol in handlers::normalize_symbols(requested) {
+                                            symbols.insert(symbol, (interval_ms, fields.clone(), *format));
+                                        }
+                                    }
+                                }
+                                ClientMessage::Unsubscribe { symbols: requested } => {
+                                    for symbol in handlers::normalize_symbols(requested) {
+                                        symbols.remove(&symbol);
+                                        last_pushed.remove(&symbol);
+                                    }
+                                }
+                                _ => {}
+                            }
+                            if handlers::is_order_entry(&client_msg) {
+                                let _ = orders.send((session.clone(), client_msg, account_id));
+                                continue;
+                            }
+                            let (mut reply, authorized) = handlers::handle_message(&state, &session, client_msg).await;
+                            // Fills and alerts only follow accounts the session was allowed to use
+                            if let Some(account_id) = account_id.filter(|_| authorized) {
+                                if accounts.insert(account_id.clone()) {
+                                    state.session_opened(&account_id).await;
+                                }
+                            }
+                            match &reply {
+                                ServerMessage::Authenticated {
+                                    user_id,
+                                    role,
+                                    key,
+                                    session_id,
+                                    tenant_id,
+                                    ..
+                                } => match tenants::admit_connection(&state, &mut usage, tenant_id.as_deref()).await {
+                                    Ok(()) => {
+                                        // Another user or key doesn't inherit the accounts followed so far
+                                        let same_login = session.user_id.as_ref() == Some(user_id)
+                                            && session.key.as_ref().map(|key| &key.key_id)
+                                                == key.as_ref().map(|key| &key.key_id);
+                                        if !same_login {
+                                            release_accounts(&state, &mut accounts).await;
+                                            ladders.clear();
+                                        }
+                                        session = auth::Session {
+                                            user_id: Some(user_id.clone()),
+                                            role: *role,
+                                            key: key.clone(),
+                                            session_id: session_id.clone(),
+                                            tenant_id: tenant_id.clone(),
+                                            ..session
+                                        };
+                                    }
+                                    Err(message) => reply = ServerMessage::Error { message },
+                                },
+                                ServerMessage::LoggedOut => {
+                                    let _ = usage.set_tenant(None, None);
+                                    release_accounts(&state, &mut accounts).await;
+                                    ladders.clear();
+                                    session = session.logged_out();
+                                }
+                                // Seed the index from cached prices and answer with its first value
+                                ServerMessage::IndexSubscribed(definition) => {
+                                    let custom = {
+                                        let engine = state.engine.lock().await;
+                                        index::CustomIndex::new(definition.clone(), |symbol| engine.last_price(symbol))
+                                    };
+                                    let snapshot = custom.snapshot();
+                                    indices.insert(custom.name().to_string(), custom);
+                                    reply = ServerMessage::Index(snapshot);
+                                }
+                                ServerMessage::IndexUnsubscribed { name } => {
+                                    indices.remove(name);
+                                }
+                                ServerMessage::SpreadSubscribed(definition) => {
+                                    let quote = {
+                                        let engine = state.engine.lock().await;
+                                        spread::SpreadQuote::new(definition.clone(), |symbol| engine.last_price(symbol))
+                                    };
+                                    let snapshot = quote.snapshot();
+                                    spreads.insert(quote.name().to_string(), quote);
+                                    reply = ServerMessage::Spread(snapshot);
+                                }
+                                ServerMessage::SpreadUnsubscribed { name } => {
+                                    spreads.remove(name);
+                                }
+                                ServerMessage::LadderSubscribed { levels, ladder } => {
+                                    let subscription = (ladder.account_id.clone(), *levels, ladder.group);
+                                    ladders.insert(ladder.symbol.clone(), subscription);
+                                }
+                                ServerMessage::LadderUnsubscribed { symbol } => {
+                                    ladders.remove(symbol);
+                                }
+                                ServerMessage::TradesSubscribed { symbol, aggregate, prints } => {
+                                    // Trades are not read while nothing is subscribed, start from the latest
+                                    if tape.is_empty() && indicators.is_empty() {
+                                        trades = trades.resubscribe();
+                                    }
+                                    tape.subscribe(symbol.clone(), *aggregate, prints.first().map(|print| print.price));
+                                }
+                                ServerMessage::TradesUnsubscribed { symbol } => tape.unsubscribe(symbol),
+                                ServerMessage::IndicatorSubscribed { symbol, interval, indicator, values } => {
+                                    if tape.is_empty() && indicators.is_empty() {
+                                        trades = trades.resubscribe();
+                                    }
+                                    let last = values.last().cloned();
+                                    indicators.subscribe(symbol.clone(), interval.clone(), *indicator, last);
+                                }
+                                ServerMessage::IndicatorUnsubscribed { symbol, interval, indicator } => {
+                                    indicators.unsubscribe(symbol, interval, *indicator);
+                                }
+                                ServerMessage::KeyLevelsSubscribed { levels } => {
+                                    key_level_symbols.extend(levels.iter().map(|levels| levels.symbol.clone()));
+                                }
+                                ServerMessage::KeyLevelsUnsubscribed { symbols } => {
+                                    for symbol in symbols {
+                                        key_level_symbols.remove(symbol);
+                                    }
+                                }
+                                ServerMessage::DepegSubscribed { .. } => depeg_subscribed = true,
+                                ServerMessage::DepegUnsubscribed => depeg_subscribed = false,
+                                _ => {}
+                            }
+                            if let Ok(json) = serde_json::to_string(&reply) {
+                                if outbound.trading(json).is_err() {
+                                    break;
+                                }
+                            }
+                            usage.set_subscriptions(symbols.len(), indices.len(), accounts.len());
+                            let streamed = symbols.keys().chain(ladders.keys()).chain(tape.symbols());
+                            watcher.set(streamed.chain(indicators.symbols()));
+                        } else if let Ok(params) = serde_json::from_str::<PaginationParams>(&text) {
+                            if let Some(fields) = &params.fields {
+                                match handlers::field_selection(Some(fields), handlers::PAGE_ROW_FIELDS) {
+                                    Ok(fields) => page_fields = fields,
+                                    Err(message) => {
+                                        if let Ok(json) = serde_json::to_string(&ServerMessage::Error { message }) {
+                                            let _ = outbound.trading(json);
+                                        }
+                                    }
+                                }
+                            }
+                            if let Some(format) = params.format {
+                                page_format = format;
+                            }
+                            if let Some(secs) = params.interval_secs {
+                                let period = Duration::from_secs(state.update_intervals.page(Some(secs)));
+                                interval = state.simulation.interval_at(period, period);
+                            }
+                            let mut changed = false;
+                            if params.quote.is_some() || params.sort.is_some() {
+                                match handlers::ticker_query(&state, &params, &ticker_query) {
+                                    Ok(query) => {
+                                        ticker_query = query;
+                                        changed = true;
+                                    }
+                                    Err(message) => {
+                                        if let Ok(json) = serde_json::to_string(&ServerMessage::Error { message }) {
+                                            let _ = outbound.trading(json);
+                                        }
+                                    }
+                                }
+                            }
                             if let Some(page) = params.page {
                                 current_page = page;
-                                // Send updated data immediately after page change
-                                if let Ok(tickers) = db::get_latest_tickers(&pool, current_page, items_per_page).await {
-                                    if let Ok(json) = serde_json::to_string(&tickers) {
-                                        let _ = write.send(Message::Text(json.into())).await;
+                                changed = true;
+                            }
+                            // Send updated data immediately after a page or filter change
+                            if changed {
+                                if let Some(tickers) = state.ticker_page(current_page, items_per_page, &ticker_query).await {
+                                    if let Some(json) = handlers::page_frame(&tickers, page_fields.as_deref(), page_format) {
+                                        let _ = outbound.market(json);
                                     }
                                 }
                             }
@@ -112,11 +613,281 @@ async fn handle_connection(
                 }
             }
 
+            Some((reply, account_id, login)) = order_reply.recv() => {
+                // Orders sent before the login changed don't follow their account
+                if let Some(account_id) = account_id.filter(|_| login == session.session_id) {
+                    if accounts.insert(account_id.clone()) {
+                        state.session_opened(&account_id).await;
+                        usage.set_subscriptions(symbols.len(), indices.len(), accounts.len());
+                    }
+                }
+                if let Ok(json) = serde_json::to_string(&reply) {
+                    if outbound.trading(json).is_err() {
+                        break;
+                    }
+                }
+            }
+
+            fill_result = fills.recv() => {
+                match fill_result {
+                    Ok(fill) if accounts.contains(&fill.account_id) => {
+                        if let Ok(json) = serde_json::to_string(&ServerMessage::Fill(fill)) {
+                            if outbound.trading(json).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Connection lagged behind, {} fills dropped", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            ticker_result = tickers.recv(), if !symbols.is_empty() || !indices.is_empty() || !spreads.is_empty() => {
+                match ticker_result {
+                    Ok(update) => {
+                        let index_updates = indices
+                            .values_mut()
+                            .filter_map(|custom| custom.on_ticker(&update))
+                            .map(ServerMessage::Index);
+                        let spread_updates = spreads
+                            .values_mut()
+                            .filter_map(|quote| quote.on_ticker(&update))
+                            .map(ServerMessage::Spread);
+                        let mut frames: Vec<String> = index_updates
+                            .chain(spread_updates)
+                            .filter_map(|message| serde_json::to_string(&message).ok())
+                            .collect();
+                        // Updates inside a symbol's interval are skipped, the next one carries the newer price
+                        if let Some((interval_ms, fields, format)) = symbols.get(&update.symbol) {
+                            let now = state.simulation.now_millis();
+                            let due = last_pushed
+                                .get(&update.symbol)
+                                .is_none_or(|last| now - last >= *interval_ms as i64);
+                            if due {
+                                last_pushed.insert(update.symbol.clone(), now);
+                                if let Some(frame) = handlers::ticker_frame(update, fields.as_deref(), *format) {
+                                    frames.insert(0, frame);
+                                }
+                            }
+                        }
+                        if frames.into_iter().any(|frame| outbound.market(frame).is_err()) {
+                            break;
+                        }
+                    }
+                    // Prices are superseded by the next update, skipped ones are not worth reporting
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            // A login revoked elsewhere logs this connection out as well
+            revocation = revocations.recv() => {
+                let ended = match revocation {
+                    Ok(revocation) => revocation.ends(&session),
+                    // The missed revocations may have included this login, the database has the answer
+                    Err(broadcast::error::RecvError::Lagged(_)) => !auth::session_active(&state, &session).await,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if ended {
+                    let _ = usage.set_tenant(None, None);
+                    release_accounts(&state, &mut accounts).await;
+                    ladders.clear();
+                    session = session.logged_out();
+                    usage.set_subscriptions(symbols.len(), indices.len(), accounts.len());
+                    if let Ok(json) = serde_json::to_string(&ServerMessage::LoggedOut) {
+                        if outbound.trading(json).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            alert_result = alerts.recv() => {
+                match alert_result {
+                    Ok(alert) if accounts.contains(&alert.account_id) => {
+                        if let Ok(json) = serde_json::to_string(&ServerMessage::Alert(alert)) {
+                            if outbound.trading(json).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            depeg_result = depeg.recv(), if depeg_subscribed => {
+                match depeg_result {
+                    Ok(status) => {
+                        if let Ok(json) = serde_json::to_string(&ServerMessage::Depeg(status)) {
+                            if outbound.market(json).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    // Superseded by the pair's next update
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            key_level_result = key_level_updates.recv(), if !key_level_symbols.is_empty() => {
+                match key_level_result {
+                    Ok(levels) if key_level_symbols.contains(&levels.symbol) => {
+                        if let Ok(json) = serde_json::to_string(&ServerMessage::KeyLevels(levels)) {
+                            if outbound.market(json).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    // Superseded by the symbol's next move
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            notice_result = maintenance_notices.recv() => {
+                match notice_result {
+                    Ok(notice) => {
+                        let message = match notice {
+                            Some(window) => ServerMessage::Maintenance(window),
+                            None => ServerMessage::MaintenanceEnded,
+                        };
+                        if let Ok(json) = serde_json::to_string(&message) {
+                            if outbound.trading(json).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    // Only the latest notice matters, and it is still to come
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            listing_result = listings.recv() => {
+                match listing_result {
+                    Ok(event) => {
+                        // A delisted symbol stops streaming, its last price was the final one
+                        if event.kind == ListingKind::Delisted && symbols.remove(&event.symbol).is_some() {
+                            last_pushed.remove(&event.symbol);
+                            let streamed = symbols.keys().chain(ladders.keys()).chain(tape.symbols());
+                            watcher.set(streamed.chain(indicators.symbols()));
+                            usage.set_subscriptions(symbols.len(), indices.len(), accounts.len());
+                            let message = ServerMessage::Unsubscribed {
+                                symbols: vec![event.symbol.clone()],
+                            };
+                            if let Ok(json) = serde_json::to_string(&message) {
+                                if outbound.trading(json).is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        if listings_channel {
+                            if let Ok(json) = serde_json::to_string(&ServerMessage::Listing(event)) {
+                                if outbound.market(json).is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Connection lagged behind, {} listing events dropped", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            setting_result = settings.recv(), if session.user_id.is_some() => {
+                match setting_result {
+                    Ok(change) if change.origin != session.connection_id && session.user_id.as_ref() == Some(&change.user_id) => {
+                        let message = ServerMessage::SettingChanged {
+                            key: change.key,
+                            value: change.value,
+                        };
+                        if let Ok(json) = serde_json::to_string(&message) {
+                            if outbound.trading(json).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Connection lagged behind, {} setting changes dropped", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            export_result = exports.recv() => {
+                match export_result {
+                    Ok(update) if update.connection_id == session.connection_id => {
+                        let token = update.token;
+                        let message = match update.stage {
+                            ExportStage::Progress { percent, rows } => ServerMessage::ExportProgress { token, percent, rows },
+                            ExportStage::Ready { url, rows } => ServerMessage::ExportReady { token, url, rows },
+                            ExportStage::Failed { message } => ServerMessage::ExportFailed { token, message },
+                        };
+                        if let Ok(json) = serde_json::to_string(&message) {
+                            if outbound.trading(json).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    // A missed percentage is covered by the next one
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            trade_result = trades.recv(), if !tape.is_empty() || !indicators.is_empty() => {
+                match trade_result {
+                    Ok(trade) => {
+                        indicators.push(&trade);
+                        tape.push(trade);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Connection lagged behind, {} trades dropped", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            _ = tape_interval.tick(), if !tape.is_empty() => {
+                let mut frames = tape.flush().into_iter().filter_map(|message| serde_json::to_string(&message).ok());
+                if frames.any(|frame| outbound.market(frame).is_err()) {
+                    break;
+                }
+            }
+
+            _ = indicator_interval.tick(), if !indicators.is_empty() => {
+                let messages = indicators.flush();
+                let mut frames = messages.iter().filter_map(|message| serde_json::to_string(message).ok());
+                if frames.any(|frame| outbound.market(frame).is_err()) {
+                    break;
+                }
+            }
+
+            _ = ladder_interval.tick(), if !ladders.is_empty() => {
+                let mut frames = Vec::new();
+                for (symbol, (account_id, levels, group)) in &ladders {
+                    // Skipped while the symbol's book is not in yet
+                    if let Ok(ladder) = ladder::ladder(&state, account_id, symbol, *levels, *group).await {
+                        frames.extend(serde_json::to_string(&ServerMessage::Ladder(ladder)).ok());
+                    }
+                }
+                if frames.into_iter().any(|frame| outbound.market(frame).is_err()) {
+                    break;
+                }
+            }
+
             _ = interval.tick() => {
-                if let Ok(tickers) = db::get_latest_tickers(&pool, current_page, items_per_page).await {
-                    if let Ok(json) = serde_json::to_string(&tickers) {
-                        if let Err(e) = write.send(Message::Text(json.into())).await {
-                            eprintln!("Error sending message: {:?}", e);
+                if let Some(tickers) = state.ticker_page(current_page, items_per_page, &ticker_query).await {
+                    if let Some(json) = handlers::page_frame(&tickers, page_fields.as_deref(), page_format) {
+                        if outbound.market(json).is_err() {
                             break;
                         }
                     }
@@ -125,5 +896,14 @@ async fn handle_connection(
         }
     }
 
+    release_accounts(&state, &mut accounts).await;
+
     Ok(())
 }
+
+// Stops following the accounts of a login that ended, the next one follows its own as it uses them
+async fn release_accounts(state: &AppState, accounts: &mut HashSet<String>) {
+    for account_id in accounts.drain() {
+        state.session_closed(&account_id).await;
+    }
+}
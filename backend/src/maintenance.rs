@@ -0,0 +1,57 @@
+use crate::audit;
+use crate::models::{ClientMessage, MaintenanceWindow};
+use crate::state::AppState;
+
+// Messages still accepted in read-only mode: signing in and out, and the admin ending it or reloading config
+fn allowed(msg: &ClientMessage) -> bool {
+    matches!(
+        msg,
+        ClientMessage::Authenticate { .. }
+            | ClientMessage::RefreshSession { .. }
+            | ClientMessage::Logout
+            | ClientMessage::SetMaintenance { .. }
+            | ClientMessage::EndMaintenance { .. }
+            | ClientMessage::ReloadConfig { .. }
+    )
+}
+
+// The announced window, if one is scheduled or running. A window past its end is dropped.
+pub async fn current(state: &AppState) -> Option<MaintenanceWindow> {
+    let mut maintenance = state.maintenance.lock().await;
+    if maintenance
+        .as_ref()
+        .and_then(|window| window.ends_at)
+        .is_some_and(|ends_at| ends_at <= state.simulation.now_millis())
+    {
+        *maintenance = None;
+    }
+    maintenance.clone()
+}
+
+// Announces the window to every connection, replacing any earlier one
+pub async fn schedule(state: &AppState, window: MaintenanceWindow) {
+    println!("Maintenance scheduled from {} until {:?}: {}", window.starts_at, window.ends_at, window.message);
+    *state.maintenance.lock().await = Some(window.clone());
+    let _ = state.maintenance_notices.send(Some(window));
+}
+
+pub async fn end(state: &AppState) {
+    println!("Maintenance ended");
+    *state.maintenance.lock().await = None;
+    let _ = state.maintenance_notices.send(None);
+}
+
+// While a window is running every message that changes orders, accounts or settings is turned
+// away, market data and other reads carry on
+pub async fn check(state: &AppState, msg: &ClientMessage) -> Result<(), String> {
+    if audit::action(msg).is_none() || allowed(msg) {
+        return Ok(());
+    }
+    match current(state).await {
+        Some(window) if window.starts_at <= state.simulation.now_millis() => Err(format!(
+            "The server is in read-only mode for maintenance, orders and account changes are rejected: {}",
+            window.message
+        )),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,249 @@
+use crate::db;
+use crate::models::{MirrorBalance, MirrorFill, MirrorPosition};
+use crate::execution::binance::{
+    BinanceClient, BinanceCredentials, BinanceEndpoints, LISTEN_KEY_KEEPALIVE,
+    USER_STREAM_RECONNECT_DELAY,
+};
+use futures_util::StreamExt;
+use reqwest::Method;
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::env;
+use std::error::Error;
+use tokio::time::{interval, sleep};
+
+// A real Binance account linked read-only to a simulator account id
+pub struct MirrorConfig {
+    pub account_id: String,
+    pub endpoints: BinanceEndpoints,
+    pub credentials: BinanceCredentials,
+}
+
+impl MirrorConfig {
+    // Mirroring is enabled by setting MIRROR_BINANCE_API_KEY and MIRROR_BINANCE_API_SECRET,
+    // a read-only key is enough since nothing is ever sent to the account
+    pub fn from_env() -> Option<Self> {
+        let api_key = env::var("MIRROR_BINANCE_API_KEY").ok()?;
+        let api_secret = env::var("MIRROR_BINANCE_API_SECRET").ok()?;
+        let endpoints = match env::var("MIRROR_BINANCE_TESTNET").as_deref() {
+            Ok("true") | Ok("1") => BinanceEndpoints::futures_testnet(),
+            _ => BinanceEndpoints::futures_mainnet_data(),
+        };
+
+        Some(MirrorConfig {
+            account_id: env::var("MIRROR_ACCOUNT_ID").unwrap_or_else(|_| "mirror".to_string()),
+            endpoints,
+            credentials: BinanceCredentials {
+                api_key,
+                api_secret,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountInfo {
+    assets: Vec<AssetInfo>,
+    positions: Vec<PositionInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssetInfo {
+    asset: String,
+    wallet_balance: String,
+    available_balance: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PositionInfo {
+    symbol: String,
+    position_amt: String,
+    entry_price: String,
+    unrealized_profit: String,
+    position_side: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserDataEvent {
+    #[serde(rename = "e")]
+    event_type: String,
+    #[serde(rename = "a")]
+    account: Option<AccountUpdate>,
+    #[serde(rename = "o")]
+    order: Option<TradeUpdate>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountUpdate {
+    #[serde(rename = "B", default)]
+    balances: Vec<BalanceUpdate>,
+    #[serde(rename = "P", default)]
+    positions: Vec<PositionUpdate>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BalanceUpdate {
+    #[serde(rename = "a")]
+    asset: String,
+    #[serde(rename = "wb")]
+    wallet_balance: String,
+    #[serde(rename = "cw")]
+    cross_wallet_balance: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PositionUpdate {
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "pa")]
+    position_amount: String,
+    #[serde(rename = "ep")]
+    entry_price: String,
+    #[serde(rename = "up")]
+    unrealized_pnl: String,
+    #[serde(rename = "ps")]
+    position_side: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeUpdate {
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "S")]
+    side: String,
+    #[serde(rename = "x")]
+    execution_type: String,
+    #[serde(rename = "i")]
+    order_id: i64,
+    #[serde(rename = "t")]
+    trade_id: i64,
+    #[serde(rename = "l")]
+    last_filled_quantity: String,
+    #[serde(rename = "L")]
+    last_filled_price: String,
+    #[serde(rename = "n", default)]
+    commission: Option<String>,
+    #[serde(rename = "T")]
+    trade_time: i64,
+}
+
+// Keep the mirror tables in sync with the linked account, reconnecting on failure
+pub async fn run_mirror(pool: PgPool, config: MirrorConfig) {
+    let client = BinanceClient::new(config.endpoints, config.credentials);
+    loop {
+        if let Err(e) = mirror_account(&pool, &client, &config.account_id).await {
+            eprintln!("Account mirror error: {}", e);
+        }
+        sleep(USER_STREAM_RECONNECT_DELAY).await;
+    }
+}
+
+async fn mirror_account(
+    pool: &PgPool,
+    client: &BinanceClient,
+    account_id: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Start from a full snapshot, the stream only carries changes
+    let info: AccountInfo = client
+        .signed_request(Method::GET, "/fapi/v2/account", Vec::new())
+        .await?;
+    for asset in &info.assets {
+        let balance = MirrorBalance {
+            asset: asset.asset.clone(),
+            wallet_balance: parse(&asset.wallet_balance),
+            available_balance: parse(&asset.available_balance),
+        };
+        db::upsert_mirror_balance(pool, account_id, &balance).await?;
+    }
+    for position in &info.positions {
+        let position = MirrorPosition {
+            symbol: position.symbol.clone(),
+            position_side: position.position_side.clone(),
+            position_amount: parse(&position.position_amt),
+            entry_price: parse(&position.entry_price),
+            unrealized_pnl: parse(&position.unrealized_profit),
+        };
+        db::upsert_mirror_position(pool, account_id, &position).await?;
+    }
+
+    let mut ws_stream = client.open_user_stream().await?;
+    println!("Mirroring Binance account into {}", account_id);
+
+    let mut keepalive = interval(LISTEN_KEY_KEEPALIVE);
+    keepalive.tick().await;
+
+    loop {
+        tokio::select! {
+            msg = ws_stream.next() => {
+                let Some(msg) = msg else {
+                    return Ok(());
+                };
+                let msg = msg?;
+                let Ok(text) = msg.to_text() else {
+                    continue;
+                };
+                match serde_json::from_str::<UserDataEvent>(text) {
+                    Ok(event) => apply_event(pool, account_id, event).await?,
+                    Err(e) => eprintln!("Unrecognized user data event: {} ({})", text, e),
+                }
+            }
+
+            _ = keepalive.tick() => {
+                if let Err(e) = client.keepalive_user_stream().await {
+                    eprintln!("Error keeping mirror listen key alive: {}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn apply_event(
+    pool: &PgPool,
+    account_id: &str,
+    event: UserDataEvent,
+) -> Result<(), sqlx::Error> {
+    match (event.event_type.as_str(), event.account, event.order) {
+        ("ACCOUNT_UPDATE", Some(update), _) => {
+            for balance in update.balances {
+                let balance = MirrorBalance {
+                    asset: balance.asset,
+                    wallet_balance: parse(&balance.wallet_balance),
+                    available_balance: parse(&balance.cross_wallet_balance),
+                };
+                db::upsert_mirror_balance(pool, account_id, &balance).await?;
+            }
+            for position in update.positions {
+                let position = MirrorPosition {
+                    symbol: position.symbol,
+                    position_side: position.position_side,
+                    position_amount: parse(&position.position_amount),
+                    entry_price: parse(&position.entry_price),
+                    unrealized_pnl: parse(&position.unrealized_pnl),
+                };
+                db::upsert_mirror_position(pool, account_id, &position).await?;
+            }
+        }
+        ("ORDER_TRADE_UPDATE", _, Some(trade)) if trade.execution_type == "TRADE" => {
+            let fill = MirrorFill {
+                trade_id: trade.trade_id,
+                order_id: trade.order_id,
+                symbol: trade.symbol,
+                side: trade.side.to_lowercase(),
+                price: parse(&trade.last_filled_price),
+                quantity: parse(&trade.last_filled_quantity),
+                commission: trade.commission.as_deref().map(parse).unwrap_or_default(),
+                created_at: trade.trade_time,
+            };
+            db::insert_mirror_fill(pool, account_id, &fill).await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn parse(value: &str) -> f64 {
+    value.parse::<f64>().unwrap_or_default()
+}
@@ -1,6 +1,10 @@
+use crate::chaos::OutageMode;
+use crate::ingest::RawJson;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TickerData {
     pub E: i64,    // Event time
     pub s: String, // Symbol
@@ -11,23 +15,2090 @@ pub struct TickerData {
     pub q: String, // Total traded quote asset volume
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VolumeData {
     pub symbol: String,
     pub price: f64,
-    pub volume: f64,
+    pub volume: f64, // Quote volume traded over the last 24 hours
+    pub quote_asset: Option<String>,
+    pub usd_price: Option<f64>,  // Price through the quote asset's USDT pair
+    pub usd_volume: Option<f64>, // The 24h quote volume at the same rate
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Quote asset and USD price stored with each tick, both None for symbols in an unknown quote
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsdPricing {
+    pub quote_asset: Option<String>,
+    pub usd_price: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaginatedResponse {
     pub data: Vec<VolumeData>,
     pub total: i64,
     pub page: i64,
     pub per_page: i64,
+    #[serde(default)]
+    pub degraded: bool, // Served from cache because the database is unavailable
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PaginationParams {
     pub page: Option<i64>,
     pub per_page: Option<i64>,
-}
\ No newline at end of file
+    pub interval_secs: Option<u64>, // How often the page is pushed to this connection
+    pub fields: Option<Vec<String>>, // Row fields to send besides the symbol, empty for all of them
+    pub format: Option<WireFormat>,
+    pub quote: Option<String>, // Only list symbols quoted in this asset, empty for all of them
+    pub sort: Option<TickerSort>,
+}
+
+// Order of the ticker list. Native volumes are in each symbol's own quote asset, so lists mixing
+// quotes compare them in USD.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TickerSort {
+    #[default]
+    Volume,
+    UsdVolume,
+    UsdPrice,
+}
+
+// Filter and order the ticker list is served in
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TickerQuery {
+    pub quote: Option<String>,
+    pub sort: TickerSort,
+}
+
+// Live price update streamed to connections subscribed to the symbol
+#[derive(Debug, Clone, Serialize)]
+pub struct TickerUpdate {
+    pub symbol: String,
+    pub price: f64,
+    pub quote_volume: f64, // Rolling 24h quote volume
+    pub event_time: i64,
+    #[serde(skip)]
+    pub raw: Option<RawJson>, // The exchange's own JSON for this ticker, when it came from the feed
+}
+
+// Rows of an ad-hoc analytics query, each value in the column order
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AnalyticsResult {
+    pub columns: Vec<String>,
+    #[schema(value_type = Vec<Vec<Object>>)]
+    pub rows: Vec<Vec<serde_json::Value>>,
+    pub truncated: bool, // The query returned more rows than the limit
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct History {
+    pub symbol: String,
+    pub resolution: String, // "raw", "1m", "5m", "1h" or a wider "<n>h" bucket
+    pub points: Vec<Candle>, // Raw ticks come back as flat candles
+    pub gaps: Vec<DataGap>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PricePoint {
+    pub symbol: String,
+    pub price: f64,
+    pub timestamp: i64,     // When the returned price was observed, epoch milliseconds
+    pub source: String,     // "ticker" or "kline"
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Conversion {
+    pub from: String,
+    pub to: String,
+    pub amount: f64,
+    pub result: f64,
+    pub rate: f64,          // Units of `to` per unit of `from`
+    pub route: Vec<String>, // Pairs priced along the way, empty when from equals to
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexComponent {
+    pub symbol: String,
+    pub weight: f64, // Units of the symbol held by one index unit
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub components: Vec<IndexComponent>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexUpdate {
+    pub name: String,
+    pub value: Option<f64>, // None until every component has a price
+    #[serde(rename = "return")]
+    pub index_return: Option<f64>, // Relative to the value when the subscription started
+}
+
+// A synthetic pair priced as long_symbol - ratio * short_symbol. One unit bought is one unit of the
+// long symbol bought and `ratio` units of the short symbol sold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpreadDefinition {
+    pub account_id: String,
+    pub name: String,
+    pub long_symbol: String,
+    pub short_symbol: String,
+    pub ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SpreadUpdate {
+    pub name: String,
+    pub price: Option<f64>, // None until both legs have a price
+    pub long_price: Option<f64>,
+    pub short_price: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Candle {
+    pub open_time: i64, // Bucket start in epoch milliseconds
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64, // Quote volume traded during the bucket
+    #[serde(default)]
+    pub gap: bool,   // The bucket overlaps a recorded data gap
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DataGap {
+    pub symbol: String,
+    pub start: i64, // Last tick before the gap, epoch milliseconds
+    pub end: i64,   // First tick after the gap, epoch milliseconds
+}
+
+// Which clock to bucket stored ticks by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Timeline {
+    #[default]
+    Event,  // Exchange event time `E`
+    Ingest, // Server receive time
+}
+
+#[derive(Debug, Serialize)]
+pub struct IngestLatencyReport {
+    pub total_ticks: u64,
+    pub samples: usize,
+    pub mean_ms: Option<f64>,
+    pub min_ms: Option<i64>, // Best case latency, negative when the exchange clock runs ahead of ours
+    pub p50_ms: Option<i64>,
+    pub p99_ms: Option<i64>,
+    pub max_ms: Option<i64>,
+    pub clock_skew_suspected: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CandleType {
+    #[default]
+    Standard,
+    HeikinAshi,
+    Renko,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CandleSeriesRequest {
+    pub symbol: String,
+    pub interval: String, // "1m", "5m", "1h", ...
+    #[serde(default)]
+    pub candle_type: CandleType,
+    pub brick_size: Option<f64>, // Required for Renko, in quote currency
+}
+
+#[derive(Debug, Serialize)]
+pub struct CandleSeries {
+    pub symbol: String,
+    pub interval: String,
+    pub candle_type: CandleType,
+    pub candles: Vec<Candle>, // Renko bricks carry the open time of the candle that completed them
+    pub gaps: Vec<DataGap>,   // Gaps inside the returned time range
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overlay: Option<TradeOverlay>, // Only when the request named an account
+}
+
+// How a subscription's frames are encoded. Compact frames keep their "type" tag but use one or two
+// letter keys, and arrays for page rows and candles. Raw tickers relay the exchange's miniTicker
+// unchanged as {"type":"raw_ticker","data":{...}}, without field selection; other frames are sent
+// as standard ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireFormat {
+    #[default]
+    Standard,
+    Compact,
+    Raw,
+}
+
+// Candle series of the compact format, each candle as [open_time, open, high, low, close, volume, gap]
+// with gap as 0 or 1, and each gap as [start, end]
+#[derive(Debug, Serialize)]
+pub struct CompactCandleSeries {
+    pub s: String,
+    pub i: String,
+    pub k: CandleType,
+    pub c: Vec<(i64, f64, f64, f64, f64, f64, u8)>,
+    pub g: Vec<(i64, i64)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub o: Option<TradeOverlay>,
+}
+
+impl From<CandleSeries> for CompactCandleSeries {
+    fn from(series: CandleSeries) -> Self {
+        CompactCandleSeries {
+            s: series.symbol,
+            i: series.interval,
+            k: series.candle_type,
+            c: series
+                .candles
+                .iter()
+                .map(|candle| {
+                    let gap = u8::from(candle.gap);
+                    (candle.open_time, candle.open, candle.high, candle.low, candle.close, candle.volume, gap)
+                })
+                .collect(),
+            g: series.gaps.iter().map(|gap| (gap.start, gap.end)).collect(),
+            o: series.overlay,
+        }
+    }
+}
+
+// Ticker frame of the compact format, fields left out of the subscription's selection are omitted
+#[derive(Debug, Serialize)]
+pub struct CompactTicker<'a> {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub s: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p: Option<f64>, // price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<f64>, // quote_volume
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<i64>, // event_time
+}
+
+// Ticker page of the compact format: total, page, per_page, degraded, and each row as
+// [symbol, price, volume, quote_asset, usd_price, usd_volume] minus the fields left out of the selection
+#[derive(Debug, Serialize)]
+pub struct CompactPage {
+    pub n: i64,
+    pub pg: i64,
+    pub pp: i64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dg: bool,
+    pub d: Vec<Vec<serde_json::Value>>,
+}
+
+// An account's trades and working orders over a candle series' time range, for charting
+#[derive(Debug, Serialize)]
+pub struct TradeOverlay {
+    pub account_id: String,
+    pub markers: Vec<TradeMarker>,
+    pub levels: Vec<OrderLevel>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TradeMarker {
+    pub order_id: u64,
+    pub side: Side,
+    pub price: f64,
+    pub quantity: f64,
+    pub kind: String,     // "entry", "exit" or "reversal" for a fill that flips the position
+    pub time: i64,        // Fill time in epoch milliseconds
+    pub candle_time: i64, // Open time of the interval bucket containing the fill
+}
+
+// Price level of a working limit order
+#[derive(Debug, Serialize)]
+pub struct OrderLevel {
+    pub order_id: u64,
+    pub side: Side,
+    pub price: f64,
+    pub quantity: f64, // Unfilled quantity
+    pub kind: String,  // "limit", or "take_profit" for a leg of an order group
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    Market,
+    Limit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeInForce {
+    #[default]
+    Gtc, // Good till cancelled
+    Ioc, // Immediate or cancel
+    Fok, // Fill or kill
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Pending, // Waiting for its parent order to fill before it can match
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+}
+
+impl OrderStatus {
+    pub fn is_open(self) -> bool {
+        matches!(self, OrderStatus::New | OrderStatus::PartiallyFilled)
+    }
+
+    pub fn is_final(self) -> bool {
+        matches!(self, OrderStatus::Filled | OrderStatus::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Liquidity {
+    Maker, // Rested on the book before trading
+    Taker, // Crossed the market on arrival
+}
+
+// Estimated place of a resting maker order in the queue at its price level
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEstimate {
+    pub ahead: f64,            // Base quantity estimated to trade before this order
+    pub fill_probability: f64, // Chance the next tick at this level reaches the order
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order {
+    pub id: u64,
+    pub account_id: String,
+    pub symbol: String,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub price: Option<f64>,
+    pub quantity: f64,
+    pub filled_quantity: f64,
+    pub time_in_force: TimeInForce,
+    pub status: OrderStatus,
+    pub group_id: Option<u64>,
+    pub client_order_id: Option<String>,
+    pub liquidity: Option<Liquidity>, // Decided once the order becomes active
+    pub queue: Option<QueueEstimate>,
+    pub version: u64, // Bumped on every amendment, used for optimistic concurrency
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    #[serde(default)]
+    pub fill_id: u64, // Assigned by the matching engine, 0 for fills reported by an exchange
+    pub order_id: u64,
+    pub account_id: String,
+    pub symbol: String,
+    pub side: Side,
+    pub price: f64,
+    pub quantity: f64,
+    pub liquidity: Liquidity,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderRequest {
+    pub symbol: String,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub price: Option<f64>,
+    pub quantity: f64,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    pub client_order_id: Option<String>, // Unique per account, resubmitting it returns the original order
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LadderLevel {
+    pub price: f64,
+    pub quantity: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AmendOrderRequest {
+    pub account_id: String,
+    pub order_id: u64,
+    pub version: u64, // Version the client last saw, the amendment is rejected if it is stale
+    pub price: Option<f64>,
+    pub quantity: Option<f64>,
+    pub time_in_force: Option<TimeInForce>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupStatus {
+    Pending,   // Entry order has not filled yet
+    Active,    // Entry filled, take-profit levels are working
+    Completed, // Every take-profit level has filled
+    Cancelled,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderGroupReport {
+    pub group_id: u64,
+    pub account_id: String,
+    pub symbol: String,
+    pub status: GroupStatus,
+    pub entry: Order,
+    pub take_profits: Vec<Order>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpreadOrderReport {
+    pub spread: SpreadDefinition,
+    pub side: Side,
+    pub quantity: f64, // Spread units, the short leg trades `ratio` times as much
+    pub long_leg: Order,
+    pub short_leg: Order,
+}
+
+// Mirrored state of a real exchange account linked read-only to a simulator account
+#[derive(Debug, Serialize)]
+pub struct MirrorBalance {
+    pub asset: String,
+    pub wallet_balance: f64,
+    pub available_balance: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MirrorPosition {
+    pub symbol: String,
+    pub position_side: String,
+    pub position_amount: f64,
+    pub entry_price: f64,
+    pub unrealized_pnl: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MirrorFill {
+    pub trade_id: i64,
+    pub order_id: i64,
+    pub symbol: String,
+    pub side: String,
+    pub price: f64,
+    pub quantity: f64,
+    pub commission: f64,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MirrorAccount {
+    pub account_id: String,
+    pub balances: Vec<MirrorBalance>,
+    pub positions: Vec<MirrorPosition>,
+    pub recent_fills: Vec<MirrorFill>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PositionReport {
+    pub symbol: String,
+    pub quantity: f64, // Signed, negative for shorts
+    pub average_price: f64,
+    pub mark_price: f64,
+    pub market_value: f64,
+    pub unrealized_pnl: f64,
+    pub realized_pnl: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RiskMetrics {
+    pub confidence: f64,
+    pub value_at_risk: f64,      // One-day loss not exceeded with `confidence` probability
+    pub expected_shortfall: f64, // Average loss beyond the VaR
+    pub scenarios: usize,        // Historical days used in the simulation
+    pub computed_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssetExposure {
+    pub asset: String,
+    pub long: f64,  // Market value of long positions
+    pub short: f64, // Absolute market value of short positions
+    pub net: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExposureReport {
+    pub long: f64,
+    pub short: f64,
+    pub gross: f64,
+    pub net: f64,
+    pub beta: Option<f64>, // Portfolio beta to BTCUSDT, None until enough daily history exists
+    pub beta_weighted_exposure: Option<f64>, // BTC-equivalent exposure in quote currency
+    pub by_asset: Vec<AssetExposure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PortfolioReport {
+    pub account_id: String,
+    pub cash: f64,
+    pub equity: f64,
+    pub positions: Vec<PositionReport>,
+    pub risk: Option<RiskMetrics>,
+    pub exposure: Option<ExposureReport>,
+    pub peak_equity: Option<f64>,
+    pub drawdown: Option<f64>, // Fraction of peak equity currently lost
+}
+
+// Discipline rules an account holder sets for themselves, enforced on every new order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradingRules {
+    pub account_id: String,
+    #[serde(default)]
+    pub no_trade_windows: Vec<NoTradeWindow>,
+    pub max_trades_per_day: Option<u32>, // Orders placed per UTC day, legs and bot orders included
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BotStatus {
+    Running,
+    Paused, // Places no new orders until resumed, a grid's orders are pulled meanwhile
+    Stopped,
+}
+
+// A neutral grid: buys below the price and sells above it at evenly spaced levels, each fill
+// answered by the opposite order one level away
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GridBotConfig {
+    pub account_id: String,
+    pub symbol: String,
+    pub lower_price: f64,
+    pub upper_price: f64,
+    pub grids: u32, // Intervals between the bounds, there is one more price level than grids
+    pub order_quantity: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GridLevel {
+    pub price: f64,
+    pub side: Option<Side>, // None for the level without an order, the one last traded at
+    pub order_id: Option<u64>,
+    #[serde(default)]
+    pub counter: bool, // The order closes a round trip opened one level away
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GridBot {
+    pub config: GridBotConfig,
+    pub status: BotStatus,
+    pub levels: Vec<GridLevel>, // Lowest price first
+    pub grid_profit: f64,       // Level spacing times quantity per round trip, before fees
+    pub round_trips: u64,
+    pub started_at: i64,
+    pub stopped_at: Option<i64>,
+    #[serde(default)]
+    pub heartbeat_at: Option<i64>, // Last time the bot's runner checked on it
+}
+
+// The strategy a bot is bound to, each has its implementation in bots.rs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BotTemplate {
+    Dca,        // Adds safety orders of growing size as the price moves against the position
+    Martingale, // Grows the next entry after every losing cycle
+}
+
+// A bot trading cycles of market orders in one direction. Every cycle closes at the take-profit
+// or the stop-loss, both measured from the average entry price.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotConfig {
+    pub account_id: String,
+    pub symbol: String,
+    pub template: BotTemplate,
+    pub side: Side,
+    pub base_quantity: f64,
+    pub take_profit: f64, // Fraction of the average entry price
+    pub stop_loss: f64,   // Fraction of the average entry price, every bot has one
+    pub max_steps: u32,   // DCA: safety orders per cycle, martingale: losing cycles in a row
+    pub multiplier: f64,  // Size of each safety order or losing cycle's successor relative to the one before
+    #[serde(default)]
+    pub step_deviation: f64, // DCA only: move against the entry between two safety orders, as a fraction
+    pub max_notional: f64,   // The largest position the bot may build, checked over its whole plan
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bot {
+    pub config: BotConfig,
+    pub status: BotStatus,
+    pub cycles: u64,
+    pub wins: u64,
+    pub losses: u64,
+    pub step: u32, // Safety orders placed in this cycle, or losing cycles in a row
+    pub quantity: f64,
+    pub average_price: f64,
+    pub first_entry_price: Option<f64>, // Safety orders are spaced from it
+    pub realized_pnl: f64,              // Before fees
+    pub pending_order: Option<u64>,
+    #[serde(default)]
+    pub pending_filled: f64,
+    #[serde(default)]
+    pub pending_exit: bool,
+    pub halted: Option<String>, // Why a risk cap stopped the bot
+    pub started_at: i64,
+    pub stopped_at: Option<i64>,
+    #[serde(default)]
+    pub heartbeat_at: Option<i64>, // Last price the bot was checked against
+}
+
+// Parameters a DCA or martingale bot takes on between two orders, the rest of its configuration
+// is fixed once started
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BotParams {
+    pub base_quantity: Option<f64>,
+    pub take_profit: Option<f64>,
+    pub stop_loss: Option<f64>,
+    pub max_steps: Option<u32>,
+    pub multiplier: Option<f64>,
+    pub step_deviation: Option<f64>,
+    pub max_notional: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoTradeWindow {
+    pub start: String, // "HH:MM" in UTC
+    pub end: String,   // Before start for a window running past midnight
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrawdownAlertSettings {
+    pub account_id: String,
+    pub threshold: f64, // Drawdown fraction that triggers the alert, e.g. 0.2 for 20%
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepegAlertSettings {
+    pub account_id: String,
+    pub threshold: f64, // Distance from 1.0 that triggers the alert, e.g. 0.01 for 1%
+    pub webhook_url: Option<String>,
+}
+
+// Latest price of a stablecoin pair pushed on the depeg channel
+#[derive(Debug, Clone, Serialize)]
+pub struct DepegStatus {
+    pub symbol: String,
+    pub price: f64,
+    pub deviation: f64, // Price minus 1.0
+    pub depegged: bool, // Beyond the server's DEPEG_THRESHOLD
+    pub event_time: i64,
+}
+
+// Reference levels of a symbol. Sessions are UTC days from SESSION_START_HOUR_UTC, the previous
+// levels are those of the session before, the weekly open is the first price of Monday's session.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyLevels {
+    pub symbol: String,
+    pub session_start: i64,
+    pub session_high: Option<f64>,
+    pub session_low: Option<f64>,
+    pub previous_high: Option<f64>,
+    pub previous_low: Option<f64>,
+    pub previous_close: Option<f64>,
+    pub weekly_open: Option<f64>,
+    pub last_price: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelKind {
+    Webhook,
+    Email,
+    Discord,
+    Slack,
+    Ntfy,
+}
+
+impl ChannelKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChannelKind::Webhook => "webhook",
+            ChannelKind::Email => "email",
+            ChannelKind::Discord => "discord",
+            ChannelKind::Slack => "slack",
+            ChannelKind::Ntfy => "ntfy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "webhook" => Some(ChannelKind::Webhook),
+            "email" => Some(ChannelKind::Email),
+            "discord" => Some(ChannelKind::Discord),
+            "slack" => Some(ChannelKind::Slack),
+            "ntfy" => Some(ChannelKind::Ntfy),
+            _ => None,
+        }
+    }
+}
+
+// Permission level of a user, each role can do everything the ones before it can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    ReadOnly, // Market data only
+    User,     // Trading on accounts
+    Admin,    // Data management and simulation controls
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read_only",
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read_only" => Ok(Role::ReadOnly),
+            "user" => Ok(Role::User),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role {}", s)),
+        }
+    }
+}
+
+// What an API key may be used for, on top of its owner's role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiScope {
+    ReadMarket,    // Market data and subscriptions
+    Trade,         // Placing, amending and cancelling orders
+    ManageAccount, // Account settings, reports, notifications and keys
+}
+
+impl ApiScope {
+    pub fn name(self) -> &'static str {
+        match self {
+            ApiScope::ReadMarket => "read-market",
+            ApiScope::Trade => "trade",
+            ApiScope::ManageAccount => "manage-account",
+        }
+    }
+}
+
+impl std::str::FromStr for ApiScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-market" => Ok(ApiScope::ReadMarket),
+            "trade" => Ok(ApiScope::Trade),
+            "manage-account" => Ok(ApiScope::ManageAccount),
+            _ => Err(format!("Unknown scope {}", s)),
+        }
+    }
+}
+
+// Standardized conditions for new accounts, e.g. for a class or a competition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountTemplate {
+    pub name: String,
+    pub starting_balance: f64,
+    #[serde(default)]
+    pub allowed_symbols: Vec<String>, // Empty allows every symbol
+    pub max_leverage: Option<f64>,    // Gross position value over equity
+    #[serde(default = "default_fee_tier")]
+    pub fee_tier: String, // "none", "standard", "vip1", "vip2" or "vip3"
+}
+
+fn default_fee_tier() -> String {
+    "standard".to_string()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Competition {
+    pub id: i64,
+    pub name: String,
+    pub enrollment_start: i64,
+    pub enrollment_end: i64,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub rules: Option<AccountTemplate>, // Frozen when the competition is created
+    pub finalized: bool,                // Final ranking computed and archived
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompetitionStanding {
+    pub rank: u32,
+    pub user_id: String,
+    pub account_id: String,
+    pub equity: f64,
+    pub return_pct: f64, // Versus the starting balance
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TeamRole {
+    Owner,  // Manages members and the approval mode
+    Member, // Trades on the shared account
+}
+
+impl TeamRole {
+    pub fn name(self) -> &'static str {
+        match self {
+            TeamRole::Owner => "owner",
+            TeamRole::Member => "member",
+        }
+    }
+}
+
+impl std::str::FromStr for TeamRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(TeamRole::Owner),
+            "member" => Ok(TeamRole::Member),
+            _ => Err(format!("Unknown team role {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TeamMember {
+    pub user_id: String,
+    pub role: TeamRole,
+}
+
+// An account shared by several users
+#[derive(Debug, Clone, Serialize)]
+pub struct Team {
+    pub account_id: String,
+    pub name: String,
+    pub approval_mode: bool,        // Orders wait for a second member's approval
+    pub approval_min_notional: f64, // Only orders worth more than this, zero holds every order
+    pub members: Vec<TeamMember>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderProposal {
+    pub proposal_id: i64,
+    pub account_id: String,
+    pub proposed_by: String,
+    pub request: OrderRequest,
+    pub status: String, // "pending_approval", "approved", "rejected", "expired" or "failed"
+    pub decided_by: Option<String>,
+    pub order_id: Option<u64>, // Set once an approved proposal is placed
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BenchmarkPoint {
+    pub time: i64, // Day start in epoch milliseconds
+    pub equity: f64,
+    pub buy_and_hold_btc: Option<f64>, // None until the benchmark has a price to start from
+    pub equal_weight_index: Option<f64>,
+}
+
+// An account's daily equity against passive strategies started with the same capital
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkReport {
+    pub account_id: String,
+    pub index_symbols: Vec<String>, // Constituents of the equal-weight index
+    pub points: Vec<BenchmarkPoint>,
+    pub account_return_pct: Option<f64>,
+    pub buy_and_hold_btc_return_pct: Option<f64>,
+    pub equal_weight_index_return_pct: Option<f64>,
+}
+
+// What a share token exposes of a published account
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PublicProfile {
+    pub account_id: String,
+    pub as_of: i64, // Everything below is as of this time, the owner's delay before now
+    pub delay_secs: i64,
+    pub equity_curve: Vec<BenchmarkPoint>,
+    pub realized_pnl: f64,
+    pub trades: u64,
+    pub win_rate: Option<f64>,
+    pub positions: Vec<PositionReport>,
+}
+
+// Realized P&L and closing trades falling into one bucket of an attribution report
+#[derive(Debug, Clone, Serialize)]
+pub struct PnlBucket {
+    pub key: String, // Symbol, "HH:00" UTC hour, weekday or holding duration range
+    pub pnl: f64,
+    pub trades: u64, // Lot closes, a fill closing several lots counts once per lot
+    pub wins: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AttributionReport {
+    pub account_id: String,
+    pub total_pnl: f64,
+    pub trades: u64,
+    pub by_symbol: Vec<PnlBucket>, // Best first
+    pub by_hour: Vec<PnlBucket>,
+    pub by_weekday: Vec<PnlBucket>,
+    pub by_holding: Vec<PnlBucket>,
+}
+
+// Teaching breakdown of a single fill, see explain::explain_fill
+#[derive(Debug, Clone, Serialize)]
+pub struct FillExplanation {
+    pub fill: Fill,
+    pub order_type: Option<OrderType>, // None once the engine no longer holds the order
+    pub limit_price: Option<f64>,
+    pub arrival_price: Option<f64>, // Market price when the order was placed
+    pub slippage: Option<f64>,      // Per unit versus the arrival price, positive when worse
+    pub slippage_bps: Option<f64>,
+    pub slippage_cost: Option<f64>,
+    pub notional: f64,
+    pub fee_rate: f64,
+    pub fee: f64,
+    pub position_before: f64, // Signed, negative for shorts
+    pub position_after: f64,
+    pub average_price_after: f64,
+    pub realized_pnl: f64, // Realized by this fill alone
+    pub leverage: f64,     // Template leverage cap, 1 for fully funded accounts
+    pub margin_before: f64,
+    pub margin_after: f64,
+    pub margin_change: f64,
+    pub maintenance_margin_rate: f64,
+    pub equity: f64, // Current account equity, used for the liquidation price
+    pub liquidation_price: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AttributedFill {
+    #[serde(flatten)]
+    pub fill: Fill,
+    pub user_id: Option<String>, // Member who placed the order, if known
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemberPnl {
+    pub user_id: String,
+    pub fills: u64,
+    pub pnl: f64, // Realized and unrealized, at current marks
+}
+
+// An API key as shown to its owner, the secret itself is only returned once on creation
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub key_id: String,
+    pub user_id: String,
+    pub scopes: Vec<ApiScope>,
+    pub account_id: Option<String>, // Restricts the key to a single simulated account
+    pub rate_limit: Option<u32>,    // Requests per rate limit window
+    pub created_at: i64,
+    pub revoked: bool,
+}
+
+// Issued on login: a short-lived access token to resume with, and a single-use refresh token
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionTokens {
+    pub session_id: String,
+    pub access_token: String,
+    pub access_expires_at: i64,
+    pub refresh_token: String,
+    pub refresh_expires_at: i64,
+}
+
+// One row of the append-only audit log
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,          // User id, or "anonymous"
+    pub key_id: Option<String>, // API key used, if any
+    pub ip: Option<String>,
+    pub action: String,
+    pub account_id: Option<String>,
+    pub outcome: String, // "ok" or "rejected"
+    pub details: serde_json::Value,
+    pub created_at: i64,
+}
+
+// WebSocket usage at one moment, or the peaks and average rates of a bucket of snapshots
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageSnapshot {
+    pub recorded_at: i64, // Snapshot time, or the bucket start
+    pub connections: i64,
+    pub ticker_subscriptions: i64, // Symbols streamed, summed over connections
+    pub index_subscriptions: i64,
+    pub account_subscriptions: i64, // Accounts whose fills and alerts are pushed
+    pub messages_in_per_sec: f64,
+    pub messages_out_per_sec: f64,
+}
+
+// One tenant's connections and stored settings at one moment, or the peaks of a bucket of snapshots
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantUsageSnapshot {
+    pub recorded_at: i64,
+    pub connections: i64,
+    pub storage_bytes: i64,
+}
+
+// Things users keep on the server that count towards their storage quota, besides settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageKind {
+    Alert, // Drawdown and depeg alert rules
+    Export,
+}
+
+impl StorageKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            StorageKind::Alert => "alert",
+            StorageKind::Export => "export",
+        }
+    }
+}
+
+// What a user stores, for the admin usage report
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UserStorage {
+    pub user_id: String,
+    pub tenant_id: Option<String>,
+    pub settings_bytes: i64,
+    pub alerts: i64,
+    pub alert_bytes: i64,
+    pub exports: i64,
+    pub export_bytes: i64,
+    pub total_bytes: i64,
+}
+
+// An isolated group of users and accounts sharing the deployment, e.g. one classroom
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tenant {
+    pub tenant_id: String,
+    pub name: String,
+    pub max_connections: Option<i64>,   // Open WebSocket connections of its users, unlimited when unset
+    pub max_storage_bytes: Option<i64>, // Settings its users store, favorites included, unlimited when unset
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantStatus {
+    #[serde(flatten)]
+    pub tenant: Tenant,
+    pub connections: i64,
+    pub storage_bytes: i64,
+    pub accounts: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued, // Waiting for a worker, possibly to retry after a failure
+    Running,
+    Succeeded,
+    Dead, // Failed every attempt, kept for inspection until retried by hand
+}
+
+impl JobStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Dead => "dead",
+        }
+    }
+}
+
+// A background job and where it stands
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRecord {
+    pub id: i64,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub status: JobStatus,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub run_after: i64, // Earliest time a worker picks it up, later after each failure
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+// A change to an account, appended to its event log. Cash and positions are a fold over these
+// events, so they can be rebuilt at any point of the account's history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AccountEvent {
+    AccountOpened { cash: f64 },
+    AccountReset { cash: f64 }, // Flat again with `cash`, earlier events no longer count
+    AccountClosed,
+    FundsCredited { amount: f64 },
+    FundsDebited { amount: f64 }, // Trading fees
+    OrderPlaced { order: Order },
+    OrderUpdated { order: Order }, // Amended, released, or partially or fully filled
+    OrderCancelled { order: Order },
+    Filled { fill: Fill },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordedEvent {
+    pub account_id: String,
+    pub sequence: u64, // Position in the account's log, gapless from 1
+    pub recorded_at: i64,
+    pub event: AccountEvent,
+}
+
+// A fill or alert that happened while the account had no open session
+#[derive(Debug, Clone, Serialize)]
+pub struct InboxNotification {
+    pub id: i64,
+    pub account_id: String,
+    pub kind: String, // "fill" or "alert"
+    pub title: String,
+    pub payload: serde_json::Value,
+    pub read: bool,
+    pub created_at: i64,
+}
+
+// Where an account wants its alerts delivered besides its open sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationChannel {
+    pub account_id: String,
+    pub kind: ChannelKind,
+    pub target: String, // Webhook URL, email address or ntfy topic
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportSchedule {
+    pub account_id: String,
+    pub send_at: String, // "HH:MM" in UTC
+    pub channel: ChannelKind,
+    pub target: String, // Address on the channel, see NotificationChannel
+    #[serde(skip_deserializing)]
+    pub last_sent_day: Option<i64>, // Days since the Unix epoch
+    #[serde(skip_deserializing)]
+    pub last_equity: Option<f64>,   // Equity at the last summary, the base for the next P&L
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailySummary {
+    pub account_id: String,
+    pub date: String,
+    pub equity: f64,
+    pub pnl: f64,
+    pub fills: Vec<Fill>,
+    pub positions: Vec<PositionReport>,
+    pub margin_usage: f64,
+}
+
+// A user setting written by one connection, pushed to the user's other connections
+#[derive(Debug, Clone)]
+pub struct SettingChange {
+    pub user_id: String,
+    pub key: String,
+    pub value: serde_json::Value, // Null when the setting was removed
+    pub origin: u64,              // Connection that made the change
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListingKind {
+    Listed,
+    Delisted,
+}
+
+// A symbol that started or stopped trading on the exchange, pushed on the `/listings` channel
+#[derive(Debug, Clone, Serialize)]
+pub struct ListingEvent {
+    pub symbol: String,
+    pub kind: ListingKind,
+    pub status: String, // Exchange status, e.g. TRADING, SETTLING or missing
+    pub detected_at: i64,
+}
+
+// Quantity resting within one price bucket of the book, `cumulative` sums it from the touch outward
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BookLevel {
+    pub price: f64, // Bucket edge nearest the touch: the floor for bids, the ceiling for asks
+    pub quantity: f64,
+    pub cumulative: f64,
+    pub simulated_quantity: f64, // Resting simulated limit orders in the bucket, not part of `quantity`
+}
+
+// The exchange's L2 book for a symbol grouped into buckets of `group` price units, best first
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BookSnapshot {
+    pub symbol: String,
+    pub event_time: i64,
+    pub group: Option<f64>, // None when levels are the exchange's own
+    pub bids: Vec<BookLevel>,
+    pub asks: Vec<BookLevel>,
+}
+
+// An exchange trade from the symbol's aggregate trade stream
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketTrade {
+    pub symbol: String,
+    pub trade_id: i64, // Aggregate trade id, increasing per symbol
+    pub price: f64,
+    pub quantity: f64,
+    pub side: Side, // Side of the taker, a buy lifted an ask
+    pub time: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TickDirection {
+    Up,
+    Down,
+    Unchanged,
+}
+
+// Taker buy against taker sell volume of one candle, in base asset units. `cvd` sums the deltas
+// from the first candle of the series.
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeDelta {
+    pub open_time: i64,
+    pub buy_volume: f64,
+    pub sell_volume: f64,
+    pub delta: f64,
+    pub cvd: f64,
+}
+
+// Volume at price of one candle as [price, bid volume, ask volume] rows, highest price first. Bid
+// volume was sold into the bid by takers, ask volume bought from the ask.
+#[derive(Debug, Clone, Serialize)]
+pub struct FootprintCandle {
+    pub open_time: i64,
+    pub levels: Vec<[f64; 3]>,
+    pub delta: f64,              // Ask volume less bid volume
+    pub point_of_control: f64,   // Price of the level with the most volume
+}
+
+// Indicators computed server-side and streamed on the indicator channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndicatorKind {
+    VolumeDelta, // Volume delta per candle with its cumulative volume delta
+}
+
+// A time-and-sales line: one trade, or consecutive trades at one price and side when aggregated.
+// `side` colors it by the taker, `tick` by the move from the print before it.
+#[derive(Debug, Clone, Serialize)]
+pub struct TradePrint {
+    pub first_trade_id: i64,
+    pub last_trade_id: i64,
+    pub price: f64,
+    pub quantity: f64,
+    pub count: usize,
+    pub side: Side,
+    pub tick: TickDirection,
+    pub time: i64, // Time of the last trade in the print
+}
+
+// One price row of a DOM ladder
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LadderRow {
+    pub price: f64,
+    pub bid_quantity: f64,
+    pub ask_quantity: f64,
+    pub simulated_quantity: f64, // Every account's resting simulated orders at the level
+    pub traded_quantity: f64,    // Volume of the recent trades at the level
+    pub own_buy_quantity: f64,   // The account's own working orders at the level
+    pub own_sell_quantity: f64,
+    pub order_ids: Vec<u64>,
+}
+
+// The symbol's book, recent trades and an account's working orders on one price column, highest first
+#[derive(Debug, Clone, Serialize)]
+pub struct Ladder {
+    pub symbol: String,
+    pub account_id: String,
+    pub event_time: i64,
+    pub group: Option<f64>,
+    pub last_price: Option<f64>,
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub rows: Vec<LadderRow>,
+    pub trades: Vec<MarketTrade>, // Newest first
+}
+
+// Announced maintenance, orders and account changes are rejected from `starts_at` until
+// `ends_at`, or until an admin ends it when that is unset
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceWindow {
+    pub message: String,
+    pub starts_at: i64,
+    pub ends_at: Option<i64>,
+}
+
+// A stored feature flag setting, deployment-wide when `user_id` is unset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureFlagSetting {
+    pub flag: String,
+    pub user_id: Option<String>,
+    pub enabled: bool,
+}
+
+// A flag as admins see it: the built-in default, the deployment-wide value and per-user overrides
+#[derive(Debug, Clone, Serialize)]
+pub struct FeatureFlag {
+    pub flag: String,
+    pub default: bool,
+    pub enabled: bool,
+    pub users: BTreeMap<String, bool>,
+}
+
+// How far a candle export job got, pushed to the connection that requested it
+#[derive(Debug, Clone)]
+pub struct ExportUpdate {
+    pub connection_id: u64,
+    pub token: String,
+    pub stage: ExportStage,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExportStage {
+    Progress { percent: u8, rows: u64 },
+    Ready { url: String, rows: u64 },
+    Failed { message: String }, // The job is retried while it has attempts left
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub account_id: String,
+    pub kind: String,
+    pub message: String,
+    pub value: f64,
+    pub threshold: f64,
+    pub created_at: i64,
+}
+
+// Messages sent by clients over the WebSocket, tagged by "type".
+// Plain pagination messages ({"page": 2}) are still accepted untagged.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    PlaceOrder {
+        account_id: String,
+        order: OrderRequest,
+    },
+    PlaceOrderGroup {
+        account_id: String,
+        entry: OrderRequest,
+        take_profits: Vec<LadderLevel>,
+    },
+    CancelOrder {
+        account_id: String,
+        order_id: u64,
+    },
+    CancelOrderGroup {
+        account_id: String,
+        group_id: u64,
+    },
+    OrderGroupStatus {
+        account_id: String,
+        group_id: u64,
+    },
+    AmendOrder(AmendOrderRequest),
+    MirrorAccount {
+        account_id: String,
+    },
+    Portfolio {
+        account_id: String,
+        #[serde(default)]
+        recalculate_risk: bool, // Recompute VaR now instead of returning the last daily run
+    },
+    // Share the account read-only over GET /public/<token>, optionally delayed. Every publish
+    // issues a new token and revokes the old one.
+    SetPublicProfile {
+        account_id: String,
+        enabled: bool,
+        #[serde(default)]
+        delay_secs: i64,
+    },
+    // Daily equity next to buy-and-hold BTCUSDT and an equal-weight top-10 index
+    Benchmarks {
+        account_id: String,
+        days: Option<i64>,
+    },
+    // Realized P&L by symbol, hour of day, weekday and holding duration over an optional window
+    PnlAttribution {
+        account_id: String,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+    },
+    // Slippage, fees, margin and liquidation price behind one fill, the order's latest fill by default
+    ExplainFill {
+        account_id: String,
+        order_id: u64,
+        fill_time: Option<i64>,
+    },
+    SetDrawdownAlert(DrawdownAlertSettings),
+    SetDepegAlert(DepegAlertSettings),
+    SetDailyReport(ReportSchedule),
+    SetTradingRules(TradingRules),
+    Inbox {
+        account_id: String,
+        #[serde(default)]
+        unread_only: bool,
+        limit: Option<i64>,
+    },
+    // Mark the given notifications read, or all of them when `ids` is empty
+    MarkRead {
+        account_id: String,
+        #[serde(default)]
+        ids: Vec<i64>,
+    },
+    AddNotificationChannel(NotificationChannel),
+    RemoveNotificationChannel(NotificationChannel),
+    // Several symbol/interval series in one round-trip, e.g. for a dashboard of mini-charts
+    Candles {
+        series: Vec<CandleSeriesRequest>,
+        limit: Option<i64>, // Candles per series
+        #[serde(default)]
+        timeline: Timeline,
+        account_id: Option<String>, // Merge this account's trades and order levels into each series
+        #[serde(default)]
+        format: WireFormat,
+    },
+    IngestionStats,
+    // Read-only SQL over the Parquet dataset, e.g. `SELECT symbol, max(high) FROM candles GROUP BY symbol`
+    AnalyticsQuery {
+        sql: String,
+        limit: Option<usize>,
+    },
+    // Write a long candle range to a CSV file in the background. Progress is pushed to this
+    // connection and the download link follows once the file is ready.
+    ExportCandles {
+        symbol: String,
+        interval: String,
+        start_time: i64,
+        end_time: Option<i64>, // Now when unset
+    },
+    // Stream live ticker updates for these symbols over this connection, at most one per symbol
+    // every `interval_ms` and with only the named `fields` besides the symbol
+    Subscribe {
+        symbols: Vec<String>,
+        interval_ms: Option<u64>,
+        fields: Option<Vec<String>>,
+        #[serde(default)]
+        format: WireFormat,
+    },
+    Unsubscribe {
+        symbols: Vec<String>,
+    },
+    // Stream the value of a custom weighted index over this connection
+    SubscribeIndex(IndexDefinition),
+    UnsubscribeIndex {
+        name: String,
+    },
+    // Save a spread under its name for the account, replacing an earlier one of the same name
+    DefineSpread(SpreadDefinition),
+    RemoveSpread {
+        account_id: String,
+        name: String,
+    },
+    ListSpreads {
+        account_id: String,
+    },
+    // Stream the price of one of the account's spreads over this connection
+    SubscribeSpread {
+        account_id: String,
+        name: String,
+    },
+    UnsubscribeSpread {
+        name: String,
+    },
+    // Candles of the spread built from its legs' 1m closes
+    SpreadCandles {
+        account_id: String,
+        name: String,
+        interval: String,
+        limit: Option<i64>,
+    },
+    // Replaces a stopped bot in the same symbol, a running one has to be stopped first
+    StartGridBot(GridBotConfig),
+    // Cancels the bot's working orders, the position it built up is left open
+    StopGridBot {
+        account_id: String,
+        symbol: String,
+    },
+    GridBots {
+        account_id: String,
+    },
+    // One DCA or martingale bot per account and symbol, starting over replaces a stopped one
+    StartBot(BotConfig),
+    // Leaves the position of an unfinished cycle open
+    StopBot {
+        account_id: String,
+        symbol: String,
+    },
+    Bots {
+        account_id: String,
+    },
+    // Pausing, resuming and stopping apply to whichever bot is active in the symbol, grid or not
+    PauseBot {
+        account_id: String,
+        symbol: String,
+    },
+    ResumeBot {
+        account_id: String,
+        symbol: String,
+    },
+    UpdateBot {
+        account_id: String,
+        symbol: String,
+        params: BotParams,
+    },
+    // Market orders in both legs, accepted together or not at all. Selling the spread sells the
+    // long symbol and buys the short one.
+    PlaceSpreadOrder {
+        account_id: String,
+        name: String,
+        side: Side,
+        quantity: f64,
+        client_order_id: Option<String>, // Suffixed with -long and -short for the legs
+    },
+    // Stream the prices of the tracked stablecoin pairs and how far they are off 1.0
+    SubscribeDepeg,
+    UnsubscribeDepeg,
+    // Accepts a user token, an API key or an access token from an earlier login
+    Authenticate {
+        token: String,
+    },
+    // Trade a refresh token for new tokens, the old refresh token stops working
+    RefreshSession {
+        refresh_token: String,
+    },
+    Logout,
+    // Admin only: revoke every session of a user, e.g. after a token leak
+    RevokeSessions {
+        user_id: String,
+    },
+    CreateApiKey {
+        scopes: Vec<ApiScope>,
+        account_id: Option<String>,
+        rate_limit: Option<u32>,
+    },
+    RevokeApiKey {
+        key_id: String,
+    },
+    ListApiKeys,
+    // Flatten the account and restore its starting balance, earlier fills are archived
+    ResetAccount {
+        account_id: String,
+    },
+    TopUp {
+        account_id: String,
+        amount: f64,
+    },
+    // Start an anonymous guest account that expires after a period of inactivity
+    CreateGuest {
+        starting_balance: Option<f64>,
+        template: Option<String>, // Takes precedence over starting_balance
+    },
+    CreateAccount {
+        account_id: String,
+        template: Option<String>,
+    },
+    ListAccountTemplates,
+    // Admin only: schedule a competition, `rules` are copied and can't change afterwards
+    CreateCompetition {
+        name: String,
+        enrollment_start: i64,
+        enrollment_end: i64,
+        start_time: i64,
+        end_time: i64,
+        rules: AccountTemplate,
+    },
+    ListCompetitions,
+    EnrollCompetition {
+        competition_id: i64,
+    },
+    CompetitionStandings {
+        competition_id: i64,
+    },
+    // Admin only: create or replace an account template
+    SetAccountTemplate(AccountTemplate),
+    // Shared account owned by the creator, members trade on it and fills record who placed them
+    CreateTeam {
+        account_id: String,
+        name: String,
+        #[serde(default)]
+        approval_mode: bool,
+    },
+    // Owners only: a null role removes the member
+    SetTeamMember {
+        account_id: String,
+        user_id: String,
+        role: Option<TeamRole>,
+    },
+    // Four-eyes mode: orders above the notional threshold wait for another member to approve them
+    SetTeamApproval {
+        account_id: String,
+        enabled: bool,
+        #[serde(default)]
+        min_notional: f64,
+    },
+    TeamBlotter {
+        account_id: String,
+        limit: Option<i64>,
+    },
+    // Orders waiting for approval, and a different member's decision on one
+    OrderProposals {
+        account_id: String,
+    },
+    ApproveOrder {
+        account_id: String,
+        proposal_id: i64,
+    },
+    RejectOrder {
+        account_id: String,
+        proposal_id: i64,
+    },
+    // Preferences such as theme, default leverage or favorite symbols, stored per user
+    GetSettings,
+    // A null value removes the setting
+    SetSetting {
+        key: String,
+        value: serde_json::Value,
+    },
+    // Admin only: newest audit entries matching every given filter
+    AuditLog {
+        actor: Option<String>,
+        account_id: Option<String>,
+        action: Option<String>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        limit: Option<i64>,
+    },
+    // Admin only: usage snapshots, newest first, grouped into `bucket_secs` buckets (hourly by default).
+    // With `tenant_id` only that tenant's connections and storage.
+    UsageStats {
+        tenant_id: Option<String>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        bucket_secs: Option<i64>,
+        limit: Option<i64>,
+    },
+    // Admin only: background jobs, newest first, optionally only those in one status
+    ListJobs {
+        status: Option<JobStatus>,
+        limit: Option<i64>,
+    },
+    // Admin only: queue a dead job again with a fresh set of attempts
+    RetryJob {
+        job_id: i64,
+    },
+    // The account's event log after `after_sequence`, oldest first
+    AccountEvents {
+        account_id: String,
+        after_sequence: Option<u64>,
+        limit: Option<i64>,
+    },
+    // Admin only: create a user with a fresh token, or change an existing user's role. Users of a
+    // tenant only see its accounts, admins of a tenant only create users in it.
+    CreateUser {
+        user_id: String,
+        role: Role,
+        tenant_id: Option<String>,
+    },
+    SetUserRole {
+        user_id: String,
+        role: Role,
+    },
+    // Admin only: load historical klines from Binance REST or from a data dump on the server
+    Backfill {
+        admin_token: Option<String>, // Not needed when authenticated as an admin
+        symbol: String,
+        interval: String,
+        start_time: i64,
+        end_time: Option<i64>,
+    },
+    ImportKlines {
+        admin_token: Option<String>,
+        symbol: String,
+        interval: String,
+        path: String,
+    },
+    // Admin only: start a simulated outage, a zero duration ends any running outage
+    SimulateOutage {
+        admin_token: Option<String>,
+        mode: OutageMode,
+        duration_secs: u64,
+    },
+    // Admin only: announce a maintenance window to every client, the server is read-only while it
+    // runs. It starts now unless `starts_at` is given and lasts until ended without `duration_secs`.
+    SetMaintenance {
+        admin_token: Option<String>,
+        message: String,
+        starts_at: Option<i64>,
+        duration_secs: Option<u64>,
+    },
+    EndMaintenance {
+        admin_token: Option<String>,
+    },
+    // Admin only: re-read the config file and apply changed rate limits, push intervals, tick
+    // filter, fee schedule and storage quotas. An invalid value rejects the reload and keeps the
+    // running config.
+    ReloadConfig {
+        admin_token: Option<String>,
+    },
+    // Admin only: switch a feature on or off for the whole deployment, or for one user when
+    // `user_id` is given. A null `enabled` removes the setting.
+    SetFeatureFlag {
+        admin_token: Option<String>,
+        flag: String,
+        user_id: Option<String>,
+        enabled: Option<bool>,
+    },
+    ListFeatureFlags {
+        admin_token: Option<String>,
+    },
+    // Admin only, and only without a tenant of one's own: create or update a tenant and its quotas
+    SetTenant(Tenant),
+    ListTenants,
+    // The symbol's order book in `levels` buckets per side, `group` price units wide
+    OrderBook {
+        symbol: String,
+        levels: Option<usize>,
+        group: Option<f64>,
+    },
+    // Stream the symbol's DOM ladder for the account on this connection
+    SubscribeLadder {
+        account_id: String,
+        symbol: String,
+        levels: Option<usize>,
+        group: Option<f64>,
+    },
+    UnsubscribeLadder {
+        symbol: String,
+    },
+    // Click-trade a ladder level: a limit order at `price` that rests there, or takes liquidity up
+    // to it when it is at or through the opposite side of the book
+    LadderOrder {
+        account_id: String,
+        symbol: String,
+        side: Side,
+        price: f64,
+        quantity: f64,
+        client_order_id: Option<String>,
+    },
+    // Cancel the account's working orders in a ladder row, on one side or both
+    LadderCancel {
+        account_id: String,
+        symbol: String,
+        price: f64,
+        group: Option<f64>, // Row width of the ladder the price was picked from
+        side: Option<Side>,
+    },
+    // Stream the symbol's time and sales on this connection, merging consecutive trades at one
+    // price and side into a single print with `aggregate`
+    SubscribeTrades {
+        symbol: String,
+        #[serde(default)]
+        aggregate: bool,
+    },
+    UnsubscribeTrades {
+        symbol: String,
+    },
+    // Scroll back through the symbol's recent trades, `limit` of them before the `before` trade id
+    TradeHistory {
+        symbol: String,
+        before: Option<i64>,
+        limit: Option<i64>,
+        #[serde(default)]
+        aggregate: bool,
+    },
+    // Session high and low, the previous session's high, low and close and the weekly open
+    KeyLevels {
+        symbol: String,
+    },
+    // Stream the key levels of these symbols whenever one of them moves
+    SubscribeKeyLevels {
+        symbols: Vec<String>,
+    },
+    UnsubscribeKeyLevels {
+        symbols: Vec<String>,
+    },
+    // Volume at price of the symbol's last `limit` candles, in rows `row_size` price units tall
+    Footprint {
+        symbol: String,
+        interval: String,
+        limit: Option<i64>,
+        row_size: Option<f64>,
+    },
+    // Stream an indicator of the symbol's `interval` candles, starting with its last `limit` values
+    SubscribeIndicator {
+        symbol: String,
+        interval: String,
+        indicator: IndicatorKind,
+        limit: Option<i64>,
+    },
+    UnsubscribeIndicator {
+        symbol: String,
+        interval: String,
+        indicator: IndicatorKind,
+    },
+    // Admin only: what users store, largest first, or just one user's
+    StorageUsage {
+        user_id: Option<String>,
+        limit: Option<i64>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Authenticated {
+        user_id: String,
+        role: Role,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        key: Option<ApiKey>, // Set when authenticated with an API key
+        session_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tenant_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tokens: Option<SessionTokens>, // Not issued again when resuming with an access token
+    },
+    SessionRefreshed(SessionTokens),
+    LoggedOut,
+    SessionsRevoked { user_id: String, count: u64 },
+    ApiKeyCreated { key: ApiKey, secret: String },
+    ApiKeyRevoked { key_id: String },
+    ApiKeys { keys: Vec<ApiKey> },
+    AuditLog { entries: Vec<AuditEntry> },
+    UsageStats { snapshots: Vec<UsageSnapshot> },
+    TenantUsageStats {
+        tenant_id: String,
+        snapshots: Vec<TenantUsageSnapshot>,
+    },
+    Jobs { jobs: Vec<JobRecord> },
+    JobRetried { job_id: i64 },
+    AccountEvents {
+        account_id: String,
+        events: Vec<RecordedEvent>,
+    },
+    AccountReset {
+        account_id: String,
+        reset_id: i64,
+        starting_balance: f64,
+        archived_fills: u64,
+        equity_before: f64,
+    },
+    ToppedUp {
+        account_id: String,
+        amount: f64,
+        cash: f64, // Balance after the top-up
+    },
+    AccountCreated {
+        account_id: String,
+        template: Option<String>,
+        starting_balance: f64,
+    },
+    AccountTemplates {
+        templates: Vec<AccountTemplate>,
+    },
+    CompetitionCreated(Competition),
+    Competitions {
+        competitions: Vec<Competition>,
+    },
+    CompetitionEnrolled {
+        competition_id: i64,
+        account_id: String,
+        starting_balance: f64,
+    },
+    // Live ranking while running, the archived final ranking once finalized
+    CompetitionStandings {
+        competition_id: i64,
+        finalized: bool,
+        standings: Vec<CompetitionStanding>,
+    },
+    GuestCreated {
+        user_id: String,
+        account_id: String,
+        role: Role,
+        token: String, // Authenticate with this to start trading
+        starting_balance: f64,
+        expires_after_days: i64,
+    },
+    TeamUpdated(Team),
+    TeamBlotter {
+        account_id: String,
+        fills: Vec<AttributedFill>, // Newest first
+        members: Vec<MemberPnl>,
+    },
+    OrderProposed(OrderProposal),
+    OrderProposals {
+        account_id: String,
+        proposals: Vec<OrderProposal>,
+    },
+    ProposalApproved {
+        proposal: OrderProposal,
+        order: Order,
+    },
+    ProposalRejected(OrderProposal),
+    Settings { settings: HashMap<String, serde_json::Value> },
+    SettingUpdated { key: String, value: serde_json::Value },
+    SettingChanged { key: String, value: serde_json::Value }, // Changed by another connection
+    UserCreated {
+        user_id: String,
+        role: Role,
+        tenant_id: Option<String>,
+        token: String,
+    },
+    UserUpdated { user_id: String, role: Role },
+    Ticker(TickerUpdate),
+    Subscribed {
+        symbols: Vec<String>,
+        interval_ms: u64,
+        fields: Option<Vec<String>>, // None sends every field
+        format: WireFormat,
+    },
+    Unsubscribed { symbols: Vec<String> },
+    IndexSubscribed(IndexDefinition),
+    IndexUnsubscribed { name: String },
+    Spreads { account_id: String, spreads: Vec<SpreadDefinition> },
+    SpreadSubscribed(SpreadDefinition),
+    SpreadUnsubscribed { name: String },
+    Spread(SpreadUpdate),
+    SpreadCandles { name: String, interval: String, candles: Vec<Candle> },
+    SpreadOrder(SpreadOrderReport),
+    GridBot(GridBot),
+    GridBots { account_id: String, bots: Vec<GridBot> },
+    Bot(Bot),
+    Bots { account_id: String, bots: Vec<Bot> },
+    DepegSubscribed { threshold: f64, pairs: Vec<DepegStatus> },
+    DepegUnsubscribed,
+    Depeg(DepegStatus),
+    Listing(ListingEvent),
+    Index(IndexUpdate),
+    OrderGroup(OrderGroupReport),
+    Order(Order),
+    Fill(Fill),
+    MirrorAccount(MirrorAccount),
+    Portfolio(PortfolioReport),
+    FillExplanation(FillExplanation),
+    PnlAttribution(AttributionReport),
+    Benchmarks(BenchmarkReport),
+    PublicProfileSet {
+        account_id: String,
+        token: Option<String>, // Shown only here, None once unpublished
+        delay_secs: i64,
+    },
+    Alert(Alert),
+    DrawdownAlertSet(DrawdownAlertSettings),
+    DepegAlertSet(DepegAlertSettings),
+    DailyReportSet(ReportSchedule),
+    TradingRulesSet(TradingRules),
+    NotificationChannels {
+        account_id: String,
+        channels: Vec<NotificationChannel>,
+    },
+    Inbox {
+        account_id: String,
+        unread: i64,
+        notifications: Vec<InboxNotification>,
+    },
+    MarkedRead {
+        account_id: String,
+        updated: u64,
+    },
+    Candles {
+        series: Vec<CandleSeries>,
+    },
+    #[serde(rename = "candles")]
+    CompactCandles {
+        s: Vec<CompactCandleSeries>,
+    },
+    IngestionStats(IngestLatencyReport),
+    AnalyticsResult(AnalyticsResult),
+    ExportQueued { token: String, job_id: i64 },
+    ExportProgress { token: String, percent: u8, rows: u64 },
+    ExportReady { token: String, url: String, rows: u64 }, // GET the url to download the CSV
+    ExportFailed { token: String, message: String },
+    Error { message: String },
+    ExchangeError { status: u16, message: String },
+    OutageStarted { mode: String, duration_secs: u64 },
+    Maintenance(MaintenanceWindow),
+    MaintenanceEnded,
+    ConfigReloaded {
+        applied: Vec<String>,
+        restart_required: Vec<String>, // Changed in the file but only read on startup
+    },
+    FeatureFlagSet {
+        flag: String,
+        user_id: Option<String>,
+        enabled: Option<bool>,
+    },
+    FeatureFlags { flags: Vec<FeatureFlag> },
+    TenantSet(Tenant),
+    Tenants { tenants: Vec<TenantStatus> },
+    StorageUsage { users: Vec<UserStorage> },
+    OrderBook(BookSnapshot),
+    LadderSubscribed { levels: Option<usize>, ladder: Ladder },
+    LadderUnsubscribed { symbol: String },
+    Ladder(Ladder),
+    LadderCancelled { symbol: String, price: f64, orders: Vec<Order> },
+    TradesSubscribed { symbol: String, aggregate: bool, prints: Vec<TradePrint> },
+    TradesUnsubscribed { symbol: String },
+    Trades { symbol: String, prints: Vec<TradePrint> }, // Newest first, like every list of prints
+    TradeHistory { symbol: String, prints: Vec<TradePrint> },
+    IndicatorSubscribed {
+        symbol: String,
+        interval: String,
+        indicator: IndicatorKind,
+        values: Vec<VolumeDelta>, // Oldest first
+    },
+    KeyLevels(KeyLevels),
+    KeyLevelsSubscribed { levels: Vec<KeyLevels> },
+    KeyLevelsUnsubscribed { symbols: Vec<String> },
+    Footprint {
+        symbol: String,
+        interval: String,
+        row_size: Option<f64>,
+        candles: Vec<FootprintCandle>, // Oldest first
+    },
+    IndicatorUnsubscribed { symbol: String, interval: String, indicator: IndicatorKind },
+    Indicator {
+        symbol: String,
+        interval: String,
+        indicator: IndicatorKind,
+        value: VolumeDelta, // The candle in progress
+    },
+    BackfillStarted { symbol: String, interval: String, job_id: i64 },
+    RateLimited {
+        limit: String, // Which limit was hit: "order", "cancel" or "api_key"
+        max_weight: u32,
+        retry_after_ms: u64,
+        message: String,
+    },
+}
@@ -0,0 +1,69 @@
+use super::{Notification, Notifier, NotifyError};
+use async_trait::async_trait;
+
+// Discord rejects webhook messages longer than this
+const DISCORD_MAX_CONTENT: usize = 2000;
+
+// Incoming webhook of a Discord channel, the target is the webhook URL
+pub struct DiscordNotifier {
+    client: reqwest::Client,
+}
+
+impl DiscordNotifier {
+    pub fn new() -> Self {
+        DiscordNotifier {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    async fn send(&self, target: &str, notification: &Notification) -> Result<(), NotifyError> {
+        let content: String = format!("**{}**\n{}", notification.title, notification.text)
+            .chars()
+            .take(DISCORD_MAX_CONTENT)
+            .collect();
+        self.client
+            .post(target)
+            .json(&serde_json::json!({ "content": content }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+// Incoming webhook of a Slack channel, the target is the webhook URL
+pub struct SlackNotifier {
+    client: reqwest::Client,
+}
+
+impl SlackNotifier {
+    pub fn new() -> Self {
+        SlackNotifier {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    async fn send(&self, target: &str, notification: &Notification) -> Result<(), NotifyError> {
+        self.client
+            .post(target)
+            .json(&serde_json::json!({ "text": format!("*{}*\n{}", notification.title, notification.text) }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use super::{Notification, Notifier, NotifyError};
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::env;
+
+// SMTP delivery configured with SMTP_HOST, SMTP_FROM and optional SMTP_USERNAME/SMTP_PASSWORD,
+// the target is the recipient address
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl EmailNotifier {
+    pub fn from_env() -> Option<Self> {
+        let host = env::var("SMTP_HOST").ok()?;
+        let from = match env::var("SMTP_FROM").ok()?.parse() {
+            Ok(from) => from,
+            Err(e) => {
+                eprintln!("Invalid SMTP_FROM address: {}", e);
+                return None;
+            }
+        };
+
+        let mut transport = match AsyncSmtpTransport::<Tokio1Executor>::relay(&host) {
+            Ok(transport) => transport,
+            Err(e) => {
+                eprintln!("Invalid SMTP_HOST {}: {}", host, e);
+                return None;
+            }
+        };
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            transport = transport.credentials(Credentials::new(username, password));
+        }
+
+        Some(EmailNotifier {
+            transport: transport.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn send(&self, target: &str, notification: &Notification) -> Result<(), NotifyError> {
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(target.parse()?)
+            .subject(notification.title.clone())
+            .body(notification.text.clone())?;
+        self.transport.send(email).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use crate::config::env_or;
+use crate::models::{ChannelKind, NotificationChannel};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+pub mod chat;
+pub mod email;
+pub mod ntfy;
+pub mod webhook;
+
+pub type NotifyError = Box<dyn Error + Send + Sync>;
+
+// Something worth telling an account about, rendered by each backend in its own format
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub account_id: String,
+    pub title: String,
+    pub text: String,
+    pub data: serde_json::Value, // Structured payload for machine consumers such as webhooks
+}
+
+// A delivery channel. The target is the backend specific address: a webhook URL, an email
+// address or an ntfy topic.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, target: &str, notification: &Notification) -> Result<(), NotifyError>;
+}
+
+// Registered backends plus each account's configured channels, with per-backend rate limiting
+// and retries around every delivery
+pub struct Notifications {
+    backends: HashMap<ChannelKind, Box<dyn Notifier>>,
+    channels: Mutex<HashMap<String, Vec<NotificationChannel>>>,
+    sent: Mutex<HashMap<ChannelKind, VecDeque<Instant>>>,
+    rate_limit: usize, // Deliveries per backend per minute
+    retries: u32,
+}
+
+impl Notifications {
+    pub fn from_env() -> Self {
+        let mut backends: HashMap<ChannelKind, Box<dyn Notifier>> = HashMap::new();
+        backends.insert(ChannelKind::Webhook, Box::new(webhook::WebhookNotifier::new()));
+        backends.insert(ChannelKind::Discord, Box::new(chat::DiscordNotifier::new()));
+        backends.insert(ChannelKind::Slack, Box::new(chat::SlackNotifier::new()));
+        backends.insert(ChannelKind::Ntfy, Box::new(ntfy::NtfyNotifier::from_env()));
+        match email::EmailNotifier::from_env() {
+            Some(notifier) => {
+                backends.insert(ChannelKind::Email, Box::new(notifier));
+            }
+            None => println!("SMTP_HOST or SMTP_FROM unset, email notifications disabled"),
+        }
+
+        Notifications {
+            backends,
+            channels: Mutex::new(HashMap::new()),
+            sent: Mutex::new(HashMap::new()),
+            rate_limit: env_or("NOTIFY_RATE_LIMIT_PER_MIN", 30),
+            retries: env_or("NOTIFY_RETRIES", 3),
+        }
+    }
+
+    pub async fn load_channels(&self, channels: Vec<NotificationChannel>) {
+        let mut by_account = self.channels.lock().await;
+        for channel in channels {
+            by_account.entry(channel.account_id.clone()).or_default().push(channel);
+        }
+    }
+
+    pub async fn add_channel(&self, channel: NotificationChannel) -> Vec<NotificationChannel> {
+        let mut by_account = self.channels.lock().await;
+        let channels = by_account.entry(channel.account_id.clone()).or_default();
+        if !channels.iter().any(|c| c.kind == channel.kind && c.target == channel.target) {
+            channels.push(channel);
+        }
+        channels.clone()
+    }
+
+    pub async fn remove_channel(&self, channel: &NotificationChannel) -> Vec<NotificationChannel> {
+        let mut by_account = self.channels.lock().await;
+        let channels = by_account.entry(channel.account_id.clone()).or_default();
+        channels.retain(|c| !(c.kind == channel.kind && c.target == channel.target));
+        channels.clone()
+    }
+
+    // Deliver through one backend, waiting out its rate limit and retrying failures with backoff
+    pub async fn send(
+        &self,
+        kind: ChannelKind,
+        target: &str,
+        notification: &Notification,
+    ) -> Result<(), NotifyError> {
+        let backend = self
+            .backends
+            .get(&kind)
+            .ok_or_else(|| format!("{} notifications are not configured", kind.name()))?;
+
+        self.wait_for_slot(kind).await;
+
+        let mut delay = Duration::from_secs(1);
+        let mut attempt = 0;
+        loop {
+            match backend.send(target, notification).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.retries => {
+                    eprintln!("{} notification to {} failed, retrying: {}", backend.name(), target, e);
+                    sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Fan a notification out to every channel the account configured, plus an optional one-off
+    // channel, in the background
+    pub fn notify_account(
+        self: &Arc<Self>,
+        notification: Notification,
+        extra: Option<(ChannelKind, String)>,
+    ) {
+        let notifications = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut targets: Vec<(ChannelKind, String)> = notifications
+                .channels
+                .lock()
+                .await
+                .get(&notification.account_id)
+                .map(|channels| channels.iter().map(|c| (c.kind, c.target.clone())).collect())
+                .unwrap_or_default();
+            targets.extend(extra);
+
+            for (kind, target) in targets {
+                if let Err(e) = notifications.send(kind, &target, &notification).await {
+                    eprintln!("Error delivering {} notification to {}: {}", kind.name(), target, e);
+                }
+            }
+        });
+    }
+
+    async fn wait_for_slot(&self, kind: ChannelKind) {
+        let window = Duration::from_secs(60);
+        loop {
+            let wait = {
+                let mut sent = self.sent.lock().await;
+                let times = sent.entry(kind).or_default();
+                let now = Instant::now();
+                while times.front().is_some_and(|at| now.duration_since(*at) >= window) {
+                    times.pop_front();
+                }
+                if times.len() < self.rate_limit.max(1) {
+                    times.push_back(now);
+                    return;
+                }
+                window - now.duration_since(times[0])
+            };
+            sleep(wait).await;
+        }
+    }
+}
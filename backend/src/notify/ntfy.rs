@@ -0,0 +1,41 @@
+use super::{Notification, Notifier, NotifyError};
+use crate::config::env_or;
+use async_trait::async_trait;
+
+// ntfy push notifications, the target is a topic on NTFY_URL or a full topic URL
+pub struct NtfyNotifier {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl NtfyNotifier {
+    pub fn from_env() -> Self {
+        NtfyNotifier {
+            client: reqwest::Client::new(),
+            base_url: env_or("NTFY_URL", "https://ntfy.sh".to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for NtfyNotifier {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    async fn send(&self, target: &str, notification: &Notification) -> Result<(), NotifyError> {
+        let url = if target.starts_with("http://") || target.starts_with("https://") {
+            target.to_string()
+        } else {
+            format!("{}/{}", self.base_url.trim_end_matches('/'), target)
+        };
+        self.client
+            .post(url)
+            .header("Title", &notification.title)
+            .body(notification.text.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
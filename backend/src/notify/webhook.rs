@@ -0,0 +1,32 @@
+use super::{Notification, Notifier, NotifyError};
+use async_trait::async_trait;
+
+// POSTs the notification as JSON to an arbitrary URL
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new() -> Self {
+        WebhookNotifier {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn send(&self, target: &str, notification: &Notification) -> Result<(), NotifyError> {
+        self.client
+            .post(target)
+            .json(notification)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
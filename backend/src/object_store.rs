@@ -0,0 +1,350 @@
+use crate::config::env_or;
+use crate::engine;
+use crate::template::civil_from_days;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use sha2::{Digest, Sha256};
+use std::env;
+use url::Url;
+
+const EMPTY_PAYLOAD_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+// An S3-compatible bucket for artifacts too large to keep on the server's disk: closed ingest
+// archive files and finished exports. Enabled by setting OBJECT_STORE_BUCKET, with
+// OBJECT_STORE_ENDPOINT pointing at AWS, MinIO, R2 or similar, OBJECT_STORE_REGION,
+// OBJECT_STORE_ACCESS_KEY / OBJECT_STORE_SECRET_KEY and an optional OBJECT_STORE_PREFIX that every
+// key is stored under. Requests use path-style URLs signed with AWS Signature Version 4.
+pub struct ObjectStore {
+    http: reqwest::Client,
+    endpoint: Url,
+    bucket: String,
+    prefix: String, // Empty, or ending in a slash
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+// One stored object, from a bucket listing
+pub struct StoredObject {
+    pub key: String,           // Relative to the prefix
+    pub last_modified: String, // ISO 8601, e.g. 2024-05-01T12:00:00.000Z
+}
+
+impl ObjectStore {
+    pub fn from_env() -> Option<Self> {
+        let bucket = env::var("OBJECT_STORE_BUCKET").ok().filter(|bucket| !bucket.is_empty())?;
+        let endpoint = env_or("OBJECT_STORE_ENDPOINT", "https://s3.amazonaws.com".to_string());
+        let endpoint = match Url::parse(&endpoint) {
+            Ok(endpoint) => endpoint,
+            Err(e) => {
+                eprintln!("Object storage disabled, invalid OBJECT_STORE_ENDPOINT {}: {}", endpoint, e);
+                return None;
+            }
+        };
+        let prefix = env_or("OBJECT_STORE_PREFIX", String::new());
+        let prefix = match prefix.trim_matches('/') {
+            "" => String::new(),
+            prefix => format!("{}/", prefix),
+        };
+        println!("Storing archives and exports in bucket {} under '{}'", bucket, prefix);
+        Some(ObjectStore {
+            http: reqwest::Client::new(),
+            endpoint,
+            bucket,
+            prefix,
+            region: env_or("OBJECT_STORE_REGION", "us-east-1".to_string()),
+            access_key: env_or("OBJECT_STORE_ACCESS_KEY", String::new()),
+            secret_key: env_or("OBJECT_STORE_SECRET_KEY", String::new()),
+        })
+    }
+
+    pub async fn put(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<(), String> {
+        let url = self.object_url(key);
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let response = self
+            .signed(Method::PUT, &url, &[], &payload_hash)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("uploading {}: {}", key, e))?;
+        check(response, key).await.map(|_| ())
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), String> {
+        let url = self.object_url(key);
+        let response = self
+            .signed(Method::DELETE, &url, &[], EMPTY_PAYLOAD_SHA256)
+            .send()
+            .await
+            .map_err(|e| format!("deleting {}: {}", key, e))?;
+        check(response, key).await.map(|_| ())
+    }
+
+    // Whether the object exists, without downloading it
+    pub async fn exists(&self, key: &str) -> Result<bool, String> {
+        let url = self.object_url(key);
+        let response = self
+            .signed(Method::HEAD, &url, &[], EMPTY_PAYLOAD_SHA256)
+            .send()
+            .await
+            .map_err(|e| format!("checking {}: {}", key, e))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(format!("checking {}: HTTP {}", key, status)),
+        }
+    }
+
+    // Every object whose key starts with `prefix`, following continuation tokens
+    pub async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String> {
+        let mut url = self.endpoint.clone();
+        url.set_path(&uri_encode(&format!("/{}", self.bucket), false));
+        let full_prefix = format!("{}{}", self.prefix, prefix);
+
+        let mut objects = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("list-type".to_string(), "2".to_string()),
+                ("prefix".to_string(), full_prefix.clone()),
+            ];
+            if let Some(token) = continuation.take() {
+                query.push(("continuation-token".to_string(), token));
+            }
+            let response = self
+                .signed(Method::GET, &url, &query, EMPTY_PAYLOAD_SHA256)
+                .send()
+                .await
+                .map_err(|e| format!("listing {}: {}", full_prefix, e))?;
+            let body = check(response, &full_prefix).await?;
+
+            for contents in elements(&body, "Contents") {
+                let (Some(key), Some(last_modified)) = (element(contents, "Key"), element(contents, "LastModified"))
+                else {
+                    continue;
+                };
+                let key = unescape(key);
+                objects.push(StoredObject {
+                    key: key.strip_prefix(&self.prefix).unwrap_or(&key).to_string(),
+                    last_modified: last_modified.to_string(),
+                });
+            }
+            match (element(&body, "IsTruncated"), element(&body, "NextContinuationToken")) {
+                (Some("true"), Some(token)) => continuation = Some(unescape(token)),
+                _ => return Ok(objects),
+            }
+        }
+    }
+
+    // Deletes the objects under `prefix` last written before `before` (epoch milliseconds), the
+    // lifecycle rule for artifacts that are only kept for a while
+    pub async fn expire(&self, prefix: &str, before: i64) -> Result<usize, String> {
+        let cutoff = iso8601(before);
+        let mut deleted = 0;
+        for object in self.list(prefix).await? {
+            // Both sides are zero padded UTC, so comparing the text compares the times
+            if object.last_modified.get(..cutoff.len()).is_some_and(|written| written < cutoff.as_str()) {
+                self.delete(&object.key).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    // A GET link anyone can follow until it expires, for handing downloads straight to clients
+    pub fn presigned_get(&self, key: &str, expires_secs: u64) -> String {
+        let mut url = self.object_url(key);
+        let now = engine::now_millis();
+        let scope = self.scope(now);
+        let mut query = vec![
+            ("X-Amz-Algorithm".to_string(), "AWS4-HMAC-SHA256".to_string()),
+            ("X-Amz-Credential".to_string(), format!("{}/{}", self.access_key, scope)),
+            ("X-Amz-Date".to_string(), amz_date(now)),
+            ("X-Amz-Expires".to_string(), expires_secs.clamp(1, 604_800).to_string()),
+            ("X-Amz-SignedHeaders".to_string(), "host".to_string()),
+        ];
+        let canonical = canonical_request("GET", &url, &query, &[("host", host(&url))], "UNSIGNED-PAYLOAD");
+        query.push(("X-Amz-Signature".to_string(), self.signature(now, &canonical)));
+        url.set_query(Some(&canonical_query(&query)));
+        url.to_string()
+    }
+
+    fn object_url(&self, key: &str) -> Url {
+        let mut url = self.endpoint.clone();
+        // Encoded here the way the signature expects, Url leaves the escapes alone
+        url.set_path(&uri_encode(&format!("/{}/{}{}", self.bucket, self.prefix, key), false));
+        url
+    }
+
+    fn signed(&self, method: Method, url: &Url, query: &[(String, String)], payload_hash: &str) -> reqwest::RequestBuilder {
+        let now = engine::now_millis();
+        let date = amz_date(now);
+        let headers = [
+            ("host", host(url)),
+            ("x-amz-content-sha256", payload_hash.to_string()),
+            ("x-amz-date", date.clone()),
+        ];
+        let canonical = canonical_request(method.as_str(), url, query, &headers, payload_hash);
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.access_key,
+            self.scope(now),
+            self.signature(now, &canonical)
+        );
+
+        let mut url = url.clone();
+        if !query.is_empty() {
+            url.set_query(Some(&canonical_query(query)));
+        }
+        self.http
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", date)
+            .header(reqwest::header::AUTHORIZATION, authorization)
+    }
+
+    fn scope(&self, now: i64) -> String {
+        format!("{}/{}/s3/aws4_request", &amz_date(now)[..8], self.region)
+    }
+
+    fn signature(&self, now: i64, canonical_request: &str) -> String {
+        let date = amz_date(now);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            date,
+            self.scope(now),
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut key = format!("AWS4{}", self.secret_key).into_bytes();
+        for part in [&date[..8], self.region.as_str(), "s3", "aws4_request", string_to_sign.as_str()] {
+            key = hmac(&key, part);
+        }
+        hex::encode(key)
+    }
+}
+
+async fn check(response: reqwest::Response, key: &str) -> Result<String, String> {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if status.is_success() {
+        Ok(body)
+    } else {
+        Err(format!("{}: HTTP {} {}", key, status, element(&body, "Message").unwrap_or_default()))
+    }
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn canonical_request(
+    method: &str,
+    url: &Url,
+    query: &[(String, String)],
+    headers: &[(&str, String)],
+    payload_hash: &str,
+) -> String {
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+    let signed_headers: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        url.path(),
+        canonical_query(query),
+        canonical_headers,
+        signed_headers.join(";"),
+        payload_hash
+    )
+}
+
+fn canonical_query(query: &[(String, String)]) -> String {
+    let mut pairs: Vec<(String, String)> = query
+        .iter()
+        .map(|(key, value)| (uri_encode(key, true), uri_encode(value, true)))
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// Percent-encodes everything but unreserved characters, and slashes too unless in a path
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn host(url: &Url) -> String {
+    match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    }
+}
+
+// 20240501T120000Z
+fn amz_date(millis: i64) -> String {
+    let (year, month, day) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+    let secs = millis.rem_euclid(MILLIS_PER_DAY) / 1000;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// 2024-05-01T12:00:00, the precision bucket listings are compared at
+fn iso8601(millis: i64) -> String {
+    let date = amz_date(millis);
+    format!(
+        "{}-{}-{}T{}:{}:{}",
+        &date[..4],
+        &date[4..6],
+        &date[6..8],
+        &date[9..11],
+        &date[11..13],
+        &date[13..15]
+    )
+}
+
+// The text of the first <name> element, enough for S3's flat XML responses
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    elements(xml, name).next()
+}
+
+fn elements<'a>(xml: &'a str, name: &str) -> impl Iterator<Item = &'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let mut rest = xml;
+    std::iter::from_fn(move || {
+        let start = rest.find(&open)? + open.len();
+        let end = start + rest[start..].find(&close)?;
+        let text = &rest[start..end];
+        rest = &rest[end + close.len()..];
+        Some(text)
+    })
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
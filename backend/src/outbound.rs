@@ -0,0 +1,70 @@
+use crate::config::env_or;
+use crate::state::AppState;
+use futures_util::stream::SplitSink;
+use futures_util::SinkExt;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+// Market data frames a client may fall behind by before newer ones are dropped
+const DEFAULT_MARKET_QUEUE: usize = 256;
+
+type ClientSink = SplitSink<WebSocketStream<TcpStream>, Message>;
+
+// The writer task went away, the client's connection is gone
+#[derive(Debug)]
+pub struct Closed;
+
+// A client's outbound frames in two queues drained by one writer task. Trading messages (replies,
+// fills, alerts, setting changes) always go out before any queued market data, so a burst of
+// tickers can't hold back a fill confirmation.
+pub struct Outbound {
+    trading: mpsc::UnboundedSender<Message>,
+    market: mpsc::Sender<Message>,
+}
+
+impl Outbound {
+    pub fn spawn(state: Arc<AppState>, sink: ClientSink) -> Self {
+        let (trading, trading_receiver) = mpsc::unbounded_channel();
+        let (market, market_receiver) = mpsc::channel(env_or("OUTBOUND_MARKET_QUEUE", DEFAULT_MARKET_QUEUE).max(1));
+        tokio::spawn(run_writer(state, sink, trading_receiver, market_receiver));
+        Outbound { trading, market }
+    }
+
+    // Never dropped, queued however far the client falls behind
+    pub fn trading(&self, frame: String) -> Result<(), Closed> {
+        self.trading.send(Message::Text(frame.into())).map_err(|_| Closed)
+    }
+
+    // Tickers, indices and pages are superseded by the next update, a full queue drops them
+    pub fn market(&self, frame: String) -> Result<(), Closed> {
+        match self.market.try_send(Message::Text(frame.into())) {
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            Err(TrySendError::Closed(_)) => Err(Closed),
+        }
+    }
+}
+
+async fn run_writer(
+    state: Arc<AppState>,
+    mut sink: ClientSink,
+    mut trading: mpsc::UnboundedReceiver<Message>,
+    mut market: mpsc::Receiver<Message>,
+) {
+    loop {
+        let message = tokio::select! {
+            biased;
+            Some(message) = trading.recv() => message,
+            Some(message) = market.recv() => message,
+            else => break,
+        };
+        if let Err(e) = sink.send(message).await {
+            eprintln!("Error sending message: {:?}", e);
+            break;
+        }
+        // Every frame sent goes through here, counted for the usage stats
+        state.usage.message_out();
+    }
+}
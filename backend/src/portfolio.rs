@@ -0,0 +1,239 @@
+use crate::accounts::FeeRates;
+use crate::config::env_or;
+use crate::models::{Fill, Liquidity, PortfolioReport, PositionReport, Side};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Below this size a position is considered flat
+const FLAT_EPSILON: f64 = 1e-12;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Position {
+    pub quantity: f64, // Signed, negative for shorts
+    pub average_price: f64,
+    pub realized_pnl: f64,
+}
+
+impl Position {
+    // Apply a signed trade, realizing P&L on the part that reduces the position
+    pub fn apply(&mut self, quantity: f64, price: f64) {
+        let same_direction = self.quantity * quantity > 0.0;
+        if self.quantity.abs() < FLAT_EPSILON || same_direction {
+            let new_quantity = self.quantity + quantity;
+            self.average_price =
+                (self.average_price * self.quantity + price * quantity) / new_quantity;
+            self.quantity = new_quantity;
+            return;
+        }
+
+        let closed = quantity.abs().min(self.quantity.abs());
+        self.realized_pnl += closed * (price - self.average_price) * self.quantity.signum();
+        self.quantity += quantity;
+
+        if self.quantity.abs() < FLAT_EPSILON {
+            self.quantity = 0.0;
+            self.average_price = 0.0;
+        } else if self.quantity * quantity > 0.0 {
+            // The trade flipped the position, the remainder opens at the trade price
+            self.average_price = price;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Portfolio {
+    pub cash: f64,
+    pub positions: HashMap<String, Position>,
+    #[serde(default)]
+    pub last_fill_id: u64, // Engine fills arrive in id order, anything at or below this is a repeat
+}
+
+impl Portfolio {
+    pub fn new(cash: f64) -> Self {
+        Portfolio {
+            cash,
+            positions: HashMap::new(),
+            last_fill_id: 0,
+        }
+    }
+
+    // Settle a fill against cash and its symbol's position, fees aside. A fill seen before is
+    // ignored and false returned, so redelivery can't move money twice.
+    pub fn apply_fill(&mut self, fill: &Fill) -> bool {
+        if fill.fill_id != 0 {
+            if fill.fill_id <= self.last_fill_id {
+                return false;
+            }
+            self.last_fill_id = fill.fill_id;
+        }
+        let signed_quantity = match fill.side {
+            Side::Buy => fill.quantity,
+            Side::Sell => -fill.quantity,
+        };
+        self.cash -= signed_quantity * fill.price;
+        self.positions
+            .entry(fill.symbol.clone())
+            .or_default()
+            .apply(signed_quantity, fill.price);
+        true
+    }
+}
+
+// Simulated cash and positions of every account, built from its fills
+pub struct PortfolioBook {
+    starting_balance: f64,
+    accounts: HashMap<String, Portfolio>,
+    fee_rates: HashMap<String, FeeRates>, // Accounts without an entry trade fee-free
+}
+
+impl PortfolioBook {
+    pub fn new() -> Self {
+        PortfolioBook {
+            starting_balance: env_or("STARTING_BALANCE", 10_000.0),
+            accounts: HashMap::new(),
+            fee_rates: HashMap::new(),
+        }
+    }
+
+    // Returns the fee charged for the fill, None when it was already applied
+    pub fn apply_fill(&mut self, fill: &Fill) -> Option<f64> {
+        let starting_balance = self.starting_balance;
+        let portfolio = self
+            .accounts
+            .entry(fill.account_id.clone())
+            .or_insert_with(|| Portfolio::new(starting_balance));
+
+        if !portfolio.apply_fill(fill) {
+            return None;
+        }
+        let fee = match self.fee_rates.get(&fill.account_id) {
+            Some(rates) => {
+                let rate = match fill.liquidity {
+                    Liquidity::Maker => rates.maker,
+                    Liquidity::Taker => rates.taker,
+                };
+                fill.quantity * fill.price * rate
+            }
+            None => 0.0,
+        };
+        portfolio.cash -= fee;
+        Some(fee)
+    }
+
+    // The accounts as they are now, for `rollback` to put back
+    pub fn save(&self, account_ids: &[String]) -> Vec<(String, Option<Portfolio>)> {
+        account_ids
+            .iter()
+            .map(|account_id| (account_id.clone(), self.accounts.get(account_id).cloned()))
+            .collect()
+    }
+
+    // Undoes fills that couldn't be stored, accounts they opened are dropped again
+    pub fn rollback(&mut self, saved: Vec<(String, Option<Portfolio>)>) {
+        for (account_id, portfolio) in saved {
+            match portfolio {
+                Some(portfolio) => self.accounts.insert(account_id, portfolio),
+                None => self.accounts.remove(&account_id),
+            };
+        }
+    }
+
+    // Puts back an account rebuilt from its event log
+    pub fn restore(&mut self, account_id: &str, portfolio: Portfolio) {
+        self.accounts.insert(account_id.to_string(), portfolio);
+    }
+
+    // Starts an account with its own balance instead of STARTING_BALANCE, e.g. for guests
+    pub fn open_account(&mut self, account_id: &str, cash: f64) {
+        self.accounts.entry(account_id.to_string()).or_insert_with(|| Portfolio::new(cash));
+    }
+
+    pub fn starting_balance(&self) -> f64 {
+        self.starting_balance
+    }
+
+    // Replaces the account with a flat one holding `cash`
+    pub fn reset_account(&mut self, account_id: &str, cash: f64) {
+        self.accounts.insert(account_id.to_string(), Portfolio::new(cash));
+    }
+
+    // Adds virtual funds, returning the new cash balance
+    pub fn deposit(&mut self, account_id: &str, amount: f64) -> f64 {
+        let starting_balance = self.starting_balance;
+        let portfolio = self
+            .accounts
+            .entry(account_id.to_string())
+            .or_insert_with(|| Portfolio::new(starting_balance));
+        portfolio.cash += amount;
+        portfolio.cash
+    }
+
+    pub fn set_fee_rates(&mut self, account_id: &str, rates: FeeRates) {
+        self.fee_rates.insert(account_id.to_string(), rates);
+    }
+
+    pub fn fee_rates(&self, account_id: &str) -> FeeRates {
+        self.fee_rates.get(account_id).copied().unwrap_or_default()
+    }
+
+    pub fn close_account(&mut self, account_id: &str) {
+        self.accounts.remove(account_id);
+        self.fee_rates.remove(account_id);
+    }
+
+    pub fn account_ids(&self) -> Vec<String> {
+        self.accounts.keys().cloned().collect()
+    }
+
+    pub fn get(&self, account_id: &str) -> Option<&Portfolio> {
+        self.accounts.get(account_id)
+    }
+
+    // Value the account at the given mark prices, falling back to the entry price when unknown
+    pub fn report(
+        &self,
+        account_id: &str,
+        mark_price: impl Fn(&str) -> Option<f64>,
+    ) -> PortfolioReport {
+        let empty = Portfolio::new(self.starting_balance);
+        let portfolio = self.accounts.get(account_id).unwrap_or(&empty);
+
+        let mut positions: Vec<PositionReport> = portfolio
+            .positions
+            .iter()
+            .filter(|(_, position)| position.quantity != 0.0 || position.realized_pnl != 0.0)
+            .map(|(symbol, position)| {
+                let mark = mark_price(symbol).unwrap_or(position.average_price);
+                PositionReport {
+                    symbol: symbol.clone(),
+                    quantity: position.quantity,
+                    average_price: position.average_price,
+                    mark_price: mark,
+                    market_value: position.quantity * mark,
+                    unrealized_pnl: position.quantity * (mark - position.average_price),
+                    realized_pnl: position.realized_pnl,
+                }
+            })
+            .collect();
+        positions.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+        let equity = portfolio.cash + positions.iter().map(|p| p.market_value).sum::<f64>();
+
+        PortfolioReport {
+            account_id: account_id.to_string(),
+            cash: portfolio.cash,
+            equity,
+            positions,
+            risk: None,
+            exposure: None,
+            peak_equity: None,
+            drawdown: None,
+        }
+    }
+}
+
+impl Default for PortfolioBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,115 @@
+use crate::attribution;
+use crate::auth;
+use crate::benchmarks;
+use crate::db;
+use crate::engine::now_millis;
+use crate::models::{PositionReport, PublicProfile, ServerMessage, Side};
+use crate::portfolio::Position;
+use crate::state::AppState;
+use std::collections::BTreeMap;
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+// Longest delay an owner can put on their public positions
+const MAX_DELAY_SECS: i64 = 7 * 86_400;
+// How far a recorded price may be from the delayed cutoff to mark a position
+const MARK_TOLERANCE_MS: i64 = 5 * 60_000;
+
+// Publishing issues a new share token and revokes the previous one, unpublishing revokes it
+pub async fn set_public_profile(
+    state: &AppState,
+    account_id: &str,
+    enabled: bool,
+    delay_secs: i64,
+) -> Result<ServerMessage, String> {
+    if !enabled {
+        db::delete_public_profile(&state.pool, account_id)
+            .await
+            .map_err(|e| format!("Error unpublishing profile: {}", e))?;
+        return Ok(ServerMessage::PublicProfileSet {
+            account_id: account_id.to_string(),
+            token: None,
+            delay_secs: 0,
+        });
+    }
+    if !(0..=MAX_DELAY_SECS).contains(&delay_secs) {
+        return Err(format!("Delay must be between 0 and {} seconds", MAX_DELAY_SECS));
+    }
+
+    let token = auth::generate_token();
+    db::save_public_profile(&state.pool, account_id, &auth::hash_secret(&token), delay_secs)
+        .await
+        .map_err(|e| format!("Error publishing profile: {}", e))?;
+    Ok(ServerMessage::PublicProfileSet {
+        account_id: account_id.to_string(),
+        token: Some(token),
+        delay_secs,
+    })
+}
+
+// Read-only view of a published account as of its delay: daily equity, realized trade stats and
+// the positions held at that time. None for unknown or revoked tokens.
+pub async fn public_profile(state: &AppState, token: &str) -> Result<Option<PublicProfile>, String> {
+    let Some((account_id, delay_secs)) = db::get_public_profile(&state.pool, &auth::hash_secret(token))
+        .await
+        .map_err(|e| format!("Error loading profile: {}", e))?
+    else {
+        return Ok(None);
+    };
+    let as_of = now_millis() - delay_secs * 1000;
+
+    // A delayed profile only shows days that had ended by the cutoff
+    let mut equity_curve = benchmarks::benchmark_report(state, &account_id, None).await?.points;
+    if delay_secs > 0 {
+        equity_curve.retain(|point| point.time + MILLIS_PER_DAY <= as_of);
+    }
+
+    let stats = attribution::attribution_report(&state.pool, &account_id, None, Some(as_of))
+        .await
+        .map_err(|e| format!("Error computing trade stats: {}", e))?;
+    let wins: u64 = stats.by_symbol.iter().map(|bucket| bucket.wins).sum();
+
+    let fills = db::get_fills_since(&state.pool, &account_id, 0)
+        .await
+        .map_err(|e| format!("Error loading fills: {}", e))?;
+    let mut positions: BTreeMap<String, Position> = BTreeMap::new();
+    for fill in fills.iter().filter(|fill| fill.created_at <= as_of) {
+        let signed = match fill.side {
+            Side::Buy => fill.quantity,
+            Side::Sell => -fill.quantity,
+        };
+        positions.entry(fill.symbol.clone()).or_default().apply(signed, fill.price);
+    }
+
+    let mut open = Vec::new();
+    for (symbol, position) in positions.into_iter().filter(|(_, position)| position.quantity != 0.0) {
+        let mark = if delay_secs > 0 {
+            db::get_price_at(&state.pool, &symbol, as_of, MARK_TOLERANCE_MS)
+                .await
+                .map_err(|e| format!("Error loading price: {}", e))?
+                .map(|point| point.price)
+        } else {
+            state.engine.lock().await.last_price(&symbol)
+        }
+        .unwrap_or(position.average_price);
+        open.push(PositionReport {
+            symbol,
+            quantity: position.quantity,
+            average_price: position.average_price,
+            mark_price: mark,
+            market_value: position.quantity * mark,
+            unrealized_pnl: position.quantity * (mark - position.average_price),
+            realized_pnl: position.realized_pnl,
+        });
+    }
+
+    Ok(Some(PublicProfile {
+        account_id,
+        as_of,
+        delay_secs,
+        equity_curve,
+        realized_pnl: stats.total_pnl,
+        trades: stats.trades,
+        win_rate: (stats.trades > 0).then(|| wins as f64 / stats.trades as f64),
+        positions: open,
+    }))
+}
@@ -0,0 +1,152 @@
+use crate::config::LiveConfig;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitKind {
+    Order,
+    Cancel,
+    ApiKey, // Every request made with an API key that has its own limit
+}
+
+impl LimitKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            LimitKind::Order => "order",
+            LimitKind::Cancel => "cancel",
+            LimitKind::ApiKey => "api_key",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub order_limit: u32,  // Order weight allowed per window
+    pub cancel_limit: u32, // Cancel weight allowed per window
+    pub window: Duration,
+}
+
+impl RateLimitConfig {
+    // Defaults loosely follow Binance Futures' per-account order limits
+    pub fn from_env() -> Self {
+        Self::from_config(&LiveConfig::default())
+    }
+
+    pub fn from_config(config: &LiveConfig) -> Self {
+        RateLimitConfig {
+            order_limit: config.value_or("ORDER_RATE_LIMIT", 300),
+            cancel_limit: config.value_or("CANCEL_RATE_LIMIT", 300),
+            window: Duration::from_secs(config.value_or("RATE_LIMIT_WINDOW_SECS", 10)),
+        }
+    }
+
+    fn limit_for(&self, kind: LimitKind) -> u32 {
+        match kind {
+            LimitKind::Order => self.order_limit,
+            LimitKind::Cancel => self.cancel_limit,
+            LimitKind::ApiKey => u32::MAX, // Keys always carry their own limit
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RateLimitError {
+    // The window is full, the request goes through after `retry_after`
+    Exceeded {
+        kind: LimitKind,
+        limit: u32,
+        retry_after: Duration,
+    },
+    // The request alone weighs more than the whole limit and never goes through
+    WeightExceedsLimit { kind: LimitKind, limit: u32, weight: u32 },
+}
+
+// Sliding-window limiter keyed by account or API key, each request consumes `weight` units.
+// Keys without requests in the window are dropped, so any number of ids can be thrown at it.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    windows: HashMap<(String, LimitKind), VecDeque<(Instant, u32)>>,
+    last_sweep: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            windows: HashMap::new(),
+            last_sweep: Instant::now(),
+        }
+    }
+
+    // New limits apply to requests from now on, the windows already counted are kept
+    pub fn set_config(&mut self, config: RateLimitConfig) {
+        self.config = config;
+    }
+
+    pub fn check(
+        &mut self,
+        account_id: &str,
+        kind: LimitKind,
+        weight: u32,
+    ) -> Result<(), RateLimitError> {
+        let limit = self.config.limit_for(kind);
+        self.check_with_limit(account_id, kind, weight, limit)
+    }
+
+    pub fn check_with_limit(
+        &mut self,
+        key: &str,
+        kind: LimitKind,
+        weight: u32,
+        limit: u32,
+    ) -> Result<(), RateLimitError> {
+        if weight > limit {
+            return Err(RateLimitError::WeightExceedsLimit { kind, limit, weight });
+        }
+        let now = Instant::now();
+        let window = self.config.window;
+        self.sweep(now);
+        let entries = self.windows.entry((key.to_string(), kind)).or_default();
+
+        while let Some((at, _)) = entries.front() {
+            if now.duration_since(*at) >= window {
+                entries.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let used: u32 = entries.iter().map(|(_, w)| *w).sum();
+        if used + weight > limit {
+            // Wait until enough of the oldest requests slide out of the window
+            let mut freed = 0;
+            let mut retry_after = window;
+            for (at, w) in entries.iter() {
+                freed += w;
+                if used - freed + weight <= limit {
+                    retry_after = window.saturating_sub(now.duration_since(*at));
+                    break;
+                }
+            }
+            return Err(RateLimitError::Exceeded {
+                kind,
+                limit,
+                retry_after,
+            });
+        }
+
+        entries.push_back((now, weight));
+        Ok(())
+    }
+
+    // Once per window, drops every key whose requests all slid out of it
+    fn sweep(&mut self, now: Instant) {
+        let window = self.config.window;
+        if now.duration_since(self.last_sweep) < window {
+            return;
+        }
+        self.last_sweep = now;
+        self.windows
+            .retain(|_, entries| entries.back().is_some_and(|(at, _)| now.duration_since(*at) < window));
+    }
+}
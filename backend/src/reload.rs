@@ -0,0 +1,182 @@
+use crate::accounts;
+use crate::config::{self, env_or, LiveConfig, UpdateIntervals};
+use crate::conversion::QuoteAssets;
+use crate::rate_limit::RateLimitConfig;
+use crate::state::AppState;
+use crate::tick_filter::TickFilterConfig;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::time::Duration;
+
+// Settings applied on a reload, anything else in the config file waits for a restart
+const RELOADABLE: &[&str] = &[
+    "ORDER_RATE_LIMIT",
+    "CANCEL_RATE_LIMIT",
+    "RATE_LIMIT_WINDOW_SECS",
+    "PAGE_INTERVAL_SECS",
+    "TICKER_INTERVAL_MS",
+    "TICK_FILTER_WINDOW",
+    "TICK_FILTER_MAX_DEVIATION_PCT",
+    "TICK_FILTER_MODE",
+    "FEE_SCHEDULE",
+    "STORAGE_MAX_MB_PER_USER",
+    "STORAGE_MAX_ALERTS_PER_USER",
+    "STORAGE_MAX_EXPORTS_PER_USER",
+    "QUOTE_ASSETS",
+];
+
+// Keys a reload changed, and changed keys it left alone because they only take effect on startup
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+}
+
+// Startup reads settings leniently, falling back to defaults. A reload is strict instead, one
+// invalid value rejects the whole file and the running config stays as it was.
+fn validate(key: &str, value: &str) -> Result<(), String> {
+    let valid = match key {
+        "ORDER_RATE_LIMIT" | "CANCEL_RATE_LIMIT" => value.parse::<u32>().is_ok(),
+        "RATE_LIMIT_WINDOW_SECS" => value.parse::<u64>().is_ok_and(|secs| secs > 0),
+        "PAGE_INTERVAL_SECS" | "TICKER_INTERVAL_MS" => value.parse::<u64>().is_ok(),
+        "TICK_FILTER_WINDOW" => value.parse::<usize>().is_ok_and(|window| window > 0),
+        "TICK_FILTER_MAX_DEVIATION_PCT" => value.parse::<f64>().is_ok_and(|pct| pct.is_finite() && pct > 0.0),
+        "TICK_FILTER_MODE" => value == "reject" || value == "flag",
+        "STORAGE_MAX_MB_PER_USER" | "STORAGE_MAX_ALERTS_PER_USER" | "STORAGE_MAX_EXPORTS_PER_USER" => {
+            value.parse::<i64>().is_ok_and(|max| max >= 0)
+        }
+        "FEE_SCHEDULE" => return accounts::parse_fee_schedule(value).map(|_| ()),
+        "QUOTE_ASSETS" => value
+            .split(',')
+            .map(str::trim)
+            .all(|asset| !asset.is_empty() && asset.chars().all(|c| c.is_ascii_alphanumeric())),
+        _ => true,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid {}: {:?}", key, value))
+    }
+}
+
+// Every template's fee tier has to stay priced, a schedule without one would leave the accounts
+// on that template trading fee-free. None checks the built-in tiers alone.
+async fn check_fee_tiers(state: &AppState, schedule: Option<&str>) -> Result<(), String> {
+    let candidate = LiveConfig::default();
+    match schedule {
+        Some(schedule) => candidate.set("FEE_SCHEDULE".to_string(), schedule.to_string()),
+        None => candidate.unset("FEE_SCHEDULE".to_string()),
+    }
+    let missing: Vec<String> = state
+        .account_templates
+        .lock()
+        .await
+        .list()
+        .into_iter()
+        .filter(|template| accounts::fee_rates(&candidate, &template.fee_tier).is_none())
+        .map(|template| format!("{} (template {})", template.fee_tier, template.name))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("FEE_SCHEDULE is missing fee tiers still in use: {}", missing.join(", ")))
+    }
+}
+
+// Re-reads the config file and applies changed rate limits, push intervals, tick filter, fee
+// schedule, storage quotas and quote assets to the running server. A setting removed from the
+// file goes back to its default. Applied settings are kept in the state's live config, the
+// process environment stays as it was at startup.
+pub async fn reload(state: &AppState) -> Result<ReloadReport, String> {
+    let settings = config::read_config_file(&config::config_file())?;
+    let removed = state
+        .live_config
+        .file_keys()
+        .into_iter()
+        .filter(|key| !settings.iter().any(|(present, _)| present == key))
+        .map(|key| (key, None));
+    let changed: Vec<(String, Option<String>)> = settings
+        .iter()
+        .filter(|(key, value)| state.live_config.var(key).as_deref() != Some(value.as_str()))
+        .map(|(key, value)| (key.clone(), Some(value.clone())))
+        .chain(removed)
+        .collect();
+    let mut errors: Vec<String> = changed
+        .iter()
+        .filter(|(key, _)| RELOADABLE.contains(&key.as_str()))
+        .filter_map(|(key, value)| validate(key, value.as_deref()?).err())
+        .collect();
+    if let Some((_, schedule)) = changed.iter().find(|(key, _)| key == "FEE_SCHEDULE") {
+        errors.extend(check_fee_tiers(state, schedule.as_deref()).await.err());
+    }
+    if !errors.is_empty() {
+        return Err(format!("Config not reloaded, the current one stays active: {}", errors.join("; ")));
+    }
+    state
+        .live_config
+        .set_file_keys(settings.into_iter().map(|(key, _)| key).collect());
+
+    let mut report = ReloadReport::default();
+    for (key, value) in changed {
+        if !RELOADABLE.contains(&key.as_str()) {
+            report.restart_required.push(key);
+            continue;
+        }
+        match value {
+            Some(value) => state.live_config.set(key.clone(), value),
+            None => state.live_config.unset(key.clone()),
+        }
+        report.applied.push(key);
+    }
+    if report.applied.is_empty() {
+        return Ok(report);
+    }
+
+    let config = &state.live_config;
+    state.rate_limiter.lock().await.set_config(RateLimitConfig::from_config(config));
+    state.update_intervals.set(UpdateIntervals::from_config(config));
+    state.tick_filter.lock().await.set_config(TickFilterConfig::from_config(config));
+    if report.applied.iter().any(|key| key == "FEE_SCHEDULE") {
+        let templates = state.account_templates.lock().await;
+        let mut portfolios = state.portfolios.lock().await;
+        for (account_id, template) in templates.assignments() {
+            portfolios.set_fee_rates(account_id, accounts::fee_rates(config, &template.fee_tier).unwrap_or_default());
+        }
+    }
+    if report.applied.iter().any(|key| key == "QUOTE_ASSETS") {
+        *state.quote_assets.write().unwrap() = QuoteAssets::from_config(config);
+    }
+    println!("Config reloaded: {}", report.applied.join(", "));
+    Ok(report)
+}
+
+// Reloads whenever the config file changes, checked every CONFIG_WATCH_SECS (0 turns it off)
+pub async fn run_config_watcher(state: Arc<AppState>) {
+    let secs = env_or("CONFIG_WATCH_SECS", 5u64);
+    if secs == 0 {
+        return;
+    }
+    let path = config::config_file();
+    let mut last_modified = modified_at(&path).await;
+    let mut ticker = state.simulation.interval(Duration::from_secs(secs));
+    loop {
+        ticker.tick().await;
+        let current = modified_at(&path).await;
+        if current.is_none() || current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        match reload(&state).await {
+            Ok(report) if !report.restart_required.is_empty() => {
+                println!("Config changes that need a restart: {}", report.restart_required.join(", "));
+            }
+            Ok(_) => {}
+            Err(message) => eprintln!("{}", message),
+        }
+    }
+}
+
+async fn modified_at(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.and_then(|metadata| metadata.modified()).ok()
+}
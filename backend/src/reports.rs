@@ -0,0 +1,175 @@
+use crate::config::env_or;
+use crate::db;
+use crate::engine::now_millis;
+use crate::jobs::{self, Task};
+use crate::models::{DailySummary, ReportSchedule};
+use crate::notify::{Notification, NotifyError};
+use crate::state::AppState;
+use crate::template;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+const DEFAULT_TEMPLATE: &str = "Daily summary for {{account_id}} on {{date}}
+
+Equity: {{equity}}
+P&L since last summary: {{pnl}}
+Fills today: {{fill_count}}
+Margin usage: {{margin_usage}}
+
+Open positions:
+{{positions}}
+";
+
+// Parse an "HH:MM" UTC time into minutes after midnight
+pub fn parse_send_at(send_at: &str) -> Option<i64> {
+    let (hours, minutes) = send_at.split_once(':')?;
+    let hours: i64 = hours.parse().ok()?;
+    let minutes: i64 = minutes.parse().ok()?;
+    if (0..24).contains(&hours) && (0..60).contains(&minutes) {
+        Some(hours * 60 + minutes)
+    } else {
+        None
+    }
+}
+
+fn report_template() -> String {
+    match env::var("REPORT_TEMPLATE_PATH") {
+        Ok(path) => std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("Error reading report template {}: {:?}, using the default", path, e);
+            DEFAULT_TEMPLATE.to_string()
+        }),
+        Err(_) => DEFAULT_TEMPLATE.to_string(),
+    }
+}
+
+// Check once a minute for schedules whose send time has passed today and queue their reports.
+// A report already queued for the day isn't queued twice.
+pub async fn run_report_scheduler(state: Arc<AppState>) {
+    let mut ticker = state.simulation.interval(Duration::from_secs(60));
+    loop {
+        ticker.tick().await;
+
+        let now = now_millis();
+        let today = now / MILLIS_PER_DAY;
+        let minute_of_day = (now % MILLIS_PER_DAY) / 60_000;
+
+        let schedules = match db::load_report_schedules(&state.pool).await {
+            Ok(schedules) => schedules,
+            Err(e) => {
+                eprintln!("Error loading report schedules: {:?}", e);
+                continue;
+            }
+        };
+
+        for schedule in schedules {
+            let due = parse_send_at(&schedule.send_at).is_some_and(|at| minute_of_day >= at);
+            if !due || schedule.last_sent_day.is_some_and(|day| day >= today) {
+                continue;
+            }
+
+            let task = Task::DailyReport {
+                account_id: schedule.account_id.clone(),
+                day: today,
+            };
+            if let Err(e) = jobs::enqueue(&state.pool, &task).await {
+                eprintln!("Error queueing daily summary for {}: {:?}", schedule.account_id, e);
+            }
+        }
+    }
+}
+
+// Builds and sends the account's summary for `day`, doing nothing if it went out already
+pub async fn send_daily_report(state: &AppState, account_id: &str, day: i64) -> Result<(), String> {
+    let schedules = db::load_report_schedules(&state.pool)
+        .await
+        .map_err(|e| format!("loading report schedules: {:?}", e))?;
+    let Some(schedule) = schedules.into_iter().find(|schedule| schedule.account_id == account_id) else {
+        return Ok(()); // Unsubscribed since it was queued
+    };
+    if schedule.last_sent_day.is_some_and(|sent| sent >= day) {
+        return Ok(());
+    }
+
+    let summary = build_summary(state, &schedule, day).await;
+    send_summary(state, &schedule, &summary, &report_template())
+        .await
+        .map_err(|e| format!("sending daily summary to {}: {}", account_id, e))?;
+    db::mark_report_sent(&state.pool, account_id, day, summary.equity)
+        .await
+        .map_err(|e| format!("recording sent report: {:?}", e))
+}
+
+async fn build_summary(state: &AppState, schedule: &ReportSchedule, today: i64) -> DailySummary {
+    let report = state.portfolio_report(&schedule.account_id).await;
+    let fills = db::get_fills_since(&state.pool, &schedule.account_id, today * MILLIS_PER_DAY)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Error loading fills for summary: {:?}", e);
+            Vec::new()
+        });
+
+    // There is no margin model yet, usage is reported as gross exposure over equity
+    let gross: f64 = report.positions.iter().map(|p| p.market_value.abs()).sum();
+    let margin_usage = if report.equity > 0.0 { gross / report.equity } else { 0.0 };
+    let previous_equity = schedule
+        .last_equity
+        .unwrap_or_else(|| env_or("STARTING_BALANCE", 10_000.0));
+
+    DailySummary {
+        account_id: schedule.account_id.clone(),
+        date: template::format_day(today),
+        equity: report.equity,
+        pnl: report.equity - previous_equity,
+        fills,
+        positions: report
+            .positions
+            .into_iter()
+            .filter(|p| p.quantity != 0.0)
+            .collect(),
+        margin_usage,
+    }
+}
+
+async fn send_summary(
+    state: &AppState,
+    schedule: &ReportSchedule,
+    summary: &DailySummary,
+    template: &str,
+) -> Result<(), NotifyError> {
+    let positions = if summary.positions.is_empty() {
+        "  none".to_string()
+    } else {
+        summary
+            .positions
+            .iter()
+            .map(|p| format!("  {} {} @ {:.4} (uPnL {:.2})", p.symbol, p.quantity, p.average_price, p.unrealized_pnl))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let values: HashMap<&str, String> = HashMap::from([
+        ("account_id", summary.account_id.clone()),
+        ("date", summary.date.clone()),
+        ("equity", format!("{:.2}", summary.equity)),
+        ("pnl", format!("{:+.2}", summary.pnl)),
+        ("fill_count", summary.fills.len().to_string()),
+        ("margin_usage", format!("{:.1}%", summary.margin_usage * 100.0)),
+        ("positions", positions),
+    ]);
+    let text = template::render(template, &values);
+
+    let notification = Notification {
+        account_id: summary.account_id.clone(),
+        title: format!("Daily summary for {} on {}", summary.account_id, summary.date),
+        text,
+        data: serde_json::to_value(summary)?,
+    };
+    state
+        .notifications
+        .send(schedule.channel, &schedule.target, &notification)
+        .await
+}
@@ -0,0 +1,111 @@
+use crate::config::env_or;
+use crate::conversion::QuoteAssets;
+use crate::db;
+use crate::ingest;
+use crate::models::{TickerData, UsdPricing};
+use crate::tick_filter::{TickAction, TickAnomaly, TickFilter, TickFilterConfig};
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+
+const MILLIS_PER_HOUR: i64 = 3_600_000;
+
+// Replays the raw ingest archive between two receive times through the current decoder and tick
+// filter, replacing ticker_data and quarantined_ticks for each hour in one transaction so repeated
+// runs give the same result. Candles are derived from ticker_data and pick the changes up directly.
+//
+//   trading_simulator_app reprocess <from_ms> <to_ms>
+pub async fn run_reprocess(pool: &PgPool, from: i64, to: i64) -> Result<(), Box<dyn Error>> {
+    let source = env_or("INGEST_ARCHIVE", "db".to_string());
+    let mut filter = TickFilter::new(TickFilterConfig::from_env());
+    // USD prices are derived from the replayed ticks of each quote's USDT pair, as live ingest does
+    let quote_assets = QuoteAssets::from_env();
+    let mut latest_prices: HashMap<String, f64> = HashMap::new();
+    let (mut messages_total, mut ticks_total) = (0, 0);
+
+    let mut window_start = from;
+    while window_start < to {
+        let window_end = (window_start - window_start.rem_euclid(MILLIS_PER_HOUR) + MILLIS_PER_HOUR).min(to);
+
+        let messages = match source.as_str() {
+            "files" => read_archive_files(window_start, window_end)?,
+            _ => read_archive_table(pool, window_start, window_end).await?,
+        };
+
+        let mut ticks: Vec<(TickerData, i64, UsdPricing)> = Vec::new();
+        let mut quarantined: Vec<(TickerData, TickAnomaly)> = Vec::new();
+        for (received_at, payload) in &messages {
+            for ticker in ingest::decode_tickers(payload) {
+                match filter.check(&ticker) {
+                    Some(anomaly) if anomaly.action == TickAction::Rejected => {
+                        quarantined.push((ticker, anomaly));
+                        continue;
+                    }
+                    Some(anomaly) => quarantined.push((ticker.clone(), anomaly)),
+                    None => {}
+                }
+                let price = ticker.c.parse::<f64>().unwrap_or_default();
+                let pricing = quote_assets.usd_pricing(&ticker.s, price, |pair| latest_prices.get(pair).copied());
+                latest_prices.insert(ticker.s.clone(), price);
+                ticks.push((ticker, *received_at, pricing));
+            }
+        }
+
+        db::replace_ingested_window(pool, window_start, window_end, &ticks, &quarantined).await?;
+        println!(
+            "Reprocessed {} messages into {} ticks ({} quarantined) for {}..{}",
+            messages.len(),
+            ticks.len(),
+            quarantined.len(),
+            window_start,
+            window_end
+        );
+        messages_total += messages.len();
+        ticks_total += ticks.len();
+        window_start = window_end;
+    }
+
+    let gaps = db::detect_data_gaps(pool, env_or("GAP_MIN_SECS", 5.0), env_or("GAP_CADENCE_MULTIPLIER", 10.0)).await?;
+    println!(
+        "Reprocess finished: {} messages, {} ticks, {} new data gaps",
+        messages_total, ticks_total, gaps
+    );
+    Ok(())
+}
+
+async fn read_archive_table(pool: &PgPool, from: i64, to: i64) -> Result<Vec<(i64, String)>, Box<dyn Error>> {
+    let mut messages = Vec::new();
+    for (received_at, compressed) in db::get_ingest_log(pool, from, to).await? {
+        let mut payload = String::new();
+        GzDecoder::new(compressed.as_slice()).read_to_string(&mut payload)?;
+        messages.push((received_at, payload));
+    }
+    Ok(messages)
+}
+
+fn read_archive_files(from: i64, to: i64) -> Result<Vec<(i64, String)>, Box<dyn Error>> {
+    let dir = PathBuf::from(env_or("INGEST_ARCHIVE_DIR", "ingest_archive".to_string()));
+    let path = dir.join(format!("ingest-{}.log.gz", from / MILLIS_PER_HOUR));
+    let Ok(file) = File::open(&path) else {
+        return Ok(Vec::new());
+    };
+
+    let mut messages = Vec::new();
+    for line in BufReader::new(MultiGzDecoder::new(file)).lines() {
+        let line = line?;
+        let Some((received_at, payload)) = line.split_once('\t') else {
+            continue;
+        };
+        let Ok(received_at) = received_at.parse::<i64>() else {
+            continue;
+        };
+        if received_at >= from && received_at < to {
+            messages.push((received_at, payload.to_string()));
+        }
+    }
+    Ok(messages)
+}
@@ -0,0 +1,138 @@
+use crate::config::env_or;
+use std::fmt;
+use std::future::Future;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+#[derive(Debug)]
+pub enum DbError {
+    CircuitOpen,
+    Query(sqlx::Error),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::CircuitOpen => write!(f, "database circuit breaker is open"),
+            DbError::Query(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+// Errors worth retrying: lost connections, pool exhaustion and Postgres connection or shutdown codes
+pub fn is_transient(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Tls(_) => true,
+        sqlx::Error::Database(e) => e
+            .code()
+            .is_some_and(|code| code.starts_with("08") || code.starts_with("57P") || code == "53300"),
+        _ => false,
+    }
+}
+
+// Stops hammering an unreachable database: after `failure_threshold` consecutive transient failures
+// the breaker opens and calls fail fast until the cooldown passes, then one trial call decides
+// whether it closes again
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    retries: u32,
+    retry_delay: Duration,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    trial_in_flight: bool,
+}
+
+impl CircuitBreaker {
+    pub fn from_env() -> Self {
+        CircuitBreaker {
+            failure_threshold: env_or("DB_BREAKER_FAILURES", 5),
+            cooldown: Duration::from_secs(env_or("DB_BREAKER_COOLDOWN_SECS", 10)),
+            retries: env_or("DB_RETRY_ATTEMPTS", 3),
+            retry_delay: Duration::from_millis(env_or("DB_RETRY_BASE_MS", 100)),
+            consecutive_failures: 0,
+            open_until: None,
+            trial_in_flight: false,
+        }
+    }
+
+    // Open and still cooling down, calls are rejected without touching the database
+    pub fn is_open(&self) -> bool {
+        self.open_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    fn allow(&mut self) -> bool {
+        match self.open_until {
+            None => true,
+            Some(until) if Instant::now() >= until && !self.trial_in_flight => {
+                self.trial_in_flight = true;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn record_success(&mut self) {
+        if self.open_until.is_some() {
+            println!("Database reachable again, circuit breaker closed");
+        }
+        self.consecutive_failures = 0;
+        self.open_until = None;
+        self.trial_in_flight = false;
+    }
+
+    fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+        self.trial_in_flight = false;
+        if self.open_until.is_some() || self.consecutive_failures >= self.failure_threshold {
+            if self.open_until.is_none() {
+                eprintln!(
+                    "Database failed {} times in a row, circuit breaker open for {:?}",
+                    self.consecutive_failures, self.cooldown
+                );
+            }
+            self.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+// Run a query through the breaker, retrying transient errors with exponential backoff
+pub async fn call<T, F, Fut>(breaker: &Mutex<CircuitBreaker>, mut query: F) -> Result<T, DbError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let (retries, mut delay) = {
+        let breaker = breaker.lock().await;
+        (breaker.retries, breaker.retry_delay)
+    };
+
+    let mut attempt = 0;
+    loop {
+        if !breaker.lock().await.allow() {
+            return Err(DbError::CircuitOpen);
+        }
+
+        match query().await {
+            Ok(value) => {
+                breaker.lock().await.record_success();
+                return Ok(value);
+            }
+            Err(e) if is_transient(&e) => {
+                let mut breaker = breaker.lock().await;
+                breaker.record_failure();
+                if attempt >= retries || breaker.is_open() {
+                    return Err(DbError::Query(e));
+                }
+            }
+            // Non-transient errors are the query's fault, not the database's
+            Err(e) => return Err(DbError::Query(e)),
+        }
+
+        attempt += 1;
+        sleep(delay).await;
+        delay *= 2;
+    }
+}
@@ -0,0 +1,117 @@
+use crate::config::env_or;
+use crate::db;
+use crate::engine::now_millis;
+use crate::models::{PositionReport, RiskMetrics};
+use crate::scheduler::ScheduledJob;
+use crate::state::AppState;
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+use tokio::time::Duration;
+
+pub const VAR_CONFIDENCE: f64 = 0.95;
+// Number of daily closes looked back for the historical simulation
+const LOOKBACK_DAYS: i64 = 250;
+// Fewer scenarios than this make the tail estimate meaningless
+const MIN_SCENARIOS: usize = 20;
+
+// One-day historical-simulation VaR and expected shortfall: the current positions are revalued
+// under every stored daily return and the worst (1 - confidence) tail of the P&L is measured
+pub async fn compute_risk(
+    pool: &PgPool,
+    positions: &[PositionReport],
+) -> Result<Option<RiskMetrics>, sqlx::Error> {
+    let open: Vec<&PositionReport> = positions.iter().filter(|p| p.quantity != 0.0).collect();
+    if open.is_empty() {
+        return Ok(None);
+    }
+
+    let symbols: Vec<String> = open.iter().map(|p| p.symbol.clone()).collect();
+    let returns = db::get_daily_returns(pool, &symbols, LOOKBACK_DAYS).await?;
+
+    // Only days on which every held symbol has a return form a complete scenario
+    let mut scenarios: BTreeMap<String, (f64, usize)> = BTreeMap::new();
+    for position in &open {
+        let Some(series) = returns.get(&position.symbol) else {
+            return Ok(None);
+        };
+        for (day, daily_return) in series {
+            let entry = scenarios.entry(day.clone()).or_insert((0.0, 0));
+            entry.0 += position.market_value * daily_return;
+            entry.1 += 1;
+        }
+    }
+    let pnl: Vec<f64> = scenarios
+        .into_values()
+        .filter(|(_, count)| *count == open.len())
+        .map(|(pnl, _)| pnl)
+        .collect();
+
+    Ok(historical_var(pnl, VAR_CONFIDENCE))
+}
+
+pub fn historical_var(mut pnl: Vec<f64>, confidence: f64) -> Option<RiskMetrics> {
+    if pnl.len() < MIN_SCENARIOS {
+        return None;
+    }
+    pnl.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+    let tail_len = (((1.0 - confidence) * pnl.len() as f64).ceil() as usize).max(1);
+    let tail = &pnl[..tail_len];
+    let var = -tail[tail_len - 1];
+    let expected_shortfall = -tail.iter().sum::<f64>() / tail_len as f64;
+
+    Some(RiskMetrics {
+        confidence,
+        value_at_risk: var.max(0.0),
+        expected_shortfall: expected_shortfall.max(0.0),
+        scenarios: pnl.len(),
+        computed_at: now_millis(),
+    })
+}
+
+pub fn daily_returns(closes: &[(String, f64)]) -> Vec<(String, f64)> {
+    closes
+        .windows(2)
+        .filter(|pair| pair[0].1 > 0.0)
+        .map(|pair| (pair[1].0.clone(), pair[1].1 / pair[0].1 - 1.0))
+        .collect()
+}
+
+// Daily simple returns per symbol, keyed by the day they were realized on
+pub type ReturnSeries = HashMap<String, Vec<(String, f64)>>;
+
+// Record today's closes, then refresh the stored risk of every account
+pub struct RiskJob;
+
+#[async_trait]
+impl ScheduledJob for RiskJob {
+    fn name(&self) -> &'static str {
+        "daily_closes_and_risk"
+    }
+
+    fn every(&self) -> Duration {
+        Duration::from_secs(env_or("RISK_INTERVAL_SECS", 86_400).max(1))
+    }
+
+    async fn run(&self, state: &AppState, _due_at: i64) -> Result<(), String> {
+        db::record_daily_closes(&state.pool)
+            .await
+            .map_err(|e| format!("recording daily closes: {:?}", e))?;
+
+        let reports = state.all_portfolio_reports().await;
+
+        for report in reports {
+            match compute_risk(&state.pool, &report.positions).await {
+                Ok(Some(risk)) => {
+                    if let Err(e) = db::save_portfolio_risk(&state.pool, &report.account_id, &risk).await {
+                        eprintln!("Error saving portfolio risk: {:?}", e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("Error computing portfolio risk: {:?}", e),
+            }
+        }
+        Ok(())
+    }
+}
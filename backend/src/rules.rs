@@ -0,0 +1,86 @@
+use crate::db;
+use crate::engine::now_millis;
+use crate::models::{ServerMessage, TradingRules};
+use crate::reports::parse_send_at;
+use crate::state::AppState;
+use std::collections::HashMap;
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+// Self-imposed trading rules per account
+#[derive(Default)]
+pub struct RuleBook {
+    rules: HashMap<String, TradingRules>, // account_id -> rules
+}
+
+impl RuleBook {
+    pub fn load(&mut self, rules: Vec<TradingRules>) {
+        self.rules = rules.into_iter().map(|rules| (rules.account_id.clone(), rules)).collect();
+    }
+
+    pub fn set(&mut self, rules: TradingRules) {
+        self.rules.insert(rules.account_id.clone(), rules);
+    }
+
+    pub fn get(&self, account_id: &str) -> Option<&TradingRules> {
+        self.rules.get(account_id)
+    }
+}
+
+pub async fn set_rules(state: &AppState, rules: TradingRules) -> Result<ServerMessage, String> {
+    for window in &rules.no_trade_windows {
+        if parse_send_at(&window.start).is_none() || parse_send_at(&window.end).is_none() {
+            return Err("No-trade windows need HH:MM start and end times in UTC".to_string());
+        }
+    }
+    if rules.max_trades_per_day == Some(0) {
+        return Err("max_trades_per_day must be at least 1, remove the limit with null".to_string());
+    }
+    db::save_trading_rules(&state.pool, &rules)
+        .await
+        .map_err(|e| format!("Error saving trading rules: {}", e))?;
+    state.trading_rules.lock().await.set(rules.clone());
+    Ok(ServerMessage::TradingRulesSet(rules))
+}
+
+// Rejects `placing` orders going in together when they break one of the account's rules, with the
+// rule in the reason. An amendment places nothing new, it is checked with 0.
+pub async fn check_orders(state: &AppState, account_id: &str, placing: u32) -> Result<(), String> {
+    let Some(rules) = state.trading_rules.lock().await.get(account_id).cloned() else {
+        return Ok(());
+    };
+    let now = now_millis();
+
+    let minute_of_day = (now % MILLIS_PER_DAY) / 60_000;
+    for window in &rules.no_trade_windows {
+        let (Some(start), Some(end)) = (parse_send_at(&window.start), parse_send_at(&window.end)) else {
+            continue;
+        };
+        // A window whose end is before its start runs past midnight
+        let inside = if start <= end {
+            (start..end).contains(&minute_of_day)
+        } else {
+            minute_of_day >= start || minute_of_day < end
+        };
+        if inside {
+            return Err(format!(
+                "Trading rule: no trading between {} and {} UTC",
+                window.start, window.end
+            ));
+        }
+    }
+
+    // Counted from the stored orders, so bot, grid, spread and approved orders all count once
+    if let Some(max_trades) = rules.max_trades_per_day {
+        let placed = db::count_orders_since(&state.pool, account_id, now - now % MILLIS_PER_DAY)
+            .await
+            .map_err(|e| format!("Error checking trading rules: {}", e))?;
+        if placed + placing as i64 > max_trades as i64 {
+            return Err(format!(
+                "Trading rule: at most {} orders per day, {} already placed today",
+                max_trades, placed
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use crate::archive::ArchiveRetentionJob;
+use crate::config::env_or;
+use crate::db;
+use crate::exports::ExportCleanupJob;
+use crate::risk::RiskJob;
+use crate::state::AppState;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+const DAY_SECS: u64 = 86_400;
+const DAY_MS: i64 = 86_400_000;
+
+// A periodic job whose runs must not be lost to downtime. Each run is for one due time, and a run
+// repeated after a crash between finishing and being recorded has to be harmless.
+#[async_trait]
+pub trait ScheduledJob: Send + Sync {
+    fn name(&self) -> &'static str; // Key of its last run in job_runs
+    fn every(&self) -> Duration;
+
+    async fn run(&self, state: &AppState, due_at: i64) -> Result<(), String>;
+}
+
+fn jobs() -> Vec<Box<dyn ScheduledJob>> {
+    vec![
+        Box::new(RiskJob),
+        Box::new(EquitySnapshotJob),
+        Box::new(RetentionJob),
+        Box::new(ExportCleanupJob),
+        Box::new(ArchiveRetentionJob),
+    ]
+}
+
+// Runs every job when due, checking every SCHEDULER_POLL_SECS. A job's runs are spaced from its
+// last recorded one, so after downtime each missed run is made in order, oldest first. Past
+// SCHEDULER_MAX_CATCH_UP missed runs only the newest are made and the rest are reported skipped.
+pub async fn run_scheduler(state: Arc<AppState>) {
+    let poll = Duration::from_secs(env_or("SCHEDULER_POLL_SECS", 30).max(1));
+    let max_catch_up: i64 = env_or("SCHEDULER_MAX_CATCH_UP", 30).max(1);
+    let jobs = jobs();
+    loop {
+        for job in &jobs {
+            run_due(&state, job.as_ref(), max_catch_up).await;
+        }
+        state.simulation.sleep(poll).await;
+    }
+}
+
+async fn run_due(state: &AppState, job: &dyn ScheduledJob, max_catch_up: i64) {
+    let last_run = match db::get_job_last_run(&state.pool, job.name()).await {
+        Ok(last_run) => last_run,
+        Err(e) => {
+            eprintln!("Error loading the last {} run: {:?}", job.name(), e);
+            return;
+        }
+    };
+
+    let now = state.simulation.now_millis();
+    let every = (job.every().as_millis() as i64).max(1);
+    let due: Vec<i64> = match last_run {
+        // A job that never ran starts its schedule now
+        None => vec![now],
+        Some(last_run) => {
+            let missed = (now - last_run) / every;
+            if missed > max_catch_up {
+                eprintln!(
+                    "Scheduled job {} missed {} runs, skipping the oldest {}",
+                    job.name(),
+                    missed,
+                    missed - max_catch_up
+                );
+            }
+            let first = (missed - max_catch_up).max(0) + 1;
+            (first..=missed).map(|n| last_run + n * every).collect()
+        }
+    };
+    if due.len() > 1 {
+        println!("Catching up {} missed runs of {}", due.len(), job.name());
+    }
+
+    for due_at in due {
+        if let Err(e) = job.run(state, due_at).await {
+            // Not recorded, so the same run is retried on the next poll
+            eprintln!("Scheduled job {} failed: {}", job.name(), e);
+            return;
+        }
+        if let Err(e) = db::record_job_run(&state.pool, job.name(), due_at).await {
+            eprintln!("Error recording the {} run: {:?}", job.name(), e);
+            return;
+        }
+    }
+}
+
+// Each account's cash and equity for the day, valued when the run happens. A run caught up after
+// downtime still fills in its own day.
+struct EquitySnapshotJob;
+
+#[async_trait]
+impl ScheduledJob for EquitySnapshotJob {
+    fn name(&self) -> &'static str {
+        "equity_snapshot"
+    }
+
+    fn every(&self) -> Duration {
+        Duration::from_secs(env_or("EQUITY_SNAPSHOT_SECS", DAY_SECS).max(1))
+    }
+
+    async fn run(&self, state: &AppState, due_at: i64) -> Result<(), String> {
+        let reports = state.all_portfolio_reports().await;
+        db::save_equity_snapshots(&state.pool, due_at, &reports)
+            .await
+            .map_err(|e| format!("saving equity snapshots: {:?}", e))
+    }
+}
+
+// Order history older than ORDER_HISTORY_RETENTION_DAYS is deleted once a day
+struct RetentionJob;
+
+#[async_trait]
+impl ScheduledJob for RetentionJob {
+    fn name(&self) -> &'static str {
+        "order_history_retention"
+    }
+
+    fn every(&self) -> Duration {
+        Duration::from_secs(DAY_SECS)
+    }
+
+    async fn run(&self, state: &AppState, due_at: i64) -> Result<(), String> {
+        let retention_days: i64 = env_or("ORDER_HISTORY_RETENTION_DAYS", 90).max(1);
+        let deleted = db::prune_order_history(&state.pool, due_at - retention_days * DAY_MS)
+            .await
+            .map_err(|e| format!("pruning order history: {:?}", e))?;
+        if deleted > 0 {
+            println!("Deleted {} rows of order history older than {} days", deleted, retention_days);
+        }
+        Ok(())
+    }
+}
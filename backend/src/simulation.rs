@@ -0,0 +1,248 @@
+use crate::config::env_or;
+use crate::engine;
+use async_trait::async_trait;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::env;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant};
+
+// Source of the timestamps the simulation stamps on orders and fills
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> i64;
+}
+
+// Wall clock time, the default outside deterministic mode
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        engine::now_millis()
+    }
+}
+
+// Time that only moves when market data says so. It follows the exchange event time of each tick
+// and never goes backwards, so a replayed feed produces the same timestamps on every run.
+pub struct VirtualClock {
+    millis: AtomicI64,
+}
+
+impl VirtualClock {
+    pub fn new(start_millis: i64) -> Self {
+        VirtualClock {
+            millis: AtomicI64::new(start_millis),
+        }
+    }
+
+    pub fn advance_to(&self, millis: i64) {
+        self.millis.fetch_max(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now_millis(&self) -> i64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
+
+// Monotonic time that intervals, timeouts and delays wait on. The tokio timer waits in real time,
+// the manual one only moves when a test advances it, so heartbeats and expiry run in no time.
+#[async_trait]
+pub trait Timer: Send + Sync {
+    fn elapsed(&self) -> Duration; // Since the timer was created
+    async fn sleep_until(&self, deadline: Duration);
+}
+
+pub struct TokioTimer {
+    started: Instant,
+}
+
+impl TokioTimer {
+    pub fn new() -> Self {
+        TokioTimer { started: Instant::now() }
+    }
+}
+
+impl Default for TokioTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Timer for TokioTimer {
+    fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    async fn sleep_until(&self, deadline: Duration) {
+        time::sleep_until(self.started + deadline).await;
+    }
+}
+
+// Time under a test's control. Sleepers wake once advance() carries the time past their deadline,
+// and the wall clock reads the start time plus whatever has elapsed. Only tests/virtual_time.rs
+// drives it, the server never does.
+#[allow(dead_code)]
+pub struct ManualTimer {
+    start_millis: i64,
+    elapsed: watch::Sender<Duration>,
+}
+
+#[allow(dead_code)]
+impl ManualTimer {
+    pub fn new(start_millis: i64) -> Self {
+        ManualTimer {
+            start_millis,
+            elapsed: watch::Sender::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.elapsed.send_modify(|elapsed| *elapsed += by);
+    }
+}
+
+#[async_trait]
+impl Timer for ManualTimer {
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.borrow()
+    }
+
+    async fn sleep_until(&self, deadline: Duration) {
+        let mut elapsed = self.elapsed.subscribe();
+        // The sender lives as long as the timer, so this only returns once the deadline passed
+        let _ = elapsed.wait_for(|elapsed| *elapsed >= deadline).await;
+    }
+}
+
+impl Clock for ManualTimer {
+    fn now_millis(&self) -> i64 {
+        self.start_millis + Timer::elapsed(self).as_millis() as i64
+    }
+}
+
+// Ticks every period on a timer. The first tick completes at once unless started with a delay,
+// and ticks missed while the owner was busy fire back to back, like tokio's interval.
+pub struct Ticker {
+    timer: Arc<dyn Timer>,
+    next: Duration,
+    period: Duration,
+}
+
+impl Ticker {
+    pub async fn tick(&mut self) {
+        self.timer.sleep_until(self.next).await;
+        self.next += self.period;
+    }
+}
+
+// Clock and randomness shared by the simulation pipeline. Setting SIMULATION_SEED switches to
+// deterministic mode: latency jitter draws from an RNG seeded with it and time runs on a virtual
+// clock starting at SIMULATION_START_MS, so a backtest over the same feed reproduces exactly.
+pub struct Simulation {
+    clock: Arc<dyn Clock>,
+    timer: Arc<dyn Timer>,
+    virtual_clock: Option<Arc<VirtualClock>>, // Set in deterministic mode
+    rng: Mutex<StdRng>,
+    seed: Option<u64>,
+}
+
+impl Simulation {
+    pub fn from_env() -> Self {
+        match env::var("SIMULATION_SEED").ok().and_then(|seed| seed.parse().ok()) {
+            Some(seed) => Simulation::deterministic(seed, env_or("SIMULATION_START_MS", 0)),
+            None => Simulation {
+                clock: Arc::new(SystemClock),
+                timer: Arc::new(TokioTimer::new()),
+                virtual_clock: None,
+                rng: Mutex::new(StdRng::from_entropy()),
+                seed: None,
+            },
+        }
+    }
+
+    pub fn deterministic(seed: u64, start_millis: i64) -> Self {
+        let virtual_clock = Arc::new(VirtualClock::new(start_millis));
+        Simulation {
+            clock: Arc::clone(&virtual_clock) as Arc<dyn Clock>,
+            timer: Arc::new(TokioTimer::new()),
+            virtual_clock: Some(virtual_clock),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            seed: Some(seed),
+        }
+    }
+
+    // Both clock and timer driven by hand, for tests
+    #[allow(dead_code)]
+    pub fn manual(seed: u64, timer: Arc<ManualTimer>) -> Self {
+        Simulation {
+            clock: Arc::clone(&timer) as Arc<dyn Clock>,
+            timer,
+            virtual_clock: None,
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            seed: Some(seed),
+        }
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
+    }
+
+    pub fn now_millis(&self) -> i64 {
+        self.clock.now_millis()
+    }
+
+    // Time on the simulation's timer, for measuring how long something has waited
+    pub fn elapsed(&self) -> Duration {
+        self.timer.elapsed()
+    }
+
+    pub async fn sleep(&self, duration: Duration) {
+        if !duration.is_zero() {
+            self.timer.sleep_until(self.timer.elapsed() + duration).await;
+        }
+    }
+
+    // None when the future didn't finish within the duration
+    pub async fn timeout<F: Future>(&self, duration: Duration, future: F) -> Option<F::Output> {
+        tokio::select! {
+            output = future => Some(output),
+            _ = self.sleep(duration) => None,
+        }
+    }
+
+    pub fn interval(&self, period: Duration) -> Ticker {
+        self.interval_at(Duration::ZERO, period)
+    }
+
+    // Like interval, with the first tick `delay` from now
+    pub fn interval_at(&self, delay: Duration, period: Duration) -> Ticker {
+        assert!(!period.is_zero(), "interval period must be non-zero");
+        Ticker {
+            timer: Arc::clone(&self.timer),
+            next: self.timer.elapsed() + delay,
+            period,
+        }
+    }
+
+    // Market data arrived with this exchange event time, the virtual clock catches up to it
+    pub fn observe(&self, event_time: i64) {
+        if let Some(clock) = &self.virtual_clock {
+            clock.advance_to(event_time);
+        }
+    }
+
+    // Draws must happen in a deterministic order for runs to repeat, take it where the order is
+    // fixed (under the engine lock, or before spawning) rather than inside spawned tasks
+    pub fn rng(&self) -> MutexGuard<'_, StdRng> {
+        self.rng.lock().unwrap()
+    }
+}
@@ -0,0 +1,199 @@
+use crate::config::env_or;
+use crate::db;
+use crate::models::{TickerData, UsdPricing};
+use crate::state::AppState;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SpooledTick {
+    ticker: TickerData,
+    received_at: i64,
+    #[serde(default)] // Segments spooled before USD pricing was stored
+    pricing: UsdPricing,
+}
+
+// Write-ahead disk queue for ticks that could not be stored while the database was unreachable.
+// Ticks are appended as JSON lines to numbered segment files, once the segment limit is reached
+// the oldest segment is dropped.
+pub struct TickSpool {
+    dir: PathBuf,
+    segment_lines: usize,
+    max_segments: usize,
+    segments: VecDeque<u64>, // Segment ids on disk, oldest first, without the one being replayed
+    next_id: u64,
+    writer: Option<(u64, BufWriter<File>, usize)>,
+}
+
+// A segment taken out of the spool to be replayed without holding the spool
+pub struct SpoolSegment {
+    id: u64,
+    path: PathBuf,
+}
+
+impl TickSpool {
+    pub fn from_env() -> io::Result<Self> {
+        let dir = PathBuf::from(env_or("TICK_SPOOL_DIR", "tick_spool".to_string()));
+        fs::create_dir_all(&dir)?;
+
+        // Segments left over from a previous run are replayed too
+        let mut segments: Vec<u64> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str()?.strip_suffix(".jsonl")?.parse().ok())
+            .collect();
+        segments.sort_unstable();
+
+        Ok(TickSpool {
+            dir,
+            segment_lines: env_or("TICK_SPOOL_SEGMENT_LINES", 10_000),
+            max_segments: env_or("TICK_SPOOL_MAX_SEGMENTS", 100),
+            next_id: segments.last().map_or(0, |last| last + 1),
+            segments: segments.into(),
+            writer: None,
+        })
+    }
+
+    // Appends a frame's ticks and syncs them once, they count as spooled when this returns.
+    // Blocking file IO, callers run it off the runtime threads.
+    pub fn push_batch(&mut self, ticks: Vec<(TickerData, i64, UsdPricing)>) -> io::Result<()> {
+        for (ticker, received_at, pricing) in ticks {
+            if self.writer.as_ref().is_none_or(|(_, _, lines)| *lines >= self.segment_lines) {
+                self.rotate()?;
+            }
+            let (_, writer, lines) = self.writer.as_mut().expect("spool segment open");
+            serde_json::to_writer(&mut *writer, &SpooledTick { ticker, received_at, pricing })?;
+            writer.write_all(b"\n")?;
+            *lines += 1;
+        }
+        // On disk before the ticks count as spooled, a crash loses nothing the database didn't take
+        if let Some((_, writer, _)) = self.writer.as_mut() {
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.close_writer()?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let file = OpenOptions::new().create(true).append(true).open(self.segment_path(id))?;
+        self.segments.push_back(id);
+        self.writer = Some((id, BufWriter::new(file), 0));
+
+        while self.segments.len() > self.max_segments.max(1) {
+            if let Some(oldest) = self.segments.pop_front() {
+                eprintln!("Tick spool full, dropping segment {}", oldest);
+                fs::remove_file(self.segment_path(oldest))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn close_writer(&mut self) -> io::Result<()> {
+        if let Some((_, mut writer, _)) = self.writer.take() {
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:010}.jsonl", id))
+    }
+
+    // Takes the oldest segment out for replay, closing it first when it is still being written.
+    // While it is out, rotation can't drop it and new ticks go to a newer segment.
+    pub fn take_oldest(&mut self) -> io::Result<Option<SpoolSegment>> {
+        let Some(&id) = self.segments.front() else {
+            return Ok(None);
+        };
+        if self.writer.as_ref().is_some_and(|(open, _, _)| *open == id) {
+            self.close_writer()?;
+        }
+        self.segments.pop_front();
+        Ok(Some(SpoolSegment {
+            id,
+            path: self.segment_path(id),
+        }))
+    }
+
+    // A segment whose replay stopped goes back as the oldest
+    pub fn put_back(&mut self, segment: SpoolSegment) {
+        self.segments.push_front(segment.id);
+    }
+}
+
+impl SpoolSegment {
+    // Replays the segment into the database and deletes it, returning how many ticks were stored
+    // and whether it is done. When an insert fails, the ticks not yet stored are written back so
+    // they are retried on the next run.
+    pub async fn replay(&self, pool: &PgPool) -> io::Result<(usize, bool)> {
+        let lines: Vec<String> = BufReader::new(File::open(&self.path)?).lines().collect::<Result<_, _>>()?;
+
+        let mut replayed = 0;
+        for (index, line) in lines.iter().enumerate() {
+            let Ok(tick) = serde_json::from_str::<SpooledTick>(line) else {
+                eprintln!("Skipping unreadable spooled tick: {}", line);
+                continue;
+            };
+            if let Err(e) = db::save_ticker_data(pool, &tick.ticker, tick.received_at, &tick.pricing).await {
+                eprintln!("Tick spool replay stopped: {:?}", e);
+                fs::write(&self.path, lines[index..].join("\n") + "\n")?;
+                return Ok((replayed, false));
+            }
+            replayed += 1;
+        }
+
+        fs::remove_file(&self.path)?;
+        Ok((replayed, true))
+    }
+}
+
+// Drain spooled ticks back into the database once it is reachable again
+pub async fn run_spool_replay(state: Arc<AppState>) {
+    let mut ticker = state.simulation.interval(Duration::from_secs(env_or("TICK_SPOOL_REPLAY_SECS", 5)));
+    loop {
+        ticker.tick().await;
+
+        // Wait for the breaker to close instead of probing a database that is known to be down
+        if state.db_breaker.lock().await.is_open() {
+            continue;
+        }
+
+        // The spool is only held to take a segment out or put it back, the feed keeps spooling
+        // while the segment is replayed
+        loop {
+            let segment = match state.spool.lock().await.take_oldest() {
+                Ok(Some(segment)) => segment,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Error replaying tick spool: {:?}", e);
+                    break;
+                }
+            };
+            match segment.replay(&state.pool).await {
+                Ok((count, true)) => println!("Replayed {} spooled ticks", count),
+                Ok((count, false)) => {
+                    if count > 0 {
+                        println!("Replayed {} spooled ticks", count);
+                    }
+                    state.spool.lock().await.put_back(segment);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Error replaying tick spool: {:?}", e);
+                    state.spool.lock().await.put_back(segment);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::candles::{self, DEFAULT_CANDLE_LIMIT, MAX_CANDLE_LIMIT};
+use crate::db;
+use crate::models::{Candle, OrderRequest, OrderType, Side, SpreadDefinition, SpreadUpdate, TickerUpdate, TimeInForce};
+use crate::state::AppState;
+
+pub fn validate(definition: &mut SpreadDefinition) -> Result<(), String> {
+    definition.name = definition.name.trim().to_string();
+    definition.long_symbol = definition.long_symbol.trim().to_uppercase();
+    definition.short_symbol = definition.short_symbol.trim().to_uppercase();
+    if definition.name.is_empty() {
+        return Err("Spread name is required".to_string());
+    }
+    if definition.long_symbol.is_empty() || definition.short_symbol.is_empty() {
+        return Err("A spread needs a long and a short symbol".to_string());
+    }
+    if definition.long_symbol == definition.short_symbol {
+        return Err("The legs of a spread must be different symbols".to_string());
+    }
+    if !(definition.ratio.is_finite() && definition.ratio > 0.0) {
+        return Err("The spread ratio must be a positive number".to_string());
+    }
+    Ok(())
+}
+
+pub async fn definition(state: &AppState, account_id: &str, name: &str) -> Result<SpreadDefinition, String> {
+    db::get_spreads(&state.pool, account_id)
+        .await
+        .map_err(|e| format!("Error loading spreads: {}", e))?
+        .into_iter()
+        .find(|spread| spread.name == name.trim())
+        .ok_or_else(|| format!("Unknown spread {}", name))
+}
+
+// Candles of the spread, oldest first. Legs are stored per minute, so shorter candles don't exist.
+pub async fn candles(
+    state: &AppState,
+    spread: &SpreadDefinition,
+    interval: &str,
+    limit: Option<i64>,
+) -> Result<Vec<Candle>, String> {
+    let seconds = match candles::interval_seconds(interval) {
+        Some(seconds) if seconds >= 60 => seconds,
+        Some(_) => return Err(format!("Spreads are priced per minute, {} candles are too short", interval)),
+        None => return Err(format!("Unsupported candle interval {}", interval)),
+    };
+    let limit = limit.unwrap_or(DEFAULT_CANDLE_LIMIT).clamp(1, MAX_CANDLE_LIMIT);
+    let mut candles = db::get_spread_candles(&state.pool, spread, seconds, limit)
+        .await
+        .map_err(|e| format!("Error loading spread candles: {}", e))?;
+    candles.reverse();
+    Ok(candles)
+}
+
+// The long and the short leg of `quantity` spread units as market orders
+pub fn legs(spread: &SpreadDefinition, side: Side, quantity: f64, client_order_id: Option<&str>) -> Vec<OrderRequest> {
+    let leg = |symbol: &str, side: Side, quantity: f64, suffix: &str| OrderRequest {
+        symbol: symbol.to_string(),
+        side,
+        order_type: OrderType::Market,
+        price: None,
+        quantity,
+        time_in_force: TimeInForce::Gtc,
+        client_order_id: client_order_id.map(|id| format!("{}-{}", id, suffix)),
+    };
+    vec![
+        leg(&spread.long_symbol, side, quantity, "long"),
+        leg(&spread.short_symbol, side.opposite(), quantity * spread.ratio, "short"),
+    ]
+}
+
+// A subscribed spread, priced from the latest price of each leg
+pub struct SpreadQuote {
+    definition: SpreadDefinition,
+    long_price: Option<f64>,
+    short_price: Option<f64>,
+}
+
+impl SpreadQuote {
+    pub fn new(definition: SpreadDefinition, latest_price: impl Fn(&str) -> Option<f64>) -> Self {
+        SpreadQuote {
+            long_price: latest_price(&definition.long_symbol),
+            short_price: latest_price(&definition.short_symbol),
+            definition,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    pub fn snapshot(&self) -> SpreadUpdate {
+        SpreadUpdate {
+            name: self.definition.name.clone(),
+            price: self
+                .long_price
+                .zip(self.short_price)
+                .map(|(long, short)| long - self.definition.ratio * short),
+            long_price: self.long_price,
+            short_price: self.short_price,
+        }
+    }
+
+    // Reprice on a leg's update, other symbols are ignored
+    pub fn on_ticker(&mut self, update: &TickerUpdate) -> Option<SpreadUpdate> {
+        if update.symbol == self.definition.long_symbol {
+            self.long_price = Some(update.price);
+        } else if update.symbol == self.definition.short_symbol {
+            self.short_price = Some(update.price);
+        } else {
+            return None;
+        }
+        Some(self.snapshot())
+    }
+}
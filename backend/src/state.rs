@@ -0,0 +1,360 @@
+use crate::accounts::TemplateBook;
+use crate::analytics::AnalyticsConfig;
+use crate::archive::IngestArchive;
+use crate::auth::Revocation;
+use crate::bots::BotBook;
+use crate::chaos::ChaosState;
+use crate::competitions::CompetitionBook;
+use crate::config::{config_file, AllowedOrigins, LiveConfig, UpdateIntervals};
+use crate::conversion::{self, QuoteAssets};
+use crate::depeg::DepegMonitor;
+use crate::depth::DepthBooks;
+use crate::drawdown::DrawdownTracker;
+use crate::engine::MatchingEngine;
+use crate::feature_flags::FeatureFlags;
+use crate::feed::FeedInterest;
+use crate::execution::binance::{BinanceCredentials, BinanceEndpoints, BinanceFuturesBackend, VenueSettlement};
+use crate::execution::internal::InternalBackend;
+use crate::execution::ExecutionBackend;
+use crate::grid::GridBook;
+use crate::ingest_metrics::IngestMetrics;
+use crate::key_levels::KeyLevelBook;
+use crate::latency::LatencyConfig;
+use crate::ledger::Ledger;
+use crate::notify::Notifications;
+use crate::object_store::ObjectStore;
+use crate::db;
+use crate::models::{
+    Alert, Conversion, DepegStatus, ExportUpdate, Fill, KeyLevels, ListingEvent, MaintenanceWindow, MarketTrade, PaginatedResponse,
+    PortfolioReport, SettingChange, TickerQuery, TickerUpdate, UsdPricing,
+};
+use crate::portfolio::PortfolioBook;
+use crate::rate_limit::{RateLimitConfig, RateLimiter};
+use crate::resilience::{self, CircuitBreaker};
+use crate::rules::RuleBook;
+use crate::simulation::Simulation;
+use crate::spool::TickSpool;
+use crate::tape::TradeTape;
+use crate::teams::TeamBook;
+use crate::tenants::TenantBook;
+use crate::tick_filter::{TickFilter, TickFilterConfig};
+use crate::updates::UpdateLog;
+use crate::usage::UsageCounters;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+
+// Capacity of the fill fan-out channel, slow connections that lag further behind miss fills
+const FILL_CHANNEL_CAPACITY: usize = 1024;
+const ALERT_CHANNEL_CAPACITY: usize = 256;
+// A miniTicker batch carries a few hundred symbols
+const TICKER_CHANNEL_CAPACITY: usize = 4096;
+const SETTINGS_CHANNEL_CAPACITY: usize = 256;
+const EXPORT_CHANNEL_CAPACITY: usize = 256;
+const DEPEG_CHANNEL_CAPACITY: usize = 256;
+const LISTING_CHANNEL_CAPACITY: usize = 256;
+const MAINTENANCE_CHANNEL_CAPACITY: usize = 16;
+const KEY_LEVEL_CHANNEL_CAPACITY: usize = 256;
+const REVOCATION_CHANNEL_CAPACITY: usize = 256;
+// Busy symbols trade dozens of times a second
+const TRADE_CHANNEL_CAPACITY: usize = 4096;
+// Degraded mode keeps the first pages of each listing, deeper pages fail until the database is back
+const CACHED_TICKER_PAGES: i64 = 5;
+const MAX_CACHED_TICKER_PAGES: usize = 64;
+const MAX_TICKERS_PER_PAGE: i64 = 100;
+
+// Shared state handed to the Binance listener and every client connection
+pub struct AppState {
+    pub pool: PgPool,
+    pub engine: Arc<Mutex<MatchingEngine>>,
+    pub backend: Box<dyn ExecutionBackend>,
+    pub portfolios: Arc<Mutex<PortfolioBook>>, // Shared with an exchange backend, which settles its own fills
+    pub rate_limiter: Mutex<RateLimiter>,
+    pub latency: LatencyConfig,
+    pub simulation: Simulation, // Clock and randomness, reproducible when SIMULATION_SEED is set
+    pub fills: broadcast::Sender<Fill>,
+    pub alerts: broadcast::Sender<Alert>,
+    pub notifications: Arc<Notifications>,
+    pub tickers: broadcast::Sender<TickerUpdate>,
+    pub settings: broadcast::Sender<SettingChange>,
+    pub exports: broadcast::Sender<ExportUpdate>,
+    pub depeg_updates: broadcast::Sender<DepegStatus>,
+    pub listings: broadcast::Sender<ListingEvent>,
+    pub maintenance_notices: broadcast::Sender<Option<MaintenanceWindow>>, // None when maintenance ended
+    pub trades: broadcast::Sender<MarketTrade>, // Exchange trades of followed symbols, for time and sales
+    pub tape: TradeTape,
+    pub key_level_updates: broadcast::Sender<KeyLevels>,
+    pub revocations: broadcast::Sender<Revocation>, // Logins ended while connections hold them
+    pub account_templates: Mutex<TemplateBook>,
+    pub competitions: Mutex<CompetitionBook>,
+    pub teams: Mutex<TeamBook>,
+    pub tenants: Mutex<TenantBook>,
+    pub trading_rules: Mutex<RuleBook>,
+    pub grid_bots: Mutex<GridBook>,
+    pub bots: Mutex<BotBook>,
+    pub drawdowns: Mutex<DrawdownTracker>,
+    pub depeg: Mutex<DepegMonitor>,
+    pub depth: Mutex<DepthBooks>, // Latest exchange order book of each followed symbol
+    pub key_levels: Mutex<KeyLevelBook>,
+    pub chaos: Mutex<ChaosState>,
+    pub maintenance: Mutex<Option<MaintenanceWindow>>, // Announced read-only window
+    pub feature_flags: Mutex<FeatureFlags>,
+    pub tick_filter: Mutex<TickFilter>,
+    pub quote_assets: std::sync::RwLock<QuoteAssets>, // Rebuilt when a reload changes QUOTE_ASSETS
+    pub ingest_metrics: Mutex<IngestMetrics>,
+    pub spool: Mutex<TickSpool>,
+    pub db_breaker: Mutex<CircuitBreaker>,
+    pub archive: Option<IngestArchive>,
+    pub analytics: Option<AnalyticsConfig>, // DuckDB over the Parquet dataset, when there is one
+    pub object_store: Option<Arc<ObjectStore>>, // Bucket for archives and exports, when OBJECT_STORE_BUCKET is set
+    pub updates: UpdateLog, // Recent pushes for long-polling REST clients
+    pub usage: UsageCounters,
+    pub feed_interest: FeedInterest, // Symbols streamed live, they pick the per-symbol upstream streams
+    pub ledger: Arc<Ledger>,         // Account event logs, shared with the internal execution backend
+    sessions: Mutex<HashMap<String, usize>>, // Open connections per account
+    ticker_pages: Mutex<HashMap<(i64, i64, TickerQuery), PaginatedResponse>>, // Last good page, served while degraded
+    pub admin_token: Option<String>, // Admin commands are disabled when ADMIN_TOKEN is unset
+    pub allowed_origins: AllowedOrigins,
+    pub update_intervals: UpdateIntervals,
+    pub live_config: LiveConfig, // Reloaded settings over the startup environment
+    pub token_secret: Vec<u8>, // Signs access tokens, SESSION_SECRET keeps them valid across restarts
+}
+
+impl AppState {
+    pub fn new(pool: PgPool) -> Self {
+        let (fills, _) = broadcast::channel(FILL_CHANNEL_CAPACITY);
+        let (alerts, _) = broadcast::channel(ALERT_CHANNEL_CAPACITY);
+        let (tickers, _) = broadcast::channel(TICKER_CHANNEL_CAPACITY);
+        let (settings, _) = broadcast::channel(SETTINGS_CHANNEL_CAPACITY);
+        let (exports, _) = broadcast::channel(EXPORT_CHANNEL_CAPACITY);
+        let (depeg_updates, _) = broadcast::channel(DEPEG_CHANNEL_CAPACITY);
+        let (listings, _) = broadcast::channel(LISTING_CHANNEL_CAPACITY);
+        let (maintenance_notices, _) = broadcast::channel(MAINTENANCE_CHANNEL_CAPACITY);
+        let (trades, _) = broadcast::channel(TRADE_CHANNEL_CAPACITY);
+        let (key_level_updates, _) = broadcast::channel(KEY_LEVEL_CHANNEL_CAPACITY);
+        let (revocations, _) = broadcast::channel(REVOCATION_CHANNEL_CAPACITY);
+        let simulation = Simulation::from_env();
+        if let Some(seed) = simulation.seed() {
+            println!("Deterministic simulation with seed {}, time follows market data", seed);
+        }
+        let engine = Arc::new(Mutex::new(MatchingEngine::with_clock(simulation.clock())));
+        let ledger = Arc::new(Ledger::default());
+        let portfolios = Arc::new(Mutex::new(PortfolioBook::new()));
+        let backend = execution_backend(&pool, &engine, &portfolios, &ledger, &fills);
+        println!("Using {} execution backend", backend.name());
+        let object_store = ObjectStore::from_env().map(Arc::new);
+        let archive = IngestArchive::from_env(&pool, object_store.clone());
+        let tape = TradeTape::new(&pool);
+
+        AppState {
+            pool,
+            engine,
+            backend,
+            portfolios,
+            rate_limiter: Mutex::new(RateLimiter::new(RateLimitConfig::from_env())),
+            latency: LatencyConfig::from_env(),
+            simulation,
+            fills,
+            alerts,
+            notifications: Arc::new(Notifications::from_env()),
+            tickers,
+            settings,
+            exports,
+            depeg_updates,
+            listings,
+            maintenance_notices,
+            trades,
+            tape,
+            key_level_updates,
+            revocations,
+            account_templates: Mutex::new(TemplateBook::default()),
+            competitions: Mutex::new(CompetitionBook::default()),
+            teams: Mutex::new(TeamBook::default()),
+            tenants: Mutex::new(TenantBook::default()),
+            trading_rules: Mutex::new(RuleBook::default()),
+            grid_bots: Mutex::new(GridBook::default()),
+            bots: Mutex::new(BotBook::default()),
+            drawdowns: Mutex::new(DrawdownTracker::default()),
+            depeg: Mutex::new(DepegMonitor::from_env()),
+            depth: Mutex::new(DepthBooks::default()),
+            key_levels: Mutex::new(KeyLevelBook::default()),
+            chaos: Mutex::new(ChaosState::default()),
+            maintenance: Mutex::new(None),
+            feature_flags: Mutex::new(FeatureFlags::default()),
+            tick_filter: Mutex::new(TickFilter::new(TickFilterConfig::from_env())),
+            quote_assets: std::sync::RwLock::new(QuoteAssets::from_env()),
+            ingest_metrics: Mutex::new(IngestMetrics::from_env()),
+            spool: Mutex::new(TickSpool::from_env().expect("Failed to open the tick spool directory")),
+            db_breaker: Mutex::new(CircuitBreaker::from_env()),
+            archive,
+            object_store,
+            analytics: AnalyticsConfig::from_env(),
+            updates: UpdateLog::from_env(),
+            usage: UsageCounters::default(),
+            feed_interest: FeedInterest::default(),
+            ledger,
+            sessions: Mutex::new(HashMap::new()),
+            ticker_pages: Mutex::new(HashMap::new()),
+            admin_token: env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty()),
+            allowed_origins: AllowedOrigins::from_env(),
+            update_intervals: UpdateIntervals::from_env(),
+            live_config: LiveConfig::from_file(&config_file()),
+            token_secret: env::var("SESSION_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty())
+                .map(String::into_bytes)
+                .unwrap_or_else(|| {
+                    println!("SESSION_SECRET is unset, access tokens will not survive a restart");
+                    rand::random::<[u8; 32]>().to_vec()
+                }),
+        }
+    }
+
+    pub fn is_admin(&self, token: &str) -> bool {
+        self.admin_token.as_deref() == Some(token)
+    }
+
+    pub async fn session_opened(&self, account_id: &str) {
+        *self.sessions.lock().await.entry(account_id.to_string()).or_default() += 1;
+    }
+
+    pub async fn session_closed(&self, account_id: &str) {
+        let mut sessions = self.sessions.lock().await;
+        if let Some(count) = sessions.get_mut(account_id) {
+            *count -= 1;
+            if *count == 0 {
+                sessions.remove(account_id);
+            }
+        }
+    }
+
+    pub async fn is_online(&self, account_id: &str) -> bool {
+        self.sessions.lock().await.contains_key(account_id)
+    }
+
+    // Drops every order and group of the account, stored ones included, returns how many orders went
+    pub async fn close_account_orders(&self, account_id: &str) -> usize {
+        let (dropped, changes, turn) = {
+            let mut engine = self.engine.lock().await;
+            (engine.close_account(account_id), engine.take_changes(), self.ledger.turn().await)
+        };
+        if let Err(e) = turn.commit(&self.pool, &changes, &[], Vec::new()).await {
+            eprintln!("Error deleting stored orders of {}: {:?}", account_id, e);
+        }
+        dropped
+    }
+
+    // Convert between currencies at the latest prices, also used to value balances held in other assets
+    pub async fn convert(&self, from: &str, to: &str, amount: f64) -> Result<Conversion, String> {
+        let engine = self.engine.lock().await;
+        conversion::convert(from, to, amount, |symbol| engine.last_price(symbol))
+    }
+
+    // Quote asset and USD price of a tick, stored with it
+    pub async fn usd_pricing(&self, symbol: &str, price: f64) -> UsdPricing {
+        let engine = self.engine.lock().await;
+        let quote_assets = self.quote_assets.read().unwrap();
+        quote_assets.usd_pricing(symbol, price, |pair| engine.last_price(pair))
+    }
+
+    // Ticker page through the circuit breaker, falling back to the last good copy flagged as degraded
+    pub async fn ticker_page(&self, page: i64, per_page: i64, query: &TickerQuery) -> Option<PaginatedResponse> {
+        let page = page.max(1);
+        let per_page = per_page.clamp(1, MAX_TICKERS_PER_PAGE);
+        let key = (page, per_page, query.clone());
+        match resilience::call(&self.db_breaker, || db::get_latest_tickers(&self.pool, page, per_page, query)).await {
+            Ok(tickers) => {
+                let mut pages = self.ticker_pages.lock().await;
+                if page <= CACHED_TICKER_PAGES && (pages.len() < MAX_CACHED_TICKER_PAGES || pages.contains_key(&key)) {
+                    pages.insert(key, tickers.clone());
+                }
+                Some(tickers)
+            }
+            Err(e) => {
+                eprintln!("Error loading tickers: {}", e);
+                let mut cached = self.ticker_pages.lock().await.get(&key).cloned()?;
+                cached.degraded = true;
+                Some(cached)
+            }
+        }
+    }
+
+    // Cash, positions and drawdown of an account valued at the latest prices
+    pub async fn portfolio_report(&self, account_id: &str) -> PortfolioReport {
+        let mut report = {
+            let engine = self.engine.lock().await;
+            let portfolios = self.portfolios.lock().await;
+            portfolios.report(account_id, |symbol| engine.last_price(symbol))
+        };
+        if let Some((peak_equity, drawdown)) = self.drawdowns.lock().await.get(account_id) {
+            report.peak_equity = Some(peak_equity);
+            report.drawdown = Some(drawdown);
+        }
+        report
+    }
+
+    pub async fn all_portfolio_reports(&self) -> Vec<PortfolioReport> {
+        let engine = self.engine.lock().await;
+        let portfolios = self.portfolios.lock().await;
+        portfolios
+            .account_ids()
+            .into_iter()
+            .map(|account_id| portfolios.report(&account_id, |symbol| engine.last_price(symbol)))
+            .collect()
+    }
+}
+
+// Pick the execution backend from EXECUTION_BACKEND, defaulting to the internal matching engine
+fn execution_backend(
+    pool: &PgPool,
+    engine: &Arc<Mutex<MatchingEngine>>,
+    portfolios: &Arc<Mutex<PortfolioBook>>,
+    ledger: &Arc<Ledger>,
+    fills: &broadcast::Sender<Fill>,
+) -> Box<dyn ExecutionBackend> {
+    let settlement = || VenueSettlement {
+        pool: pool.clone(),
+        portfolios: Arc::clone(portfolios),
+        ledger: Arc::clone(ledger),
+        fills: fills.clone(),
+    };
+    match env::var("EXECUTION_BACKEND").as_deref() {
+        Ok("binance_testnet") => Box::new(BinanceFuturesBackend::new(
+            "binance_testnet",
+            BinanceEndpoints::futures_testnet(),
+            BinanceCredentials {
+                api_key: env::var("BINANCE_TESTNET_API_KEY")
+                    .expect("BINANCE_TESTNET_API_KEY must be set"),
+                api_secret: env::var("BINANCE_TESTNET_API_SECRET")
+                    .expect("BINANCE_TESTNET_API_SECRET must be set"),
+            },
+            settlement(),
+        )),
+        #[cfg(feature = "live-trading")]
+        Ok("binance_live") => {
+            println!("WARNING: live trading is enabled, orders are sent to the real Binance account");
+            Box::new(BinanceFuturesBackend::new(
+                "binance_live",
+                BinanceEndpoints::futures_live(),
+                BinanceCredentials {
+                    api_key: env::var("BINANCE_API_KEY").expect("BINANCE_API_KEY must be set"),
+                    api_secret: env::var("BINANCE_API_SECRET")
+                        .expect("BINANCE_API_SECRET must be set"),
+                },
+                settlement(),
+            ))
+        }
+        #[cfg(not(feature = "live-trading"))]
+        Ok("binance_live") => {
+            panic!("EXECUTION_BACKEND=binance_live requires building with --features live-trading")
+        }
+        Ok("internal") | Err(_) => Box::new(InternalBackend::new(Arc::clone(engine), Arc::clone(ledger), pool.clone())),
+        // A typo must not quietly send orders somewhere other than intended
+        Ok(other) => panic!(
+            "Unknown EXECUTION_BACKEND {:?}, expected internal, binance_testnet or binance_live",
+            other
+        ),
+    }
+}
@@ -0,0 +1,79 @@
+use crate::auth::Session;
+use crate::db;
+use crate::models::{StorageKind, UserStorage};
+use crate::state::AppState;
+use crate::tenants;
+
+const MB: i64 = 1024 * 1024;
+
+// Per-user quotas, read on every check so a config reload applies them right away
+fn max_bytes(state: &AppState) -> i64 {
+    state.live_config.value_or("STORAGE_MAX_MB_PER_USER", 100i64) * MB
+}
+
+fn max_items(state: &AppState, kind: StorageKind) -> i64 {
+    match kind {
+        StorageKind::Alert => state.live_config.value_or("STORAGE_MAX_ALERTS_PER_USER", 50),
+        StorageKind::Export => state.live_config.value_or("STORAGE_MAX_EXPORTS_PER_USER", 20),
+    }
+}
+
+async fn usage(state: &AppState, user_id: &str) -> Result<UserStorage, String> {
+    db::get_user_storage(&state.pool, Some(user_id), 1)
+        .await
+        .map(|users| users.into_iter().next().unwrap_or_default())
+        .map_err(|e| format!("Error checking your storage: {}", e))
+}
+
+// Refuses a new item, or a bigger copy of `item_id`, that would take the signed-in user or their
+// tenant past a quota. Anonymous sessions store nothing attributable and are not limited.
+pub async fn check(
+    state: &AppState,
+    session: &Session,
+    kind: StorageKind,
+    item_id: &str,
+    bytes: usize,
+) -> Result<(), String> {
+    let Some(user_id) = &session.user_id else {
+        return Ok(());
+    };
+    let replaced = db::get_storage_item(&state.pool, kind, item_id)
+        .await
+        .map_err(|e| format!("Error checking your storage: {}", e))?;
+    let usage = usage(state, user_id).await?;
+
+    let (count, label) = match kind {
+        StorageKind::Alert => (usage.alerts, "alerts"),
+        StorageKind::Export => (usage.exports, "exports"),
+    };
+    let max = max_items(state, kind);
+    if replaced.is_none() && count >= max {
+        let hint = match kind {
+            StorageKind::Alert => "alerts are kept per account, change an existing one instead",
+            StorageKind::Export => "older exports are deleted once they expire",
+        };
+        return Err(format!("You already store {} {}, the most allowed, {}", count, label, hint));
+    }
+    let added = bytes as i64 - replaced.unwrap_or(0);
+    if usage.total_bytes + added > max_bytes(state) {
+        return Err(format!(
+            "This would take your stored data to {:.1} MB, past your {} MB quota",
+            (usage.total_bytes + added) as f64 / MB as f64,
+            max_bytes(state) / MB
+        ));
+    }
+    if let Some(tenant_id) = &session.tenant_id {
+        tenants::check_storage(state, tenant_id, bytes, replaced.unwrap_or(0) as usize).await?;
+    }
+    Ok(())
+}
+
+// Attributes a stored item to the user that created it, anonymous items are not tracked
+pub async fn record(state: &AppState, user_id: Option<&str>, kind: StorageKind, item_id: &str, bytes: u64) {
+    let Some(user_id) = user_id else {
+        return;
+    };
+    if let Err(e) = db::save_storage_item(&state.pool, kind, item_id, user_id, bytes as i64).await {
+        eprintln!("Error recording {} {} of {}: {:?}", kind.name(), item_id, user_id, e);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::config::env_or;
+use crate::db;
+use crate::models::{MarketTrade, ServerMessage, TickDirection, TradePrint};
+use crate::state::AppState;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+const TAPE_QUEUE: usize = 10_000;
+const TAPE_BATCH: usize = 500;
+const DEFAULT_HISTORY_LIMIT: i64 = 100;
+
+// Time between two pushes of a subscribed time and sales, TAPE_INTERVAL_MS
+pub fn interval() -> Duration {
+    Duration::from_millis(env_or("TAPE_INTERVAL_MS", 250u64).max(50))
+}
+
+// Hands exchange trades to a background writer that stores them in batches for scroll-back,
+// TRADES_RETENTION_HOURS drops them again
+pub struct TradeTape {
+    sender: mpsc::Sender<MarketTrade>,
+}
+
+impl TradeTape {
+    pub fn new(pool: &PgPool) -> Self {
+        let (sender, receiver) = mpsc::channel(TAPE_QUEUE);
+        tokio::spawn(run_writer(pool.clone(), receiver));
+        TradeTape { sender }
+    }
+
+    pub fn record(&self, trade: MarketTrade) {
+        if self.sender.try_send(trade).is_err() {
+            eprintln!("Trade tape queue full, trade dropped");
+        }
+    }
+}
+
+async fn run_writer(pool: PgPool, mut receiver: mpsc::Receiver<MarketTrade>) {
+    let mut batch = Vec::with_capacity(TAPE_BATCH);
+    while let Some(trade) = receiver.recv().await {
+        batch.push(trade);
+        // Take whatever queued up during the last write along
+        while batch.len() < TAPE_BATCH {
+            match receiver.try_recv() {
+                Ok(trade) => batch.push(trade),
+                Err(_) => break,
+            }
+        }
+        if let Err(e) = db::save_market_trades(&pool, &batch).await {
+            eprintln!("Error saving {} trades: {:?}", batch.len(), e);
+        }
+        batch.clear();
+    }
+}
+
+// Prints of trades given oldest first, returned newest first. `previous` is the price before the
+// first trade, its print is unchanged without one.
+pub fn prints(
+    trades: impl IntoIterator<Item = MarketTrade>,
+    aggregate: bool,
+    previous: Option<f64>,
+) -> Vec<TradePrint> {
+    let mut prints: Vec<TradePrint> = Vec::new();
+    let mut previous = previous;
+    for trade in trades {
+        let same_print = |last: &&mut TradePrint| aggregate && last.price == trade.price && last.side == trade.side;
+        if let Some(last) = prints.last_mut().filter(same_print) {
+            last.last_trade_id = trade.trade_id;
+            last.quantity += trade.quantity;
+            last.count += 1;
+            last.time = trade.time;
+            continue;
+        }
+        let tick = match previous {
+            Some(price) if trade.price > price => TickDirection::Up,
+            Some(price) if trade.price < price => TickDirection::Down,
+            _ => TickDirection::Unchanged,
+        };
+        previous = Some(trade.price);
+        prints.push(TradePrint {
+            first_trade_id: trade.trade_id,
+            last_trade_id: trade.trade_id,
+            price: trade.price,
+            quantity: trade.quantity,
+            count: 1,
+            side: trade.side,
+            tick,
+            time: trade.time,
+        });
+    }
+    prints.reverse();
+    prints
+}
+
+// Answers a subscription with the trades still in memory
+pub async fn subscribe(state: &AppState, symbol: &str, aggregate: bool) -> ServerMessage {
+    let symbol = symbol.trim().to_uppercase();
+    let recent = state.depth.lock().await.trades(&symbol);
+    ServerMessage::TradesSubscribed {
+        prints: prints(recent.into_iter().rev(), aggregate, None),
+        symbol,
+        aggregate,
+    }
+}
+
+pub async fn history(
+    state: &AppState,
+    symbol: &str,
+    before: Option<i64>,
+    limit: Option<i64>,
+    aggregate: bool,
+) -> Result<ServerMessage, String> {
+    let symbol = symbol.trim().to_uppercase();
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, 1000);
+    // One trade more than asked for sets the tick of the oldest print
+    let mut trades = db::get_market_trades(&state.pool, &symbol, before, limit + 1)
+        .await
+        .map_err(|e| format!("Error loading trades: {}", e))?;
+    let previous = if trades.len() as i64 > limit {
+        trades.pop().map(|trade| trade.price)
+    } else {
+        None
+    };
+    Ok(ServerMessage::TradeHistory {
+        prints: prints(trades.into_iter().rev(), aggregate, previous),
+        symbol,
+    })
+}
+
+struct TapeSubscription {
+    aggregate: bool,
+    pending: Vec<MarketTrade>,
+    last_price: Option<f64>,
+}
+
+// Time and sales streamed to one connection. Trades of its symbols are held and sent as prints
+// every TAPE_INTERVAL_MS, aggregation merges trades within one push.
+#[derive(Default)]
+pub struct TapeStream {
+    symbols: HashMap<String, TapeSubscription>,
+}
+
+impl TapeStream {
+    pub fn subscribe(&mut self, symbol: String, aggregate: bool, last_price: Option<f64>) {
+        let subscription = TapeSubscription {
+            aggregate,
+            pending: Vec::new(),
+            last_price,
+        };
+        self.symbols.insert(symbol, subscription);
+    }
+
+    pub fn unsubscribe(&mut self, symbol: &str) {
+        self.symbols.remove(symbol);
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &String> {
+        self.symbols.keys()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn push(&mut self, trade: MarketTrade) {
+        if let Some(subscription) = self.symbols.get_mut(&trade.symbol) {
+            subscription.pending.push(trade);
+        }
+    }
+
+    pub fn flush(&mut self) -> Vec<ServerMessage> {
+        let mut messages = Vec::new();
+        for (symbol, subscription) in &mut self.symbols {
+            if subscription.pending.is_empty() {
+                continue;
+            }
+            let prints = prints(subscription.pending.drain(..), subscription.aggregate, subscription.last_price);
+            subscription.last_price = prints.first().map(|print| print.price);
+            messages.push(ServerMessage::Trades {
+                symbol: symbol.clone(),
+                prints,
+            });
+        }
+        messages
+    }
+}
@@ -0,0 +1,327 @@
+use crate::alerts;
+use crate::auth::{self, Session};
+use crate::config::env_or;
+use crate::db;
+use crate::engine::now_millis;
+use crate::models::{
+    Alert, ApiScope, ClientMessage, MemberPnl, OrderProposal, OrderRequest, Role, ServerMessage, Side, Team,
+    TeamMember, TeamRole,
+};
+use crate::state::AppState;
+use std::collections::HashMap;
+
+// Team accounts and their members, consulted on every message that targets an account
+#[derive(Default)]
+pub struct TeamBook {
+    teams: HashMap<String, Team>, // account_id -> team
+}
+
+impl TeamBook {
+    pub fn load(&mut self, teams: Vec<Team>) {
+        self.teams = teams.into_iter().map(|team| (team.account_id.clone(), team)).collect();
+    }
+
+    pub fn set(&mut self, team: Team) {
+        self.teams.insert(team.account_id.clone(), team);
+    }
+
+    pub fn get(&self, account_id: &str) -> Option<&Team> {
+        self.teams.get(account_id)
+    }
+
+    // Orders without a known price count as above any threshold
+    pub fn requires_approval(&self, account_id: &str, notional: Option<f64>) -> bool {
+        self.teams.get(account_id).is_some_and(|team| {
+            team.approval_mode && !notional.is_some_and(|notional| notional <= team.approval_min_notional)
+        })
+    }
+}
+
+impl Team {
+    pub fn role_of(&self, user_id: &str) -> Option<TeamRole> {
+        self.members
+            .iter()
+            .find(|member| member.user_id == user_id)
+            .map(|member| member.role)
+    }
+}
+
+// Only members may act on a team account, admins can inspect it but not trade on it
+pub async fn authorize(
+    state: &AppState,
+    session: &Session,
+    account_id: &str,
+    msg: &ClientMessage,
+) -> Result<(), String> {
+    let teams = state.teams.lock().await;
+    let Some(team) = teams.get(account_id) else {
+        return Ok(());
+    };
+    if session.role == Role::Admin && auth::required_scope(msg) != Some(ApiScope::Trade) {
+        return Ok(());
+    }
+    match &session.user_id {
+        Some(user_id) if team.role_of(user_id).is_some() => Ok(()),
+        _ => Err(format!("Only members of {} can use its account", team.name)),
+    }
+}
+
+async fn team_for_owner(state: &AppState, session: &Session, account_id: &str) -> Result<Team, String> {
+    let team = state
+        .teams
+        .lock()
+        .await
+        .get(account_id)
+        .cloned()
+        .ok_or_else(|| format!("{} is not a team account", account_id))?;
+    let is_owner = session
+        .user_id
+        .as_deref()
+        .and_then(|user_id| team.role_of(user_id))
+        == Some(TeamRole::Owner);
+    if !is_owner && session.role != Role::Admin {
+        return Err(format!("Only owners of {} can change the team", team.name));
+    }
+    Ok(team)
+}
+
+pub async fn create_team(
+    state: &AppState,
+    session: &Session,
+    account_id: &str,
+    name: String,
+    approval_mode: bool,
+) -> Result<ServerMessage, String> {
+    let Some(user_id) = &session.user_id else {
+        return Err("Authenticate before creating a team".to_string());
+    };
+    let created = db::create_account(&state.pool, account_id, None)
+        .await
+        .map_err(|e| format!("Error creating team account: {}", e))?;
+    if !created {
+        return Err(format!("Account {} already exists", account_id));
+    }
+
+    let team = Team {
+        account_id: account_id.to_string(),
+        name,
+        approval_mode,
+        approval_min_notional: 0.0,
+        members: vec![TeamMember {
+            user_id: user_id.clone(),
+            role: TeamRole::Owner,
+        }],
+    };
+    save(state, team).await
+}
+
+pub async fn set_member(
+    state: &AppState,
+    session: &Session,
+    account_id: &str,
+    user_id: &str,
+    role: Option<TeamRole>,
+) -> Result<ServerMessage, String> {
+    let mut team = team_for_owner(state, session, account_id).await?;
+    team.members.retain(|member| member.user_id != user_id);
+    if let Some(role) = role {
+        team.members.push(TeamMember {
+            user_id: user_id.to_string(),
+            role,
+        });
+    }
+    if !team.members.iter().any(|member| member.role == TeamRole::Owner) {
+        return Err("A team needs at least one owner".to_string());
+    }
+    save(state, team).await
+}
+
+pub async fn set_approval_mode(
+    state: &AppState,
+    session: &Session,
+    account_id: &str,
+    enabled: bool,
+    min_notional: f64,
+) -> Result<ServerMessage, String> {
+    if !min_notional.is_finite() || min_notional < 0.0 {
+        return Err("Approval threshold must be zero or positive".to_string());
+    }
+    let team = team_for_owner(state, session, account_id).await?;
+    save(
+        state,
+        Team {
+            approval_mode: enabled,
+            approval_min_notional: min_notional,
+            ..team
+        },
+    )
+    .await
+}
+
+// Value of an order at its limit price, or at the last trade for market orders
+pub async fn order_notional(state: &AppState, order: &OrderRequest) -> Option<f64> {
+    let price = match order.price {
+        Some(price) => price,
+        None => state.engine.lock().await.last_price(&order.symbol)?,
+    };
+    Some(price * order.quantity)
+}
+
+async fn save(state: &AppState, team: Team) -> Result<ServerMessage, String> {
+    db::save_team(&state.pool, &team)
+        .await
+        .map_err(|e| format!("Error saving team: {}", e))?;
+    state.teams.lock().await.set(team.clone());
+    Ok(ServerMessage::TeamUpdated(team))
+}
+
+// Records which member placed each order, fills inherit it in the blotter
+pub async fn attribute(state: &AppState, session: &Session, account_id: &str, order_ids: &[u64]) {
+    let Some(user_id) = &session.user_id else {
+        return;
+    };
+    if state.teams.lock().await.get(account_id).is_none() {
+        return;
+    }
+    if let Err(e) = db::save_order_attribution(&state.pool, account_id, user_id, order_ids).await {
+        eprintln!("Error attributing orders on {}: {:?}", account_id, e);
+    }
+}
+
+// Parks an order until another member approves it, and tells the team about it
+pub async fn propose(
+    state: &AppState,
+    session: &Session,
+    account_id: &str,
+    order: OrderRequest,
+) -> Result<ServerMessage, String> {
+    let Some(user_id) = &session.user_id else {
+        return Err("Authenticate before proposing orders".to_string());
+    };
+    let mut proposal = OrderProposal {
+        proposal_id: 0,
+        account_id: account_id.to_string(),
+        proposed_by: user_id.clone(),
+        request: order,
+        status: "pending_approval".to_string(),
+        decided_by: None,
+        order_id: None,
+        created_at: now_millis(),
+    };
+    proposal.proposal_id = db::save_order_proposal(&state.pool, &proposal)
+        .await
+        .map_err(|e| format!("Error saving proposal: {}", e))?;
+
+    let request = &proposal.request;
+    alerts::deliver(
+        state,
+        Alert {
+            account_id: account_id.to_string(),
+            kind: "order_proposal".to_string(),
+            message: format!(
+                "{} proposed {:?} {} {} (proposal {}), another member needs to approve it",
+                user_id, request.side, request.quantity, request.symbol, proposal.proposal_id
+            ),
+            value: request.quantity,
+            threshold: 0.0,
+            created_at: proposal.created_at,
+        },
+        None,
+    );
+    Ok(ServerMessage::OrderProposed(proposal))
+}
+
+// Claims a pending proposal for the deciding member, who must not be the proposer
+pub async fn decide(
+    state: &AppState,
+    session: &Session,
+    account_id: &str,
+    proposal_id: i64,
+    approve: bool,
+) -> Result<OrderProposal, String> {
+    let Some(user_id) = &session.user_id else {
+        return Err("Authenticate before deciding on proposals".to_string());
+    };
+    let proposal = db::get_order_proposal(&state.pool, account_id, proposal_id)
+        .await
+        .map_err(|e| format!("Error loading proposal: {}", e))?
+        .ok_or_else(|| format!("Unknown proposal {}", proposal_id))?;
+    if proposal.status != "pending_approval" {
+        return Err(format!("Proposal {} is already {}", proposal_id, proposal.status));
+    }
+    if &proposal.proposed_by == user_id {
+        return Err("A proposal has to be decided by a different member".to_string());
+    }
+    let ttl_ms = env_or("PROPOSAL_TTL_SECS", 3600i64) * 1000;
+    let status = if now_millis() - proposal.created_at > ttl_ms {
+        "expired"
+    } else if approve {
+        "approved"
+    } else {
+        "rejected"
+    };
+
+    let claimed = db::decide_order_proposal(&state.pool, proposal_id, status, user_id)
+        .await
+        .map_err(|e| format!("Error saving decision: {}", e))?;
+    if !claimed {
+        return Err(format!("Proposal {} was decided concurrently", proposal_id));
+    }
+    if status == "expired" {
+        return Err(format!("Proposal {} has expired", proposal_id));
+    }
+    Ok(OrderProposal {
+        status: status.to_string(),
+        decided_by: Some(user_id.clone()),
+        ..proposal
+    })
+}
+
+// Fills on a team account with the member behind each, and every member's share of the P&L
+pub async fn blotter(state: &AppState, account_id: &str, limit: i64) -> Result<ServerMessage, String> {
+    let fills = db::get_attributed_fills(&state.pool, account_id)
+        .await
+        .map_err(|e| format!("Error loading fills: {}", e))?;
+
+    // Each member's fills form a sub-portfolio: cash flow plus the net position at current marks
+    let mut members: HashMap<String, (f64, HashMap<String, f64>, u64)> = HashMap::new();
+    for fill in &fills {
+        let member = members
+            .entry(fill.user_id.clone().unwrap_or_else(|| "unattributed".to_string()))
+            .or_default();
+        let signed = match fill.fill.side {
+            Side::Buy => fill.fill.quantity,
+            Side::Sell => -fill.fill.quantity,
+        };
+        member.0 -= signed * fill.fill.price;
+        *member.1.entry(fill.fill.symbol.clone()).or_default() += signed;
+        member.2 += 1;
+    }
+
+    let engine = state.engine.lock().await;
+    let mut pnl: Vec<MemberPnl> = members
+        .into_iter()
+        .map(|(user_id, (cash_flow, positions, fills))| {
+            let value: f64 = positions
+                .iter()
+                .map(|(symbol, quantity)| quantity * engine.last_price(symbol).unwrap_or_default())
+                .sum();
+            MemberPnl {
+                user_id,
+                fills,
+                pnl: cash_flow + value,
+            }
+        })
+        .collect();
+    drop(engine);
+    pnl.sort_by(|a, b| b.pnl.total_cmp(&a.pnl));
+
+    let mut recent = fills;
+    recent.reverse();
+    recent.truncate(limit.max(0) as usize);
+    Ok(ServerMessage::TeamBlotter {
+        account_id: account_id.to_string(),
+        fills: recent,
+        members: pnl,
+    })
+}
@@ -0,0 +1,165 @@
+use crate::auth::Session;
+use crate::db;
+use crate::models::{Role, ServerMessage, Tenant, TenantStatus};
+use crate::state::AppState;
+use crate::usage::ConnectionUsage;
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct TenantBook {
+    tenants: HashMap<String, Tenant>,
+    accounts: HashMap<String, String>, // Account to the tenant it belongs to
+}
+
+impl TenantBook {
+    pub fn load(&mut self, tenants: Vec<Tenant>, accounts: HashMap<String, String>) {
+        self.tenants = tenants.into_iter().map(|tenant| (tenant.tenant_id.clone(), tenant)).collect();
+        self.accounts = accounts;
+    }
+
+    pub fn get(&self, tenant_id: &str) -> Option<&Tenant> {
+        self.tenants.get(tenant_id)
+    }
+
+    pub fn set(&mut self, tenant: Tenant) {
+        self.tenants.insert(tenant.tenant_id.clone(), tenant);
+    }
+
+    pub fn tenant_ids(&self) -> Vec<String> {
+        self.tenants.keys().cloned().collect()
+    }
+
+    pub fn owner(&self, account_id: &str) -> Option<&str> {
+        self.accounts.get(account_id).map(String::as_str)
+    }
+
+    fn claim(&mut self, account_id: &str, tenant_id: &str) {
+        self.accounts.insert(account_id.to_string(), tenant_id.to_string());
+    }
+
+    fn account_count(&self, tenant_id: &str) -> usize {
+        self.accounts.values().filter(|owner| *owner == tenant_id).count()
+    }
+}
+
+// Users of a tenant only reach its accounts, claiming new ones on first use. Users without a
+// tenant share the accounts outside every tenant, admins without one reach every account.
+pub async fn authorize(state: &AppState, session: &Session, account_id: &str) -> Result<(), String> {
+    let owner = state.tenants.lock().await.owner(account_id).map(str::to_string);
+    match (owner, &session.tenant_id) {
+        (Some(owner), Some(tenant_id)) if owner == *tenant_id => Ok(()),
+        (Some(_), None) if session.role == Role::Admin => Ok(()),
+        (Some(_), _) => Err(format!("{} belongs to another tenant", account_id)),
+        (None, None) => Ok(()),
+        (None, Some(tenant_id)) => claim(state, account_id, tenant_id).await,
+    }
+}
+
+async fn claim(state: &AppState, account_id: &str, tenant_id: &str) -> Result<(), String> {
+    // Accounts already in use outside every tenant stay there
+    if state.portfolios.lock().await.get(account_id).is_some() {
+        return Err(format!("{} belongs to another tenant", account_id));
+    }
+    match db::save_account_tenant(&state.pool, account_id, tenant_id).await {
+        Ok(true) => {
+            state.tenants.lock().await.claim(account_id, tenant_id);
+            Ok(())
+        }
+        // Another tenant claimed it first
+        Ok(false) => Err(format!("{} belongs to another tenant", account_id)),
+        Err(e) => Err(format!("Error assigning {} to tenant {}: {}", account_id, tenant_id, e)),
+    }
+}
+
+// Tenants are managed by admins outside every tenant
+fn require_global_admin(session: &Session) -> Result<(), String> {
+    match &session.tenant_id {
+        Some(tenant_id) => Err(format!("Admins of tenant {} can't manage tenants", tenant_id)),
+        None => Ok(()),
+    }
+}
+
+pub async fn set_tenant(state: &AppState, session: &Session, tenant: Tenant) -> Result<ServerMessage, String> {
+    require_global_admin(session)?;
+    if tenant.tenant_id.is_empty() {
+        return Err("Tenant id can't be empty".to_string());
+    }
+    db::save_tenant(&state.pool, &tenant)
+        .await
+        .map_err(|e| format!("Error saving tenant: {}", e))?;
+    state.tenants.lock().await.set(tenant.clone());
+    println!("Tenant {} set", tenant.tenant_id);
+    Ok(ServerMessage::TenantSet(tenant))
+}
+
+pub async fn list_tenants(state: &AppState, session: &Session) -> Result<ServerMessage, String> {
+    require_global_admin(session)?;
+    let storage = db::get_tenant_storage(&state.pool, None)
+        .await
+        .map_err(|e| format!("Error measuring tenant storage: {}", e))?;
+    let book = state.tenants.lock().await;
+    let mut tenants: Vec<TenantStatus> = book
+        .tenants
+        .values()
+        .map(|tenant| TenantStatus {
+            tenant: tenant.clone(),
+            connections: state.usage.tenant_connections(&tenant.tenant_id),
+            storage_bytes: storage.get(&tenant.tenant_id).copied().unwrap_or(0),
+            accounts: book.account_count(&tenant.tenant_id),
+        })
+        .collect();
+    tenants.sort_by(|a, b| a.tenant.tenant_id.cmp(&b.tenant.tenant_id));
+    Ok(ServerMessage::Tenants { tenants })
+}
+
+// Tenant a new user goes into: the one requested, which admins of a tenant can only pick as their own
+pub async fn user_tenant(
+    state: &AppState,
+    session: &Session,
+    requested: Option<String>,
+) -> Result<Option<String>, String> {
+    let tenant_id = match (&session.tenant_id, requested) {
+        (Some(own), Some(requested)) if *own != requested => {
+            return Err(format!("Admins of tenant {} can only create users in it", own));
+        }
+        (Some(own), _) => Some(own.clone()),
+        (None, requested) => requested,
+    };
+    if let Some(tenant_id) = &tenant_id {
+        if state.tenants.lock().await.get(tenant_id).is_none() {
+            return Err(format!("Unknown tenant {}", tenant_id));
+        }
+    }
+    Ok(tenant_id)
+}
+
+// Counts a connection that just signed in against its tenant's connection quota
+pub async fn admit_connection(
+    state: &AppState,
+    usage: &mut ConnectionUsage<'_>,
+    tenant_id: Option<&str>,
+) -> Result<(), String> {
+    let max_connections = match tenant_id {
+        Some(tenant_id) => state.tenants.lock().await.get(tenant_id).and_then(|tenant| tenant.max_connections),
+        None => None,
+    };
+    usage.set_tenant(tenant_id, max_connections)
+}
+
+// Refuses a setting that would take the tenant's stored settings past its quota. `replaced` is the
+// size of the value it overwrites.
+pub async fn check_storage(state: &AppState, tenant_id: &str, added: usize, replaced: usize) -> Result<(), String> {
+    let Some(max) = state.tenants.lock().await.get(tenant_id).and_then(|tenant| tenant.max_storage_bytes) else {
+        return Ok(());
+    };
+    let used = db::get_tenant_storage(&state.pool, Some(tenant_id))
+        .await
+        .map_err(|e| format!("Error measuring tenant storage: {}", e))?
+        .get(tenant_id)
+        .copied()
+        .unwrap_or(0);
+    if used - replaced as i64 + added as i64 > max {
+        return Err(format!("Tenant {} would exceed its storage quota of {} bytes", tenant_id, max));
+    }
+    Ok(())
+}
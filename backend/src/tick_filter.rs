@@ -0,0 +1,122 @@
+use crate::config::LiveConfig;
+use crate::models::TickerData;
+use std::collections::{HashMap, VecDeque};
+
+// Prices kept per symbol for the rolling median and how many are needed before it is trusted
+const MIN_SAMPLES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickAction {
+    Rejected, // Dropped before storage and matching
+    Flagged,  // Kept, but recorded in quarantine for inspection
+}
+
+impl TickAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            TickAction::Rejected => "rejected",
+            TickAction::Flagged => "flagged",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TickAnomaly {
+    pub action: TickAction,
+    pub reason: String,
+    pub median: Option<f64>,
+    pub deviation: Option<f64>, // Relative distance from the median
+}
+
+#[derive(Debug, Clone)]
+pub struct TickFilterConfig {
+    pub window: usize,
+    pub max_deviation: f64, // Fraction of the rolling median, 0.1 = 10%
+    pub reject: bool,       // Reject outliers instead of only flagging them
+}
+
+impl TickFilterConfig {
+    pub fn from_env() -> Self {
+        Self::from_config(&LiveConfig::default())
+    }
+
+    pub fn from_config(config: &LiveConfig) -> Self {
+        TickFilterConfig {
+            window: config.value_or("TICK_FILTER_WINDOW", 50),
+            max_deviation: config.value_or("TICK_FILTER_MAX_DEVIATION_PCT", 10.0) / 100.0,
+            reject: config.value_or::<String>("TICK_FILTER_MODE", "reject".to_string()) != "flag",
+        }
+    }
+}
+
+// Sanity check for incoming ticks against each symbol's rolling median close
+pub struct TickFilter {
+    config: TickFilterConfig,
+    history: HashMap<String, VecDeque<f64>>,
+}
+
+impl TickFilter {
+    pub fn new(config: TickFilterConfig) -> Self {
+        TickFilter {
+            config,
+            history: HashMap::new(),
+        }
+    }
+
+    // Recent prices are kept, a smaller window trims them with each symbol's next tick
+    pub fn set_config(&mut self, config: TickFilterConfig) {
+        self.config = config;
+    }
+
+    // Returns the anomaly when the tick is suspicious, zero and unparseable prices are always rejected
+    pub fn check(&mut self, ticker: &TickerData) -> Option<TickAnomaly> {
+        let price = match ticker.c.parse::<f64>() {
+            Ok(price) if price.is_finite() && price > 0.0 => price,
+            _ => {
+                return Some(TickAnomaly {
+                    action: TickAction::Rejected,
+                    reason: format!("invalid close price {:?}", ticker.c),
+                    median: None,
+                    deviation: None,
+                })
+            }
+        };
+
+        let history = self.history.entry(ticker.s.clone()).or_default();
+        let anomaly = if history.len() >= MIN_SAMPLES {
+            let median = median(history);
+            let deviation = (price - median).abs() / median;
+            (deviation > self.config.max_deviation).then(|| TickAnomaly {
+                action: if self.config.reject {
+                    TickAction::Rejected
+                } else {
+                    TickAction::Flagged
+                },
+                reason: format!("{:.2}% away from the rolling median", deviation * 100.0),
+                median: Some(median),
+                deviation: Some(deviation),
+            })
+        } else {
+            None
+        };
+
+        // Outliers still enter the window, so a real sustained move shifts the median within half a window
+        history.push_back(price);
+        while history.len() > self.config.window.max(MIN_SAMPLES) {
+            history.pop_front();
+        }
+
+        anomaly
+    }
+}
+
+fn median(values: &VecDeque<f64>) -> f64 {
+    let mut sorted: Vec<f64> = values.iter().copied().collect();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
@@ -0,0 +1,175 @@
+use crate::config::env_or;
+use crate::models::ServerMessage;
+use crate::state::AppState;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use utoipa::ToSchema;
+
+// Updates returned by one poll, the next poll continues from the returned cursor
+const MAX_UPDATES_PER_POLL: usize = 1000;
+pub const DEFAULT_POLL_TIMEOUT_SECS: u64 = 25;
+pub const MAX_POLL_TIMEOUT_SECS: u64 = 60;
+
+struct Entry {
+    cursor: u64,
+    symbol: Option<String>,     // Set for ticker updates
+    account_id: Option<String>, // Set for fills and alerts
+    payload: serde_json::Value, // The WebSocket frame plus its cursor, serialized once for every poller
+}
+
+// Recent tickers, fills and alerts numbered by a cursor, for clients polling over REST instead of
+// holding a WebSocket open. The oldest entries are dropped past UPDATE_BUFFER_SIZE.
+pub struct UpdateLog {
+    entries: Mutex<VecDeque<Entry>>,
+    capacity: usize,
+    latest: watch::Sender<u64>, // Cursor the next entry will get
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UpdatesPage {
+    pub cursor: u64, // Pass back to receive what came after this page
+    pub reset: bool, // The requested cursor was older than the buffer, updates were missed
+    #[schema(value_type = Vec<Object>)]
+    pub updates: Vec<serde_json::Value>, // WebSocket frames ("ticker", "fill", "alert") with their cursor
+}
+
+// What a poll is interested in
+pub struct UpdateFilter {
+    pub symbols: HashSet<String>,
+    pub account_id: Option<String>,
+}
+
+impl UpdateFilter {
+    fn matches(&self, entry: &Entry) -> bool {
+        entry.symbol.as_ref().is_some_and(|symbol| self.symbols.contains(symbol))
+            || (entry.account_id.is_some() && entry.account_id == self.account_id)
+    }
+}
+
+impl UpdateLog {
+    pub fn from_env() -> Self {
+        let (latest, _) = watch::channel(0);
+        UpdateLog {
+            entries: Mutex::new(VecDeque::new()),
+            capacity: env_or("UPDATE_BUFFER_SIZE", 20_000).max(1),
+            latest,
+        }
+    }
+
+    fn latest(&self) -> u64 {
+        *self.latest.borrow()
+    }
+
+    fn push(&self, symbol: Option<String>, account_id: Option<String>, message: ServerMessage) {
+        let Ok(mut payload) = serde_json::to_value(&message) else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let cursor = self.latest();
+        if let Some(fields) = payload.as_object_mut() {
+            fields.insert("cursor".to_string(), cursor.into());
+        }
+        entries.push_back(Entry {
+            cursor,
+            symbol,
+            account_id,
+            payload,
+        });
+        while entries.len() > self.capacity {
+            entries.pop_front();
+        }
+        self.latest.send_replace(cursor + 1);
+    }
+
+    // Matching updates from `cursor` on, and the cursor to continue from
+    fn since(&self, cursor: u64, filter: &UpdateFilter) -> UpdatesPage {
+        let entries = self.entries.lock().unwrap();
+        let oldest = entries.front().map(|entry| entry.cursor).unwrap_or_else(|| self.latest());
+        let mut page = UpdatesPage {
+            cursor: self.latest(),
+            reset: cursor < oldest,
+            updates: Vec::new(),
+        };
+        for entry in entries.iter().skip_while(|entry| entry.cursor < cursor) {
+            if page.updates.len() == MAX_UPDATES_PER_POLL {
+                page.cursor = entry.cursor;
+                break;
+            }
+            if filter.matches(entry) {
+                page.updates.push(entry.payload.clone());
+            }
+        }
+        page
+    }
+}
+
+// Waits until an update matching `filter` arrives after `cursor`, or the timeout passes. Without a
+// cursor the poll starts from now.
+pub async fn poll(state: &AppState, cursor: Option<u64>, filter: &UpdateFilter, timeout: Duration) -> UpdatesPage {
+    let deadline = state.simulation.elapsed() + timeout;
+    let mut changes = state.updates.latest.subscribe();
+    let latest = state.updates.latest();
+    let mut cursor = cursor.unwrap_or(latest);
+    // A cursor from before a restart is ahead of the new numbering
+    let mut reset = cursor > latest;
+    if reset {
+        cursor = latest;
+    }
+    loop {
+        // Seen before reading, so an update arriving in between still wakes the wait below
+        changes.borrow_and_update();
+        let page = state.updates.since(cursor, filter);
+        reset |= page.reset;
+        if !page.updates.is_empty() {
+            return UpdatesPage { reset, ..page };
+        }
+        // Nothing of interest yet, skip what was scanned
+        cursor = page.cursor;
+        if cursor < state.updates.latest() {
+            continue;
+        }
+        let remaining = deadline.saturating_sub(state.simulation.elapsed());
+        if state.simulation.timeout(remaining, changes.changed()).await.is_none() {
+            return UpdatesPage {
+                cursor,
+                reset,
+                updates: Vec::new(),
+            };
+        }
+    }
+}
+
+// Copy the broadcast tickers, fills and alerts into the update log
+pub async fn run_update_recorder(state: Arc<AppState>) {
+    let mut tickers = state.tickers.subscribe();
+    let mut fills = state.fills.subscribe();
+    let mut alerts = state.alerts.subscribe();
+
+    loop {
+        tokio::select! {
+            ticker = tickers.recv() => match ticker {
+                Ok(ticker) => state.updates.push(Some(ticker.symbol.clone()), None, ServerMessage::Ticker(ticker)),
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            fill = fills.recv() => match fill {
+                Ok(fill) => state.updates.push(None, Some(fill.account_id.clone()), ServerMessage::Fill(fill)),
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Update recorder lagged behind, {} fills not recorded", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            },
+            alert = alerts.recv() => match alert {
+                Ok(alert) => state.updates.push(None, Some(alert.account_id.clone()), ServerMessage::Alert(alert)),
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Update recorder lagged behind, {} alerts not recorded", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
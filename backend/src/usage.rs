@@ -0,0 +1,159 @@
+use crate::config::env_or;
+use crate::db;
+use crate::engine::now_millis;
+use crate::models::{TenantUsageSnapshot, UsageSnapshot};
+use crate::state::AppState;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::Duration;
+
+// Live WebSocket usage, sampled into usage_stats every USAGE_SNAPSHOT_SECS
+#[derive(Default)]
+pub struct UsageCounters {
+    connections: AtomicI64,
+    tickers: AtomicI64,  // Symbols streamed, summed over connections
+    indices: AtomicI64,  // Custom indices streamed, summed over connections
+    accounts: AtomicI64, // Accounts whose fills and alerts are pushed, summed over connections
+    messages_in: AtomicU64,
+    messages_out: AtomicU64,
+    tenant_connections: Mutex<HashMap<String, i64>>, // Connections signed in as users of each tenant
+}
+
+impl UsageCounters {
+    // Counts a new connection until the returned guard is dropped
+    pub fn connection(&self) -> ConnectionUsage<'_> {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionUsage {
+            counters: self,
+            tickers: 0,
+            indices: 0,
+            accounts: 0,
+            tenant: None,
+        }
+    }
+
+    pub fn tenant_connections(&self, tenant_id: &str) -> i64 {
+        self.tenant_connections.lock().unwrap().get(tenant_id).copied().unwrap_or(0)
+    }
+
+    pub fn message_in(&self) {
+        self.messages_in.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_out(&self) {
+        self.messages_out.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// One connection's share of the counters, taken back when it closes
+pub struct ConnectionUsage<'a> {
+    counters: &'a UsageCounters,
+    tickers: i64,
+    indices: i64,
+    accounts: i64,
+    tenant: Option<String>,
+}
+
+impl ConnectionUsage<'_> {
+    // Counts the connection for the tenant it signed in to, refused when that tenant already has
+    // `max_connections` open
+    pub fn set_tenant(&mut self, tenant_id: Option<&str>, max_connections: Option<i64>) -> Result<(), String> {
+        if self.tenant.as_deref() == tenant_id {
+            return Ok(());
+        }
+        let mut connections = self.counters.tenant_connections.lock().unwrap();
+        if let (Some(tenant_id), Some(max)) = (tenant_id, max_connections) {
+            if connections.get(tenant_id).copied().unwrap_or(0) >= max {
+                return Err(format!("Tenant {} already has its maximum of {} connections open", tenant_id, max));
+            }
+        }
+        if let Some(previous) = self.tenant.take() {
+            if let Some(count) = connections.get_mut(&previous) {
+                *count -= 1;
+                if *count == 0 {
+                    connections.remove(&previous);
+                }
+            }
+        }
+        if let Some(tenant_id) = tenant_id {
+            *connections.entry(tenant_id.to_string()).or_default() += 1;
+            self.tenant = Some(tenant_id.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn set_subscriptions(&mut self, tickers: usize, indices: usize, accounts: usize) {
+        let (tickers, indices, accounts) = (tickers as i64, indices as i64, accounts as i64);
+        self.counters.tickers.fetch_add(tickers - self.tickers, Ordering::Relaxed);
+        self.counters.indices.fetch_add(indices - self.indices, Ordering::Relaxed);
+        self.counters.accounts.fetch_add(accounts - self.accounts, Ordering::Relaxed);
+        (self.tickers, self.indices, self.accounts) = (tickers, indices, accounts);
+    }
+}
+
+impl Drop for ConnectionUsage<'_> {
+    fn drop(&mut self) {
+        self.set_subscriptions(0, 0, 0);
+        let _ = self.set_tenant(None, None);
+        self.counters.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub async fn run_usage_recorder(state: Arc<AppState>) {
+    let mut ticker = state.simulation.interval(Duration::from_secs(env_or("USAGE_SNAPSHOT_SECS", 60).max(1)));
+    let counters = &state.usage;
+    let mut last = (
+        now_millis(),
+        counters.messages_in.load(Ordering::Relaxed),
+        counters.messages_out.load(Ordering::Relaxed),
+    );
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+
+        let now = now_millis();
+        let messages_in = counters.messages_in.load(Ordering::Relaxed);
+        let messages_out = counters.messages_out.load(Ordering::Relaxed);
+        let elapsed_secs = ((now - last.0) as f64 / 1000.0).max(0.001);
+        let snapshot = UsageSnapshot {
+            recorded_at: now,
+            connections: counters.connections.load(Ordering::Relaxed),
+            ticker_subscriptions: counters.tickers.load(Ordering::Relaxed),
+            index_subscriptions: counters.indices.load(Ordering::Relaxed),
+            account_subscriptions: counters.accounts.load(Ordering::Relaxed),
+            messages_in_per_sec: (messages_in - last.1) as f64 / elapsed_secs,
+            messages_out_per_sec: (messages_out - last.2) as f64 / elapsed_secs,
+        };
+        last = (now, messages_in, messages_out);
+
+        if let Err(e) = db::save_usage_snapshot(&state.pool, &snapshot).await {
+            eprintln!("Error saving usage snapshot: {:?}", e);
+        }
+        record_tenant_usage(&state, now).await;
+    }
+}
+
+async fn record_tenant_usage(state: &AppState, now: i64) {
+    let tenant_ids = state.tenants.lock().await.tenant_ids();
+    if tenant_ids.is_empty() {
+        return;
+    }
+    let storage = match db::get_tenant_storage(&state.pool, None).await {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Error measuring tenant storage: {:?}", e);
+            return;
+        }
+    };
+    for tenant_id in tenant_ids {
+        let snapshot = TenantUsageSnapshot {
+            recorded_at: now,
+            connections: state.usage.tenant_connections(&tenant_id),
+            storage_bytes: storage.get(&tenant_id).copied().unwrap_or(0),
+        };
+        if let Err(e) = db::save_tenant_usage(&state.pool, &tenant_id, &snapshot).await {
+            eprintln!("Error saving usage of tenant {}: {:?}", tenant_id, e);
+        }
+    }
+}
@@ -0,0 +1,664 @@
+// Properties of the matching engine and the portfolio accounting it feeds, checked over random
+// order flow and price paths.
+//
+//   cargo test --test engine_properties
+//
+// Failing cases shrink to a minimal sequence of actions; PROPTEST_CASES raises the case count.
+use proptest::prelude::*;
+
+// The server is a binary crate, so the modules under test are compiled in from its sources
+#[allow(dead_code)]
+#[path = "../src/chaos.rs"]
+mod chaos;
+#[allow(dead_code)]
+#[path = "../src/config.rs"]
+mod config;
+#[allow(dead_code)]
+#[path = "../src/engine.rs"]
+mod engine;
+#[allow(dead_code)]
+#[path = "../src/ingest.rs"]
+mod ingest;
+#[allow(dead_code, non_snake_case)]
+#[path = "../src/models.rs"]
+mod models;
+#[allow(dead_code)]
+#[path = "../src/portfolio.rs"]
+mod portfolio;
+#[allow(dead_code)]
+#[path = "../src/simulation.rs"]
+mod simulation;
+
+// portfolio.rs only needs the fee rates from the accounts module, which pulls in the database
+mod accounts {
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct FeeRates {
+        pub maker: f64,
+        pub taker: f64,
+    }
+}
+
+use accounts::FeeRates;
+use engine::MatchingEngine;
+use models::{AmendOrderRequest, Fill, LadderLevel, Order, OrderRequest, OrderStatus, OrderType, Side, TimeInForce};
+use portfolio::PortfolioBook;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const SYMBOLS: [&str; 2] = ["BTCUSDT", "ETHUSDT"];
+const ACCOUNTS: [&str; 3] = ["alice", "bob", "carol"];
+// Matches the engine's own tolerance for a tick printed exactly at a limit
+const PRICE_EPSILON: f64 = 1e-9;
+const QUANTITY_EPSILON: f64 = 1e-9;
+// Largest move of a single tick, which bounds the price a market order can fill at
+const MAX_TICK_MOVE: f64 = 0.03;
+
+#[derive(Debug, Clone)]
+enum Action {
+    Place {
+        account: usize,
+        symbol: usize,
+        side: Side,
+        limit: Option<f64>, // Offset from the last price as a fraction, None for a market order
+        quantity: f64,
+        time_in_force: TimeInForce,
+    },
+    Tick {
+        symbol: usize,
+        move_by: f64, // Fraction of the last price
+        traded: f64,  // Quote volume added to the rolling total
+    },
+    Cancel {
+        account: usize,
+        nth: usize, // Index into the orders placed so far
+    },
+}
+
+fn side() -> impl Strategy<Value = Side> {
+    prop_oneof![Just(Side::Buy), Just(Side::Sell)]
+}
+
+fn time_in_force() -> impl Strategy<Value = TimeInForce> {
+    prop_oneof![
+        3 => Just(TimeInForce::Gtc),
+        1 => Just(TimeInForce::Ioc),
+        1 => Just(TimeInForce::Fok),
+    ]
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    prop_oneof![
+        3 => (
+            0..ACCOUNTS.len(),
+            0..SYMBOLS.len(),
+            side(),
+            prop::option::weighted(0.8, -0.02..0.02f64),
+            0.001..5.0f64,
+            time_in_force(),
+        )
+            .prop_map(|(account, symbol, side, limit, quantity, time_in_force)| Action::Place {
+                account,
+                symbol,
+                side,
+                limit,
+                quantity,
+                time_in_force,
+            }),
+        4 => (0..SYMBOLS.len(), -MAX_TICK_MOVE..MAX_TICK_MOVE, 0.0..500_000.0f64)
+            .prop_map(|(symbol, move_by, traded)| Action::Tick { symbol, move_by, traded }),
+        1 => (0..ACCOUNTS.len(), 0..64usize).prop_map(|(account, nth)| Action::Cancel { account, nth }),
+    ]
+}
+
+fn fee_rates() -> impl Strategy<Value = FeeRates> {
+    (0.0..0.001f64, 0.0..0.001f64).prop_map(|(maker, taker)| FeeRates { maker, taker })
+}
+
+// Everything a run produced, for the properties to check
+struct Run {
+    engine: MatchingEngine,
+    book: PortfolioBook,
+    fills: Vec<Fill>,
+    fees: HashMap<String, f64>,
+    last_prices: HashMap<String, f64>,
+    // Each fill with the order as it stood before the tick and the tick's price
+    matched: Vec<(Fill, Order, f64)>,
+    // Lowest cash each account held after any tick
+    lowest_cash: HashMap<String, f64>,
+}
+
+fn run(actions: &[Action], rates: &[FeeRates]) -> Run {
+    simulate(actions, rates, false)
+}
+
+// Like `run`, but every account trades without margin: buys only go in when cash covers them at
+// the worst price they can fill at, fees and other open buys included, and sells never take a
+// position short. Orders that don't fit are turned away.
+fn run_unleveraged(actions: &[Action], rates: &[FeeRates]) -> Run {
+    simulate(actions, rates, true)
+}
+
+fn simulate(actions: &[Action], rates: &[FeeRates], unleveraged: bool) -> Run {
+    let mut engine = MatchingEngine::new();
+    let mut book = PortfolioBook::new();
+    for (account, rates) in ACCOUNTS.iter().zip(rates) {
+        book.set_fee_rates(account, *rates);
+    }
+
+    let mut last_prices: HashMap<String, f64> = SYMBOLS.iter().map(|symbol| (symbol.to_string(), 100.0)).collect();
+    let mut quote_volumes: HashMap<String, f64> = HashMap::new();
+    for symbol in SYMBOLS {
+        engine.on_price(symbol, last_prices[symbol], 0.0);
+    }
+
+    let mut placed: Vec<(usize, u64)> = Vec::new();
+    let mut fills = Vec::new();
+    let mut fees = HashMap::new();
+    let mut matched = Vec::new();
+    let mut lowest_cash = HashMap::new();
+    // Highest price each buy can fill at, a market order's is one tick away from its placing price
+    let mut worst_prices: HashMap<u64, f64> = HashMap::new();
+
+    for action in actions {
+        match action {
+            Action::Place {
+                account,
+                symbol,
+                side,
+                limit,
+                quantity,
+                time_in_force,
+            } => {
+                let symbol = SYMBOLS[*symbol];
+                let request = OrderRequest {
+                    symbol: symbol.to_string(),
+                    side: *side,
+                    order_type: if limit.is_some() { OrderType::Limit } else { OrderType::Market },
+                    price: limit.map(|offset| last_prices[symbol] * (1.0 + offset)),
+                    quantity: *quantity,
+                    time_in_force: *time_in_force,
+                    client_order_id: None,
+                };
+                let worst_price = request.price.unwrap_or(last_prices[symbol] * (1.0 + MAX_TICK_MOVE));
+                if unleveraged && !funded(&engine, &book, &worst_prices, ACCOUNTS[*account], &request, worst_price) {
+                    continue;
+                }
+                let order = engine
+                    .place_order(ACCOUNTS[*account], request)
+                    .expect("generated orders are valid");
+                worst_prices.insert(order.id, worst_price);
+                placed.push((*account, order.id));
+            }
+            Action::Tick { symbol, move_by, traded } => {
+                let symbol = SYMBOLS[*symbol];
+                let price = last_prices[symbol] * (1.0 + move_by);
+                let quote_volume = quote_volumes.entry(symbol.to_string()).or_default();
+                *quote_volume += traded;
+
+                let before: HashMap<u64, Order> = placed
+                    .iter()
+                    .filter_map(|(account, order_id)| engine.order(ACCOUNTS[*account], *order_id))
+                    .map(|order| (order.id, order.clone()))
+                    .collect();
+                let tick_fills = engine.on_price(symbol, price, *quote_volume);
+                last_prices.insert(symbol.to_string(), price);
+
+                for fill in tick_fills {
+                    let fee = book.apply_fill(&fill).expect("every engine fill is new");
+                    *fees.entry(fill.account_id.clone()).or_insert(0.0) += fee;
+                    matched.push((fill.clone(), before[&fill.order_id].clone(), price));
+                    fills.push(fill);
+                }
+                for account in ACCOUNTS {
+                    if let Some(portfolio) = book.get(account) {
+                        let lowest = lowest_cash.entry(account.to_string()).or_insert(portfolio.cash);
+                        *lowest = lowest.min(portfolio.cash);
+                    }
+                }
+            }
+            Action::Cancel { account, nth } => {
+                if let Some((_, order_id)) = placed.get(*nth) {
+                    // Someone else's order or a closed one is turned away, both are fine here
+                    let _ = engine.cancel_order(ACCOUNTS[*account], *order_id);
+                }
+            }
+        }
+    }
+
+    Run {
+        engine,
+        book,
+        fills,
+        fees,
+        last_prices,
+        matched,
+        lowest_cash,
+    }
+}
+
+// Whether an unleveraged account can afford the order on top of the ones it has working
+fn funded(
+    engine: &MatchingEngine,
+    book: &PortfolioBook,
+    worst_prices: &HashMap<u64, f64>,
+    account: &str,
+    request: &OrderRequest,
+    worst_price: f64,
+) -> bool {
+    let portfolio = book.get(account);
+    let rates = book.fee_rates(account);
+    let fee_rate = rates.maker.max(rates.taker);
+    let working = SYMBOLS
+        .iter()
+        .flat_map(|symbol| engine.open_orders(symbol))
+        .filter(|(owner, _)| owner == account)
+        .filter_map(|(owner, order_id)| engine.order(&owner, order_id).cloned());
+    match request.side {
+        Side::Buy => {
+            let cash = portfolio.map(|p| p.cash).unwrap_or(book.starting_balance());
+            let reserved: f64 = working
+                .filter(|order| order.side == Side::Buy)
+                .map(|order| (order.quantity - order.filled_quantity) * worst_prices[&order.id] * (1.0 + fee_rate))
+                .sum();
+            reserved + request.quantity * worst_price * (1.0 + fee_rate) <= cash
+        }
+        Side::Sell => {
+            let held = portfolio
+                .and_then(|p| p.positions.get(&request.symbol))
+                .map(|position| position.quantity)
+                .unwrap_or_default();
+            let selling: f64 = working
+                .filter(|order| order.side == Side::Sell && order.symbol == request.symbol)
+                .map(|order| order.quantity - order.filled_quantity)
+                .sum();
+            selling + request.quantity <= held
+        }
+    }
+}
+
+fn close(a: f64, b: f64, scale: f64) -> bool {
+    (a - b).abs() <= 1e-6 * scale.max(1.0)
+}
+
+proptest! {
+    // A fill never happens at a price the tick didn't reach: buys at or below their limit, sells
+    // at or above it, and market orders at the tick itself
+    #[test]
+    fn fills_only_at_crossable_prices(actions in prop::collection::vec(action(), 1..200)) {
+        let run = run(&actions, &[FeeRates::default(); 3]);
+        for (fill, order, tick) in &run.matched {
+            prop_assert_eq!(fill.side, order.side);
+            prop_assert_eq!(&fill.symbol, &order.symbol);
+            match (order.order_type, order.price) {
+                (OrderType::Market, _) => prop_assert_eq!(fill.price, *tick),
+                (OrderType::Limit, Some(limit)) => {
+                    let slack = limit * PRICE_EPSILON;
+                    match order.side {
+                        Side::Buy => {
+                            prop_assert!(*tick <= limit + slack, "buy limit {} filled on tick {}", limit, tick);
+                            prop_assert!(fill.price <= limit + slack, "buy limit {} filled at {}", limit, fill.price);
+                        }
+                        Side::Sell => {
+                            prop_assert!(*tick >= limit - slack, "sell limit {} filled on tick {}", limit, tick);
+                            prop_assert!(fill.price >= limit - slack, "sell limit {} filled at {}", limit, fill.price);
+                        }
+                    }
+                }
+                (OrderType::Limit, None) => prop_assert!(false, "limit order {} without a price filled", order.id),
+            }
+        }
+    }
+
+    // Orders never fill beyond their quantity, fills add up to what the order reports, and a
+    // fill-or-kill order either fills completely on its tick or not at all
+    #[test]
+    fn fills_add_up_to_order_quantities(actions in prop::collection::vec(action(), 1..200)) {
+        let run = run(&actions, &[FeeRates::default(); 3]);
+        let mut filled: HashMap<u64, f64> = HashMap::new();
+        for fill in &run.fills {
+            prop_assert!(fill.quantity > 0.0);
+            *filled.entry(fill.order_id).or_default() += fill.quantity;
+        }
+        for (order_id, quantity) in filled {
+            let account = &run.fills.iter().find(|fill| fill.order_id == order_id).unwrap().account_id;
+            let order = run.engine.order(account, order_id).unwrap();
+            prop_assert!(order.filled_quantity <= order.quantity + QUANTITY_EPSILON);
+            prop_assert!(close(order.filled_quantity, quantity, order.quantity));
+            if order.time_in_force == TimeInForce::Fok {
+                prop_assert_eq!(order.status, OrderStatus::Filled);
+            }
+        }
+        for (_, order, _) in &run.matched {
+            prop_assert!(order.status.is_open(), "order {} filled while {:?}", order.id, order.status);
+        }
+    }
+
+    // Fill ids are handed out once each and in order, so settling them is exactly-once
+    #[test]
+    fn fill_ids_are_unique_and_increasing(actions in prop::collection::vec(action(), 1..200)) {
+        let run = run(&actions, &[FeeRates::default(); 3]);
+        for pair in run.fills.windows(2) {
+            prop_assert!(pair[0].fill_id < pair[1].fill_id);
+        }
+        prop_assert!(run.fills.iter().all(|fill| fill.fill_id != 0));
+    }
+
+    // Trading only moves value between cash and positions: at any mark, an account's equity is
+    // its starting balance plus what each fill gained against that mark, minus the fees paid.
+    // Realized plus unrealized P&L must come to the same gain.
+    #[test]
+    fn equity_is_conserved_minus_fees(
+        actions in prop::collection::vec(action(), 1..200),
+        rates in prop::array::uniform3(fee_rates()),
+    ) {
+        let run = run(&actions, &rates);
+        let starting_balance = run.book.starting_balance();
+        for account in ACCOUNTS {
+            let Some(portfolio) = run.book.get(account) else {
+                prop_assert!(run.fills.iter().all(|fill| fill.account_id != account));
+                continue;
+            };
+
+            let mut gained = 0.0;
+            let mut notional = starting_balance;
+            for fill in run.fills.iter().filter(|fill| fill.account_id == account) {
+                let signed_quantity = match fill.side {
+                    Side::Buy => fill.quantity,
+                    Side::Sell => -fill.quantity,
+                };
+                gained += signed_quantity * (run.last_prices[&fill.symbol] - fill.price);
+                notional += fill.quantity * fill.price;
+            }
+            let fees = run.fees.get(account).copied().unwrap_or_default();
+            prop_assert!(fees >= 0.0);
+
+            let marked: f64 = portfolio
+                .positions
+                .iter()
+                .map(|(symbol, position)| position.quantity * run.last_prices[symbol])
+                .sum();
+            let equity = portfolio.cash + marked;
+            prop_assert!(
+                close(equity, starting_balance + gained - fees, notional),
+                "{} equity {} expected {}", account, equity, starting_balance + gained - fees
+            );
+
+            let pnl: f64 = portfolio
+                .positions
+                .iter()
+                .map(|(symbol, position)| {
+                    position.realized_pnl + position.quantity * (run.last_prices[symbol] - position.average_price)
+                })
+                .sum();
+            prop_assert!(close(pnl, gained, notional), "{} pnl {} expected {}", account, pnl, gained);
+        }
+    }
+
+    // An account trading without margin never goes negative: its cash stays at or above zero
+    // after every tick, it never ends up short, so its equity can't drop below zero either
+    #[test]
+    fn no_negative_balances_without_margin(
+        actions in prop::collection::vec(action(), 1..200),
+        rates in prop::array::uniform3(fee_rates()),
+    ) {
+        let run = run_unleveraged(&actions, &rates);
+        let slack = run.book.starting_balance() * 1e-9;
+        for (account, lowest) in &run.lowest_cash {
+            prop_assert!(*lowest >= -slack, "{} cash fell to {}", account, lowest);
+        }
+        for account in ACCOUNTS {
+            let Some(portfolio) = run.book.get(account) else {
+                continue;
+            };
+            let mut equity = portfolio.cash;
+            for (symbol, position) in &portfolio.positions {
+                prop_assert!(
+                    position.quantity >= -QUANTITY_EPSILON,
+                    "{} is short {} {}", account, position.quantity, symbol
+                );
+                equity += position.quantity * run.last_prices[symbol];
+            }
+            prop_assert!(equity >= -slack, "{} equity fell to {}", account, equity);
+        }
+    }
+
+    // Redelivering fills that were already settled leaves every account untouched
+    #[test]
+    fn redelivered_fills_settle_once(actions in prop::collection::vec(action(), 1..200)) {
+        let mut run = run(&actions, &[FeeRates { maker: 0.0002, taker: 0.0004 }; 3]);
+        let before: Vec<_> = ACCOUNTS.iter().map(|account| run.book.get(account).cloned()).collect();
+        for fill in &run.fills {
+            prop_assert!(run.book.apply_fill(fill).is_none());
+        }
+        for (account, before) in ACCOUNTS.iter().zip(before) {
+            let after = run.book.get(account);
+            prop_assert_eq!(before.as_ref().map(|p| p.cash), after.map(|p| p.cash));
+            prop_assert_eq!(before.as_ref().map(|p| p.positions.len()), after.map(|p| p.positions.len()));
+        }
+    }
+
+    // Legs are placed all together or not at all, and a retry returns the legs already placed
+    #[test]
+    fn legs_are_placed_together(
+        quantities in prop::collection::vec(-1.0..10.0f64, 2..4),
+        client_order_id in prop::option::of("[a-z]{4}"),
+        shared_id in any::<bool>(),
+    ) {
+        let legs: Vec<OrderRequest> = quantities
+            .iter()
+            .enumerate()
+            .map(|(leg, quantity)| OrderRequest {
+                symbol: SYMBOLS[leg % SYMBOLS.len()].to_string(),
+                side: if leg % 2 == 0 { Side::Buy } else { Side::Sell },
+                order_type: OrderType::Market,
+                price: None,
+                quantity: *quantity,
+                time_in_force: TimeInForce::Gtc,
+                client_order_id: client_order_id
+                    .as_ref()
+                    .map(|id| if shared_id { id.clone() } else { format!("{}-{}", id, leg) }),
+            })
+            .collect();
+        let mut engine = MatchingEngine::new();
+        let working = |engine: &MatchingEngine| {
+            SYMBOLS.iter().map(|symbol| engine.open_orders(symbol).len()).sum::<usize>()
+        };
+
+        // Legs sharing a client_order_id can't tell a retry from a second leg
+        let valid = quantities.iter().all(|quantity| *quantity > 0.0) && !(shared_id && client_order_id.is_some());
+        match engine.place_legs(ACCOUNTS[0], legs.clone()) {
+            Ok(placed) => {
+                prop_assert!(valid);
+                prop_assert_eq!(placed.len(), legs.len());
+                prop_assert_eq!(working(&engine), legs.len());
+                if client_order_id.is_some() {
+                    let retried = engine.place_legs(ACCOUNTS[0], legs.clone()).unwrap();
+                    let ids = |orders: &[Order]| orders.iter().map(|order| order.id).collect::<Vec<_>>();
+                    prop_assert_eq!(ids(&retried), ids(&placed));
+                    prop_assert_eq!(working(&engine), legs.len());
+                }
+            }
+            Err(_) => {
+                prop_assert!(!valid);
+                prop_assert_eq!(working(&engine), 0);
+            }
+        }
+    }
+
+    // Cutting a partly filled entry down to its fills completes it, its take-profits go to work
+    #[test]
+    fn amending_an_entry_to_its_fills_releases_the_ladder(
+        quantity in 1.0..10.0f64,
+        filled in 0.1..0.9f64,
+        take_profit in 0.1..1.0f64,
+    ) {
+        let mut engine = MatchingEngine::new();
+        engine.on_price(SYMBOLS[0], 101.0, 0.0);
+        let entry = OrderRequest {
+            symbol: SYMBOLS[0].to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            price: Some(100.0),
+            quantity,
+            time_in_force: TimeInForce::Gtc,
+            client_order_id: None,
+        };
+        let ladder = vec![LadderLevel { price: 110.0, quantity: quantity * filled * take_profit }];
+        let group = engine.place_group(ACCOUNTS[0], entry, ladder).unwrap();
+        // The tick at the limit trades part of the entry's quantity through its empty queue
+        engine.on_price(SYMBOLS[0], 100.0, quantity * filled * 100.0);
+        let partial = engine.order(ACCOUNTS[0], group.entry.id).unwrap().clone();
+        prop_assert_eq!(partial.status, OrderStatus::PartiallyFilled);
+
+        let amended = engine
+            .amend_order(AmendOrderRequest {
+                account_id: ACCOUNTS[0].to_string(),
+                order_id: partial.id,
+                version: partial.version,
+                price: None,
+                quantity: Some(partial.filled_quantity),
+                time_in_force: None,
+            })
+            .unwrap();
+        prop_assert_eq!(amended.status, OrderStatus::Filled);
+        for take_profit in &group.take_profits {
+            let status = engine.order(ACCOUNTS[0], take_profit.id).unwrap().status;
+            prop_assert_eq!(status, OrderStatus::New);
+        }
+    }
+
+    // An operation rolled back leaves the account's orders as they were, client_order_id included
+    #[test]
+    fn rolled_back_orders_are_gone(quantities in prop::collection::vec(0.1..10.0f64, 1..4)) {
+        let mut engine = MatchingEngine::new();
+        engine.on_price(SYMBOLS[0], 100.0, 0.0);
+        let request = |quantity: f64, client_order_id: &str| OrderRequest {
+            symbol: SYMBOLS[0].to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            price: Some(90.0),
+            quantity,
+            time_in_force: TimeInForce::Gtc,
+            client_order_id: Some(client_order_id.to_string()),
+        };
+        let resting = engine.place_order(ACCOUNTS[0], request(1.0, "resting")).unwrap();
+        engine.take_changes();
+
+        let snapshot = engine.snapshot(ACCOUNTS[0]);
+        let legs = quantities.iter().enumerate().map(|(leg, quantity)| request(*quantity, &format!("leg-{}", leg)));
+        engine.place_legs(ACCOUNTS[0], legs.collect()).unwrap();
+        engine.cancel_order(ACCOUNTS[0], resting.id).unwrap();
+        let changes = engine.take_changes();
+        engine.rollback(snapshot, &changes);
+
+        let open = engine.open_orders(SYMBOLS[0]);
+        prop_assert_eq!(open.len(), 1);
+        prop_assert_eq!(engine.order(ACCOUNTS[0], resting.id).unwrap().status, OrderStatus::New);
+        // The rolled back ids are free again, a retry places the legs anew
+        let retried = engine.place_order(ACCOUNTS[0], request(1.0, "leg-0")).unwrap();
+        prop_assert!(retried.id != resting.id);
+        prop_assert_eq!(engine.open_orders(SYMBOLS[0]).len(), 2);
+    }
+}
+
+// Ticks matched and settled from several tasks at once, the way the price feed does it: a task
+// holds the engine while it settles the tick's fills, and redelivers every fill seen so far
+fn run_concurrently(run: Run, ticks: Vec<Vec<(usize, f64, f64)>>) -> (MatchingEngine, PortfolioBook, Vec<Fill>) {
+    let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(4).build().unwrap();
+    let engine = Arc::new(Mutex::new(run.engine));
+    let book = Arc::new(Mutex::new(run.book));
+    let settled = Arc::new(Mutex::new(run.fills));
+
+    runtime.block_on(async {
+        let tasks: Vec<_> = ticks
+            .into_iter()
+            .map(|ticks| {
+                let (engine, book, settled) = (Arc::clone(&engine), Arc::clone(&book), Arc::clone(&settled));
+                tokio::spawn(async move {
+                    for (symbol, price, quote_volume) in ticks {
+                        let mut engine = engine.lock().await;
+                        let fills = engine.on_price(SYMBOLS[symbol], price, quote_volume);
+                        let mut book = book.lock().await;
+                        for fill in &fills {
+                            assert!(book.apply_fill(fill).is_some(), "fill {} settled twice", fill.fill_id);
+                        }
+                        drop(engine);
+
+                        let mut settled = settled.lock().await;
+                        settled.extend(fills);
+                        for fill in settled.iter() {
+                            assert!(book.apply_fill(fill).is_none(), "redelivered fill {} settled again", fill.fill_id);
+                        }
+                        drop(book);
+                        drop(settled);
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+    });
+
+    // Every task is done, so this is the last handle on each
+    (
+        Arc::into_inner(engine).unwrap().into_inner(),
+        Arc::into_inner(book).unwrap().into_inner(),
+        Arc::into_inner(settled).unwrap().into_inner(),
+    )
+}
+
+// Ticks from up to four tasks, prices around the start so resting orders keep crossing. Rolling
+// volumes arrive out of order between tasks, which the engine counts as no traded volume.
+fn concurrent_ticks() -> impl Strategy<Value = Vec<Vec<(usize, f64, f64)>>> {
+    let tick = (0..SYMBOLS.len(), 90.0..110.0f64, 0.0..10_000_000.0f64);
+    prop::collection::vec(prop::collection::vec(tick, 1..50), 2..5)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    // Concurrent ticks never fill an order twice or beyond its quantity, and the accounts come
+    // out as if every fill had been settled once, one after another in fill id order
+    #[test]
+    fn concurrent_ticks_settle_each_fill_once(
+        actions in prop::collection::vec(action(), 1..100),
+        ticks in concurrent_ticks(),
+        rates in prop::array::uniform3(fee_rates()),
+    ) {
+        let (engine, book, mut fills) = run_concurrently(run(&actions, &rates), ticks);
+
+        fills.sort_by_key(|fill| fill.fill_id);
+        for pair in fills.windows(2) {
+            prop_assert!(pair[0].fill_id < pair[1].fill_id, "fill id {} handed out twice", pair[0].fill_id);
+        }
+
+        let mut filled: HashMap<(String, u64), f64> = HashMap::new();
+        for fill in &fills {
+            *filled.entry((fill.account_id.clone(), fill.order_id)).or_default() += fill.quantity;
+        }
+        for ((account, order_id), quantity) in filled {
+            let order = engine.order(&account, order_id).unwrap();
+            prop_assert!(order.filled_quantity <= order.quantity + QUANTITY_EPSILON);
+            prop_assert!(close(order.filled_quantity, quantity, order.quantity));
+        }
+
+        let mut replayed = PortfolioBook::new();
+        for (account, rates) in ACCOUNTS.iter().zip(&rates) {
+            replayed.set_fee_rates(account, *rates);
+        }
+        for fill in &fills {
+            replayed.apply_fill(fill);
+        }
+        for account in ACCOUNTS {
+            let (settled, replayed) = (book.get(account), replayed.get(account));
+            prop_assert_eq!(settled.map(|p| p.cash), replayed.map(|p| p.cash), "{} cash", account);
+            for (symbol, position) in settled.iter().flat_map(|p| &p.positions) {
+                let expected = replayed.and_then(|p| p.positions.get(symbol)).map(|p| p.quantity);
+                prop_assert_eq!(Some(position.quantity), expected, "{} {} position", account, symbol);
+            }
+        }
+    }
+}
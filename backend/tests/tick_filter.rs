@@ -0,0 +1,138 @@
+// The ingest outlier filter: the rolling median, the warm-up before it is trusted and the reject and
+// flag modes.
+//
+//   cargo test --test tick_filter
+
+// The server is a binary crate, so the modules under test are compiled in from its sources
+#[allow(dead_code)]
+#[path = "../src/chaos.rs"]
+mod chaos;
+#[allow(dead_code)]
+#[path = "../src/config.rs"]
+mod config;
+#[allow(dead_code)]
+#[path = "../src/ingest.rs"]
+mod ingest;
+#[allow(dead_code, non_snake_case)]
+#[path = "../src/models.rs"]
+mod models;
+#[allow(dead_code)]
+#[path = "../src/tick_filter.rs"]
+mod tick_filter;
+
+use models::TickerData;
+use tick_filter::{TickAction, TickFilter, TickFilterConfig};
+
+// Matches the filter's own MIN_SAMPLES
+const WARM_UP: usize = 5;
+
+fn filter(reject: bool) -> TickFilter {
+    TickFilter::new(TickFilterConfig {
+        window: 10,
+        max_deviation: 0.1,
+        reject,
+    })
+}
+
+fn tick(symbol: &str, close: &str) -> TickerData {
+    TickerData {
+        E: 0,
+        s: symbol.to_string(),
+        c: close.to_string(),
+        o: close.to_string(),
+        h: close.to_string(),
+        l: close.to_string(),
+        q: "0".to_string(),
+    }
+}
+
+fn feed(filter: &mut TickFilter, symbol: &str, closes: &[f64]) {
+    for close in closes {
+        assert!(filter.check(&tick(symbol, &close.to_string())).is_none(), "{close} was not accepted");
+    }
+}
+
+#[test]
+fn accepts_anything_until_warmed_up() {
+    let mut filter = filter(true);
+    for close in ["100", "1000", "10", "500"] {
+        assert!(filter.check(&tick("BTCUSDT", close)).is_none());
+    }
+    assert!(filter.check(&tick("BTCUSDT", "100")).is_none());
+
+    // Five samples are in, the median of 10, 100, 100, 500, 1000 is now trusted
+    let anomaly = filter.check(&tick("BTCUSDT", "200")).expect("200 is far from a median of 100");
+    assert_eq!(anomaly.median, Some(100.0));
+}
+
+#[test]
+fn median_of_an_odd_and_an_even_window() {
+    let mut filter = filter(true);
+    feed(&mut filter, "BTCUSDT", &[100.0, 108.0, 102.0, 106.0, 104.0]);
+    let anomaly = filter.check(&tick("BTCUSDT", "150")).unwrap();
+    assert_eq!(anomaly.median, Some(104.0));
+
+    // The outlier entered the window, the middle pair of six is now 104 and 106
+    let anomaly = filter.check(&tick("BTCUSDT", "150")).unwrap();
+    assert_eq!(anomaly.median, Some(105.0));
+}
+
+#[test]
+fn reject_mode_rejects_outliers() {
+    let mut filter = filter(true);
+    feed(&mut filter, "BTCUSDT", &[100.0; WARM_UP]);
+    assert!(filter.check(&tick("BTCUSDT", "109")).is_none());
+
+    let anomaly = filter.check(&tick("BTCUSDT", "120")).unwrap();
+    assert_eq!(anomaly.action, TickAction::Rejected);
+    assert!((anomaly.deviation.unwrap() - 0.2).abs() < 1e-9);
+
+    let anomaly = filter.check(&tick("BTCUSDT", "80")).unwrap();
+    assert_eq!(anomaly.action, TickAction::Rejected);
+}
+
+#[test]
+fn flag_mode_flags_outliers() {
+    let mut filter = filter(false);
+    feed(&mut filter, "BTCUSDT", &[100.0; WARM_UP]);
+
+    let anomaly = filter.check(&tick("BTCUSDT", "120")).unwrap();
+    assert_eq!(anomaly.action, TickAction::Flagged);
+}
+
+#[test]
+fn invalid_prices_are_rejected_in_either_mode() {
+    for reject in [true, false] {
+        let mut filter = filter(reject);
+        for close in ["0", "-1", "abc", "NaN", "inf", ""] {
+            let anomaly = filter.check(&tick("BTCUSDT", close)).expect(close);
+            assert_eq!(anomaly.action, TickAction::Rejected);
+            assert_eq!(anomaly.median, None);
+        }
+
+        // None of them counted towards the warm-up
+        feed(&mut filter, "BTCUSDT", &[100.0, 1000.0, 10.0, 500.0, 100.0]);
+    }
+}
+
+#[test]
+fn symbols_are_tracked_separately() {
+    let mut filter = filter(true);
+    feed(&mut filter, "BTCUSDT", &[100.0; WARM_UP]);
+    feed(&mut filter, "ETHUSDT", &[2.0; WARM_UP]);
+
+    assert!(filter.check(&tick("ETHUSDT", "2.1")).is_none());
+    assert!(filter.check(&tick("BTCUSDT", "2.1")).is_some());
+}
+
+#[test]
+fn a_sustained_move_shifts_the_median() {
+    let mut filter = filter(true);
+    feed(&mut filter, "BTCUSDT", &[100.0; 10]);
+
+    // Rejected until the new level holds more than half of the ten tick window
+    for _ in 0..6 {
+        assert!(filter.check(&tick("BTCUSDT", "150")).is_some());
+    }
+    assert!(filter.check(&tick("BTCUSDT", "150")).is_none());
+}
@@ -0,0 +1,96 @@
+// Intervals, sleeps and timeouts on the manual timer, which only moves when the test advances it.
+//
+//   cargo test --test virtual_time
+use futures::FutureExt;
+use std::future::pending;
+use std::sync::Arc;
+use std::time::Duration;
+
+// The server is a binary crate, so the modules under test are compiled in from its sources
+#[allow(dead_code)]
+#[path = "../src/chaos.rs"]
+mod chaos;
+#[allow(dead_code)]
+#[path = "../src/config.rs"]
+mod config;
+#[allow(dead_code)]
+#[path = "../src/engine.rs"]
+mod engine;
+#[allow(dead_code)]
+#[path = "../src/ingest.rs"]
+mod ingest;
+#[allow(dead_code, non_snake_case)]
+#[path = "../src/models.rs"]
+mod models;
+#[allow(dead_code)]
+#[path = "../src/simulation.rs"]
+mod simulation;
+
+use simulation::{ManualTimer, Simulation};
+
+const START_MILLIS: i64 = 1_700_000_000_000;
+
+fn manual() -> (Arc<ManualTimer>, Simulation) {
+    let timer = Arc::new(ManualTimer::new(START_MILLIS));
+    (Arc::clone(&timer), Simulation::manual(7, timer))
+}
+
+// A heartbeat fires at once, then once per period as time passes and never in between
+#[tokio::test]
+async fn interval_ticks_as_time_advances() {
+    let (timer, simulation) = manual();
+    let mut heartbeat = simulation.interval(Duration::from_secs(30));
+    assert!(heartbeat.tick().now_or_never().is_some());
+    assert!(heartbeat.tick().now_or_never().is_none());
+
+    timer.advance(Duration::from_secs(29));
+    assert!(heartbeat.tick().now_or_never().is_none());
+    timer.advance(Duration::from_secs(1));
+    assert!(heartbeat.tick().now_or_never().is_some());
+    assert!(heartbeat.tick().now_or_never().is_none());
+}
+
+// Ticks missed while the owner was busy are delivered back to back
+#[tokio::test]
+async fn missed_ticks_fire_back_to_back() {
+    let (timer, simulation) = manual();
+    let mut ticker = simulation.interval_at(Duration::from_secs(5), Duration::from_secs(5));
+    assert!(ticker.tick().now_or_never().is_none());
+
+    timer.advance(Duration::from_secs(16));
+    for _ in 0..3 {
+        assert!(ticker.tick().now_or_never().is_some());
+    }
+    assert!(ticker.tick().now_or_never().is_none());
+}
+
+// An expiry waiting on a sleep completes only once its deadline has passed, and the wall clock
+// moves along with the timer
+#[tokio::test]
+async fn sleep_waits_for_the_deadline() {
+    let (timer, simulation) = manual();
+    let simulation = Arc::new(simulation);
+    let sleeper = Arc::clone(&simulation);
+    let expired = tokio::spawn(async move {
+        sleeper.sleep(Duration::from_secs(3600)).await;
+        sleeper.now_millis()
+    });
+
+    tokio::task::yield_now().await;
+    assert!(!expired.is_finished());
+
+    timer.advance(Duration::from_secs(3600));
+    assert_eq!(expired.await.unwrap(), START_MILLIS + 3_600_000);
+    assert_eq!(simulation.elapsed(), Duration::from_secs(3600));
+}
+
+#[tokio::test]
+async fn timeout_gives_up_at_the_deadline() {
+    let (timer, simulation) = manual();
+    assert_eq!(simulation.timeout(Duration::from_secs(1), async { 42 }).await, Some(42));
+
+    let mut waiting = Box::pin(simulation.timeout(Duration::from_secs(10), pending::<()>()));
+    assert!(waiting.as_mut().now_or_never().is_none());
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(waiting.await, None);
+}
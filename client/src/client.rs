@@ -0,0 +1,258 @@
+use crate::protocol::{ClientMessage, Fill, Order, OrderProposal, OrderRequest, ServerMessage, TickerUpdate};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashSet;
+use std::fmt;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+// Pushed messages buffered per subscriber, a slower reader skips the oldest ones
+const PUSH_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    WebSocket(tungstenite::Error),
+    Server(String), // The server's `error` reply
+    Exchange { status: u16, message: String },
+    RateLimited { limit: String, retry_after_ms: u64, message: String },
+    UnexpectedReply(String),
+    Closed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::WebSocket(e) => write!(f, "WebSocket error: {}", e),
+            Error::Server(message) => write!(f, "{}", message),
+            Error::Exchange { status, message } => write!(f, "Exchange error {}: {}", status, message),
+            Error::RateLimited { message, .. } => write!(f, "{}", message),
+            Error::UnexpectedReply(reply) => write!(f, "Unexpected reply {}", reply),
+            Error::Closed => write!(f, "Connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        Error::WebSocket(e)
+    }
+}
+
+// What became of a placed order
+#[derive(Debug, Clone)]
+pub enum Placement {
+    Placed(Order),
+    Proposed(OrderProposal), // The account's team approves orders before they reach the market
+}
+
+// One connection to the simulator. The server answers requests in the order it receives them, so
+// requests go out one at a time and the next non-push frame is the reply. Tickers and fills are
+// routed to their streams as they arrive, frames the client doesn't model are dropped.
+pub struct Client {
+    outgoing: mpsc::UnboundedSender<Message>,
+    replies: Mutex<mpsc::UnboundedReceiver<ServerMessage>>,
+    tickers: broadcast::Sender<TickerUpdate>,
+    fills: broadcast::Sender<Fill>,
+}
+
+impl Client {
+    // Connects to `url` (ws:// or wss://) and logs in with a user token, API key or access token
+    pub async fn connect(url: &str, token: Option<&str>) -> Result<Client, Error> {
+        let (ws_stream, _) = connect_async(url).await?;
+        let (mut write, mut read) = ws_stream.split();
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<Message>();
+        let (replies_tx, replies) = mpsc::unbounded_channel();
+        let (tickers, _) = broadcast::channel(PUSH_CHANNEL_CAPACITY);
+        let (fills, _) = broadcast::channel(PUSH_CHANNEL_CAPACITY);
+
+        tokio::spawn(async move {
+            while let Some(message) = outgoing_rx.recv().await {
+                if write.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (tickers_tx, fills_tx) = (tickers.clone(), fills.clone());
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = read.next().await {
+                let Message::Text(text) = message else {
+                    continue;
+                };
+                // Untagged frames, like the periodic ticker page, aren't part of this protocol
+                let Ok(message) = serde_json::from_str::<ServerMessage>(&text) else {
+                    continue;
+                };
+                match message {
+                    ServerMessage::Ticker(ticker) => {
+                        let _ = tickers_tx.send(ticker);
+                    }
+                    ServerMessage::Fill(fill) => {
+                        let _ = fills_tx.send(fill);
+                    }
+                    message if message.is_push() => {}
+                    reply => {
+                        if replies_tx.send(reply).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        let client = Client {
+            outgoing,
+            replies: Mutex::new(replies),
+            tickers,
+            fills,
+        };
+        if let Some(token) = token {
+            let message = ClientMessage::Authenticate {
+                token: token.to_string(),
+            };
+            match client.request(message).await? {
+                ServerMessage::Authenticated { .. } => {}
+                reply => return Err(unexpected(reply)),
+            }
+        }
+        Ok(client)
+    }
+
+    // Starts ticker updates for `symbols` on this connection, the returned stream yields only them
+    pub async fn subscribe_ticker(&self, symbols: &[&str]) -> Result<Tickers, Error> {
+        // Subscribe before asking, so no update between the reply and the first read is lost
+        let receiver = self.tickers.subscribe();
+        let message = ClientMessage::Subscribe {
+            symbols: symbols.iter().map(|symbol| symbol.to_string()).collect(),
+        };
+        match self.request(message).await? {
+            ServerMessage::Subscribed { symbols } => Ok(Tickers {
+                receiver,
+                symbols: symbols.into_iter().collect(),
+            }),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub async fn unsubscribe_ticker(&self, symbols: &[&str]) -> Result<(), Error> {
+        let message = ClientMessage::Unsubscribe {
+            symbols: symbols.iter().map(|symbol| symbol.to_string()).collect(),
+        };
+        match self.request(message).await? {
+            ServerMessage::Unsubscribed { .. } => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub async fn place_order(&self, account_id: &str, order: OrderRequest) -> Result<Placement, Error> {
+        let message = ClientMessage::PlaceOrder {
+            account_id: account_id.to_string(),
+            order,
+        };
+        match self.request(message).await? {
+            ServerMessage::Order(order) => Ok(Placement::Placed(order)),
+            ServerMessage::OrderProposed(proposal) => Ok(Placement::Proposed(proposal)),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub async fn cancel_order(&self, account_id: &str, order_id: u64) -> Result<Order, Error> {
+        let message = ClientMessage::CancelOrder {
+            account_id: account_id.to_string(),
+            order_id,
+        };
+        match self.request(message).await? {
+            ServerMessage::Order(order) => Ok(order),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    // Fills of every account this connection has placed or cancelled orders on, from now on
+    pub fn stream_fills(&self) -> Fills {
+        Fills {
+            receiver: self.fills.subscribe(),
+        }
+    }
+
+    async fn request(&self, message: ClientMessage) -> Result<ServerMessage, Error> {
+        // Held until the reply arrives, so concurrent requests can't take each other's replies
+        let mut replies = self.replies.lock().await;
+        let text = serde_json::to_string(&message).map_err(|e| Error::UnexpectedReply(e.to_string()))?;
+        self.outgoing.send(Message::Text(text.into())).map_err(|_| Error::Closed)?;
+        let reply = loop {
+            let reply = replies.recv().await.ok_or(Error::Closed)?;
+            if !is_delisting(&message, &reply) {
+                break reply;
+            }
+        };
+        match reply {
+            ServerMessage::Error { message } => Err(Error::Server(message)),
+            ServerMessage::ExchangeError { status, message } => Err(Error::Exchange { status, message }),
+            ServerMessage::RateLimited {
+                limit,
+                retry_after_ms,
+                message,
+            } => Err(Error::RateLimited {
+                limit,
+                retry_after_ms,
+                message,
+            }),
+            reply => Ok(reply),
+        }
+    }
+}
+
+// The server also sends `unsubscribed` unasked when a subscribed symbol is delisted. That one
+// only answers an Unsubscribe for exactly its symbols.
+fn is_delisting(request: &ClientMessage, reply: &ServerMessage) -> bool {
+    let ServerMessage::Unsubscribed { symbols } = reply else {
+        return false;
+    };
+    let ClientMessage::Unsubscribe { symbols: requested } = request else {
+        return true;
+    };
+    let requested: Vec<String> = requested
+        .iter()
+        .map(|symbol| symbol.trim().to_uppercase())
+        .filter(|symbol| !symbol.is_empty())
+        .collect();
+    *symbols != requested
+}
+
+fn unexpected(reply: ServerMessage) -> Error {
+    Error::UnexpectedReply(format!("{:?}", reply))
+}
+
+// Ticker updates for the symbols of one subscription
+pub struct Tickers {
+    receiver: broadcast::Receiver<TickerUpdate>,
+    symbols: HashSet<String>,
+}
+
+impl Tickers {
+    // The next update, None once the connection closed. Updates a slow reader fell behind on are
+    // skipped, the next price supersedes them.
+    pub async fn next(&mut self) -> Option<TickerUpdate> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(ticker) if self.symbols.contains(&ticker.symbol) => return Some(ticker),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+pub struct Fills {
+    receiver: broadcast::Receiver<Fill>,
+}
+
+impl Fills {
+    // The next fill, None once the connection closed or this reader fell so far behind that fills
+    // were lost, resynchronize from the account's portfolio then
+    pub async fn next(&mut self) -> Option<Fill> {
+        self.receiver.recv().await.ok()
+    }
+}
@@ -0,0 +1,27 @@
+//! Typed async client for the trading simulator's WebSocket protocol.
+//!
+//! ```no_run
+//! use trading_simulator_client::{Client, OrderRequest, Placement, Side};
+//!
+//! # async fn run() -> Result<(), trading_simulator_client::Error> {
+//! let client = Client::connect("ws://localhost:8080", Some("my-token")).await?;
+//! let mut tickers = client.subscribe_ticker(&["BTCUSDT"]).await?;
+//! let mut fills = client.stream_fills();
+//!
+//! if let Some(ticker) = tickers.next().await {
+//!     let order = OrderRequest::limit("BTCUSDT", Side::Buy, 0.01, ticker.price * 0.99);
+//!     if let Placement::Placed(order) = client.place_order("bot-1", order).await? {
+//!         println!("Resting order {}", order.id);
+//!     }
+//! }
+//! while let Some(fill) = fills.next().await {
+//!     println!("Filled {} {} @ {}", fill.quantity, fill.symbol, fill.price);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+mod client;
+pub mod protocol;
+
+pub use client::{Client, Error, Fills, Placement, Tickers};
+pub use protocol::{Fill, Order, OrderRequest, OrderStatus, OrderType, Side, TickerUpdate, TimeInForce};
@@ -0,0 +1,207 @@
+// The subset of the simulator's WebSocket protocol the client speaks. Frames are JSON objects
+// tagged by "type"; field names and enum spellings match the server's `models.rs`.
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    Market,
+    Limit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeInForce {
+    #[default]
+    Gtc, // Good till cancelled
+    Ioc, // Immediate or cancel
+    Fok, // Fill or kill
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Pending, // Waiting for its parent order to fill before it can match
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Liquidity {
+    Maker, // Rested on the book before trading
+    Taker, // Crossed the market on arrival
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderRequest {
+    pub symbol: String,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub price: Option<f64>,
+    pub quantity: f64,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    pub client_order_id: Option<String>, // Unique per account, resubmitting it returns the original order
+}
+
+impl OrderRequest {
+    pub fn market(symbol: &str, side: Side, quantity: f64) -> Self {
+        OrderRequest {
+            symbol: symbol.to_uppercase(),
+            side,
+            order_type: OrderType::Market,
+            price: None,
+            quantity,
+            time_in_force: TimeInForce::Gtc,
+            client_order_id: None,
+        }
+    }
+
+    pub fn limit(symbol: &str, side: Side, quantity: f64, price: f64) -> Self {
+        OrderRequest {
+            order_type: OrderType::Limit,
+            price: Some(price),
+            ..OrderRequest::market(symbol, side, quantity)
+        }
+    }
+
+    pub fn time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn client_order_id(mut self, client_order_id: &str) -> Self {
+        self.client_order_id = Some(client_order_id.to_string());
+        self
+    }
+}
+
+// Estimated place of a resting maker order in the queue at its price level
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueueEstimate {
+    pub ahead: f64,            // Base quantity estimated to trade before this order
+    pub fill_probability: f64, // Chance the next tick at this level reaches the order
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Order {
+    pub id: u64,
+    pub account_id: String,
+    pub symbol: String,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub price: Option<f64>,
+    pub quantity: f64,
+    pub filled_quantity: f64,
+    pub time_in_force: TimeInForce,
+    pub status: OrderStatus,
+    pub group_id: Option<u64>,
+    pub client_order_id: Option<String>,
+    pub liquidity: Option<Liquidity>, // Decided once the order becomes active
+    pub queue: Option<QueueEstimate>,
+    pub version: u64, // Bumped on every amendment
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fill {
+    pub order_id: u64,
+    pub account_id: String,
+    pub symbol: String,
+    pub side: Side,
+    pub price: f64,
+    pub quantity: f64,
+    pub liquidity: Liquidity,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TickerUpdate {
+    pub symbol: String,
+    pub price: f64,
+    pub quote_volume: f64, // Rolling 24h quote volume
+    pub event_time: i64,
+}
+
+// An order the account's team holds until a second member approves it
+#[derive(Debug, Clone, Deserialize)]
+pub struct OrderProposal {
+    pub proposal_id: i64,
+    pub account_id: String,
+    pub proposed_by: String,
+    pub status: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Authenticate { token: String },
+    Subscribe { symbols: Vec<String> },
+    Unsubscribe { symbols: Vec<String> },
+    PlaceOrder { account_id: String, order: OrderRequest },
+    CancelOrder { account_id: String, order_id: u64 },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Authenticated {
+        user_id: String,
+    },
+    Subscribed {
+        symbols: Vec<String>,
+    },
+    Unsubscribed {
+        symbols: Vec<String>,
+    },
+    Order(Order),
+    OrderProposed(OrderProposal),
+    Error {
+        message: String,
+    },
+    ExchangeError {
+        status: u16,
+        message: String,
+    },
+    RateLimited {
+        limit: String,
+        retry_after_ms: u64,
+        message: String,
+    },
+    // Pushed without a request
+    Ticker(TickerUpdate),
+    Fill(Fill),
+    // Other pushes, dropped by this client
+    Alert {},
+    Index {},
+    SettingChanged {},
+    // Any other frame, like maintenance notices. Every request this client sends has its replies
+    // modeled above, so these are pushes too.
+    #[serde(other)]
+    Other,
+}
+
+impl ServerMessage {
+    // Pushes arrive between replies and never answer a request
+    pub(crate) fn is_push(&self) -> bool {
+        matches!(
+            self,
+            ServerMessage::Ticker(_)
+                | ServerMessage::Fill(_)
+                | ServerMessage::Alert {}
+                | ServerMessage::Index {}
+                | ServerMessage::SettingChanged {}
+                | ServerMessage::Other
+        )
+    }
+}
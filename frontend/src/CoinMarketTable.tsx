@@ -55,7 +55,8 @@ const CoinMarketTable = () => {
       }
 
       try {
-        const ws = new WebSocket("ws://127.0.0.1:8080");
+        // The backend serves this page too, its WebSocket listens on the same host
+        const ws = new WebSocket(`ws://${window.location.hostname}:8080`);
         wsRef.current = ws;
 
         ws.onopen = () => {